@@ -0,0 +1,25 @@
+// Times (fib 25) on both interpreters. Run with `cargo bench --bench fib`.
+
+extern crate rusty_scheme;
+
+use rusty_scheme::interpreter;
+use std::time::Instant;
+
+const FIB: &str = "(define (fib n) (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2)))))";
+
+fn main() {
+    for t in ["ast_walk", "cps"].iter() {
+        let i = interpreter::new(t);
+        i.execute(FIB).unwrap();
+
+        let mut best = None;
+        for _ in 0..5 {
+            let start = Instant::now();
+            assert_eq!(i.execute("(fib 25)").unwrap(), "75025");
+            let elapsed = start.elapsed();
+            let ms = elapsed.as_secs() * 1000 + elapsed.subsec_millis() as u64;
+            best = Some(best.map_or(ms, |b: u64| if ms < b { ms } else { b }));
+        }
+        println!("{:>8} (fib 25): {}ms (best of 5)", t, best.unwrap());
+    }
+}
@@ -0,0 +1,32 @@
+// Times code full of constants from a macro, on both interpreters with and without constant folding. Run with
+// `cargo bench --bench fold`.
+
+extern crate rusty_scheme;
+
+use rusty_scheme::interpreter::Builder;
+use std::time::Instant;
+
+const CODE: &str = "
+(define-syntax-rule (header-size)
+  (+ (string-length \"Content-Type: \") (string-length \"text/plain\") (* 2 (string-length \"\r\n\"))))
+(define (sizes n)
+  (if (< n 2) (+ (header-size) (length+ (list 1 2 3))) (+ (sizes (- n 1)) (sizes (- n 2)))))";
+
+fn main() {
+    for t in ["ast_walk", "cps"].iter() {
+        for fold in [false, true].iter() {
+            let i = Builder::new().interpreter_type(t).fold_constants(*fold).build().unwrap();
+            i.execute(CODE).unwrap();
+
+            let mut best = None;
+            for _ in 0..5 {
+                let start = Instant::now();
+                assert_eq!(i.execute("(sizes 20)").unwrap(), "339326");
+                let elapsed = start.elapsed();
+                let ms = elapsed.as_secs() * 1000 + elapsed.subsec_millis() as u64;
+                best = Some(best.map_or(ms, |b: u64| if ms < b { ms } else { b }));
+            }
+            println!("{:>8} (sizes 20){}: {}ms (best of 5)", t, if *fold { " folded" } else { "" }, best.unwrap());
+        }
+    }
+}
@@ -0,0 +1,29 @@
+// Times printing a 100,000 element list on both interpreters. Run with `cargo bench --bench print`.
+
+extern crate rusty_scheme;
+
+use rusty_scheme::interpreter;
+use std::time::Instant;
+
+const LENGTH: usize = 100000;
+
+fn main() {
+    let elements: Vec<String> = (0..LENGTH).map(|n| n.to_string()).collect();
+    let define = format!("(define big '({}))", elements.join(" "));
+
+    for t in ["ast_walk", "cps"].iter() {
+        let i = interpreter::new(t);
+        i.execute(&define).unwrap();
+
+        let mut best = None;
+        for _ in 0..5 {
+            let start = Instant::now();
+            let printed = i.execute("big").unwrap();
+            let elapsed = start.elapsed();
+            assert!(printed.ends_with(" 99999)"));
+            let ms = elapsed.as_secs() * 1000 + elapsed.subsec_millis() as u64;
+            best = Some(best.map_or(ms, |b: u64| if ms < b { ms } else { b }));
+        }
+        println!("{:>8} (print {} elements): {}ms (best of 5)", t, LENGTH, best.unwrap());
+    }
+}
@@ -0,0 +1,42 @@
+// Other names for builtins, resolved only when a name isn't bound (so scripts can still define their own). Some are
+// just alternatives, like R7RS's long name for call/cc. Others are old names, kept when a builtin is renamed so
+// existing scripts keep running; those warn, once, that they're deprecated.
+
+use warning;
+
+pub struct Alias {
+    pub name: &'static str,
+    pub target: &'static str,
+    pub deprecated: bool,
+}
+
+pub const ALIASES: &[Alias] = &[
+    Alias { name: "call-with-current-continuation", target: "call/cc", deprecated: false },
+    ];
+
+pub fn find(name: &str) -> Option<&'static Alias> {
+    ALIASES.iter().find(|a| a.name == name)
+}
+
+impl Alias {
+    // The name to look up instead, after warning about it if it's deprecated
+    pub fn resolve(&self) -> &'static str {
+        if self.deprecated {
+            warning::warn_once(self.deprecation_message());
+        }
+        self.target
+    }
+
+    fn deprecation_message(&self) -> String {
+        format!("{} is deprecated, use {} instead", self.name, self.target)
+    }
+}
+
+#[test]
+fn test_aliases() {
+    assert_eq!(find("call-with-current-continuation").map(|a| a.target), Some("call/cc"));
+    assert!(find("call/cc").is_none());
+    let old = Alias { name: "old", target: "new", deprecated: true };
+    assert_eq!(old.deprecation_message(), "old is deprecated, use new instead");
+    assert_eq!(old.resolve(), "new");
+}
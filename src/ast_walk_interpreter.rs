@@ -1,9 +1,72 @@
+use aliases;
+use bindings::Bindings;
+use cbor;
+use docs;
+use condition::Kind;
+use expand;
+use features;
+use lexer::{self, ReaderConfig};
+use log::{self, Level};
 use parser::*;
+use plugin;
+use printer::{self, Printable, PrinterConfig, Shape, Style};
+use random::{self, HashState, HashTag};
+use ranges;
+use search_path;
+use shared::unwrap_or_clone;
+use stats;
+use strict;
+use text;
+use warning;
+use watch;
+
+#[cfg(feature = "io")]
+use canonical;
+#[cfg(feature = "io")]
+use csv;
+#[cfg(feature = "io")]
+use files;
+#[cfg(feature = "io")]
+use input;
+#[cfg(feature = "io")]
+use paths;
+#[cfg(feature = "io")]
+use printer::Setting;
+#[cfg(feature = "io")]
+use std::io::{self, Write};
+
+use numbers::Number;
+#[cfg(feature = "math")]
+use numbers;
+use std::cmp::Ordering;
+
+use capabilities::Capabilities;
+#[cfg(feature = "net")]
+use http;
+
+#[cfg(all(unix, feature = "process"))]
+use signals;
+
+#[cfg(feature = "parallel")]
+use parallel;
+
+#[cfg(feature = "sqlite")]
+use sqlite;
+
+#[cfg(feature = "ffi")]
+use ffi;
 
 use std::fmt;
-use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::rc::Rc;
 use std::cell::RefCell;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::slice;
 
 pub fn new() -> Interpreter {
     Interpreter::new()
@@ -21,44 +84,364 @@ impl Interpreter {
 
     pub fn run(&self, nodes: &[Node]) -> Result<Value, RuntimeError> {
         let values = Value::from_nodes(nodes);
-        evaluate_values(&values, self.root.clone())
+        evaluate_values(&values, &self.root)
+    }
+
+    // Like run, but in the environment an earlier error happened in
+    pub fn run_in(&self, nodes: &[Node], frame: &Frame) -> Result<Value, RuntimeError> {
+        let values = Value::from_nodes(nodes);
+        evaluate_values(&values, &frame.env)
+    }
+
+    pub fn autoload(&self, path: &str, name: &str) {
+        self.root.borrow_mut().autoloads.insert(name.to_string(), path.to_string());
+    }
+
+    pub fn grant(&self, capabilities: Capabilities) {
+        self.root.borrow_mut().capabilities = capabilities;
+    }
+
+    // Bind a global variable, replacing any existing binding
+    pub fn set_global(&self, name: &str, value: Value) {
+        self.root.borrow_mut().values.insert(name.to_string(), value);
+    }
+
+    // Define a plugin's builtins and constants as globals
+    pub fn load_plugin(&self, env: &plugin::Environment) {
+        for builtin in env.builtins().iter() {
+            self.set_global(builtin.name(), Value::Procedure(Function::Plugin(builtin.clone())));
+        }
+        for (name, value) in env.constants().iter() {
+            self.set_global(name, Value::from_node(value));
+        }
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<Value> {
+        self.root.borrow().get(&name.to_string())
+    }
+
+    pub fn is_macro(&self, name: &str) -> bool {
+        matches!(self.get_global(name), Some(Value::Macro(_, _)))
+    }
+
+    // The global macros, with their parameters and templates, for stepping through their expansion. A macro whose
+    // body has more than one expression expands to a begin of them.
+    pub fn macros(&self) -> Vec<(String, Vec<String>, Node)> {
+        let mut macros = Vec::new();
+        for (name, value) in self.root.borrow().values.iter() {
+            if let Value::Macro(ref arg_names, ref body) = *value {
+                let body: Result<Vec<Node>, Value> = body.iter().map(Value::to_data).collect();
+                let template = match body {
+                    Ok(ref body) if body.len() == 1 => body[0].clone(),
+                    Ok(body) => Node::List(Some(Node::Identifier("begin".to_string())).into_iter().chain(body).collect()),
+                    Err(_) => continue
+                };
+                macros.push((name.clone(), (**arg_names).clone(), template));
+            }
+        }
+        macros
+    }
+
+    // The result of a call to a builtin, for fold, if its name is still bound to the builtin and it returns plain data
+    pub fn fold_call(&self, call: &Node) -> Option<Node> {
+        let name = match *call {
+            Node::List(ref items) => match items.first() {
+                Some(Node::Identifier(name)) => name,
+                _ => return None
+            },
+            _ => return None
+        };
+        match (self.get_global(name), builtin(name)) {
+            (Some(Value::Procedure(Function::Native(f))), Some(g)) if f as usize == g as usize => (),
+            _ => return None
+        }
+        self.run(slice::from_ref(call)).ok().and_then(|v| v.to_data().ok())
+    }
+
+    // Make the global environment iterate in the order given by the current random seed
+    pub fn rehash_globals(&self) {
+        self.root.borrow_mut().values.rehash();
     }
 }
 
-#[derive(PartialEq, Clone)]
+#[derive(Clone)]
 pub enum Value {
     Symbol(String),
     Integer(i64),
+    Rational(i64, i64),
+    Float(f64),
     Boolean(bool),
-    String(String),
-    List(Vec<Value>),
+    Character(char),
+    String(Rc<str>),
+    List(Rc<Items>),
+    // what (a b . c) and cons onto something other than a list make: a list of at least one item, and a tail that
+    // isn't a list
+    DottedList(Rc<Items>, Rc<Value>),
+    // what #(...) and vector make, with its elements indexed in constant time, and changed in place by vector-fill!
+    Vector(Rc<RefCell<Items>>),
+    // what #u8(...) makes: bytes, for binary data
+    Bytevector(Rc<Vec<u8>>),
     Procedure(Function),
-    Macro(Vec<String>, Vec<Value>),
+    Macro(Rc<Vec<String>>, Rc<Vec<Value>>),
+    EofObject,
+    Keyword(String),
+    Box(Rc<RefCell<Value>>),
+    // what a guard clause gets when an error is raised: its kind and message
+    Condition(Kind, Rc<str>),
+    // the result of (values ...) with other than one value, which call-with-values passes on as arguments
+    Values(Rc<Items>),
+    // what make-queue makes: elements go in at the back with enqueue! and come out at the front with dequeue!
+    Queue(Rc<RefCell<VecDeque<Value>>>),
+    // what make-set makes: distinct values, compared by structure as hash does
+    Set(Rc<RefCell<ValueSet>>),
+    // what make-heap makes: a priority queue whose least element, by its ordering procedure, comes out first
+    Heap(Rc<RefCell<Heap>>),
+    // what sqlite-open opens, closed when the last reference to it goes away
+    #[cfg(feature = "sqlite")]
+    Database(Rc<sqlite::Database>),
+    // what load-foreign-library loads, unloaded when neither it nor a procedure from it is referred to any more
+    #[cfg(feature = "ffi")]
+    Library(Rc<ffi::Library>),
+}
+
+// The elements of a list, dotted list, vector or multiple values. Dropping the last reference to them takes apart the
+// lists and vectors nested in them with a stack of its own, rather than recursing, so that a deeply nested list can't
+// overflow the Rust stack when it goes away.
+#[derive(Clone)]
+pub struct Items(Vec<Value>);
+
+impl Deref for Items {
+    type Target = Vec<Value>;
+
+    fn deref(&self) -> &Vec<Value> {
+        &self.0
+    }
+}
+
+impl DerefMut for Items {
+    fn deref_mut(&mut self) -> &mut Vec<Value> {
+        &mut self.0
+    }
+}
+
+impl fmt::Debug for Items {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Drop for Items {
+    fn drop(&mut self) {
+        let mut stack = mem::take(&mut self.0);
+        while let Some(value) = stack.pop() {
+            let (items, tail) = match value {
+                Value::List(items) | Value::Values(items) => (items, None),
+                Value::DottedList(items, tail) => (items, Some(tail)),
+                Value::Vector(items) => {
+                    if let Ok(items) = Rc::try_unwrap(items) {
+                        stack.append(&mut items.into_inner().0);
+                    }
+                    continue
+                },
+                _ => continue
+            };
+            // only what nothing else refers to is dropped now; the rest is left to its last reference
+            if let Ok(mut items) = Rc::try_unwrap(items) {
+                stack.append(&mut items.0);
+            }
+            if let Some(Ok(tail)) = tail.map(Rc::try_unwrap) {
+                stack.push(tail);
+            }
+        }
+    }
+}
+
+pub struct Heap {
+    // called as (less a b), and true when a should come out before b
+    less: Function,
+    // a binary heap: each element's children are at 2i + 1 and 2i + 2, and none comes out before its parent
+    items: Vec<Value>,
+}
+
+impl Heap {
+    // Swap two elements, unless the ordering procedure removed one of them while it was deciding
+    fn swap(&mut self, a: usize, b: usize) -> bool {
+        if a < self.items.len() && b < self.items.len() {
+            self.items.swap(a, b);
+            true
+        } else {
+            false
+        }
+    }
 }
 
 // null == empty list
-macro_rules! null { () => (Value::List(vec![])) }
+macro_rules! null { () => (Value::List(Rc::new(Items(vec![])))) }
 
 pub enum Function {
     Native(ValueOperation),
-    Scheme(Vec<String>, Vec<Value>, Rc<RefCell<Environment>>),
+    Scheme(Rc<Vec<String>>, Rc<Vec<Value>>, Rc<RefCell<Environment>>),
+    Plugin(Rc<plugin::Builtin>),
+    #[cfg(feature = "ffi")]
+    Foreign(Rc<ffi::Procedure>),
 }
 
 // type signature for all native functions
-type ValueOperation = fn(&[Value], Rc<RefCell<Environment>>) -> Result<Value, RuntimeError>;
+type ValueOperation = fn(&[Value], &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError>;
+
+type ValueSet = HashSet<Value, HashState>;
 
 impl Value {
     fn from_nodes(nodes: &[Node]) -> Vec<Value> {
         nodes.iter().map(Value::from_node).collect()
     }
 
+    fn from_number(n: Number) -> Value {
+        match n {
+            Number::Integer(i) => Value::Integer(i),
+            Number::Rational(n, d) => Value::Rational(n, d),
+            Number::Float(f) => Value::Float(f),
+        }
+    }
+
+    // The value as a node, if it's plain data, or else the part of it that isn't
+    fn to_data(&self) -> Result<Node, Value> {
+        match *self {
+            Value::Symbol(ref v) => Ok(Node::Identifier(v.clone())),
+            Value::Integer(v) => Ok(Node::Integer(v)),
+            Value::Rational(n, d) => Ok(Node::Rational(n, d)),
+            Value::Float(v) => Ok(Node::Float(v)),
+            Value::Boolean(v) => Ok(Node::Boolean(v)),
+            Value::Character(c) => Ok(Node::Character(c)),
+            Value::String(ref v) => Ok(Node::String(v.to_string())),
+            Value::List(ref list) => {
+                let res: Result<Vec<Node>, Value> = list.iter().map(Value::to_data).collect();
+                Ok(Node::List(try!(res)))
+            },
+            Value::DottedList(ref list, ref tail) => {
+                let res: Result<Vec<Node>, Value> = list.iter().map(Value::to_data).collect();
+                Ok(Node::DottedList(try!(res), Box::new(try!(tail.to_data()))))
+            },
+            Value::Vector(ref items) => {
+                let res: Result<Vec<Node>, Value> = items.borrow().iter().map(Value::to_data).collect();
+                Ok(Node::Vector(try!(res)))
+            },
+            Value::Bytevector(ref bytes) => Ok(Node::Bytevector(bytes.to_vec())),
+            Value::EofObject => Ok(Node::EofObject),
+            Value::Keyword(ref v) => Ok(Node::Keyword(v.clone())),
+            _ => Err(self.clone())
+        }
+    }
+
+    // The value as a node that can be sent to another thread, if it's plain data
+    #[cfg(feature = "parallel")]
+    fn to_node(&self) -> Result<Node, RuntimeError> {
+        self.to_data().map_err(|v| {
+            RuntimeError { kind: Kind::TypeError, message: message!("Can't send {:?} to another thread", v), frame: None }
+        })
+    }
+
     fn from_node(node: &Node) -> Value {
+        Value::from_labelled_node(node, &mut HashMap::new())
+    }
+
+    // Labelled data are recorded so references to them share them, and a labelled box or vector is made before its
+    // contents, so that references to it from inside them can share it too
+    fn from_labelled_node(node: &Node, labels: &mut HashMap<u32, Value>) -> Value {
         match *node {
             Node::Identifier(ref val) => Value::Symbol(val.clone()),
             Node::Integer(val) => Value::Integer(val),
+            Node::Rational(n, d) => Value::Rational(n, d),
+            Node::Float(val) => Value::Float(val),
             Node::Boolean(val) => Value::Boolean(val),
-            Node::String(ref val) => Value::String(val.clone()),
-            Node::List(ref nodes) => Value::List(Value::from_nodes(&nodes))
+            Node::Character(val) => Value::Character(val),
+            Node::String(ref val) => Value::String(Rc::from(val.as_str())),
+            Node::List(ref nodes) => Value::List(Rc::new(Items(nodes.iter().map(|n| Value::from_labelled_node(n, labels)).collect()))),
+            Node::DottedList(ref nodes, ref tail) => {
+                let items = nodes.iter().map(|n| Value::from_labelled_node(n, labels)).collect();
+                Value::DottedList(Rc::new(Items(items)), Rc::new(Value::from_labelled_node(tail, labels)))
+            },
+            Node::Vector(ref nodes) => {
+                Value::Vector(Rc::new(RefCell::new(Items(nodes.iter().map(|n| Value::from_labelled_node(n, labels)).collect()))))
+            },
+            Node::Bytevector(ref bytes) => Value::Bytevector(Rc::new(bytes.clone())),
+            Node::EofObject => Value::EofObject,
+            Node::Keyword(ref val) => Value::Keyword(val.clone()),
+            Node::Box(label, ref inner) => {
+                let cell = Rc::new(RefCell::new(null!()));
+                if let Some(n) = label {
+                    labels.insert(n, Value::Box(cell.clone()));
+                }
+                let contents = Value::from_labelled_node(inner, labels);
+                *cell.borrow_mut() = contents;
+                Value::Box(cell)
+            },
+            Node::Labelled(n, ref inner) => match **inner {
+                Node::Vector(ref nodes) => {
+                    let cell = Rc::new(RefCell::new(Items(Vec::new())));
+                    labels.insert(n, Value::Vector(cell.clone()));
+                    let items: Vec<Value> = nodes.iter().map(|n| Value::from_labelled_node(n, labels)).collect();
+                    cell.borrow_mut().extend(items);
+                    Value::Vector(cell)
+                },
+                _ => {
+                    let value = Value::from_labelled_node(inner, labels);
+                    labels.insert(n, value.clone());
+                    value
+                }
+            },
+            // the parser only makes references to data it has already started reading, but a hand-built node could
+            // refer to any label
+            Node::Reference(n) => {
+                match labels.get(&n) {
+                    Some(value) => value.clone(),
+                    None => null!()
+                }
+            },
+        }
+    }
+
+    // What (hash-by-identity v) hashes: the allocation behind lists, strings, boxes and procedures, and the value
+    // itself for everything else
+    fn identity_hash(&self) -> i64 {
+        let address = match *self {
+            Value::List(ref l) if !l.is_empty() => &**l as *const Items as usize,
+            Value::DottedList(ref l, _) => &**l as *const Items as usize,
+            Value::Vector(ref v) => &**v as *const RefCell<Items> as usize,
+            Value::Bytevector(ref b) => &**b as *const Vec<u8> as usize,
+            Value::String(ref s) => s.as_ptr() as usize,
+            Value::Box(ref b) => &**b as *const RefCell<Value> as usize,
+            Value::Queue(ref q) => &**q as *const RefCell<VecDeque<Value>> as usize,
+            Value::Set(ref s) => &**s as *const RefCell<HashSet<Value, HashState>> as usize,
+            Value::Heap(ref h) => &**h as *const RefCell<Heap> as usize,
+            #[cfg(feature = "sqlite")]
+            Value::Database(ref d) => &**d as *const sqlite::Database as usize,
+            #[cfg(feature = "ffi")]
+            Value::Library(ref l) => &**l as *const ffi::Library as usize,
+            #[cfg(feature = "ffi")]
+            Value::Procedure(Function::Foreign(ref p)) => &**p as *const ffi::Procedure as usize,
+            Value::Procedure(Function::Plugin(ref b)) => &**b as *const plugin::Builtin as usize,
+            Value::Procedure(Function::Native(f)) => f as usize,
+            Value::Procedure(Function::Scheme(ref arg_names, _, _)) => &**arg_names as *const Vec<String> as usize,
+            Value::Macro(ref arg_names, _) => &**arg_names as *const Vec<String> as usize,
+            _ => return random::stable_hash(self)
+        };
+        random::stable_hash(&address)
+    }
+
+    // What eqv? compares by: numbers, characters, symbols and the like by value, where an exact number is never eqv?
+    // to an inexact one, and everything else, strings included, by identity
+    fn is_eqv(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::List(a), Value::List(b)) => Rc::ptr_eq(a, b) || (a.is_empty() && b.is_empty()),
+            (Value::DottedList(a, _), Value::DottedList(b, _)) => Rc::ptr_eq(a, b),
+            (Value::Vector(a), Value::Vector(b)) => Rc::ptr_eq(a, b),
+            (Value::Values(a), Value::Values(b)) => Rc::ptr_eq(a, b),
+            (Value::String(a), Value::String(b)) => Rc::ptr_eq(a, b),
+            (Value::Bytevector(a), Value::Bytevector(b)) => Rc::ptr_eq(a, b),
+            (Value::Box(a), Value::Box(b)) => Rc::ptr_eq(a, b),
+            (Value::Macro(a, _), Value::Macro(b, _)) => Rc::ptr_eq(a, b),
+            _ => self == other
         }
     }
 }
@@ -68,14 +451,31 @@ impl fmt::Display for Value {
         match *self {
             Value::Symbol(ref val) => write!(f, "{}", val),
             Value::Integer(val)    => write!(f, "{}", val),
+            Value::Rational(n, d)  => write!(f, "{}/{}", n, d),
+            Value::Float(val)      => write!(f, "{}", printer::float(val)),
             Value::Boolean(val)    => write!(f, "#{}", if val { "t" } else { "f" }),
+            Value::Character(val)  => write!(f, "{}", val),
             Value::String(ref val) => write!(f, "{}", val),
-            Value::List(ref list)  => {
-                let strs: Vec<String> = list.iter().map(|v| format!("{}", v)).collect();
-                write!(f, "({})", &strs.connect(" "))
-            },
+            // lists, vectors and boxes can nest arbitrarily deep, so they're left to the printer, which doesn't recurse
+            Value::List(_) | Value::DottedList(..) | Value::Vector(_) => write!(f, "{}", printer::print_with(self, Style::Display, PrinterConfig::default())),
+            Value::Bytevector(ref bytes) => write!(f, "{}", printer::bytevector(bytes)),
             Value::Procedure(_)   => write!(f, "#<procedure>"),
             Value::Macro(_,_)     => write!(f, "#<macro>"),
+            Value::EofObject      => write!(f, "#<eof>"),
+            Value::Keyword(ref val) => write!(f, "#:{}", val),
+            Value::Condition(kind, ref message) => write!(f, "#<{}: {}>", kind.name(), message),
+            Value::Queue(_)       => write!(f, "#<queue>"),
+            Value::Set(_)         => write!(f, "#<set>"),
+            Value::Heap(_)        => write!(f, "#<heap>"),
+            #[cfg(feature = "sqlite")]
+            Value::Database(ref d) => write!(f, "{:?}", d),
+            #[cfg(feature = "ffi")]
+            Value::Library(ref l) => write!(f, "{:?}", l),
+            Value::Box(_)         => write!(f, "{}", printer::print_with(self, Style::Display, PrinterConfig::default())),
+            Value::Values(ref vals) => {
+                let printed: Vec<String> = vals.iter().map(|v| format!("{}", v)).collect();
+                write!(f, "{}", printed.join(" "))
+            },
         }
     }
 }
@@ -84,18 +484,182 @@ impl fmt::Debug for Value {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Value::String(ref val) => write!(f, "\"{}\"", val),
-            Value::List(ref list)  => {
-                let strs: Vec<String> = list.iter().map(|v| format!("{:?}", v)).collect();
-                write!(f, "({})", &strs.connect(" "))
+            Value::Symbol(ref val) => write!(f, "{}", printer::symbol(val)),
+            Value::Character(val)  => write!(f, "{}", printer::character(val)),
+            Value::List(_) | Value::DottedList(..) | Value::Vector(_) | Value::Box(_) => write!(f, "{}", printer::print_with(self, Style::Write, PrinterConfig::default())),
+            Value::Values(ref vals) => {
+                let printed: Vec<String> = vals.iter().map(|v| format!("{:?}", v)).collect();
+                write!(f, "{}", printed.join(" "))
             },
             _                      => write!(f, "{}", self)
         }
     }
 }
 
+// Compared with an explicit stack of pairs still to check rather than by recursing, so that deeply nested lists
+// can't overflow the Rust stack (the clones are cheap, since lists and boxes are reference counted)
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        let mut pending = vec![(self.clone(), other.clone())];
+        while let Some(pair) = pending.pop() {
+            let same = match pair {
+                (Value::List(ref a), Value::List(ref b)) => {
+                    if Rc::ptr_eq(a, b) {
+                        continue
+                    }
+                    if a.len() != b.len() {
+                        return false
+                    }
+                    pending.extend(a.iter().cloned().zip(b.iter().cloned()));
+                    true
+                },
+                (Value::DottedList(ref a, ref a_tail), Value::DottedList(ref b, ref b_tail)) => {
+                    pending.push((Value::List(a.clone()), Value::List(b.clone())));
+                    pending.push(((**a_tail).clone(), (**b_tail).clone()));
+                    true
+                },
+                (Value::Values(ref a), Value::Values(ref b)) => {
+                    if a.len() != b.len() {
+                        return false
+                    }
+                    pending.extend(a.iter().cloned().zip(b.iter().cloned()));
+                    true
+                },
+                (Value::Symbol(ref a), Value::Symbol(ref b)) => a == b,
+                (Value::Integer(a), Value::Integer(b)) => a == b,
+                (Value::Rational(an, ad), Value::Rational(bn, bd)) => an == bn && ad == bd,
+                // by their bits, so that equality stays an equivalence: +nan.0 equals itself, and 0.0 isn't -0.0
+                (Value::Float(a), Value::Float(b)) => a.to_bits() == b.to_bits(),
+                (Value::Boolean(a), Value::Boolean(b)) => a == b,
+                (Value::Character(a), Value::Character(b)) => a == b,
+                (Value::String(ref a), Value::String(ref b)) => a == b,
+                (Value::Bytevector(ref a), Value::Bytevector(ref b)) => a == b,
+                (Value::Procedure(ref a), Value::Procedure(ref b)) => a == b,
+                (Value::Macro(ref a1, ref a2), Value::Macro(ref b1, ref b2)) => a1 == b1 && a2 == b2,
+                (Value::EofObject, Value::EofObject) => true,
+                (Value::Keyword(ref a), Value::Keyword(ref b)) => a == b,
+                (Value::Condition(a1, ref a2), Value::Condition(b1, ref b2)) => a1 == b1 && a2 == b2,
+                // what boxes, vectors, queues, sets and heaps hold can change, so they're only equal to themselves
+                (Value::Box(ref a), Value::Box(ref b)) => Rc::ptr_eq(a, b),
+                (Value::Vector(ref a), Value::Vector(ref b)) => Rc::ptr_eq(a, b),
+                (Value::Queue(ref a), Value::Queue(ref b)) => Rc::ptr_eq(a, b),
+                (Value::Set(ref a), Value::Set(ref b)) => Rc::ptr_eq(a, b),
+                (Value::Heap(ref a), Value::Heap(ref b)) => Rc::ptr_eq(a, b),
+                #[cfg(feature = "sqlite")]
+                (Value::Database(ref a), Value::Database(ref b)) => Rc::ptr_eq(a, b),
+                #[cfg(feature = "ffi")]
+                (Value::Library(ref a), Value::Library(ref b)) => Rc::ptr_eq(a, b),
+                _ => false
+            };
+            if !same {
+                return false
+            }
+        }
+        true
+    }
+}
+
+impl Eq for Value {}
+
+// Consistent with ==: lists and strings are hashed by structure, and boxes, vectors and procedures by identity. Macros are
+// only hashed by their kind, which is coarse, but agrees with ==.
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let mut pending = vec![self.clone()];
+        while let Some(value) = pending.pop() {
+            match value {
+                Value::Symbol(ref s) => { HashTag::Symbol.hash(state); s.hash(state) },
+                Value::Integer(i) => { HashTag::Integer.hash(state); i.hash(state) },
+                Value::Rational(n, d) => { HashTag::Rational.hash(state); n.hash(state); d.hash(state) },
+                Value::Float(f) => { HashTag::Float.hash(state); f.to_bits().hash(state) },
+                Value::Boolean(b) => { HashTag::Boolean.hash(state); b.hash(state) },
+                Value::Character(c) => { HashTag::Character.hash(state); c.hash(state) },
+                Value::String(ref s) => { HashTag::String.hash(state); s.hash(state) },
+                Value::List(ref l) => {
+                    HashTag::List.hash(state);
+                    l.len().hash(state);
+                    pending.extend(l.iter().cloned());
+                },
+                Value::Vector(ref v) => { HashTag::Vector.hash(state); (&**v as *const RefCell<Items> as usize).hash(state) },
+                Value::Bytevector(ref b) => { HashTag::Bytevector.hash(state); b.hash(state) },
+                Value::DottedList(ref l, ref tail) => {
+                    HashTag::DottedList.hash(state);
+                    pending.push((**tail).clone());
+                    pending.push(Value::List(l.clone()));
+                },
+                Value::Procedure(ref p) => { HashTag::Procedure.hash(state); p.hash(state) },
+                Value::Macro(_, _) => HashTag::Macro.hash(state),
+                Value::EofObject => HashTag::EofObject.hash(state),
+                Value::Keyword(ref k) => { HashTag::Keyword.hash(state); k.hash(state) },
+                Value::Condition(kind, ref message) => { HashTag::Condition.hash(state); kind.hash(state); message.hash(state) },
+                Value::Values(ref vals) => {
+                    HashTag::Values.hash(state);
+                    vals.len().hash(state);
+                    pending.extend(vals.iter().cloned());
+                },
+                Value::Queue(ref q) => { HashTag::Queue.hash(state); (&**q as *const RefCell<VecDeque<Value>> as usize).hash(state) },
+                Value::Set(ref s) => { HashTag::Set.hash(state); (&**s as *const RefCell<HashSet<Value, HashState>> as usize).hash(state) },
+                Value::Heap(ref h) => { HashTag::Heap.hash(state); (&**h as *const RefCell<Heap> as usize).hash(state) },
+                #[cfg(feature = "sqlite")]
+                Value::Database(ref d) => { HashTag::Database.hash(state); (&**d as *const sqlite::Database as usize).hash(state) },
+                #[cfg(feature = "ffi")]
+                Value::Library(ref l) => { HashTag::Library.hash(state); (&**l as *const ffi::Library as usize).hash(state) },
+                Value::Box(ref b) => { HashTag::Box.hash(state); (&**b as *const RefCell<Value> as usize).hash(state) },
+            }
+        }
+    }
+}
+
+impl Printable for Value {
+    fn shape(&self, style: Style) -> Shape<Value> {
+        match *self {
+            Value::List(ref list) => Shape::List(list_id(list), list.to_vec()),
+            Value::DottedList(ref list, ref tail) => Shape::DottedList(list_id(list), list.to_vec(), (**tail).clone()),
+            Value::Vector(ref items) => Shape::Vector(Some(&**items as *const RefCell<Items> as usize), items.borrow().to_vec()),
+            Value::Box(ref b) => Shape::Box(&**b as *const RefCell<Value> as usize, b.borrow().clone()),
+            Value::String(_) | Value::Character(_) | Value::Symbol(_) if style == Style::Display => Shape::Atom(format!("{}", self)),
+            Value::String(ref s) => Shape::String(s.as_ptr() as usize, format!("{:?}", self)),
+            Value::Procedure(Function::Scheme(ref params, ref body, _)) if printer::config().show_procedures => {
+                Shape::Atom(printer::procedure(params, body.iter()))
+            },
+            _ => Shape::Atom(format!("{:?}", self))
+        }
+    }
+}
+
+// The address of a non-empty list's items, which the printer uses to find shared lists
+fn list_id(list: &Rc<Items>) -> Option<usize> {
+    if list.is_empty() { None } else { Some(&**list as *const Items as usize) }
+}
+
+// A procedure is only equal to itself: a closure made by evaluating the same lambda in the same environment
 impl PartialEq for Function {
     fn eq(&self, other: &Function) -> bool {
-        self == other
+        match (self, other) {
+            (&Function::Native(a), &Function::Native(b)) => a as usize == b as usize,
+            (Function::Scheme(a_params, _, a_env), Function::Scheme(b_params, _, b_env)) => {
+                Rc::ptr_eq(a_params, b_params) && Rc::ptr_eq(a_env, b_env)
+            },
+            (Function::Plugin(a), Function::Plugin(b)) => Rc::ptr_eq(a, b),
+            #[cfg(feature = "ffi")]
+            (Function::Foreign(a), Function::Foreign(b)) => Rc::ptr_eq(a, b),
+            _ => false
+        }
+    }
+}
+
+impl Hash for Function {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match *self {
+            Function::Native(f) => (f as usize).hash(state),
+            Function::Scheme(ref params, _, ref env) => {
+                (&**params as *const Vec<String> as usize).hash(state);
+                (&**env as *const RefCell<Environment> as usize).hash(state);
+            },
+            Function::Plugin(ref b) => (&**b as *const plugin::Builtin as usize).hash(state),
+            #[cfg(feature = "ffi")]
+            Function::Foreign(ref p) => (&**p as *const ffi::Procedure as usize).hash(state)
+        }
     }
 }
 
@@ -103,13 +667,41 @@ impl Clone for Function {
     fn clone(&self) -> Function {
         match *self {
             Function::Native(ref func) => Function::Native(*func),
-            Function::Scheme(ref a, ref b, ref env) => Function::Scheme(a.clone(), b.clone(), env.clone())
+            Function::Scheme(ref a, ref b, ref env) => Function::Scheme(a.clone(), b.clone(), env.clone()),
+            Function::Plugin(ref b) => Function::Plugin(b.clone()),
+            #[cfg(feature = "ffi")]
+            Function::Foreign(ref p) => Function::Foreign(p.clone())
         }
     }
 }
 
 pub struct RuntimeError {
+    kind: Kind,
     message: String,
+    // Where it happened, for the REPL to inspect
+    frame: Option<Box<Frame>>,
+}
+
+impl RuntimeError {
+    pub fn kind(&self) -> Kind {
+        self.kind
+    }
+
+    pub fn take_frame(&mut self) -> Option<Frame> {
+        self.frame.take().map(|f| *f)
+    }
+
+    // Record the call the error happened in, unless it happened in a call nested inside that one
+    fn in_frame<F: FnOnce() -> Value>(mut self, expression: F, env: &Rc<RefCell<Environment>>) -> RuntimeError {
+        if self.frame.is_none() {
+            self.frame = Some(Box::new(Frame { expression: expression(), env: env.clone() }));
+        }
+        self
+    }
+
+    fn to_condition(&self) -> Value {
+        Value::Condition(self.kind, Rc::from(self.message.as_str()))
+    }
 }
 
 impl fmt::Display for RuntimeError {
@@ -123,64 +715,85 @@ impl fmt::Debug for RuntimeError {
     }
 }
 
+// The innermost call being evaluated when an error happened, and the environment it was evaluated in
+pub struct Frame {
+    expression: Value,
+    env: Rc<RefCell<Environment>>,
+}
+
+impl Frame {
+    pub fn expression(&self) -> String {
+        printer::print(&self.expression, Style::Write)
+    }
+
+    // The local variables visible in the frame and their values, innermost first and then by name (globals are left
+    // out)
+    pub fn locals(&self) -> Vec<(String, String)> {
+        let mut locals: Vec<(String, String)> = Vec::new();
+        let mut env = self.env.clone();
+        loop {
+            let parent = match env.borrow().parent {
+                Some(ref parent) => parent.clone(),
+                None => break
+            };
+            let mut level: Vec<(String, String)> = env.borrow().values.iter()
+                .filter(|&(name, _)| !locals.iter().any(|l| l.0 == *name))
+                .map(|(name, value)| (name.clone(), printer::print(value, Style::Write)))
+                .collect();
+            level.sort();
+            locals.extend(level);
+            env = parent;
+        }
+        locals
+    }
+}
+
 macro_rules! runtime_error {
     ($($arg:tt)*) => (
-        return Err(RuntimeError { message: format!($($arg)*)})
+        return Err(RuntimeError { kind: Kind::Error, message: message!($($arg)*), frame: None})
+    )
+}
+
+// Errors of a more specific kind (see condition.rs), which guard clauses can test for
+macro_rules! raise {
+    ($kind:expr, $($arg:tt)*) => (
+        return Err(RuntimeError { kind: $kind, message: message!($($arg)*), frame: None})
     )
 }
+macro_rules! file_error { ($($arg:tt)*) => (raise!(Kind::FileError, $($arg)*)) }
+macro_rules! read_error { ($($arg:tt)*) => (raise!(Kind::ReadError, $($arg)*)) }
+macro_rules! arity_error { ($($arg:tt)*) => (raise!(Kind::ArityError, $($arg)*)) }
+macro_rules! type_error { ($($arg:tt)*) => (raise!(Kind::TypeError, $($arg)*)) }
 
 struct Environment {
     parent: Option<Rc<RefCell<Environment>>>,
-    values: HashMap<String, Value>,
+    values: Bindings<Value>,
+    // only used in the root environment: maps not-yet-loaded names to the file that defines them
+    autoloads: HashMap<String, String>,
+    // names bound by a letrec whose values are still being evaluated; reading or setting one is an error
+    uninitialized: Vec<String>,
+    // only used in the root environment: what the interpreter's builtins may reach outside the process for
+    #[cfg_attr(not(any(feature = "net", feature = "ffi")), allow(dead_code))]
+    capabilities: Capabilities,
 }
 
 impl Environment {
     fn new_root() -> Rc<RefCell<Environment>> {
-        let mut env = Environment { parent: None, values: HashMap::new() };
-        let predefined_functions = &[
-            ("define", Function::Native(native_define)),
-            ("define-syntax-rule", Function::Native(native_define_syntax_rule)),
-            ("begin", Function::Native(native_begin)),
-            ("let", Function::Native(native_let)),
-            ("set!", Function::Native(native_set)),
-            ("lambda", Function::Native(native_lambda)),
-            ("λ", Function::Native(native_lambda)),
-            ("if", Function::Native(native_if)),
-            ("+", Function::Native(native_plus)),
-            ("-", Function::Native(native_minus)),
-            ("*", Function::Native(native_multiply)),
-            ("/", Function::Native(native_divide)),
-            ("<", Function::Native(native_lessthan)),
-            (">", Function::Native(native_greaterthan)),
-            ("=", Function::Native(native_equal)),
-            ("and", Function::Native(native_and)),
-            ("or", Function::Native(native_or)),
-            ("null?", Function::Native(native_null)),
-            ("list", Function::Native(native_list)),
-            ("car", Function::Native(native_car)),
-            ("cdr", Function::Native(native_cdr)),
-            ("cons", Function::Native(native_cons)),
-            ("append", Function::Native(native_append)),
-            ("quote", Function::Native(native_quote)),
-            ("quasiquote", Function::Native(native_quasiquote)),
-            ("error", Function::Native(native_error)),
-            ("apply", Function::Native(native_apply)),
-            ("eval", Function::Native(native_eval)),
-            ("write", Function::Native(native_write)),
-            ("display", Function::Native(native_display)),
-            ("displayln", Function::Native(native_displayln)),
-            ("print", Function::Native(native_print)),
-            ("newline", Function::Native(native_newline)),
-            ];
-        for item in predefined_functions.iter() {
-            let (name, ref func) = *item;
-            env.define(name.to_string(), Value::Procedure(func.clone())).unwrap();
+        let mut env = Environment { parent: None, values: Bindings::new(), autoloads: HashMap::new(), uninitialized: Vec::new(),
+                                    capabilities: Capabilities::default() };
+        for group in [CORE_FUNCTIONS, MATH_FUNCTIONS, IO_FUNCTIONS, NET_FUNCTIONS, PROCESS_FUNCTIONS, PARALLEL_FUNCTIONS, SQLITE_FUNCTIONS,
+                  FFI_FUNCTIONS].iter() {
+            for &(name, func) in group.iter() {
+                env.define(name.to_string(), Value::Procedure(Function::Native(func))).unwrap();
+            }
         }
         Rc::new(RefCell::new(env))
     }
 
     fn new_child(parent: Rc<RefCell<Environment>>) -> Rc<RefCell<Environment>> {
-        let env = Environment { parent: Some(parent), values: HashMap::new() };
+        stats::count_allocations(1);
+        let env = Environment { parent: Some(parent), values: Bindings::new(), autoloads: HashMap::new(), uninitialized: Vec::new(),
+                                capabilities: Capabilities::default() };
         Rc::new(RefCell::new(env))
     }
 
@@ -192,6 +805,11 @@ impl Environment {
         if self.values.contains_key(&key) {
             runtime_error!("Duplicate define: {:?}", key)
         } else {
+            if self.parent.is_some() && warning::enabled() {
+                if let Some(Value::Procedure(Function::Native(_))) = self.get(&key) {
+                    warning::warn_once(format!("Local variable {} shadows a builtin procedure", key))
+                }
+            }
             self.values.insert(key, value);
             Ok(())
         }
@@ -199,7 +817,9 @@ impl Environment {
 
     // Set a variable to a value, at any level in the env, or throw a runtime error if it isn't defined at all
     fn set(&mut self, key: String, value: Value) -> Result<(), RuntimeError>  {
-        if self.values.contains_key(&key) {
+        if self.uninitialized.contains(&key) {
+            runtime_error!("Can't set! {} before its letrec initialization finished", key)
+        } else if self.values.contains_key(&key) {
             self.values.insert(key, value);
             Ok(())
         } else {
@@ -211,9 +831,11 @@ impl Environment {
         }
     }
 
+    // Cloning the value is cheap, since anything big inside it is reference counted
     fn get(&self, key: &String) -> Option<Value> {
         match self.values.get(key) {
             Some(val) => Some(val.clone()),
+            None if self.uninitialized.contains(key) => None,
             None => {
                 // recurse up the environment tree until a value is found or the end is reached
                 match self.parent {
@@ -224,112 +846,561 @@ impl Environment {
         }
     }
 
-    fn get_root(env_ref: Rc<RefCell<Environment>>) -> Rc<RefCell<Environment>> {
+    // whether key refers to a letrec variable that doesn't have its value yet
+    fn is_uninitialized(&self, key: &String) -> bool {
+        if self.values.contains_key(key) {
+            false
+        } else if self.uninitialized.contains(key) {
+            true
+        } else {
+            match self.parent {
+                Some(ref parent) => parent.borrow().is_uninitialized(key),
+                None => false
+            }
+        }
+    }
+
+    // give a letrec variable its value, making it usable
+    fn initialize(&mut self, key: String, value: Value) -> Result<(), RuntimeError> {
+        self.uninitialized.retain(|k| *k != key);
+        self.define(key, value)
+    }
+
+    // every name visible from here, including ones a library would define when autoloaded
+    fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.values.iter().map(|(k, _)| k.clone()).collect();
+        names.extend(self.autoloads.keys().cloned());
+        if let Some(ref parent) = self.parent {
+            names.extend(parent.borrow().names());
+        }
+        names
+    }
+
+    fn get_root(env_ref: &Rc<RefCell<Environment>>) -> Rc<RefCell<Environment>> {
         let env = env_ref.borrow();
         match env.parent {
-            Some(ref parent) => Environment::get_root(parent.clone()),
+            Some(ref parent) => Environment::get_root(parent),
             None => env_ref.clone()
         }
     }
 }
 
-fn evaluate_values(values: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+// native functions, grouped by the crate feature that compiles them in
+const CORE_FUNCTIONS: &[(&str, ValueOperation)] = &[
+    ("define", native_define),
+    ("define-syntax-rule", native_define_syntax_rule),
+    ("autoload", native_autoload),
+    ("begin", native_begin),
+    ("let", native_let),
+    ("letrec", native_letrec),
+    ("letrec*", native_letrec_star),
+    ("set!", native_set),
+    ("lambda", native_lambda),
+    ("λ", native_lambda),
+    ("if", native_if),
+    ("and", native_and),
+    ("or", native_or),
+    ("null?", native_null),
+    ("eof-object", native_eof_object),
+    ("eof-object?", native_is_eof_object),
+    ("keyword?", native_is_keyword),
+    ("keyword->string", native_keyword_to_string),
+    ("string->keyword", native_string_to_keyword),
+    ("char?", native_is_char),
+    ("char->integer", native_char_to_integer),
+    ("integer->char", native_integer_to_char),
+    ("char-alphabetic?", native_is_char_alphabetic),
+    ("char-numeric?", native_is_char_numeric),
+    ("char-whitespace?", native_is_char_whitespace),
+    ("char-upcase", native_char_upcase),
+    ("char-downcase", native_char_downcase),
+    ("gensym", native_gensym),
+    ("hash", native_hash),
+    ("hash-by-identity", native_hash_by_identity),
+    ("eqv?", native_is_eqv),
+    ("features", native_features),
+    ("box", native_box),
+    ("box?", native_is_box),
+    ("unbox", native_unbox),
+    ("set-box!", native_set_box),
+    ("make-queue", native_make_queue),
+    ("queue?", native_is_queue),
+    ("queue-length", native_queue_length),
+    ("enqueue!", native_enqueue),
+    ("dequeue!", native_dequeue),
+    ("queue->list", native_queue_to_list),
+    ("make-set", native_make_set),
+    ("set?", native_is_set),
+    ("set-add!", native_set_add),
+    ("set-contains?", native_set_contains),
+    ("set-union", native_set_union),
+    ("set-intersection", native_set_intersection),
+    ("set->list", native_set_to_list),
+    ("make-heap", native_make_heap),
+    ("heap?", native_is_heap),
+    ("heap-size", native_heap_size),
+    ("heap-push!", native_heap_push),
+    ("heap-pop!", native_heap_pop),
+    ("heap-peek", native_heap_peek),
+    ("string-foldcase", native_string_foldcase),
+    ("string-length", native_string_length),
+    ("substring", native_substring),
+    ("string-copy", native_string_copy),
+    ("string->list", native_string_to_list),
+    ("list->string", native_list_to_string),
+    ("vector?", native_is_vector),
+    ("vector", native_vector),
+    ("vector-length", native_vector_length),
+    ("vector-ref", native_vector_ref),
+    ("vector->list", native_vector_to_list),
+    ("list->vector", native_list_to_vector),
+    ("vector-copy", native_vector_copy),
+    ("vector-fill!", native_vector_fill),
+    ("vector-copy!", native_vector_copy_to),
+    ("vector-append", native_vector_append),
+    ("vector-push!", native_vector_push),
+    ("vector-pop!", native_vector_pop),
+    ("bytevector?", native_is_bytevector),
+    ("bytevector", native_bytevector),
+    ("bytevector-length", native_bytevector_length),
+    ("bytevector-u8-ref", native_bytevector_u8_ref),
+    ("bytevector-copy", native_bytevector_copy),
+    ("display->string", native_display_to_string),
+    ("value->cbor", native_value_to_cbor),
+    ("cbor->value", native_cbor_to_value),
+    ("document", native_document),
+    ("length+", native_length_plus),
+    ("list-copy", native_list_copy),
+    ("last-pair", native_last_pair),
+    ("proper-list?", native_is_proper_list),
+    ("circular-list?", native_is_circular_list),
+    ("dotted-list?", native_is_dotted_list),
+    ("tree-size", native_tree_size),
+    ("depth", native_depth),
+    ("string-ci=?", native_string_ci_equal),
+    ("string-ci<?", native_string_ci_lessthan),
+    ("string-ci>?", native_string_ci_greaterthan),
+    ("string-ci<=?", native_string_ci_lessthan_or_equal),
+    ("string-ci>=?", native_string_ci_greaterthan_or_equal),
+    ("list", native_list),
+    ("car", native_car),
+    ("cdr", native_cdr),
+    ("cons", native_cons),
+    ("append", native_append),
+    ("reverse", native_reverse),
+    ("quote", native_quote),
+    ("quasiquote", native_quasiquote),
+    ("error", native_error),
+    ("warn", native_warn),
+    ("log-debug", native_log_debug),
+    ("log-info", native_log_info),
+    ("log-warn", native_log_warn),
+    ("log-error", native_log_error),
+    ("raise", native_raise),
+    ("assertion-violation", native_assertion_violation),
+    ("guard", native_guard),
+    ("error-object-message", native_error_object_message),
+    ("error?", native_is_error),
+    ("file-error?", native_is_file_error),
+    ("read-error?", native_is_read_error),
+    ("assertion-violation?", native_is_assertion_violation),
+    ("arity-error?", native_is_arity_error),
+    ("type-error?", native_is_type_error),
+    ("apply", native_apply),
+    ("eval", native_eval),
+    ("values", native_values),
+    ("call-with-values", native_call_with_values),
+    ("+", native_plus),
+    ("-", native_minus),
+    ("*", native_multiply),
+    ("/", native_divide),
+    ("<", native_lessthan),
+    (">", native_greaterthan),
+    ("=", native_equal),
+    ];
+
+#[cfg(feature = "math")]
+const MATH_FUNCTIONS: &[(&str, ValueOperation)] = &[
+    ("random", native_random),
+    ("floor/", native_floor_divide),
+    ("truncate/", native_truncate_divide),
+    ("exact-integer-sqrt", native_exact_integer_sqrt),
+    ("number->string", native_number_to_string),
+    ];
+#[cfg(not(feature = "math"))]
+const MATH_FUNCTIONS: &[(&str, ValueOperation)] = &[];
+
+#[cfg(feature = "io")]
+const IO_FUNCTIONS: &[(&str, ValueOperation)] = &[
+    ("write", native_write),
+    ("write-simple", native_write_simple),
+    ("write-shared", native_write_shared),
+    ("display", native_display),
+    ("displayln", native_displayln),
+    ("print", native_print),
+    ("newline", native_newline),
+    ("read", native_read),
+    ("read-char", native_read_char),
+    ("write-canonical", native_write_canonical),
+    ("read-canonical", native_read_canonical),
+    ("csv-read", native_csv_read),
+    ("csv-write", native_csv_write),
+    ("directory-walk", native_directory_walk),
+    ("glob", native_glob),
+    ("path-join", native_path_join),
+    ("path-directory", native_path_directory),
+    ("path-filename", native_path_filename),
+    ("path-extension", native_path_extension),
+    ("path-absolute?", native_path_absolute),
+    ("expand-user-path", native_expand_user_path),
+    ("printer-option", native_printer_option),
+    ("set-printer-option!", native_set_printer_option),
+    ];
+#[cfg(not(feature = "io"))]
+const IO_FUNCTIONS: &[(&str, ValueOperation)] = &[];
+
+#[cfg(feature = "net")]
+const NET_FUNCTIONS: &[(&str, ValueOperation)] = &[
+    ("http-get", native_http_get),
+    ("http-post", native_http_post),
+    ("serve-http", native_serve_http),
+    ];
+#[cfg(not(feature = "net"))]
+const NET_FUNCTIONS: &[(&str, ValueOperation)] = &[];
+
+#[cfg(all(unix, feature = "process"))]
+const PROCESS_FUNCTIONS: &[(&str, ValueOperation)] = &[
+    ("on-signal", native_on_signal),
+    ];
+#[cfg(not(all(unix, feature = "process")))]
+const PROCESS_FUNCTIONS: &[(&str, ValueOperation)] = &[];
+
+#[cfg(feature = "parallel")]
+const PARALLEL_FUNCTIONS: &[(&str, ValueOperation)] = &[
+    ("par-map", native_par_map),
+    ];
+#[cfg(not(feature = "parallel"))]
+const PARALLEL_FUNCTIONS: &[(&str, ValueOperation)] = &[];
+
+#[cfg(feature = "sqlite")]
+const SQLITE_FUNCTIONS: &[(&str, ValueOperation)] = &[
+    ("sqlite-open", native_sqlite_open),
+    ("sqlite-exec", native_sqlite_exec),
+    ("sqlite-query", native_sqlite_query),
+    ];
+#[cfg(not(feature = "sqlite"))]
+const SQLITE_FUNCTIONS: &[(&str, ValueOperation)] = &[];
+
+#[cfg(feature = "ffi")]
+const FFI_FUNCTIONS: &[(&str, ValueOperation)] = &[
+    ("load-foreign-library", native_load_foreign_library),
+    ("foreign-procedure", native_foreign_procedure),
+    ];
+#[cfg(not(feature = "ffi"))]
+const FFI_FUNCTIONS: &[(&str, ValueOperation)] = &[];
+
+fn evaluate_values(values: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
     let mut res = null!();
     for v in values.iter() {
-        res = try!(evaluate_value(v, env.clone()));
+        res = try!(evaluate_value(v, env));
     }
     Ok(res)
 }
 
-fn evaluate_value(value: &Value, env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+// The error for a name bound nowhere, mentioning any bound names (or aliases) it's probably a typo of
+fn identifier_not_found(name: &str, env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    let names = env.borrow().names();
+    let candidates = names.iter().map(|n| n.as_str()).chain(aliases::ALIASES.iter().map(|a| a.name));
+    let suggestions = text::closest(name, candidates);
+    if suggestions.is_empty() {
+        runtime_error!("Identifier not found: {}", name)
+    } else {
+        runtime_error!("Identifier not found: {} (did you mean {}?)", name, suggestions.join(", "))
+    }
+}
+
+fn evaluate_value(value: &Value, env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    stats::count_step();
+    #[cfg(all(unix, feature = "process"))]
+    {
+        if signals::pending() {
+            try!(handle_signals(env));
+        }
+    }
     match value {
         &Value::Symbol(ref v) => {
-            match env.borrow().get(v) {
+            let found = env.borrow().get(v);
+            match found {
                 Some(val) => Ok(val),
-                None => runtime_error!("Identifier not found: {:?}", value)
+                None if env.borrow().is_uninitialized(v) => {
+                    runtime_error!("{} was used before its letrec initialization finished", v)
+                },
+                None => {
+                    match try!(autoload(env, v)) {
+                        Some(val) => Ok(val),
+                        None => {
+                            match aliases::find(v) {
+                                Some(alias) => evaluate_value(&Value::Symbol(alias.resolve().to_string()), env),
+                                None => identifier_not_found(v, env)
+                            }
+                        }
+                    }
+                }
             }
         },
         &Value::Integer(v) => Ok(Value::Integer(v)),
+        &Value::Rational(n, d) => Ok(Value::Rational(n, d)),
+        &Value::Float(v) => Ok(Value::Float(v)),
         &Value::Boolean(v) => Ok(Value::Boolean(v)),
+        &Value::Character(c) => Ok(Value::Character(c)),
         &Value::String(ref v) => Ok(Value::String(v.clone())),
         &Value::List(ref vec) => {
             if vec.len() > 0 {
-                evaluate_expression(vec, env.clone())
+                evaluate_expression(vec, env)
             } else {
                 Ok(null!())
             }
         },
-        &Value::Procedure(ref v) => Ok(Value::Procedure(v.clone())),
-        &Value::Macro(ref a, ref b) => Ok(Value::Macro(a.clone(), b.clone())),
+        // vectors evaluate to themselves, as in R7RS
+        &Value::DottedList(..) => runtime_error!("Can't evaluate a dotted list: {:?}", value),
+        Value::Vector(v) => Ok(Value::Vector(v.clone())),
+        Value::Bytevector(v) => Ok(Value::Bytevector(v.clone())),
+        Value::Procedure(v) => Ok(Value::Procedure(v.clone())),
+        Value::Macro(a, b) => Ok(Value::Macro(a.clone(), b.clone())),
+        &Value::EofObject => Ok(Value::EofObject),
+        Value::Keyword(v) => Ok(Value::Keyword(v.clone())),
+        Value::Box(v) => Ok(Value::Box(v.clone())),
+        &Value::Condition(kind, ref message) => Ok(Value::Condition(kind, message.clone())),
+        Value::Values(vals) => Ok(Value::Values(vals.clone())),
+        Value::Queue(q) => Ok(Value::Queue(q.clone())),
+        Value::Set(s) => Ok(Value::Set(s.clone())),
+        Value::Heap(h) => Ok(Value::Heap(h.clone())),
+        #[cfg(feature = "sqlite")]
+        Value::Database(d) => Ok(Value::Database(d.clone())),
+        #[cfg(feature = "ffi")]
+        Value::Library(l) => Ok(Value::Library(l.clone())),
     }
 }
 
-fn quote_value(value: &Value, quasi: bool, env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
-    match value {
-        &Value::Symbol(ref v) => Ok(Value::Symbol(v.clone())),
-        &Value::Integer(v) => Ok(Value::Integer(v)),
-        &Value::Boolean(v) => Ok(Value::Boolean(v)),
-        &Value::String(ref v) => Ok(Value::String(v.clone())),
-        &Value::List(ref vec) => {
-            // check if we are unquoting inside a quasiquote
-            if quasi && vec.len() > 0 && vec[0] == Value::Symbol("unquote".to_string()) {
+// look up an unbound name in the autoload registry, and if a library provides it, load that library into the root environment and try again
+fn autoload(env: &Rc<RefCell<Environment>>, name: &String) -> Result<Option<Value>, RuntimeError> {
+    let root = Environment::get_root(env);
+    let path = match root.borrow().autoloads.get(name) {
+        Some(path) => path.clone(),
+        None => return Ok(None)
+    };
+
+    // set aside every name provided by the same library while it loads, so it's only loaded once, and put them
+    // back if loading fails so that a later reference can try again
+    let pending: Vec<(String, String)> = root.borrow().autoloads.iter().filter(|&(_, p)| *p == path)
+        .map(|(n, p)| (n.clone(), p.clone())).collect();
+    root.borrow_mut().autoloads.retain(|_, p| *p != path);
+    match load_file(&path, &root) {
+        Ok(_) => (),
+        Err(e) => {
+            root.borrow_mut().autoloads.extend(pending);
+            return Err(e)
+        }
+    }
+    let found = root.borrow().get(name);
+    Ok(found)
+}
+
+fn load_file(name: &str, env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    let path = search_path::resolve(Path::new(""), name);
+    watch::record(&path);
+    let mut contents = String::new();
+    match File::open(&path).and_then(|mut f| f.read_to_string(&mut contents)) {
+        Ok(_) => (),
+        Err(e) => file_error!("Couldn't load {}: {}", path.display(), e)
+    }
+    let tokens = match lexer::tokenize(&contents) {
+        Ok(tokens) => tokens,
+        Err(e) => read_error!("{}", e)
+    };
+    let nodes = match parse(&tokens) {
+        Ok(nodes) => nodes,
+        Err(e) => read_error!("{}", e)
+    };
+    let nodes = match expand::expand_includes(nodes, Some(&path), &ReaderConfig::default()) {
+        Ok(nodes) => nodes,
+        Err(e) => raise!(e.kind(), "{}", e)
+    };
+    evaluate_values(&Value::from_nodes(&nodes), env)
+}
+
+fn quote_value(value: &Value, quasi: bool, env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    // quoted data is shared rather than copied
+    if !quasi {
+        return Ok(value.clone())
+    }
+
+    // Rebuild the template with an explicit stack of partly built lists rather than by recursing, so that deeply
+    // nested templates can't overflow the Rust stack. Each list has the tail it ends in, if it's dotted: a datum, or
+    // an expression to evaluate for `(a . ,x), which reads as (a unquote x)
+    type Frame<'a> = (slice::Iter<'a, Value>, Vec<Value>, Option<(&'a Value, bool)>);
+    let mut stack: Vec<Frame> = Vec::new();
+    let mut next = Some(value);
+    loop {
+        let done = match next.take() {
+            Some(Value::List(vec)) if !vec.is_empty() && vec[0] == Value::Symbol("unquote".to_string()) => {
                 if vec.len() != 2 {
-                    runtime_error!("Must supply exactly one argument to unquote: {:?}", vec);
+                    arity_error!("Must supply exactly one argument to unquote: {:?}", vec);
+                }
+                Some(try!(evaluate_value(&vec[1], env)))
+            },
+            Some(Value::List(vec)) if !vec.is_empty() && vec[0] == Value::Symbol("unquote-splicing".to_string()) => {
+                if vec.len() != 2 {
+                    arity_error!("Must supply exactly one argument to unquote-splicing: {:?}", vec);
+                }
+                let frame = match stack.last_mut() {
+                    Some(frame) => frame,
+                    None => runtime_error!("Can't use unquote-splicing outside a quasiquoted list: {:?}", value)
+                };
+                // the items of the list are added to the one being built, in place of the template
+                match try!(evaluate_value(&vec[1], env)) {
+                    Value::List(ref items) => frame.1.extend(items.iter().cloned()),
+                    v => type_error!("Can't splice a non-list into a quasiquoted list: {:?}", v)
+                }
+                None
+            },
+            Some(Value::List(vec)) => {
+                let unquote = Value::Symbol("unquote".to_string());
+                if vec.len() >= 3 && vec[vec.len() - 2] == unquote {
+                    stack.push((vec[..vec.len() - 2].iter(), Vec::with_capacity(vec.len()), Some((&vec[vec.len() - 1], true))));
+                } else {
+                    stack.push((vec.iter(), Vec::with_capacity(vec.len()), None));
+                }
+                None
+            },
+            Some(Value::DottedList(vec, tail)) => {
+                stack.push((vec.iter(), Vec::with_capacity(vec.len()), Some((&**tail, false))));
+                None
+            },
+            Some(v) => Some(v.clone()),
+            None => None
+        };
+        if let Some(v) = done {
+            match stack.last_mut() {
+                Some(frame) => frame.1.push(v),
+                None => return Ok(v)
+            }
+        }
+
+        // move on to the next element of the innermost unfinished list, closing any lists that are finished
+        while next.is_none() {
+            let item = match stack.last_mut() {
+                Some(frame) => frame.0.next(),
+                None => unreachable!()
+            };
+            match item {
+                Some(v) => next = Some(v),
+                None => {
+                    let (_, items, tail) = stack.pop().unwrap();
+                    let list = match tail {
+                        Some((tail, true)) => with_tail(items, try!(evaluate_value(tail, env))),
+                        Some((tail, false)) => with_tail(items, tail.clone()),
+                        None => Value::List(Rc::new(Items(items)))
+                    };
+                    match stack.last_mut() {
+                        Some(frame) => frame.1.push(list),
+                        None => return Ok(list)
+                    }
                 }
-                evaluate_value(&vec[1], env.clone())
-            } else {
-                let res: Result<Vec<Value>, RuntimeError> = vec.iter().map(|v| quote_value(v, quasi, env.clone())).collect();
-                let new_vec = try!(res);
-                Ok(Value::List(new_vec))
             }
+        }
+    }
+}
+
+// The items followed by tail, which continues the list if it's a list itself
+fn with_tail(mut items: Vec<Value>, tail: Value) -> Value {
+    match tail {
+        Value::List(rest) => {
+            items.extend(rest.iter().cloned());
+            Value::List(Rc::new(Items(items)))
+        },
+        Value::DottedList(rest, tail) => {
+            items.extend(rest.iter().cloned());
+            Value::DottedList(Rc::new(Items(items)), tail)
         },
-        &Value::Procedure(ref v) => Ok(Value::Procedure(v.clone())),
-        &Value::Macro(ref a, ref b) => Ok(Value::Macro(a.clone(), b.clone())),
+        tail => Value::DottedList(Rc::new(Items(items)), Rc::new(tail))
     }
 }
 
-fn evaluate_expression(values: &Vec<Value>, env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+fn evaluate_expression(values: &Vec<Value>, env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    evaluate_call(values, env).map_err(|e| e.in_frame(|| Value::List(Rc::new(Items(values.clone()))), env))
+}
+
+fn evaluate_call(values: &Vec<Value>, env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
     if values.len() == 0 {
         runtime_error!("Can't evaluate an empty expression: {:?}", values);
     }
-    let first = try!(evaluate_value(&values[0], env.clone()));
+    let first = try!(evaluate_value(&values[0], env));
     match first {
-        Value::Procedure(f) => apply_function(&f, &values[1..], env.clone()),
-        Value::Macro(a, b) => expand_macro(a, b, &values[1..], env.clone()),
-        _ => runtime_error!("First element in an expression must be a procedure: {:?}", first)
+        Value::Procedure(f) => apply_function(&f, &values[1..], env),
+        Value::Macro(a, b) => expand_macro(&a, &b, &values[1..], env),
+        _ => type_error!("First element in an expression must be a procedure: {:?}", first)
+    }
+}
+
+// Evaluate arguments for a procedure written in Rust, which takes plain data
+fn data_arguments(args: &[Value], env: &Rc<RefCell<Environment>>, to: &str) -> Result<Vec<Node>, RuntimeError> {
+    let mut values = Vec::new();
+    for arg in args.iter() {
+        let v = try!(evaluate_value(arg, env));
+        match v.to_data() {
+            Ok(node) => values.push(node),
+            Err(_) => type_error!("Can't pass {:?} to {}", v, to)
+        }
     }
+    Ok(values)
 }
 
-fn apply_function(func: &Function, args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+fn apply_function(func: &Function, args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
     match func {
         &Function::Native(native_fn) => {
             native_fn(args, env)
         },
+        Function::Plugin(builtin) => {
+            let values = try!(data_arguments(args, env, builtin.name()));
+            match builtin.call(&values) {
+                Ok(result) => Ok(Value::from_node(&result)),
+                Err(e) => runtime_error!("{}", e)
+            }
+        },
+        #[cfg(feature = "ffi")]
+        Function::Foreign(procedure) => {
+            let values = try!(data_arguments(args, env, "a foreign procedure"));
+            match procedure.call(&values) {
+                Ok(result) => Ok(Value::from_node(&result)),
+                Err(e) => runtime_error!("{}", e)
+            }
+        },
         &Function::Scheme(ref arg_names, ref body, ref func_env) => {
             if arg_names.len() != args.len() {
-                runtime_error!("Must supply exactly {} arguments to function: {:?}", arg_names.len(), args);
+                arity_error!("Must supply exactly {} arguments to function: {:?}", arg_names.len(), args);
             }
 
             // create a new, child environment for the procedure and define the arguments as local variables
             let proc_env = Environment::new_child(func_env.clone());
             for (name, arg) in arg_names.iter().zip(args.iter()) {
-                let val = try!(evaluate_value(arg, env.clone()));
+                let val = try!(evaluate_value(arg, env));
                 try!(proc_env.borrow_mut().define(name.clone(), val));
             }
 
             // evaluate procedure body with new environment with procedure environment as parent
             let inner_env = Environment::new_child(proc_env);
-            evaluate_values(&body, inner_env)
+            evaluate_values(body, &inner_env)
         }
     }
 }
 
-fn expand_macro(arg_names: Vec<String>, body: Vec<Value>, args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+fn expand_macro(arg_names: &[String], body: &[Value], args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
     let mut substitutions = HashMap::new();
     for (name, arg) in arg_names.iter().zip(args.iter()) {
         substitutions.insert(name.clone(), arg.clone());
     }
-    let expanded = try!(expand_macro_substitute_values(&body, substitutions));
+    let expanded = try!(expand_macro_substitute_values(body, substitutions));
     evaluate_values(&expanded, env)
 }
 
@@ -347,20 +1418,20 @@ fn expand_macro_substitute_value(value: &Value, substitutions: HashMap<String,Va
             }
         },
         &Value::List(ref l) => {
-            Value::List(try!(expand_macro_substitute_values(&l, substitutions)))
+            Value::List(Rc::new(Items(try!(expand_macro_substitute_values(l, substitutions)))))
         },
         other => other.clone()
     };
     Ok(res)
 }
 
-fn native_define(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+fn native_define(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
     if args.len() < 2 {
-        runtime_error!("Must supply at least two arguments to define: {:?}", args);
+        arity_error!("Must supply at least two arguments to define: {:?}", args);
     }
     let (name, val) = match args[0] {
         Value::Symbol(ref name) => {
-            let val = try!(evaluate_value(&args[1], env.clone()));
+            let val = try!(evaluate_value(&args[1], env));
             (name, val)
         },
         Value::List(ref list) => {
@@ -377,7 +1448,8 @@ fn native_define(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value,
                     }).collect();
                     let arg_names = try!(res);
                     let body = (&args[1..]).to_vec();
-                    let val = Value::Procedure(Function::Scheme(arg_names, body, env.clone()));
+                    stats::count_allocations(1);
+                    let val = Value::Procedure(Function::Scheme(Rc::new(arg_names), Rc::new(body), env.clone()));
                     (name, val)
                 },
                 _ => runtime_error!("Must supply a symbol in list part of define: {:?}", list)
@@ -390,9 +1462,9 @@ fn native_define(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value,
     Ok(null!())
 }
 
-fn native_define_syntax_rule(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+fn native_define_syntax_rule(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
     if args.len() != 2 {
-        runtime_error!("Must supply exactly two arguments to define-syntax-rule: {:?}", args);
+        arity_error!("Must supply exactly two arguments to define-syntax-rule: {:?}", args);
     }
     let (name, val) = match args[0] {
         Value::List(ref list) => {
@@ -408,7 +1480,7 @@ fn native_define_syntax_rule(args: &[Value], env: Rc<RefCell<Environment>>) -> R
                     }).collect();
                     let arg_names = try!(res);
                     let body = (&args[1..]).to_vec();
-                    let val = Value::Macro(arg_names, body);
+                    let val = Value::Macro(Rc::new(arg_names), Rc::new(body));
                     (name, val)
                 },
                 _ => runtime_error!("Must supply a symbol in list part of define: {:?}", list)
@@ -421,16 +1493,34 @@ fn native_define_syntax_rule(args: &[Value], env: Rc<RefCell<Environment>>) -> R
     Ok(null!())
 }
 
-fn native_begin(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+fn native_autoload(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() < 2 {
+        arity_error!("Must supply at least two arguments to autoload: {:?}", args);
+    }
+    let path = match args[0] {
+        Value::String(ref s) => s,
+        _ => runtime_error!("First argument to autoload must be a file name: {:?}", args)
+    };
+    let root = Environment::get_root(env);
+    for name in args[1..].iter() {
+        match *name {
+            Value::Symbol(ref s) => root.borrow_mut().autoloads.insert(s.clone(), path.to_string()),
+            _ => runtime_error!("Unexpected name in autoload: {:?}", name)
+        };
+    }
+    Ok(null!())
+}
+
+fn native_begin(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
     if args.len() < 1 {
-        runtime_error!("Must supply at least one argument to begin: {:?}", args);
+        arity_error!("Must supply at least one argument to begin: {:?}", args);
     }
     evaluate_values(args, env)
 }
 
-fn native_let(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+fn native_let(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
     if args.len() < 2 {
-        runtime_error!("Must supply at least two arguments to let: {:?}", args);
+        arity_error!("Must supply at least two arguments to let: {:?}", args);
     }
 
     // create a new, child environment for the let expression and define the arguments as local variables
@@ -447,7 +1537,7 @@ fn native_let(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, Ru
                             Value::Symbol(ref x) => x,
                             _ => runtime_error!("Unexpected value for name in set!: {:?}", args)
                         };
-                        let val = try!(evaluate_value(&entry[1], env.clone()));
+                        let val = try!(evaluate_value(&entry[1], env));
                         try!(let_env.borrow_mut().define(name.clone(), val));
                     },
                     _ => runtime_error!("Unexpected value inside expression in let: {:?}", i)
@@ -460,25 +1550,76 @@ fn native_let(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, Ru
     // evaluate let statement body with new environment with let environment as parent
     let inner_env = Environment::new_child(let_env);
     let body = &args[1..];
-    evaluate_values(body, inner_env)
+    evaluate_values(body, &inner_env)
+}
+
+fn native_letrec(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    letrec("letrec", args, env)
+}
+
+fn native_letrec_star(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    letrec("letrec*", args, env)
 }
 
-fn native_set(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+// letrec evaluates every value in the scope of all the names, and only binds them once they're all evaluated, so a
+// value can use the others only from inside a lambda. letrec* binds each name as soon as its value is ready, so
+// later values can use it directly. Until then a name is uninitialized, and using it is an error rather than quietly
+// finding the same name further out.
+fn letrec(form: &str, args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() < 2 {
+        arity_error!("Must supply at least two arguments to {}: {:?}", form, args);
+    }
+    let mut bindings = Vec::new();
+    match args[0] {
+        Value::List(ref list) => {
+            for i in list.iter() {
+                match *i {
+                    Value::List(ref entry) if entry.len() == 2 => match entry[0] {
+                        Value::Symbol(ref name) => bindings.push((name.clone(), &entry[1])),
+                        _ => runtime_error!("Unexpected value for name in {}: {:?}", form, entry[0])
+                    },
+                    _ => runtime_error!("{} expression values must have exactly 2 params: {:?}", form, i)
+                }
+            }
+        },
+        _ => runtime_error!("Unexpected value for expressions in {}: {:?}", form, args)
+    };
+
+    let letrec_env = Environment::new_child(env.clone());
+    letrec_env.borrow_mut().uninitialized = bindings.iter().map(|(name, _)| name.clone()).collect();
+    let mut values = Vec::new();
+    for (name, expr) in bindings.into_iter() {
+        let val = try!(evaluate_value(expr, &letrec_env));
+        if form == "letrec*" {
+            try!(letrec_env.borrow_mut().initialize(name, val));
+        } else {
+            values.push((name, val));
+        }
+    }
+    for (name, val) in values.into_iter() {
+        try!(letrec_env.borrow_mut().initialize(name, val));
+    }
+
+    let inner_env = Environment::new_child(letrec_env);
+    evaluate_values(&args[1..], &inner_env)
+}
+
+fn native_set(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
     if args.len() != 2 {
-        runtime_error!("Must supply exactly two arguments to set!: {:?}", args);
+        arity_error!("Must supply exactly two arguments to set!: {:?}", args);
     }
     let name = match args[0] {
         Value::Symbol(ref x) => x,
         _ => runtime_error!("Unexpected value for name in set!: {:?}", args)
     };
-    let val = try!(evaluate_value(&args[1], env.clone()));
+    let val = try!(evaluate_value(&args[1], env));
     try!(env.borrow_mut().set(name.clone(), val));
     Ok(null!())
 }
 
-fn native_lambda(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+fn native_lambda(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
     if args.len() < 2 {
-        runtime_error!("Must supply at least two arguments to lambda: {:?}", args);
+        arity_error!("Must supply at least two arguments to lambda: {:?}", args);
     }
     let arg_names = match args[0] {
         Value::List(ref list) => {
@@ -491,347 +1632,2270 @@ fn native_lambda(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value,
         _ => runtime_error!("Unexpected value for arguments in lambda: {:?}", args)
     };
     let body = (&args[1..]).to_vec();
-    Ok(Value::Procedure(Function::Scheme(arg_names, body, env.clone())))
+    stats::count_allocations(1);
+    Ok(Value::Procedure(Function::Scheme(Rc::new(arg_names), Rc::new(body), env.clone())))
 }
 
-fn native_if(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+fn native_if(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
     if args.len() != 3 {
-        runtime_error!("Must supply exactly three arguments to if: {:?}", args);
+        arity_error!("Must supply exactly three arguments to if: {:?}", args);
+    }
+    let condition = try!(evaluate_value(&args[0], env));
+    if try!(is_true("if", &condition)) {
+        evaluate_value(&args[1], env)
+    } else {
+        evaluate_value(&args[2], env)
     }
-    let condition = try!(evaluate_value(&args[0], env.clone()));
-    match condition {
-        Value::Boolean(false) => evaluate_value(&args[2], env.clone()),
-        _ => evaluate_value(&args[1], env.clone())
+}
+
+// Whether a condition counts as true: anything but #f, unless booleans are strict and it has to be #t
+fn is_true(form: &str, condition: &Value) -> Result<bool, RuntimeError> {
+    match *condition {
+        Value::Boolean(b) => Ok(b),
+        _ if strict::booleans() => type_error!("Expected a boolean condition in {}: {:?}", form, condition),
+        _ => Ok(true)
     }
 }
 
-fn native_plus(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+fn native_plus(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
     if args.len() < 2 {
-        runtime_error!("Must supply at least two arguments to +: {:?}", args);
+        arity_error!("Must supply at least two arguments to +: {:?}", args);
     }
-    let mut sum = 0;
+    let mut sum = Number::Integer(0);
     for n in args.iter() {
-        let v = try!(evaluate_value(n, env.clone()));
-        match v {
-            Value::Integer(x) => sum += x,
-            _ => runtime_error!("Unexpected value during +: {:?}", n)
-        };
+        sum = sum.add(try!(evaluate_number(n, env)));
     };
-    Ok(Value::Integer(sum))
+    Ok(Value::from_number(sum))
 }
 
-fn native_minus(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+fn native_minus(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
     if args.len() != 2 {
-        runtime_error!("Must supply exactly two arguments to -: {:?}", args);
+        arity_error!("Must supply exactly two arguments to -: {:?}", args);
     }
-    let l = try!(evaluate_value(&args[0], env.clone()));
-    let r = try!(evaluate_value(&args[1], env.clone()));
-    let mut result = match l {
-        Value::Integer(x) => x,
-        _ => runtime_error!("Unexpected value during -: {:?}", args)
-    };
-    result -= match r {
-        Value::Integer(x) => x,
-        _ => runtime_error!("Unexpected value during -: {:?}", args)
-    };
-    Ok(Value::Integer(result))
+    let l = try!(evaluate_number(&args[0], env));
+    let r = try!(evaluate_number(&args[1], env));
+    Ok(Value::from_number(l.subtract(r)))
 }
 
-fn native_multiply(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+fn native_multiply(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
     if args.len() < 2 {
-        runtime_error!("Must supply at least two arguments to *: {:?}", args);
+        arity_error!("Must supply at least two arguments to *: {:?}", args);
     }
-    let mut product = 1;
+    let mut product = Number::Integer(1);
     for n in args.iter() {
-        let v = try!(evaluate_value(n, env.clone()));
-        match v {
-            Value::Integer(x) => product *= x,
-            _ => runtime_error!("Unexpected value during *: {:?}", n)
-        };
+        product = product.multiply(try!(evaluate_number(n, env)));
     };
-    Ok(Value::Integer(product))
+    Ok(Value::from_number(product))
 }
 
-fn native_divide(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+fn native_divide(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
     if args.len() != 2 {
-        runtime_error!("Must supply exactly two arguments to /: {:?}", args);
+        arity_error!("Must supply exactly two arguments to /: {:?}", args);
+    }
+    let l = try!(evaluate_number(&args[0], env));
+    let r = try!(evaluate_number(&args[1], env));
+    match l.divide(r) {
+        Some(n) => Ok(Value::from_number(n)),
+        None => runtime_error!("Division by zero in /: {:?}", args)
     }
-    let l = try!(evaluate_value(&args[0], env.clone()));
-    let r = try!(evaluate_value(&args[1], env.clone()));
-    let mut result = match l {
-        Value::Integer(x) => x,
-        _ => runtime_error!("Unexpected value during /: {:?}", args)
-    };
-    result /= match r {
-        Value::Integer(x) => x,
-        _ => runtime_error!("Unexpected value during /: {:?}", args)
-    };
-    Ok(Value::Integer(result))
 }
 
-fn native_lessthan(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
-    if args.len() != 2 {
-        runtime_error!("Must supply exactly two arguments to <: {:?}", args);
-    }
-    let l_raw = try!(evaluate_value(&args[0], env.clone()));
-    let r_raw = try!(evaluate_value(&args[1], env.clone()));
-    let l = match l_raw {
-        Value::Integer(x) => x,
-        _ => runtime_error!("Unexpected value during <: {:?}", args)
-    };
-    let r = match r_raw {
-        Value::Integer(x) => x,
-        _ => runtime_error!("Unexpected value during <: {:?}", args)
+fn native_lessthan(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    compare(args, env, "<", |o| o == Ordering::Less)
+}
+
+fn native_greaterthan(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    compare(args, env, ">", |o| o == Ordering::Greater)
+}
+
+fn native_equal(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    compare(args, env, "=", |o| o == Ordering::Equal)
+}
+
+// Compare two numbers by value, where +nan.0 compares false with anything
+fn compare<F: Fn(Ordering) -> bool>(args: &[Value], env: &Rc<RefCell<Environment>>, name: &str, test: F) -> Result<Value, RuntimeError> {
+    if args.len() != 2 {
+        arity_error!("Must supply exactly two arguments to {}: {:?}", name, args);
+    }
+    let l = try!(evaluate_number(&args[0], env));
+    let r = try!(evaluate_number(&args[1], env));
+    Ok(Value::Boolean(l.compare(r).is_some_and(test)))
+}
+
+fn evaluate_number(arg: &Value, env: &Rc<RefCell<Environment>>) -> Result<Number, RuntimeError> {
+    match try!(evaluate_value(arg, env)) {
+        Value::Integer(i) => Ok(Number::Integer(i)),
+        Value::Rational(n, d) => Ok(Number::Rational(n, d)),
+        Value::Float(f) => Ok(Number::Float(f)),
+        v => type_error!("Expected a number: {:?}", v)
+    }
+}
+
+#[cfg(feature = "math")]
+fn native_random(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to random: {:?}", args);
+    }
+    match try!(evaluate_value(&args[0], env)) {
+        Value::Integer(n) if n > 0 => Ok(Value::Integer(random::below(n as u64) as i64)),
+        v => type_error!("Argument to random must be a positive integer: {:?}", v)
+    }
+}
+
+#[cfg(feature = "math")]
+fn native_floor_divide(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    divide_into_values("floor/", numbers::floor_divide, args, env)
+}
+
+#[cfg(feature = "math")]
+fn native_truncate_divide(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    divide_into_values("truncate/", numbers::truncate_divide, args, env)
+}
+
+// floor/ and truncate/ differ only in which way the quotient is rounded
+#[cfg(feature = "math")]
+fn divide_into_values(name: &str, divide: fn(i64, i64) -> Option<(i64, i64)>, args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 2 {
+        arity_error!("Must supply exactly two arguments to {}: {:?}", name, args);
+    }
+    let (n, d) = match (try!(evaluate_value(&args[0], env)), try!(evaluate_value(&args[1], env))) {
+        (Value::Integer(n), Value::Integer(d)) => (n, d),
+        _ => type_error!("Unexpected value during {}: {:?}", name, args)
+    };
+    match divide(n, d) {
+        Some((q, r)) => Ok(Value::Values(Rc::new(Items(vec![Value::Integer(q), Value::Integer(r)])))),
+        None if d == 0 => runtime_error!("Division by zero in {}: {:?}", name, args),
+        None => runtime_error!("Integer overflow in {}: {:?}", name, args)
+    }
+}
+
+#[cfg(feature = "math")]
+fn native_exact_integer_sqrt(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to exact-integer-sqrt: {:?}", args);
+    }
+    let v = try!(evaluate_value(&args[0], env));
+    match v {
+        Value::Integer(n) => match numbers::exact_integer_sqrt(n) {
+            Some((s, r)) => Ok(Value::Values(Rc::new(Items(vec![Value::Integer(s), Value::Integer(r)])))),
+            None => type_error!("Argument to exact-integer-sqrt must be a non-negative integer: {:?}", v)
+        },
+        _ => type_error!("Argument to exact-integer-sqrt must be a non-negative integer: {:?}", v)
+    }
+}
+
+// (number->string n [radix [width]])
+#[cfg(feature = "math")]
+fn native_number_to_string(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.is_empty() || args.len() > 3 {
+        arity_error!("Must supply one to three arguments to number->string: {:?}", args);
+    }
+    let mut ints = Vec::with_capacity(args.len());
+    for n in args.iter() {
+        match try!(evaluate_value(n, env)) {
+            Value::Integer(i) => ints.push(i),
+            v @ Value::Float(_) | v @ Value::Rational(..) if ints.is_empty() => {
+                if args.len() > 1 {
+                    runtime_error!("number->string only takes a radix and width for integers, not {}", v)
+                }
+                return Ok(Value::String(Rc::from(v.to_string())))
+            },
+            v => type_error!("Unexpected value during number->string: {:?}", v)
+        }
+    }
+    let radix = ints.get(1).cloned().unwrap_or(10);
+    let width = ints.get(2).cloned().unwrap_or(0);
+    if !(2..=36).contains(&radix) {
+        runtime_error!("Radix must be from 2 to 36: {}", radix)
+    }
+    if width < 0 {
+        runtime_error!("Width must be a non-negative integer: {}", width)
+    }
+    Ok(Value::String(Rc::from(numbers::to_string_radix(ints[0], radix as u32, width as usize))))
+}
+
+// The last operand of and and or is returned without being tested
+fn native_and(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    for (i, n) in args.iter().enumerate() {
+        let v = try!(evaluate_value(n, env));
+        if i + 1 == args.len() {
+            return Ok(v)
+        }
+        if !try!(is_true("and", &v)) {
+            return Ok(Value::Boolean(false))
+        }
+    }
+    Ok(Value::Boolean(true))
+}
+
+fn native_or(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    for (i, n) in args.iter().enumerate() {
+        let v = try!(evaluate_value(n, env));
+        if i + 1 == args.len() || try!(is_true("or", &v)) {
+            return Ok(v)
+        }
+    }
+    Ok(Value::Boolean(false))
+}
+
+fn native_null(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to null?: {:?}", args);
+    }
+    let v = try!(evaluate_value(&args[0], env));
+    match v {
+        Value::List(l) => Ok(Value::Boolean(l.len() == 0)),
+        _ => Ok(Value::Boolean(false))
+    }
+}
+
+#[allow(unused_variables)]
+fn native_eof_object(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 0 {
+        arity_error!("Must supply exactly zero arguments to eof-object: {:?}", args);
+    }
+    Ok(Value::EofObject)
+}
+
+fn native_is_eof_object(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to eof-object?: {:?}", args);
+    }
+    let v = try!(evaluate_value(&args[0], env));
+    Ok(Value::Boolean(v == Value::EofObject))
+}
+
+fn native_is_keyword(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to keyword?: {:?}", args);
+    }
+    match try!(evaluate_value(&args[0], env)) {
+        Value::Keyword(_) => Ok(Value::Boolean(true)),
+        _ => Ok(Value::Boolean(false))
+    }
+}
+
+fn native_keyword_to_string(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to keyword->string: {:?}", args);
+    }
+    match try!(evaluate_value(&args[0], env)) {
+        Value::Keyword(k) => Ok(Value::String(Rc::from(k))),
+        v => type_error!("Expected a keyword value: {:?}", v)
+    }
+}
+
+fn native_string_to_keyword(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to string->keyword: {:?}", args);
+    }
+    match try!(evaluate_value(&args[0], env)) {
+        Value::String(s) => Ok(Value::Keyword(s.to_string())),
+        v => type_error!("Expected a string value: {:?}", v)
+    }
+}
+
+fn native_is_char(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to char?: {:?}", args);
+    }
+    match try!(evaluate_value(&args[0], env)) {
+        Value::Character(_) => Ok(Value::Boolean(true)),
+        _ => Ok(Value::Boolean(false))
+    }
+}
+
+fn native_char_to_integer(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to char->integer: {:?}", args);
+    }
+    match try!(evaluate_value(&args[0], env)) {
+        Value::Character(c) => Ok(Value::Integer(c as i64)),
+        v => type_error!("Expected a character value: {:?}", v)
+    }
+}
+
+// evaluates the one argument of a character procedure, which has to be a character
+fn char_arg(name: &str, args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<char, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to {}: {:?}", name, args);
+    }
+    match try!(evaluate_value(&args[0], env)) {
+        Value::Character(c) => Ok(c),
+        v => type_error!("Expected a character value: {:?}", v)
+    }
+}
+
+fn native_is_char_alphabetic(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    Ok(Value::Boolean(try!(char_arg("char-alphabetic?", args, env)).is_alphabetic()))
+}
+
+fn native_is_char_numeric(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    Ok(Value::Boolean(try!(char_arg("char-numeric?", args, env)).is_numeric()))
+}
+
+fn native_is_char_whitespace(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    Ok(Value::Boolean(try!(char_arg("char-whitespace?", args, env)).is_whitespace()))
+}
+
+fn native_char_upcase(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    Ok(Value::Character(text::upcase(try!(char_arg("char-upcase", args, env)))))
+}
+
+fn native_char_downcase(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    Ok(Value::Character(text::downcase(try!(char_arg("char-downcase", args, env)))))
+}
+
+fn native_integer_to_char(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to integer->char: {:?}", args);
+    }
+    match try!(evaluate_value(&args[0], env)) {
+        Value::Integer(n) => match text::char_from_code(n) {
+            Some(c) => Ok(Value::Character(c)),
+            None => runtime_error!("Not a Unicode code point: {}", n)
+        },
+        v => type_error!("Expected an integer value: {:?}", v)
+    }
+}
+
+fn native_gensym(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() > 1 {
+        arity_error!("Must supply at most one argument to gensym: {:?}", args);
+    }
+    if args.is_empty() {
+        return Ok(Value::Symbol(random::gensym("g")))
+    }
+    match try!(evaluate_value(&args[0], env)) {
+        Value::String(s) => Ok(Value::Symbol(random::gensym(&s))),
+        Value::Symbol(s) => Ok(Value::Symbol(random::gensym(&s))),
+        v => type_error!("Expected a string or symbol prefix: {:?}", v)
+    }
+}
+
+fn native_hash(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to hash: {:?}", args);
+    }
+    let v = try!(evaluate_value(&args[0], env));
+    Ok(Value::Integer(random::stable_hash(&v)))
+}
+
+fn native_hash_by_identity(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to hash-by-identity: {:?}", args);
+    }
+    let v = try!(evaluate_value(&args[0], env));
+    Ok(Value::Integer(v.identity_hash()))
+}
+
+fn native_is_eqv(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 2 {
+        arity_error!("Must supply exactly two arguments to eqv?: {:?}", args);
+    }
+    let a = try!(evaluate_value(&args[0], env));
+    let b = try!(evaluate_value(&args[1], env));
+    Ok(Value::Boolean(a.is_eqv(&b)))
+}
+
+fn native_features(args: &[Value], _env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if !args.is_empty() {
+        arity_error!("Must supply exactly zero arguments to features: {:?}", args);
+    }
+    let features: Vec<Value> = features::features().into_iter().map(Value::Symbol).collect();
+    Ok(Value::List(Rc::new(Items(features))))
+}
+
+fn native_box(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to box: {:?}", args);
+    }
+    let v = try!(evaluate_value(&args[0], env));
+    stats::count_allocations(1);
+    Ok(Value::Box(Rc::new(RefCell::new(v))))
+}
+
+fn native_is_box(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to box?: {:?}", args);
+    }
+    match try!(evaluate_value(&args[0], env)) {
+        Value::Box(_) => Ok(Value::Boolean(true)),
+        _ => Ok(Value::Boolean(false))
+    }
+}
+
+fn native_unbox(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to unbox: {:?}", args);
+    }
+    match try!(evaluate_value(&args[0], env)) {
+        Value::Box(b) => Ok(b.borrow().clone()),
+        v => type_error!("Expected a box value: {:?}", v)
+    }
+}
+
+fn native_set_box(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 2 {
+        arity_error!("Must supply exactly two arguments to set-box!: {:?}", args);
+    }
+    let b = try!(evaluate_value(&args[0], env));
+    let v = try!(evaluate_value(&args[1], env));
+    match b {
+        Value::Box(b) => {
+            *b.borrow_mut() = v;
+            Ok(null!())
+        },
+        _ => type_error!("Expected a box value: {:?}", b)
+    }
+}
+
+fn native_make_queue(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    let mut queue = VecDeque::with_capacity(args.len());
+    for n in args.iter() {
+        queue.push_back(try!(evaluate_value(n, env)));
+    }
+    stats::count_allocations(1);
+    Ok(Value::Queue(Rc::new(RefCell::new(queue))))
+}
+
+fn native_is_queue(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to queue?: {:?}", args);
+    }
+    match try!(evaluate_value(&args[0], env)) {
+        Value::Queue(_) => Ok(Value::Boolean(true)),
+        _ => Ok(Value::Boolean(false))
+    }
+}
+
+fn native_queue_length(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to queue-length: {:?}", args);
+    }
+    match try!(evaluate_value(&args[0], env)) {
+        Value::Queue(q) => Ok(Value::Integer(q.borrow().len() as i64)),
+        v => type_error!("Expected a queue value: {:?}", v)
+    }
+}
+
+fn native_enqueue(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 2 {
+        arity_error!("Must supply exactly two arguments to enqueue!: {:?}", args);
+    }
+    let q = try!(evaluate_value(&args[0], env));
+    let v = try!(evaluate_value(&args[1], env));
+    match q {
+        Value::Queue(q) => {
+            q.borrow_mut().push_back(v);
+            Ok(null!())
+        },
+        _ => type_error!("Expected a queue value: {:?}", q)
+    }
+}
+
+fn native_dequeue(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to dequeue!: {:?}", args);
+    }
+    match try!(evaluate_value(&args[0], env)) {
+        Value::Queue(q) => match q.borrow_mut().pop_front() {
+            Some(v) => Ok(v),
+            None => runtime_error!("Can't dequeue from an empty queue")
+        },
+        v => type_error!("Expected a queue value: {:?}", v)
+    }
+}
+
+fn native_queue_to_list(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to queue->list: {:?}", args);
+    }
+    match try!(evaluate_value(&args[0], env)) {
+        Value::Queue(q) => {
+            let elements: Vec<Value> = q.borrow().iter().cloned().collect();
+            stats::count_allocations(elements.len() as u64);
+            Ok(Value::List(Rc::new(Items(elements))))
+        },
+        v => type_error!("Expected a queue value: {:?}", v)
+    }
+}
+
+fn native_make_set(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    let mut set = HashSet::with_hasher(HashState::new());
+    for n in args.iter() {
+        set.insert(try!(evaluate_value(n, env)));
+    }
+    stats::count_allocations(1);
+    Ok(Value::Set(Rc::new(RefCell::new(set))))
+}
+
+fn native_is_set(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to set?: {:?}", args);
+    }
+    match try!(evaluate_value(&args[0], env)) {
+        Value::Set(_) => Ok(Value::Boolean(true)),
+        _ => Ok(Value::Boolean(false))
+    }
+}
+
+fn native_set_add(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 2 {
+        arity_error!("Must supply exactly two arguments to set-add!: {:?}", args);
+    }
+    let s = try!(evaluate_value(&args[0], env));
+    let v = try!(evaluate_value(&args[1], env));
+    match s {
+        Value::Set(s) => {
+            s.borrow_mut().insert(v);
+            Ok(null!())
+        },
+        _ => type_error!("Expected a set value: {:?}", s)
+    }
+}
+
+fn native_set_contains(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 2 {
+        arity_error!("Must supply exactly two arguments to set-contains?: {:?}", args);
+    }
+    let s = try!(evaluate_value(&args[0], env));
+    let v = try!(evaluate_value(&args[1], env));
+    match s {
+        Value::Set(s) => Ok(Value::Boolean(s.borrow().contains(&v))),
+        _ => type_error!("Expected a set value: {:?}", s)
+    }
+}
+
+// The sets among args, for set-union and set-intersection to combine into a new one
+fn evaluate_sets(name: &str, args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Vec<Rc<RefCell<ValueSet>>>, RuntimeError> {
+    if args.is_empty() {
+        arity_error!("Must supply at least one argument to {}: {:?}", name, args);
+    }
+    let mut sets = Vec::with_capacity(args.len());
+    for n in args.iter() {
+        match try!(evaluate_value(n, env)) {
+            Value::Set(s) => sets.push(s),
+            v => type_error!("Expected a set value: {:?}", v)
+        }
+    }
+    Ok(sets)
+}
+
+fn native_set_union(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    let sets = try!(evaluate_sets("set-union", args, env));
+    let mut union = HashSet::with_hasher(HashState::new());
+    for s in sets.iter() {
+        union.extend(s.borrow().iter().cloned());
+    }
+    stats::count_allocations(1);
+    Ok(Value::Set(Rc::new(RefCell::new(union))))
+}
+
+fn native_set_intersection(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    let sets = try!(evaluate_sets("set-intersection", args, env));
+    let mut intersection = HashSet::with_hasher(HashState::new());
+    for v in sets[0].borrow().iter() {
+        if sets[1..].iter().all(|s| s.borrow().contains(v)) {
+            intersection.insert(v.clone());
+        }
+    }
+    stats::count_allocations(1);
+    Ok(Value::Set(Rc::new(RefCell::new(intersection))))
+}
+
+fn native_set_to_list(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to set->list: {:?}", args);
+    }
+    match try!(evaluate_value(&args[0], env)) {
+        Value::Set(s) => {
+            let elements: Vec<Value> = s.borrow().iter().cloned().collect();
+            stats::count_allocations(elements.len() as u64);
+            Ok(Value::List(Rc::new(Items(elements))))
+        },
+        v => type_error!("Expected a set value: {:?}", v)
+    }
+}
+
+fn native_make_heap(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to make-heap: {:?}", args);
+    }
+    match try!(evaluate_value(&args[0], env)) {
+        Value::Procedure(less) => {
+            stats::count_allocations(1);
+            Ok(Value::Heap(Rc::new(RefCell::new(Heap { less, items: Vec::new() }))))
+        },
+        v => type_error!("Argument to make-heap must be a procedure: {:?}", v)
+    }
+}
+
+fn native_is_heap(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to heap?: {:?}", args);
+    }
+    match try!(evaluate_value(&args[0], env)) {
+        Value::Heap(_) => Ok(Value::Boolean(true)),
+        _ => Ok(Value::Boolean(false))
+    }
+}
+
+fn native_heap_size(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to heap-size: {:?}", args);
+    }
+    match try!(evaluate_value(&args[0], env)) {
+        Value::Heap(h) => Ok(Value::Integer(h.borrow().items.len() as i64)),
+        v => type_error!("Expected a heap value: {:?}", v)
+    }
+}
+
+fn native_heap_peek(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to heap-peek: {:?}", args);
+    }
+    match try!(evaluate_value(&args[0], env)) {
+        Value::Heap(h) => match h.borrow().items.first() {
+            Some(v) => Ok(v.clone()),
+            None => runtime_error!("Can't peek at an empty heap")
+        },
+        v => type_error!("Expected a heap value: {:?}", v)
+    }
+}
+
+fn native_heap_push(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 2 {
+        arity_error!("Must supply exactly two arguments to heap-push!: {:?}", args);
+    }
+    let h = try!(evaluate_value(&args[0], env));
+    let v = try!(evaluate_value(&args[1], env));
+    let heap = match h {
+        Value::Heap(heap) => heap,
+        _ => type_error!("Expected a heap value: {:?}", h)
+    };
+    let mut i = {
+        let mut h = heap.borrow_mut();
+        h.items.push(v);
+        h.items.len() - 1
+    };
+    // move it up past every parent it comes out before
+    while i > 0 {
+        let parent = (i - 1) / 2;
+        if !try!(heap_less(&heap, i, parent, env)) || !heap.borrow_mut().swap(i, parent) {
+            break
+        }
+        i = parent;
+    }
+    Ok(null!())
+}
+
+fn native_heap_pop(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to heap-pop!: {:?}", args);
+    }
+    let heap = match try!(evaluate_value(&args[0], env)) {
+        Value::Heap(heap) => heap,
+        v => type_error!("Expected a heap value: {:?}", v)
+    };
+    if heap.borrow().items.is_empty() {
+        runtime_error!("Can't pop from an empty heap")
+    }
+    // the last element takes the root's place, then moves down past every child that comes out before it
+    let result = heap.borrow_mut().items.swap_remove(0);
+    let mut i = 0;
+    loop {
+        let (left, right) = (2 * i + 1, 2 * i + 2);
+        let len = heap.borrow().items.len();
+        if left >= len {
+            break
+        }
+        let child = if right < len && try!(heap_less(&heap, right, left, env)) { right } else { left };
+        if !try!(heap_less(&heap, child, i, env)) || !heap.borrow_mut().swap(i, child) {
+            break
+        }
+        i = child;
+    }
+    Ok(result)
+}
+
+// Whether the heap's element at index a comes out before the one at b. The heap isn't borrowed while the ordering
+// procedure runs, and if the indexes are gone, nothing needs moving.
+fn heap_less(heap: &Rc<RefCell<Heap>>, a: usize, b: usize, env: &Rc<RefCell<Environment>>) -> Result<bool, RuntimeError> {
+    let (less, args) = {
+        let h = heap.borrow();
+        match (h.items.get(a), h.items.get(b)) {
+            (Some(x), Some(y)) => (h.less.clone(), quote_values(&[x.clone(), y.clone()])),
+            _ => return Ok(false)
+        }
+    };
+    match try!(apply_function(&less, &args, env)) {
+        Value::Boolean(false) => Ok(false),
+        _ => Ok(true)
+    }
+}
+
+fn native_value_to_cbor(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to value->cbor: {:?}", args);
+    }
+    let val = try!(evaluate_value(&args[0], env));
+    let encoded = match val.to_data() {
+        Ok(node) => cbor::encode(&node),
+        Err(v) => type_error!("Can't encode {:?} as CBOR", v)
+    };
+    match encoded {
+        Ok(bytes) => Ok(Value::Bytevector(Rc::new(bytes))),
+        Err(e) => type_error!("{}", e)
+    }
+}
+
+fn native_cbor_to_value(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to cbor->value: {:?}", args);
+    }
+    let bytes = match try!(evaluate_value(&args[0], env)) {
+        Value::Bytevector(bytes) => bytes,
+        v => type_error!("Expected a bytevector value: {:?}", v)
+    };
+    match cbor::decode(&bytes) {
+        Ok(node) => Ok(Value::from_node(&node)),
+        Err(e) => read_error!("{}", e)
+    }
+}
+
+// (document 'name), the rendered documentation for the builtin name, or #f
+fn native_document(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to document: {:?}", args);
+    }
+    match try!(evaluate_value(&args[0], env)) {
+        Value::Symbol(name) => Ok(docs::lookup(&name).map_or(Value::Boolean(false), |d| Value::String(Rc::from(d.render())))),
+        v => type_error!("Expected a symbol value: {:?}", v)
+    }
+}
+
+fn native_string_length(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to string-length: {:?}", args);
+    }
+    match try!(evaluate_value(&args[0], env)) {
+        Value::String(s) => Ok(Value::Integer(text::char_length(&s) as i64)),
+        v => type_error!("Expected a string value: {:?}", v)
+    }
+}
+
+// The start and end of the range that a procedure's optional index arguments give in a sequence of length elements
+fn evaluate_range(indexes: &[Value], length: usize, kind: &str, env: &Rc<RefCell<Environment>>) -> Result<(usize, usize), RuntimeError> {
+    let mut evaluated = Vec::with_capacity(2);
+    for n in indexes.iter() {
+        match try!(evaluate_value(n, env)) {
+            Value::Integer(i) if i >= 0 => evaluated.push(i as usize),
+            v => type_error!("Expected a non-negative integer value: {:?}", v)
+        }
+    }
+    match ranges::range(&evaluated, length, kind) {
+        Ok(range) => Ok(range),
+        Err(e) => runtime_error!("{}", e)
+    }
+}
+
+// The string argument of a procedure that takes a string and optional start and end indexes, and that part of it
+fn evaluate_string_range(name: &str, args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<String, RuntimeError> {
+    if args.is_empty() || args.len() > 3 {
+        arity_error!("Must supply one to three arguments to {}: {:?}", name, args);
+    }
+    let s = match try!(evaluate_value(&args[0], env)) {
+        Value::String(s) => s,
+        v => type_error!("Expected a string value: {:?}", v)
+    };
+    let (start, end) = try!(evaluate_range(&args[1..], text::char_length(&s), "string", env));
+    Ok(text::substring(&s, start, end).unwrap().to_string())
+}
+
+// (substring s start [end]), indexed by character
+fn native_substring(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() < 2 || args.len() > 3 {
+        arity_error!("Must supply two or three arguments to substring: {:?}", args);
+    }
+    Ok(Value::String(Rc::from(try!(evaluate_string_range("substring", args, env)))))
+}
+
+// (string-copy s [start [end]])
+fn native_string_copy(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    Ok(Value::String(Rc::from(try!(evaluate_string_range("string-copy", args, env)))))
+}
+
+// (string->list s [start [end]])
+fn native_string_to_list(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    let s = try!(evaluate_string_range("string->list", args, env));
+    Ok(Value::List(Rc::new(Items(s.chars().map(Value::Character).collect()))))
+}
+
+// (list->string list [start [end]])
+fn native_list_to_string(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.is_empty() || args.len() > 3 {
+        arity_error!("Must supply one to three arguments to list->string: {:?}", args);
+    }
+    let elements = match try!(evaluate_value(&args[0], env)) {
+        Value::List(elements) => elements,
+        v => type_error!("Expected a list value: {:?}", v)
+    };
+    let (start, end) = try!(evaluate_range(&args[1..], elements.len(), "list", env));
+    let mut s = String::new();
+    for element in elements[start..end].iter() {
+        match *element {
+            Value::Character(c) => s.push(c),
+            ref v => type_error!("Expected a character value: {:?}", v)
+        }
+    }
+    Ok(Value::String(Rc::from(s)))
+}
+
+fn native_is_vector(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to vector?: {:?}", args);
+    }
+    match try!(evaluate_value(&args[0], env)) {
+        Value::Vector(_) => Ok(Value::Boolean(true)),
+        _ => Ok(Value::Boolean(false))
+    }
+}
+
+fn native_vector(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    let mut items = Vec::with_capacity(args.len());
+    for arg in args.iter() {
+        items.push(try!(evaluate_value(arg, env)));
+    }
+    Ok(Value::Vector(Rc::new(RefCell::new(Items(items)))))
+}
+
+fn native_vector_length(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to vector-length: {:?}", args);
+    }
+    match try!(evaluate_value(&args[0], env)) {
+        Value::Vector(items) => Ok(Value::Integer(items.borrow().len() as i64)),
+        v => type_error!("Expected a vector value: {:?}", v)
+    }
+}
+
+fn native_vector_ref(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 2 {
+        arity_error!("Must supply exactly two arguments to vector-ref: {:?}", args);
+    }
+    let items = try!(evaluate_vector(&args[0], env));
+    let items = items.borrow();
+    match try!(evaluate_value(&args[1], env)) {
+        Value::Integer(i) if i >= 0 && (i as usize) < items.len() => Ok(items[i as usize].clone()),
+        Value::Integer(i) => runtime_error!("Index {} out of range for a vector of length {}", i, items.len()),
+        v => type_error!("Expected an integer value: {:?}", v)
+    }
+}
+
+// (vector->list v [start [end]])
+fn native_vector_to_list(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.is_empty() || args.len() > 3 {
+        arity_error!("Must supply one to three arguments to vector->list: {:?}", args);
+    }
+    let items = try!(evaluate_vector(&args[0], env));
+    let items = items.borrow();
+    let (start, end) = try!(evaluate_range(&args[1..], items.len(), "vector", env));
+    Ok(Value::List(Rc::new(Items(items[start..end].to_vec()))))
+}
+
+// (vector-copy v [start [end]])
+fn native_vector_copy(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.is_empty() || args.len() > 3 {
+        arity_error!("Must supply one to three arguments to vector-copy: {:?}", args);
+    }
+    let items = try!(evaluate_vector(&args[0], env));
+    let items = items.borrow();
+    let (start, end) = try!(evaluate_range(&args[1..], items.len(), "vector", env));
+    Ok(Value::Vector(Rc::new(RefCell::new(Items(items[start..end].to_vec())))))
+}
+
+// (vector-fill! v x [start [end]])
+fn native_vector_fill(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() < 2 || args.len() > 4 {
+        arity_error!("Must supply two to four arguments to vector-fill!: {:?}", args);
+    }
+    let items = try!(evaluate_vector(&args[0], env));
+    let fill = try!(evaluate_value(&args[1], env));
+    let length = items.borrow().len();
+    let (start, end) = try!(evaluate_range(&args[2..], length, "vector", env));
+    for item in items.borrow_mut()[start..end].iter_mut() {
+        *item = fill.clone();
+    }
+    Ok(null!())
+}
+
+// (vector-copy! to at from [start [end]])
+fn native_vector_copy_to(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() < 3 || args.len() > 5 {
+        arity_error!("Must supply three to five arguments to vector-copy!: {:?}", args);
+    }
+    let to = try!(evaluate_vector(&args[0], env));
+    let at = match try!(evaluate_value(&args[1], env)) {
+        Value::Integer(i) if i >= 0 => i as usize,
+        v => type_error!("Expected a non-negative integer value: {:?}", v)
+    };
+    // Taken out before to is borrowed, since it can be the same vector as from
+    let items = {
+        let from = try!(evaluate_vector(&args[2], env));
+        let from = from.borrow();
+        let (start, end) = try!(evaluate_range(&args[3..], from.len(), "vector", env));
+        from[start..end].to_vec()
+    };
+    let mut to = to.borrow_mut();
+    if at + items.len() > to.len() {
+        runtime_error!("Indexes {} to {} out of range for a vector of length {}", at, at + items.len(), to.len());
+    }
+    to[at..at + items.len()].clone_from_slice(&items);
+    Ok(null!())
+}
+
+fn native_vector_append(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    let mut items = Vec::new();
+    for arg in args.iter() {
+        items.extend(try!(evaluate_vector(arg, env)).borrow().iter().cloned());
+    }
+    Ok(Value::Vector(Rc::new(RefCell::new(Items(items)))))
+}
+
+fn native_vector_push(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 2 {
+        arity_error!("Must supply exactly two arguments to vector-push!: {:?}", args);
+    }
+    let items = try!(evaluate_vector(&args[0], env));
+    let x = try!(evaluate_value(&args[1], env));
+    items.borrow_mut().push(x);
+    Ok(null!())
+}
+
+fn native_vector_pop(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to vector-pop!: {:?}", args);
+    }
+    let items = try!(evaluate_vector(&args[0], env));
+    let popped = items.borrow_mut().pop();
+    match popped {
+        Some(v) => Ok(v),
+        None => runtime_error!("Can't pop from an empty vector")
+    }
+}
+
+fn evaluate_vector(arg: &Value, env: &Rc<RefCell<Environment>>) -> Result<Rc<RefCell<Items>>, RuntimeError> {
+    match try!(evaluate_value(arg, env)) {
+        Value::Vector(items) => Ok(items),
+        v => type_error!("Expected a vector value: {:?}", v)
+    }
+}
+
+// (list->vector list [start [end]])
+fn native_list_to_vector(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.is_empty() || args.len() > 3 {
+        arity_error!("Must supply one to three arguments to list->vector: {:?}", args);
+    }
+    let elements = match try!(evaluate_value(&args[0], env)) {
+        Value::List(elements) => elements,
+        v => type_error!("Expected a list value: {:?}", v)
+    };
+    let (start, end) = try!(evaluate_range(&args[1..], elements.len(), "list", env));
+    Ok(Value::Vector(Rc::new(RefCell::new(Items(elements[start..end].to_vec())))))
+}
+
+fn native_is_bytevector(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to bytevector?: {:?}", args);
+    }
+    match try!(evaluate_value(&args[0], env)) {
+        Value::Bytevector(_) => Ok(Value::Boolean(true)),
+        _ => Ok(Value::Boolean(false))
+    }
+}
+
+fn native_bytevector(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    let mut bytes = Vec::with_capacity(args.len());
+    for arg in args.iter() {
+        match try!(evaluate_value(arg, env)) {
+            Value::Integer(b) if (0..=255).contains(&b) => bytes.push(b as u8),
+            v => type_error!("Expected a byte (an integer from 0 to 255): {:?}", v)
+        }
+    }
+    Ok(Value::Bytevector(Rc::new(bytes)))
+}
+
+fn native_bytevector_length(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to bytevector-length: {:?}", args);
+    }
+    match try!(evaluate_value(&args[0], env)) {
+        Value::Bytevector(bytes) => Ok(Value::Integer(bytes.len() as i64)),
+        v => type_error!("Expected a bytevector value: {:?}", v)
+    }
+}
+
+// (bytevector-copy b [start [end]])
+fn native_bytevector_copy(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.is_empty() || args.len() > 3 {
+        arity_error!("Must supply one to three arguments to bytevector-copy: {:?}", args);
+    }
+    let bytes = match try!(evaluate_value(&args[0], env)) {
+        Value::Bytevector(bytes) => bytes,
+        v => type_error!("Expected a bytevector value: {:?}", v)
+    };
+    let (start, end) = try!(evaluate_range(&args[1..], bytes.len(), "bytevector", env));
+    Ok(Value::Bytevector(Rc::new(bytes[start..end].to_vec())))
+}
+
+fn native_bytevector_u8_ref(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 2 {
+        arity_error!("Must supply exactly two arguments to bytevector-u8-ref: {:?}", args);
+    }
+    let bytes = match try!(evaluate_value(&args[0], env)) {
+        Value::Bytevector(bytes) => bytes,
+        v => type_error!("Expected a bytevector value: {:?}", v)
     };
+    match try!(evaluate_value(&args[1], env)) {
+        Value::Integer(i) if i >= 0 && (i as usize) < bytes.len() => Ok(Value::Integer(bytes[i as usize] as i64)),
+        Value::Integer(i) => runtime_error!("Index {} out of range for a bytevector of length {}", i, bytes.len()),
+        v => type_error!("Expected an integer value: {:?}", v)
+    }
+}
+
+fn native_string_foldcase(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to string-foldcase: {:?}", args);
+    }
+    match try!(evaluate_value(&args[0], env)) {
+        Value::String(s) => Ok(Value::String(Rc::from(text::fold_case(&s)))),
+        v => type_error!("Expected a string value: {:?}", v)
+    }
+}
+
+// (display->string obj...) is what displaying each obj in turn would print, as a string
+fn native_display_to_string(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    let mut s = String::new();
+    for arg in args.iter() {
+        s.push_str(&printer::print(&try!(evaluate_value(arg, env)), Style::Display));
+    }
+    Ok(Value::String(Rc::from(s)))
+}
+
+// The length of a list, or #f for anything else. Lists here are always proper and can't be cyclic (only boxes can
+// make a cycle), so unlike SRFI 1's length+ this never has to look for one.
+fn native_length_plus(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to length+: {:?}", args);
+    }
+    match try!(evaluate_value(&args[0], env)) {
+        Value::List(l) => Ok(Value::Integer(l.len() as i64)),
+        _ => Ok(Value::Boolean(false))
+    }
+}
+
+// A copy of a list, or anything else unchanged
+fn native_list_copy(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to list-copy: {:?}", args);
+    }
+    match try!(evaluate_value(&args[0], env)) {
+        Value::List(l) => Ok(Value::List(Rc::new((*l).clone()))),
+        v => Ok(v)
+    }
+}
+
+// The list of a list's last element, which is its last pair, along with the tail of a dotted list
+fn native_last_pair(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to last-pair: {:?}", args);
+    }
+    match try!(evaluate_value(&args[0], env)) {
+        Value::List(ref l) if l.is_empty() => runtime_error!("Can't take the last pair of an empty list"),
+        Value::List(l) => Ok(Value::List(Rc::new(Items(vec![l[l.len() - 1].clone()])))),
+        Value::DottedList(l, tail) => Ok(Value::DottedList(Rc::new(Items(vec![l[l.len() - 1].clone()])), tail)),
+        v => type_error!("Expected a list value: {:?}", v)
+    }
+}
+
+// Like length+, the SRFI 1 predicates on list structure are simple here: no list is circular, and anything that isn't
+// a proper list counts as a dotted one, with no pairs if it isn't a dotted list
+fn native_is_proper_list(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to proper-list?: {:?}", args);
+    }
+    match try!(evaluate_value(&args[0], env)) {
+        Value::List(_) => Ok(Value::Boolean(true)),
+        _ => Ok(Value::Boolean(false))
+    }
+}
+
+fn native_is_circular_list(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to circular-list?: {:?}", args);
+    }
+    try!(evaluate_value(&args[0], env));
+    Ok(Value::Boolean(false))
+}
+
+fn native_is_dotted_list(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to dotted-list?: {:?}", args);
+    }
+    match try!(evaluate_value(&args[0], env)) {
+        Value::List(_) => Ok(Value::Boolean(false)),
+        _ => Ok(Value::Boolean(true))
+    }
+}
+
+fn native_tree_size(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to tree-size: {:?}", args);
+    }
+    Ok(Value::Integer(printer::size(&try!(evaluate_value(&args[0], env))) as i64))
+}
+
+fn native_depth(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to depth: {:?}", args);
+    }
+    match printer::depth(&try!(evaluate_value(&args[0], env))) {
+        Some(n) => Ok(Value::Integer(n as i64)),
+        None => Ok(Value::Boolean(false))
+    }
+}
+
+// evaluates both arguments of a string-ci comparison and case-folds them
+fn folded_string_args(name: &str, args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<(String, String), RuntimeError> {
+    if args.len() != 2 {
+        arity_error!("Must supply exactly two arguments to {}: {:?}", name, args);
+    }
+    let l = match try!(evaluate_value(&args[0], env)) {
+        Value::String(s) => text::fold_case(&s),
+        v => type_error!("Expected a string value: {:?}", v)
+    };
+    let r = match try!(evaluate_value(&args[1], env)) {
+        Value::String(s) => text::fold_case(&s),
+        v => type_error!("Expected a string value: {:?}", v)
+    };
+    Ok((l, r))
+}
+
+fn native_string_ci_equal(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    let (l, r) = try!(folded_string_args("string-ci=?", args, env));
+    Ok(Value::Boolean(l == r))
+}
+
+fn native_string_ci_lessthan(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    let (l, r) = try!(folded_string_args("string-ci<?", args, env));
     Ok(Value::Boolean(l < r))
 }
 
-fn native_greaterthan(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+fn native_string_ci_greaterthan(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    let (l, r) = try!(folded_string_args("string-ci>?", args, env));
+    Ok(Value::Boolean(l > r))
+}
+
+fn native_string_ci_lessthan_or_equal(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    let (l, r) = try!(folded_string_args("string-ci<=?", args, env));
+    Ok(Value::Boolean(l <= r))
+}
+
+fn native_string_ci_greaterthan_or_equal(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    let (l, r) = try!(folded_string_args("string-ci>=?", args, env));
+    Ok(Value::Boolean(l >= r))
+}
+
+fn native_list(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    // sized up front, since collecting a Result can't know how many elements are coming
+    let mut elements = Vec::with_capacity(args.len());
+    for n in args.iter() {
+        elements.push(try!(evaluate_value(n, env)));
+    }
+    stats::count_allocations(elements.len() as u64);
+    Ok(Value::List(Rc::new(Items(elements))))
+}
+
+fn native_car(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to car: {:?}", args);
+    }
+    let v = try!(evaluate_value(&args[0], env));
+    match v {
+        Value::List(l) => {
+            if l.len() > 0 {
+                Ok(l[0].clone())
+            } else {
+                type_error!("Can't run car on an empty list")
+            }
+        }
+        Value::DottedList(l, _) => Ok(l[0].clone()),
+        _ => type_error!("Must supply a list to car")
+    }
+}
+
+fn native_cdr(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to cdr: {:?}", args);
+    }
+    let v = try!(evaluate_value(&args[0], env));
+    match v {
+        Value::List(l) => {
+            if l.len() > 0 {
+                Ok(Value::List(Rc::new(Items(l[1..].to_vec()))))
+            } else {
+                type_error!("Can't run cdr on an empty list")
+            }
+        }
+        Value::DottedList(ref l, ref tail) if l.len() == 1 => Ok((**tail).clone()),
+        Value::DottedList(l, tail) => Ok(Value::DottedList(Rc::new(Items(l[1..].to_vec())), tail)),
+        _ => type_error!("Must supply a list to cdr")
+    }
+}
+
+fn native_cons(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 2 {
+        arity_error!("Must supply exactly two arguments to cons: {:?}", args);
+    }
+
+    let first = try!(evaluate_value(&args[0], env));
+    let second = try!(evaluate_value(&args[1], env));
+    match second {
+        Value::List(elements) => {
+            let mut new_elements = vec![first];
+            for e in elements.iter() {
+                new_elements.push(e.clone());
+            }
+            // vector-backed lists are copied rather than shared, so every element counts
+            stats::count_allocations(new_elements.len() as u64);
+            Ok(Value::List(Rc::new(Items(new_elements))))
+        }
+        Value::DottedList(elements, tail) => {
+            let mut new_elements = vec![first];
+            new_elements.extend(elements.iter().cloned());
+            stats::count_allocations(new_elements.len() as u64);
+            Ok(Value::DottedList(Rc::new(Items(new_elements)), tail))
+        },
+        // onto anything else, it makes a dotted list
+        _ => {
+            stats::count_allocations(1);
+            Ok(Value::DottedList(Rc::new(Items(vec![first])), Rc::new(second)))
+        }
+    }
+}
+
+// The first list's vector is reused when nothing else holds it, and the others are copied onto its end
+fn native_append(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    let mut lists = Vec::with_capacity(args.len());
+    for n in args.iter() {
+        match try!(evaluate_value(n, env)) {
+            Value::List(elements) => lists.push(elements),
+            v => type_error!("Arguments to append must be lists: {:?}", v)
+        }
+    }
+    let mut rest = lists.into_iter();
+    let mut result = match rest.next() {
+        Some(first) => unwrap_or_clone(first),
+        None => return Ok(null!())
+    };
+    for list in rest {
+        stats::count_allocations(list.len() as u64);
+        result.extend(list.iter().cloned());
+    }
+    Ok(Value::List(Rc::new(result)))
+}
+
+// Reverses in place when nothing else holds the list
+fn native_reverse(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to reverse: {:?}", args);
+    }
+    match try!(evaluate_value(&args[0], env)) {
+        Value::List(elements) => {
+            let mut reversed = unwrap_or_clone(elements);
+            reversed.reverse();
+            Ok(Value::List(Rc::new(reversed)))
+        },
+        v => type_error!("Argument to reverse must be a list: {:?}", v)
+    }
+}
+
+fn native_quote(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to quote: {:?}", args);
+    }
+    quote_value(&args[0], false, env)
+}
+
+fn native_quasiquote(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to quasiquote: {:?}", args);
+    }
+    quote_value(&args[0], true, env)
+}
+
+fn native_error(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one arguments to error: {:?}", args);
+    }
+    let e = try!(evaluate_value(&args[0], env));
+    runtime_error!("{:?}", e);
+}
+
+// (warn "message" irritant...) reports the message and irritants to the warning handler, and carries on
+fn native_warn(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.is_empty() {
+        arity_error!("Must supply at least one argument to warn: {:?}", args);
+    }
+    let mut message = match try!(evaluate_value(&args[0], env)) {
+        Value::String(s) => s.to_string(),
+        v => type_error!("Expected a string value: {:?}", v)
+    };
+    for arg in args[1..].iter() {
+        message.push_str(&format!(" {:?}", try!(evaluate_value(arg, env))));
+    }
+    warning::warn(&message);
+    Ok(null!())
+}
+
+fn native_log_debug(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    log_message(Level::Debug, args, env)
+}
+
+fn native_log_info(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    log_message(Level::Info, args, env)
+}
+
+fn native_log_warn(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    log_message(Level::Warn, args, env)
+}
+
+fn native_log_error(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    log_message(Level::Error, args, env)
+}
+
+// (log-info template arg ...), with the args filled in to the template's ~a and ~s
+fn log_message(level: Level, args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.is_empty() {
+        arity_error!("Must supply at least one argument to log-{}: {:?}", level.name(), args);
+    }
+    let template = match try!(evaluate_value(&args[0], env)) {
+        Value::String(s) => s,
+        v => type_error!("Expected a string value: {:?}", v)
+    };
+    let mut values = Vec::new();
+    for arg in args[1..].iter() {
+        values.push(try!(evaluate_value(arg, env)));
+    }
+    match log::format(&template, &values) {
+        Ok(message) => log::log(level, &message),
+        Err(e) => runtime_error!("{}", e)
+    }
+    Ok(null!())
+}
+
+// Like error, but for bugs in the program, which an embedder may not want to recover from
+fn native_assertion_violation(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to assertion-violation: {:?}", args);
+    }
+    let e = try!(evaluate_value(&args[0], env));
+    raise!(Kind::AssertionViolation, "{:?}", e);
+}
+
+// (raise c) raises the condition c again, as a guard does with the ones no clause handles. Anything else is raised
+// as a plain error, like (error obj).
+fn native_raise(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to raise: {:?}", args);
+    }
+    match try!(evaluate_value(&args[0], env)) {
+        Value::Condition(kind, message) => raise!(kind, "{}", message),
+        v => runtime_error!("{:?}", v)
+    }
+}
+
+// (guard (e clause...) body...) evaluates the body, and if that raises an error, binds its condition to e and
+// evaluates the first clause whose test is true, as cond would (an else clause always matches). If no clause
+// matches, the error is raised again.
+fn native_guard(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() < 2 {
+        arity_error!("Must supply at least two arguments to guard: {:?}", args);
+    }
+    let (name, clauses) = match args[0] {
+        Value::List(ref list) if !list.is_empty() => {
+            match list[0] {
+                Value::Symbol(ref name) => (name.clone(), &list[1..]),
+                _ => runtime_error!("Unexpected value for name in guard: {:?}", args)
+            }
+        },
+        _ => runtime_error!("Unexpected value for clauses in guard: {:?}", args)
+    };
+    let error = match evaluate_values(&args[1..], env) {
+        Ok(v) => return Ok(v),
+        Err(e) => e
+    };
+    let handler_env = Environment::new_child(env.clone());
+    try!(handler_env.borrow_mut().define(name, error.to_condition()));
+    for clause in clauses.iter() {
+        let parts = match *clause {
+            Value::List(ref parts) if !parts.is_empty() => parts,
+            _ => runtime_error!("Unexpected clause in guard: {:?}", clause)
+        };
+        let test = match parts[0] {
+            Value::Symbol(ref s) if s == "else" => Value::Boolean(true),
+            ref test => try!(evaluate_value(test, &handler_env))
+        };
+        match test {
+            Value::Boolean(false) => (),
+            _ if parts.len() == 1 => return Ok(test),
+            _ => return evaluate_values(&parts[1..], &handler_env)
+        }
+    }
+    Err(error)
+}
+
+fn native_error_object_message(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to error-object-message: {:?}", args);
+    }
+    match try!(evaluate_value(&args[0], env)) {
+        Value::Condition(_, message) => Ok(Value::String(message)),
+        v => type_error!("Expected a condition value: {:?}", v)
+    }
+}
+
+fn is_condition(kind: Kind, args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to {}: {:?}", kind.predicate(), args);
+    }
+    match try!(evaluate_value(&args[0], env)) {
+        Value::Condition(k, _) => Ok(Value::Boolean(k.is_a(kind))),
+        _ => Ok(Value::Boolean(false))
+    }
+}
+
+fn native_is_error(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    is_condition(Kind::Error, args, env)
+}
+
+fn native_is_file_error(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    is_condition(Kind::FileError, args, env)
+}
+
+fn native_is_read_error(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    is_condition(Kind::ReadError, args, env)
+}
+
+fn native_is_assertion_violation(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    is_condition(Kind::AssertionViolation, args, env)
+}
+
+fn native_is_arity_error(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    is_condition(Kind::ArityError, args, env)
+}
+
+fn native_is_type_error(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    is_condition(Kind::TypeError, args, env)
+}
+
+fn native_apply(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
     if args.len() != 2 {
-        runtime_error!("Must supply exactly two arguments to >: {:?}", args);
+        arity_error!("Must supply exactly two arguments to apply: {:?}", args);
     }
-    let l_raw = try!(evaluate_value(&args[0], env.clone()));
-    let r_raw = try!(evaluate_value(&args[1], env.clone()));
-    let l = match l_raw {
-        Value::Integer(x) => x,
-        _ => runtime_error!("Unexpected value during >: {:?}", args)
+    let func = match try!(evaluate_value(&args[0], env)) {
+        Value::Procedure(func) => func,
+        _ => type_error!("First argument to apply must be a procedure: {:?}", args)
     };
-    let r = match r_raw {
-        Value::Integer(x) => x,
-        _ => runtime_error!("Unexpected value during >: {:?}", args)
+    let func_args = match try!(evaluate_value(&args[1], env)) {
+        Value::List(func_args) => func_args,
+        _ => type_error!("Second argument to apply must be a list of arguments: {:?}", args)
     };
-    Ok(Value::Boolean(l > r))
+    apply_function(&func, &func_args[..], env)
+}
+
+fn native_values(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    let mut vals = Vec::with_capacity(args.len());
+    for n in args.iter() {
+        vals.push(try!(evaluate_value(n, env)));
+    }
+    if vals.len() == 1 {
+        return Ok(vals.remove(0))
+    }
+    Ok(Value::Values(Rc::new(Items(vals))))
 }
 
-fn native_equal(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+fn native_call_with_values(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
     if args.len() != 2 {
-        runtime_error!("Must supply exactly two arguments to =: {:?}", args);
+        arity_error!("Must supply exactly two arguments to call-with-values: {:?}", args);
     }
-    let l_raw = try!(evaluate_value(&args[0], env.clone()));
-    let r_raw = try!(evaluate_value(&args[1], env.clone()));
-    let l = match l_raw {
-        Value::Integer(x) => x,
-        _ => runtime_error!("Unexpected value during =: {:?}", args)
+    let (producer, consumer) = match (try!(evaluate_value(&args[0], env)), try!(evaluate_value(&args[1], env))) {
+        (Value::Procedure(p), Value::Procedure(c)) => (p, c),
+        _ => type_error!("Arguments to call-with-values must be procedures: {:?}", args)
     };
-    let r = match r_raw {
-        Value::Integer(x) => x,
-        _ => runtime_error!("Unexpected value during =: {:?}", args)
-    };
-    Ok(Value::Boolean(l == r))
+    match try!(apply_function(&producer, &[], env)) {
+        Value::Values(vals) => apply_function(&consumer, &quote_values(&vals), env),
+        val => apply_function(&consumer, &quote_values(&[val]), env)
+    }
 }
 
-fn native_and(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
-    let mut res = Value::Boolean(true);
-    for n in args.iter() {
-        let v = try!(evaluate_value(n, env.clone()));
-        match v {
-            Value::Boolean(false) => return Ok(Value::Boolean(false)),
-            _ => res = v
-        }
-    }
-    Ok(res)
+// Values to pass to apply_function, which evaluates its arguments: lists, dotted lists and symbols are quoted so that
+// they stand for themselves, and everything else already evaluates to itself
+fn quote_values(values: &[Value]) -> Vec<Value> {
+    values.iter().map(|v| match *v {
+        Value::List(_) | Value::DottedList(..) | Value::Symbol(_) => Value::List(Rc::new(Items(vec![Value::Symbol("quote".to_string()), v.clone()]))),
+        _ => v.clone()
+    }).collect()
 }
 
-fn native_or(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
-    for n in args.iter() {
-        let v = try!(evaluate_value(n, env.clone()));
-        match v {
-            Value::Boolean(false) => (),
-            _ => return Ok(v)
-        }
+fn native_eval(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to eval: {:?}", args);
     }
-    Ok(Value::Boolean(false))
+
+    // eval is basically just a double-evaluation -- the first evaluate returns the data using the local envirnoment, and the second evaluate evaluates the data as code using the global environment
+    let res = try!(evaluate_value(&args[0], env));
+    evaluate_value(&res, &Environment::get_root(env))
 }
 
-fn native_null(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+#[cfg(feature = "io")]
+fn native_write(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
     if args.len() != 1 {
-        runtime_error!("Must supply exactly one argument to null?: {:?}", args);
+        arity_error!("Must supply exactly one argument to write: {:?}", args);
     }
-    let v = try!(evaluate_value(&args[0], env.clone()));
-    match v {
-        Value::List(l) => Ok(Value::Boolean(l.len() == 0)),
-        _ => Ok(Value::Boolean(false))
+
+    let val = try!(evaluate_value(&args[0], env));
+    print!("{}", printer::print(&val, Style::Write));
+    Ok(null!())
+}
+
+#[cfg(feature = "io")]
+fn native_write_simple(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to write-simple: {:?}", args);
     }
+
+    let val = try!(evaluate_value(&args[0], env));
+    print!("{}", printer::print(&val, Style::WriteSimple));
+    Ok(null!())
 }
 
-fn native_list(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
-    let res: Result<Vec<Value>, RuntimeError> = args.iter().map(|n| evaluate_value(n, env.clone())).collect();
-    let elements = try!(res);
-    Ok(Value::List(elements))
+#[cfg(feature = "io")]
+fn native_write_shared(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to write-shared: {:?}", args);
+    }
+
+    let val = try!(evaluate_value(&args[0], env));
+    print!("{}", printer::print(&val, Style::WriteShared));
+    Ok(null!())
 }
 
-fn native_car(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+#[cfg(feature = "io")]
+fn native_display(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
     if args.len() != 1 {
-        runtime_error!("Must supply exactly one argument to car: {:?}", args);
+        arity_error!("Must supply exactly one argument to display: {:?}", args);
     }
-    let v = try!(evaluate_value(&args[0], env.clone()));
-    match v {
-        Value::List(mut l) => {
-            if l.len() > 0 {
-                Ok(l.remove(0))
-            } else {
-                runtime_error!("Can't run car on an empty list")
-            }
-        }
-        _ => runtime_error!("Must supply a list to car")
+
+    let val = try!(evaluate_value(&args[0], env));
+    print!("{}", printer::print(&val, Style::Display));
+    Ok(null!())
+}
+
+#[cfg(feature = "io")]
+fn native_displayln(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to displayln: {:?}", args);
     }
+
+    let val = try!(evaluate_value(&args[0], env));
+    println!("{}", printer::print(&val, Style::Display));
+    Ok(null!())
 }
 
-fn native_cdr(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+#[cfg(feature = "io")]
+fn native_print(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
     if args.len() != 1 {
-        runtime_error!("Must supply exactly one argument to cdr: {:?}", args);
+        arity_error!("Must supply exactly one argument to print: {:?}", args);
     }
-    let v = try!(evaluate_value(&args[0], env.clone()));
-    match v {
-        Value::List(mut l) => {
-            if l.len() > 0 {
-                l.remove(0);
-                Ok(Value::List(l))
-            } else {
-                runtime_error!("Can't run cdr on an empty list")
-            }
-        }
-        _ => runtime_error!("Must supply a list to cdr")
+
+    let val = try!(evaluate_value(&args[0], env));
+    match val {
+        Value::Symbol(_) | Value::List(_) => print!("'{}", printer::print(&val, Style::Write)),
+        _ => print!("{}", printer::print(&val, Style::Write))
     }
+    Ok(null!())
 }
 
-fn native_cons(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
-    if args.len() != 2 {
-        runtime_error!("Must supply exactly two arguments to cons: {:?}", args);
+#[cfg(feature = "io")]
+#[allow(unused_variables)]
+fn native_newline(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if !args.is_empty() {
+        arity_error!("Must supply exactly zero arguments to newline: {:?}", args);
     }
+    println!("");
+    Ok(null!())
+}
 
-    let first = try!(evaluate_value(&args[0], env.clone()));
-    let second = try!(evaluate_value(&args[1], env.clone()));
-    match second {
-        Value::List(elements) => {
-            let mut new_elements = vec![first];
-            for e in elements.into_iter() {
-                new_elements.push(e);
-            }
-            return Ok(Value::List(new_elements))
-        }
-        _ => runtime_error!("Second argument to cons must be a list: {:?}", second)
+#[cfg(feature = "io")]
+fn native_printer_option(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 1 {
+        arity_error!("Must supply exactly one argument to printer-option: {:?}", args);
+    }
+    let name = match try!(evaluate_value(&args[0], env)) {
+        Value::Symbol(name) => name,
+        v => type_error!("Expected a symbol value: {:?}", v)
+    };
+    match printer::option(&name) {
+        Ok(Setting::Limit(n)) => Ok(Value::Integer(n as i64)),
+        Ok(Setting::On) => Ok(Value::Boolean(true)),
+        Ok(Setting::Off) => Ok(Value::Boolean(false)),
+        Err(e) => runtime_error!("{}", e)
     }
 }
 
-fn native_append(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+// (set-printer-option! 'max-length 10) limits printing to that many elements; #f removes the limit again
+#[cfg(feature = "io")]
+fn native_set_printer_option(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
     if args.len() != 2 {
-        runtime_error!("Must supply exactly two arguments to append: {:?}", args);
+        arity_error!("Must supply exactly two arguments to set-printer-option!: {:?}", args);
     }
-
-    let first = try!(evaluate_value(&args[0], env.clone()));
-    let second = try!(evaluate_value(&args[1], env.clone()));
-    let mut first_vec = match first {
-        Value::List(elements) => elements,
-        _ => runtime_error!("First argument to append must be a list: {:?}", first)
+    let name = match try!(evaluate_value(&args[0], env)) {
+        Value::Symbol(name) => name,
+        v => type_error!("Expected a symbol value: {:?}", v)
     };
-    let second_vec = match second {
-        Value::List(elements) => elements,
-        _ => runtime_error!("Second argument to append must be a list: {:?}", second)
+    let setting = match try!(evaluate_value(&args[1], env)) {
+        Value::Integer(n) if n >= 0 => Setting::Limit(n as usize),
+        Value::Boolean(true) => Setting::On,
+        Value::Boolean(false) => Setting::Off,
+        v => type_error!("Printer options must be a non-negative integer, #t or #f: {:?}", v)
     };
-    for e in second_vec.into_iter() {
-        first_vec.push(e);
+    match printer::set_option(&name, setting) {
+        Ok(_) => Ok(null!()),
+        Err(e) => runtime_error!("{}", e)
     }
-    return Ok(Value::List(first_vec))
 }
 
-fn native_quote(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
-    if args.len() != 1 {
-        runtime_error!("Must supply exactly one argument to quote: {:?}", args);
+#[cfg(feature = "io")]
+#[allow(unused_variables)]
+fn native_read(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if !args.is_empty() {
+        arity_error!("Must supply exactly zero arguments to read: {:?}", args);
+    }
+    match input::read_datum() {
+        Ok(Some(node)) => Ok(Value::from_node(&node)),
+        Ok(None) => Ok(Value::EofObject),
+        Err(e) => read_error!("{}", e)
+    }
+}
+
+#[cfg(feature = "io")]
+#[allow(unused_variables)]
+fn native_read_char(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if !args.is_empty() {
+        arity_error!("Must supply exactly zero arguments to read-char: {:?}", args);
+    }
+    match input::read_char() {
+        Ok(Some(c)) => Ok(Value::Character(c)),
+        Ok(None) => Ok(Value::EofObject),
+        Err(e) => runtime_error!("{}", e)
     }
-    quote_value(&args[0], false, env.clone())
 }
 
-fn native_quasiquote(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+#[cfg(feature = "io")]
+fn native_write_canonical(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
     if args.len() != 1 {
-        runtime_error!("Must supply exactly one argument to quasiquote: {:?}", args);
+        arity_error!("Must supply exactly one argument to write-canonical: {:?}", args);
+    }
+    let val = try!(evaluate_value(&args[0], env));
+    let written = match val.to_data() {
+        Ok(node) => canonical::write(&node),
+        Err(v) => type_error!("Can't write {:?} as a canonical s-expression", v)
+    };
+    match written {
+        Ok(text) => print!("{}", text),
+        Err(e) => type_error!("{}", e)
+    }
+    // whatever's reading it is waiting for the whole expression
+    match io::stdout().flush() {
+        Ok(_) => Ok(null!()),
+        Err(e) => file_error!("Couldn't write to stdout: {}", e)
+    }
+}
+
+#[cfg(feature = "io")]
+#[allow(unused_variables)]
+fn native_read_canonical(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if !args.is_empty() {
+        arity_error!("Must supply exactly zero arguments to read-canonical: {:?}", args);
+    }
+    match canonical::read(&mut input::read_char) {
+        Ok(Some(node)) => Ok(Value::from_node(&node)),
+        Ok(None) => Ok(Value::EofObject),
+        Err(e) => read_error!("{}", e)
     }
-    quote_value(&args[0], true, env.clone())
 }
 
-fn native_error(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+// (csv-read [text]), reading the rest of stdin, or text if it's given, as a list of records of string fields
+#[cfg(feature = "io")]
+fn native_csv_read(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() > 1 {
+        arity_error!("Must supply zero or one arguments to csv-read: {:?}", args);
+    }
+    let records = match args.first() {
+        Some(arg) => match try!(evaluate_value(arg, env)) {
+            Value::String(text) => {
+                let mut chars = text.chars();
+                csv::read(&mut || Ok(chars.next()))
+            },
+            v => type_error!("Expected a string value: {:?}", v)
+        },
+        None => csv::read(&mut input::read_char)
+    };
+    match records {
+        Ok(records) => Ok(Value::from_node(&csv_records(records))),
+        Err(e) => read_error!("{}", e)
+    }
+}
+
+// (csv-write rows), writing a list of lists of strings, integers and symbols to stdout
+#[cfg(feature = "io")]
+fn native_csv_write(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
     if args.len() != 1 {
-        runtime_error!("Must supply exactly one arguments to error: {:?}", args);
+        arity_error!("Must supply exactly one argument to csv-write: {:?}", args);
     }
-    let e = try!(evaluate_value(&args[0], env.clone()));
-    runtime_error!("{:?}", e);
+    let val = try!(evaluate_value(&args[0], env));
+    let written = match val.to_data() {
+        Ok(node) => csv::write(&node),
+        Err(v) => type_error!("Can't write {:?} as a CSV field", v)
+    };
+    match written {
+        Ok(text) => print!("{}", text),
+        Err(e) => type_error!("{}", e)
+    }
+    match io::stdout().flush() {
+        Ok(_) => Ok(null!()),
+        Err(e) => file_error!("Couldn't write to stdout: {}", e)
+    }
+}
+
+#[cfg(feature = "io")]
+fn csv_records(records: Vec<Vec<String>>) -> Node {
+    Node::List(records.into_iter().map(|record| Node::List(record.into_iter().map(Node::String).collect())).collect())
 }
 
-fn native_apply(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+// (directory-walk path proc), calling (proc file) for every file under path
+#[cfg(feature = "io")]
+fn native_directory_walk(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
     if args.len() != 2 {
-        runtime_error!("Must supply exactly two arguments to apply: {:?}", args);
+        arity_error!("Must supply exactly two arguments to directory-walk: {:?}", args);
     }
-    let func = match try!(evaluate_value(&args[0], env.clone())) {
-        Value::Procedure(func) => func,
-        _ => runtime_error!("First argument to apply must be a procedure: {:?}", args)
+    let root = match try!(evaluate_value(&args[0], env)) {
+        Value::String(s) => s,
+        v => type_error!("Expected a string value: {:?}", v)
     };
-    let func_args = match try!(evaluate_value(&args[1], env.clone())) {
-        Value::List(func_args) => func_args,
-        _ => runtime_error!("Second argument to apply must be a list of arguments: {:?}", args)
+    let procedure = match try!(evaluate_value(&args[1], env)) {
+        Value::Procedure(f) => f,
+        v => type_error!("Expected a procedure value: {:?}", v)
+    };
+    let files = match files::walk(&root) {
+        Ok(files) => files,
+        Err(e) => file_error!("{}", e)
     };
-    apply_function(&func, &func_args, env.clone())
+    for file in files.into_iter() {
+        try!(apply_function(&procedure, &[Value::String(Rc::from(file))], env));
+    }
+    Ok(null!())
 }
 
-fn native_eval(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+// (glob pattern), the sorted list of paths matching pattern
+#[cfg(feature = "io")]
+fn native_glob(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
     if args.len() != 1 {
-        runtime_error!("Must supply exactly one argument to eval: {:?}", args);
+        arity_error!("Must supply exactly one argument to glob: {:?}", args);
     }
+    match try!(evaluate_value(&args[0], env)) {
+        Value::String(pattern) => Ok(Value::List(Rc::new(Items(files::glob(&pattern).into_iter().map(|p| Value::String(Rc::from(p))).collect())))),
+        v => type_error!("Expected a string value: {:?}", v)
+    }
+}
 
-    // eval is basically just a double-evaluation -- the first evaluate returns the data using the local envirnoment, and the second evaluate evaluates the data as code using the global environment
-    let res = try!(evaluate_value(&args[0], env.clone()));
-    evaluate_value(&res, Environment::get_root(env))
+#[cfg(feature = "io")]
+fn native_path_join(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.is_empty() {
+        arity_error!("Must supply at least one argument to path-join: {:?}", args);
+    }
+    let mut parts = Vec::new();
+    for arg in args.iter() {
+        match try!(evaluate_value(arg, env)) {
+            Value::String(s) => parts.push(s),
+            v => type_error!("Expected a string value: {:?}", v)
+        }
+    }
+    let parts: Vec<&str> = parts.iter().map(|p| &**p).collect();
+    Ok(Value::String(Rc::from(paths::join(&parts))))
+}
+
+#[cfg(feature = "io")]
+fn native_path_directory(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    let path = try!(path_argument("path-directory", args, env));
+    Ok(paths::directory(&path).map_or(Value::Boolean(false), |p| Value::String(Rc::from(p))))
+}
+
+#[cfg(feature = "io")]
+fn native_path_filename(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    let path = try!(path_argument("path-filename", args, env));
+    Ok(paths::filename(&path).map_or(Value::Boolean(false), |p| Value::String(Rc::from(p))))
+}
+
+#[cfg(feature = "io")]
+fn native_path_extension(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    let path = try!(path_argument("path-extension", args, env));
+    Ok(paths::extension(&path).map_or(Value::Boolean(false), |p| Value::String(Rc::from(p))))
+}
+
+#[cfg(feature = "io")]
+fn native_path_absolute(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    let path = try!(path_argument("path-absolute?", args, env));
+    Ok(Value::Boolean(paths::is_absolute(&path)))
 }
 
-fn native_write(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+#[cfg(feature = "io")]
+fn native_expand_user_path(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    let path = try!(path_argument("expand-user-path", args, env));
+    match paths::expand_user(&path) {
+        Ok(expanded) => Ok(Value::String(Rc::from(expanded))),
+        Err(e) => runtime_error!("{}", e)
+    }
+}
+
+#[cfg(feature = "io")]
+fn path_argument(name: &str, args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Rc<str>, RuntimeError> {
     if args.len() != 1 {
-        runtime_error!("Must supply exactly one argument to write: {:?}", args);
+        arity_error!("Must supply exactly one argument to {}: {:?}", name, args);
+    }
+    match try!(evaluate_value(&args[0], env)) {
+        Value::String(s) => Ok(s),
+        v => type_error!("Expected a string value: {:?}", v)
     }
+}
 
-    let val = try!(evaluate_value(&args[0], env.clone()));
-    print!("{:?}", val);
-    Ok(null!())
+// (http-get url [headers])
+#[cfg(feature = "net")]
+fn native_http_get(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.is_empty() || args.len() > 2 {
+        arity_error!("Must supply one or two arguments to http-get: {:?}", args);
+    }
+    let url = try!(evaluate_value(&args[0], env));
+    let headers = match args.get(1) {
+        Some(headers) => Some(try!(evaluate_value(headers, env))),
+        None => None
+    };
+    http_request("http-get", "GET", url, None, headers, env)
 }
 
-fn native_display(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
-    if args.len() != 1 {
-        runtime_error!("Must supply exactly one argument to display: {:?}", args);
+// (http-post url body [headers])
+#[cfg(feature = "net")]
+fn native_http_post(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() < 2 || args.len() > 3 {
+        arity_error!("Must supply two or three arguments to http-post: {:?}", args);
+    }
+    let url = try!(evaluate_value(&args[0], env));
+    let body = try!(evaluate_value(&args[1], env));
+    let headers = match args.get(2) {
+        Some(headers) => Some(try!(evaluate_value(headers, env))),
+        None => None
+    };
+    http_request("http-post", "POST", url, Some(body), headers, env)
+}
+
+// An error for the named builtin unless the interpreter was granted what it needs (see capabilities.rs)
+#[cfg(any(feature = "net", feature = "ffi"))]
+fn check_capability(name: &str, env: &Rc<RefCell<Environment>>) -> Result<(), RuntimeError> {
+    let root = Environment::get_root(env);
+    let granted = root.borrow().capabilities;
+    match granted.check(name) {
+        Ok(_) => Ok(()),
+        Err(e) => runtime_error!("{}", e)
+    }
+}
+
+// The response as (status ((name value) ...) body)
+#[cfg(feature = "net")]
+fn http_request(name: &str, method: &str, url: Value, body: Option<Value>, headers: Option<Value>,
+                env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    try!(check_capability(name, env));
+    let url = match url {
+        Value::String(s) => s,
+        v => type_error!("Expected a string value: {:?}", v)
+    };
+    let body = match body {
+        Some(Value::String(s)) => Some(s),
+        Some(v) => type_error!("Expected a string value: {:?}", v),
+        None => None
+    };
+    let headers = match headers {
+        Some(v) => match v.to_data().map_err(|_| v.to_string()).and_then(|node| http::headers_from_node(&node)) {
+            Ok(headers) => headers,
+            Err(e) => type_error!("{}", e)
+        },
+        None => Vec::new()
+    };
+    match http::request(method, &url, &headers, body.as_deref()) {
+        Ok(response) => Ok(Value::from_node(&response.to_node())),
+        Err(e) => runtime_error!("{}", e)
     }
+}
+
+// (serve-http port handler [count]), answering each request with what (handler request) returns, forever or until
+// count requests have been answered. port is a port number on localhost or a "host:port" string.
+#[cfg(feature = "net")]
+fn native_serve_http(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() < 2 || args.len() > 3 {
+        arity_error!("Must supply two or three arguments to serve-http: {:?}", args);
+    }
+    let address = match try!(evaluate_value(&args[0], env)) {
+        Value::Integer(port) if (0..=65535).contains(&port) => format!("127.0.0.1:{}", port),
+        Value::String(address) => address.to_string(),
+        v => type_error!("Expected a port number or \"host:port\" string: {:?}", v)
+    };
+    let handler = match try!(evaluate_value(&args[1], env)) {
+        Value::Procedure(f) => f,
+        v => type_error!("Expected a procedure value: {:?}", v)
+    };
+    let count = match args.get(2) {
+        Some(count) => match try!(evaluate_value(count, env)) {
+            Value::Integer(n) if n >= 0 => Some(n),
+            v => type_error!("Expected a non-negative integer value: {:?}", v)
+        },
+        None => None
+    };
+    try!(check_capability("serve-http", env));
 
-    let val = try!(evaluate_value(&args[0], env.clone()));
-    print!("{}", val);
+    let mut server = match http::Server::bind(&address) {
+        Ok(server) => server,
+        Err(e) => runtime_error!("{}", e)
+    };
+    let mut answered = 0;
+    while count.is_none_or(|count| answered < count) {
+        let request = match server.accept() {
+            Ok(request) => Value::from_node(&request),
+            Err(e) => runtime_error!("{}", e)
+        };
+        let response = try!(apply_function(&handler, &quote_values(&[request]), env));
+        let response = match response.to_data() {
+            Ok(node) => node,
+            Err(_) => type_error!("Expected a response list or string: {:?}", response)
+        };
+        match server.respond(&response) {
+            Ok(_) => answered += 1,
+            Err(e) => runtime_error!("{}", e)
+        }
+    }
     Ok(null!())
 }
 
-fn native_displayln(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+// (load-foreign-library path), e.g. "libm.so.6"
+#[cfg(feature = "ffi")]
+fn native_load_foreign_library(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
     if args.len() != 1 {
-        runtime_error!("Must supply exactly one argument to displayln: {:?}", args);
+        arity_error!("Must supply exactly one argument to load-foreign-library: {:?}", args);
     }
+    try!(check_capability("load-foreign-library", env));
+    let path = match try!(evaluate_value(&args[0], env)) {
+        Value::String(s) => s,
+        v => type_error!("Expected a string value: {:?}", v)
+    };
+    match ffi::Library::open(&path) {
+        Ok(library) => Ok(Value::Library(Rc::new(library))),
+        Err(e) => runtime_error!("{}", e)
+    }
+}
 
-    let val = try!(evaluate_value(&args[0], env.clone()));
-    println!("{}", val);
-    Ok(null!())
+// (foreign-procedure library name '(parameter-type ...) 'result-type), e.g. (foreign-procedure libc "labs" '(long) 'long)
+#[cfg(feature = "ffi")]
+fn native_foreign_procedure(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 4 {
+        arity_error!("Must supply exactly four arguments to foreign-procedure: {:?}", args);
+    }
+    try!(check_capability("foreign-procedure", env));
+    let library = match try!(evaluate_value(&args[0], env)) {
+        Value::Library(l) => l,
+        v => type_error!("Expected a foreign library value: {:?}", v)
+    };
+    let name = match try!(evaluate_value(&args[1], env)) {
+        Value::String(s) => s,
+        v => type_error!("Expected a string value: {:?}", v)
+    };
+    let mut parameters = Vec::new();
+    match try!(evaluate_value(&args[2], env)) {
+        Value::List(types) => for t in types.iter() {
+            match *t {
+                Value::Symbol(ref t) => parameters.push(t.clone()),
+                ref v => type_error!("Expected a symbol value: {:?}", v)
+            }
+        },
+        v => type_error!("Expected a list value: {:?}", v)
+    }
+    let result = match try!(evaluate_value(&args[3], env)) {
+        Value::Symbol(s) => s,
+        v => type_error!("Expected a symbol value: {:?}", v)
+    };
+    match ffi::Procedure::new(library, &name, &parameters, &result) {
+        Ok(procedure) => Ok(Value::Procedure(Function::Foreign(Rc::new(procedure)))),
+        Err(e) => runtime_error!("{}", e)
+    }
 }
 
-fn native_print(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+// (sqlite-open path), where path ":memory:" opens a new database in memory
+#[cfg(feature = "sqlite")]
+fn native_sqlite_open(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
     if args.len() != 1 {
-        runtime_error!("Must supply exactly one argument to print: {:?}", args);
+        arity_error!("Must supply exactly one argument to sqlite-open: {:?}", args);
     }
+    let path = match try!(evaluate_value(&args[0], env)) {
+        Value::String(s) => s,
+        v => type_error!("Expected a string value: {:?}", v)
+    };
+    match sqlite::Database::open(&path) {
+        Ok(db) => Ok(Value::Database(Rc::new(db))),
+        Err(e) => runtime_error!("{}", e)
+    }
+}
 
-    let val = try!(evaluate_value(&args[0], env.clone()));
-    match val {
-        Value::Symbol(_) | Value::List(_) => print!("'{:?}", val),
-        _ => print!("{:?}", val)
+// (sqlite-exec db sql parameter ...), returning how many rows the last statement changed
+#[cfg(feature = "sqlite")]
+fn native_sqlite_exec(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    let (db, sql, parameters) = try!(sqlite_arguments("sqlite-exec", args, env));
+    match db.exec(&sql, &parameters) {
+        Ok(changed) => Ok(Value::Integer(changed)),
+        Err(e) => runtime_error!("{}", e)
     }
-    Ok(null!())
 }
 
-#[allow(unused_variables)]
-fn native_newline(args: &[Value], env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
-    if args.len() != 0 {
-        runtime_error!("Must supply exactly zero arguments to newline: {:?}", args);
+// (sqlite-query db sql parameter ...), returning the rows as lists of (column value) lists
+#[cfg(feature = "sqlite")]
+fn native_sqlite_query(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    let (db, sql, parameters) = try!(sqlite_arguments("sqlite-query", args, env));
+    match db.query(&sql, &parameters) {
+        Ok(rows) => Ok(Value::from_node(&rows)),
+        Err(e) => runtime_error!("{}", e)
+    }
+}
+
+// A database, a statement for it and the statement's parameters
+#[cfg(feature = "sqlite")]
+type Statement = (Rc<sqlite::Database>, Rc<str>, Vec<Node>);
+
+#[cfg(feature = "sqlite")]
+fn sqlite_arguments(name: &str, args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Statement, RuntimeError> {
+    if args.len() < 2 {
+        arity_error!("Must supply a database and a statement to {}: {:?}", name, args);
+    }
+    let db = match try!(evaluate_value(&args[0], env)) {
+        Value::Database(db) => db,
+        v => type_error!("Expected a database value: {:?}", v)
+    };
+    let sql = match try!(evaluate_value(&args[1], env)) {
+        Value::String(s) => s,
+        v => type_error!("Expected a string value: {:?}", v)
+    };
+    let mut parameters = Vec::new();
+    for arg in args[2..].iter() {
+        let v = try!(evaluate_value(arg, env));
+        match v.to_data() {
+            Ok(node) => parameters.push(node),
+            Err(_) => type_error!("Can't pass {:?} to SQLite", v)
+        }
+    }
+    Ok((db, sql, parameters))
+}
+
+// The Scheme handlers on-signal set on this thread
+#[cfg(all(unix, feature = "process"))]
+thread_local!(static SIGNAL_HANDLERS: RefCell<Vec<(signals::Signal, Function)>> = const { RefCell::new(Vec::new()) });
+
+// (on-signal 'SIGTERM handler) calls (handler 'SIGTERM) when the signal arrives, at the next step of evaluation, and
+// (on-signal 'SIGTERM #f) goes back to the signal's default action
+#[cfg(all(unix, feature = "process"))]
+fn native_on_signal(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 2 {
+        arity_error!("Must supply exactly two arguments to on-signal: {:?}", args);
+    }
+    let signal = match try!(evaluate_value(&args[0], env)) {
+        Value::Symbol(ref name) => match signals::number(name) {
+            Some(signal) => signal,
+            None => runtime_error!("Can't handle the signal {} (only {} can be)", name, signals::names().join(", "))
+        },
+        v => type_error!("Expected a symbol value: {:?}", v)
+    };
+    let handler = match try!(evaluate_value(&args[1], env)) {
+        Value::Procedure(f) => Some(f),
+        Value::Boolean(false) => None,
+        v => type_error!("Expected a procedure value or #f: {:?}", v)
+    };
+    SIGNAL_HANDLERS.with(|h| h.borrow_mut().retain(|&(s, _)| s != signal));
+    match handler {
+        Some(f) => {
+            SIGNAL_HANDLERS.with(|h| h.borrow_mut().push((signal, f)));
+            match signals::watch(signal) {
+                Ok(_) => Ok(null!()),
+                Err(e) => runtime_error!("{}", e)
+            }
+        },
+        None => {
+            signals::unwatch(signal);
+            Ok(null!())
+        }
+    }
+}
+
+// Call the handlers of the signals that have arrived. An error in one stops the program like any other.
+#[cfg(all(unix, feature = "process"))]
+fn handle_signals(env: &Rc<RefCell<Environment>>) -> Result<(), RuntimeError> {
+    let handlers = SIGNAL_HANDLERS.with(|h| h.borrow().clone());
+    for (signal, handler) in handlers.into_iter() {
+        if signals::take(signal) {
+            let name = Value::Symbol(signals::name(signal).to_string());
+            try!(apply_function(&handler, &quote_values(&[name]), env));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "parallel")]
+fn native_par_map(args: &[Value], env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    if args.len() != 2 {
+        arity_error!("Must supply exactly two arguments to par-map: {:?}", args);
+    }
+    let procedure = match try!(evaluate_value(&args[0], env)) {
+        Value::Procedure(f) => try!(procedure_source(&f)),
+        v => type_error!("First argument to par-map must be a procedure: {:?}", v)
+    };
+    let elements: Result<Vec<Node>, RuntimeError> = match try!(evaluate_value(&args[1], env)) {
+        Value::List(list) => list.iter().map(Value::to_node).collect(),
+        v => type_error!("Second argument to par-map must be a list: {:?}", v)
+    };
+    let prelude = global_definitions(&Environment::get_root(env));
+    match parallel::map::<Interpreter>(prelude, procedure, try!(elements)) {
+        Ok(results) => Ok(Value::List(Rc::new(Items(Value::from_nodes(&results))))),
+        Err(e) => runtime_error!("{}", e)
+    }
+}
+
+// The source for a procedure, including the local variables it closed over (which must hold plain data)
+#[cfg(feature = "parallel")]
+fn procedure_source(f: &Function) -> Result<Node, RuntimeError> {
+    match *f {
+        Function::Native(native_fn) => {
+            match native_names(native_fn).first() {
+                Some(name) => Ok(Node::Identifier(name.to_string())),
+                None => runtime_error!("Can't send an unnamed native procedure to another thread")
+            }
+        },
+        Function::Plugin(ref b) => Ok(Node::Identifier(b.name().to_string())),
+        #[cfg(feature = "ffi")]
+        Function::Foreign(_) => runtime_error!("Can't send a foreign procedure to another thread"),
+        Function::Scheme(ref arg_names, ref body, ref env) => {
+            let body: Result<Vec<Node>, RuntimeError> = body.iter().map(Value::to_node).collect();
+            // innermost scope first, up to but not including the global environment
+            let mut captured: Vec<(String, Node)> = Vec::new();
+            let mut scope = env.clone();
+            loop {
+                let parent = match scope.borrow().parent {
+                    Some(ref parent) => parent.clone(),
+                    None => break
+                };
+                for (name, value) in scope.borrow().values.iter() {
+                    if !captured.iter().any(|c| c.0 == *name) {
+                        captured.push((name.clone(), try!(value.to_node())));
+                    }
+                }
+                scope = parent;
+            }
+            Ok(parallel::lambda(arg_names, try!(body), captured))
+        }
+    }
+}
+
+// The builtin called name
+fn builtin(name: &str) -> Option<ValueOperation> {
+    for group in [CORE_FUNCTIONS, MATH_FUNCTIONS, IO_FUNCTIONS, NET_FUNCTIONS, PROCESS_FUNCTIONS, PARALLEL_FUNCTIONS, SQLITE_FUNCTIONS,
+                  FFI_FUNCTIONS].iter() {
+        for &(n, func) in group.iter() {
+            if n == name {
+                return Some(func)
+            }
+        }
+    }
+    None
+}
+
+// The builtin names a native is registered under (there can be several, e.g. lambda and λ)
+#[cfg(feature = "parallel")]
+fn native_names(f: ValueOperation) -> Vec<&'static str> {
+    let mut names = Vec::new();
+    for group in [CORE_FUNCTIONS, MATH_FUNCTIONS, IO_FUNCTIONS, NET_FUNCTIONS, PROCESS_FUNCTIONS, PARALLEL_FUNCTIONS, SQLITE_FUNCTIONS,
+                  FFI_FUNCTIONS].iter() {
+        for &(name, func) in group.iter() {
+            if func as usize == f as usize {
+                names.push(name);
+            }
+        }
+    }
+    names
+}
+
+// Definitions recreating the global environment in a worker. Anything that can't be sent is left out, so a worker
+// only fails if it actually uses it.
+#[cfg(feature = "parallel")]
+fn global_definitions(root: &Rc<RefCell<Environment>>) -> Vec<Node> {
+    let mut prelude = Vec::new();
+    for (name, value) in root.borrow().values.iter() {
+        let definition = match *value {
+            Value::Procedure(Function::Native(f)) if native_names(f).contains(&name.as_str()) => continue,
+            Value::Procedure(ref f) => procedure_source(f).map(|source| parallel::define(name, source)),
+            Value::Macro(ref arg_names, ref body) => {
+                let body: Result<Vec<Node>, RuntimeError> = body.iter().map(Value::to_node).collect();
+                body.map(|body| parallel::define_syntax_rule(name, arg_names, body))
+            },
+            _ => value.to_node().map(|data| parallel::define(name, parallel::quote(data)))
+        };
+        if let Ok(definition) = definition {
+            prelude.push(definition);
+        }
+    }
+    prelude
+}
+
+#[cfg(feature = "parallel")]
+impl parallel::Worker for Interpreter {
+    fn start(prelude: &[Node]) -> Result<Interpreter, String> {
+        let interpreter = Interpreter::new();
+        match interpreter.run(prelude) {
+            Ok(_) => Ok(interpreter),
+            Err(e) => Err(e.message)
+        }
+    }
+
+    fn call(&self, procedure: &Node, element: Node) -> Result<Node, String> {
+        let call = Node::List(vec![procedure.clone(), parallel::quote(element)]);
+        match self.run(&[call]).and_then(|v| v.to_node()) {
+            Ok(result) => Ok(result),
+            Err(e) => Err(e.message)
+        }
+    }
+}
+
+#[test]
+fn test_builtins_documented() {
+    for group in [CORE_FUNCTIONS, MATH_FUNCTIONS, IO_FUNCTIONS, NET_FUNCTIONS, PROCESS_FUNCTIONS, PARALLEL_FUNCTIONS, SQLITE_FUNCTIONS,
+                  FFI_FUNCTIONS].iter() {
+        for &(name, _) in group.iter() {
+            assert!(docs::lookup(name).is_some(), "{} isn't documented", name);
+        }
     }
-    println!("");
-    Ok(null!())
 }
 
 #[test]
@@ -845,3 +3909,27 @@ fn test_interpreter_global_function_definition() {
     assert_eq!(new().run(&[Node::List(vec![Node::Identifier("define".to_string()), Node::Identifier("double".to_string()), Node::List(vec![Node::Identifier("lambda".to_string()), Node::List(vec![Node::Identifier("x".to_string())]), Node::List(vec![Node::Identifier("+".to_string()), Node::Identifier("x".to_string()), Node::Identifier("x".to_string())])])]), Node::List(vec![Node::Identifier("double".to_string()), Node::Integer(8)])]).unwrap(),
                Value::Integer(16));
 }
+
+#[test]
+fn test_deep_values() {
+    // ((((... (unquote x) ...)))) nested a million deep, and what it should quasiquote to
+    let env = Environment::new_root();
+    env.borrow_mut().define("x".to_string(), Value::Integer(1)).unwrap();
+    let mut template = Value::List(Rc::new(Items(vec![Value::Symbol("unquote".to_string()), Value::Symbol("x".to_string())])));
+    let mut expected = Value::Integer(1);
+    for _ in 0..1000000 {
+        template = Value::List(Rc::new(Items(vec![template])));
+        expected = Value::List(Rc::new(Items(vec![expected])));
+    }
+    let quoted = quote_value(&template, true, &env).unwrap();
+    assert!(quoted == expected);
+    assert_eq!(format!("{:?}", quoted), format!("{}1{}", "(".repeat(1000000), ")".repeat(1000000)));
+}
+
+#[test]
+fn test_write_shared() {
+    let src = "(let ((x (list 1 2)) (s \"ab\")) (list x (list 1 2) (vector x) s s))";
+    let value = new().run(&parse(&lexer::tokenize(src).unwrap()).unwrap()).unwrap();
+    assert_eq!(printer::print(&value, Style::Write), "((1 2) (1 2) #((1 2)) \"ab\" \"ab\")");
+    assert_eq!(printer::print(&value, Style::WriteShared), "(#0=(1 2) (1 2) #(#0#) #1=\"ab\" #1#)");
+}
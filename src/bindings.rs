@@ -0,0 +1,135 @@
+// Variable storage for an environment, shared by both interpreters. Every procedure call creates an environment
+// holding just its arguments, so a few bindings are kept inline (searched linearly, no table to allocate) and only
+// moved into a HashMap once an environment outgrows that, as the global one does.
+
+extern crate smallvec;
+
+use self::smallvec::SmallVec;
+use random::HashState;
+
+use std::collections::HashMap;
+use std::collections::hash_map;
+use std::slice;
+
+const INLINE: usize = 4;
+
+#[derive(Debug)]
+pub enum Bindings<V> {
+    Small(SmallVec<[(String, V); INLINE]>),
+    Large(HashMap<String, V, HashState>),
+}
+
+impl<V> Bindings<V> {
+    pub fn new() -> Bindings<V> {
+        Bindings::Small(SmallVec::new())
+    }
+
+    pub fn len(&self) -> usize {
+        match *self {
+            Bindings::Small(ref entries) => entries.len(),
+            Bindings::Large(ref map) => map.len(),
+        }
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&V> {
+        match *self {
+            Bindings::Small(ref entries) => entries.iter().find(|e| e.0 == key).map(|e| &e.1),
+            Bindings::Large(ref map) => map.get(key),
+        }
+    }
+
+    // Add a binding, replacing any existing one with the same name
+    pub fn insert(&mut self, key: String, value: V) {
+        let spilled = match *self {
+            Bindings::Small(ref mut entries) => {
+                if let Some(i) = entries.iter().position(|e| e.0 == key) {
+                    entries[i].1 = value;
+                    return
+                }
+                if entries.len() < INLINE {
+                    entries.push((key, value));
+                    return
+                }
+                let mut map = HashMap::with_hasher(HashState::new());
+                map.extend(entries.drain(..));
+                map.insert(key, value);
+                map
+            },
+            Bindings::Large(ref mut map) => {
+                map.insert(key, value);
+                return
+            }
+        };
+        *self = Bindings::Large(spilled);
+    }
+
+    // Hash the names again, after the random seed has changed, so the iteration order follows the new seed
+    pub fn rehash(&mut self) {
+        let rehashed = match *self {
+            Bindings::Small(_) => return,
+            Bindings::Large(ref mut map) => {
+                let mut rehashed = HashMap::with_hasher(HashState::new());
+                rehashed.extend(map.drain());
+                rehashed
+            }
+        };
+        *self = Bindings::Large(rehashed);
+    }
+
+    pub fn iter(&self) -> Iter<'_, V> {
+        match *self {
+            Bindings::Small(ref entries) => Iter::Small(entries.iter()),
+            Bindings::Large(ref map) => Iter::Large(map.iter()),
+        }
+    }
+}
+
+// Equal when they bind the same names to equal values, however each side happens to be stored
+impl<V: PartialEq> PartialEq for Bindings<V> {
+    fn eq(&self, other: &Bindings<V>) -> bool {
+        self.len() == other.len() && self.iter().all(|(k, v)| other.get(k) == Some(v))
+    }
+}
+
+pub enum Iter<'a, V: 'a> {
+    Small(slice::Iter<'a, (String, V)>),
+    Large(hash_map::Iter<'a, String, V>),
+}
+
+impl<'a, V> Iterator for Iter<'a, V> {
+    type Item = (&'a String, &'a V);
+
+    fn next(&mut self) -> Option<(&'a String, &'a V)> {
+        match *self {
+            Iter::Small(ref mut it) => it.next().map(|e| (&e.0, &e.1)),
+            Iter::Large(ref mut it) => it.next(),
+        }
+    }
+}
+
+#[test]
+fn test_bindings_spill() {
+    let mut b = Bindings::new();
+    for i in 0..INLINE {
+        b.insert(format!("x{}", i), i);
+    }
+    b.insert("x0".to_string(), 10);
+    match b {
+        Bindings::Small(_) => (),
+        Bindings::Large(_) => panic!("spilled too early"),
+    }
+    b.insert("y".to_string(), 20);
+    match b {
+        Bindings::Small(_) => panic!("didn't spill"),
+        Bindings::Large(_) => (),
+    }
+    assert_eq!(b.len(), INLINE + 1);
+    assert_eq!(b.get("x0"), Some(&10));
+    assert_eq!(b.get("y"), Some(&20));
+    assert!(!b.contains_key("z"));
+    assert_eq!(b.iter().count(), INLINE + 1);
+}
@@ -0,0 +1,136 @@
+// Standalone programs: a copy of the rusty_scheme executable with a script appended to it. On startup the CLI looks
+// for a script at the end of its own executable, and if there is one, runs it instead of reading its options, so a
+// tool written in Scheme can be shipped as a single file. The script is stored already read, with its includes and
+// cond-expands expanded, so the files it includes don't have to be shipped along with it and the reader settings it
+// was bundled with still apply. There's no byte-code yet, so it's stored as text and parsed again when it starts.
+//
+// A bundle is the executable, then the script, then the script's length as 8 little-endian bytes, then MAGIC.
+
+use expand;
+use lexer::ReaderConfig;
+use parser::{self, Node};
+use printer::{self, PrinterConfig, Style};
+
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+const MAGIC: &[u8] = b"\0rusty_scheme bundle";
+const TRAILER_LENGTH: usize = 8 + MAGIC.len();
+
+// Write a copy of runtime to out with the script at the given path embedded in it. If runtime is itself a bundle,
+// its script is replaced.
+pub fn bundle(runtime: &Path, script: &Path, reader: &ReaderConfig, out: &Path) -> Result<(), String> {
+    let source = match fs::read_to_string(script) {
+        Ok(source) => source,
+        Err(e) => return Err(format!("Couldn't read {}: {}", script.display(), e))
+    };
+    let nodes = match parser::parse_str_with(&source, reader) {
+        Ok(nodes) => nodes,
+        Err(e) => return Err(e.to_string())
+    };
+    let nodes = match expand::expand_includes(nodes, Some(script), reader) {
+        Ok(nodes) => nodes,
+        Err(e) => return Err(e.to_string())
+    };
+
+    let mut bytes = match fs::read(runtime) {
+        Ok(bytes) => bytes,
+        Err(e) => return Err(format!("Couldn't read {}: {}", runtime.display(), e))
+    };
+    let length = runtime_length(&bytes);
+    bytes.truncate(length);
+    let code = write_code(&nodes);
+    bytes.extend_from_slice(code.as_bytes());
+    bytes.extend_from_slice(&(code.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(MAGIC);
+
+    // the bundle is as executable as the runtime it was made from
+    let written = fs::write(out, &bytes)
+        .and_then(|_| fs::metadata(runtime))
+        .and_then(|metadata| fs::set_permissions(out, metadata.permissions()));
+    match written {
+        Ok(_) => Ok(()),
+        Err(e) => Err(format!("Couldn't write {}: {}", out.display(), e))
+    }
+}
+
+// The script embedded in the executable at the given path, or None if it isn't a bundle
+pub fn embedded_script(exe: &Path) -> Result<Option<String>, String> {
+    read_script(exe).map_err(|e| format!("Couldn't read the script bundled in {}: {}", exe.display(), e))
+}
+
+fn read_script(exe: &Path) -> Result<Option<String>, String> {
+    let mut file = try!(File::open(exe).map_err(|e| e.to_string()));
+    let size = try!(file.metadata().map_err(|e| e.to_string())).len();
+    if size < TRAILER_LENGTH as u64 {
+        return Ok(None)
+    }
+    let mut trailer = [0u8; TRAILER_LENGTH];
+    try!(file.seek(SeekFrom::End(-(TRAILER_LENGTH as i64)))
+             .and_then(|_| file.read_exact(&mut trailer))
+             .map_err(|e| e.to_string()));
+    let length = match script_length(&trailer) {
+        Some(length) if length <= size - TRAILER_LENGTH as u64 => length,
+        Some(_) => return Err("the bundle is truncated".to_string()),
+        None => return Ok(None)
+    };
+    let mut code = vec![0u8; length as usize];
+    try!(file.seek(SeekFrom::End(-(TRAILER_LENGTH as i64) - length as i64))
+             .and_then(|_| file.read_exact(&mut code))
+             .map_err(|e| e.to_string()));
+    String::from_utf8(code).map(Some).map_err(|e| e.to_string())
+}
+
+// The length of the script a bundle's trailer describes, or None if it isn't a trailer
+fn script_length(trailer: &[u8]) -> Option<u64> {
+    if trailer.len() != TRAILER_LENGTH || &trailer[8..] != MAGIC {
+        return None
+    }
+    let mut length = [0u8; 8];
+    length.copy_from_slice(&trailer[..8]);
+    Some(u64::from_le_bytes(length))
+}
+
+// How much of bytes is the executable, leaving out any script bundled with it
+fn runtime_length(bytes: &[u8]) -> usize {
+    if bytes.len() < TRAILER_LENGTH {
+        return bytes.len()
+    }
+    let body = bytes.len() - TRAILER_LENGTH;
+    match script_length(&bytes[body..]) {
+        Some(length) if length <= body as u64 => body - length as usize,
+        _ => bytes.len()
+    }
+}
+
+fn write_code(nodes: &[Node]) -> String {
+    let printed: Vec<String> = nodes.iter().map(|n| printer::print_with(n, Style::Write, PrinterConfig::default())).collect();
+    printed.join("\n")
+}
+
+#[test]
+fn test_bundle() {
+    use std::env;
+
+    let dir = env::temp_dir().join("rusty_scheme_bundle");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("runtime"), b"not really an executable").unwrap();
+    fs::write(dir.join("lib.scm"), "(define (square x) (* x x))").unwrap();
+    fs::write(dir.join("app.scm"), "(include \"lib.scm\")\n(display #i(square(3) + 1))").unwrap();
+    let reader = ReaderConfig { infix: true, ..ReaderConfig::default() };
+
+    assert_eq!(embedded_script(&dir.join("runtime")), Ok(None));
+    bundle(&dir.join("runtime"), &dir.join("app.scm"), &reader, &dir.join("app")).unwrap();
+    assert_eq!(embedded_script(&dir.join("app")).unwrap().unwrap(),
+               "(begin (define (square x) (* x x)))\n(display (+ (square 3) 1))");
+    assert!(fs::read(dir.join("app")).unwrap().starts_with(b"not really an executable(begin"));
+
+    // bundling from a bundle replaces its script
+    fs::write(dir.join("other.scm"), "(display 1)").unwrap();
+    bundle(&dir.join("app"), &dir.join("other.scm"), &reader, &dir.join("other")).unwrap();
+    assert_eq!(fs::read(dir.join("other")).unwrap().len(), "not really an executable(display 1)".len() + TRAILER_LENGTH);
+    assert_eq!(embedded_script(&dir.join("other")), Ok(Some("(display 1)".to_string())));
+
+    assert!(bundle(&dir.join("runtime"), &dir.join("missing.scm"), &reader, &dir.join("missing")).is_err());
+}
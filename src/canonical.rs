@@ -0,0 +1,162 @@
+// Canonical s-expressions (Rivest's csexp), shared by write-canonical and read-canonical in both interpreters, for
+// passing data between processes. Every atom is written as its length in bytes, a colon and then the bytes, with no
+// whitespace, so each value has exactly one encoding and can be read back without a full Scheme reader. Symbols are
+// plain atoms, which is how other Lisps read them; strings, integers, rationals, floats, booleans and keywords carry a
+// display hint saying what they are, e.g. [6:string]5:hello, so they come back as what they were written as.
+
+use parser::Node;
+use printer::{self, Style};
+
+pub fn write(node: &Node) -> Result<String, String> {
+    let mut out = String::new();
+    try!(write_into(node, &mut out));
+    Ok(out)
+}
+
+fn write_into(node: &Node, out: &mut String) -> Result<(), String> {
+    match *node {
+        Node::Identifier(ref s) => write_atom(None, s, out),
+        Node::String(ref s) => write_atom(Some("string"), s, out),
+        Node::Integer(i) => write_atom(Some("integer"), &i.to_string(), out),
+        Node::Rational(n, d) => write_atom(Some("rational"), &format!("{}/{}", n, d), out),
+        Node::Float(f) => write_atom(Some("float"), &printer::float(f), out),
+        Node::Boolean(b) => write_atom(Some("boolean"), if b { "#t" } else { "#f" }, out),
+        Node::Keyword(ref k) => write_atom(Some("keyword"), k, out),
+        Node::List(ref items) => {
+            out.push('(');
+            for item in items.iter() {
+                try!(write_into(item, out));
+            }
+            out.push(')');
+        },
+        _ => return Err(message!("Can't write {} as a canonical s-expression", printer::print(node, Style::Write)))
+    }
+    Ok(())
+}
+
+fn write_atom(hint: Option<&str>, text: &str, out: &mut String) {
+    if let Some(hint) = hint { out.push_str(&format!("[{}:{}]", hint.len(), hint)) }
+    out.push_str(&format!("{}:{}", text.len(), text));
+}
+
+// Read the next canonical s-expression from a source of characters, or None if it's at its end. Whitespace before
+// the expression is skipped, so expressions written one per line read back too.
+pub fn read<F: FnMut() -> Result<Option<char>, String>>(next: &mut F) -> Result<Option<Node>, String> {
+    loop {
+        match try!(next()) {
+            Some(c) if c.is_whitespace() => (),
+            Some(c) => return read_expression(c, next).map(Some),
+            None => return Ok(None)
+        }
+    }
+}
+
+fn read_expression<F: FnMut() -> Result<Option<char>, String>>(first: char, next: &mut F) -> Result<Node, String> {
+    match first {
+        '(' => {
+            let mut items = Vec::new();
+            loop {
+                match try!(next()) {
+                    Some(')') => return Ok(Node::List(items)),
+                    Some(c) => items.push(try!(read_expression(c, next))),
+                    None => return Err(message!("Expected ) to end a canonical s-expression, but found the end of input"))
+                }
+            }
+        },
+        '[' => {
+            let hint = match try!(next()) {
+                Some(c) => try!(read_simple(c, next)),
+                None => return Err(message!("Expected a display hint, but found the end of input"))
+            };
+            match try!(next()) {
+                Some(']') => (),
+                _ => return Err(message!("Expected ] after the display hint {}", hint))
+            }
+            let text = match try!(next()) {
+                Some(c) => try!(read_simple(c, next)),
+                None => return Err(message!("Expected an atom after the display hint {}, but found the end of input", hint))
+            };
+            match hint.as_ref() {
+                "string" => Ok(Node::String(text)),
+                "integer" => text.parse().map(Node::Integer).map_err(|_| message!("Not an integer: {}", text)),
+                "rational" => {
+                    let mut parts = text.splitn(2, '/').map(|part| part.parse::<i64>());
+                    match (parts.next(), parts.next()) {
+                        (Some(Ok(n)), Some(Ok(d))) if d > 0 => Ok(Node::rational(n as i128, d as i128).unwrap()),
+                        _ => Err(message!("Not a rational: {}", text))
+                    }
+                },
+                "float" => match text.as_ref() {
+                    "+inf.0" => Ok(Node::Float(f64::INFINITY)),
+                    "-inf.0" => Ok(Node::Float(-f64::INFINITY)),
+                    "+nan.0" => Ok(Node::Float(f64::NAN)),
+                    _ => text.parse().map(Node::Float).map_err(|_| message!("Not a float: {}", text))
+                },
+                "boolean" if text == "#t" => Ok(Node::Boolean(true)),
+                "boolean" if text == "#f" => Ok(Node::Boolean(false)),
+                "boolean" => Err(message!("Not a boolean: {}", text)),
+                "keyword" => Ok(Node::Keyword(text)),
+                _ => Err(message!("Unknown display hint in canonical s-expression: {}", hint))
+            }
+        },
+        _ => read_simple(first, next).map(Node::Identifier)
+    }
+}
+
+// A length in decimal, a colon, and that many bytes of text
+fn read_simple<F: FnMut() -> Result<Option<char>, String>>(first: char, next: &mut F) -> Result<String, String> {
+    let mut digits = String::new();
+    let mut c = Some(first);
+    while let Some(d) = c.filter(|d| d.is_ascii_digit()) {
+        digits.push(d);
+        c = try!(next());
+    }
+    if digits.is_empty() || (digits.len() > 1 && digits.starts_with('0')) || c != Some(':') {
+        return Err(message!("Expected the length of an atom in a canonical s-expression, but found {}",
+                            digits + &c.map_or("the end of input".to_string(), |c| c.to_string())))
+    }
+    let length: usize = match digits.parse() {
+        Ok(length) => length,
+        Err(_) => return Err(message!("Atom length is too large: {}", digits))
+    };
+    let mut text = String::new();
+    while text.len() < length {
+        match try!(next()) {
+            Some(c) => text.push(c),
+            None => return Err(message!("Expected {} bytes of atom, but found the end of input after {}", length, text.len()))
+        }
+    }
+    if text.len() > length {
+        return Err(message!("An atom of {} bytes ends in the middle of a character", length))
+    }
+    Ok(text)
+}
+
+#[test]
+fn test_canonical() {
+    use parser::parse_str;
+
+    let read_str = |s: &str| {
+        let mut chars = s.chars();
+        read(&mut || Ok(chars.next()))
+    };
+    let node = parse_str("(define x (\"héllo\" 42 #f #:key ()))").unwrap().remove(0);
+    let written = write(&node).unwrap();
+    assert_eq!(written, "(6:define1:x([6:string]6:héllo[7:integer]2:42[7:boolean]2:#f[7:keyword]3:key()))");
+    assert_eq!(read_str(&written), Ok(Some(node)));
+    assert_eq!(read_str("\n (3:a b0:)"), Ok(Some(Node::List(vec![Node::Identifier("a b".to_string()),
+                                                                  Node::Identifier("".to_string())]))));
+    assert_eq!(read_str("  "), Ok(None));
+    assert_eq!(write(&Node::EofObject), Err("Can't write #<eof> as a canonical s-expression".to_string()));
+    assert_eq!(read_str("(1:a"), Err("Expected ) to end a canonical s-expression, but found the end of input".to_string()));
+    assert_eq!(read_str("01:a"), Err("Expected the length of an atom in a canonical s-expression, but found 01:".to_string()));
+    assert_eq!(read_str("5:ab"), Err("Expected 5 bytes of atom, but found the end of input after 2".to_string()));
+    assert_eq!(read_str("1:é"), Err("An atom of 1 bytes ends in the middle of a character".to_string()));
+    assert_eq!(write(&Node::Float(-1.5)), Ok("[5:float]4:-1.5".to_string()));
+    assert_eq!(read_str("[5:float]6:+inf.0"), Ok(Some(Node::Float(f64::INFINITY))));
+    assert_eq!(read_str("[5:float]1:x"), Err("Not a float: x".to_string()));
+    assert_eq!(write(&Node::Rational(-1, 3)), Ok("[8:rational]4:-1/3".to_string()));
+    assert_eq!(read_str("[8:rational]3:2/4"), Ok(Some(Node::Rational(1, 2))));
+    assert_eq!(read_str("[8:rational]3:1/0"), Err("Not a rational: 1/0".to_string()));
+    assert_eq!(read_str("[6:double]3:1.5"), Err("Unknown display hint in canonical s-expression: double".to_string()));
+}
@@ -0,0 +1,44 @@
+// What scripts may reach outside the process for. Builtins that use the network are compiled in with the net
+// feature, but refuse to run until the embedder allows it with Builder::allow_network (the CLI's --allow-net), so
+// running untrusted code doesn't hand it the network just because the build includes it. Calling C functions, with the
+// ffi feature, is allowed the same way with Builder::allow_foreign (--allow-ffi). Each interpreter keeps what it was
+// granted in its root environment, so building a trusted interpreter doesn't lend its access to a sandboxed one.
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Capabilities {
+    pub network: bool,
+    pub foreign: bool,
+}
+
+const NETWORK_BUILTINS: &[&str] = &["http-get", "http-post", "serve-http"];
+const FOREIGN_BUILTINS: &[&str] = &["load-foreign-library", "foreign-procedure"];
+
+// Whether the named builtin needs a capability, so lookups of anything else can skip the check
+pub fn is_guarded(name: &str) -> bool {
+    NETWORK_BUILTINS.contains(&name) || FOREIGN_BUILTINS.contains(&name)
+}
+
+impl Capabilities {
+    // An error for the named builtin if it needs a capability that wasn't granted
+    pub fn check(&self, name: &str) -> Result<(), String> {
+        if NETWORK_BUILTINS.contains(&name) && !self.network {
+            Err(message!("{} needs network access, which isn't allowed (see --allow-net)", name))
+        } else if FOREIGN_BUILTINS.contains(&name) && !self.foreign {
+            Err(message!("{} needs foreign function access, which isn't allowed (see --allow-ffi)", name))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[test]
+fn test_capabilities() {
+    let sandboxed = Capabilities::default();
+    let trusted = Capabilities { network: true, foreign: false };
+    assert_eq!(sandboxed.check("http-get"), Err("http-get needs network access, which isn't allowed (see --allow-net)".to_string()));
+    assert_eq!(trusted.check("http-get"), Ok(()));
+    assert_eq!(trusted.check("foreign-procedure"),
+               Err("foreign-procedure needs foreign function access, which isn't allowed (see --allow-ffi)".to_string()));
+    assert_eq!(sandboxed.check("car"), Ok(()));
+    assert!(is_guarded("serve-http") && !is_guarded("car"));
+}
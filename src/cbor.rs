@@ -0,0 +1,257 @@
+// CBOR (RFC 8949), a compact binary encoding of data, for caching results or sending them to other programs. Rust
+// code can encode parsed data directly; value->cbor and cbor->value do the same for Scheme values, with the bytes
+// in a bytevector.
+//
+// Integers, floats, strings, booleans, lists and bytevectors are CBOR's integers, floats, text strings, booleans,
+// arrays and byte strings, with floats always encoded in double precision. Vectors are arrays too, so they decode as
+// lists. Rationals are arrays of their numerator and denominator with tag 30
+// (registered for rational numbers), and symbols are text strings with tag 39 (registered for identifiers).
+// Decoding also accepts what other encoders are likely to produce for the same data: null becomes the empty list, a
+// map becomes a list of (key value) lists, half and single precision floats are widened, and any other tag is
+// ignored. Indefinite-length items aren't supported.
+
+use parser::Node;
+use printer::{self, Style};
+
+const RATIONAL_TAG: u64 = 30;
+const IDENTIFIER_TAG: u64 = 39;
+
+// How deeply arrays and maps can nest while decoding, so that a few bytes of hostile input can't overflow the stack
+const MAX_DEPTH: usize = 512;
+
+pub fn encode(node: &Node) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    try!(encode_into(node, &mut out));
+    Ok(out)
+}
+
+fn encode_into(node: &Node, out: &mut Vec<u8>) -> Result<(), String> {
+    match *node {
+        Node::Integer(i) if i >= 0 => head(0, i as u64, out),
+        // -1 - i, without overflowing for i64::MIN
+        Node::Integer(i) => head(1, !i as u64, out),
+        Node::Float(f) => {
+            out.push(0xfb);
+            out.extend_from_slice(&f.to_bits().to_be_bytes());
+        },
+        Node::Rational(n, d) => {
+            head(6, RATIONAL_TAG, out);
+            head(4, 2, out);
+            try!(encode_into(&Node::Integer(n), out));
+            try!(encode_into(&Node::Integer(d), out));
+        },
+        Node::String(ref s) => {
+            head(3, s.len() as u64, out);
+            out.extend_from_slice(s.as_bytes());
+        },
+        Node::Identifier(ref s) => {
+            head(6, IDENTIFIER_TAG, out);
+            head(3, s.len() as u64, out);
+            out.extend_from_slice(s.as_bytes());
+        },
+        Node::Boolean(b) => out.push(if b { 0xf5 } else { 0xf4 }),
+        Node::List(ref items) | Node::Vector(ref items) => {
+            head(4, items.len() as u64, out);
+            for item in items.iter() {
+                try!(encode_into(item, out));
+            }
+        },
+        Node::Bytevector(ref bytes) => {
+            head(2, bytes.len() as u64, out);
+            out.extend_from_slice(bytes);
+        },
+        _ => return Err(message!("Can't encode {} as CBOR", printer::print(node, Style::Write)))
+    }
+    Ok(())
+}
+
+// The initial byte of an item, with its argument in the shortest form that holds it
+fn head(major: u8, argument: u64, out: &mut Vec<u8>) {
+    let major = major << 5;
+    if argument < 24 {
+        out.push(major | argument as u8);
+    } else if argument <= 0xff {
+        out.push(major | 24);
+        out.push(argument as u8);
+    } else if argument <= 0xffff {
+        out.push(major | 25);
+        out.extend_from_slice(&(argument as u16).to_be_bytes());
+    } else if argument <= 0xffff_ffff {
+        out.push(major | 26);
+        out.extend_from_slice(&(argument as u32).to_be_bytes());
+    } else {
+        out.push(major | 27);
+        out.extend_from_slice(&argument.to_be_bytes());
+    }
+}
+
+// A half precision float, which encoders use for values like 1.5 that it holds exactly
+fn half(bits: u16) -> f64 {
+    let exponent = (bits >> 10) & 0x1f;
+    let mantissa = (bits & 0x3ff) as f64;
+    let magnitude = match exponent {
+        0 => mantissa * 2f64.powi(-24),
+        31 if mantissa == 0.0 => f64::INFINITY,
+        31 => f64::NAN,
+        _ => (mantissa + 1024.0) * 2f64.powi(exponent as i32 - 25)
+    };
+    if bits & 0x8000 != 0 { -magnitude } else { magnitude }
+}
+
+// Decode a single item, which has to take up all of bytes
+pub fn decode(bytes: &[u8]) -> Result<Node, String> {
+    let mut decoder = Decoder { bytes, position: 0 };
+    let node = try!(decoder.item(0));
+    if decoder.position < bytes.len() {
+        return Err(message!("Found {} bytes after the end of the CBOR item", bytes.len() - decoder.position))
+    }
+    Ok(node)
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() - self.position < n {
+            return Err(message!("CBOR item ends early, at byte {}", self.bytes.len()))
+        }
+        let taken = &self.bytes[self.position..self.position + n];
+        self.position += n;
+        Ok(taken)
+    }
+
+    fn item(&mut self, depth: usize) -> Result<Node, String> {
+        if depth > MAX_DEPTH {
+            return Err(message!("CBOR nests more than {} deep", MAX_DEPTH))
+        }
+        let initial = try!(self.take(1))[0];
+        let (major, info) = (initial >> 5, initial & 0x1f);
+        if major == 7 {
+            return match info {
+                20 => Ok(Node::Boolean(false)),
+                21 => Ok(Node::Boolean(true)),
+                22 => Ok(Node::List(Vec::new())),
+                25 => {
+                    let bytes = try!(self.take(2));
+                    Ok(Node::Float(half((bytes[0] as u16) << 8 | bytes[1] as u16)))
+                },
+                26 => {
+                    let bytes = try!(self.take(4));
+                    Ok(Node::Float(f32::from_bits(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])) as f64))
+                },
+                27 => {
+                    let bytes = try!(self.take(8));
+                    let mut bits = [0; 8];
+                    bits.copy_from_slice(bytes);
+                    Ok(Node::Float(f64::from_bits(u64::from_be_bytes(bits))))
+                },
+                _ => Err(message!("Unsupported CBOR simple value: {}", info))
+            }
+        }
+        let argument = try!(self.argument(info));
+        match major {
+            0 if argument <= i64::MAX as u64 => Ok(Node::Integer(argument as i64)),
+            1 if argument <= i64::MAX as u64 => Ok(Node::Integer(!(argument as i64))),
+            0 | 1 => Err(message!("CBOR integer is too large")),
+            2 => self.take_length(argument).map(|bytes| Node::Bytevector(bytes.to_vec())),
+            3 => self.text(argument).map(Node::String),
+            4 => {
+                let mut items = Vec::new();
+                for _ in 0..argument {
+                    items.push(try!(self.item(depth + 1)));
+                }
+                Ok(Node::List(items))
+            },
+            5 => {
+                let mut pairs = Vec::new();
+                for _ in 0..argument {
+                    let key = try!(self.item(depth + 1));
+                    let value = try!(self.item(depth + 1));
+                    pairs.push(Node::List(vec![key, value]));
+                }
+                Ok(Node::List(pairs))
+            },
+            _ if argument == RATIONAL_TAG => match try!(self.item(depth + 1)) {
+                Node::List(ref parts) if parts.len() == 2 => match (&parts[0], &parts[1]) {
+                    (&Node::Integer(n), &Node::Integer(d)) if d > 0 => Ok(Node::rational(n as i128, d as i128).unwrap()),
+                    _ => Err(message!("Expected an integer and a positive integer in a rational tag, but found {}",
+                                      printer::print(&Node::List(parts.clone()), Style::Write)))
+                },
+                other => Err(message!("Expected an array of two integers in a rational tag, but found {}",
+                                      printer::print(&other, Style::Write)))
+            },
+            _ if argument == IDENTIFIER_TAG => match try!(self.item(depth + 1)) {
+                Node::String(s) => Ok(Node::Identifier(s)),
+                other => Err(message!("Expected a text string in an identifier tag, but found {}",
+                                      printer::print(&other, Style::Write)))
+            },
+            _ => self.item(depth + 1)
+        }
+    }
+
+    fn argument(&mut self, info: u8) -> Result<u64, String> {
+        let size = match info {
+            0..=23 => return Ok(info as u64),
+            24 => 1,
+            25 => 2,
+            26 => 4,
+            27 => 8,
+            31 => return Err(message!("Indefinite-length CBOR items aren't supported")),
+            _ => return Err(message!("Malformed CBOR: reserved additional information {}", info))
+        };
+        Ok(try!(self.take(size)).iter().fold(0, |n, &b| n << 8 | b as u64))
+    }
+
+    fn take_length(&mut self, length: u64) -> Result<&'a [u8], String> {
+        if length > (self.bytes.len() - self.position) as u64 {
+            return Err(message!("CBOR item ends early, at byte {}", self.bytes.len()))
+        }
+        self.take(length as usize)
+    }
+
+    fn text(&mut self, length: u64) -> Result<String, String> {
+        let bytes = try!(self.take_length(length));
+        String::from_utf8(bytes.to_vec()).map_err(|_| message!("CBOR text string isn't valid UTF-8"))
+    }
+}
+
+#[test]
+fn test_cbor() {
+    use parser::parse_str;
+
+    let node = parse_str("(define x (\"héllo\" 0 -1 23 24 -500 70000 2.5 #t #f ()))").unwrap().remove(0);
+    let encoded = encode(&node).unwrap();
+    assert_eq!(encoded[..10].to_vec(), vec![0x83, 0xd8, 39, 0x66, b'd', b'e', b'f', b'i', b'n', b'e']);
+    assert_eq!(decode(&encoded), Ok(node));
+    assert_eq!(encode(&Node::Integer(i64::MIN)).unwrap(), vec![0x3b, 0x7f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+    assert_eq!(decode(&[0x3b, 0x7f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]), Ok(Node::Integer(i64::MIN)));
+    assert_eq!(encode(&Node::Integer(500)).unwrap(), vec![0x19, 0x01, 0xf4]);
+    assert_eq!(encode(&Node::Float(1.5)).unwrap(), vec![0xfb, 0x3f, 0xf8, 0, 0, 0, 0, 0, 0]);
+    assert_eq!(decode(&[0xfb, 0x3f, 0xf8, 0, 0, 0, 0, 0, 0]), Ok(Node::Float(1.5)));
+    assert_eq!(encode(&Node::Rational(-1, 3)).unwrap(), vec![0xd8, 30, 0x82, 0x20, 0x03]);
+    assert_eq!(decode(&[0xd8, 30, 0x82, 0x02, 0x04]), Ok(Node::Rational(1, 2)));
+    assert_eq!(encode(&Node::Bytevector(vec![1, 255])).unwrap(), vec![0x42, 1, 255]);
+    assert_eq!(decode(&[0x42, 1, 255]), Ok(Node::Bytevector(vec![1, 255])));
+    assert_eq!(encode(&Node::Vector(vec![Node::Integer(1)])).unwrap(), vec![0x81, 0x01]);
+    assert_eq!(decode(&[0xd8, 30, 0x82, 0x01, 0x00]), Err("Expected an integer and a positive integer in a rational tag, but found (1 0)".to_string()));
+
+    // what other encoders make of the same data
+    assert_eq!(decode(&[0xa1, 0x61, b'a', 0x42, 1, 2]), parse_str("((\"a\" #u8(1 2)))").map(|mut n| n.remove(0)).map_err(|e| e.to_string()));
+    assert_eq!(decode(&[0xc1, 0x1a, 0x5e, 0x0b, 0xe1, 0x00]), Ok(Node::Integer(1577836800)));
+    assert_eq!(decode(&[0xf6]), Ok(Node::List(vec![])));
+    assert_eq!(decode(&[0xf9, 0x3e, 0x00]), Ok(Node::Float(1.5)));
+    assert_eq!(decode(&[0xf9, 0x80, 0x01]), Ok(Node::Float(-2f64.powi(-24))));
+    assert_eq!(decode(&[0xf9, 0x7c, 0x00]), Ok(Node::Float(f64::INFINITY)));
+    assert_eq!(decode(&[0xfa, 0x3f, 0xc0, 0, 0]), Ok(Node::Float(1.5)));
+
+    assert_eq!(encode(&Node::Keyword("k".to_string())), Err("Can't encode #:k as CBOR".to_string()));
+    assert_eq!(decode(&[0x82, 0x01]), Err("CBOR item ends early, at byte 2".to_string()));
+    assert_eq!(decode(&[0x01, 0x02]), Err("Found 1 bytes after the end of the CBOR item".to_string()));
+    assert_eq!(decode(&[0x1b, 0x80, 0, 0, 0, 0, 0, 0, 0]), Err("CBOR integer is too large".to_string()));
+    assert_eq!(decode(&[0x9f, 0xff]), Err("Indefinite-length CBOR items aren't supported".to_string()));
+    assert_eq!(decode(&[0x7b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]), Err("CBOR item ends early, at byte 9".to_string()));
+    assert!(decode(&vec![0x81; 1000]).unwrap_err().contains("nests more than 512 deep"));
+}
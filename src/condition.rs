@@ -0,0 +1,89 @@
+// The kinds of condition an error can raise, shared by both interpreters so a guard clause can tell them apart with
+// the predicates below. Every kind is also an error, and arity and type errors are also assertion violations: bugs
+// in the program, as opposed to problems with its surroundings (a missing file, unreadable input) that it might
+// want to recover from.
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Kind {
+    Error,
+    // a file couldn't be opened, read or written
+    FileError,
+    // source text couldn't be tokenized or parsed
+    ReadError,
+    // raised by (assertion-violation obj), and the parent of the two kinds below
+    AssertionViolation,
+    // a procedure was called with the wrong number of arguments
+    ArityError,
+    // an argument was of the wrong type (or out of range)
+    TypeError,
+}
+
+pub const KINDS: &[Kind] = &[Kind::Error, Kind::FileError, Kind::ReadError, Kind::AssertionViolation,
+                                     Kind::ArityError, Kind::TypeError];
+
+impl Kind {
+    pub fn name(self) -> &'static str {
+        match self {
+            Kind::Error => "error",
+            Kind::FileError => "file-error",
+            Kind::ReadError => "read-error",
+            Kind::AssertionViolation => "assertion-violation",
+            Kind::ArityError => "arity-error",
+            Kind::TypeError => "type-error",
+        }
+    }
+
+    // The Scheme predicate recognizing conditions of this kind
+    pub fn predicate(self) -> &'static str {
+        match self {
+            Kind::Error => "error?",
+            Kind::FileError => "file-error?",
+            Kind::ReadError => "read-error?",
+            Kind::AssertionViolation => "assertion-violation?",
+            Kind::ArityError => "arity-error?",
+            Kind::TypeError => "type-error?",
+        }
+    }
+
+    pub fn from_predicate(name: &str) -> Option<Kind> {
+        KINDS.iter().cloned().find(|k| k.predicate() == name)
+    }
+
+    pub fn parent(self) -> Option<Kind> {
+        match self {
+            Kind::Error => None,
+            Kind::ArityError | Kind::TypeError => Some(Kind::AssertionViolation),
+            _ => Some(Kind::Error),
+        }
+    }
+
+    pub fn is_a(self, other: Kind) -> bool {
+        let mut kind = Some(self);
+        while let Some(k) = kind {
+            if k == other {
+                return true
+            }
+            kind = k.parent();
+        }
+        false
+    }
+
+    // Whether it's a bug in the program rather than something it could recover from
+    pub fn is_violation(self) -> bool {
+        self.is_a(Kind::AssertionViolation)
+    }
+}
+
+#[test]
+fn test_condition_kinds() {
+    assert_eq!(Kind::from_predicate("type-error?"), Some(Kind::TypeError));
+    assert_eq!(Kind::from_predicate("type-error"), None);
+    assert!(Kind::FileError.is_a(Kind::Error));
+    assert!(Kind::FileError.is_a(Kind::FileError));
+    assert!(!Kind::FileError.is_a(Kind::ReadError));
+    assert!(!Kind::Error.is_a(Kind::TypeError));
+    assert!(Kind::TypeError.is_a(Kind::Error));
+    assert!(Kind::TypeError.is_violation());
+    assert!(!Kind::FileError.is_violation());
+    assert!(!Kind::Error.is_violation());
+}
@@ -0,0 +1,153 @@
+// A battery of checks on the core language, for plugin authors to run against an interpreter with their builtins
+// loaded. Since load_plugin replaces any global with the same name, a plugin that defines list, +, hash or
+// display->string differently from the builtin it shadows breaks code that relies on it; this finds that before
+// a script does:
+//
+//     let interpreter = interpreter::new("cps");
+//     interpreter.load_plugin(&Strings);
+//     let report = conformance::run(&interpreter);
+//     assert!(report.passed(), "{}", report);
+//
+// Each check evaluates an expression and compares what it prints with what the builtins print, so they assume the
+// default printer options and reader, and that strict booleans are off. Every check is run twice, since a result
+// that changes from one run to the next (e.g. one that depends on a HashMap's order) is a failure too. Checks don't
+// define any globals, so running them leaves the interpreter as it was.
+
+use interpreter::Interpreter;
+
+use std::fmt;
+
+pub struct Check {
+    pub name: &'static str,
+    pub code: &'static str,
+    // What execute returns: the printed result, or the error message
+    pub expected: &'static str,
+}
+
+pub const CHECKS: &[Check] = &[
+    // equality
+    Check { name: "numeric equality", code: "(list (= 1 1) (= 1 1.0) (= 1/2 0.5) (= 1 2) (< 1/3 0.5))",
+            expected: "(#t #t #t #f #t)" },
+    Check { name: "structural hashing", code: "(= (hash (list 1 \"a\" #\\b 'c 1/2)) (hash (list 1 \"a\" #\\b 'c 1/2)))",
+            expected: "#t" },
+    Check { name: "hashing tells lists apart", code: "(= (hash '(1 2)) (hash '(2 1)))", expected: "#f" },
+    Check { name: "identity hashing", code: "(let ((b (box 1))) (list (= (hash-by-identity b) (hash-by-identity b)) (= (hash-by-identity b) (hash-by-identity (box 1)))))",
+            expected: "(#t #f)" },
+    Check { name: "set membership", code: "(let ((s (make-set '(1 \"a\") 2))) (list (set-contains? s (list 1 \"a\")) (set-contains? s '(1 \"b\")) (set-contains? s 2.5)))",
+            expected: "(#t #f #f)" },
+    Check { name: "case-insensitive strings", code: "(list (string-ci=? \"Straße\" \"STRASSE\") (string-ci<? \"a\" \"B\"))",
+            expected: "(#t #t)" },
+    // printing
+    Check { name: "printing integers", code: "(list 0 -7 #xff 9223372036854775807)", expected: "(0 -7 255 9223372036854775807)" },
+    Check { name: "printing floats", code: "(list 1.5 -0.25 0.1 +inf.0)", expected: "(1.5 -0.25 0.1 +inf.0)" },
+    Check { name: "printing rationals", code: "(list 1/3 (+ 1/3 1/6) 4/2 -2/4)", expected: "(1/3 1/2 2 -1/2)" },
+    Check { name: "printing characters", code: "(list #\\a #\\space #\\x3bb)", expected: "(#\\a #\\space #\\λ)" },
+    Check { name: "printing other atoms", code: "(list 'abc \"héllo\" #t #f '())", expected: "(abc \"héllo\" #t #f ())" },
+    Check { name: "printing nested lists", code: "'(1 (2 (3 ())) \"x\")", expected: "(1 (2 (3 ())) \"x\")" },
+    Check { name: "display", code: "(display->string \"a\" #\\b '(\"c\" #\\d) 1/2)", expected: "\"ab(c d)1/2\"" },
+    // evaluation
+    Check { name: "closures", code: "(let ((make (lambda (n) (lambda (x) (+ x n))))) ((make 3) 4))", expected: "7" },
+    Check { name: "let scope", code: "(let ((x 1)) (list (let ((x 2)) x) x))", expected: "(2 1)" },
+    Check { name: "letrec", code: "(letrec ((ev? (lambda (n) (if (= n 0) #t (od? (- n 1))))) (od? (lambda (n) (if (= n 0) #f (ev? (- n 1)))))) (list (ev? 10) (od? 7)))",
+            expected: "(#t #t)" },
+    Check { name: "set!", code: "(let ((x 1)) (set! x (+ x 1)) x)", expected: "2" },
+    Check { name: "truthiness", code: "(list (if '() 'yes 'no) (if 0 'yes 'no) (if #f 'yes 'no))", expected: "(yes yes no)" },
+    Check { name: "and and or", code: "(list (and 1 2) (and) (or #f 3) (or))", expected: "(2 #t 3 #f)" },
+    Check { name: "quasiquote", code: "(let ((x 1)) `(a ,x ,(+ x 1)))", expected: "(a 1 2)" },
+    Check { name: "apply and eval", code: "(list (apply + '(1 2 3)) (eval '(* 2 3)))", expected: "(6 6)" },
+    Check { name: "multiple values", code: "(call-with-values (lambda () (values 1 2)) list)", expected: "(1 2)" },
+    Check { name: "guard", code: "(list (guard (e ((error? e) 'caught)) (error \"boom\")) (guard (e (#f 'no) (#t 'yes)) (car '())))",
+            expected: "(caught yes)" },
+    // builtins
+    Check { name: "lists", code: "(list (car '(1 2)) (cdr '(1 2)) (cons 0 '(1)) (append '(1) '(2 3)) (reverse '(1 2 3)) (null? '()))",
+            expected: "(1 (2) (0 1) (1 2 3) (3 2 1) #t)" },
+    Check { name: "arithmetic", code: "(list (+ 1 2) (- 5 7) (* 2 1/4) (/ 6 3) (/ 1.0 4))", expected: "(3 -2 1/2 2 0.25)" },
+    Check { name: "strings", code: "(list (string-length \"héllo\") (substring \"héllo\" 1 3) (string->list \"ab\"))",
+            expected: "(5 \"él\" (#\\a #\\b))" },
+    Check { name: "characters", code: "(list (char? #\\a) (char->integer #\\A) (integer->char 955))", expected: "(#t 65 #\\λ)" },
+    Check { name: "boxes", code: "(let ((b (box 1))) (set-box! b 2) (list (box? b) (unbox b)))", expected: "(#t 2)" },
+    Check { name: "errors", code: "(car '())", expected: "RuntimeError: Can't run car on an empty list" },
+];
+
+pub struct Failure {
+    pub check: &'static Check,
+    // What execute returned, the first time or, if that was right, the second
+    pub actual: String,
+}
+
+pub struct Report {
+    failures: Vec<Failure>,
+}
+
+impl Report {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    pub fn failures(&self) -> &[Failure] {
+        &self.failures
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{} of {} conformance checks failed", self.failures.len(), CHECKS.len()));
+        for failure in self.failures.iter() {
+            try!(write!(f, "\n{}: {}\n    expected: {}\n    got: {}", failure.check.name, failure.check.code,
+                        failure.check.expected, failure.actual));
+        }
+        Ok(())
+    }
+}
+
+// Run every check in the interpreter
+pub fn run(interpreter: &Interpreter) -> Report {
+    let mut failures = Vec::new();
+    for check in CHECKS.iter() {
+        for _ in 0..2 {
+            let actual = match interpreter.execute(check.code) {
+                Ok(s) => s,
+                Err(e) => e
+            };
+            if actual != check.expected {
+                failures.push(Failure { check, actual });
+                break
+            }
+        }
+    }
+    Report { failures }
+}
+
+#[test]
+fn test_conformance() {
+    use interpreter;
+    use parser::Node;
+    use plugin::{Environment, Plugin};
+
+    for t in ["cps", "ast_walk"].iter() {
+        let i = interpreter::new(t);
+        let report = run(&i);
+        assert!(report.passed(), "{}: {}", t, report);
+    }
+
+    // A plugin whose reverse leaves lists alone
+    struct Careless;
+
+    impl Plugin for Careless {
+        fn register(&self, env: &mut Environment) {
+            env.define_builtin("reverse", |args| match args {
+                [l] => Ok(l.clone()),
+                _ => Err("reverse takes a list".to_string())
+            });
+            env.define("answer", Node::Integer(42));
+        }
+    }
+
+    let i = interpreter::new("cps");
+    i.load_plugin(&Careless);
+    let report = run(&i);
+    let lists = CHECKS.iter().find(|c| c.name == "lists").unwrap();
+    assert_eq!(report.failures().len(), 1);
+    assert_eq!(report.to_string(), format!("1 of {} conformance checks failed\nlists: {}\n    expected: {}\n    got: {}", CHECKS.len(),
+                                           lists.code, lists.expected, "(1 (2) (0 1) (1 2 3) (1 2 3) #t)"));
+}
@@ -1,11 +1,74 @@
+use aliases;
+use bindings::Bindings;
+use cbor;
+use docs;
+use condition::{Kind, KINDS};
+use expand;
+use features;
+use lexer::{self, ReaderConfig};
+use log::{self, Level};
 use parser::*;
+use plugin;
+use printer::{self, Printable, PrinterConfig, Shape, Style};
+use random::{self, HashState, HashTag};
+use ranges;
+use search_path;
+use shared::unwrap_or_clone;
+use stats;
+use strict;
+use text;
+use warning;
+use watch;
+
+#[cfg(feature = "io")]
+use canonical;
+#[cfg(feature = "io")]
+use csv;
+#[cfg(feature = "io")]
+use files;
+#[cfg(feature = "io")]
+use input;
+#[cfg(feature = "io")]
+use paths;
+#[cfg(feature = "io")]
+use printer::Setting;
+#[cfg(feature = "io")]
+use std::io;
+
+use numbers::Number;
+#[cfg(feature = "math")]
+use numbers;
+use std::cmp::Ordering;
+
+use capabilities::{self, Capabilities};
+#[cfg(feature = "net")]
+use http;
+
+#[cfg(feature = "parallel")]
+use parallel;
+
+#[cfg(all(unix, feature = "process"))]
+use signals;
+
+#[cfg(feature = "sqlite")]
+use sqlite;
+
+#[cfg(feature = "ffi")]
+use ffi;
 
 use std::fmt;
-use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::rc::Rc;
 use std::cell::RefCell;
+use std::char;
 use std::iter;
+use std::mem;
 use std::vec;
+use std::slice;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
 
 pub fn new() -> Result<Interpreter, RuntimeError> {
     Interpreter::new()
@@ -26,85 +89,383 @@ impl Interpreter {
         let exprs = List::from_nodes(nodes);
         process(exprs, self.root.clone())
     }
+
+    // Like run, but in the environment an earlier error happened in
+    pub fn run_in(&self, nodes: &[Node], frame: &Frame) -> Result<Value, RuntimeError> {
+        let exprs = List::from_nodes(nodes);
+        process(exprs, frame.env.clone())
+    }
+
+    pub fn autoload(&self, path: &str, name: &str) {
+        self.root.borrow_mut().autoloads.insert(name.to_string(), path.to_string());
+    }
+
+    pub fn grant(&self, capabilities: Capabilities) {
+        self.root.borrow_mut().capabilities = capabilities;
+    }
+
+    // Bind a global variable, replacing any existing binding
+    pub fn set_global(&self, name: &str, value: Value) {
+        self.root.borrow_mut().values.insert(name.to_string(), value);
+    }
+
+    // Define a plugin's builtins and constants as globals
+    pub fn load_plugin(&self, env: &plugin::Environment) {
+        for builtin in env.builtins().iter() {
+            self.set_global(builtin.name(), Value::Procedure(Function::Plugin(builtin.clone())));
+        }
+        for (name, value) in env.constants().iter() {
+            self.set_global(name, Value::from_node(value));
+        }
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<Value> {
+        self.root.borrow().get(&name.to_string())
+    }
+
+    pub fn is_macro(&self, name: &str) -> bool {
+        matches!(self.get_global(name), Some(Value::Macro(_, _)))
+    }
+
+    // The global macros, with their parameters and templates, for stepping through their expansion
+    pub fn macros(&self) -> Vec<(String, Vec<String>, Node)> {
+        let mut macros = Vec::new();
+        for (name, value) in self.root.borrow().values.iter() {
+            if let Value::Macro(ref arg_names, ref body) = *value { if let Ok(template) = body.to_data() { macros.push((name.clone(), (**arg_names).clone(), template)) } }
+        }
+        macros
+    }
+
+    // The result of a call to a builtin, for fold, if its name is still bound to the builtin and it returns plain data
+    pub fn fold_call(&self, call: &Node) -> Option<Node> {
+        let name = match *call {
+            Node::List(ref items) => match items.first() {
+                Some(Node::Identifier(name)) => name,
+                _ => return None
+            },
+            _ => return None
+        };
+        match self.get_global(name) {
+            Some(Value::Procedure(Function::Native(n))) if n == name => (),
+            _ => return None
+        }
+        self.run(slice::from_ref(call)).ok().and_then(|v| v.to_data().ok())
+    }
+
+    // Make the global environment iterate in the order given by the current random seed
+    pub fn rehash_globals(&self) {
+        self.root.borrow_mut().values.rehash();
+    }
 }
 
 macro_rules! runtime_error {
     ($($arg:tt)*) => (
-        return Err(RuntimeError { message: format!($($arg)*)})
+        return Err(RuntimeError { kind: Kind::Error, message: message!($($arg)*), frame: None})
+    )
+}
+
+// Errors of a more specific kind (see condition.rs), which guard clauses can test for
+macro_rules! raise {
+    ($kind:expr, $($arg:tt)*) => (
+        return Err(RuntimeError { kind: $kind, message: message!($($arg)*), frame: None})
     )
 }
+macro_rules! file_error { ($($arg:tt)*) => (raise!(Kind::FileError, $($arg)*)) }
+macro_rules! read_error { ($($arg:tt)*) => (raise!(Kind::ReadError, $($arg)*)) }
+macro_rules! arity_error { ($($arg:tt)*) => (raise!(Kind::ArityError, $($arg)*)) }
+macro_rules! type_error { ($($arg:tt)*) => (raise!(Kind::TypeError, $($arg)*)) }
 
 macro_rules! shift_or_error {
     ($list:expr, $($arg:tt)*) => (
         try!(
             match $list.shift() {
                 Some((car, cdr)) => Ok((car, cdr)),
-                None => Err(RuntimeError { message: format!($($arg)*)})
+                None => Err(RuntimeError { kind: Kind::Error, message: message!($($arg)*), frame: None})
             }
         )
     )
 }
 
-#[derive(PartialEq, Clone)]
-enum Value {
+// Public so the interpreter module can pass values between runs; what they hold stays private to this module
+#[derive(Clone)]
+#[allow(private_interfaces)]
+pub enum Value {
     Symbol(String),
     Integer(i64),
+    Rational(i64, i64),
+    Float(f64),
     Boolean(bool),
-    String(String),
+    Character(char),
+    String(Rc<str>),
     List(List),
+    // what (a b . c) and cons onto something other than a list make: a list of at least one item, and a tail that
+    // isn't a list
+    DottedList(List, Rc<Value>),
+    // what #(...) and vector make, with its elements indexed in constant time, and changed in place by vector-fill!
+    Vector(Rc<RefCell<Vec<Value>>>),
+    // what #u8(...) makes: bytes, for binary data
+    Bytevector(Rc<Vec<u8>>),
     Procedure(Function),
     SpecialForm(SpecialForm),
-    Macro(Vec<String>, Box<Value>),
+    Macro(Rc<Vec<String>>, Rc<Value>),
     Continuation(Box<Continuation>),
+    EofObject,
+    Keyword(String),
+    Box(Rc<RefCell<Value>>),
+    // what a guard clause gets when an error is raised: its kind and message
+    Condition(Kind, Rc<str>),
+    // the result of (values ...) with other than one value, which call-with-values passes on as arguments
+    Values(Rc<Vec<Value>>),
+    // what make-queue makes: elements go in at the back with enqueue! and come out at the front with dequeue!
+    Queue(Rc<RefCell<VecDeque<Value>>>),
+    // what make-set makes: distinct values, compared by structure as hash does
+    Set(Rc<RefCell<HashSet<Value, HashState>>>),
+    // what make-heap makes: a priority queue whose least element, by its ordering procedure, comes out first
+    Heap(Rc<RefCell<Heap>>),
+    // what sqlite-open opens, closed when the last reference to it goes away
+    #[cfg(feature = "sqlite")]
+    Database(Rc<sqlite::Database>),
+    // what load-foreign-library loads, unloaded when neither it nor a procedure from it is referred to any more
+    #[cfg(feature = "ffi")]
+    Library(Rc<ffi::Library>),
+}
+
+#[derive(PartialEq, Debug)]
+pub struct Heap {
+    // called as (less a b), and true when a should come out before b
+    less: Value,
+    // a binary heap: each element's children are at 2i + 1 and 2i + 2, and none comes out before its parent
+    items: Vec<Value>,
+}
+
+impl Heap {
+    // Swap two elements, unless the ordering procedure removed one of them while it was deciding
+    fn swap(&mut self, a: usize, b: usize) -> bool {
+        if a < self.items.len() && b < self.items.len() {
+            self.items.swap(a, b);
+            true
+        } else {
+            false
+        }
+    }
 }
 
+// The as_ conversions take the value by move, since they're used on arguments already unpacked from their list
+#[allow(clippy::wrong_self_convention)]
 impl Value {
     fn from_vec(vec: Vec<Value>) -> Value {
         List::from_vec(vec).to_value()
     }
 
+    fn from_number(n: Number) -> Value {
+        match n {
+            Number::Integer(i) => Value::Integer(i),
+            Number::Rational(n, d) => Value::Rational(n, d),
+            Number::Float(f) => Value::Float(f),
+        }
+    }
+
+    // The value as a node, if it's plain data, or else the part of it that isn't
+    fn to_data(&self) -> Result<Node, Value> {
+        match *self {
+            Value::Symbol(ref v) => Ok(Node::Identifier(v.clone())),
+            Value::Integer(v) => Ok(Node::Integer(v)),
+            Value::Rational(n, d) => Ok(Node::Rational(n, d)),
+            Value::Float(v) => Ok(Node::Float(v)),
+            Value::Boolean(v) => Ok(Node::Boolean(v)),
+            Value::Character(c) => Ok(Node::Character(c)),
+            Value::String(ref v) => Ok(Node::String(v.to_string())),
+            Value::List(ref list) => {
+                let res: Result<Vec<Node>, Value> = list.iter().map(Value::to_data).collect();
+                Ok(Node::List(try!(res)))
+            },
+            Value::DottedList(ref list, ref tail) => {
+                let res: Result<Vec<Node>, Value> = list.iter().map(Value::to_data).collect();
+                Ok(Node::DottedList(try!(res), Box::new(try!(tail.to_data()))))
+            },
+            Value::Vector(ref items) => {
+                let res: Result<Vec<Node>, Value> = items.borrow().iter().map(Value::to_data).collect();
+                Ok(Node::Vector(try!(res)))
+            },
+            Value::Bytevector(ref bytes) => Ok(Node::Bytevector(bytes.to_vec())),
+            Value::EofObject => Ok(Node::EofObject),
+            Value::Keyword(ref v) => Ok(Node::Keyword(v.clone())),
+            _ => Err(self.clone())
+        }
+    }
+
+    // The value as a node that can be sent to another thread, if it's plain data
+    #[cfg(feature = "parallel")]
+    fn to_node(&self) -> Result<Node, RuntimeError> {
+        match self.to_data() {
+            Ok(node) => Ok(node),
+            Err(v) => type_error!("Can't send {:?} to another thread", v)
+        }
+    }
+
     fn from_node(node: &Node) -> Value {
+        Value::from_labelled_node(node, &mut HashMap::new())
+    }
+
+    // Labelled data are recorded so references to them share them, and a labelled box or vector is made before its
+    // contents, so that references to it from inside them can share it too
+    fn from_labelled_node(node: &Node, labels: &mut HashMap<u32, Value>) -> Value {
         match *node {
             Node::Identifier(ref val) => Value::Symbol(val.clone()),
             Node::Integer(val) => Value::Integer(val),
+            Node::Rational(n, d) => Value::Rational(n, d),
+            Node::Float(val) => Value::Float(val),
             Node::Boolean(val) => Value::Boolean(val),
-            Node::String(ref val) => Value::String(val.clone()),
-            Node::List(ref nodes) => Value::List(List::from_nodes(&nodes))
+            Node::Character(val) => Value::Character(val),
+            Node::String(ref val) => Value::String(Rc::from(val.as_str())),
+            Node::List(ref nodes) => Value::from_vec(nodes.iter().map(|n| Value::from_labelled_node(n, labels)).collect()),
+            Node::DottedList(ref nodes, ref tail) => {
+                let items = List::from_vec(nodes.iter().map(|n| Value::from_labelled_node(n, labels)).collect());
+                Value::DottedList(items, Rc::new(Value::from_labelled_node(tail, labels)))
+            },
+            Node::Vector(ref nodes) => {
+                Value::Vector(Rc::new(RefCell::new(nodes.iter().map(|n| Value::from_labelled_node(n, labels)).collect())))
+            },
+            Node::Bytevector(ref bytes) => Value::Bytevector(Rc::new(bytes.clone())),
+            Node::EofObject => Value::EofObject,
+            Node::Keyword(ref val) => Value::Keyword(val.clone()),
+            Node::Box(label, ref inner) => {
+                let cell = Rc::new(RefCell::new(List::Null.to_value()));
+                if let Some(n) = label {
+                    labels.insert(n, Value::Box(cell.clone()));
+                }
+                let contents = Value::from_labelled_node(inner, labels);
+                *cell.borrow_mut() = contents;
+                Value::Box(cell)
+            },
+            Node::Labelled(n, ref inner) => match **inner {
+                Node::Vector(ref nodes) => {
+                    let cell = Rc::new(RefCell::new(Vec::new()));
+                    labels.insert(n, Value::Vector(cell.clone()));
+                    let items: Vec<Value> = nodes.iter().map(|n| Value::from_labelled_node(n, labels)).collect();
+                    cell.borrow_mut().extend(items);
+                    Value::Vector(cell)
+                },
+                _ => {
+                    let value = Value::from_labelled_node(inner, labels);
+                    labels.insert(n, value.clone());
+                    value
+                }
+            },
+            // the parser only makes references to data it has already started reading, but a hand-built node could
+            // refer to any label
+            Node::Reference(n) => {
+                match labels.get(&n) {
+                    Some(value) => value.clone(),
+                    None => List::Null.to_value()
+                }
+            },
+        }
+    }
+
+    // What (hash-by-identity v) hashes: the allocation behind lists, strings, boxes and procedures, and the value
+    // itself for everything else
+    fn identity_hash(&self) -> i64 {
+        let address = match *self {
+            Value::List(List::Cell(ref car, ref cdr)) => {
+                return random::stable_hash(&(&**car as *const Value as usize, &**cdr as *const List as usize))
+            },
+            Value::DottedList(_, ref tail) => &**tail as *const Value as usize,
+            Value::String(ref s) => s.as_ptr() as usize,
+            Value::Vector(ref v) => &**v as *const RefCell<Vec<Value>> as usize,
+            Value::Bytevector(ref b) => &**b as *const Vec<u8> as usize,
+            Value::Box(ref b) => &**b as *const RefCell<Value> as usize,
+            Value::Queue(ref q) => &**q as *const RefCell<VecDeque<Value>> as usize,
+            Value::Set(ref s) => &**s as *const RefCell<HashSet<Value, HashState>> as usize,
+            Value::Heap(ref h) => &**h as *const RefCell<Heap> as usize,
+            #[cfg(feature = "sqlite")]
+            Value::Database(ref d) => &**d as *const sqlite::Database as usize,
+            #[cfg(feature = "ffi")]
+            Value::Library(ref l) => &**l as *const ffi::Library as usize,
+            #[cfg(feature = "ffi")]
+            Value::Procedure(Function::Foreign(ref p)) => &**p as *const ffi::Procedure as usize,
+            Value::Procedure(Function::Plugin(ref b)) => &**b as *const plugin::Builtin as usize,
+            Value::Procedure(Function::Native(name)) => name.as_ptr() as usize,
+            Value::Procedure(Function::Scheme(ref arg_names, _, _)) => &**arg_names as *const Vec<String> as usize,
+            Value::Macro(ref arg_names, _) => &**arg_names as *const Vec<String> as usize,
+            _ => return random::stable_hash(self)
+        };
+        random::stable_hash(&address)
+    }
+
+    // What eqv? compares by: numbers, characters, symbols and the like by value, where an exact number is never eqv?
+    // to an inexact one, and everything else, strings included, by identity
+    fn is_eqv(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::List(a), Value::List(b)) => a.is_same(b),
+            (Value::DottedList(a, a_tail), Value::DottedList(b, b_tail)) => {
+                a.is_same(b) && Rc::ptr_eq(a_tail, b_tail)
+            },
+            (Value::Vector(a), Value::Vector(b)) => Rc::ptr_eq(a, b),
+            (Value::Values(a), Value::Values(b)) => Rc::ptr_eq(a, b),
+            (Value::String(a), Value::String(b)) => Rc::ptr_eq(a, b),
+            (Value::Bytevector(a), Value::Bytevector(b)) => Rc::ptr_eq(a, b),
+            (Value::Box(a), Value::Box(b)) => Rc::ptr_eq(a, b),
+            (Value::Macro(a, _), Value::Macro(b, _)) => Rc::ptr_eq(a, b),
+            _ => self == other
         }
     }
 
     fn as_symbol(self) -> Result<String, RuntimeError> {
         match self {
             Value::Symbol(s) => Ok(s),
-            _ => runtime_error!("Expected a symbol value: {:?}", self)
+            _ => type_error!("Expected a symbol value: {:?}", self)
+        }
+    }
+
+    fn as_vector(self) -> Result<Rc<RefCell<Vec<Value>>>, RuntimeError> {
+        match self {
+            Value::Vector(items) => Ok(items),
+            _ => type_error!("Expected a vector value: {:?}", self)
+        }
+    }
+
+    fn as_bytevector(self) -> Result<Rc<Vec<u8>>, RuntimeError> {
+        match self {
+            Value::Bytevector(bytes) => Ok(bytes),
+            _ => type_error!("Expected a bytevector value: {:?}", self)
         }
     }
 
     fn as_integer(self) -> Result<i64, RuntimeError> {
         match self {
             Value::Integer(i) => Ok(i),
-            _ => runtime_error!("Expected an integer value: {:?}", self)
+            _ => type_error!("Expected an integer value: {:?}", self)
+        }
+    }
+
+    fn as_number(self) -> Result<Number, RuntimeError> {
+        match self {
+            Value::Integer(i) => Ok(Number::Integer(i)),
+            Value::Rational(n, d) => Ok(Number::Rational(n, d)),
+            Value::Float(f) => Ok(Number::Float(f)),
+            _ => type_error!("Expected a number: {:?}", self)
         }
     }
 
     // fn as_boolean(self) -> Result<bool, RuntimeError> {
     //     match self {
     //         Value::Boolean(b) => Ok(b),
-    //         _ => runtime_error!("Expected a boolean value: {:?}", self)
+    //         _ => type_error!("Expected a boolean value: {:?}", self)
     //     }
     // }
 
-    // fn as_string(self) -> Result<String, RuntimeError> {
-    //     match self {
-    //         Value::String(s) => Ok(s),
-    //         _ => runtime_error!("Expected a string value: {:?}", self)
-    //     }
-    // }
+    fn as_string(self) -> Result<String, RuntimeError> {
+        match self {
+            Value::String(s) => Ok(s.to_string()),
+            _ => type_error!("Expected a string value: {:?}", self)
+        }
+    }
 
     fn as_list(self) -> Result<List, RuntimeError> {
         match self {
             Value::List(l) => Ok(l),
-            _ => runtime_error!("Expected a list value: {:?}", self)
+            _ => type_error!("Expected a list value: {:?}", self)
         }
     }
 }
@@ -114,13 +475,32 @@ impl fmt::Display for Value {
         match *self {
             Value::Symbol(ref val) => write!(f, "{}", val),
             Value::Integer(val)    => write!(f, "{}", val),
+            Value::Rational(n, d)  => write!(f, "{}/{}", n, d),
+            Value::Float(val)      => write!(f, "{}", printer::float(val)),
             Value::Boolean(val)    => write!(f, "#{}", if val { "t" } else { "f" }),
+            Value::Character(val)  => write!(f, "{}", val),
             Value::String(ref val) => write!(f, "{}", val),
             Value::List(ref list)  => write!(f, "{}", list),
             Value::Procedure(_)    => write!(f, "#<procedure>"),
             Value::SpecialForm(_)  => write!(f, "#<special_form>"),
             Value::Continuation(_) => write!(f, "#<continuation>"),
             Value::Macro(_,_)      => write!(f, "#<macro>"),
+            Value::EofObject       => write!(f, "#<eof>"),
+            Value::Keyword(ref val) => write!(f, "#:{}", val),
+            Value::Condition(kind, ref message) => write!(f, "#<{}: {}>", kind.name(), message),
+            Value::Queue(_)        => write!(f, "#<queue>"),
+            Value::Set(_)          => write!(f, "#<set>"),
+            Value::Heap(_)         => write!(f, "#<heap>"),
+            #[cfg(feature = "sqlite")]
+            Value::Database(ref d) => write!(f, "{:?}", d),
+            #[cfg(feature = "ffi")]
+            Value::Library(ref l) => write!(f, "{:?}", l),
+            Value::DottedList(..) | Value::Vector(_) | Value::Box(_) => write!(f, "{}", printer::print_with(self, Style::Display, PrinterConfig::default())),
+            Value::Bytevector(ref bytes) => write!(f, "{}", printer::bytevector(bytes)),
+            Value::Values(ref vals) => {
+                let printed: Vec<String> = vals.iter().map(|v| format!("{}", v)).collect();
+                write!(f, "{}", printed.join(" "))
+            },
         }
     }
 }
@@ -129,16 +509,194 @@ impl fmt::Debug for Value {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Value::String(ref val) => write!(f, "\"{}\"", val),
+            Value::Symbol(ref val) => write!(f, "{}", printer::symbol(val)),
+            Value::Character(val)  => write!(f, "{}", printer::character(val)),
             Value::List(ref list)  => write!(f, "{:?}", list),
+            Value::DottedList(..) | Value::Vector(_) | Value::Box(_) => write!(f, "{}", printer::print_with(self, Style::Write, PrinterConfig::default())),
+            Value::Values(ref vals) => {
+                let printed: Vec<String> = vals.iter().map(|v| format!("{:?}", v)).collect();
+                write!(f, "{}", printed.join(" "))
+            },
             _                      => write!(f, "{}", self)
         }
     }
 }
 
-#[derive(Clone, PartialEq)]
+// Compared with an explicit stack of pairs still to check rather than by recursing (the clones are cheap, since
+// lists and boxes are reference counted)
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        let mut pending = vec![(self.clone(), other.clone())];
+        while let Some(pair) = pending.pop() {
+            let same = match pair {
+                (Value::List(ref a), Value::List(ref b)) => {
+                    let (mut a, mut b) = (a.iter(), b.iter());
+                    loop {
+                        match (a.next(), b.next()) {
+                            (Some(x), Some(y)) => pending.push((x.clone(), y.clone())),
+                            (None, None) => break,
+                            _ => return false
+                        }
+                    }
+                    true
+                },
+                (Value::DottedList(ref a, ref a_tail), Value::DottedList(ref b, ref b_tail)) => {
+                    pending.push((Value::List(a.clone()), Value::List(b.clone())));
+                    pending.push(((**a_tail).clone(), (**b_tail).clone()));
+                    true
+                },
+                (Value::Values(ref a), Value::Values(ref b)) => {
+                    if a.len() != b.len() {
+                        return false
+                    }
+                    pending.extend(a.iter().cloned().zip(b.iter().cloned()));
+                    true
+                },
+                (Value::Symbol(ref a), Value::Symbol(ref b)) => a == b,
+                (Value::Integer(a), Value::Integer(b)) => a == b,
+                (Value::Rational(an, ad), Value::Rational(bn, bd)) => an == bn && ad == bd,
+                // by their bits, so that equality stays an equivalence: +nan.0 equals itself, and 0.0 isn't -0.0
+                (Value::Float(a), Value::Float(b)) => a.to_bits() == b.to_bits(),
+                (Value::Boolean(a), Value::Boolean(b)) => a == b,
+                (Value::Character(a), Value::Character(b)) => a == b,
+                (Value::String(ref a), Value::String(ref b)) => a == b,
+                (Value::Bytevector(ref a), Value::Bytevector(ref b)) => a == b,
+                (Value::Procedure(ref a), Value::Procedure(ref b)) => a == b,
+                (Value::SpecialForm(ref a), Value::SpecialForm(ref b)) => a == b,
+                (Value::Macro(ref a1, ref a2), Value::Macro(ref b1, ref b2)) => a1 == b1 && a2 == b2,
+                (Value::Continuation(ref a), Value::Continuation(ref b)) => a == b,
+                (Value::EofObject, Value::EofObject) => true,
+                (Value::Keyword(ref a), Value::Keyword(ref b)) => a == b,
+                (Value::Condition(a1, ref a2), Value::Condition(b1, ref b2)) => a1 == b1 && a2 == b2,
+                // what boxes, vectors, queues, sets and heaps hold can change, so they're only equal to themselves
+                (Value::Box(ref a), Value::Box(ref b)) => Rc::ptr_eq(a, b),
+                (Value::Vector(ref a), Value::Vector(ref b)) => Rc::ptr_eq(a, b),
+                (Value::Queue(ref a), Value::Queue(ref b)) => Rc::ptr_eq(a, b),
+                (Value::Set(ref a), Value::Set(ref b)) => Rc::ptr_eq(a, b),
+                (Value::Heap(ref a), Value::Heap(ref b)) => Rc::ptr_eq(a, b),
+                #[cfg(feature = "sqlite")]
+                (Value::Database(ref a), Value::Database(ref b)) => Rc::ptr_eq(a, b),
+                #[cfg(feature = "ffi")]
+                (Value::Library(ref a), Value::Library(ref b)) => Rc::ptr_eq(a, b),
+                _ => false
+            };
+            if !same {
+                return false
+            }
+        }
+        true
+    }
+}
+
+impl Eq for Value {}
+
+// Consistent with ==: lists and strings are hashed by structure, and boxes, vectors and procedures by identity. Special
+// forms, macros and continuations are only hashed by their kind, which is coarse, but agrees with ==.
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let mut pending = vec![self.clone()];
+        while let Some(value) = pending.pop() {
+            match value {
+                Value::Symbol(ref s) => { HashTag::Symbol.hash(state); s.hash(state) },
+                Value::Integer(i) => { HashTag::Integer.hash(state); i.hash(state) },
+                Value::Rational(n, d) => { HashTag::Rational.hash(state); n.hash(state); d.hash(state) },
+                Value::Float(f) => { HashTag::Float.hash(state); f.to_bits().hash(state) },
+                Value::Boolean(b) => { HashTag::Boolean.hash(state); b.hash(state) },
+                Value::Character(c) => { HashTag::Character.hash(state); c.hash(state) },
+                Value::String(ref s) => { HashTag::String.hash(state); s.hash(state) },
+                Value::List(ref l) => {
+                    HashTag::List.hash(state);
+                    l.len().hash(state);
+                    pending.extend(l.iter().cloned());
+                },
+                Value::Vector(ref v) => { HashTag::Vector.hash(state); (&**v as *const RefCell<Vec<Value>> as usize).hash(state) },
+                Value::Bytevector(ref b) => { HashTag::Bytevector.hash(state); b.hash(state) },
+                Value::DottedList(ref l, ref tail) => {
+                    HashTag::DottedList.hash(state);
+                    pending.push((**tail).clone());
+                    pending.push(Value::List(l.clone()));
+                },
+                Value::Procedure(ref p) => { HashTag::Procedure.hash(state); p.hash(state) },
+                Value::SpecialForm(_) => HashTag::SpecialForm.hash(state),
+                Value::Macro(_, _) => HashTag::Macro.hash(state),
+                Value::Continuation(_) => HashTag::Continuation.hash(state),
+                Value::EofObject => HashTag::EofObject.hash(state),
+                Value::Keyword(ref k) => { HashTag::Keyword.hash(state); k.hash(state) },
+                Value::Condition(kind, ref message) => { HashTag::Condition.hash(state); kind.hash(state); message.hash(state) },
+                Value::Values(ref vals) => {
+                    HashTag::Values.hash(state);
+                    vals.len().hash(state);
+                    pending.extend(vals.iter().cloned());
+                },
+                Value::Queue(ref q) => { HashTag::Queue.hash(state); (&**q as *const RefCell<VecDeque<Value>> as usize).hash(state) },
+                Value::Set(ref s) => { HashTag::Set.hash(state); (&**s as *const RefCell<HashSet<Value, HashState>> as usize).hash(state) },
+                Value::Heap(ref h) => { HashTag::Heap.hash(state); (&**h as *const RefCell<Heap> as usize).hash(state) },
+                #[cfg(feature = "sqlite")]
+                Value::Database(ref d) => { HashTag::Database.hash(state); (&**d as *const sqlite::Database as usize).hash(state) },
+                #[cfg(feature = "ffi")]
+                Value::Library(ref l) => { HashTag::Library.hash(state); (&**l as *const ffi::Library as usize).hash(state) },
+                Value::Box(ref b) => { HashTag::Box.hash(state); (&**b as *const RefCell<Value> as usize).hash(state) },
+            }
+        }
+    }
+}
+
+impl Printable for Value {
+    fn shape(&self, style: Style) -> Shape<Value> {
+        match *self {
+            Value::List(ref list) => Shape::List(list.id(), list.iter().cloned().collect()),
+            Value::DottedList(ref list, ref tail) => Shape::DottedList(list.id(), list.iter().cloned().collect(), (**tail).clone()),
+            Value::Vector(ref items) => Shape::Vector(Some(&**items as *const RefCell<Vec<Value>> as usize), items.borrow().to_vec()),
+            Value::Box(ref b) => Shape::Box(&**b as *const RefCell<Value> as usize, b.borrow().clone()),
+            Value::String(_) | Value::Character(_) | Value::Symbol(_) if style == Style::Display => Shape::Atom(format!("{}", self)),
+            Value::String(ref s) => Shape::String(s.as_ptr() as usize, format!("{:?}", self)),
+            Value::Procedure(Function::Scheme(ref params, ref body, _)) if printer::config().show_procedures => {
+                Shape::Atom(printer::procedure(params, body.iter()))
+            },
+            _ => Shape::Atom(format!("{:?}", self))
+        }
+    }
+}
+
+#[derive(Clone)]
 enum Function {
-    Scheme(Vec<String>, List, Rc<RefCell<Environment>>),
+    Scheme(Rc<Vec<String>>, List, Rc<RefCell<Environment>>),
     Native(&'static str),
+    Plugin(Rc<plugin::Builtin>),
+    #[cfg(feature = "ffi")]
+    Foreign(Rc<ffi::Procedure>),
+}
+
+// A procedure is only equal to itself: a closure made by evaluating the same lambda in the same environment. Comparing
+// the environments themselves would recurse through every procedure defined in them.
+impl PartialEq for Function {
+    fn eq(&self, other: &Function) -> bool {
+        match (self, other) {
+            (Function::Scheme(a_params, _, a_env), Function::Scheme(b_params, _, b_env)) => {
+                Rc::ptr_eq(a_params, b_params) && Rc::ptr_eq(a_env, b_env)
+            },
+            (&Function::Native(a), &Function::Native(b)) => a == b,
+            (Function::Plugin(a), Function::Plugin(b)) => Rc::ptr_eq(a, b),
+            #[cfg(feature = "ffi")]
+            (Function::Foreign(a), Function::Foreign(b)) => Rc::ptr_eq(a, b),
+            _ => false
+        }
+    }
+}
+
+impl Hash for Function {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match *self {
+            Function::Scheme(ref params, _, ref env) => {
+                (&**params as *const Vec<String> as usize).hash(state);
+                (&**env as *const RefCell<Environment> as usize).hash(state);
+            },
+            Function::Native(name) => name.hash(state),
+            Function::Plugin(ref b) => (&**b as *const plugin::Builtin as usize).hash(state),
+            #[cfg(feature = "ffi")]
+            Function::Foreign(ref p) => (&**p as *const ffi::Procedure as usize).hash(state)
+        }
+    }
 }
 
 impl fmt::Debug for Function {
@@ -146,6 +704,9 @@ impl fmt::Debug for Function {
         match *self {
             Function::Scheme(_, _, _) => write!(f, "#<procedure>"),
             Function::Native(ref s) => write!(f, "#<procedure:{}>", s),
+            Function::Plugin(ref b) => write!(f, "{:?}", b),
+            #[cfg(feature = "ffi")]
+            Function::Foreign(ref p) => write!(f, "{:?}", p),
         }
     }
 }
@@ -157,6 +718,8 @@ enum SpecialForm {
     Set,
     Lambda,
     Let,
+    Letrec,
+    LetrecStar,
     Quote,
     Quasiquote,
     Eval,
@@ -165,7 +728,10 @@ enum SpecialForm {
     And,
     Or,
     CallCC,
+    CallWithValues,
     DefineSyntaxRule,
+    Autoload,
+    Guard,
 }
 
 enum Trampoline {
@@ -178,25 +744,73 @@ enum Trampoline {
 #[derive(PartialEq, Clone, Debug)]
 enum Continuation {
     EvaluateExpressions(List, Rc<RefCell<Environment>>, Box<Continuation>),
+    // Holds the whole call, (f arg...), to report as the frame of any error in it
     BeginFunc(List, Rc<RefCell<Environment>>, Box<Continuation>),
     EvaluateIf(Value, Value, Rc<RefCell<Environment>>, Box<Continuation>),
     EvaluateDefine(String, Rc<RefCell<Environment>>, Box<Continuation>),
     EvaluateSet(String, Rc<RefCell<Environment>>, Box<Continuation>),
-    EvaluateFunc(Value, List, List, Rc<RefCell<Environment>>, Box<Continuation>),
+    EvaluateFunc(Value, List, List, List, Rc<RefCell<Environment>>, Box<Continuation>),
     EvaluateLet(String, List, List, Rc<RefCell<Environment>>, Box<Continuation>),
+    // The name being evaluated, the bindings left, the values waiting for the rest (letrec only), the body, and
+    // whether it's letrec*
+    EvaluateLetrec(String, List, Vec<(String, Value)>, List, bool, Rc<RefCell<Environment>>, Box<Continuation>),
     ContinueQuasiquoting(List, List, Rc<RefCell<Environment>>, Box<Continuation>),
+    // Like ContinueQuasiquoting, but the value is a list of items to splice in, from (unquote-splicing X)
+    SpliceQuasiquoting(List, List, Rc<RefCell<Environment>>, Box<Continuation>),
+    // The items (reversed) of a quasiquoted list before `(... . ,X), which the value of X is the tail of
+    EndQuasiquoting(List, Box<Continuation>),
+    // The tail of a quasiquoted dotted list, to put after the items once they're quasiquoted
+    AttachTail(Rc<Value>, Box<Continuation>),
     ExecuteEval(Rc<RefCell<Environment>>, Box<Continuation>),
     EvaluateApplyArgs(Value, Rc<RefCell<Environment>>, Box<Continuation>),
     ExecuteApply(Value, Box<Continuation>),
     EvaluateAnd(List, Rc<RefCell<Environment>>, Box<Continuation>),
     EvaluateOr(List, Rc<RefCell<Environment>>, Box<Continuation>),
     ExecuteCallCC(Box<Continuation>),
+    EvaluateConsumer(Value, Rc<RefCell<Environment>>, Box<Continuation>),
+    ExecuteProducer(Value, Box<Continuation>),
+    ExecuteConsumer(Value, Box<Continuation>),
+    // heap-push! and heap-pop! call the heap's ordering procedure to decide each move
+    SiftHeapUp(Rc<RefCell<Heap>>, usize, Box<Continuation>),
+    ChooseHeapChild(Rc<RefCell<Heap>>, usize, Value, Box<Continuation>),
+    SiftHeapDown(Rc<RefCell<Heap>>, usize, usize, Value, Box<Continuation>),
+    // directory-walk calls its procedure on each of the files left
+    #[cfg(feature = "io")]
+    WalkFiles(List, Value, Box<Continuation>),
+    // serve-http calls the handler for each request, with the number left to answer if there's a limit
+    #[cfg(feature = "net")]
+    RespondHttp(Rc<RefCell<http::Server>>, Value, Option<i64>, Box<Continuation>),
     Return,
 }
 
 #[derive(Debug)]
 pub struct RuntimeError {
+    kind: Kind,
     message: String,
+    // Where it happened, for the REPL to inspect
+    frame: Option<Box<Frame>>,
+}
+
+impl RuntimeError {
+    pub fn kind(&self) -> Kind {
+        self.kind
+    }
+
+    pub fn take_frame(&mut self) -> Option<Frame> {
+        self.frame.take().map(|f| *f)
+    }
+
+    // Record the call the error happened in, unless it happened in a call nested inside that one
+    fn in_frame<F: FnOnce() -> Value>(mut self, expression: F, env: &Rc<RefCell<Environment>>) -> RuntimeError {
+        if self.frame.is_none() {
+            self.frame = Some(Box::new(Frame { expression: expression(), env: env.clone() }));
+        }
+        self
+    }
+
+    fn to_condition(&self) -> Value {
+        Value::Condition(self.kind, Rc::from(self.message.as_str()))
+    }
 }
 
 impl fmt::Display for RuntimeError {
@@ -205,22 +819,109 @@ impl fmt::Display for RuntimeError {
     }
 }
 
-#[derive(PartialEq, Clone)]
+// The innermost call being evaluated when an error happened, and the environment it was evaluated in
+#[derive(Debug)]
+pub struct Frame {
+    expression: Value,
+    env: Rc<RefCell<Environment>>,
+}
+
+impl Frame {
+    pub fn expression(&self) -> String {
+        printer::print(&self.expression, Style::Write)
+    }
+
+    // The local variables visible in the frame and their values, innermost first and then by name (globals are left
+    // out)
+    pub fn locals(&self) -> Vec<(String, String)> {
+        let mut locals: Vec<(String, String)> = Vec::new();
+        let mut env = self.env.clone();
+        loop {
+            let parent = match env.borrow().parent {
+                Some(ref parent) => parent.clone(),
+                None => break
+            };
+            let mut level: Vec<(String, String)> = env.borrow().values.iter()
+                .filter(|&(name, _)| !locals.iter().any(|l| l.0 == *name))
+                .map(|(name, value)| (name.clone(), printer::print(value, Style::Write)))
+                .collect();
+            level.sort();
+            locals.extend(level);
+            env = parent;
+        }
+        locals
+    }
+}
+
+#[derive(Clone)]
 enum List {
-    Cell(Box<Value>, Box<List>),
+    Cell(Rc<Value>, Rc<List>),
     Null
 }
 
 // null == empty list
 macro_rules! null { () => (List::Null.to_value()) }
 
+// Dropping the last reference to a list takes apart its cells, and the lists and vectors in them, with a stack of its
+// own rather than recursing, so that a long or deeply nested list can't overflow the Rust stack when it goes away
+impl Drop for List {
+    fn drop(&mut self) {
+        // the usual case, where dropping the cell doesn't drop anything nested in it, needs no stack
+        let nested = match *self {
+            List::Cell(ref car, ref cdr) => {
+                (Rc::strong_count(cdr) == 1 && !cdr.is_empty()) || (Rc::strong_count(car) == 1 && match **car {
+                    Value::List(ref list) => !list.is_empty(),
+                    Value::DottedList(..) | Value::Vector(_) | Value::Values(_) => true,
+                    _ => false
+                })
+            },
+            List::Null => false
+        };
+        if !nested {
+            return
+        }
+        let mut lists = vec![mem::replace(self, List::Null)];
+        let mut values = Vec::new();
+        while !lists.is_empty() || !values.is_empty() {
+            while let Some(mut list) = lists.pop() {
+                // only what nothing else refers to is taken apart now; the rest is left to its last reference
+                match list {
+                    List::Cell(ref mut car, ref mut cdr) => {
+                        if let Some(car) = Rc::get_mut(car) {
+                            values.push(mem::replace(car, Value::Boolean(false)));
+                        }
+                        if let Some(cdr) = Rc::get_mut(cdr) {
+                            lists.push(mem::replace(cdr, List::Null));
+                        }
+                    },
+                    List::Null => ()
+                }
+            }
+            while let Some(value) = values.pop() {
+                match value {
+                    Value::List(list) => lists.push(list),
+                    Value::DottedList(list, tail) => {
+                        lists.push(list);
+                        if let Ok(tail) = Rc::try_unwrap(tail) {
+                            values.push(tail);
+                        }
+                    },
+                    Value::Vector(items) => if let Ok(items) = Rc::try_unwrap(items) { values.extend(items.into_inner()) },
+                    Value::Values(items) => if let Ok(items) = Rc::try_unwrap(items) { values.extend(items) },
+                    _ => ()
+                }
+            }
+        }
+    }
+}
+
 impl List {
     fn from_vec(mut vec: Vec<Value>) -> List {
         if vec.len() > 0 {
             let mut out = List::Null;
             while vec.len() > 0 {
                 let v = vec.pop().unwrap();
-                out = List::Cell(Box::new(v), Box::new(out));
+                out = out.unshift(v);
             }
             out
         } else {
@@ -234,25 +935,69 @@ impl List {
     }
 
     fn is_empty(&self) -> bool {
-        self == &List::Null
+        match *self {
+            List::Null => true,
+            List::Cell(_, _) => false
+        }
+    }
+
+    // Whether both are the same cell, or both empty
+    fn is_same(&self, other: &List) -> bool {
+        match (self, other) {
+            (List::Cell(a_car, a_cdr), List::Cell(b_car, b_cdr)) => {
+                Rc::ptr_eq(a_car, b_car) && Rc::ptr_eq(a_cdr, b_cdr)
+            },
+            (&List::Null, &List::Null) => true,
+            _ => false
+        }
     }
 
-    fn shift(self) -> Option<(Value, List)> {
+    // The address of the first cell's element, which no other cell shares, so the printer can find shared lists
+    fn id(&self) -> Option<usize> {
+        match *self {
+            List::Cell(ref car, _) => Some(&**car as *const Value as usize),
+            List::Null => None
+        }
+    }
+
+    // The contents of a cell nothing else holds are taken rather than copied, leaving an empty cell behind
+    fn shift(mut self) -> Option<(Value, List)> {
         match self {
-            List::Cell(car, cdr) => Some((*car, *cdr)),
+            List::Cell(ref mut car, ref mut cdr) => {
+                let car = match Rc::get_mut(car) {
+                    Some(car) => mem::replace(car, Value::Boolean(false)),
+                    None => (**car).clone()
+                };
+                let cdr = match Rc::get_mut(cdr) {
+                    Some(cdr) => mem::replace(cdr, List::Null),
+                    None => (**cdr).clone()
+                };
+                Some((car, cdr))
+            },
             List::Null => None
         }
     }
 
+    // Everything after the first element, without taking the list apart
+    fn rest(&self) -> List {
+        match *self {
+            List::Cell(_, ref cdr) => (**cdr).clone(),
+            List::Null => List::Null
+        }
+    }
+
     fn unshift(self, car: Value) -> List {
-        List::Cell(Box::new(car), Box::new(self))
+        stats::count_allocations(1);
+        List::Cell(Rc::new(car), Rc::new(self))
+    }
+
+    // Walk the elements without taking the list apart
+    fn iter(&self) -> ListIter<'_> {
+        ListIter { next: self }
     }
 
     fn len(&self) -> usize {
-        match self {
-            &List::Cell(_, ref cdr) => 1 + cdr.len(),
-            &List::Null => 0
-        }
+        self.iter().count()
     }
 
     fn unpack1(self) -> Result<Value, RuntimeError> {
@@ -276,12 +1021,51 @@ impl List {
         Ok((car, cadr, caddr))
     }
 
+    // Cells nothing else holds are relinked rather than copied, so reversing a list that was just built backwards
+    // allocates nothing
     fn reverse(self) -> List {
         let mut out = List::Null;
-        for v in self {
-            out = out.unshift(v)
+        let mut rest = self;
+        loop {
+            // point the first cell of rest at out, and move it over
+            let next = match rest {
+                List::Cell(_, ref mut cdr) => {
+                    let reversed = mem::replace(&mut out, List::Null);
+                    match Rc::get_mut(cdr) {
+                        Some(next) => mem::replace(next, reversed),
+                        None => {
+                            stats::count_allocations(1);
+                            let next = (**cdr).clone();
+                            *cdr = Rc::new(reversed);
+                            next
+                        }
+                    }
+                },
+                List::Null => return out
+            };
+            out = mem::replace(&mut rest, next);
         }
-        out
+    }
+
+    // The list followed by tail, which is shared rather than copied, as are any of the list's cells nothing else holds
+    fn append(self, tail: List) -> List {
+        let mut head = self;
+        {
+            let mut cursor = &mut head;
+            loop {
+                match *cursor {
+                    List::Cell(_, ref mut cdr) => {
+                        if Rc::strong_count(cdr) > 1 {
+                            stats::count_allocations(1);
+                        }
+                        cursor = Rc::make_mut(cdr);
+                    },
+                    List::Null => break
+                }
+            }
+            *cursor = tail;
+        }
+        head
     }
 
     fn to_value(self) -> Value {
@@ -291,14 +1075,9 @@ impl List {
     fn to_vec(self) -> Vec<Value> {
         let mut out = vec![];
         let mut l = self;
-        loop {
-            match l.shift() {
-                Some((car, cdr)) => {
-                    out.push(car);
-                    l = cdr;
-                },
-                None => break
-            }
+        while let Some((car, cdr)) = l.shift() {
+            out.push(car);
+            l = cdr;
         }
         out
     }
@@ -313,17 +1092,41 @@ impl iter::IntoIterator for List {
     }
 }
 
+struct ListIter<'a> {
+    next: &'a List,
+}
+
+impl<'a> Iterator for ListIter<'a> {
+    type Item = &'a Value;
+
+    fn next(&mut self) -> Option<&'a Value> {
+        match *self.next {
+            List::Cell(ref car, ref cdr) => {
+                self.next = &**cdr;
+                Some(&**car)
+            },
+            List::Null => None
+        }
+    }
+}
+
+// Lists can nest arbitrarily deep, so they're printed and compared without recursing: the printer keeps its own
+// stack, and so does Value's PartialEq
+impl PartialEq for List {
+    fn eq(&self, other: &List) -> bool {
+        Value::List(self.clone()) == Value::List(other.clone())
+    }
+}
+
 impl fmt::Display for List {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let strs: Vec<String> = self.clone().into_iter().map(|v| format!("{}", v)).collect();
-        write!(f, "({})", &strs.connect(" "))
+        write!(f, "{}", printer::print_with(&Value::List(self.clone()), Style::Display, PrinterConfig::default()))
     }
 }
 
 impl fmt::Debug for List {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let strs: Vec<String> = self.clone().into_iter().map(|v| format!("{:?}", v)).collect();
-        write!(f, "({})", &strs.connect(" "))
+        write!(f, "{}", printer::print_with(&Value::List(self.clone()), Style::Write, PrinterConfig::default()))
     }
 }
 
@@ -337,165 +1140,19 @@ impl Continuation {
                     Ok(Trampoline::Run(val, *k))
                 }
             },
-            Continuation::BeginFunc(rest, env, k) => {
-                match val {
-                    Value::SpecialForm(f) => {
-                        match f {
-                            SpecialForm::If => {
-                                let (condition, if_expr, else_expr) = try!(rest.unpack3());
-                                Ok(Trampoline::Bounce(condition, env.clone(), Continuation::EvaluateIf(if_expr, else_expr, env, k)))
-                            },
-                            SpecialForm::Define => {
-                                let (car, cdr) = shift_or_error!(rest, "Must provide at least two arguments to define");
-                                match car {
-                                    Value::Symbol(name) => {
-                                        let val = try!(cdr.unpack1());
-                                        Ok(Trampoline::Bounce(val, env.clone(), Continuation::EvaluateDefine(name, env, k)))
-                                    },
-                                    Value::List(list) => {
-                                        let (caar, cdar) = shift_or_error!(list, "Must provide at least two params in first argument of define");
-                                        let name = try!(caar.as_symbol());
-
-                                        let arg_names = try!(cdar.into_iter().map(|v| v.as_symbol()).collect());
-                                        let body = cdr;
-                                        let f = Function::Scheme(arg_names, body, env.clone());
-
-                                        try!(env.borrow_mut().define(name, Value::Procedure(f)));
-                                        Ok(Trampoline::Run(null!(), *k))
-                                    },
-                                    _ => runtime_error!("Bad argument to define: {:?}", car)
-                                }
-                            },
-                            SpecialForm::Set => {
-                                let (name_raw, val) = try!(rest.unpack2());
-                                let name = try!(name_raw.as_symbol());
-                                Ok(Trampoline::Bounce(val, env.clone(), Continuation::EvaluateSet(name, env, k)))
-                            },
-                            SpecialForm::Lambda => {
-                                let (arg_defns_raw, body) = shift_or_error!(rest, "Must provide at least two arguments to lambda");
-                                let arg_defns = try!(arg_defns_raw.as_list());
-                                let arg_names = try!(arg_defns.into_iter().map(|v| v.as_symbol()).collect());
-
-                                let f = Function::Scheme(arg_names, body, env);
-                                Ok(Trampoline::Run(Value::Procedure(f), *k))
-                            },
-                            SpecialForm::Let => {
-                                let (arg_defns_raw, body) = shift_or_error!(rest, "Must provide at least two arguments to let");
-                                let arg_defns = try!(arg_defns_raw.as_list());
-
-                                // Create a new, child environment for the procedure and define the arguments as local variables
-                                let proc_env = Environment::new_child(env.clone());
-
-                                // Iterate through the provided arguments, defining them
-                                if !arg_defns.is_empty() {
-                                    let (first_defn, rest_defns) = shift_or_error!(arg_defns, "Error in let definiton");
-                                    let (defn_key, defn_val) = try!(try!(first_defn.as_list()).unpack2());
-                                    let name = try!(defn_key.as_symbol());
-                                    Ok(Trampoline::Bounce(defn_val, env, Continuation::EvaluateLet(name, rest_defns, body, proc_env, k)))
-                                } else {
-                                    // Let bindings were empty, just execute the body directly
-                                    evaluate_expressions(body, env, k)
-                                }
-                            },
-                            SpecialForm::Quote => {
-                                let expr = try!(rest.unpack1());
-                                Ok(Trampoline::Run(expr, *k))
-                            },
-                            SpecialForm::Quasiquote => {
-                                let expr = try!(rest.unpack1());
-                                match expr {
-                                    Value::List(list) => {
-                                        match list.shift() {
-                                            Some((car, cdr)) => Ok(Trampoline::QuasiBounce(car, env.clone(), Continuation::ContinueQuasiquoting(cdr, List::Null, env, k))),
-                                            None => Ok(Trampoline::Run(null!(), *k))
-                                        }
-                                    },
-                                    _ => Ok(Trampoline::Run(expr, *k))
-                                }
-                            },
-                            SpecialForm::Eval => {
-                                let expr = try!(rest.unpack1());
-                                Ok(Trampoline::Bounce(expr, env.clone(), Continuation::ExecuteEval(env, k)))
-                            },
-                            SpecialForm::Apply => {
-                                let (func, args) = try!(rest.unpack2());
-                                Ok(Trampoline::Bounce(func, env.clone(), Continuation::EvaluateApplyArgs(args, env, k)))
-                            },
-                            SpecialForm::Begin => {
-                                match rest.shift() {
-                                    Some((car, cdr)) => Ok(Trampoline::Bounce(car, env.clone(), Continuation::EvaluateExpressions(cdr, env, k))),
-                                    None => runtime_error!("Must provide at least one argument to a begin statement")
-                                }
-                            },
-                            SpecialForm::And => {
-                                match rest.shift() {
-                                    Some((car, cdr)) => Ok(Trampoline::Bounce(car, env.clone(), Continuation::EvaluateAnd(cdr, env, k))),
-                                    None => Ok(Trampoline::Run(Value::Boolean(true), *k))
-                                }
-                            },
-                            SpecialForm::Or => {
-                                match rest.shift() {
-                                    Some((car, cdr)) => Ok(Trampoline::Bounce(car, env.clone(), Continuation::EvaluateOr(cdr, env, k))),
-                                    None => Ok(Trampoline::Run(Value::Boolean(false), *k))
-                                }
-                            },
-                            SpecialForm::CallCC => {
-                                let f = try!(rest.unpack1());
-                                Ok(Trampoline::Bounce(f, env, Continuation::ExecuteCallCC(k)))
-                            },
-                            SpecialForm::DefineSyntaxRule => {
-                                let (defn, body) = try!(rest.unpack2());
-
-                                let (name, arg_names_raw) = match try!(defn.as_list()).shift() {
-                                    Some((car, cdr)) => (try!(car.as_symbol()), cdr),
-                                    None => runtime_error!("Must supply at least two params to first argument in define-syntax-rule")
-                                };
-
-                                let arg_names = try!(arg_names_raw.into_iter().map(|v| v.as_symbol()).collect());
-
-                                let m = Value::Macro(arg_names, Box::new(body));
-                                try!(env.borrow_mut().define(name, m));
-                                Ok(Trampoline::Run(null!(), *k))
-                            },
-                        }
-                    },
-                    Value::Macro(arg_names, body) => {
-                        let args = rest;
-                        if arg_names.len() != args.len() {
-                            runtime_error!("Must supply exactly {} arguments to macro: {:?}", arg_names.len(), args);
-                        }
-
-                        // Create a lookup table for symbol substitutions
-                        let mut substitutions = HashMap::new();
-                        for (name, value) in arg_names.into_iter().zip(args.into_iter()) {
-                            substitutions.insert(name, value);
-                        }
-
-                        // Expand the macro
-                        let expanded = expand_macro(*body, &substitutions);
-
-                        // Finished expanding macro, now evaluate the code manually
-                        Ok(Trampoline::Bounce(expanded, env, *k))
-                    },
-                    _ => {
-                        match rest.shift() {
-                            Some((car, cdr)) => Ok(Trampoline::Bounce(car, env.clone(), Continuation::EvaluateFunc(val, cdr, List::Null, env, k))),
-                            None => apply(val, List::Null, k)
-                        }
-                    }
-                }
+            Continuation::BeginFunc(call, env, k) => {
+                let frame_env = env.clone();
+                begin_func(val, call.clone(), env, k).map_err(|e| e.in_frame(|| call.to_value(), &frame_env))
             },
-            Continuation::EvaluateFunc(f, rest, acc, env, k) => {
-                let acc2 = acc.unshift(val);
-                match rest.shift() {
-                    Some((car, cdr)) => Ok(Trampoline::Bounce(car, env.clone(), Continuation::EvaluateFunc(f, cdr, acc2, env, k))),
-                    None => apply(f, acc2.reverse(), k)
-                }
+            Continuation::EvaluateFunc(f, rest, acc, call, env, k) => {
+                let frame_env = env.clone();
+                evaluate_arguments(f, rest, acc.unshift(val), call.clone(), env, k).map_err(|e| e.in_frame(|| call.to_value(), &frame_env))
             },
             Continuation::EvaluateIf(if_expr, else_expr, env, k) => {
-                match val {
-                    Value::Boolean(false) => Ok(Trampoline::Bounce(else_expr, env, *k)),
-                    _ => Ok(Trampoline::Bounce(if_expr, env, *k))
+                if try!(is_true("if", &val)) {
+                    Ok(Trampoline::Bounce(if_expr, env, *k))
+                } else {
+                    Ok(Trampoline::Bounce(else_expr, env, *k))
                 }
             },
             Continuation::EvaluateDefine(name, env, k) => {
@@ -521,12 +1178,33 @@ impl Continuation {
                     }
                 }
             },
+            Continuation::EvaluateLetrec(name, rest, mut values, body, star, env, k) => {
+                if star {
+                    try!(env.borrow_mut().initialize(name, val));
+                } else {
+                    values.push((name, val));
+                }
+                evaluate_letrec(rest, values, body, star, env, k)
+            },
             Continuation::ContinueQuasiquoting(rest, acc, env, k) => {
-                let acc2 = acc.unshift(val);
-                match rest.shift() {
-                    Some((car, cdr)) => Ok(Trampoline::QuasiBounce(car, env.clone(), Continuation::ContinueQuasiquoting(cdr, acc2, env, k))),
-                    None => Ok(Trampoline::Run(acc2.reverse().to_value(), *k))
+                quasiquote_items(rest, acc.unshift(val), env, k)
+            },
+            Continuation::SpliceQuasiquoting(rest, mut acc, env, k) => {
+                let items = match val {
+                    Value::List(items) => items,
+                    _ => type_error!("Can't splice a non-list into a quasiquoted list: {:?}", val)
+                };
+                for item in items.iter() {
+                    acc = acc.unshift(item.clone());
                 }
+                quasiquote_items(rest, acc, env, k)
+            },
+            Continuation::EndQuasiquoting(acc, k) => {
+                Ok(Trampoline::Run(with_tail(acc.reverse(), val), *k))
+            },
+            Continuation::AttachTail(tail, k) => {
+                let items = try!(val.as_list());
+                Ok(Trampoline::Run(with_tail(items, unwrap_or_clone(tail)), *k))
             },
             Continuation::ExecuteEval(env, k) => {
                 Ok(Trampoline::Bounce(val, Environment::get_root(env), *k))
@@ -537,70 +1215,356 @@ impl Continuation {
             Continuation::ExecuteApply(f, k) => {
                 apply(f, try!(val.as_list()), k)
             },
+            // the last operand of and and or is returned without being tested
             Continuation::EvaluateAnd(rest, env, k) => {
-                match val {
-                    Value::Boolean(false) => Ok(Trampoline::Run(Value::Boolean(false), *k)),
-                    _ => {
-                        match rest.shift() {
-                            Some((car, cdr)) => Ok(Trampoline::Bounce(car, env.clone(), Continuation::EvaluateAnd(cdr, env, k))),
-                            None => Ok(Trampoline::Run(val, *k))
-                        }
-                    }
+                match rest.shift() {
+                    Some(_) if !try!(is_true("and", &val)) => Ok(Trampoline::Run(Value::Boolean(false), *k)),
+                    Some((car, cdr)) => Ok(Trampoline::Bounce(car, env.clone(), Continuation::EvaluateAnd(cdr, env, k))),
+                    None => Ok(Trampoline::Run(val, *k))
                 }
             },
             Continuation::EvaluateOr(rest, env, k) => {
-                match val {
-                    Value::Boolean(false) => {
-                        match rest.shift() {
-                            Some((car, cdr)) => Ok(Trampoline::Bounce(car, env.clone(), Continuation::EvaluateOr(cdr, env, k))),
-                            None => Ok(Trampoline::Run(Value::Boolean(false), *k))
-                        }
-                    },
-                    _ => Ok(Trampoline::Run(val, *k))
+                match rest.shift() {
+                    Some(_) if try!(is_true("or", &val)) => Ok(Trampoline::Run(val, *k)),
+                    Some((car, cdr)) => Ok(Trampoline::Bounce(car, env.clone(), Continuation::EvaluateOr(cdr, env, k))),
+                    None => Ok(Trampoline::Run(val, *k))
                 }
             },
             Continuation::ExecuteCallCC(k) => {
                 apply(val, List::Null.unshift(Value::Continuation(k.clone())), k)
             },
+            Continuation::EvaluateConsumer(consumer, env, k) => {
+                Ok(Trampoline::Bounce(consumer, env, Continuation::ExecuteProducer(val, k)))
+            },
+            Continuation::ExecuteProducer(producer, k) => {
+                apply(producer, List::Null, Box::new(Continuation::ExecuteConsumer(val, k)))
+            },
+            Continuation::ExecuteConsumer(consumer, k) => {
+                let args = match val {
+                    Value::Values(vals) => List::from_vec(vals.iter().cloned().collect()),
+                    _ => List::Null.unshift(val)
+                };
+                apply(consumer, args, k)
+            },
+            Continuation::SiftHeapUp(heap, i, k) => {
+                let parent = (i - 1) / 2;
+                if val == Value::Boolean(false) || !heap.borrow_mut().swap(i, parent) {
+                    return Ok(Trampoline::Run(null!(), *k))
+                }
+                sift_heap_up(heap, parent, k)
+            },
+            Continuation::ChooseHeapChild(heap, i, result, k) => {
+                let child = if val == Value::Boolean(false) { 2 * i + 1 } else { 2 * i + 2 };
+                compare_heap_child(heap, i, child, result, k)
+            },
+            Continuation::SiftHeapDown(heap, i, child, result, k) => {
+                if val == Value::Boolean(false) || !heap.borrow_mut().swap(i, child) {
+                    return Ok(Trampoline::Run(result, *k))
+                }
+                sift_heap_down(heap, child, result, k)
+            },
+            #[cfg(feature = "io")]
+            Continuation::WalkFiles(files, procedure, k) => walk_files(files, procedure, k),
+            #[cfg(feature = "net")]
+            Continuation::RespondHttp(server, handler, count, k) => {
+                let response = match val.to_data() {
+                    Ok(node) => node,
+                    Err(_) => type_error!("Expected a response list or string: {:?}", val)
+                };
+                match server.borrow_mut().respond(&response) {
+                    Ok(_) => (),
+                    Err(e) => runtime_error!("{}", e)
+                }
+                answer_http_requests(server, handler, count, k)
+            },
             Continuation::Return => Ok(Trampoline::Land(val))
         }
     }
 }
 
+// Carry on with a call once its first element is evaluated: a special form or macro takes the rest unevaluated,
+// anything else is applied to the rest's values
+fn begin_func(val: Value, call: List, env: Rc<RefCell<Environment>>, k: Box<Continuation>) -> Result<Trampoline, RuntimeError> {
+    let rest = call.rest();
+            match val {
+                Value::SpecialForm(f) => {
+                    match f {
+                        SpecialForm::If => {
+                            let (condition, if_expr, else_expr) = try!(rest.unpack3());
+                            Ok(Trampoline::Bounce(condition, env.clone(), Continuation::EvaluateIf(if_expr, else_expr, env, k)))
+                        },
+                        SpecialForm::Define => {
+                            let (car, cdr) = shift_or_error!(rest, "Must provide at least two arguments to define");
+                            match car {
+                                Value::Symbol(name) => {
+                                    let val = try!(cdr.unpack1());
+                                    Ok(Trampoline::Bounce(val, env.clone(), Continuation::EvaluateDefine(name, env, k)))
+                                },
+                                Value::List(list) => {
+                                    let (caar, cdar) = shift_or_error!(list, "Must provide at least two params in first argument of define");
+                                    let name = try!(caar.as_symbol());
+
+                                    let arg_names = try!(cdar.into_iter().map(|v| v.as_symbol()).collect());
+                                    let body = cdr;
+                                    stats::count_allocations(1);
+                                    let f = Function::Scheme(Rc::new(arg_names), body, env.clone());
+
+                                    try!(env.borrow_mut().define(name, Value::Procedure(f)));
+                                    Ok(Trampoline::Run(null!(), *k))
+                                },
+                                _ => runtime_error!("Bad argument to define: {:?}", car)
+                            }
+                        },
+                        SpecialForm::Set => {
+                            let (name_raw, val) = try!(rest.unpack2());
+                            let name = try!(name_raw.as_symbol());
+                            Ok(Trampoline::Bounce(val, env.clone(), Continuation::EvaluateSet(name, env, k)))
+                        },
+                        SpecialForm::Lambda => {
+                            let (arg_defns_raw, body) = shift_or_error!(rest, "Must provide at least two arguments to lambda");
+                            let arg_defns = try!(arg_defns_raw.as_list());
+                            let arg_names = try!(arg_defns.into_iter().map(|v| v.as_symbol()).collect());
+
+                            stats::count_allocations(1);
+                            let f = Function::Scheme(Rc::new(arg_names), body, env);
+                            Ok(Trampoline::Run(Value::Procedure(f), *k))
+                        },
+                        SpecialForm::Let => {
+                            let (arg_defns_raw, body) = shift_or_error!(rest, "Must provide at least two arguments to let");
+                            let arg_defns = try!(arg_defns_raw.as_list());
+
+                            // Create a new, child environment for the procedure and define the arguments as local variables
+                            let proc_env = Environment::new_child(env.clone());
+
+                            // Iterate through the provided arguments, defining them
+                            if !arg_defns.is_empty() {
+                                let (first_defn, rest_defns) = shift_or_error!(arg_defns, "Error in let definiton");
+                                let (defn_key, defn_val) = try!(try!(first_defn.as_list()).unpack2());
+                                let name = try!(defn_key.as_symbol());
+                                Ok(Trampoline::Bounce(defn_val, env, Continuation::EvaluateLet(name, rest_defns, body, proc_env, k)))
+                            } else {
+                                // Let bindings were empty, just execute the body directly
+                                evaluate_expressions(body, env, k)
+                            }
+                        },
+                        SpecialForm::Letrec | SpecialForm::LetrecStar => {
+                            let star = f == SpecialForm::LetrecStar;
+                            let (arg_defns_raw, body) = shift_or_error!(rest, "Must provide at least two arguments to letrec");
+                            let arg_defns = try!(arg_defns_raw.as_list());
+
+                            // Every name is in scope, but unusable, until its value is ready
+                            let letrec_env = Environment::new_child(env);
+                            for defn in arg_defns.clone().into_iter() {
+                                let (defn_key, _) = try!(try!(defn.as_list()).unpack2());
+                                let name = try!(defn_key.as_symbol());
+                                letrec_env.borrow_mut().uninitialized.push(name);
+                            }
+                            evaluate_letrec(arg_defns, Vec::new(), body, star, letrec_env, k)
+                        },
+                        SpecialForm::Quote => {
+                            let expr = try!(rest.unpack1());
+                            Ok(Trampoline::Run(expr, *k))
+                        },
+                        SpecialForm::Quasiquote => {
+                            let expr = try!(rest.unpack1());
+                            match expr {
+                                Value::List(_) | Value::DottedList(..) => Ok(Trampoline::QuasiBounce(expr, env, *k)),
+                                _ => Ok(Trampoline::Run(expr, *k))
+                            }
+                        },
+                        SpecialForm::Eval => {
+                            let expr = try!(rest.unpack1());
+                            Ok(Trampoline::Bounce(expr, env.clone(), Continuation::ExecuteEval(env, k)))
+                        },
+                        SpecialForm::Apply => {
+                            let (func, args) = try!(rest.unpack2());
+                            Ok(Trampoline::Bounce(func, env.clone(), Continuation::EvaluateApplyArgs(args, env, k)))
+                        },
+                        SpecialForm::Begin => {
+                            match rest.shift() {
+                                Some((car, cdr)) => Ok(Trampoline::Bounce(car, env.clone(), Continuation::EvaluateExpressions(cdr, env, k))),
+                                None => runtime_error!("Must provide at least one argument to a begin statement")
+                            }
+                        },
+                        SpecialForm::And => {
+                            match rest.shift() {
+                                Some((car, cdr)) => Ok(Trampoline::Bounce(car, env.clone(), Continuation::EvaluateAnd(cdr, env, k))),
+                                None => Ok(Trampoline::Run(Value::Boolean(true), *k))
+                            }
+                        },
+                        SpecialForm::Or => {
+                            match rest.shift() {
+                                Some((car, cdr)) => Ok(Trampoline::Bounce(car, env.clone(), Continuation::EvaluateOr(cdr, env, k))),
+                                None => Ok(Trampoline::Run(Value::Boolean(false), *k))
+                            }
+                        },
+                        SpecialForm::CallCC => {
+                            let f = try!(rest.unpack1());
+                            Ok(Trampoline::Bounce(f, env, Continuation::ExecuteCallCC(k)))
+                        },
+                        SpecialForm::CallWithValues => {
+                            let (producer, consumer) = try!(rest.unpack2());
+                            Ok(Trampoline::Bounce(producer, env.clone(), Continuation::EvaluateConsumer(consumer, env, k)))
+                        },
+                        SpecialForm::DefineSyntaxRule => {
+                            let (defn, body) = try!(rest.unpack2());
+
+                            let (name, arg_names_raw) = match try!(defn.as_list()).shift() {
+                                Some((car, cdr)) => (try!(car.as_symbol()), cdr),
+                                None => runtime_error!("Must supply at least two params to first argument in define-syntax-rule")
+                            };
+
+                            let arg_names = try!(arg_names_raw.into_iter().map(|v| v.as_symbol()).collect());
+
+                            let m = Value::Macro(Rc::new(arg_names), Rc::new(body));
+                            try!(env.borrow_mut().define(name, m));
+                            Ok(Trampoline::Run(null!(), *k))
+                        },
+                        SpecialForm::Autoload => {
+                            let (path_raw, names) = shift_or_error!(rest, "Must provide at least two arguments to autoload");
+                            let path = match path_raw {
+                                Value::String(s) => s.to_string(),
+                                _ => runtime_error!("First argument to autoload must be a file name: {:?}", path_raw)
+                            };
+                            let root = Environment::get_root(env);
+                            for name in names {
+                                root.borrow_mut().autoloads.insert(try!(name.as_symbol()), path.clone());
+                            }
+                            Ok(Trampoline::Run(null!(), *k))
+                        },
+                        SpecialForm::Guard => {
+                            let (handler, body) = shift_or_error!(rest, "Must provide at least two arguments to guard");
+                            let (name_raw, clauses) = shift_or_error!(try!(handler.as_list()), "Must provide a name for the condition in guard");
+                            let name = try!(name_raw.as_symbol());
+
+                            // The body runs on a trampoline of its own, so that its errors can be caught here (like
+                            // load, this means a continuation captured inside can't escape the guard)
+                            match process(body, env.clone()) {
+                                Ok(val) => Ok(Trampoline::Run(val, *k)),
+                                Err(e) => {
+                                    let handler_env = Environment::new_child(env);
+                                    try!(handler_env.borrow_mut().define(name.clone(), e.to_condition()));
+                                    Ok(Trampoline::Bounce(try!(guard_clauses(clauses, name)), handler_env, *k))
+                                }
+                            }
+                        },
+                    }
+                },
+                Value::Macro(arg_names, body) => {
+                    let args = rest;
+                    if arg_names.len() != args.len() {
+                        arity_error!("Must supply exactly {} arguments to macro: {:?}", arg_names.len(), args);
+                    }
+
+                    // Create a lookup table for symbol substitutions
+                    let mut substitutions = HashMap::new();
+                    for (name, value) in arg_names.iter().zip(args) {
+                        substitutions.insert(name.clone(), value);
+                    }
+
+                    // Expand the macro
+                    let expanded = expand_macro(unwrap_or_clone(body), &substitutions);
+
+                    // Finished expanding macro, now evaluate the code manually
+                    Ok(Trampoline::Bounce(expanded, env, *k))
+                },
+                _ => evaluate_arguments(val, rest, List::Null, call, env, k)
+            }
+}
+
+// Arguments for a procedure written in Rust, which takes plain data
+fn data_arguments(args: List, to: &str) -> Result<Vec<Node>, RuntimeError> {
+    let mut values = Vec::new();
+    for v in args {
+        match v.to_data() {
+            Ok(node) => values.push(node),
+            Err(_) => type_error!("Can't pass {:?} to {}", v, to)
+        }
+    }
+    Ok(values)
+}
+
 fn apply(val: Value, args: List, k: Box<Continuation>) -> Result<Trampoline, RuntimeError> {
     match val {
         Value::Procedure(f) => {
             match f {
                 Function::Scheme(arg_names, body, func_env) => {
                     if arg_names.len() != args.len() {
-                        runtime_error!("Must supply exactly {} arguments to function: {:?}", arg_names.len(), args);
+                        arity_error!("Must supply exactly {} arguments to function: {:?}", arg_names.len(), args);
                     }
 
                     // Create a new, child environment for the procedure and define the arguments as local variables
                     let proc_env = Environment::new_child(func_env);
-                    for (name, value) in arg_names.into_iter().zip(args.into_iter()) {
-                        try!(proc_env.borrow_mut().define(name, value));
+                    for (name, value) in arg_names.iter().zip(args) {
+                        try!(proc_env.borrow_mut().define(name.clone(), value));
                     }
 
                     // Evaluate procedure body with new environment with procedure environment as parent
                     let inner_env = Environment::new_child(proc_env);
                     evaluate_expressions(body, inner_env, k)
                 },
+                Function::Native("heap-push!") | Function::Native("heap-pop!") => {
+                    heap_operation(&f, args, k)
+                },
+                #[cfg(feature = "io")]
+                Function::Native("directory-walk") => directory_walk(args, k),
+                #[cfg(feature = "net")]
+                Function::Native("serve-http") => serve_http(args, k),
                 Function::Native(g) => {
                     let res = try!(primitive(g, args));
                     Ok(Trampoline::Run(res, *k))
                 },
+                Function::Plugin(builtin) => {
+                    let values = try!(data_arguments(args, builtin.name()));
+                    match builtin.call(&values) {
+                        Ok(result) => Ok(Trampoline::Run(Value::from_node(&result), *k)),
+                        Err(e) => runtime_error!("{}", e)
+                    }
+                },
+                #[cfg(feature = "ffi")]
+                Function::Foreign(procedure) => {
+                    let values = try!(data_arguments(args, "a foreign procedure"));
+                    match procedure.call(&values) {
+                        Ok(result) => Ok(Trampoline::Run(Value::from_node(&result), *k)),
+                        Err(e) => runtime_error!("{}", e)
+                    }
+                },
             }
         },
         Value::Continuation(k_prime) => {
             Ok(Trampoline::Run(args.to_value(), *k_prime))
         },
         _ => {
-            runtime_error!("Don't know how to apply: {:?}", val)
+            type_error!("Don't know how to apply: {:?}", val)
         }
     }
 }
 
+// Whether a condition counts as true: anything but #f, unless booleans are strict and it has to be #t
+fn is_true(form: &str, condition: &Value) -> Result<bool, RuntimeError> {
+    match *condition {
+        Value::Boolean(b) => Ok(b),
+        _ if strict::booleans() => type_error!("Expected a boolean condition in {}: {:?}", form, condition),
+        _ => Ok(true)
+    }
+}
+
+// The clauses of a guard as one expression: (test body...) clauses become nested ifs, as in cond, else always
+// matches, and if nothing does the condition is raised again
+fn guard_clauses(clauses: List, name: String) -> Result<Value, RuntimeError> {
+    let mut expr = Value::from_vec(vec![Value::Symbol("raise".to_string()), Value::Symbol(name)]);
+    for clause in clauses.to_vec().into_iter().rev() {
+        let (test, body) = shift_or_error!(try!(clause.as_list()), "Empty clause in guard");
+        let sequence = body.clone().unshift(Value::Symbol("begin".to_string())).to_value();
+        expr = match test {
+            Value::Symbol(ref s) if s == "else" => sequence,
+            _ if body.is_empty() => Value::from_vec(vec![Value::Symbol("or".to_string()), test, expr]),
+            _ => Value::from_vec(vec![Value::Symbol("if".to_string()), test, sequence, expr])
+        };
+    }
+    Ok(expr)
+}
+
 fn expand_macro(value: Value, substitutions: &HashMap<String,Value>) -> Value {
     match value {
         Value::Symbol(s) => {
@@ -617,6 +1581,54 @@ fn expand_macro(value: Value, substitutions: &HashMap<String,Value>) -> Value {
     }
 }
 
+// Look up an unbound name in the autoload registry, and if a library provides it, load that library into the root
+// environment and try again
+fn autoload(env: &Rc<RefCell<Environment>>, name: &String) -> Result<Option<Value>, RuntimeError> {
+    let root = Environment::get_root(env.clone());
+    let path = match root.borrow().autoloads.get(name) {
+        Some(path) => path.clone(),
+        None => return Ok(None)
+    };
+
+    // Set aside every name provided by the same library while it loads, so it's only loaded once, and put them
+    // back if loading fails so that a later reference can try again
+    let pending: Vec<(String, String)> = root.borrow().autoloads.iter().filter(|&(_, p)| *p == path)
+        .map(|(n, p)| (n.clone(), p.clone())).collect();
+    root.borrow_mut().autoloads.retain(|_, p| *p != path);
+    match load_file(&path, root.clone()) {
+        Ok(_) => (),
+        Err(e) => {
+            root.borrow_mut().autoloads.extend(pending);
+            return Err(e)
+        }
+    }
+    let found = root.borrow().get(name);
+    Ok(found)
+}
+
+fn load_file(name: &str, env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    let path = search_path::resolve(Path::new(""), name);
+    watch::record(&path);
+    let mut contents = String::new();
+    match File::open(&path).and_then(|mut f| f.read_to_string(&mut contents)) {
+        Ok(_) => (),
+        Err(e) => file_error!("Couldn't load {}: {}", path.display(), e)
+    }
+    let tokens = match lexer::tokenize(&contents) {
+        Ok(tokens) => tokens,
+        Err(e) => read_error!("{}", e)
+    };
+    let nodes = match parse(&tokens) {
+        Ok(nodes) => nodes,
+        Err(e) => read_error!("{}", e)
+    };
+    let nodes = match expand::expand_includes(nodes, Some(&path), &ReaderConfig::default()) {
+        Ok(nodes) => nodes,
+        Err(e) => raise!(e.kind(), "{}", e)
+    };
+    process(List::from_nodes(&nodes), env)
+}
+
 fn evaluate_expressions(exprs: List, env: Rc<RefCell<Environment>>, k: Box<Continuation>) -> Result<Trampoline, RuntimeError> {
     match exprs.shift() {
         Some((car, cdr)) => Ok(Trampoline::Bounce(car, env.clone(), Continuation::EvaluateExpressions(cdr, env, k))),
@@ -624,50 +1636,385 @@ fn evaluate_expressions(exprs: List, env: Rc<RefCell<Environment>>, k: Box<Conti
     }
 }
 
+// Evaluate the next letrec binding, or once they're all done, bind whatever letrec held back and run the body. letrec
+// only binds the names after evaluating every value, so a value can use the others only from inside a lambda;
+// letrec* binds each name as soon as its value is ready.
+fn evaluate_letrec(defns: List, values: Vec<(String, Value)>, body: List, star: bool, env: Rc<RefCell<Environment>>, k: Box<Continuation>) -> Result<Trampoline, RuntimeError> {
+    match defns.shift() {
+        Some((defn, rest)) => {
+            let (defn_key, defn_val) = try!(try!(defn.as_list()).unpack2());
+            let name = try!(defn_key.as_symbol());
+            Ok(Trampoline::Bounce(defn_val, env.clone(), Continuation::EvaluateLetrec(name, rest, values, body, star, env, k)))
+        },
+        None => {
+            for (name, val) in values.into_iter() {
+                try!(env.borrow_mut().initialize(name, val));
+            }
+            let inner_env = Environment::new_child(env);
+            evaluate_expressions(body, inner_env, k)
+        }
+    }
+}
+
+// Quasiquote the items left in a list, after the ones (reversed) in acc: each is quasiquoted in turn, except that
+// (unquote-splicing X) evaluates X and adds the items of the list it returns, and the list ends in the value of X
+// if the last two items are unquote and X, which is how `(a . ,X) reads
+fn quasiquote_items(items: List, acc: List, env: Rc<RefCell<Environment>>, k: Box<Continuation>) -> Result<Trampoline, RuntimeError> {
+    if !acc.is_empty() && items.len() == 2 {
+        match items.iter().next() {
+            Some(Value::Symbol(s)) if s == "unquote" => {
+                let (_, expr) = try!(items.unpack2());
+                return Ok(Trampoline::Bounce(expr, env, Continuation::EndQuasiquoting(acc, k)))
+            },
+            _ => ()
+        }
+    }
+    match items.shift() {
+        Some((Value::List(item), rest)) => match item.clone().shift() {
+            Some((Value::Symbol(ref s), args)) if s == "unquote-splicing" => {
+                let expr = try!(args.unpack1());
+                Ok(Trampoline::Bounce(expr, env.clone(), Continuation::SpliceQuasiquoting(rest, acc, env, k)))
+            },
+            _ => Ok(Trampoline::QuasiBounce(Value::List(item), env.clone(), Continuation::ContinueQuasiquoting(rest, acc, env, k)))
+        },
+        Some((item, rest)) => Ok(Trampoline::QuasiBounce(item, env.clone(), Continuation::ContinueQuasiquoting(rest, acc, env, k))),
+        None => Ok(Trampoline::Run(acc.reverse().to_value(), *k))
+    }
+}
+
+// The items followed by tail, which continues the list if it's a list itself
+fn with_tail(items: List, tail: Value) -> Value {
+    match tail {
+        Value::List(rest) => items.append(rest).to_value(),
+        Value::DottedList(rest, tail) => Value::DottedList(items.append(rest), tail),
+        _ if items.is_empty() => tail,
+        tail => Value::DottedList(items, Rc::new(tail))
+    }
+}
+
+// The names evaluate_atom resolves to special forms, for suggesting in place of a misspelled name
+const SPECIAL_FORM_NAMES: &[&str] = &["if", "define", "set!", "lambda", "λ", "let", "letrec", "letrec*",
+                                                     "quote", "quasiquote", "eval", "apply", "begin", "and", "or", "call/cc",
+                                                     "call-with-values", "define-syntax-rule", "autoload", "guard"];
+
+// The error for a name bound nowhere, mentioning any bound names (or aliases) it's probably a typo of
+fn identifier_not_found(name: &str, env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    let names = env.borrow().names();
+    let candidates = names.iter().map(|n| n.as_str())
+                          .chain(SPECIAL_FORM_NAMES.iter().cloned())
+                          .chain(aliases::ALIASES.iter().map(|a| a.name));
+    let suggestions = text::closest(name, candidates);
+    if suggestions.is_empty() {
+        runtime_error!("Identifier not found: {}", name)
+    } else {
+        runtime_error!("Identifier not found: {} (did you mean {}?)", name, suggestions.join(", "))
+    }
+}
+
+// Evaluate anything but a list: symbols are looked up (special form names resolve to the forms themselves, so they
+// can't be redefined), and everything else evaluates to itself
+fn evaluate_atom(a: Value, env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+    match a {
+        Value::Symbol(ref s) => {
+            let val = match s.as_ref() {
+                "if"     => Value::SpecialForm(SpecialForm::If),
+                "define" => Value::SpecialForm(SpecialForm::Define),
+                "set!"   => Value::SpecialForm(SpecialForm::Set),
+                "lambda" => Value::SpecialForm(SpecialForm::Lambda),
+                "λ"      => Value::SpecialForm(SpecialForm::Lambda),
+                "let"    => Value::SpecialForm(SpecialForm::Let),
+                "letrec" => Value::SpecialForm(SpecialForm::Letrec),
+                "letrec*" => Value::SpecialForm(SpecialForm::LetrecStar),
+                "quote"  => Value::SpecialForm(SpecialForm::Quote),
+                "quasiquote" => Value::SpecialForm(SpecialForm::Quasiquote),
+                "eval"   => Value::SpecialForm(SpecialForm::Eval),
+                "apply"  => Value::SpecialForm(SpecialForm::Apply),
+                "begin"  => Value::SpecialForm(SpecialForm::Begin),
+                "and"    => Value::SpecialForm(SpecialForm::And),
+                "or"     => Value::SpecialForm(SpecialForm::Or),
+                "call/cc" => Value::SpecialForm(SpecialForm::CallCC),
+                "call-with-values" => Value::SpecialForm(SpecialForm::CallWithValues),
+                "define-syntax-rule" => Value::SpecialForm(SpecialForm::DefineSyntaxRule),
+                "autoload" => Value::SpecialForm(SpecialForm::Autoload),
+                "guard"  => Value::SpecialForm(SpecialForm::Guard),
+                _ => {
+                    let found = env.borrow().get(s);
+                    match found {
+                        Some(Value::Procedure(Function::Native(name))) if capabilities::is_guarded(name) => {
+                            // checked when the builtin is looked up, since it runs without the environment
+                            let root = Environment::get_root(env.clone());
+                            let granted = root.borrow().capabilities;
+                            match granted.check(name) {
+                                Ok(_) => Value::Procedure(Function::Native(name)),
+                                Err(e) => runtime_error!("{}", e)
+                            }
+                        },
+                        Some(v) => v,
+                        None if env.borrow().is_uninitialized(s) => {
+                            runtime_error!("{} was used before its letrec initialization finished", s)
+                        },
+                        None => {
+                            match try!(autoload(env, s)) {
+                                Some(v) => v,
+                                None => {
+                                    match aliases::find(s) {
+                                        Some(alias) => try!(evaluate_atom(Value::Symbol(alias.resolve().to_string()), env)),
+                                        None => return identifier_not_found(s, env)
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            };
+            Ok(val)
+        },
+        Value::DottedList(..) => runtime_error!("Can't evaluate a dotted list: {:?}", a),
+        _ => Ok(a)
+    }
+}
+
+// Evaluate the remaining arguments of a function call and then apply it. Arguments that aren't lists are evaluated
+// right here instead of bouncing, which saves a trip through the trampoline (and an environment clone) per argument.
+fn evaluate_arguments(f: Value, mut rest: List, mut acc: List, call: List, env: Rc<RefCell<Environment>>, k: Box<Continuation>) -> Result<Trampoline, RuntimeError> {
+    loop {
+        match rest.shift() {
+            Some((car @ Value::List(_), cdr)) => return Ok(Trampoline::Bounce(car, env.clone(), Continuation::EvaluateFunc(f, cdr, acc, call, env, k))),
+            Some((car, cdr)) => {
+                stats::count_step();
+                acc = acc.unshift(try!(evaluate_atom(car, &env)));
+                rest = cdr;
+            },
+            None => return apply_reversed(f, acc, k)
+        }
+    }
+}
+
+// Like apply, but with the arguments in reverse order, as evaluate_arguments accumulates them
+fn apply_reversed(val: Value, reversed: List, k: Box<Continuation>) -> Result<Trampoline, RuntimeError> {
+    match val {
+        Value::Procedure(Function::Scheme(arg_names, body, func_env)) => {
+            if arg_names.len() != reversed.len() {
+                arity_error!("Must supply exactly {} arguments to function: {:?}", arg_names.len(), reversed.reverse());
+            }
+
+            // Bind from the last argument back, which saves reversing the list first
+            let proc_env = Environment::new_child(func_env);
+            for (name, value) in arg_names.iter().rev().zip(reversed) {
+                try!(proc_env.borrow_mut().define(name.clone(), value));
+            }
+
+            let inner_env = Environment::new_child(proc_env);
+            evaluate_expressions(body, inner_env, k)
+        },
+        _ => apply(val, reversed.reverse(), k)
+    }
+}
+
+// heap-push! and heap-pop!, which can't be primitives since they call the heap's ordering procedure
+fn heap_operation(f: &Function, args: List, k: Box<Continuation>) -> Result<Trampoline, RuntimeError> {
+    if *f == Function::Native("heap-push!") {
+        if args.len() != 2 {
+            arity_error!("Must supply exactly two arguments to heap-push!: {:?}", args);
+        }
+        match try!(args.unpack2()) {
+            (Value::Heap(heap), v) => {
+                let i = {
+                    let mut h = heap.borrow_mut();
+                    h.items.push(v);
+                    h.items.len() - 1
+                };
+                sift_heap_up(heap, i, k)
+            },
+            (h, _) => type_error!("Expected a heap value: {:?}", h)
+        }
+    } else {
+        if args.len() != 1 {
+            arity_error!("Must supply exactly one argument to heap-pop!: {:?}", args);
+        }
+        match try!(args.unpack1()) {
+            Value::Heap(heap) => {
+                if heap.borrow().items.is_empty() {
+                    runtime_error!("Can't pop from an empty heap")
+                }
+                // the last element takes the root's place, then moves down past every child that comes out before it
+                let result = heap.borrow_mut().items.swap_remove(0);
+                sift_heap_down(heap, 0, result, k)
+            },
+            h => type_error!("Expected a heap value: {:?}", h)
+        }
+    }
+}
+
+// Ask the heap's ordering procedure whether the element at index a comes out before the one at b, continuing with
+// k. If the procedure changed the heap so the indexes are gone, nothing needs moving, which #f says.
+fn compare_heap_elements(heap: &Rc<RefCell<Heap>>, a: usize, b: usize, k: Continuation) -> Result<Trampoline, RuntimeError> {
+    let (less, args) = {
+        let h = heap.borrow();
+        match (h.items.get(a), h.items.get(b)) {
+            (Some(x), Some(y)) => (h.less.clone(), List::from_vec(vec![x.clone(), y.clone()])),
+            _ => return Ok(Trampoline::Run(Value::Boolean(false), k))
+        }
+    };
+    apply(less, args, Box::new(k))
+}
+
+// Move the element at index i up past every parent it comes out before
+fn sift_heap_up(heap: Rc<RefCell<Heap>>, i: usize, k: Box<Continuation>) -> Result<Trampoline, RuntimeError> {
+    if i == 0 {
+        return Ok(Trampoline::Run(null!(), *k))
+    }
+    let parent = (i - 1) / 2;
+    let k = Continuation::SiftHeapUp(heap.clone(), i, k);
+    compare_heap_elements(&heap, i, parent, k)
+}
+
+// Move the element at index i down past every child that comes out before it, then carry on with result
+fn sift_heap_down(heap: Rc<RefCell<Heap>>, i: usize, result: Value, k: Box<Continuation>) -> Result<Trampoline, RuntimeError> {
+    let (left, right) = (2 * i + 1, 2 * i + 2);
+    let len = heap.borrow().items.len();
+    if left >= len {
+        Ok(Trampoline::Run(result, *k))
+    } else if right < len {
+        let k = Continuation::ChooseHeapChild(heap.clone(), i, result, k);
+        compare_heap_elements(&heap, right, left, k)
+    } else {
+        compare_heap_child(heap, i, left, result, k)
+    }
+}
+
+fn compare_heap_child(heap: Rc<RefCell<Heap>>, i: usize, child: usize, result: Value, k: Box<Continuation>) -> Result<Trampoline, RuntimeError> {
+    let k = Continuation::SiftHeapDown(heap.clone(), i, child, result, k);
+    compare_heap_elements(&heap, child, i, k)
+}
+
+// (directory-walk path proc), which can't be a primitive since it calls (proc file) for every file under path
+#[cfg(feature = "io")]
+fn directory_walk(args: List, k: Box<Continuation>) -> Result<Trampoline, RuntimeError> {
+    if args.len() != 2 {
+        arity_error!("Must supply exactly two arguments to directory-walk: {:?}", args);
+    }
+    let (root, procedure) = try!(args.unpack2());
+    let root = try!(root.as_string());
+    let procedure = match procedure {
+        f @ Value::Procedure(_) => f,
+        v => type_error!("Expected a procedure value: {:?}", v)
+    };
+    let files = match files::walk(&root) {
+        Ok(files) => files,
+        Err(e) => file_error!("{}", e)
+    };
+    walk_files(List::from_vec(files.into_iter().map(|f| Value::String(Rc::from(f))).collect()), procedure, k)
+}
+
+// Call procedure on the first of files, then go on to the rest
+#[cfg(feature = "io")]
+fn walk_files(files: List, procedure: Value, k: Box<Continuation>) -> Result<Trampoline, RuntimeError> {
+    match files.shift() {
+        Some((file, rest)) => {
+            let k = Continuation::WalkFiles(rest, procedure.clone(), k);
+            apply(procedure, List::Null.unshift(file), Box::new(k))
+        },
+        None => Ok(Trampoline::Run(null!(), *k))
+    }
+}
+
+// (serve-http port handler [count]), which can't be a primitive since it calls the handler for each request. port is
+// a port number on localhost or a "host:port" string.
+#[cfg(feature = "net")]
+fn serve_http(args: List, k: Box<Continuation>) -> Result<Trampoline, RuntimeError> {
+    if args.len() < 2 || args.len() > 3 {
+        arity_error!("Must supply two or three arguments to serve-http: {:?}", args);
+    }
+    let mut values: Vec<Value> = args.into_iter().collect();
+    let count = if values.len() == 3 {
+        match values.pop().unwrap() {
+            Value::Integer(n) if n >= 0 => Some(n),
+            v => type_error!("Expected a non-negative integer value: {:?}", v)
+        }
+    } else {
+        None
+    };
+    let handler = match values.pop().unwrap() {
+        f @ Value::Procedure(_) => f,
+        v => type_error!("Expected a procedure value: {:?}", v)
+    };
+    let address = match values.pop().unwrap() {
+        Value::Integer(port) if (0..=65535).contains(&port) => format!("127.0.0.1:{}", port),
+        Value::String(address) => address.to_string(),
+        v => type_error!("Expected a port number or \"host:port\" string: {:?}", v)
+    };
+    let server = match http::Server::bind(&address) {
+        Ok(server) => Rc::new(RefCell::new(server)),
+        Err(e) => runtime_error!("{}", e)
+    };
+    answer_http_requests(server, handler, count, k)
+}
+
+// Wait for the next request and pass it to the handler, unless count says they've all been answered
+#[cfg(feature = "net")]
+fn answer_http_requests(server: Rc<RefCell<http::Server>>, handler: Value, count: Option<i64>, k: Box<Continuation>) -> Result<Trampoline, RuntimeError> {
+    if count == Some(0) {
+        return Ok(Trampoline::Run(null!(), *k))
+    }
+    let request = match server.borrow_mut().accept() {
+        Ok(request) => Value::from_node(&request),
+        Err(e) => runtime_error!("{}", e)
+    };
+    let k = Continuation::RespondHttp(server.clone(), handler.clone(), count.map(|n| n - 1), k);
+    apply(handler, List::Null.unshift(request), Box::new(k))
+}
+
+// The Scheme handlers on-signal set on this thread
+#[cfg(all(unix, feature = "process"))]
+thread_local!(static SIGNAL_HANDLERS: RefCell<Vec<(signals::Signal, Value)>> = const { RefCell::new(Vec::new()) });
+
+// Call the handlers of the signals that have arrived, each in a trampoline of its own. An error in one stops the
+// program like any other.
+#[cfg(all(unix, feature = "process"))]
+fn handle_signals() -> Result<(), RuntimeError> {
+    let handlers = SIGNAL_HANDLERS.with(|h| h.borrow().clone());
+    for (signal, handler) in handlers.into_iter() {
+        if signals::take(signal) {
+            let name = Value::Symbol(signals::name(signal).to_string());
+            try!(run(try!(apply(handler, List::Null.unshift(name), Box::new(Continuation::Return)))));
+        }
+    }
+    Ok(())
+}
+
 fn process(exprs: List, env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
     if exprs.len() == 0 {
         return Ok(null!());
     }
 
-    let mut b = try!(evaluate_expressions(exprs, env, Box::new(Continuation::Return)));
+    let b = try!(evaluate_expressions(exprs, env, Box::new(Continuation::Return)));
+    run(b)
+}
+
+// Bounce b until it lands. Between bounces is a safe point, where the handlers of any signals that have arrived are
+// run to completion before carrying on.
+fn run(mut b: Trampoline) -> Result<Value, RuntimeError> {
     loop {
+        stats::count_step();
+        #[cfg(all(unix, feature = "process"))]
+        {
+            if signals::pending() {
+                try!(handle_signals());
+            }
+        }
         match b {
             // Bounce is the usual execution path. It's used for pretty much everything.
             // Special forms are caught here instead of in env so that they can't be redefined in env.
             Trampoline::Bounce(a, env, k) => {
                 b = match a {
                     Value::List(list) => {
-                        match list.shift() {
-                            Some((car, cdr)) => Trampoline::Bounce(car, env.clone(), Continuation::BeginFunc(cdr, env, Box::new(k))),
+                        match list.clone().shift() {
+                            Some((car, _)) => Trampoline::Bounce(car, env.clone(), Continuation::BeginFunc(list, env, Box::new(k))),
                             None => runtime_error!("Can't apply an empty list as a function")
                         }
                     },
-                    Value::Symbol(ref s) => {
-                        let val = match s.as_ref() {
-                            "if"     => Value::SpecialForm(SpecialForm::If),
-                            "define" => Value::SpecialForm(SpecialForm::Define),
-                            "set!"   => Value::SpecialForm(SpecialForm::Set),
-                            "lambda" => Value::SpecialForm(SpecialForm::Lambda),
-                            "λ"      => Value::SpecialForm(SpecialForm::Lambda),
-                            "let"    => Value::SpecialForm(SpecialForm::Let),
-                            "quote"  => Value::SpecialForm(SpecialForm::Quote),
-                            "quasiquote" => Value::SpecialForm(SpecialForm::Quasiquote),
-                            "eval"   => Value::SpecialForm(SpecialForm::Eval),
-                            "apply"  => Value::SpecialForm(SpecialForm::Apply),
-                            "begin"  => Value::SpecialForm(SpecialForm::Begin),
-                            "and"    => Value::SpecialForm(SpecialForm::And),
-                            "or"     => Value::SpecialForm(SpecialForm::Or),
-                            "call/cc" => Value::SpecialForm(SpecialForm::CallCC),
-                            "define-syntax-rule" => Value::SpecialForm(SpecialForm::DefineSyntaxRule),
-                            _ => {
-                                match env.borrow().get(s) {
-                                    Some(v) => v,
-                                    None => runtime_error!("Identifier not found: {}", s)
-                                }
-                            }
-                        };
-                        try!(k.run(val))
-                    },
+                    Value::Symbol(_) | Value::DottedList(..) => try!(k.run(try!(evaluate_atom(a, &env)))),
                     _ => try!(k.run(a))
                 }
             },
@@ -683,14 +2030,18 @@ fn process(exprs: List, env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeE
                                         let expr = try!(cdr.unpack1());
                                         Trampoline::Bounce(expr, env, k)
                                     },
-                                    _ => {
-                                        Trampoline::QuasiBounce(car, env.clone(), Continuation::ContinueQuasiquoting(cdr, List::Null, env, Box::new(k)))
-                                    }
+                                    Value::Symbol(ref s) if s == "unquote-splicing" => {
+                                        runtime_error!("Can't use unquote-splicing outside a quasiquoted list: {:?}", Value::List(cdr.unshift(car.clone())))
+                                    },
+                                    _ => try!(quasiquote_items(cdr.unshift(car), List::Null, env, Box::new(k)))
                                 }
                             },
                             None => try!(k.run(null!()))
                         }
                     },
+                    Value::DottedList(items, tail) => {
+                        try!(quasiquote_items(items, List::Null, env, Box::new(Continuation::AttachTail(tail, Box::new(k)))))
+                    },
                     _ => try!(k.run(a))
                 }
             },
@@ -711,7 +2062,13 @@ fn process(exprs: List, env: Rc<RefCell<Environment>>) -> Result<Value, RuntimeE
 #[derive(PartialEq)]
 struct Environment {
     parent: Option<Rc<RefCell<Environment>>>,
-    values: HashMap<String, Value>,
+    values: Bindings<Value>,
+    // Only used in the root environment: maps not-yet-loaded names to the file that defines them
+    autoloads: HashMap<String, String>,
+    // Names bound by a letrec whose values are still being evaluated; reading or setting one is an error
+    uninitialized: Vec<String>,
+    // Only used in the root environment: what the interpreter's builtins may reach outside the process for
+    capabilities: Capabilities,
 }
 
 impl fmt::Debug for Environment {
@@ -725,31 +2082,21 @@ impl fmt::Debug for Environment {
 
 impl Environment {
     fn new_root() -> Result<Rc<RefCell<Environment>>, RuntimeError> {
-        let mut env = Environment { parent: None, values: HashMap::new() };
-        try!(env.define("+".to_string(), Value::Procedure(Function::Native("+"))));
-        try!(env.define("-".to_string(), Value::Procedure(Function::Native("-"))));
-        try!(env.define("*".to_string(), Value::Procedure(Function::Native("*"))));
-        try!(env.define("/".to_string(), Value::Procedure(Function::Native("/"))));
-        try!(env.define("<".to_string(), Value::Procedure(Function::Native("<"))));
-        try!(env.define(">".to_string(), Value::Procedure(Function::Native(">"))));
-        try!(env.define("=".to_string(), Value::Procedure(Function::Native("="))));
-        try!(env.define("null?".to_string(), Value::Procedure(Function::Native("null?"))));
-        try!(env.define("list".to_string(), Value::Procedure(Function::Native("list"))));
-        try!(env.define("car".to_string(), Value::Procedure(Function::Native("car"))));
-        try!(env.define("cdr".to_string(), Value::Procedure(Function::Native("cdr"))));
-        try!(env.define("cons".to_string(), Value::Procedure(Function::Native("cons"))));
-        try!(env.define("append".to_string(), Value::Procedure(Function::Native("append"))));
-        try!(env.define("error".to_string(), Value::Procedure(Function::Native("error"))));
-        try!(env.define("write".to_string(), Value::Procedure(Function::Native("write"))));
-        try!(env.define("display".to_string(), Value::Procedure(Function::Native("display"))));
-        try!(env.define("displayln".to_string(), Value::Procedure(Function::Native("displayln"))));
-        try!(env.define("print".to_string(), Value::Procedure(Function::Native("print"))));
-        try!(env.define("newline".to_string(), Value::Procedure(Function::Native("newline"))));
+        let mut env = Environment { parent: None, values: Bindings::new(), autoloads: HashMap::new(), uninitialized: Vec::new(),
+                                    capabilities: Capabilities::default() };
+        for group in [CORE_PRIMITIVES, MATH_PRIMITIVES, IO_PRIMITIVES, NET_PRIMITIVES, PROCESS_PRIMITIVES, PARALLEL_PRIMITIVES, SQLITE_PRIMITIVES,
+                      FFI_PRIMITIVES].iter() {
+            for name in group.iter() {
+                try!(env.define(name.to_string(), Value::Procedure(Function::Native(name))));
+            }
+        }
         Ok(Rc::new(RefCell::new(env)))
     }
 
     fn new_child(parent: Rc<RefCell<Environment>>) -> Rc<RefCell<Environment>> {
-        let env = Environment { parent: Some(parent), values: HashMap::new() };
+        stats::count_allocations(1);
+        let env = Environment { parent: Some(parent), values: Bindings::new(), autoloads: HashMap::new(), uninitialized: Vec::new(),
+                                capabilities: Capabilities::default() };
         Rc::new(RefCell::new(env))
     }
 
@@ -761,6 +2108,11 @@ impl Environment {
         if self.values.contains_key(&key) {
             runtime_error!("Duplicate define: {:?}", key)
         } else {
+            if self.parent.is_some() && warning::enabled() {
+                if let Some(Value::Procedure(Function::Native(_))) = self.get(&key) {
+                    warning::warn_once(format!("Local variable {} shadows a builtin procedure", key))
+                }
+            }
             self.values.insert(key, value);
             Ok(())
         }
@@ -768,7 +2120,9 @@ impl Environment {
 
     // Set a variable to a value, at any level in the env, or throw a runtime error if it isn't defined at all
     fn set(&mut self, key: String, value: Value) -> Result<(), RuntimeError>  {
-        if self.values.contains_key(&key) {
+        if self.uninitialized.contains(&key) {
+            runtime_error!("Can't set! {} before its letrec initialization finished", key)
+        } else if self.values.contains_key(&key) {
             self.values.insert(key, value);
             Ok(())
         } else {
@@ -780,9 +2134,11 @@ impl Environment {
         }
     }
 
+    // Cloning the value is cheap, since anything big inside it is reference counted
     fn get(&self, key: &String) -> Option<Value> {
         match self.values.get(key) {
             Some(val) => Some(val.clone()),
+            None if self.uninitialized.contains(key) => None,
             None => {
                 // Recurse up the environment tree until a value is found or the end is reached
                 match self.parent {
@@ -793,6 +2149,36 @@ impl Environment {
         }
     }
 
+    // Whether key refers to a letrec variable that doesn't have its value yet
+    fn is_uninitialized(&self, key: &String) -> bool {
+        if self.values.contains_key(key) {
+            false
+        } else if self.uninitialized.contains(key) {
+            true
+        } else {
+            match self.parent {
+                Some(ref parent) => parent.borrow().is_uninitialized(key),
+                None => false
+            }
+        }
+    }
+
+    // Give a letrec variable its value, making it usable
+    fn initialize(&mut self, key: String, value: Value) -> Result<(), RuntimeError> {
+        self.uninitialized.retain(|k| *k != key);
+        self.define(key, value)
+    }
+
+    // Every name visible from here, including ones a library would define when autoloaded
+    fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.values.iter().map(|(k, _)| k.clone()).collect();
+        names.extend(self.autoloads.keys().cloned());
+        if let Some(ref parent) = self.parent {
+            names.extend(parent.borrow().names());
+        }
+        names
+    }
+
     fn get_root(env_ref: Rc<RefCell<Environment>>) -> Rc<RefCell<Environment>> {
         let env = env_ref.borrow();
         match env.parent {
@@ -802,158 +2188,1427 @@ impl Environment {
     }
 }
 
+// Native procedures, grouped by the crate feature that compiles them in
+const CORE_PRIMITIVES: &[&str] = &["null?", "list", "car", "cdr", "cons", "append", "reverse",
+                                                    "error", "warn", "log-debug", "log-info", "log-warn", "log-error", "eof-object", "eof-object?",
+                                                    "raise", "assertion-violation", "error-object-message", "error?",
+                                                    "file-error?", "read-error?", "assertion-violation?", "arity-error?",
+                                                    "type-error?", "gensym", "hash", "hash-by-identity", "eqv?", "features",
+                                                    "keyword?", "keyword->string", "string->keyword", "values",
+                                                    "char?", "char->integer", "integer->char", "char-alphabetic?", "char-numeric?",
+                                                    "char-whitespace?", "char-upcase", "char-downcase",
+                                                    "box", "box?", "unbox", "set-box!",
+                                                    "make-queue", "queue?", "queue-length", "enqueue!", "dequeue!", "queue->list",
+                                                    "make-set", "set?", "set-add!", "set-contains?", "set-union", "set-intersection",
+                                                    "set->list", "make-heap", "heap?", "heap-size", "heap-push!", "heap-pop!", "heap-peek",
+                                                    "string-foldcase", "string-length", "substring", "string-copy",
+                                                    "string->list", "list->string", "display->string",
+                                                    "vector?", "vector", "vector-length", "vector-ref", "vector->list", "list->vector",
+                                                    "vector-copy", "vector-fill!", "vector-copy!", "vector-append",
+                                                    "vector-push!", "vector-pop!",
+                                                    "bytevector?", "bytevector", "bytevector-length", "bytevector-u8-ref",
+                                                    "bytevector-copy",
+                                                    "value->cbor", "cbor->value", "document",
+                                                    "length+", "list-copy", "last-pair", "proper-list?", "circular-list?",
+                                                    "dotted-list?", "tree-size", "depth",
+                                                    "string-ci=?", "string-ci<?", "string-ci>?", "string-ci<=?", "string-ci>=?",
+                                                    "+", "-", "*", "/", "<", ">", "="];
+
+#[cfg(feature = "math")]
+const MATH_PRIMITIVES: &[&str] = &["random", "floor/", "truncate/", "exact-integer-sqrt", "number->string"];
+#[cfg(not(feature = "math"))]
+const MATH_PRIMITIVES: &[&str] = &[];
+
+#[cfg(feature = "io")]
+const IO_PRIMITIVES: &[&str] = &["write", "write-simple", "write-shared", "display", "displayln", "print", "newline",
+                                                  "read", "read-char", "write-canonical", "read-canonical", "csv-read", "csv-write",
+                                                  "directory-walk", "glob", "path-join", "path-directory", "path-filename",
+                                                  "path-extension", "path-absolute?", "expand-user-path",
+                                                  "printer-option", "set-printer-option!"];
+#[cfg(not(feature = "io"))]
+const IO_PRIMITIVES: &[&str] = &[];
+
+#[cfg(feature = "net")]
+const NET_PRIMITIVES: &[&str] = &["http-get", "http-post", "serve-http"];
+#[cfg(not(feature = "net"))]
+const NET_PRIMITIVES: &[&str] = &[];
+
+#[cfg(all(unix, feature = "process"))]
+const PROCESS_PRIMITIVES: &[&str] = &["on-signal"];
+#[cfg(not(all(unix, feature = "process")))]
+const PROCESS_PRIMITIVES: &[&str] = &[];
+
+#[cfg(feature = "parallel")]
+const PARALLEL_PRIMITIVES: &[&str] = &["par-map"];
+#[cfg(not(feature = "parallel"))]
+const PARALLEL_PRIMITIVES: &[&str] = &[];
+
+#[cfg(feature = "sqlite")]
+const SQLITE_PRIMITIVES: &[&str] = &["sqlite-open", "sqlite-exec", "sqlite-query"];
+#[cfg(not(feature = "sqlite"))]
+const SQLITE_PRIMITIVES: &[&str] = &[];
+
+#[cfg(feature = "ffi")]
+const FFI_PRIMITIVES: &[&str] = &["load-foreign-library", "foreign-procedure"];
+#[cfg(not(feature = "ffi"))]
+const FFI_PRIMITIVES: &[&str] = &[];
+
+// The start and end of the range that a primitive's optional index arguments give in a sequence of length elements
+fn range(indexes: List, length: usize, kind: &str) -> Result<(usize, usize), RuntimeError> {
+    let mut evaluated = Vec::with_capacity(2);
+    for v in indexes {
+        match v {
+            Value::Integer(i) if i >= 0 => evaluated.push(i as usize),
+            v => type_error!("Expected a non-negative integer value: {:?}", v)
+        }
+    }
+    match ranges::range(&evaluated, length, kind) {
+        Ok(range) => Ok(range),
+        Err(e) => runtime_error!("{}", e)
+    }
+}
+
+// The part of the string argument of a primitive that takes a string and optional start and end indexes
+fn string_range(name: &str, args: List) -> Result<String, RuntimeError> {
+    if args.is_empty() || args.len() > 3 {
+        arity_error!("Must supply one to three arguments to {}: {:?}", name, args);
+    }
+    let (first, rest) = args.shift().unwrap();
+    let s = try!(first.as_string());
+    let (start, end) = try!(range(rest, text::char_length(&s), "string"));
+    Ok(text::substring(&s, start, end).unwrap().to_string())
+}
+
 fn primitive(f: &'static str, args: List) -> Result<Value, RuntimeError> {
     match f {
         "+" => {
-            let sum = try!(args.into_iter().fold(Ok(0), |s, a| match s {
-                Ok(z) => Ok(z + try!(a.as_integer())),
-                _ => s
-            }));
-            Ok(Value::Integer(sum))
+            let sum = try!(args.into_iter().try_fold(Number::Integer(0), |z, a| a.as_number().map(|n| z.add(n))));
+            Ok(Value::from_number(sum))
         },
         "-" => {
             if args.len() != 2 {
-                runtime_error!("Must supply exactly two arguments to -: {:?}", args);
+                arity_error!("Must supply exactly two arguments to -: {:?}", args);
             }
             let (l, r) = try!(args.unpack2());
-            Ok(Value::Integer(try!(l.as_integer()) - try!(r.as_integer())))
+            Ok(Value::from_number(try!(l.as_number()).subtract(try!(r.as_number()))))
         },
         "*" => {
-            let product = try!(args.into_iter().fold(Ok(1), |s, a| match s {
-                Ok(z) => Ok(z * try!(a.as_integer())),
-                _ => s
-            }));
-            Ok(Value::Integer(product))
+            let product = try!(args.into_iter().try_fold(Number::Integer(1), |z, a| a.as_number().map(|n| z.multiply(n))));
+            Ok(Value::from_number(product))
         },
         "/" => {
             if args.len() != 2 {
-                runtime_error!("Must supply exactly two arguments to /: {:?}", args);
+                arity_error!("Must supply exactly two arguments to /: {:?}", args);
+            }
+            let (l, r) = try!(args.unpack2());
+            let (n, d) = (try!(l.clone().as_number()), try!(r.clone().as_number()));
+            match n.divide(d) {
+                Some(q) => Ok(Value::from_number(q)),
+                None => runtime_error!("Division by zero in /: {:?}", vec![l, r])
+            }
+        },
+        "<" | ">" | "=" => {
+            if args.len() != 2 {
+                arity_error!("Must supply exactly two arguments to {}: {:?}", f, args);
+            }
+            let (l, r) = try!(args.unpack2());
+            let ordering = try!(l.as_number()).compare(try!(r.as_number()));
+            let expected = match f {
+                "<" => Ordering::Less,
+                ">" => Ordering::Greater,
+                _ => Ordering::Equal
+            };
+            // +nan.0 compares false with anything
+            Ok(Value::Boolean(ordering == Some(expected)))
+        },
+        #[cfg(feature = "math")]
+        "floor/" | "truncate/" => {
+            if args.len() != 2 {
+                arity_error!("Must supply exactly two arguments to {}: {:?}", f, args);
+            }
+            let (l, r) = try!(args.unpack2());
+            let (n, d) = (try!(l.as_integer()), try!(r.as_integer()));
+            let divide = if f == "floor/" { numbers::floor_divide } else { numbers::truncate_divide };
+            match divide(n, d) {
+                Some((q, r)) => Ok(Value::Values(Rc::new(vec![Value::Integer(q), Value::Integer(r)]))),
+                None if d == 0 => runtime_error!("Division by zero in {}: {:?}", f, vec![n, d]),
+                None => runtime_error!("Integer overflow in {}: {:?}", f, vec![n, d])
+            }
+        },
+        // (number->string n [radix [width]])
+        #[cfg(feature = "math")]
+        "number->string" => {
+            if args.is_empty() || args.len() > 3 {
+                arity_error!("Must supply one to three arguments to number->string: {:?}", args);
+            }
+            let mut ints = Vec::with_capacity(3);
+            for v in args.clone() {
+                match v {
+                    Value::Float(_) | Value::Rational(..) if ints.is_empty() => {
+                        if args.len() > 1 {
+                            runtime_error!("number->string only takes a radix and width for integers, not {}", v)
+                        }
+                        return Ok(Value::String(Rc::from(v.to_string())))
+                    },
+                    v => ints.push(try!(v.as_integer()))
+                }
+            }
+            let radix = ints.get(1).cloned().unwrap_or(10);
+            let width = ints.get(2).cloned().unwrap_or(0);
+            if !(2..=36).contains(&radix) {
+                runtime_error!("Radix must be from 2 to 36: {}", radix)
+            }
+            if width < 0 {
+                runtime_error!("Width must be a non-negative integer: {}", width)
+            }
+            Ok(Value::String(Rc::from(numbers::to_string_radix(ints[0], radix as u32, width as usize))))
+        },
+        #[cfg(feature = "math")]
+        "exact-integer-sqrt" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to exact-integer-sqrt: {:?}", args);
+            }
+            let v = try!(args.unpack1());
+            match v {
+                Value::Integer(n) => match numbers::exact_integer_sqrt(n) {
+                    Some((s, r)) => Ok(Value::Values(Rc::new(vec![Value::Integer(s), Value::Integer(r)]))),
+                    None => type_error!("Argument to exact-integer-sqrt must be a non-negative integer: {:?}", v)
+                },
+                _ => type_error!("Argument to exact-integer-sqrt must be a non-negative integer: {:?}", v)
+            }
+        },
+        #[cfg(feature = "math")]
+        "random" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to random: {:?}", args);
+            }
+            match try!(args.unpack1()) {
+                Value::Integer(n) if n > 0 => Ok(Value::Integer(random::below(n as u64) as i64)),
+                v => type_error!("Argument to random must be a positive integer: {:?}", v)
+            }
+        },
+        "null?" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to null?: {:?}", args);
+            }
+            let v = try!(args.unpack1());
+            match v {
+                Value::List(l) => Ok(Value::Boolean(l.is_empty())),
+                _ => Ok(Value::Boolean(false))
+            }
+        },
+        "list" => {
+            Ok(args.to_value())
+        },
+        "values" => {
+            if args.len() == 1 {
+                return args.unpack1()
+            }
+            Ok(Value::Values(Rc::new(args.into_iter().collect())))
+        },
+        "car" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly two arguments to car: {:?}", args);
+            }
+            let l = match try!(args.unpack1()) {
+                Value::DottedList(l, _) => l,
+                v => try!(v.as_list())
+            };
+            match l.shift() {
+                Some((car, _)) => Ok(car),
+                None => type_error!("Can't run car on an empty list")
+            }
+        },
+        "cdr" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly two arguments to cdr: {:?}", args);
+            }
+            match try!(args.unpack1()) {
+                Value::DottedList(l, tail) => {
+                    let rest = l.rest();
+                    Ok(if rest.is_empty() { (*tail).clone() } else { Value::DottedList(rest, tail) })
+                },
+                v => match try!(v.as_list()).shift() {
+                    Some((_, cdr)) => Ok(cdr.to_value()),
+                    None => type_error!("Can't run cdr on an empty list")
+                }
+            }
+        },
+        "cons" => {
+            if args.len() != 2 {
+                arity_error!("Must supply exactly two arguments to cons: {:?}", args);
+            }
+            // onto anything but a list, it makes a dotted list
+            match try!(args.unpack2()) {
+                (elem, Value::List(l)) => Ok(l.unshift(elem).to_value()),
+                (elem, Value::DottedList(l, tail)) => Ok(Value::DottedList(l.unshift(elem), tail)),
+                (elem, tail) => Ok(Value::DottedList(List::Null.unshift(elem), Rc::new(tail)))
+            }
+        },
+        "append" => {
+            // from the last list back, so each is shared as the tail of the one before
+            let mut result = None;
+            for list in args.reverse() {
+                let list = match list {
+                    Value::List(l) => l,
+                    v => type_error!("Arguments to append must be lists: {:?}", v)
+                };
+                result = Some(match result {
+                    Some(tail) => list.append(tail),
+                    None => list
+                });
+            }
+            Ok(result.unwrap_or(List::Null).to_value())
+        },
+        "reverse" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to reverse: {:?}", args);
+            }
+            match try!(args.unpack1()) {
+                Value::List(l) => Ok(l.reverse().to_value()),
+                v => type_error!("Argument to reverse must be a list: {:?}", v)
+            }
+        },
+        "error" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to error: {:?}", args);
+            }
+            let msg = try!(args.unpack1());
+            runtime_error!("{:?}", msg)
+        },
+        // (warn "message" irritant...) reports the message and irritants to the warning handler, and carries on
+        "warn" => {
+            let (first, irritants) = match args.shift() {
+                Some(pair) => pair,
+                None => arity_error!("Must supply at least one argument to warn: ()")
+            };
+            let mut message = try!(first.as_string());
+            for irritant in irritants.iter() {
+                message.push_str(&format!(" {:?}", irritant));
+            }
+            warning::warn(&message);
+            Ok(null!())
+        },
+        // (log-info template arg ...), with the args filled in to the template's ~a and ~s
+        "log-debug" | "log-info" | "log-warn" | "log-error" => {
+            let level = match f {
+                "log-debug" => Level::Debug,
+                "log-info" => Level::Info,
+                "log-warn" => Level::Warn,
+                _ => Level::Error
+            };
+            let (template, values) = match args.shift() {
+                Some(pair) => pair,
+                None => arity_error!("Must supply at least one argument to {}: ()", f)
+            };
+            let template = try!(template.as_string());
+            let values: Vec<Value> = values.into_iter().collect();
+            match log::format(&template, &values) {
+                Ok(message) => log::log(level, &message),
+                Err(e) => runtime_error!("{}", e)
+            }
+            Ok(null!())
+        },
+        // Like error, but for bugs in the program, which an embedder may not want to recover from
+        "assertion-violation" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to assertion-violation: {:?}", args);
+            }
+            let msg = try!(args.unpack1());
+            raise!(Kind::AssertionViolation, "{:?}", msg)
+        },
+        // (raise c) raises the condition c again, as a guard does with the ones no clause handles. Anything else is
+        // raised as a plain error, like (error obj).
+        "raise" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to raise: {:?}", args);
+            }
+            match try!(args.unpack1()) {
+                Value::Condition(kind, message) => raise!(kind, "{}", message),
+                v => runtime_error!("{:?}", v)
+            }
+        },
+        "error-object-message" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to error-object-message: {:?}", args);
+            }
+            match try!(args.unpack1()) {
+                Value::Condition(_, message) => Ok(Value::String(message)),
+                v => type_error!("Expected a condition value: {:?}", v)
+            }
+        },
+        "error?" | "file-error?" | "read-error?" | "assertion-violation?" | "arity-error?" | "type-error?" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to {}: {:?}", f, args);
+            }
+            match (try!(args.unpack1()), Kind::from_predicate(f)) {
+                (Value::Condition(k, _), Some(kind)) => Ok(Value::Boolean(k.is_a(kind))),
+                _ => Ok(Value::Boolean(false))
+            }
+        },
+        "eof-object" => {
+            if args.len() != 0 {
+                arity_error!("Must supply exactly zero arguments to eof-object: {:?}", args);
+            }
+            Ok(Value::EofObject)
+        },
+        "gensym" => {
+            if args.len() > 1 {
+                arity_error!("Must supply at most one argument to gensym: {:?}", args);
+            }
+            if args.is_empty() {
+                return Ok(Value::Symbol(random::gensym("g")))
+            }
+            match try!(args.unpack1()) {
+                Value::String(s) => Ok(Value::Symbol(random::gensym(&s))),
+                Value::Symbol(s) => Ok(Value::Symbol(random::gensym(&s))),
+                v => type_error!("Expected a string or symbol prefix: {:?}", v)
+            }
+        },
+        "hash" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to hash: {:?}", args);
+            }
+            Ok(Value::Integer(random::stable_hash(&try!(args.unpack1()))))
+        },
+        "hash-by-identity" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to hash-by-identity: {:?}", args);
+            }
+            Ok(Value::Integer(try!(args.unpack1()).identity_hash()))
+        },
+        "eqv?" => {
+            if args.len() != 2 {
+                arity_error!("Must supply exactly two arguments to eqv?: {:?}", args);
+            }
+            let (a, b) = try!(args.unpack2());
+            Ok(Value::Boolean(a.is_eqv(&b)))
+        },
+        "features" => {
+            if !args.is_empty() {
+                arity_error!("Must supply exactly zero arguments to features: {:?}", args);
+            }
+            Ok(List::from_vec(features::features().into_iter().map(Value::Symbol).collect()).to_value())
+        },
+        "eof-object?" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to eof-object?: {:?}", args);
+            }
+            let v = try!(args.unpack1());
+            Ok(Value::Boolean(v == Value::EofObject))
+        },
+        "keyword?" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to keyword?: {:?}", args);
+            }
+            match try!(args.unpack1()) {
+                Value::Keyword(_) => Ok(Value::Boolean(true)),
+                _ => Ok(Value::Boolean(false))
+            }
+        },
+        "keyword->string" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to keyword->string: {:?}", args);
+            }
+            match try!(args.unpack1()) {
+                Value::Keyword(k) => Ok(Value::String(Rc::from(k))),
+                v => type_error!("Expected a keyword value: {:?}", v)
+            }
+        },
+        "string->keyword" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to string->keyword: {:?}", args);
+            }
+            match try!(args.unpack1()) {
+                Value::String(s) => Ok(Value::Keyword(s.to_string())),
+                v => type_error!("Expected a string value: {:?}", v)
+            }
+        },
+        "char?" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to char?: {:?}", args);
+            }
+            match try!(args.unpack1()) {
+                Value::Character(_) => Ok(Value::Boolean(true)),
+                _ => Ok(Value::Boolean(false))
+            }
+        },
+        "char->integer" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to char->integer: {:?}", args);
+            }
+            match try!(args.unpack1()) {
+                Value::Character(c) => Ok(Value::Integer(c as i64)),
+                v => type_error!("Expected a character value: {:?}", v)
+            }
+        },
+        "char-alphabetic?" | "char-numeric?" | "char-whitespace?" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to {}: {:?}", f, args);
+            }
+            let c = match try!(args.unpack1()) {
+                Value::Character(c) => c,
+                v => type_error!("Expected a character value: {:?}", v)
+            };
+            Ok(Value::Boolean(match f {
+                "char-alphabetic?" => c.is_alphabetic(),
+                "char-numeric?" => c.is_numeric(),
+                _ => c.is_whitespace()
+            }))
+        },
+        "char-upcase" | "char-downcase" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to {}: {:?}", f, args);
+            }
+            match try!(args.unpack1()) {
+                Value::Character(c) if f == "char-upcase" => Ok(Value::Character(text::upcase(c))),
+                Value::Character(c) => Ok(Value::Character(text::downcase(c))),
+                v => type_error!("Expected a character value: {:?}", v)
+            }
+        },
+        "integer->char" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to integer->char: {:?}", args);
+            }
+            match try!(args.unpack1()) {
+                Value::Integer(n) => match text::char_from_code(n) {
+                    Some(c) => Ok(Value::Character(c)),
+                    None => runtime_error!("Not a Unicode code point: {}", n)
+                },
+                v => type_error!("Expected an integer value: {:?}", v)
+            }
+        },
+        "box" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to box: {:?}", args);
+            }
+            let v = try!(args.unpack1());
+            stats::count_allocations(1);
+            Ok(Value::Box(Rc::new(RefCell::new(v))))
+        },
+        "box?" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to box?: {:?}", args);
+            }
+            match try!(args.unpack1()) {
+                Value::Box(_) => Ok(Value::Boolean(true)),
+                _ => Ok(Value::Boolean(false))
+            }
+        },
+        "unbox" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to unbox: {:?}", args);
+            }
+            match try!(args.unpack1()) {
+                Value::Box(b) => Ok(b.borrow().clone()),
+                v => type_error!("Expected a box value: {:?}", v)
+            }
+        },
+        "set-box!" => {
+            if args.len() != 2 {
+                arity_error!("Must supply exactly two arguments to set-box!: {:?}", args);
+            }
+            let (b, v) = try!(args.unpack2());
+            match b {
+                Value::Box(b) => {
+                    *b.borrow_mut() = v;
+                    Ok(null!())
+                },
+                _ => type_error!("Expected a box value: {:?}", b)
+            }
+        },
+        "make-queue" => {
+            stats::count_allocations(1);
+            Ok(Value::Queue(Rc::new(RefCell::new(args.into_iter().collect()))))
+        },
+        "queue?" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to queue?: {:?}", args);
+            }
+            match try!(args.unpack1()) {
+                Value::Queue(_) => Ok(Value::Boolean(true)),
+                _ => Ok(Value::Boolean(false))
+            }
+        },
+        "queue-length" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to queue-length: {:?}", args);
+            }
+            match try!(args.unpack1()) {
+                Value::Queue(q) => Ok(Value::Integer(q.borrow().len() as i64)),
+                v => type_error!("Expected a queue value: {:?}", v)
+            }
+        },
+        "enqueue!" => {
+            if args.len() != 2 {
+                arity_error!("Must supply exactly two arguments to enqueue!: {:?}", args);
+            }
+            match try!(args.unpack2()) {
+                (Value::Queue(q), v) => {
+                    q.borrow_mut().push_back(v);
+                    Ok(null!())
+                },
+                (q, _) => type_error!("Expected a queue value: {:?}", q)
+            }
+        },
+        "dequeue!" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to dequeue!: {:?}", args);
+            }
+            match try!(args.unpack1()) {
+                Value::Queue(q) => match q.borrow_mut().pop_front() {
+                    Some(v) => Ok(v),
+                    None => runtime_error!("Can't dequeue from an empty queue")
+                },
+                v => type_error!("Expected a queue value: {:?}", v)
+            }
+        },
+        "queue->list" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to queue->list: {:?}", args);
+            }
+            match try!(args.unpack1()) {
+                Value::Queue(q) => Ok(List::from_vec(q.borrow().iter().cloned().collect()).to_value()),
+                v => type_error!("Expected a queue value: {:?}", v)
+            }
+        },
+        "make-set" => {
+            stats::count_allocations(1);
+            let mut set = HashSet::with_hasher(HashState::new());
+            set.extend(args);
+            Ok(Value::Set(Rc::new(RefCell::new(set))))
+        },
+        "set?" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to set?: {:?}", args);
+            }
+            match try!(args.unpack1()) {
+                Value::Set(_) => Ok(Value::Boolean(true)),
+                _ => Ok(Value::Boolean(false))
+            }
+        },
+        "set-add!" => {
+            if args.len() != 2 {
+                arity_error!("Must supply exactly two arguments to set-add!: {:?}", args);
+            }
+            match try!(args.unpack2()) {
+                (Value::Set(s), v) => {
+                    s.borrow_mut().insert(v);
+                    Ok(null!())
+                },
+                (s, _) => type_error!("Expected a set value: {:?}", s)
+            }
+        },
+        "set-contains?" => {
+            if args.len() != 2 {
+                arity_error!("Must supply exactly two arguments to set-contains?: {:?}", args);
+            }
+            match try!(args.unpack2()) {
+                (Value::Set(s), v) => Ok(Value::Boolean(s.borrow().contains(&v))),
+                (s, _) => type_error!("Expected a set value: {:?}", s)
+            }
+        },
+        "set-union" | "set-intersection" => {
+            if args.is_empty() {
+                arity_error!("Must supply at least one argument to {}: {:?}", f, args);
+            }
+            let mut sets = Vec::with_capacity(args.len());
+            for v in args {
+                match v {
+                    Value::Set(s) => sets.push(s),
+                    v => type_error!("Expected a set value: {:?}", v)
+                }
+            }
+            let mut result = HashSet::with_hasher(HashState::new());
+            if f == "set-union" {
+                for s in sets.iter() {
+                    result.extend(s.borrow().iter().cloned());
+                }
+            } else {
+                for v in sets[0].borrow().iter() {
+                    if sets[1..].iter().all(|s| s.borrow().contains(v)) {
+                        result.insert(v.clone());
+                    }
+                }
+            }
+            stats::count_allocations(1);
+            Ok(Value::Set(Rc::new(RefCell::new(result))))
+        },
+        "set->list" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to set->list: {:?}", args);
+            }
+            match try!(args.unpack1()) {
+                Value::Set(s) => Ok(List::from_vec(s.borrow().iter().cloned().collect()).to_value()),
+                v => type_error!("Expected a set value: {:?}", v)
+            }
+        },
+        "make-heap" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to make-heap: {:?}", args);
+            }
+            match try!(args.unpack1()) {
+                less @ Value::Procedure(_) => {
+                    stats::count_allocations(1);
+                    Ok(Value::Heap(Rc::new(RefCell::new(Heap { less, items: Vec::new() }))))
+                },
+                v => type_error!("Argument to make-heap must be a procedure: {:?}", v)
+            }
+        },
+        "heap?" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to heap?: {:?}", args);
+            }
+            match try!(args.unpack1()) {
+                Value::Heap(_) => Ok(Value::Boolean(true)),
+                _ => Ok(Value::Boolean(false))
+            }
+        },
+        "heap-size" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to heap-size: {:?}", args);
+            }
+            match try!(args.unpack1()) {
+                Value::Heap(h) => Ok(Value::Integer(h.borrow().items.len() as i64)),
+                v => type_error!("Expected a heap value: {:?}", v)
+            }
+        },
+        "heap-peek" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to heap-peek: {:?}", args);
+            }
+            match try!(args.unpack1()) {
+                Value::Heap(h) => match h.borrow().items.first() {
+                    Some(v) => Ok(v.clone()),
+                    None => runtime_error!("Can't peek at an empty heap")
+                },
+                v => type_error!("Expected a heap value: {:?}", v)
+            }
+        },
+        "string-length" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to string-length: {:?}", args);
+            }
+            Ok(Value::Integer(text::char_length(&try!(try!(args.unpack1()).as_string())) as i64))
+        },
+        // (substring s start [end]), indexed by character
+        "substring" => {
+            if args.len() < 2 || args.len() > 3 {
+                arity_error!("Must supply two or three arguments to substring: {:?}", args);
+            }
+            Ok(Value::String(Rc::from(try!(string_range("substring", args)))))
+        },
+        // (string-copy s [start [end]])
+        "string-copy" => Ok(Value::String(Rc::from(try!(string_range("string-copy", args))))),
+        // (string->list s [start [end]])
+        "string->list" => {
+            let s = try!(string_range("string->list", args));
+            Ok(Value::from_vec(s.chars().map(Value::Character).collect()))
+        },
+        // (list->string list [start [end]])
+        "list->string" => {
+            if args.is_empty() || args.len() > 3 {
+                arity_error!("Must supply one to three arguments to list->string: {:?}", args);
+            }
+            let (first, rest) = args.shift().unwrap();
+            let elements = try!(first.as_list());
+            let (start, end) = try!(range(rest, elements.len(), "list"));
+            let mut s = String::new();
+            for element in elements.iter().skip(start).take(end - start) {
+                match *element {
+                    Value::Character(c) => s.push(c),
+                    ref v => type_error!("Expected a character value: {:?}", v)
+                }
+            }
+            Ok(Value::String(Rc::from(s)))
+        },
+        "vector?" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to vector?: {:?}", args);
+            }
+            match try!(args.unpack1()) {
+                Value::Vector(_) => Ok(Value::Boolean(true)),
+                _ => Ok(Value::Boolean(false))
+            }
+        },
+        "vector" => Ok(Value::Vector(Rc::new(RefCell::new(args.into_iter().collect())))),
+        "vector-length" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to vector-length: {:?}", args);
+            }
+            Ok(Value::Integer(try!(try!(args.unpack1()).as_vector()).borrow().len() as i64))
+        },
+        "vector-ref" => {
+            if args.len() != 2 {
+                arity_error!("Must supply exactly two arguments to vector-ref: {:?}", args);
+            }
+            let (v, i) = try!(args.unpack2());
+            let items = try!(v.as_vector());
+            let items = items.borrow();
+            match try!(i.as_integer()) {
+                i if i >= 0 && (i as usize) < items.len() => Ok(items[i as usize].clone()),
+                i => runtime_error!("Index {} out of range for a vector of length {}", i, items.len())
+            }
+        },
+        // (vector->list v [start [end]])
+        "vector->list" => {
+            if args.is_empty() || args.len() > 3 {
+                arity_error!("Must supply one to three arguments to vector->list: {:?}", args);
+            }
+            let (first, rest) = args.shift().unwrap();
+            let items = try!(first.as_vector());
+            let items = items.borrow();
+            let (start, end) = try!(range(rest, items.len(), "vector"));
+            Ok(Value::from_vec(items[start..end].to_vec()))
+        },
+        // (vector-copy v [start [end]])
+        "vector-copy" => {
+            if args.is_empty() || args.len() > 3 {
+                arity_error!("Must supply one to three arguments to vector-copy: {:?}", args);
+            }
+            let (first, rest) = args.shift().unwrap();
+            let items = try!(first.as_vector());
+            let items = items.borrow();
+            let (start, end) = try!(range(rest, items.len(), "vector"));
+            Ok(Value::Vector(Rc::new(RefCell::new(items[start..end].to_vec()))))
+        },
+        // (vector-fill! v x [start [end]])
+        "vector-fill!" => {
+            if args.len() < 2 || args.len() > 4 {
+                arity_error!("Must supply two to four arguments to vector-fill!: {:?}", args);
+            }
+            let (first, rest) = args.shift().unwrap();
+            let (fill, rest) = rest.shift().unwrap();
+            let items = try!(first.as_vector());
+            let mut items = items.borrow_mut();
+            let (start, end) = try!(range(rest, items.len(), "vector"));
+            for item in items[start..end].iter_mut() {
+                *item = fill.clone();
+            }
+            Ok(null!())
+        },
+        // (vector-copy! to at from [start [end]])
+        "vector-copy!" => {
+            if args.len() < 3 || args.len() > 5 {
+                arity_error!("Must supply three to five arguments to vector-copy!: {:?}", args);
+            }
+            let (to, rest) = args.shift().unwrap();
+            let (at, rest) = rest.shift().unwrap();
+            let (from, rest) = rest.shift().unwrap();
+            let to = try!(to.as_vector());
+            let at = match at {
+                Value::Integer(i) if i >= 0 => i as usize,
+                v => type_error!("Expected a non-negative integer value: {:?}", v)
+            };
+            // Taken out before to is borrowed, since it can be the same vector as from
+            let items = {
+                let from = try!(from.as_vector());
+                let from = from.borrow();
+                let (start, end) = try!(range(rest, from.len(), "vector"));
+                from[start..end].to_vec()
+            };
+            let mut to = to.borrow_mut();
+            if at + items.len() > to.len() {
+                runtime_error!("Indexes {} to {} out of range for a vector of length {}", at, at + items.len(), to.len());
+            }
+            to[at..at + items.len()].clone_from_slice(&items);
+            Ok(null!())
+        },
+        "vector-append" => {
+            let mut items = Vec::new();
+            for v in args {
+                items.extend(try!(v.as_vector()).borrow().iter().cloned());
+            }
+            Ok(Value::Vector(Rc::new(RefCell::new(items))))
+        },
+        "vector-push!" => {
+            if args.len() != 2 {
+                arity_error!("Must supply exactly two arguments to vector-push!: {:?}", args);
+            }
+            let (v, x) = try!(args.unpack2());
+            try!(v.as_vector()).borrow_mut().push(x);
+            Ok(null!())
+        },
+        "vector-pop!" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to vector-pop!: {:?}", args);
+            }
+            match try!(try!(args.unpack1()).as_vector()).borrow_mut().pop() {
+                Some(v) => Ok(v),
+                None => runtime_error!("Can't pop from an empty vector")
+            }
+        },
+        // (list->vector list [start [end]])
+        "list->vector" => {
+            if args.is_empty() || args.len() > 3 {
+                arity_error!("Must supply one to three arguments to list->vector: {:?}", args);
+            }
+            let (first, rest) = args.shift().unwrap();
+            let elements = try!(first.as_list());
+            let (start, end) = try!(range(rest, elements.len(), "list"));
+            Ok(Value::Vector(Rc::new(RefCell::new(elements.iter().skip(start).take(end - start).cloned().collect()))))
+        },
+        "bytevector?" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to bytevector?: {:?}", args);
+            }
+            match try!(args.unpack1()) {
+                Value::Bytevector(_) => Ok(Value::Boolean(true)),
+                _ => Ok(Value::Boolean(false))
+            }
+        },
+        "bytevector" => {
+            let mut bytes = Vec::with_capacity(args.len());
+            for v in args {
+                match v {
+                    Value::Integer(b) if (0..=255).contains(&b) => bytes.push(b as u8),
+                    v => type_error!("Expected a byte (an integer from 0 to 255): {:?}", v)
+                }
+            }
+            Ok(Value::Bytevector(Rc::new(bytes)))
+        },
+        "bytevector-length" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to bytevector-length: {:?}", args);
+            }
+            Ok(Value::Integer(try!(try!(args.unpack1()).as_bytevector()).len() as i64))
+        },
+        // (bytevector-copy b [start [end]])
+        "bytevector-copy" => {
+            if args.is_empty() || args.len() > 3 {
+                arity_error!("Must supply one to three arguments to bytevector-copy: {:?}", args);
+            }
+            let (first, rest) = args.shift().unwrap();
+            let bytes = try!(first.as_bytevector());
+            let (start, end) = try!(range(rest, bytes.len(), "bytevector"));
+            Ok(Value::Bytevector(Rc::new(bytes[start..end].to_vec())))
+        },
+        "bytevector-u8-ref" => {
+            if args.len() != 2 {
+                arity_error!("Must supply exactly two arguments to bytevector-u8-ref: {:?}", args);
+            }
+            let (b, i) = try!(args.unpack2());
+            let bytes = try!(b.as_bytevector());
+            match try!(i.as_integer()) {
+                i if i >= 0 && (i as usize) < bytes.len() => Ok(Value::Integer(bytes[i as usize] as i64)),
+                i => runtime_error!("Index {} out of range for a bytevector of length {}", i, bytes.len())
+            }
+        },
+        "value->cbor" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to value->cbor: {:?}", args);
+            }
+            let val = try!(args.unpack1());
+            let encoded = match val.to_data() {
+                Ok(node) => cbor::encode(&node),
+                Err(v) => type_error!("Can't encode {:?} as CBOR", v)
+            };
+            match encoded {
+                Ok(bytes) => Ok(Value::Bytevector(Rc::new(bytes))),
+                Err(e) => type_error!("{}", e)
+            }
+        },
+        "cbor->value" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to cbor->value: {:?}", args);
+            }
+            let bytes = try!(try!(args.unpack1()).as_bytevector());
+            match cbor::decode(&bytes) {
+                Ok(node) => Ok(Value::from_node(&node)),
+                Err(e) => read_error!("{}", e)
+            }
+        },
+        // (document 'name), the rendered documentation for the builtin name, or #f
+        "document" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to document: {:?}", args);
+            }
+            let name = try!(try!(args.unpack1()).as_symbol());
+            Ok(docs::lookup(&name).map_or(Value::Boolean(false), |d| Value::String(Rc::from(d.render()))))
+        },
+        "string-foldcase" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to string-foldcase: {:?}", args);
+            }
+            Ok(Value::String(Rc::from(text::fold_case(&try!(try!(args.unpack1()).as_string())))))
+        },
+        "display->string" => {
+            let mut s = String::new();
+            for arg in args.iter() {
+                s.push_str(&printer::print(arg, Style::Display));
+            }
+            Ok(Value::String(Rc::from(s)))
+        },
+        "length+" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to length+: {:?}", args);
+            }
+            match try!(args.unpack1()) {
+                Value::List(l) => Ok(Value::Integer(l.len() as i64)),
+                _ => Ok(Value::Boolean(false))
+            }
+        },
+        "list-copy" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to list-copy: {:?}", args);
+            }
+            match try!(args.unpack1()) {
+                Value::List(l) => Ok(Value::from_vec(l.iter().cloned().collect())),
+                v => Ok(v)
+            }
+        },
+        // The last cell of a list, shared with it, along with the tail of a dotted list
+        "last-pair" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to last-pair: {:?}", args);
+            }
+            let (mut l, tail) = match try!(args.unpack1()) {
+                Value::DottedList(l, tail) => (l, Some(tail)),
+                v => (try!(v.as_list()), None)
+            };
+            if l.is_empty() {
+                runtime_error!("Can't take the last pair of an empty list");
+            }
+            loop {
+                let rest = l.rest();
+                if rest.is_empty() {
+                    return Ok(match tail {
+                        Some(tail) => Value::DottedList(l, tail),
+                        None => Value::List(l)
+                    })
+                }
+                l = rest;
+            }
+        },
+        // Like length+, these are simple here: no list is circular, and anything that isn't a proper list counts as a
+        // dotted one, with no pairs if it isn't a dotted list
+        "proper-list?" | "circular-list?" | "dotted-list?" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to {}: {:?}", f, args);
+            }
+            let is_list = matches!(try!(args.unpack1()), Value::List(_));
+            Ok(Value::Boolean(match f {
+                "proper-list?" => is_list,
+                "circular-list?" => false,
+                _ => !is_list
+            }))
+        },
+        "tree-size" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to tree-size: {:?}", args);
+            }
+            Ok(Value::Integer(printer::size(&try!(args.unpack1())) as i64))
+        },
+        "depth" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to depth: {:?}", args);
+            }
+            match printer::depth(&try!(args.unpack1())) {
+                Some(n) => Ok(Value::Integer(n as i64)),
+                None => Ok(Value::Boolean(false))
+            }
+        },
+        "string-ci=?" | "string-ci<?" | "string-ci>?" | "string-ci<=?" | "string-ci>=?" => {
+            if args.len() != 2 {
+                arity_error!("Must supply exactly two arguments to {}: {:?}", f, args);
+            }
+            let (l, r) = try!(args.unpack2());
+            let l = text::fold_case(&try!(l.as_string()));
+            let r = text::fold_case(&try!(r.as_string()));
+            Ok(Value::Boolean(match f {
+                "string-ci=?" => l == r,
+                "string-ci<?" => l < r,
+                "string-ci>?" => l > r,
+                "string-ci<=?" => l <= r,
+                _ => l >= r
+            }))
+        },
+        #[cfg(feature = "io")]
+        "write" | "write-simple" | "write-shared" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to {}: {:?}", f, args);
+            }
+            let val = try!(args.unpack1());
+            let style = match f {
+                "write-simple" => Style::WriteSimple,
+                "write-shared" => Style::WriteShared,
+                _ => Style::Write
+            };
+            print!("{}", printer::print(&val, style));
+            Ok(null!())
+        },
+        #[cfg(feature = "io")]
+        "display" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to display: {:?}", args);
+            }
+            let val = try!(args.unpack1());
+            print!("{}", printer::print(&val, Style::Display));
+            Ok(null!())
+        },
+        #[cfg(feature = "io")]
+        "displayln" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to displayln: {:?}", args);
+            }
+            let val = try!(args.unpack1());
+            println!("{}", printer::print(&val, Style::Display));
+            Ok(null!())
+        },
+        #[cfg(feature = "io")]
+        "print" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to print: {:?}", args);
+            }
+            let val = try!(args.unpack1());
+            match val {
+                Value::Symbol(_) | Value::List(_) => print!("'{}", printer::print(&val, Style::Write)),
+                _ => print!("{}", printer::print(&val, Style::Write))
+            }
+            Ok(null!())
+        },
+        #[cfg(feature = "io")]
+        "newline" => {
+            if !args.is_empty() {
+                arity_error!("Must supply exactly zero arguments to newline: {:?}", args);
+            }
+            println!("");
+            Ok(null!())
+        },
+        #[cfg(feature = "io")]
+        "read" => {
+            if !args.is_empty() {
+                arity_error!("Must supply exactly zero arguments to read: {:?}", args);
+            }
+            match input::read_datum() {
+                Ok(Some(node)) => Ok(Value::from_node(&node)),
+                Ok(None) => Ok(Value::EofObject),
+                Err(e) => read_error!("{}", e)
+            }
+        },
+        #[cfg(feature = "io")]
+        "write-canonical" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to write-canonical: {:?}", args);
+            }
+            let val = try!(args.unpack1());
+            let written = match val.to_data() {
+                Ok(node) => canonical::write(&node),
+                Err(v) => type_error!("Can't write {:?} as a canonical s-expression", v)
+            };
+            match written {
+                Ok(text) => print!("{}", text),
+                Err(e) => type_error!("{}", e)
+            }
+            // Whatever's reading it is waiting for the whole expression
+            match io::stdout().flush() {
+                Ok(_) => Ok(null!()),
+                Err(e) => file_error!("Couldn't write to stdout: {}", e)
+            }
+        },
+        #[cfg(feature = "io")]
+        "read-canonical" => {
+            if !args.is_empty() {
+                arity_error!("Must supply exactly zero arguments to read-canonical: {:?}", args);
+            }
+            match canonical::read(&mut input::read_char) {
+                Ok(Some(node)) => Ok(Value::from_node(&node)),
+                Ok(None) => Ok(Value::EofObject),
+                Err(e) => read_error!("{}", e)
+            }
+        },
+        // (glob pattern), the sorted list of paths matching pattern
+        #[cfg(feature = "io")]
+        "glob" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to glob: {:?}", args);
+            }
+            let pattern = try!(try!(args.unpack1()).as_string());
+            Ok(List::from_vec(files::glob(&pattern).into_iter().map(|p| Value::String(Rc::from(p))).collect()).to_value())
+        },
+        #[cfg(feature = "io")]
+        "path-join" => {
+            if args.is_empty() {
+                arity_error!("Must supply at least one argument to path-join: {:?}", args);
+            }
+            let mut parts = Vec::new();
+            for part in args.into_iter() {
+                parts.push(try!(part.as_string()));
+            }
+            let parts: Vec<&str> = parts.iter().map(|p| p.as_str()).collect();
+            Ok(Value::String(Rc::from(paths::join(&parts))))
+        },
+        // #f when the path has no such part
+        #[cfg(feature = "io")]
+        "path-directory" | "path-filename" | "path-extension" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to {}: {:?}", f, args);
             }
-            let (l, r) = try!(args.unpack2());
-            Ok(Value::Integer(try!(l.as_integer()) / try!(r.as_integer())))
+            let path = try!(try!(args.unpack1()).as_string());
+            let part = match f {
+                "path-directory" => paths::directory(&path),
+                "path-filename" => paths::filename(&path),
+                _ => paths::extension(&path)
+            };
+            Ok(part.map_or(Value::Boolean(false), |p| Value::String(Rc::from(p))))
         },
-        "<" => {
-            if args.len() != 2 {
-                runtime_error!("Must supply exactly two arguments to <: {:?}", args);
+        #[cfg(feature = "io")]
+        "path-absolute?" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to path-absolute?: {:?}", args);
             }
-            let (l, r) = try!(args.unpack2());
-            Ok(Value::Boolean(try!(l.as_integer()) < try!(r.as_integer())))
+            let path = try!(try!(args.unpack1()).as_string());
+            Ok(Value::Boolean(paths::is_absolute(&path)))
         },
-        ">" => {
-            if args.len() != 2 {
-                runtime_error!("Must supply exactly two arguments to >: {:?}", args);
+        #[cfg(feature = "io")]
+        "expand-user-path" => {
+            if args.len() != 1 {
+                arity_error!("Must supply exactly one argument to expand-user-path: {:?}", args);
+            }
+            let path = try!(try!(args.unpack1()).as_string());
+            match paths::expand_user(&path) {
+                Ok(expanded) => Ok(Value::String(Rc::from(expanded))),
+                Err(e) => runtime_error!("{}", e)
             }
-            let (l, r) = try!(args.unpack2());
-            Ok(Value::Boolean(try!(l.as_integer()) > try!(r.as_integer())))
         },
-        "=" => {
-            if args.len() != 2 {
-                runtime_error!("Must supply exactly two arguments to =: {:?}", args);
+        // (csv-read [text]), reading the rest of stdin, or text if it's given, as a list of records of string fields
+        #[cfg(feature = "io")]
+        "csv-read" => {
+            if args.len() > 1 {
+                arity_error!("Must supply zero or one arguments to csv-read: {:?}", args);
+            }
+            let records = match args.shift() {
+                Some((text, _)) => {
+                    let text = try!(text.as_string());
+                    let mut chars = text.chars();
+                    csv::read(&mut || Ok(chars.next()))
+                },
+                None => csv::read(&mut input::read_char)
+            };
+            match records {
+                Ok(records) => {
+                    let records = records.into_iter().map(|record| Node::List(record.into_iter().map(Node::String).collect()));
+                    Ok(Value::from_node(&Node::List(records.collect())))
+                },
+                Err(e) => read_error!("{}", e)
             }
-            let (l, r) = try!(args.unpack2());
-            Ok(Value::Boolean(try!(l.as_integer()) == try!(r.as_integer())))
         },
-        "null?" => {
+        // (csv-write rows), writing a list of lists of strings, integers and symbols to stdout
+        #[cfg(feature = "io")]
+        "csv-write" => {
             if args.len() != 1 {
-                runtime_error!("Must supply exactly one argument to null?: {:?}", args);
+                arity_error!("Must supply exactly one argument to csv-write: {:?}", args);
             }
-            let v = try!(args.unpack1());
-            match v {
-                Value::List(l) => Ok(Value::Boolean(l.is_empty())),
-                _ => Ok(Value::Boolean(false))
+            let val = try!(args.unpack1());
+            let written = match val.to_data() {
+                Ok(node) => csv::write(&node),
+                Err(v) => type_error!("Can't write {:?} as a CSV field", v)
+            };
+            match written {
+                Ok(text) => print!("{}", text),
+                Err(e) => type_error!("{}", e)
+            }
+            match io::stdout().flush() {
+                Ok(_) => Ok(null!()),
+                Err(e) => file_error!("Couldn't write to stdout: {}", e)
             }
         },
-        "list" => {
-            Ok(args.to_value())
+        // (http-get url [headers]) and (http-post url body [headers]), returning (status ((name value) ...) body)
+        #[cfg(feature = "net")]
+        "http-get" | "http-post" => {
+            let (method, required) = if f == "http-get" { ("GET", 1) } else { ("POST", 2) };
+            if args.len() < required || args.len() > required + 1 {
+                arity_error!("Must supply {} or {} arguments to {}: {:?}", required, required + 1, f, args);
+            }
+            let mut values: Vec<Value> = args.into_iter().collect();
+            let headers = if values.len() > required { values.pop() } else { None };
+            let body = if method == "POST" { Some(try!(values.pop().unwrap().as_string())) } else { None };
+            let url = try!(values.pop().unwrap().as_string());
+            let headers = match headers {
+                Some(v) => match v.to_data().map_err(|_| v.to_string()).and_then(|node| http::headers_from_node(&node)) {
+                    Ok(headers) => headers,
+                    Err(e) => type_error!("{}", e)
+                },
+                None => Vec::new()
+            };
+            match http::request(method, &url, &headers, body.as_deref()) {
+                Ok(response) => Ok(Value::from_node(&response.to_node())),
+                Err(e) => runtime_error!("{}", e)
+            }
         },
-        "car" => {
+        // (load-foreign-library path), e.g. "libm.so.6"
+        #[cfg(feature = "ffi")]
+        "load-foreign-library" => {
             if args.len() != 1 {
-                runtime_error!("Must supply exactly two arguments to car: {:?}", args);
+                arity_error!("Must supply exactly one argument to load-foreign-library: {:?}", args);
             }
-            let l = try!(try!(args.unpack1()).as_list());
-            match l.shift() {
-                Some((car, _)) => Ok(car),
-                None => runtime_error!("Can't run car on an empty list")
+            let path = try!(try!(args.unpack1()).as_string());
+            match ffi::Library::open(&path) {
+                Ok(library) => Ok(Value::Library(Rc::new(library))),
+                Err(e) => runtime_error!("{}", e)
             }
         },
-        "cdr" => {
+        // (foreign-procedure library name '(parameter-type ...) 'result-type), e.g.
+        // (foreign-procedure libc "labs" '(long) 'long)
+        #[cfg(feature = "ffi")]
+        "foreign-procedure" => {
+            if args.len() != 4 {
+                arity_error!("Must supply exactly four arguments to foreign-procedure: {:?}", args);
+            }
+            let mut values = args.into_iter();
+            let library = match values.next().unwrap() {
+                Value::Library(l) => l,
+                v => type_error!("Expected a foreign library value: {:?}", v)
+            };
+            let name = try!(values.next().unwrap().as_string());
+            let parameters = match values.next().unwrap() {
+                Value::List(types) => {
+                    let names: Result<Vec<String>, RuntimeError> = types.into_iter().map(Value::as_symbol).collect();
+                    try!(names)
+                },
+                v => type_error!("Expected a list value: {:?}", v)
+            };
+            let result = try!(values.next().unwrap().as_symbol());
+            match ffi::Procedure::new(library, &name, &parameters, &result) {
+                Ok(procedure) => Ok(Value::Procedure(Function::Foreign(Rc::new(procedure)))),
+                Err(e) => runtime_error!("{}", e)
+            }
+        },
+        // (sqlite-open path), where path ":memory:" opens a new database in memory
+        #[cfg(feature = "sqlite")]
+        "sqlite-open" => {
             if args.len() != 1 {
-                runtime_error!("Must supply exactly two arguments to cdr: {:?}", args);
+                arity_error!("Must supply exactly one argument to sqlite-open: {:?}", args);
             }
-            let l = try!(try!(args.unpack1()).as_list());
-            match l.shift() {
-                Some((_, cdr)) => Ok(cdr.to_value()),
-                None => runtime_error!("Can't run cdr on an empty list")
+            let path = try!(try!(args.unpack1()).as_string());
+            match sqlite::Database::open(&path) {
+                Ok(db) => Ok(Value::Database(Rc::new(db))),
+                Err(e) => runtime_error!("{}", e)
             }
         },
-        "cons" => {
-            if args.len() != 2 {
-                runtime_error!("Must supply exactly two arguments to cons: {:?}", args);
+        // (sqlite-exec db sql parameter ...), returning how many rows the last statement changed, and
+        // (sqlite-query db sql parameter ...), returning the rows as lists of (column value) lists
+        #[cfg(feature = "sqlite")]
+        "sqlite-exec" | "sqlite-query" => {
+            if args.len() < 2 {
+                arity_error!("Must supply a database and a statement to {}: {:?}", f, args);
+            }
+            let mut values = args.into_iter();
+            let db = match values.next().unwrap() {
+                Value::Database(db) => db,
+                v => type_error!("Expected a database value: {:?}", v)
+            };
+            let sql = try!(values.next().unwrap().as_string());
+            let mut parameters = Vec::new();
+            for v in values {
+                match v.to_data() {
+                    Ok(node) => parameters.push(node),
+                    Err(_) => type_error!("Can't pass {:?} to SQLite", v)
+                }
+            }
+            let result = if f == "sqlite-exec" {
+                db.exec(&sql, &parameters).map(Value::Integer)
+            } else {
+                db.query(&sql, &parameters).map(|rows| Value::from_node(&rows))
+            };
+            match result {
+                Ok(v) => Ok(v),
+                Err(e) => runtime_error!("{}", e)
             }
-            let (elem, list) = try!(args.unpack2());
-            Ok(try!(list.as_list()).unshift(elem).to_value())
         },
-        "append" => {
+        // (on-signal 'SIGTERM handler) calls (handler 'SIGTERM) when the signal arrives, at the next step of
+        // evaluation, and (on-signal 'SIGTERM #f) goes back to the signal's default action
+        #[cfg(all(unix, feature = "process"))]
+        "on-signal" => {
             if args.len() != 2 {
-                runtime_error!("Must supply exactly two arguments to append: {:?}", args);
+                arity_error!("Must supply exactly two arguments to on-signal: {:?}", args);
             }
-            let (list1raw, list2raw) = try!(args.unpack2());
-            let list1 = try!(list1raw.as_list());
-            let mut list2 = try!(list2raw.as_list());
-
-            for elem in list1.reverse() {
-                list2 = list2.unshift(elem)
+            let (name, handler) = try!(args.unpack2());
+            let name = try!(name.as_symbol());
+            let signal = match signals::number(&name) {
+                Some(signal) => signal,
+                None => runtime_error!("Can't handle the signal {} (only {} can be)", name, signals::names().join(", "))
+            };
+            match handler {
+                Value::Procedure(_) | Value::Boolean(false) => (),
+                v => type_error!("Expected a procedure value or #f: {:?}", v)
             }
-            Ok(list2.to_value())
-        },
-        "error" => {
-            if args.len() != 1 {
-                runtime_error!("Must supply exactly one argument to error: {:?}", args);
+            SIGNAL_HANDLERS.with(|h| h.borrow_mut().retain(|&(s, _)| s != signal));
+            if handler == Value::Boolean(false) {
+                signals::unwatch(signal);
+                return Ok(null!())
             }
-            let msg = try!(args.unpack1());
-            runtime_error!("{:?}", msg)
-        },
-        "write" => {
-            if args.len() != 1 {
-                runtime_error!("Must supply exactly one argument to write: {:?}", args);
+            SIGNAL_HANDLERS.with(|h| h.borrow_mut().push((signal, handler)));
+            match signals::watch(signal) {
+                Ok(_) => Ok(null!()),
+                Err(e) => runtime_error!("{}", e)
             }
-            let val = try!(args.unpack1());
-            print!("{:?}", val);
-            Ok(null!())
         },
-        "display" => {
+        #[cfg(feature = "io")]
+        "printer-option" => {
             if args.len() != 1 {
-                runtime_error!("Must supply exactly one argument to display: {:?}", args);
+                arity_error!("Must supply exactly one argument to printer-option: {:?}", args);
+            }
+            let name = try!(try!(args.unpack1()).as_symbol());
+            match printer::option(&name) {
+                Ok(Setting::Limit(n)) => Ok(Value::Integer(n as i64)),
+                Ok(Setting::On) => Ok(Value::Boolean(true)),
+                Ok(Setting::Off) => Ok(Value::Boolean(false)),
+                Err(e) => runtime_error!("{}", e)
             }
-            let val = try!(args.unpack1());
-            print!("{}", val);
-            Ok(null!())
         },
-        "displayln" => {
-            if args.len() != 1 {
-                runtime_error!("Must supply exactly one argument to displayln: {:?}", args);
+        // (set-printer-option! 'max-length 10) limits printing to that many elements; #f removes the limit again
+        #[cfg(feature = "io")]
+        "set-printer-option!" => {
+            if args.len() != 2 {
+                arity_error!("Must supply exactly two arguments to set-printer-option!: {:?}", args);
+            }
+            let (name, setting) = try!(args.unpack2());
+            let setting = match setting {
+                Value::Integer(n) if n >= 0 => Setting::Limit(n as usize),
+                Value::Boolean(true) => Setting::On,
+                Value::Boolean(false) => Setting::Off,
+                v => type_error!("Printer options must be a non-negative integer, #t or #f: {:?}", v)
+            };
+            match printer::set_option(&try!(name.as_symbol()), setting) {
+                Ok(_) => Ok(null!()),
+                Err(e) => runtime_error!("{}", e)
             }
-            let val = try!(args.unpack1());
-            println!("{}", val);
-            Ok(null!())
         },
-        "print" => {
-            if args.len() != 1 {
-                runtime_error!("Must supply exactly one argument to print: {:?}", args);
+        #[cfg(feature = "io")]
+        "read-char" => {
+            if !args.is_empty() {
+                arity_error!("Must supply exactly zero arguments to read-char: {:?}", args);
             }
-            let val = try!(args.unpack1());
-            match val {
-                Value::Symbol(_) | Value::List(_) => print!("'{:?}", val),
-                _ => print!("{:?}", val)
+            match input::read_char() {
+                Ok(Some(c)) => Ok(Value::Character(c)),
+                Ok(None) => Ok(Value::EofObject),
+                Err(e) => runtime_error!("{}", e)
             }
-            Ok(null!())
         },
-        "newline" => {
-            if args.len() != 0 {
-                runtime_error!("Must supply exactly zero arguments to newline: {:?}", args);
+        // (par-map f l) is (map f l) with the calls spread over one thread per core; see parallel.rs
+        #[cfg(feature = "parallel")]
+        "par-map" => {
+            if args.len() != 2 {
+                arity_error!("Must supply exactly two arguments to par-map: {:?}", args);
+            }
+            let (f, list) = try!(args.unpack2());
+            let (procedure, prelude) = match f {
+                Value::Procedure(ref func) => {
+                    // natives don't need anything from the global environment
+                    let prelude = match *func {
+                        Function::Scheme(_, _, ref env) => global_definitions(&Environment::get_root(env.clone())),
+                        _ => Vec::new()
+                    };
+                    (try!(procedure_source(func)), prelude)
+                },
+                v => type_error!("First argument to par-map must be a procedure: {:?}", v)
+            };
+            let elements: Result<Vec<Node>, RuntimeError> = try!(list.as_list()).iter().map(Value::to_node).collect();
+            match parallel::map::<Interpreter>(prelude, procedure, try!(elements)) {
+                Ok(results) => Ok(List::from_nodes(&results).to_value()),
+                Err(e) => runtime_error!("{}", e)
             }
-            println!("");
-            Ok(null!())
         },
         _ => {
             runtime_error!("Unknown primitive: {:?}", f)
@@ -961,11 +3616,592 @@ fn primitive(f: &'static str, args: List) -> Result<Value, RuntimeError> {
     }
 }
 
+// The source for a procedure, including the local variables it closed over (which must hold plain data)
+#[cfg(feature = "parallel")]
+fn procedure_source(f: &Function) -> Result<Node, RuntimeError> {
+    match *f {
+        Function::Native(name) => Ok(Node::Identifier(name.to_string())),
+        Function::Plugin(ref b) => Ok(Node::Identifier(b.name().to_string())),
+        #[cfg(feature = "ffi")]
+        Function::Foreign(_) => runtime_error!("Can't send a foreign procedure to another thread"),
+        Function::Scheme(ref arg_names, ref body, ref env) => {
+            let body: Result<Vec<Node>, RuntimeError> = body.iter().map(Value::to_node).collect();
+            // innermost scope first, up to but not including the global environment
+            let mut captured: Vec<(String, Node)> = Vec::new();
+            let mut scope = env.clone();
+            loop {
+                let parent = match scope.borrow().parent {
+                    Some(ref parent) => parent.clone(),
+                    None => break
+                };
+                for (name, value) in scope.borrow().values.iter() {
+                    if !captured.iter().any(|c| c.0 == *name) {
+                        captured.push((name.clone(), try!(value.to_node())));
+                    }
+                }
+                scope = parent;
+            }
+            Ok(parallel::lambda(arg_names, try!(body), captured))
+        }
+    }
+}
+
+// Definitions recreating the global environment in a worker. Anything that can't be sent is left out, so a worker
+// only fails if it actually uses it.
+#[cfg(feature = "parallel")]
+fn global_definitions(root: &Rc<RefCell<Environment>>) -> Vec<Node> {
+    let mut prelude = Vec::new();
+    for (name, value) in root.borrow().values.iter() {
+        let definition = match *value {
+            Value::Procedure(Function::Native(native)) if native == name => continue,
+            Value::Procedure(ref f) => procedure_source(f).map(|source| parallel::define(name, source)),
+            Value::Macro(ref arg_names, ref body) => body.to_node().map(|body| parallel::define_syntax_rule(name, arg_names, vec![body])),
+            _ => value.to_node().map(|data| parallel::define(name, parallel::quote(data)))
+        };
+        if let Ok(definition) = definition {
+            prelude.push(definition);
+        }
+    }
+    prelude
+}
+
+#[cfg(feature = "parallel")]
+impl parallel::Worker for Interpreter {
+    fn start(prelude: &[Node]) -> Result<Interpreter, String> {
+        let interpreter = match Interpreter::new() {
+            Ok(interpreter) => interpreter,
+            Err(e) => return Err(e.message)
+        };
+        match interpreter.run(prelude) {
+            Ok(_) => Ok(interpreter),
+            Err(e) => Err(e.message)
+        }
+    }
+
+    fn call(&self, procedure: &Node, element: Node) -> Result<Node, String> {
+        let call = Node::List(vec![procedure.clone(), parallel::quote(element)]);
+        match self.run(&[call]).and_then(|v| v.to_node()) {
+            Ok(result) => Ok(result),
+            Err(e) => Err(e.message)
+        }
+    }
+}
+
+// Heap images serialize the environment graph reachable from the root (bindings, data, procedure bodies and
+// the environments they close over). Native procedures are written by name and re-linked against a fresh root
+// environment when the image is loaded, so images stay valid across rebuilds of the interpreter. Continuations,
+// plugin procedures, databases and foreign libraries refer to state outside the interpreter, so an environment that
+// holds any of them can't be written.
+const IMAGE_MAGIC: &[u8] = b"RSIMG\x01";
+
+impl Interpreter {
+    pub fn dump_image(&self, path: &Path) -> Result<(), RuntimeError> {
+        let image = try!(ImageWriter::write(&self.root));
+        match File::create(path).and_then(|mut f| f.write_all(&image)) {
+            Ok(_) => Ok(()),
+            Err(e) => file_error!("Couldn't write image {}: {}", path.display(), e)
+        }
+    }
+
+    pub fn load_image(path: &Path) -> Result<Interpreter, RuntimeError> {
+        let mut image = Vec::new();
+        match File::open(path).and_then(|mut f| f.read_to_end(&mut image)) {
+            Ok(_) => (),
+            Err(e) => file_error!("Couldn't read image {}: {}", path.display(), e)
+        }
+        let env = try!(ImageReader::read(&image));
+        Ok(Interpreter { root: env })
+    }
+}
+
+struct ImageWriter {
+    out: Vec<u8>,
+    envs: Vec<Rc<RefCell<Environment>>>,
+    env_ids: HashMap<usize, u32>,
+    // the boxes, queues, sets and heaps written so far, by address
+    shared_ids: HashMap<usize, u32>,
+}
+
+impl ImageWriter {
+    fn write(root: &Rc<RefCell<Environment>>) -> Result<Vec<u8>, RuntimeError> {
+        let mut w = ImageWriter { out: Vec::new(), envs: Vec::new(), env_ids: HashMap::new(), shared_ids: HashMap::new() };
+        w.env_id(root);
+
+        // Environments are discovered while their values are written, so keep going until the queue is drained
+        let mut i = 0;
+        while i < w.envs.len() {
+            let env_ref = w.envs[i].clone();
+            let env = env_ref.borrow();
+            match env.parent {
+                Some(ref parent) => {
+                    let id = w.env_id(parent);
+                    w.write_u32(id + 1);
+                },
+                None => w.write_u32(0)
+            }
+            w.write_u32(env.values.len() as u32);
+            for (name, value) in env.values.iter() {
+                w.write_str(name);
+                try!(w.write_value(value));
+            }
+            i += 1;
+        }
+
+        let mut image = IMAGE_MAGIC.to_vec();
+        image.extend(u32_bytes(w.envs.len() as u32).iter());
+        image.extend(w.out);
+        Ok(image)
+    }
+
+    fn env_id(&mut self, env: &Rc<RefCell<Environment>>) -> u32 {
+        let key = &**env as *const RefCell<Environment> as usize;
+        if let Some(id) = self.env_ids.get(&key) {
+            return *id;
+        }
+        let id = self.envs.len() as u32;
+        self.envs.push(env.clone());
+        self.env_ids.insert(key, id);
+        id
+    }
+
+    fn write_u32(&mut self, n: u32) {
+        self.out.extend(u32_bytes(n).iter());
+    }
+
+    fn write_str(&mut self, s: &str) {
+        self.write_u32(s.len() as u32);
+        self.out.extend(s.as_bytes().iter());
+    }
+
+    fn write_names(&mut self, names: &[String]) {
+        self.write_u32(names.len() as u32);
+        for name in names.iter() {
+            self.write_str(name);
+        }
+    }
+
+    fn write_value(&mut self, value: &Value) -> Result<(), RuntimeError> {
+        match *value {
+            Value::Symbol(ref s) => {
+                self.out.push(0);
+                self.write_str(s);
+            },
+            Value::Integer(i) => {
+                self.out.push(1);
+                for shift in 0..8 {
+                    self.out.push((i >> (shift * 8)) as u8);
+                }
+            },
+            Value::Float(f) => {
+                self.out.push(12);
+                let bits = f.to_bits();
+                for shift in 0..8 {
+                    self.out.push((bits >> (shift * 8)) as u8);
+                }
+            },
+            Value::Character(c) => {
+                self.out.push(14);
+                self.write_u32(c as u32);
+            },
+            Value::Vector(ref items) => {
+                if !self.write_shared(&**items as *const RefCell<Vec<Value>> as usize, 15) {
+                    let items = items.borrow().clone();
+                    try!(self.write_values(&items));
+                }
+            },
+            Value::Bytevector(ref bytes) => {
+                self.out.push(16);
+                self.write_u32(bytes.len() as u32);
+                self.out.extend(bytes.iter());
+            },
+            Value::DottedList(ref list, ref tail) => {
+                self.out.push(17);
+                self.write_u32(list.len() as u32);
+                for v in list.clone() {
+                    try!(self.write_value(&v));
+                }
+                try!(self.write_value(tail));
+            },
+            Value::Rational(n, d) => {
+                self.out.push(13);
+                for &i in [n, d].iter() {
+                    for shift in 0..8 {
+                        self.out.push((i >> (shift * 8)) as u8);
+                    }
+                }
+            },
+            Value::Boolean(b) => {
+                self.out.push(2);
+                self.out.push(if b { 1 } else { 0 });
+            },
+            Value::String(ref s) => {
+                self.out.push(3);
+                self.write_str(s);
+            },
+            Value::List(ref list) => {
+                self.out.push(4);
+                self.write_u32(list.len() as u32);
+                for v in list.clone() {
+                    try!(self.write_value(&v));
+                }
+            },
+            Value::Procedure(Function::Native(name)) => {
+                self.out.push(5);
+                self.write_str(name);
+            },
+            Value::Procedure(Function::Scheme(ref arg_names, ref body, ref env)) => {
+                self.out.push(6);
+                self.write_names(arg_names);
+                try!(self.write_value(&body.clone().to_value()));
+                let id = self.env_id(env);
+                self.write_u32(id);
+            },
+            Value::Macro(ref arg_names, ref body) => {
+                self.out.push(7);
+                self.write_names(arg_names);
+                try!(self.write_value(body));
+            },
+            Value::EofObject => {
+                self.out.push(8);
+            },
+            Value::Keyword(ref k) => {
+                self.out.push(9);
+                self.write_str(k);
+            },
+            Value::Box(ref b) => {
+                if !self.write_shared(&**b as *const RefCell<Value> as usize, 10) {
+                    try!(self.write_value(&b.borrow()));
+                }
+            },
+            Value::Queue(ref q) => {
+                if !self.write_shared(&**q as *const RefCell<VecDeque<Value>> as usize, 18) {
+                    let items: Vec<Value> = q.borrow().iter().cloned().collect();
+                    try!(self.write_values(&items));
+                }
+            },
+            Value::Set(ref set) => {
+                if !self.write_shared(&**set as *const RefCell<HashSet<Value, HashState>> as usize, 19) {
+                    let items: Vec<Value> = set.borrow().iter().cloned().collect();
+                    try!(self.write_values(&items));
+                }
+            },
+            Value::Heap(ref h) => {
+                if !self.write_shared(&**h as *const RefCell<Heap> as usize, 20) {
+                    let (less, items) = {
+                        let heap = h.borrow();
+                        (heap.less.clone(), heap.items.clone())
+                    };
+                    try!(self.write_value(&less));
+                    try!(self.write_values(&items));
+                }
+            },
+            Value::Condition(kind, ref message) => {
+                self.out.push(21);
+                self.write_str(kind.name());
+                self.write_str(message);
+            },
+            Value::SpecialForm(_) | Value::Continuation(_) | Value::Values(_) | Value::Procedure(Function::Plugin(_)) => {
+                runtime_error!("Can't write {:?} to an image", value)
+            },
+            #[cfg(feature = "sqlite")]
+            Value::Database(_) => runtime_error!("Can't write {:?} to an image", value),
+            #[cfg(feature = "ffi")]
+            Value::Library(_) | Value::Procedure(Function::Foreign(_)) => runtime_error!("Can't write {:?} to an image", value),
+        }
+        Ok(())
+    }
+
+    fn write_values(&mut self, values: &[Value]) -> Result<(), RuntimeError> {
+        self.write_u32(values.len() as u32);
+        for v in values.iter() {
+            try!(self.write_value(v));
+        }
+        Ok(())
+    }
+
+    // Boxes, vectors, queues, sets and heaps are numbered in the order they're first written, and later references point
+    // back to that number, so ones shared between bindings stay shared when the image is loaded. Writes either a
+    // reference to the one at address, returning true, or the tag it starts with, followed by its contents.
+    fn write_shared(&mut self, address: usize, tag: u8) -> bool {
+        if let Some(&id) = self.shared_ids.get(&address) {
+            self.out.push(11);
+            self.write_u32(id);
+            return true
+        }
+        let id = self.shared_ids.len() as u32;
+        self.shared_ids.insert(address, id);
+        self.out.push(tag);
+        false
+    }
+}
+
+fn u32_bytes(n: u32) -> [u8; 4] {
+    [n as u8, (n >> 8) as u8, (n >> 16) as u8, (n >> 24) as u8]
+}
+
+struct ImageReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    envs: Vec<Rc<RefCell<Environment>>>,
+    // the boxes, queues, sets and heaps read so far, in the order they were written
+    shared: Vec<Value>,
+    natives: Rc<RefCell<Environment>>,
+}
+
+impl<'a> ImageReader<'a> {
+    fn read(data: &[u8]) -> Result<Rc<RefCell<Environment>>, RuntimeError> {
+        if data.len() < IMAGE_MAGIC.len() || &data[..IMAGE_MAGIC.len()] != IMAGE_MAGIC {
+            runtime_error!("Not a RustyScheme image")
+        }
+        let mut r = ImageReader { data, pos: IMAGE_MAGIC.len(), envs: Vec::new(), shared: Vec::new(), natives: try!(Environment::new_root()) };
+
+        // Create every environment up front so that closures can refer to environments that come later in the image
+        let env_count = try!(r.read_u32());
+        if env_count == 0 {
+            runtime_error!("Image doesn't contain a root environment")
+        }
+        for _ in 0..env_count {
+            r.envs.push(Rc::new(RefCell::new(Environment { parent: None, values: Bindings::new(), autoloads: HashMap::new(), uninitialized: Vec::new(),
+                                                           capabilities: Capabilities::default() })));
+        }
+
+        for i in 0..env_count as usize {
+            let parent_id = try!(r.read_u32());
+            if parent_id > 0 {
+                let parent = try!(r.env(parent_id - 1));
+                r.envs[i].borrow_mut().parent = Some(parent);
+            }
+            let count = try!(r.read_u32());
+            for _ in 0..count {
+                let name = try!(r.read_str());
+                let value = try!(r.read_value());
+                r.envs[i].borrow_mut().values.insert(name, value);
+            }
+        }
+        Ok(r.envs[0].clone())
+    }
+
+    fn env(&self, id: u32) -> Result<Rc<RefCell<Environment>>, RuntimeError> {
+        match self.envs.get(id as usize) {
+            Some(env) => Ok(env.clone()),
+            None => runtime_error!("Image refers to a missing environment: {}", id)
+        }
+    }
+
+    fn read_u8(&mut self) -> Result<u8, RuntimeError> {
+        match self.data.get(self.pos) {
+            Some(b) => {
+                self.pos += 1;
+                Ok(*b)
+            },
+            None => runtime_error!("Unexpected end of image")
+        }
+    }
+
+    fn read_u32(&mut self) -> Result<u32, RuntimeError> {
+        let mut n = 0;
+        for shift in 0..4 {
+            n |= (try!(self.read_u8()) as u32) << (shift * 8);
+        }
+        Ok(n)
+    }
+
+    fn read_str(&mut self) -> Result<String, RuntimeError> {
+        let len = try!(self.read_u32()) as usize;
+        if self.pos + len > self.data.len() {
+            runtime_error!("Unexpected end of image")
+        }
+        let bytes = self.data[self.pos..self.pos + len].to_vec();
+        self.pos += len;
+        match String::from_utf8(bytes) {
+            Ok(s) => Ok(s),
+            Err(_) => runtime_error!("Image contains an invalid string")
+        }
+    }
+
+    fn read_names(&mut self) -> Result<Vec<String>, RuntimeError> {
+        let count = try!(self.read_u32());
+        let mut names = Vec::new();
+        for _ in 0..count {
+            names.push(try!(self.read_str()));
+        }
+        Ok(names)
+    }
+
+    fn read_values(&mut self) -> Result<Vec<Value>, RuntimeError> {
+        let count = try!(self.read_u32());
+        let mut values = Vec::new();
+        for _ in 0..count {
+            values.push(try!(self.read_value()));
+        }
+        Ok(values)
+    }
+
+    fn read_value(&mut self) -> Result<Value, RuntimeError> {
+        match try!(self.read_u8()) {
+            0 => Ok(Value::Symbol(try!(self.read_str()))),
+            1 => {
+                let mut i = 0;
+                for shift in 0..8 {
+                    i |= (try!(self.read_u8()) as i64) << (shift * 8);
+                }
+                Ok(Value::Integer(i))
+            },
+            2 => Ok(Value::Boolean(try!(self.read_u8()) != 0)),
+            3 => Ok(Value::String(Rc::from(try!(self.read_str())))),
+            4 => {
+                let count = try!(self.read_u32());
+                let mut vec = Vec::new();
+                for _ in 0..count {
+                    vec.push(try!(self.read_value()));
+                }
+                Ok(Value::from_vec(vec))
+            },
+            5 => {
+                // Re-link native procedures by name
+                let name = try!(self.read_str());
+                match self.natives.borrow().get(&name) {
+                    Some(v @ Value::Procedure(Function::Native(_))) => Ok(v),
+                    _ => runtime_error!("Image refers to an unknown native procedure: {}", name)
+                }
+            },
+            6 => {
+                let arg_names = try!(self.read_names());
+                let body = try!(try!(self.read_value()).as_list());
+                let env_id = try!(self.read_u32());
+                let env = try!(self.env(env_id));
+                Ok(Value::Procedure(Function::Scheme(Rc::new(arg_names), body, env)))
+            },
+            7 => {
+                let arg_names = try!(self.read_names());
+                let body = try!(self.read_value());
+                Ok(Value::Macro(Rc::new(arg_names), Rc::new(body)))
+            },
+            8 => Ok(Value::EofObject),
+            9 => Ok(Value::Keyword(try!(self.read_str()))),
+            10 => {
+                // Register the box before reading its contents, which may refer back to it
+                let b = Rc::new(RefCell::new(null!()));
+                self.shared.push(Value::Box(b.clone()));
+                let contents = try!(self.read_value());
+                *b.borrow_mut() = contents;
+                Ok(Value::Box(b))
+            },
+            11 => {
+                let id = try!(self.read_u32());
+                match self.shared.get(id as usize) {
+                    Some(v) => Ok(v.clone()),
+                    None => runtime_error!("Image refers to a missing box, vector, queue, set or heap: {}", id)
+                }
+            },
+            12 => {
+                let mut bits = 0;
+                for shift in 0..8 {
+                    bits |= (try!(self.read_u8()) as u64) << (shift * 8);
+                }
+                Ok(Value::Float(f64::from_bits(bits)))
+            },
+            13 => {
+                let mut parts = [0i64; 2];
+                for part in parts.iter_mut() {
+                    for shift in 0..8 {
+                        *part |= (try!(self.read_u8()) as i64) << (shift * 8);
+                    }
+                }
+                Ok(Value::Rational(parts[0], parts[1]))
+            },
+            14 => {
+                let code = try!(self.read_u32());
+                match char::from_u32(code) {
+                    Some(c) => Ok(Value::Character(c)),
+                    None => runtime_error!("Image contains an invalid character: {}", code)
+                }
+            },
+            15 => {
+                let v = Rc::new(RefCell::new(Vec::new()));
+                self.shared.push(Value::Vector(v.clone()));
+                let items = try!(self.read_values());
+                *v.borrow_mut() = items;
+                Ok(Value::Vector(v))
+            },
+            16 => {
+                let count = try!(self.read_u32());
+                let mut bytes = Vec::new();
+                for _ in 0..count {
+                    bytes.push(try!(self.read_u8()));
+                }
+                Ok(Value::Bytevector(Rc::new(bytes)))
+            },
+            17 => {
+                let count = try!(self.read_u32());
+                let mut items = Vec::new();
+                for _ in 0..count {
+                    items.push(try!(self.read_value()));
+                }
+                let tail = try!(self.read_value());
+                Ok(Value::DottedList(List::from_vec(items), Rc::new(tail)))
+            },
+            // like boxes and vectors, queues, sets and heaps are registered before their contents are read
+            18 => {
+                let q = Rc::new(RefCell::new(VecDeque::new()));
+                self.shared.push(Value::Queue(q.clone()));
+                let items = try!(self.read_values());
+                q.borrow_mut().extend(items);
+                Ok(Value::Queue(q))
+            },
+            19 => {
+                let set = Rc::new(RefCell::new(HashSet::with_hasher(HashState::new())));
+                self.shared.push(Value::Set(set.clone()));
+                let items = try!(self.read_values());
+                set.borrow_mut().extend(items);
+                Ok(Value::Set(set))
+            },
+            20 => {
+                let h = Rc::new(RefCell::new(Heap { less: null!(), items: Vec::new() }));
+                self.shared.push(Value::Heap(h.clone()));
+                let less = try!(self.read_value());
+                let items = try!(self.read_values());
+                *h.borrow_mut() = Heap { less, items };
+                Ok(Value::Heap(h))
+            },
+            21 => {
+                let name = try!(self.read_str());
+                let message = try!(self.read_str());
+                match KINDS.iter().find(|k| k.name() == name) {
+                    Some(&kind) => Ok(Value::Condition(kind, Rc::from(message))),
+                    None => runtime_error!("Image contains an unknown kind of condition: {}", name)
+                }
+            },
+            tag => runtime_error!("Image contains an unknown value tag: {}", tag)
+        }
+    }
+}
+
 #[cfg(test)]
 fn exec(list: List) -> Result<Value, RuntimeError> {
     process(list, try!(Environment::new_root()))
 }
 
+#[test]
+fn test_builtins_documented() {
+    for group in [CORE_PRIMITIVES, MATH_PRIMITIVES, IO_PRIMITIVES, NET_PRIMITIVES, PROCESS_PRIMITIVES, PARALLEL_PRIMITIVES, SQLITE_PRIMITIVES,
+                  FFI_PRIMITIVES, SPECIAL_FORM_NAMES].iter() {
+        for name in group.iter() {
+            assert!(docs::lookup(name).is_some(), "{} isn't documented", name);
+        }
+    }
+}
+
+#[test]
+fn test_feature_groups() {
+    // (features) only lists a group of builtins that has some
+    let features = features::features();
+    for &(name, group) in [("math", MATH_PRIMITIVES), ("io", IO_PRIMITIVES), ("net", NET_PRIMITIVES), ("process", PROCESS_PRIMITIVES),
+                           ("parallel", PARALLEL_PRIMITIVES), ("sqlite", SQLITE_PRIMITIVES), ("ffi", FFI_PRIMITIVES)].iter() {
+        assert_eq!(features.iter().any(|f| f == name), !group.is_empty(), "{}", name);
+    }
+}
+
 #[test]
 fn test_add1() {
     // runTest (+ 1 2) => 3
@@ -1507,12 +4743,12 @@ fn test_macros() {
 #[test]
 fn test_list_iter() {
     let l = List::Cell(
-        Box::new(Value::Integer(1)),
-        Box::new(List::Cell(
-            Box::new(Value::Integer(2)),
-            Box::new(List::Cell(
-                Box::new(Value::Integer(3)),
-                Box::new(List::Null))))));
+        Rc::new(Value::Integer(1)),
+        Rc::new(List::Cell(
+            Rc::new(Value::Integer(2)),
+            Rc::new(List::Cell(
+                Rc::new(Value::Integer(3)),
+                Rc::new(List::Null))))));
     let mut x = 0;
     for i in l {
         x += 1;
@@ -1524,11 +4760,66 @@ fn test_list_iter() {
 #[test]
 fn test_list_to_string() {
     let l = List::Cell(
-        Box::new(Value::Integer(1)),
-        Box::new(List::Cell(
-            Box::new(Value::Integer(2)),
-            Box::new(List::Cell(
-                Box::new(Value::Integer(3)),
-                Box::new(List::Null))))));
+        Rc::new(Value::Integer(1)),
+        Rc::new(List::Cell(
+            Rc::new(Value::Integer(2)),
+            Rc::new(List::Cell(
+                Rc::new(Value::Integer(3)),
+                Rc::new(List::Null))))));
     assert_eq!(l.to_string(), "(1 2 3)");
 }
+
+#[test]
+fn test_lookup_shares_list() {
+    let env = Environment::new_root().unwrap();
+    let big = List::from_vec((0..1000).map(Value::Integer).collect()).to_value();
+    env.borrow_mut().define("big".to_string(), big).unwrap();
+    let first = env.borrow().get(&"big".to_string());
+    let second = env.borrow().get(&"big".to_string());
+    match (first, second) {
+        (Some(Value::List(List::Cell(ref a, _))), Some(Value::List(List::Cell(ref b, _)))) => assert!(Rc::ptr_eq(a, b)),
+        _ => panic!("Expected two lists")
+    }
+}
+
+#[test]
+fn test_write_shared() {
+    let src = "(let ((x (list 1 2)) (s \"ab\")) (list x (list 1 2) (vector x) s s))";
+    let value = exec(List::from_nodes(&parse(&lexer::tokenize(src).unwrap()).unwrap())).unwrap();
+    assert_eq!(printer::print(&value, Style::Write), "((1 2) (1 2) #((1 2)) \"ab\" \"ab\")");
+    assert_eq!(printer::print(&value, Style::WriteShared), "(#0=(1 2) (1 2) #(#0#) #1=\"ab\" #1#)");
+}
+
+#[test]
+fn test_list_reuses_cells() {
+    use std::ptr;
+
+    let shared = List::from_vec(vec![Value::Integer(3), Value::Integer(4)]);
+    let built = List::Null.unshift(Value::Integer(2)).unshift(Value::Integer(1));
+    let appended = built.append(shared.clone());
+    // the tail is the same cells, not a copy of them
+    assert!(ptr::eq(appended.iter().nth(2).unwrap(), shared.iter().next().unwrap()));
+    assert_eq!(format!("{:?}", appended), "(1 2 3 4)");
+
+    let (reversed, counted) = stats::measure(|| appended.reverse());
+    assert_eq!(format!("{:?}", reversed), "(4 3 2 1)");
+    // the cells of shared are still held by it, so only those are copied
+    assert_eq!(counted.allocations, 2);
+    assert_eq!(format!("{:?}", shared), "(3 4)");
+}
+
+#[test]
+fn test_deep_values() {
+    let mut a = Value::Integer(1);
+    let mut b = Value::Integer(1);
+    for _ in 0..1000000 {
+        a = List::Null.unshift(a).to_value();
+        b = List::Null.unshift(b).to_value();
+    }
+    assert!(a == b);
+    assert_eq!(format!("{:?}", a), format!("{}1{}", "(".repeat(1000000), ")".repeat(1000000)));
+
+    let long = List::from_vec((0..1000000).map(Value::Integer).collect());
+    assert_eq!(long.len(), 1000000);
+    assert!(long == long.clone());
+}
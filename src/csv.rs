@@ -0,0 +1,141 @@
+// CSV (RFC 4180) for csv-read and csv-write in both interpreters. A field that contains a comma, a double quote or
+// a line break is written in double quotes, with any double quotes in it doubled; reading undoes that. Records are
+// written ending in CRLF, as the RFC says, and read ending in either CRLF or LF. Every field reads back as a string,
+// since CSV doesn't say which ones are numbers.
+
+use parser::Node;
+use printer::{self, Style};
+
+// Read every record up to the end of input, each as a list of its fields. An empty line is a record with no fields.
+pub fn read<F: FnMut() -> Result<Option<char>, String>>(next: &mut F) -> Result<Vec<Vec<String>>, String> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    // whether the field was quoted, which an empty line's only field can't be
+    let mut quoted = false;
+    let mut c = try!(next());
+    loop {
+        match c {
+            Some('"') if field.is_empty() && !quoted => {
+                quoted = true;
+                loop {
+                    match try!(next()) {
+                        Some('"') => match try!(next()) {
+                            Some('"') => field.push('"'),
+                            after => {
+                                c = after;
+                                break
+                            }
+                        },
+                        Some(d) => field.push(d),
+                        None => return Err(message!("A quoted CSV field isn't closed by the end of input, in record {}",
+                                                    records.len() + 1))
+                    }
+                }
+                match c {
+                    Some(',') | Some('\r') | Some('\n') | None => (),
+                    Some(d) => return Err(message!("Expected a comma or the end of the line after a quoted CSV field, but found {}, in record {}",
+                                                   d, records.len() + 1))
+                }
+                continue
+            },
+            Some(',') => {
+                record.push(field);
+                field = String::new();
+                quoted = false;
+            },
+            Some('\r') | Some('\n') | None => {
+                if c == Some('\r') {
+                    c = try!(next());
+                    if c != Some('\n') {
+                        field.push('\r');
+                        continue
+                    }
+                }
+                let empty_line = record.is_empty() && field.is_empty() && !quoted;
+                if c.is_none() && empty_line {
+                    return Ok(records)
+                }
+                if !empty_line {
+                    record.push(field);
+                    field = String::new();
+                }
+                records.push(record);
+                record = Vec::new();
+                quoted = false;
+                if c.is_none() {
+                    return Ok(records)
+                }
+            },
+            Some(d) => field.push(d)
+        }
+        c = try!(next());
+    }
+}
+
+// Write rows, a list of lists of strings, integers and symbols, as CSV records
+pub fn write(rows: &Node) -> Result<String, String> {
+    let rows = match *rows {
+        Node::List(ref rows) => rows,
+        _ => return Err(message!("Expected a list of CSV rows: {}", printer::print(rows, Style::Write)))
+    };
+    let mut out = String::new();
+    for row in rows.iter() {
+        let fields = match *row {
+            Node::List(ref fields) => fields,
+            _ => return Err(message!("Expected a CSV row as a list of fields: {}", printer::print(row, Style::Write)))
+        };
+        for (i, field) in fields.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            let text = match *field {
+                Node::String(ref s) | Node::Identifier(ref s) => s.clone(),
+                Node::Integer(n) => n.to_string(),
+                Node::Rational(n, d) => format!("{}/{}", n, d),
+                Node::Float(f) => printer::float(f),
+                _ => return Err(message!("Can't write {} as a CSV field", printer::print(field, Style::Write)))
+            };
+            // a row with one empty field would otherwise be an empty line, which reads back as no fields
+            if text.contains([',', '"', '\r', '\n']) || (fields.len() == 1 && text.is_empty()) {
+                out.push('"');
+                out.push_str(&text.replace('"', "\"\""));
+                out.push('"');
+            } else {
+                out.push_str(&text);
+            }
+        }
+        out.push_str("\r\n");
+    }
+    Ok(out)
+}
+
+#[test]
+fn test_csv() {
+    use parser::parse_str;
+
+    let read_str = |s: &str| {
+        let mut chars = s.chars();
+        read(&mut || Ok(chars.next()))
+    };
+    let strings = |rows: &[&[&str]]| -> Vec<Vec<String>> {
+        rows.iter().map(|row| row.iter().map(|f| f.to_string()).collect()).collect()
+    };
+    assert_eq!(read_str("a,b,c\n1,,3\n"), Ok(strings(&[&["a", "b", "c"], &["1", "", "3"]])));
+    assert_eq!(read_str("a,\"b,\"\"c\"\"\r\nd\"\r\n\r\n,\r\"\""), Ok(strings(&[&["a", "b,\"c\"\r\nd"], &[], &["", "\r\"\""]])));
+    assert_eq!(read_str("\"\""), Ok(strings(&[&[""]])));
+    assert_eq!(read_str(""), Ok(strings(&[])));
+    assert_eq!(read_str("a\n\"b"), Err("A quoted CSV field isn't closed by the end of input, in record 2".to_string()));
+    assert_eq!(read_str("\"a\"b"), Err("Expected a comma or the end of the line after a quoted CSV field, but found b, in record 1".to_string()));
+
+    let mut rows = parse_str("((name \"a, b\" 42) (\"\" \"two\nlines\") (\"\") ())").unwrap().remove(0);
+    // the reader has no escapes for a double quote in a string
+    if let Node::List(ref mut rows) = rows {
+        rows[1] = Node::List(vec![Node::String("say \"hi\"".to_string()), Node::String("two\nlines".to_string())]);
+    }
+    let written = write(&rows).unwrap();
+    assert_eq!(written, "name,\"a, b\",42\r\n\"say \"\"hi\"\"\",\"two\nlines\"\r\n\"\"\r\n\r\n");
+    assert_eq!(read_str(&written), Ok(strings(&[&["name", "a, b", "42"], &["say \"hi\"", "two\nlines"], &[""], &[]])));
+    let rows = parse_str("((#t))").unwrap().remove(0);
+    assert_eq!(write(&rows), Err("Can't write #t as a CSV field".to_string()));
+}
@@ -0,0 +1,374 @@
+// Documentation for the builtins, which (document 'name) and `rusty_scheme doc` render. Each builtin has its
+// parameters, written as they'd be called, and a sentence or two on what it does. A parameter in brackets is
+// optional and one followed by ... stands for any number of arguments, including none, so the parameters also give
+// the arity. As with the builtins themselves, each feature has its own table, so only what's compiled in is
+// documented. Plugins can add documentation for their builtins (per thread, like the builtins).
+//
+// The registry also says which builtins are pure: they have no side effects and their results depend only on their
+// arguments, so fold can evaluate calls to them with literal arguments before the code runs.
+
+use std::cell::RefCell;
+
+pub struct Doc {
+    pub name: String,
+    pub parameters: Vec<String>,
+    pub text: String,
+    pub pure: bool,
+}
+
+impl Doc {
+    // The least and most arguments it takes, with no most if it takes any number
+    pub fn arity(&self) -> (usize, Option<usize>) {
+        let required = self.parameters.iter().filter(|p| !p.starts_with('[') && *p != "...").count();
+        // nested brackets, like [radix [width]], are optional one after another
+        let optional: usize = self.parameters.iter().filter(|p| p.starts_with('[')).map(|p| p.matches('[').count()).sum();
+        if self.parameters.iter().any(|p| p == "...") {
+            (required - 1, None)
+        } else {
+            (required, Some(required + optional))
+        }
+    }
+
+    // How it's called, e.g. (substring s start [end])
+    pub fn usage(&self) -> String {
+        let mut parts = vec![self.name.clone()];
+        parts.extend(self.parameters.iter().cloned());
+        format!("({})", parts.join(" "))
+    }
+
+    // The usage followed by the text, indented
+    pub fn render(&self) -> String {
+        format!("{}\n    {}", self.usage(), self.text)
+    }
+}
+
+type Entry = (&'static str, &'static str, &'static str);
+
+const CORE_DOCS: &[Entry] = &[
+    ("define", "name value", "Binds name to value in the current environment. (define (name parameter ...) body ...) defines a procedure."),
+    ("define-syntax-rule", "(name parameter ...) template", "Defines a macro that replaces (name argument ...) with the template, its parameters filled in."),
+    ("autoload", "path name1 name2 ...", "Loads the file at path the first time one of the names is referred to."),
+    ("begin", "expression1 expression2 ...", "Evaluates the expressions in order, returning the last one's value."),
+    ("let", "((name value) ...) body1 body2 ...", "Evaluates the body with each name bound to its value, which can't refer to the others."),
+    ("letrec", "((name value) ...) body1 body2 ...", "Like let, but the values can refer to all the names, as mutually recursive procedures do."),
+    ("letrec*", "((name value) ...) body1 body2 ...", "Like letrec, but the values are evaluated in order, so each can use the ones before it."),
+    ("set!", "name value", "Changes the value of the existing variable name."),
+    ("lambda", "(parameter ...) body1 body2 ...", "A procedure taking the parameters and evaluating the body when called."),
+    ("λ", "(parameter ...) body1 body2 ...", "The same as lambda."),
+    ("if", "test then else", "Evaluates then if test is true (anything but #f), or else otherwise."),
+    ("and", "expression ...", "Evaluates the expressions until one is #f, returning the last value evaluated (#t for none)."),
+    ("or", "expression ...", "Evaluates the expressions until one is true, returning it (#f for none)."),
+    ("quote", "datum", "The datum itself, unevaluated; 'datum is short for it."),
+    ("quasiquote", "template", "Like quote, but parts of the template written (unquote expression), or ,expression, are evaluated, and the items of the list returned by (unquote-splicing expression), or ,@expression, are spliced into the list around it."),
+    ("eval", "expression", "Evaluates expression, a datum, in the global environment."),
+    ("apply", "procedure arguments", "Calls procedure with the elements of the list arguments as its arguments."),
+    ("call/cc", "procedure", "Calls procedure with the current continuation, which returns to where call/cc was called when called itself (cps interpreter only)."),
+    ("call-with-values", "producer consumer", "Calls producer with no arguments, then consumer with the values it returned."),
+    ("values", "value ...", "Returns the values as separate results, for call-with-values."),
+    ("guard", "(name clause ...) body1 body2 ...", "Evaluates the body, and if it raises an error, binds the condition to name and evaluates the first clause whose test is true, as cond would, raising the error again if none is."),
+    ("null?", "x", "Whether x is the empty list."),
+    ("list", "x ...", "A list of the arguments."),
+    ("car", "list", "The first element of a non-empty list."),
+    ("cdr", "list", "A non-empty list without its first element, or the tail of a dotted list with only one."),
+    ("cons", "x list", "A list of x followed by the elements of list, or the dotted list (x . list) if list isn't a list."),
+    ("append", "list ...", "A list of the elements of each list in turn."),
+    ("reverse", "list", "A list of the elements of list in the opposite order."),
+    ("length+", "x", "The length of x if it's a list, or #f."),
+    ("list-copy", "x", "A copy of x if it's a list, otherwise x itself."),
+    ("last-pair", "list", "The last pair of a non-empty list: a list of its last element."),
+    ("proper-list?", "x", "Whether x is a proper list, which every list is."),
+    ("circular-list?", "x", "Whether x is a circular list, which no list is, since only boxes can contain themselves."),
+    ("dotted-list?", "x", "Whether x is a dotted list, which is anything but a list, since pairs are only made onto lists."),
+    ("tree-size", "x", "How many values x is made of, counting each list and box and everything in it."),
+    ("depth", "x", "How deeply the lists and boxes in x nest, or #f if x contains itself."),
+    ("error", "message", "Raises an error with the message."),
+    ("warn", "message irritant ...", "Reports the message and irritants to the warning handler, and carries on."),
+    ("log-debug", "template argument ...", "Logs the template at the debug level, with ~a in it replaced by the next argument as display shows it and ~s as write does."),
+    ("log-info", "template argument ...", "Logs the template at the info level, filled in as log-debug does."),
+    ("log-warn", "template argument ...", "Logs the template at the warn level, filled in as log-debug does."),
+    ("log-error", "template argument ...", "Logs the template at the error level, filled in as log-debug does."),
+    ("raise", "condition", "Raises the condition again, as a guard does with one no clause handles; anything else is raised as an error."),
+    ("assertion-violation", "message", "Raises an error for a bug in the program, which an embedder may choose not to recover from."),
+    ("error-object-message", "condition", "The message of the condition a guard caught."),
+    ("error?", "x", "Whether x is a condition a guard caught."),
+    ("file-error?", "x", "Whether x is the condition of an error opening or reading a file."),
+    ("read-error?", "x", "Whether x is the condition of a syntax error."),
+    ("assertion-violation?", "x", "Whether x is the condition of an assertion violation."),
+    ("arity-error?", "x", "Whether x is the condition of calling a procedure with the wrong number of arguments."),
+    ("type-error?", "x", "Whether x is the condition of passing a procedure the wrong type of argument."),
+    ("eof-object", "", "The value read returns at the end of input."),
+    ("eof-object?", "x", "Whether x is the end of input value."),
+    ("gensym", "[prefix]", "A new symbol, different from every other."),
+    ("hash", "x", "A hash of x by its structure, so equal values hash the same; boxes and procedures by identity."),
+    ("hash-by-identity", "x", "A hash of x by identity, so only the same box, queue, set or heap hashes the same."),
+    ("eqv?", "x y", "Whether x and y are the same: numbers, characters and symbols by value, so 2/4 is eqv? to 1/2 but 1 isn't to 1.0, and anything else only when it's the very same one."),
+    ("features", "", "The list of features cond-expand can test for."),
+    ("keyword?", "x", "Whether x is a keyword, like #:name."),
+    ("keyword->string", "keyword", "The name of keyword as a string."),
+    ("string->keyword", "string", "The keyword named string."),
+    ("char?", "x", "Whether x is a character, like #\\a."),
+    ("char->integer", "char", "The Unicode code point of char."),
+    ("integer->char", "n", "The character whose Unicode code point is n."),
+    ("char-alphabetic?", "char", "Whether char is a letter, in any script."),
+    ("char-numeric?", "char", "Whether char is a digit or other numeric character, in any script."),
+    ("char-whitespace?", "char", "Whether char is white space, as Unicode defines it."),
+    ("char-upcase", "char", "The upper case form of char, or char itself if that takes more than one character, as ß's does."),
+    ("char-downcase", "char", "The lower case form of char, or char itself if that takes more than one character."),
+    ("box", "x", "A new box holding x."),
+    ("box?", "x", "Whether x is a box."),
+    ("unbox", "box", "What box holds."),
+    ("set-box!", "box x", "Makes box hold x."),
+    ("make-queue", "x ...", "A new queue holding the arguments, the first at the front."),
+    ("queue?", "x", "Whether x is a queue."),
+    ("queue-length", "queue", "How many elements queue holds."),
+    ("enqueue!", "queue x", "Adds x to the back of queue."),
+    ("dequeue!", "queue", "Removes the element at the front of queue and returns it."),
+    ("queue->list", "queue", "The elements of queue, front first."),
+    ("make-set", "x ...", "A new set of the arguments."),
+    ("set?", "x", "Whether x is a set."),
+    ("set-add!", "set x", "Adds x to set, if it doesn't already contain an equal value."),
+    ("set-contains?", "set x", "Whether set contains a value equal to x."),
+    ("set-union", "set1 set2", "A new set of the values in either set."),
+    ("set-intersection", "set1 set2", "A new set of the values in both sets."),
+    ("set->list", "set", "The values in set."),
+    ("make-heap", "less?", "A new empty heap ordered by less?, a procedure of two elements."),
+    ("heap?", "x", "Whether x is a heap."),
+    ("heap-size", "heap", "How many elements heap holds."),
+    ("heap-push!", "heap x", "Adds x to heap."),
+    ("heap-pop!", "heap", "Removes the least element of heap and returns it."),
+    ("heap-peek", "heap", "The least element of heap, leaving it there."),
+    ("string-length", "string", "How many characters string has."),
+    ("substring", "string start [end]", "The characters of string from start up to end, or to its end."),
+    ("string-copy", "string [start [end]]", "A copy of string, or of its characters from start up to end."),
+    ("string->list", "string [start [end]]", "The characters of string, or those from start up to end, as a list."),
+    ("list->string", "list [start [end]]", "A string of the characters in list, or of those from start up to end."),
+    ("vector?", "x", "Whether x is a vector."),
+    ("vector", "x ...", "A vector of the arguments."),
+    ("vector-length", "vector", "The number of elements in vector."),
+    ("vector-ref", "vector k", "The element of vector at index k, counting from 0."),
+    ("vector->list", "vector [start [end]]", "The elements of vector, or those from start up to end, as a list."),
+    ("list->vector", "list [start [end]]", "A vector of the elements of list, or of those from start up to end."),
+    ("vector-copy", "vector [start [end]]", "A new vector of the elements of vector, or of those from start up to end."),
+    ("vector-fill!", "vector x [start [end]]", "Sets every element of vector, or those from start up to end, to x."),
+    ("vector-copy!", "to at from [start [end]]", "Copies the elements of vector from, or those from start up to end, into vector to starting at index at."),
+    ("vector-append", "vector ...", "A new vector of the elements of each vector in turn."),
+    ("vector-push!", "vector x", "Adds x to the end of vector, making it one longer."),
+    ("vector-pop!", "vector", "Removes the last element of vector and returns it."),
+    ("bytevector?", "x", "Whether x is a bytevector."),
+    ("bytevector", "byte ...", "A bytevector of the arguments, which are integers from 0 to 255."),
+    ("bytevector-length", "bytevector", "The number of bytes in bytevector."),
+    ("bytevector-u8-ref", "bytevector k", "The byte of bytevector at index k, counting from 0."),
+    ("bytevector-copy", "bytevector [start [end]]", "A new bytevector of the bytes of bytevector, or of those from start up to end."),
+    ("string-foldcase", "string", "string with its case folded, for comparing without regard to case."),
+    ("string-ci=?", "string1 string2", "Whether the strings are equal, ignoring case."),
+    ("string-ci<?", "string1 string2", "Whether string1 sorts before string2, ignoring case."),
+    ("string-ci>?", "string1 string2", "Whether string1 sorts after string2, ignoring case."),
+    ("string-ci<=?", "string1 string2", "Whether string1 sorts before or with string2, ignoring case."),
+    ("string-ci>=?", "string1 string2", "Whether string1 sorts after or with string2, ignoring case."),
+    ("display->string", "x ...", "What displaying each x in turn would print, as a string."),
+    ("value->cbor", "x", "x encoded as CBOR, as a bytevector."),
+    ("cbor->value", "bytevector", "The value a bytevector encodes as CBOR."),
+    ("document", "name", "The documentation for the builtin called name, or #f if there isn't any."),
+    ("+", "n1 n2 n3 ...", "The sum of the numbers, a float if any of them is."),
+    ("-", "n1 n2", "n1 minus n2."),
+    ("*", "n1 n2 n3 ...", "The product of the numbers."),
+    ("/", "n1 n2", "n1 divided by n2, exactly (as a rational if need be) unless either is a float."),
+    ("<", "n1 n2", "Whether n1 is less than n2."),
+    (">", "n1 n2", "Whether n1 is greater than n2."),
+    ("=", "n1 n2", "Whether the numbers are equal, so 1 is equal to 1.0."),
+];
+
+#[cfg(feature = "math")]
+const MATH_DOCS: &[Entry] = &[
+    ("random", "n", "A random integer from 0 up to but not including n."),
+    ("floor/", "n1 n2", "The quotient of n1 and n2 rounded down, and the remainder, as two values."),
+    ("truncate/", "n1 n2", "The quotient of n1 and n2 rounded towards zero, and the remainder, as two values."),
+    ("exact-integer-sqrt", "n", "The largest integer whose square is at most n, and what's left over, as two values."),
+    ("number->string", "n [radix [width]]", "n written in radix (10 by default), padded with zeros to width digits. Floats and rationals are only written in decimal."),
+];
+#[cfg(not(feature = "math"))]
+const MATH_DOCS: &[Entry] = &[];
+
+#[cfg(feature = "io")]
+const IO_DOCS: &[Entry] = &[
+    ("write", "x", "Writes x to stdout as it would be read back in."),
+    ("write-simple", "x", "Like write, but without datum labels: where a box appears again inside itself, it's cut off with ... instead."),
+    ("write-shared", "x", "Like write, but marking every list, string, box and vector that appears more than once."),
+    ("display", "x", "Writes x to stdout for people to read, with strings as their characters."),
+    ("displayln", "x", "Like display, followed by a newline."),
+    ("print", "x", "Writes x to stdout as an expression evaluating to it."),
+    ("newline", "", "Writes a newline to stdout."),
+    ("read", "", "Reads a datum from stdin."),
+    ("read-char", "", "Reads a character from stdin."),
+    ("write-canonical", "x", "Writes x to stdout as a canonical s-expression."),
+    ("read-canonical", "", "Reads a canonical s-expression from stdin."),
+    ("csv-read", "[text]", "The records of CSV text, or of the rest of stdin, as lists of string fields."),
+    ("csv-write", "rows", "Writes rows, a list of lists of strings, integers and symbols, to stdout as CSV."),
+    ("directory-walk", "path procedure", "Calls procedure with every file under path, depth first in order of name."),
+    ("glob", "pattern", "The sorted list of paths matching pattern, with *, ?, [...] and ** in it."),
+    ("path-join", "part1 part2 ...", "The parts joined into a path."),
+    ("path-directory", "path", "Everything before the last component of path, or #f if there isn't anything."),
+    ("path-filename", "path", "The last component of path, or #f if there isn't one."),
+    ("path-extension", "path", "What follows the last dot of the last component of path, or #f."),
+    ("path-absolute?", "path", "Whether path is absolute."),
+    ("expand-user-path", "path", "path with a leading ~ replaced by the user's home directory."),
+    ("printer-option", "name", "The current value of the printer option name."),
+    ("set-printer-option!", "name value", "Sets the printer option name, such as max-length, to value."),
+];
+#[cfg(not(feature = "io"))]
+const IO_DOCS: &[Entry] = &[];
+
+#[cfg(feature = "net")]
+const NET_DOCS: &[Entry] = &[
+    ("http-get", "url [headers]", "Fetches url, returning (status headers body)."),
+    ("http-post", "url body [headers]", "Posts body to url, returning (status headers body)."),
+    ("serve-http", "port handler [count]", "Answers each HTTP request to port with what (handler request) returns, forever or until count have been answered."),
+];
+#[cfg(not(feature = "net"))]
+const NET_DOCS: &[Entry] = &[];
+
+#[cfg(all(unix, feature = "process"))]
+const PROCESS_DOCS: &[Entry] = &[
+    ("on-signal", "signal handler", "Calls (handler signal) when the signal, such as SIGTERM, arrives; a handler of #f restores its default action."),
+];
+#[cfg(not(all(unix, feature = "process")))]
+const PROCESS_DOCS: &[Entry] = &[];
+
+#[cfg(feature = "parallel")]
+const PARALLEL_DOCS: &[Entry] = &[
+    ("par-map", "procedure list", "A list of procedure applied to each element of list, spread over a thread per core."),
+];
+#[cfg(not(feature = "parallel"))]
+const PARALLEL_DOCS: &[Entry] = &[];
+
+#[cfg(feature = "sqlite")]
+const SQLITE_DOCS: &[Entry] = &[
+    ("sqlite-open", "path", "Opens the SQLite database at path, creating it if need be, or a new one in memory for \":memory:\"."),
+    ("sqlite-exec", "database sql parameter ...", "Runs statements that don't return rows, returning how many rows changed."),
+    ("sqlite-query", "database sql parameter ...", "Runs a query, returning each row as a list of (column value) lists."),
+];
+#[cfg(not(feature = "sqlite"))]
+const SQLITE_DOCS: &[Entry] = &[];
+
+#[cfg(feature = "ffi")]
+const FFI_DOCS: &[Entry] = &[
+    ("load-foreign-library", "path", "Loads the shared library at path, for foreign-procedure."),
+    ("foreign-procedure", "library name parameter-types result-type", "A procedure calling the C function name in library, with arguments and result of the given types."),
+];
+#[cfg(not(feature = "ffi"))]
+const FFI_DOCS: &[Entry] = &[];
+
+// Builtins that make mutable objects, like box and make-queue, aren't pure even though they have no other effect, nor
+// are ones that depend on settings, like hash (on the seed) and display->string (on the printer options)
+const PURE: &[&str] = &[
+    "null?", "list", "car", "cdr", "cons", "append", "reverse", "length+", "list-copy", "last-pair", "proper-list?",
+    "circular-list?", "dotted-list?", "tree-size", "depth",
+    "keyword?", "keyword->string", "string->keyword", "eof-object?", "char?", "char->integer", "integer->char",
+    "string-length", "substring", "string-copy", "string->list", "list->string", "string-foldcase", "string-ci=?",
+    "string-ci<?", "string-ci>?", "string-ci<=?", "string-ci>=?", "value->cbor", "cbor->value",
+    "vector?", "vector", "vector-length", "vector-ref", "vector->list", "list->vector",
+    "vector-copy", "vector-append", "bytevector?", "bytevector", "bytevector-length", "bytevector-u8-ref", "bytevector-copy",
+    "+", "-", "*", "/", "<", ">", "=", "number->string",
+    "path-join", "path-directory", "path-filename", "path-extension", "path-absolute?",
+];
+
+pub fn is_pure(name: &str) -> bool {
+    PURE.contains(&name)
+}
+
+thread_local!(static PLUGIN_DOCS: RefCell<Vec<(String, String, String)>> = const { RefCell::new(Vec::new()) });
+
+// Document a plugin's builtin, replacing any documentation it had
+pub fn add(name: &str, parameters: &str, text: &str) {
+    PLUGIN_DOCS.with(|d| {
+        let mut docs = d.borrow_mut();
+        docs.retain(|entry| entry.0 != name);
+        docs.push((name.to_string(), parameters.to_string(), text.to_string()));
+    });
+}
+
+fn doc(name: &str, parameters: &str, text: &str) -> Doc {
+    Doc { name: name.to_string(), parameters: split_parameters(parameters), text: text.to_string(), pure: is_pure(name) }
+}
+
+// The parameters separated by spaces, keeping lists like ((name value) ...) and [radix [width]] whole
+fn split_parameters(parameters: &str) -> Vec<String> {
+    let mut split = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0;
+    for c in parameters.chars() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth -= 1,
+            ' ' if depth == 0 => {
+                if !current.is_empty() {
+                    split.push(current);
+                    current = String::new();
+                }
+                continue
+            },
+            _ => ()
+        }
+        current.push(c);
+    }
+    if !current.is_empty() {
+        split.push(current);
+    }
+    split
+}
+
+// Every documented builtin, in the order they're listed
+pub fn all() -> Vec<Doc> {
+    let mut docs = Vec::new();
+    for group in [CORE_DOCS, MATH_DOCS, IO_DOCS, NET_DOCS, PROCESS_DOCS, PARALLEL_DOCS, SQLITE_DOCS, FFI_DOCS].iter() {
+        for &(name, parameters, text) in group.iter() {
+            docs.push(doc(name, parameters, text));
+        }
+    }
+    PLUGIN_DOCS.with(|d| {
+        for (name, parameters, text) in d.borrow().iter() {
+            docs.push(doc(name, parameters, text));
+        }
+    });
+    docs
+}
+
+pub fn lookup(name: &str) -> Option<Doc> {
+    all().into_iter().find(|d| d.name == name)
+}
+
+#[test]
+fn test_docs() {
+    let substring = lookup("substring").unwrap();
+    assert_eq!(substring.arity(), (2, Some(3)));
+    assert_eq!(substring.render(), "(substring string start [end])\n    The characters of string from start up to end, or to its end.");
+    assert_eq!(lookup("list").unwrap().arity(), (0, None));
+    assert_eq!(lookup("warn").unwrap().arity(), (1, None));
+    if cfg!(feature = "math") {
+        assert_eq!(lookup("number->string").unwrap().arity(), (1, Some(3)));
+    }
+    let guard = lookup("guard").unwrap();
+    assert_eq!(guard.parameters, vec!["(name clause ...)", "body1", "body2", "..."]);
+    assert_eq!(guard.arity(), (2, None));
+    assert_eq!(lookup("make-queue").unwrap().usage(), "(make-queue x ...)");
+    assert_eq!(lookup("make-queue").unwrap().arity(), (0, None));
+    assert_eq!(lookup("features").unwrap().usage(), "(features)");
+    assert!(lookup("no-such-builtin").is_none());
+    assert!(lookup("car").unwrap().pure);
+    assert!(!lookup("box").unwrap().pure);
+    if cfg!(all(feature = "math", feature = "io")) {
+        for name in PURE.iter() {
+            assert!(lookup(name).is_some(), "{} isn't documented", name);
+        }
+    }
+
+    add("string-reverse", "string", "string backwards.");
+    assert_eq!(lookup("string-reverse").unwrap().usage(), "(string-reverse string)");
+
+    // no builtin is documented twice
+    let mut names: Vec<String> = all().into_iter().map(|d| d.name).collect();
+    let count = names.len();
+    names.sort();
+    names.dedup();
+    assert_eq!(names.len(), count);
+}
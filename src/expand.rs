@@ -0,0 +1,396 @@
+// Forms expanded before the code runs, each into a begin, so they can appear anywhere a begin can (e.g. inside a
+// define). (include "file" ...) and (include-ci "file" ...) become the forms read from the files. Paths are relative
+// to the file the include is in, or to the current directory for code that isn't from a file. include-ci reads the
+// files case-insensitively, folding the case of every identifier. (cond-expand (requirement body...) ...) becomes
+// the body of the first clause whose requirement the features registry satisfies.
+//
+// The interpreters leave macros to run time, but tooling can expand them ahead of time with expand, which also
+// replaces each (define-syntax-rule (name param...) template) with an empty begin and each use of the macro with its
+// template, the parameters filled in with the arguments. Macros are recorded in a MacroEnv, so expanding one batch of
+// code can use the macros another batch defined. Unlike at run time they aren't scoped: a macro defined anywhere can
+// be used anywhere after it, and a local variable with the same name doesn't hide it.
+
+use condition::Kind;
+use features;
+use lexer::ReaderConfig;
+use parser::{self, Node};
+use printer::{self, Style};
+use search_path;
+use text;
+use watch;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+pub struct ExpandError {
+    kind: Kind,
+    message: String,
+}
+
+impl ExpandError {
+    pub fn kind(&self) -> Kind {
+        self.kind
+    }
+}
+
+impl fmt::Display for ExpandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ExpandError: {}", self.message)
+    }
+}
+impl fmt::Debug for ExpandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ExpandError: {}", self.message)
+    }
+}
+
+macro_rules! expand_error {
+    ($kind:expr, $($arg:tt)*) => (
+        return Err(ExpandError { kind: $kind, message: message!($($arg)*) })
+    )
+}
+
+// How deeply macro uses can expand into further macro uses, so a macro that uses itself is an error instead of a
+// stack overflow
+const MAX_MACRO_DEPTH: usize = 256;
+
+// The macros defined so far, and where the code being expanded was read from
+#[derive(Default)]
+pub struct MacroEnv {
+    // Includes are relative to this file, or to the current directory if it's None
+    pub file: Option<PathBuf>,
+    pub reader: ReaderConfig,
+    macros: HashMap<String, Macro>,
+}
+
+#[derive(Clone)]
+struct Macro {
+    params: Vec<String>,
+    template: Node,
+}
+
+impl MacroEnv {
+    pub fn new() -> MacroEnv {
+        MacroEnv { file: None, reader: ReaderConfig::default(), macros: HashMap::new() }
+    }
+
+    pub fn is_macro(&self, name: &str) -> bool {
+        self.macros.contains_key(name)
+    }
+
+    // Define a macro that fills in template with the arguments for params, e.g. one an interpreter defined with
+    // define-syntax-rule
+    pub fn define(&mut self, name: &str, params: Vec<String>, template: Node) {
+        self.macros.insert(name.to_string(), Macro { params, template });
+    }
+}
+
+// Expand includes, cond-expand and macros, recording the macros defined along the way in env
+pub fn expand(nodes: Vec<Node>, env: &mut MacroEnv) -> Result<Vec<Node>, ExpandError> {
+    let MacroEnv { ref file, ref reader, ref mut macros } = *env;
+    let mut expander = Expander { config: reader, including: Vec::new(), macros: Some(macros), depth: 0 };
+    expander.expand_all(nodes, file.as_ref().map(|f| f.as_path()))
+}
+
+// Expand the includes (and cond-expands) in forms read from the given file, or from somewhere else if it's None,
+// leaving macros to the interpreter
+pub fn expand_includes(nodes: Vec<Node>, file: Option<&Path>, config: &ReaderConfig) -> Result<Vec<Node>, ExpandError> {
+    let mut expander = Expander { config, including: Vec::new(), macros: None, depth: 0 };
+    expander.expand_all(nodes, file)
+}
+
+// Expand just the first macro use in node, the outermost and then the leftmost, leaving any macro uses in what it
+// expands to for the next step. Returns the new form and the path to the expansion in it (the index of the list item
+// to go into at each level), or None if node has no macro uses left. Quoted data and macro definitions are left
+// alone, and includes aren't expanded.
+pub fn expand_step(node: &Node, env: &MacroEnv) -> Result<Option<(Node, Vec<usize>)>, ExpandError> {
+    let mut path = Vec::new();
+    Ok(try!(step(node, &env.macros, &mut path)).map(|expanded| (expanded, path)))
+}
+
+fn step(node: &Node, macros: &HashMap<String, Macro>, path: &mut Vec<usize>) -> Result<Option<Node>, ExpandError> {
+    let items = match *node {
+        Node::List(ref items) => items,
+        _ => return Ok(None)
+    };
+    if let Some(Node::Identifier(head)) = items.first() { match head.as_str() {
+        "quote" | "quasiquote" | "define-syntax-rule" => return Ok(None),
+        _ => if let Some(m) = macros.get(head) {
+            let args = &items[1..];
+            if args.len() != m.params.len() {
+                expand_error!(Kind::ArityError, "Must supply exactly {} arguments to {}: {}", m.params.len(), head, write_all(args));
+            }
+            let substitutions: HashMap<&str, &Node> = m.params.iter().map(|p| p.as_str()).zip(args.iter()).collect();
+            return Ok(Some(substitute(&m.template, &substitutions)))
+        }
+    } }
+    for (i, item) in items.iter().enumerate() {
+        path.push(i);
+        match try!(step(item, macros, path)) {
+            Some(expanded) => {
+                let mut items = items.clone();
+                items[i] = expanded;
+                return Ok(Some(Node::List(items)))
+            },
+            None => { path.pop(); }
+        }
+    }
+    Ok(None)
+}
+
+fn directory_of(file: &Path) -> PathBuf {
+    match file.parent() {
+        Some(dir) => dir.to_path_buf(),
+        None => PathBuf::new()
+    }
+}
+
+// What identifies a file for spotting cycles, however it was reached
+fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn begin(forms: Vec<Node>) -> Node {
+    let mut list = vec![Node::Identifier("begin".to_string())];
+    list.extend(forms);
+    Node::List(list)
+}
+
+fn fold_identifiers(node: Node) -> Node {
+    match node {
+        Node::Identifier(s) => Node::Identifier(text::fold_case(&s)),
+        Node::List(items) => Node::List(items.into_iter().map(fold_identifiers).collect()),
+        other => other
+    }
+}
+
+struct Expander<'a> {
+    config: &'a ReaderConfig,
+    // The files being included, outermost first, as written and canonicalized
+    including: Vec<(PathBuf, PathBuf)>,
+    // Where to record and look up macros, if they're being expanded
+    macros: Option<&'a mut HashMap<String, Macro>>,
+    // How many macro uses are being expanded inside each other
+    depth: usize,
+}
+
+impl<'a> Expander<'a> {
+    fn expand_all(&mut self, nodes: Vec<Node>, file: Option<&Path>) -> Result<Vec<Node>, ExpandError> {
+        let dir = match file {
+            Some(file) => {
+                self.including.push((file.to_path_buf(), canonical(file)));
+                directory_of(file)
+            },
+            None => PathBuf::new()
+        };
+        nodes.into_iter().map(|n| self.expand(n, &dir)).collect()
+    }
+
+    fn expand(&mut self, node: Node, dir: &Path) -> Result<Node, ExpandError> {
+        let items = match node {
+            Node::List(items) => items,
+            other => return Ok(other)
+        };
+        let head = match items.first() {
+            Some(Node::Identifier(s)) => s.clone(),
+            _ => String::new()
+        };
+        match head.as_ref() {
+            // quoted code is data, so an include in it stays as it is
+            "quote" | "quasiquote" => Ok(Node::List(items)),
+            "include" | "include-ci" => {
+                let mut forms = Vec::new();
+                for arg in items.into_iter().skip(1) {
+                    let name = match arg {
+                        Node::String(name) => name,
+                        other => expand_error!(Kind::TypeError, "{} expects file names as strings: {}", head, write(&other))
+                    };
+                    forms.extend(try!(self.include(&search_path::resolve(dir, &name), head == "include-ci")));
+                }
+                Ok(begin(forms))
+            },
+            "cond-expand" => {
+                for clause in items.into_iter().skip(1) {
+                    let mut parts = match clause {
+                        Node::List(parts) => parts,
+                        other => expand_error!(Kind::Error, "Expected a cond-expand clause, but found {}", write(&other))
+                    };
+                    if parts.is_empty() {
+                        expand_error!(Kind::Error, "Expected a cond-expand clause, but found an empty list");
+                    }
+                    let requirement = parts.remove(0);
+                    match features::satisfied(&requirement) {
+                        Ok(true) => return self.expand(begin(parts), dir),
+                        Ok(false) => (),
+                        Err(e) => expand_error!(Kind::Error, "Not a cond-expand requirement: {}", e)
+                    }
+                }
+                expand_error!(Kind::Error, "No matching cond-expand clause")
+            },
+            "define-syntax-rule" if self.macros.is_some() => {
+                let (name, m) = try!(syntax_rule(items));
+                self.macros.as_mut().unwrap().insert(name, m);
+                Ok(begin(Vec::new()))
+            },
+            _ => {
+                let found = self.macros.as_ref().and_then(|macros| macros.get(&head).cloned());
+                match found {
+                    Some(m) => self.expand_use(&head, m, items, dir),
+                    None => {
+                        let expanded: Result<Vec<Node>, ExpandError> = items.into_iter().map(|n| self.expand(n, dir)).collect();
+                        Ok(Node::List(try!(expanded)))
+                    }
+                }
+            }
+        }
+    }
+
+    // Fill in the macro's template with the arguments, then expand any macros that used
+    fn expand_use(&mut self, name: &str, m: Macro, items: Vec<Node>, dir: &Path) -> Result<Node, ExpandError> {
+        let args = &items[1..];
+        if args.len() != m.params.len() {
+            expand_error!(Kind::ArityError, "Must supply exactly {} arguments to {}: {}", m.params.len(), name, write_all(args));
+        }
+        if self.depth == MAX_MACRO_DEPTH {
+            expand_error!(Kind::Error, "Expanding {} went more than {} macros deep", name, MAX_MACRO_DEPTH);
+        }
+        let substitutions: HashMap<&str, &Node> = m.params.iter().map(|p| p.as_str()).zip(args.iter()).collect();
+        self.depth += 1;
+        let expanded = self.expand(substitute(&m.template, &substitutions), dir);
+        self.depth -= 1;
+        expanded
+    }
+
+    fn include(&mut self, path: &Path, fold_case: bool) -> Result<Vec<Node>, ExpandError> {
+        let id = canonical(path);
+        if self.including.iter().any(|i| i.1 == id) {
+            let chain: Vec<String> = self.including.iter().map(|i| i.0.display().to_string())
+                                         .chain(Some(path.display().to_string())).collect();
+            expand_error!(Kind::Error, "Circular include: {}", chain.join(" -> "));
+        }
+        watch::record(path);
+        let mut contents = String::new();
+        match File::open(path).and_then(|mut f| f.read_to_string(&mut contents)) {
+            Ok(_) => (),
+            Err(e) => expand_error!(Kind::FileError, "Couldn't include {}: {}", path.display(), e)
+        }
+        let mut nodes = match parser::parse_str_with(&contents, self.config) {
+            Ok(nodes) => nodes,
+            Err(e) => expand_error!(Kind::ReadError, "In {}: {}", path.display(), e)
+        };
+        if fold_case {
+            nodes = nodes.into_iter().map(fold_identifiers).collect();
+        }
+
+        self.including.push((path.to_path_buf(), id));
+        let dir = directory_of(path);
+        let expanded: Result<Vec<Node>, ExpandError> = nodes.into_iter().map(|n| self.expand(n, &dir)).collect();
+        self.including.pop();
+        expanded
+    }
+}
+
+// A node as it appears in an error message, written as Scheme
+fn write(node: &Node) -> String {
+    printer::print(node, Style::Write)
+}
+
+// The arguments of a form, written as a list
+fn write_all(nodes: &[Node]) -> String {
+    write(&Node::List(nodes.to_vec()))
+}
+
+// The name and macro of (define-syntax-rule (name param...) template)
+fn syntax_rule(mut items: Vec<Node>) -> Result<(String, Macro), ExpandError> {
+    if items.len() != 3 {
+        expand_error!(Kind::ArityError, "Must supply exactly two arguments to define-syntax-rule: {}", write_all(&items[1..]));
+    }
+    let template = items.pop().unwrap();
+    let pattern = match items.pop().unwrap() {
+        Node::List(pattern) => pattern,
+        other => expand_error!(Kind::Error, "Unexpected value for pattern in define-syntax-rule: {}", write(&other))
+    };
+    let mut names = Vec::new();
+    for node in pattern.into_iter() {
+        match node {
+            Node::Identifier(name) => names.push(name),
+            other => expand_error!(Kind::Error, "Unexpected argument in define-syntax-rule arguments: {}", write(&other))
+        }
+    }
+    if names.is_empty() {
+        expand_error!(Kind::Error, "Must supply a name in define-syntax-rule");
+    }
+    let name = names.remove(0);
+    Ok((name, Macro { params: names, template }))
+}
+
+fn substitute(node: &Node, substitutions: &HashMap<&str, &Node>) -> Node {
+    match *node {
+        Node::Identifier(ref s) => match substitutions.get(s.as_str()) {
+            Some(arg) => (*arg).clone(),
+            None => node.clone()
+        },
+        Node::List(ref items) => Node::List(items.iter().map(|n| substitute(n, substitutions)).collect()),
+        _ => node.clone()
+    }
+}
+
+#[test]
+fn test_include_leaves_quoted_code_alone() {
+    let nodes = parser::parse_str("'(include \"missing.scm\") (f (quasiquote (include-ci 1)))").unwrap();
+    assert_eq!(expand_includes(nodes.clone(), None, &ReaderConfig::default()).ok(), Some(nodes));
+    let nodes = parser::parse_str("(include 'missing)").unwrap();
+    assert_eq!(expand_includes(nodes, None, &ReaderConfig::default()).err().unwrap().to_string(),
+               "ExpandError: include expects file names as strings: (quote missing)");
+}
+
+#[test]
+fn test_cond_expand() {
+    let expanded = |s: &str| expand_includes(parser::parse_str(s).unwrap(), None, &ReaderConfig::default()).map(|n| n[0].clone());
+    assert_eq!(expanded("(cond-expand (spaceships 1) ((and r7rs (not spaceships)) 2 3) (else 4))").unwrap(),
+               begin(vec![Node::Integer(2), Node::Integer(3)]));
+    assert_eq!(expanded("(cond-expand (spaceships 1) (else (cond-expand (r7rs 2))))").unwrap(),
+               begin(vec![begin(vec![Node::Integer(2)])]));
+    assert_eq!(expanded("(cond-expand (spaceships 1))").err().unwrap().to_string(),
+               "ExpandError: No matching cond-expand clause");
+    assert_eq!(expanded("(cond-expand 1)").err().unwrap().to_string(),
+               "ExpandError: Expected a cond-expand clause, but found 1");
+    assert_eq!(expanded("(cond-expand ((library) 1))").err().unwrap().to_string(),
+               "ExpandError: Not a cond-expand requirement: (library)");
+}
+
+#[test]
+fn test_expand_macros() {
+    let mut env = MacroEnv::new();
+    let mut expanded = |s: &str| expand(parser::parse_str(s).unwrap(), &mut env);
+    assert_eq!(expanded("(define-syntax-rule (swap! a b) (let ((tmp a)) (set! a b) (set! b tmp))) (swap! x (car y))").unwrap(),
+               parser::parse_str("(begin) (let ((tmp x)) (set! x (car y)) (set! (car y) tmp))").unwrap());
+    // macros stay defined from one call to the next, and uses in the arguments or the result are expanded too
+    assert_eq!(expanded("(define-syntax-rule (unless c body) (if c #f body)) (unless a (unless b '(unless c d)))").unwrap(),
+               parser::parse_str("(begin) (if a #f (if b #f '(unless c d)))").unwrap());
+    assert_eq!(expanded("(swap! x)").err().unwrap().to_string(),
+               "ExpandError: Must supply exactly 2 arguments to swap!: (x)");
+    assert_eq!(expanded("(define-syntax-rule (forever x) (forever x)) (forever 1)").err().unwrap().to_string(),
+               "ExpandError: Expanding forever went more than 256 macros deep");
+    assert!(env.is_macro("swap!"));
+    // the interpreters expand macros as they run
+    let nodes = parser::parse_str("(define-syntax-rule (f x) x) (f 1)").unwrap();
+    assert_eq!(expand_includes(nodes.clone(), None, &ReaderConfig::default()).unwrap(), nodes);
+}
+
+#[test]
+fn test_expand_step() {
+    let mut env = MacroEnv::new();
+    env.define("unless", vec!["c".to_string(), "body".to_string()], parser::parse_str("(if c #f body)").unwrap().remove(0));
+    let stepped = |s: &str| expand_step(&parser::parse_str(s).unwrap()[0], &env);
+    // the outermost use first, leaving the ones inside it for later steps
+    assert_eq!(stepped("(unless a (unless b c))").unwrap(), Some((parser::parse_str("(if a #f (unless b c))").unwrap().remove(0), vec![])));
+    assert_eq!(stepped("(list 1 '(unless a b) (f (unless a b)))").unwrap(),
+               Some((parser::parse_str("(list 1 '(unless a b) (f (if a #f b)))").unwrap().remove(0), vec![3, 1])));
+    assert_eq!(stepped("(list (if a #f b))").unwrap(), None);
+    assert_eq!(stepped("(unless a)").err().unwrap().to_string(),
+               "ExpandError: Must supply exactly 2 arguments to unless: (a)");
+}
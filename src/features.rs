@@ -0,0 +1,106 @@
+// What cond-expand can test for: feature identifiers, listed by (features), and libraries, named as in (library
+// (srfi 1)). The defaults describe this build; embedders add their own with Interpreter::add_feature and
+// add_library. Like the printer settings, the registry is per thread.
+
+use parser::{self, Node};
+use printer::{self, Style};
+
+use std::cell::RefCell;
+
+struct Registry {
+    features: Vec<String>,
+    libraries: Vec<Node>,
+}
+
+thread_local!(static REGISTRY: RefCell<Registry> = RefCell::new(Registry { features: default_features(), libraries: Vec::new() }));
+
+fn default_features() -> Vec<String> {
+    let mut features = vec!["r7rs", "rusty-scheme", "full-unicode"];
+    // the crate features that are compiled in
+    if cfg!(feature = "io") { features.push("io") }
+    if cfg!(feature = "math") { features.push("math") }
+    if cfg!(feature = "net") { features.push("net") }
+    // on-signal, the only builtin process has so far, needs Unix signals
+    if cfg!(all(unix, feature = "process")) { features.push("process") }
+    if cfg!(feature = "parallel") { features.push("parallel") }
+    if cfg!(feature = "sqlite") { features.push("sqlite") }
+    if cfg!(feature = "ffi") { features.push("ffi") }
+    features.into_iter().map(|f| f.to_string()).collect()
+}
+
+pub fn features() -> Vec<String> {
+    REGISTRY.with(|r| r.borrow().features.clone())
+}
+
+pub fn add_feature(name: &str) {
+    REGISTRY.with(|r| {
+        let mut registry = r.borrow_mut();
+        if !registry.features.iter().any(|f| f == name) {
+            registry.features.push(name.to_string());
+        }
+    })
+}
+
+// A library's name is written as in Scheme, e.g. "(srfi 1)"
+pub fn add_library(name: &str) -> Result<(), String> {
+    let library = match parser::parse_str(name) {
+        Ok(ref mut nodes) if nodes.len() == 1 => nodes.remove(0),
+        _ => return Err(format!("Not a library name: {}", name))
+    };
+    REGISTRY.with(|r| r.borrow_mut().libraries.push(library));
+    Ok(())
+}
+
+// Whether a cond-expand requirement holds: a feature, (library name), (and req...), (or req...), (not req), or else
+pub fn satisfied(requirement: &Node) -> Result<bool, String> {
+    match *requirement {
+        Node::Identifier(ref name) => {
+            Ok(name == "else" || REGISTRY.with(|r| r.borrow().features.iter().any(|f| f == name)))
+        },
+        Node::List(ref items) if !items.is_empty() => {
+            let args = &items[1..];
+            match items[0] {
+                Node::Identifier(ref op) if op == "and" => {
+                    for arg in args.iter() {
+                        if !try!(satisfied(arg)) {
+                            return Ok(false)
+                        }
+                    }
+                    Ok(true)
+                },
+                Node::Identifier(ref op) if op == "or" => {
+                    for arg in args.iter() {
+                        if try!(satisfied(arg)) {
+                            return Ok(true)
+                        }
+                    }
+                    Ok(false)
+                },
+                Node::Identifier(ref op) if op == "not" && args.len() == 1 => Ok(!try!(satisfied(&args[0]))),
+                Node::Identifier(ref op) if op == "library" && args.len() == 1 => {
+                    Ok(REGISTRY.with(|r| r.borrow().libraries.iter().any(|l| *l == args[0])))
+                },
+                _ => Err(printer::print(requirement, Style::Write))
+            }
+        },
+        _ => Err(printer::print(requirement, Style::Write))
+    }
+}
+
+#[test]
+fn test_features() {
+    let requirement = |s: &str| parser::parse_str(s).unwrap().remove(0);
+    assert!(satisfied(&requirement("r7rs")).unwrap());
+    assert!(!satisfied(&requirement("spaceships")).unwrap());
+    assert!(satisfied(&requirement("(or spaceships (and r7rs (not spaceships)))")).unwrap());
+    assert!(!satisfied(&requirement("(library (srfi 1))")).unwrap());
+
+    add_feature("spaceships");
+    add_feature("spaceships");
+    add_library("(srfi 1)").unwrap();
+    assert_eq!(features().iter().filter(|f| *f == "spaceships").count(), 1);
+    assert!(satisfied(&requirement("(and spaceships (library (srfi 1)))")).unwrap());
+    assert!(!satisfied(&requirement("(library (srfi 2))")).unwrap());
+    assert!(add_library("srfi 1").is_err());
+    assert!(satisfied(&requirement("(not)")).is_err());
+}
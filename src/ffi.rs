@@ -0,0 +1,270 @@
+// Calling C functions from shared libraries, for load-foreign-library and foreign-procedure in both interpreters.
+// It's for extending the runtime without recompiling it, so nothing stops a script from passing a function the wrong
+// arguments and crashing the process: the types given to foreign-procedure are trusted to match the C declaration.
+//
+// The types are int, long, double, string (a const char * that the function mustn't keep, since it's freed after the
+// call) and void, which only a result can be. A string result is copied, and is #f if the function returns NULL.
+// Functions take at most six integer and string arguments and at most eight doubles.
+
+use parser::Node;
+use printer::{self, Style};
+
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::mem;
+use std::os::raw::{c_char, c_double, c_int, c_long, c_void};
+use std::ptr;
+use std::rc::Rc;
+
+const RTLD_NOW: c_int = 2;
+
+#[cfg_attr(target_os = "linux", link(name = "dl"))]
+extern "C" {
+    fn dlopen(filename: *const c_char, flags: c_int) -> *mut c_void;
+    fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+    fn dlclose(handle: *mut c_void) -> c_int;
+    fn dlerror() -> *const c_char;
+}
+
+// The reason the last dl* call failed
+fn last_error() -> String {
+    let error = unsafe { dlerror() };
+    if error.is_null() {
+        "unknown error".to_string()
+    } else {
+        unsafe { CStr::from_ptr(error) }.to_string_lossy().into_owned()
+    }
+}
+
+// A loaded library, unloaded when the last reference to it, including from its procedures, goes away
+pub struct Library {
+    handle: *mut c_void,
+    path: String,
+}
+
+impl Library {
+    // Load the library at path, which dlopen looks for on the usual library path if it has no slashes
+    pub fn open(path: &str) -> Result<Library, String> {
+        let name = try!(CString::new(path).map_err(|_| message!("Can't load a library whose name contains NUL: {:?}", path)));
+        let handle = unsafe { dlopen(name.as_ptr(), RTLD_NOW) };
+        if handle.is_null() {
+            return Err(message!("Couldn't load the library {}: {}", path, last_error()))
+        }
+        Ok(Library { handle, path: path.to_string() })
+    }
+
+    // The address of the function or variable called name
+    pub fn symbol(&self, name: &str) -> Result<*mut c_void, String> {
+        let symbol = try!(CString::new(name).map_err(|_| message!("Can't look up a name that contains NUL: {:?}", name)));
+        // clear any earlier error, since a symbol can legitimately be NULL
+        unsafe { dlerror() };
+        let address = unsafe { dlsym(self.handle, symbol.as_ptr()) };
+        if address.is_null() {
+            return Err(message!("Couldn't find {} in {}: {}", name, self.path, last_error()))
+        }
+        Ok(address)
+    }
+}
+
+impl Drop for Library {
+    fn drop(&mut self) {
+        unsafe { dlclose(self.handle) };
+    }
+}
+
+// A library is only equal to itself
+impl PartialEq for Library {
+    fn eq(&self, other: &Library) -> bool {
+        ptr::eq(self, other)
+    }
+}
+
+impl fmt::Debug for Library {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#<foreign-library {}>", self.path)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Type {
+    Int,
+    Long,
+    Double,
+    String,
+    Void,
+}
+
+impl Type {
+    fn from_name(name: &str) -> Result<Type, String> {
+        match name {
+            "int" => Ok(Type::Int),
+            "long" => Ok(Type::Long),
+            "double" => Ok(Type::Double),
+            "string" => Ok(Type::String),
+            "void" => Ok(Type::Void),
+            _ => Err(message!("Unknown foreign type {} (expected int, long, double, string or void)", name))
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match *self {
+            Type::Int => "int",
+            Type::Long => "long",
+            Type::Double => "double",
+            Type::String => "string",
+            Type::Void => "void",
+        }
+    }
+}
+
+// A C function from a library, called with arguments converted to the C types it was declared with
+pub struct Procedure {
+    // Keeps the library loaded for as long as the function can be called
+    _library: Rc<Library>,
+    name: String,
+    address: *mut c_void,
+    parameters: Vec<Type>,
+    result: Type,
+}
+
+impl Procedure {
+    // Look up the function called name, with parameters and result as type names, e.g. ["long"] and "long" for labs
+    pub fn new(library: Rc<Library>, name: &str, parameters: &[String], result: &str) -> Result<Procedure, String> {
+        let mut types = Vec::new();
+        for parameter in parameters.iter() {
+            match try!(Type::from_name(parameter)) {
+                Type::Void => return Err(message!("A foreign procedure can't take a void argument: {}", name)),
+                t => types.push(t)
+            }
+        }
+        let doubles = types.iter().filter(|t| **t == Type::Double).count();
+        if types.len() - doubles > MAX_WORDS || doubles > MAX_DOUBLES {
+            return Err(message!("A foreign procedure can take at most {} integer or string arguments and {} doubles: {}",
+                                MAX_WORDS, MAX_DOUBLES, name))
+        }
+        let address = try!(library.symbol(name));
+        Ok(Procedure { _library: library, name: name.to_string(), address, parameters: types,
+                       result: try!(Type::from_name(result)) })
+    }
+
+    pub fn call(&self, args: &[Node]) -> Result<Node, String> {
+        if args.len() != self.parameters.len() {
+            return Err(message!("{} takes {} arguments, not {}", self.name, self.parameters.len(), args.len()))
+        }
+        // the strings have to outlive the call
+        let mut strings = Vec::new();
+        let mut words: Vec<c_long> = Vec::new();
+        let mut doubles: Vec<c_double> = Vec::new();
+        for (arg, t) in args.iter().zip(self.parameters.iter()) {
+            let word = match (*t, arg) {
+                (Type::Double, &Node::Float(f)) => {
+                    doubles.push(f);
+                    continue
+                },
+                (Type::Double, &Node::Integer(n)) => {
+                    doubles.push(n as c_double);
+                    continue
+                },
+                (Type::Int, &Node::Integer(n)) if n >= c_int::MIN as i64 && n <= c_int::MAX as i64 => n as c_long,
+                (Type::Long, &Node::Integer(n)) => n as c_long,
+                (Type::String, Node::String(s)) => {
+                    let s = try!(CString::new(s.as_str()).map_err(|_| message!("Can't pass a string containing NUL to {}", self.name)));
+                    let pointer = s.as_ptr() as c_long;
+                    strings.push(s);
+                    pointer
+                },
+                _ => return Err(message!("Can't pass {} to {} as {}", printer::print(arg, Style::Write), self.name, t.name()))
+            };
+            words.push(word);
+        }
+        if self.result == Type::Double {
+            return Ok(Node::Float(unsafe { call_for_double(self.address, &words, &doubles) }))
+        }
+        let word = unsafe { call_for_word(self.address, &words, &doubles) };
+        Ok(match self.result {
+            Type::Double => unreachable!(),
+            Type::Int => Node::Integer(word as c_int as i64),
+            Type::Long => Node::Integer(word as i64),
+            Type::String if word == 0 => Node::Boolean(false),
+            Type::String => Node::String(unsafe { CStr::from_ptr(word as *const c_char) }.to_string_lossy().into_owned()),
+            Type::Void => Node::List(Vec::new())
+        })
+    }
+}
+
+// The C calling conventions this runs on (System V on x86-64, and AArch64's) pass the first integer and pointer
+// arguments in general purpose registers and the first doubles in floating point ones, each kind in order whatever
+// order the declaration mixes them in, and an int in a whole register. So every function is called as if it took all
+// of both kinds of register, padded with zeros: it only reads the ones its declaration says it takes.
+const MAX_WORDS: usize = 6;
+const MAX_DOUBLES: usize = 8;
+
+type W = c_long;
+type D = c_double;
+
+fn registers(words: &[W], doubles: &[D]) -> ([W; MAX_WORDS], [D; MAX_DOUBLES]) {
+    let mut w = [0; MAX_WORDS];
+    let mut d = [0.0; MAX_DOUBLES];
+    w[..words.len()].copy_from_slice(words);
+    d[..doubles.len()].copy_from_slice(doubles);
+    (w, d)
+}
+
+unsafe fn call_for_word(address: *mut c_void, words: &[W], doubles: &[D]) -> W {
+    let (w, d) = registers(words, doubles);
+    let f = mem::transmute::<*mut c_void, extern "C" fn(W, W, W, W, W, W, D, D, D, D, D, D, D, D) -> W>(address);
+    f(w[0], w[1], w[2], w[3], w[4], w[5], d[0], d[1], d[2], d[3], d[4], d[5], d[6], d[7])
+}
+
+// The same, for a function returning a double, which comes back in a floating point register
+unsafe fn call_for_double(address: *mut c_void, words: &[W], doubles: &[D]) -> D {
+    let (w, d) = registers(words, doubles);
+    let f = mem::transmute::<*mut c_void, extern "C" fn(W, W, W, W, W, W, D, D, D, D, D, D, D, D) -> D>(address);
+    f(w[0], w[1], w[2], w[3], w[4], w[5], d[0], d[1], d[2], d[3], d[4], d[5], d[6], d[7])
+}
+
+// A procedure is only equal to itself
+impl PartialEq for Procedure {
+    fn eq(&self, other: &Procedure) -> bool {
+        ptr::eq(self, other)
+    }
+}
+
+impl fmt::Debug for Procedure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#<foreign-procedure {}>", self.name)
+    }
+}
+
+#[test]
+fn test_ffi() {
+    // the C library, which dlopen(NULL) would also find, but by name is how scripts will load it
+    let libc = Rc::new(Library::open(if cfg!(target_os = "linux") { "libc.so.6" } else { "libSystem.dylib" }).unwrap());
+    let labs = Procedure::new(libc.clone(), "labs", &["long".to_string()], "long").unwrap();
+    assert_eq!(labs.call(&[Node::Integer(-5)]), Ok(Node::Integer(5)));
+    assert_eq!(labs.call(&[]), Err("labs takes 1 arguments, not 0".to_string()));
+    assert_eq!(labs.call(&[Node::String("5".to_string())]), Err("Can't pass \"5\" to labs as long".to_string()));
+    let strlen = Procedure::new(libc.clone(), "strlen", &["string".to_string()], "long").unwrap();
+    assert_eq!(strlen.call(&[Node::String("hello".to_string())]), Ok(Node::Integer(5)));
+    let getenv = Procedure::new(libc.clone(), "getenv", &["string".to_string()], "string").unwrap();
+    assert_eq!(getenv.call(&[Node::String("RUSTY_SCHEME_UNSET".to_string())]), Ok(Node::Boolean(false)));
+    let abs = Procedure::new(libc.clone(), "abs", &["int".to_string()], "int").unwrap();
+    assert_eq!(abs.call(&[Node::Integer(-7)]), Ok(Node::Integer(7)));
+    assert!(abs.call(&[Node::Integer(1 << 40)]).is_err());
+
+    assert!(Procedure::new(libc.clone(), "no_such_function", &[], "void").unwrap_err().starts_with("Couldn't find no_such_function in "));
+    let libm = Rc::new(Library::open(if cfg!(target_os = "linux") { "libm.so.6" } else { "libSystem.dylib" }).unwrap());
+    let cos = Procedure::new(libm.clone(), "cos", &["double".to_string()], "double").unwrap();
+    assert_eq!(cos.call(&[Node::Float(0.0)]), Ok(Node::Float(1.0)));
+    assert_eq!(cos.call(&[Node::Integer(0)]), Ok(Node::Float(1.0)));
+    let ldexp = Procedure::new(libm.clone(), "ldexp", &["double".to_string(), "int".to_string()], "double").unwrap();
+    assert_eq!(ldexp.call(&[Node::Float(1.5), Node::Integer(3)]), Ok(Node::Float(12.0)));
+    let ilogb = Procedure::new(libm.clone(), "ilogb", &["double".to_string()], "int").unwrap();
+    assert_eq!(ilogb.call(&[Node::Float(1024.0)]), Ok(Node::Integer(10)));
+    assert_eq!(ldexp.call(&[Node::Integer(1), Node::Float(2.0)]), Err("Can't pass 2.0 to ldexp as int".to_string()));
+    let nine = vec!["double".to_string(); 9];
+    assert_eq!(Procedure::new(libm.clone(), "cos", &nine, "double").unwrap_err(),
+               "A foreign procedure can take at most 6 integer or string arguments and 8 doubles: cos");
+    assert!(Library::open("no-such-library.so").unwrap_err().starts_with("Couldn't load the library no-such-library.so: "));
+    assert_eq!(format!("{:?}", labs), "#<foreign-procedure labs>");
+}
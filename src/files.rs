@@ -0,0 +1,203 @@
+// Finding files, for directory-walk and glob in both interpreters. Paths are strings as the script wrote them, with
+// names joined on with /, and come back sorted so scripts run the same way on every filesystem.
+//
+// A glob pattern is matched a path component at a time: * matches any run of characters and ? any one, [abc],
+// [a-z] and [!abc] match one character in (or not in) the set, and a ** component matches any number of directories,
+// including none. As in the shell, a name starting with a dot is only matched by a pattern that starts with one.
+
+use std::fs;
+
+// Every file under root, depth first with each directory's entries sorted by name. Symbolic links are listed rather
+// than followed, so a link back up the tree can't send the walk round in circles.
+pub fn walk(root: &str) -> Result<Vec<String>, String> {
+    let mut files = Vec::new();
+    match fs::symlink_metadata(root) {
+        Ok(ref metadata) if metadata.is_dir() => try!(walk_into(root, &mut files)),
+        Ok(_) => files.push(root.to_string()),
+        Err(e) => return Err(message!("Couldn't read {}: {}", root, e))
+    }
+    Ok(files)
+}
+
+fn walk_into(dir: &str, files: &mut Vec<String>) -> Result<(), String> {
+    let entries = match read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => return Err(message!("Couldn't read the directory {}: {}", dir, e))
+    };
+    for (name, is_dir) in entries {
+        let path = join(dir, &name);
+        if is_dir {
+            try!(walk_into(&path, files));
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+// The paths matching pattern, sorted, or none if nothing does
+pub fn glob(pattern: &str) -> Vec<String> {
+    let (root, rest) = match pattern.strip_prefix('/') {
+        Some(rest) => ("/", rest),
+        None => ("", pattern)
+    };
+    let components: Vec<&str> = rest.split('/').filter(|c| !c.is_empty()).collect();
+    let mut found = Vec::new();
+    glob_in(root, &components, &mut found);
+    // different ways through a ** can reach the same path
+    found.sort();
+    found.dedup();
+    found
+}
+
+// Match components against what's in dir, which is "" for the current directory. Directories that can't be read
+// just don't match anything, as in the shell.
+fn glob_in(dir: &str, components: &[&str], found: &mut Vec<String>) {
+    let (first, rest) = match components.split_first() {
+        Some((first, rest)) => (*first, rest),
+        None => return
+    };
+    if first == "**" {
+        // at the end, ** is everything below
+        if rest.is_empty() {
+            return glob_in(dir, &["**", "*"], found)
+        }
+        glob_in(dir, rest, found);
+        for (name, is_dir) in read_dir(if dir.is_empty() { "." } else { dir }).unwrap_or_default() {
+            if is_dir && !name.starts_with('.') {
+                glob_in(&join(dir, &name), components, found);
+            }
+        }
+        return
+    }
+    if !first.contains(['*', '?', '[']) {
+        let path = join(dir, first);
+        if rest.is_empty() {
+            if fs::symlink_metadata(&path).is_ok() {
+                found.push(path);
+            }
+        } else if fs::metadata(&path).map(|m| m.is_dir()).unwrap_or(false) {
+            glob_in(&path, rest, found);
+        }
+        return
+    }
+    for (name, is_dir) in read_dir(if dir.is_empty() { "." } else { dir }).unwrap_or_default() {
+        if matches(first, &name) {
+            let path = join(dir, &name);
+            if rest.is_empty() {
+                found.push(path);
+            } else if is_dir {
+                glob_in(&path, rest, found);
+            }
+        }
+    }
+}
+
+// The names in dir sorted, each with whether it's a directory (not following symbolic links)
+fn read_dir(dir: &str) -> Result<Vec<(String, bool)>, String> {
+    let mut entries = Vec::new();
+    for entry in try!(fs::read_dir(dir).map_err(|e| e.to_string())) {
+        let entry = try!(entry.map_err(|e| e.to_string()));
+        let is_dir = entry.file_type().map(|t| t.is_dir()).unwrap_or(false);
+        entries.push((entry.file_name().to_string_lossy().into_owned(), is_dir));
+    }
+    entries.sort();
+    Ok(entries)
+}
+
+fn join(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", dir.trim_end_matches('/'), name)
+    }
+}
+
+// Whether a single path component matches a pattern component
+fn matches(pattern: &str, name: &str) -> bool {
+    if name.starts_with('.') && !pattern.starts_with('.') {
+        return false
+    }
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    matches_from(&pattern, &name)
+}
+
+fn matches_from(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        // try every split, shortest first; patterns are short enough that backtracking is fine
+        Some(&'*') => (0..name.len() + 1).any(|i| matches_from(&pattern[1..], &name[i..])),
+        Some(&'?') => !name.is_empty() && matches_from(&pattern[1..], &name[1..]),
+        Some(&'[') => match (name.first(), class(&pattern[1..])) {
+            (Some(&c), Some((set, negated, length))) => {
+                let found = set.iter().any(|&(low, high)| low <= c && c <= high);
+                found != negated && matches_from(&pattern[length + 1..], &name[1..])
+            },
+            (Some(&c), None) => c == '[' && matches_from(&pattern[1..], &name[1..]),
+            (None, _) => false
+        },
+        Some(&p) => name.first() == Some(&p) && matches_from(&pattern[1..], &name[1..])
+    }
+}
+
+type Class = (Vec<(char, char)>, bool, usize);
+
+// The character ranges of a [...] class whose opening bracket has been taken, whether it starts with !, and how many
+// characters it takes up including the closing bracket. None if it isn't closed, so the [ is an ordinary character.
+fn class(pattern: &[char]) -> Option<Class> {
+    let negated = pattern.first() == Some(&'!');
+    let mut i = if negated { 1 } else { 0 };
+    let mut set = Vec::new();
+    // a ] straight after the [ is part of the set
+    let start = i;
+    while i < pattern.len() && (pattern[i] != ']' || i == start) {
+        if i + 2 < pattern.len() && pattern[i + 1] == '-' && pattern[i + 2] != ']' {
+            set.push((pattern[i], pattern[i + 2]));
+            i += 3;
+        } else {
+            set.push((pattern[i], pattern[i]));
+            i += 1;
+        }
+    }
+    if i < pattern.len() { Some((set, negated, i + 1)) } else { None }
+}
+
+#[test]
+fn test_files() {
+    use std::env;
+
+    assert!(matches("*.scm", "main.scm"));
+    assert!(!matches("*.scm", "main.rs"));
+    assert!(!matches("*.scm", ".hidden.scm"));
+    assert!(matches(".*", ".hidden.scm"));
+    assert!(matches("a?c", "abc"));
+    assert!(matches("[a-c]x[!0-9]", "bxy"));
+    assert!(!matches("[a-c]x[!0-9]", "bx1"));
+    assert!(matches("[]]", "]"));
+    assert!(matches("[a", "[a"));
+    assert!(matches("*a*b*", "xxaxxbxx"));
+
+    let dir = env::temp_dir().join("rusty_scheme_files");
+    let _ = fs::remove_dir_all(&dir);
+    for path in ["src/lib", "src/.git", "docs"].iter() {
+        fs::create_dir_all(dir.join(path)).unwrap();
+    }
+    for file in ["README", "src/main.scm", "src/lib/list.scm", "src/lib/list.rs", "src/.git/HEAD", "docs/guide.scm"].iter() {
+        fs::write(dir.join(file), "").unwrap();
+    }
+    let root = dir.to_string_lossy().into_owned();
+    let under = |paths: &[&str]| -> Vec<String> { paths.iter().map(|p| format!("{}/{}", root, p)).collect() };
+
+    assert_eq!(walk(&root), Ok(under(&["README", "docs/guide.scm", "src/.git/HEAD", "src/lib/list.rs", "src/lib/list.scm", "src/main.scm"])));
+    assert_eq!(walk(&format!("{}/README", root)), Ok(under(&["README"])));
+    assert!(walk(&format!("{}/missing", root)).unwrap_err().starts_with("Couldn't read "));
+
+    assert_eq!(glob(&format!("{}/src/**/*.scm", root)), under(&["src/lib/list.scm", "src/main.scm"]));
+    assert_eq!(glob(&format!("{}/**/*.scm", root)), under(&["docs/guide.scm", "src/lib/list.scm", "src/main.scm"]));
+    assert_eq!(glob(&format!("{}/src/**", root)), under(&["src/lib", "src/lib/list.rs", "src/lib/list.scm", "src/main.scm"]));
+    assert_eq!(glob(&format!("{}/*/*.scm", root)), under(&["docs/guide.scm", "src/main.scm"]));
+    assert_eq!(glob(&format!("{}/src/lib/list.[rs]*", root)), under(&["src/lib/list.rs", "src/lib/list.scm"]));
+    assert_eq!(glob(&format!("{}/src/.git/HEAD", root)), under(&["src/.git/HEAD"]));
+    assert_eq!(glob(&format!("{}/nothing/*", root)), Vec::<String>::new());
+}
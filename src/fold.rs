@@ -0,0 +1,216 @@
+// Constant folding, which Builder::fold_constants turns on: a call to a pure builtin (see docs::is_pure) whose
+// arguments are all literals is evaluated before the code runs and replaced by its result, so (string-length "hello")
+// becomes 5. Arguments are folded first, so nested calls fold from the inside out, and macro templates are folded
+// too, which is where code that expands macros gets most of its constants.
+//
+// A call is only folded if nothing in the code being folded could bind its name to anything else: a name defined,
+// set! or bound as a variable anywhere in it (or a macro parameter) isn't folded anywhere in it. Calls that fail are
+// left to fail when they run, and quoted data and the arguments of macro uses are left alone, since they aren't
+// code yet. Like the interpreters' macros, templates aren't hygienic, so a folded template doesn't see a local
+// variable shadowing a builtin where the macro is used.
+
+use docs;
+use parser::Node;
+
+use std::collections::HashSet;
+
+// What folding needs from an interpreter
+pub trait Folder {
+    // Whether name is bound to a macro
+    fn is_macro(&self, name: &str) -> bool;
+
+    // The result of evaluating a call, if it can be folded: the builtin it calls is still bound to its name, the
+    // call succeeds and its result is plain data
+    fn call(&self, call: &Node) -> Option<Node>;
+}
+
+pub fn fold<F: Folder>(nodes: Vec<Node>, folder: &F) -> Vec<Node> {
+    let mut bound = HashSet::new();
+    let mut macros = HashSet::new();
+    for node in nodes.iter() {
+        find_bindings(node, &mut bound, &mut macros);
+    }
+    let folding = Folding { folder, bound, macros };
+    nodes.into_iter().map(|n| folding.fold(n)).collect()
+}
+
+struct Folding<'a, F: 'a> {
+    folder: &'a F,
+    // Names that might not refer to their builtin somewhere in the code
+    bound: HashSet<String>,
+    // Macros the code defines
+    macros: HashSet<String>,
+}
+
+impl<'a, F: Folder> Folding<'a, F> {
+    fn fold(&self, node: Node) -> Node {
+        let items = match node {
+            Node::List(items) => items,
+            _ => return node
+        };
+        let head = match items.first() {
+            Some(Node::Identifier(name)) => Some(name.clone()),
+            _ => None
+        };
+        match head.as_deref() {
+            Some("quote") | Some("quasiquote") | Some("autoload") => return Node::List(items),
+            Some("define-syntax-rule") => {
+                // the pattern stays, the template is code once it's expanded
+                let mut items = items.into_iter();
+                let mut folded: Vec<Node> = items.by_ref().take(2).collect();
+                folded.extend(items.map(|n| self.fold(n)));
+                return Node::List(folded)
+            },
+            Some(name) if self.macros.contains(name) || self.folder.is_macro(name) => return Node::List(items),
+            _ => ()
+        }
+        let items: Vec<Node> = items.into_iter().map(|n| self.fold(n)).collect();
+        match head {
+            Some(ref name) if docs::is_pure(name) && !self.bound.contains(name) && items[1..].iter().all(is_literal) => {
+                let call = Node::List(items);
+                match self.folder.call(&call).and_then(literal) {
+                    Some(result) => result,
+                    None => call
+                }
+            },
+            _ => Node::List(items)
+        }
+    }
+}
+
+// Whether node evaluates to itself, or is quoted
+fn is_literal(node: &Node) -> bool {
+    match *node {
+        Node::Integer(_) | Node::Rational(..) | Node::Float(_) | Node::String(_) | Node::Boolean(_) | Node::Character(_)
+            | Node::Keyword(_) | Node::Vector(_) | Node::Bytevector(_) => true,
+        Node::List(ref items) => items.len() == 2 && items[0] == Node::Identifier("quote".to_string()),
+        _ => false
+    }
+}
+
+// The expression that evaluates to data, quoting it if it has to be
+fn literal(data: Node) -> Option<Node> {
+    match data {
+        Node::Identifier(_) | Node::List(_) | Node::DottedList(..) => Some(Node::List(vec![Node::Identifier("quote".to_string()), data])),
+        Node::Integer(_) | Node::Rational(..) | Node::Float(_) | Node::String(_) | Node::Boolean(_) | Node::Character(_)
+            | Node::Keyword(_) | Node::Vector(_) | Node::Bytevector(_) => Some(data),
+        _ => None
+    }
+}
+
+// Add the names node binds anywhere in it to bound, and the macros it defines to macros as well
+fn find_bindings(node: &Node, bound: &mut HashSet<String>, macros: &mut HashSet<String>) {
+    let items = match *node {
+        Node::List(ref items) if !items.is_empty() => items,
+        _ => return
+    };
+    let head = match items[0] {
+        Node::Identifier(ref name) => name.as_str(),
+        _ => ""
+    };
+    match (head, items.get(1)) {
+        ("quote", _) | ("quasiquote", _) => return,
+        ("define", Some(&Node::Identifier(ref name))) | ("set!", Some(&Node::Identifier(ref name))) => {
+            bound.insert(name.clone());
+        },
+        ("define", Some(&Node::List(ref signature))) | ("lambda", Some(&Node::List(ref signature)))
+            | ("λ", Some(&Node::List(ref signature))) => add_names(signature, bound),
+        ("lambda", Some(&Node::Identifier(ref name))) | ("λ", Some(&Node::Identifier(ref name))) => {
+            bound.insert(name.clone());
+        },
+        ("define-syntax-rule", Some(Node::List(pattern))) => {
+            add_names(pattern, bound);
+            if let Some(Node::Identifier(name)) = pattern.first() {
+                macros.insert(name.clone());
+            }
+        },
+        ("let", _) | ("letrec", _) | ("letrec*", _) => {
+            // a named let binds its name as well as its variables
+            let bindings = match items.get(1) {
+                Some(Node::Identifier(name)) => {
+                    bound.insert(name.clone());
+                    items.get(2)
+                },
+                bindings => bindings
+            };
+            if let Some(Node::List(bindings)) = bindings {
+                for binding in bindings.iter() {
+                    if let Node::List(ref binding) = *binding {
+                        add_names(&binding[..binding.len().min(1)], bound);
+                    }
+                }
+            }
+        },
+        ("guard", Some(Node::List(clauses))) => add_names(&clauses[..clauses.len().min(1)], bound),
+        _ => ()
+    }
+    for item in items.iter() {
+        find_bindings(item, bound, macros);
+    }
+}
+
+fn add_names(nodes: &[Node], bound: &mut HashSet<String>) {
+    for node in nodes.iter() {
+        if let Node::Identifier(ref name) = *node {
+            bound.insert(name.clone());
+        }
+    }
+}
+
+#[test]
+fn test_fold() {
+    use parser;
+    use printer::{self, Style};
+
+    // Folds calls to + and string-length by adding up integers and measuring strings, as long as they're literals
+    struct Arithmetic;
+
+    impl Folder for Arithmetic {
+        fn is_macro(&self, name: &str) -> bool {
+            name == "my-quote"
+        }
+
+        fn call(&self, call: &Node) -> Option<Node> {
+            match *call {
+                Node::List(ref items) => match (&items[0], &items[1..]) {
+                    (Node::Identifier(f), args) if f == "+" => {
+                        let mut sum = 0;
+                        for arg in args.iter() {
+                            match *arg {
+                                Node::Integer(n) => sum += n,
+                                _ => return None
+                            }
+                        }
+                        Some(Node::Integer(sum))
+                    },
+                    (Node::Identifier(f), [Node::String(ref s)]) if f == "string-length" => Some(Node::Integer(s.len() as i64)),
+                    (Node::Identifier(f), args) if f == "list" => Some(Node::List(args.to_vec())),
+                    _ => None
+                },
+                _ => None
+            }
+        }
+    }
+
+    fn folded(code: &str) -> String {
+        let nodes = fold(parser::parse_str(code).unwrap(), &Arithmetic);
+        nodes.iter().map(|n| printer::print(n, Style::Write)).collect::<Vec<String>>().join(" ")
+    }
+
+    assert_eq!(folded("(+ 1 (string-length \"hello\") (+ 2 3))"), "11");
+    assert_eq!(folded("(define (f x) (+ x (+ 1 2)))"), "(define (f x) (+ x 3))");
+    assert_eq!(folded("(list 1 (+ 1 2))"), "(quote (1 3))");
+    // errors are left to happen at run time
+    assert_eq!(folded("(+ 1 \"2\")"), "(+ 1 \"2\")");
+    // neither quoted data nor macro arguments are code
+    assert_eq!(folded("'(+ 1 2) `(+ 1 ,(+ 2 3)) (my-quote (+ 1 2))"), "(quote (+ 1 2)) (quasiquote (+ 1 (unquote (+ 2 3)))) (my-quote (+ 1 2))");
+    assert_eq!(folded("(define-syntax-rule (q x) 'x) (q (+ 1 2))"), "(define-syntax-rule (q x) (quote x)) (q (+ 1 2))");
+    // macro templates are
+    assert_eq!(folded("(define-syntax-rule (k) (+ 1 2))"), "(define-syntax-rule (k) 3)");
+    assert_eq!(folded("(define-syntax-rule (k +) (+ 1 2))"), "(define-syntax-rule (k +) (+ 1 2))");
+    // a name bound anywhere isn't folded anywhere
+    assert_eq!(folded("(+ 1 2) (let ((+ -)) (+ 1 2))"), "(+ 1 2) (let ((+ -)) (+ 1 2))");
+    assert_eq!(folded("(string-length \"a\") (define (f string-length) 1)"), "(string-length \"a\") (define (f string-length) 1)");
+    assert_eq!(folded("(+ 1 2) (set! + -)"), "(+ 1 2) (set! + -)");
+    assert_eq!(folded("(let loop ((list 1)) (list 2)) (+ 1 2)"), "(let loop ((list 1)) (list 2)) 3");
+}
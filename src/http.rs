@@ -0,0 +1,439 @@
+// A minimal HTTP/1.1 client for http-get and http-post, and server for serve-http, shared by both interpreters. Each
+// request opens its own connection and asks the server to close it, so the response is everything read until then.
+// The server works the same way from the other side: it answers one request per connection, one connection at a
+// time, on the interpreter's own thread, so a handler never runs alongside another. Plain http only: there's no TLS
+// implementation to build https on yet.
+
+use parser::Node;
+use printer::{self, Style};
+
+use std::fmt;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::ptr;
+use std::time::Duration;
+
+const TIMEOUT_SECONDS: u64 = 30;
+
+// The most a request's head or body can take up, so a client can't make the server hold on to any amount of memory
+const MAX_REQUEST_HEAD: usize = 64 * 1024;
+const MAX_REQUEST_BODY: usize = 16 * 1024 * 1024;
+
+// Header names and values, in the order they were given
+pub type Headers = Vec<(String, String)>;
+
+pub struct Response {
+    pub status: u16,
+    pub headers: Headers,
+    // Decoded as UTF-8, with any invalid sequences replaced
+    pub body: String,
+}
+
+impl Response {
+    // (status ((name value) ...) body), as http-get and http-post return it
+    pub fn to_node(&self) -> Node {
+        let headers = self.headers.iter().map(|(name, value)| {
+            Node::List(vec![Node::String(name.clone()), Node::String(value.clone())])
+        }).collect();
+        Node::List(vec![Node::Integer(self.status as i64), Node::List(headers), Node::String(self.body.clone())])
+    }
+}
+
+pub fn request(method: &str, url: &str, headers: &[(String, String)], body: Option<&str>) -> Result<Response, String> {
+    let (host, port, path) = try!(parse_url(url));
+    let mut stream = match TcpStream::connect((host.as_str(), port)) {
+        Ok(stream) => stream,
+        Err(e) => return Err(message!("Couldn't connect to {}: {}", url, e))
+    };
+    let timeout = Some(Duration::from_secs(TIMEOUT_SECONDS));
+    let _ = stream.set_read_timeout(timeout).and_then(|_| stream.set_write_timeout(timeout));
+
+    let host_header = if host.contains(':') { format!("[{}]", host) } else { host.clone() };
+    let host_header = if port == 80 { host_header } else { format!("{}:{}", host_header, port) };
+    let mut request = format!("{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nUser-Agent: rusty_scheme\r\n",
+                              method, path, host_header);
+    for (name, value) in headers.iter() {
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    match body {
+        Some(body) => request.push_str(&format!("Content-Length: {}\r\n\r\n{}", body.len(), body)),
+        None => request.push_str("\r\n")
+    }
+    let mut response = Vec::new();
+    match stream.write_all(request.as_bytes()).and_then(|_| stream.read_to_end(&mut response)) {
+        Ok(_) => parse_response(&response).ok_or_else(|| message!("Malformed HTTP response from {}", url)),
+        Err(e) => Err(message!("Couldn't request {}: {}", url, e))
+    }
+}
+
+pub struct Server {
+    listener: TcpListener,
+    // The connection whose request the handler is answering
+    connection: Option<TcpStream>,
+}
+
+impl Server {
+    pub fn bind(address: &str) -> Result<Server, String> {
+        match TcpListener::bind(address) {
+            Ok(listener) => Ok(Server { listener, connection: None }),
+            Err(e) => Err(message!("Couldn't listen on {}: {}", address, e))
+        }
+    }
+
+    // Wait for the next well-formed request, answering any malformed ones with 400 Bad Request, and return it as
+    // ((method "GET") (path "/") (query "") (headers ((name value) ...)) (body ""))
+    pub fn accept(&mut self) -> Result<Node, String> {
+        loop {
+            let mut stream = match self.listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(e) => return Err(message!("Couldn't accept a connection: {}", e))
+            };
+            let timeout = Some(Duration::from_secs(TIMEOUT_SECONDS));
+            let _ = stream.set_read_timeout(timeout).and_then(|_| stream.set_write_timeout(timeout));
+            match read_request(&mut stream) {
+                Some(request) => {
+                    self.connection = Some(stream);
+                    return Ok(request)
+                },
+                None => {
+                    let _ = write_response(&mut stream, 400, &[], "Bad Request\n");
+                }
+            }
+        }
+    }
+
+    // Answer the request accept returned with what the handler made of it: either a list of (status code),
+    // (headers ((name value) ...)) and (body string) entries, each of which can be left out, or just the body
+    pub fn respond(&mut self, response: &Node) -> Result<(), String> {
+        let (status, headers, body) = match response_from_node(response) {
+            Ok(response) => response,
+            Err(e) => {
+                self.fail();
+                return Err(e)
+            }
+        };
+        match self.connection.take() {
+            // a client that has gone away doesn't stop the server
+            Some(mut stream) => {
+                let _ = write_response(&mut stream, status, &headers, &body);
+                Ok(())
+            },
+            None => Err(message!("There's no request to respond to"))
+        }
+    }
+
+    // Answer the request accept returned with 500 Internal Server Error, when the handler failed
+    fn fail(&mut self) {
+        if let Some(mut stream) = self.connection.take() {
+            let _ = write_response(&mut stream, 500, &[], "Internal Server Error\n");
+        }
+    }
+}
+
+// A handler that fails unwinds past the server, which answers for it on the way
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.fail();
+    }
+}
+
+// A server is only equal to itself, which is all a continuation waiting on one needs
+impl PartialEq for Server {
+    fn eq(&self, other: &Server) -> bool {
+        ptr::eq(self, other)
+    }
+}
+
+impl fmt::Debug for Server {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.listener.local_addr() {
+            Ok(address) => write!(f, "#<http-server {}>", address),
+            Err(_) => write!(f, "#<http-server>")
+        }
+    }
+}
+
+fn read_request(stream: &mut TcpStream) -> Option<Node> {
+    let mut bytes = Vec::new();
+    let mut buffer = [0u8; 4096];
+    let head_end = loop {
+        match bytes.windows(4).position(|w| w == b"\r\n\r\n") {
+            Some(i) => break i,
+            None if bytes.len() > MAX_REQUEST_HEAD => return None,
+            None => match stream.read(&mut buffer) {
+                Ok(0) | Err(_) => return None,
+                Ok(n) => bytes.extend_from_slice(&buffer[..n])
+            }
+        }
+    };
+    let head = String::from_utf8_lossy(&bytes[..head_end]).into_owned();
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap().split(' ');
+    let (method, target) = match (request_line.next(), request_line.next(), request_line.next(), request_line.next()) {
+        (Some(method), Some(target), Some(version), None) if !method.is_empty() && version.starts_with("HTTP/") => (method, target),
+        _ => return None
+    };
+    let headers = parse_headers(lines)?;
+    let length = match headers.iter().find(|h| h.0.eq_ignore_ascii_case("content-length")) {
+        Some((_, length)) => match length.parse::<usize>() {
+            Ok(length) if length <= MAX_REQUEST_BODY => length,
+            _ => return None
+        },
+        None => 0
+    };
+    let mut body = bytes.split_off(head_end + 4);
+    if body.len() < length {
+        let read = body.len();
+        body.resize(length, 0);
+        if stream.read_exact(&mut body[read..]).is_err() {
+            return None
+        }
+    }
+    body.truncate(length);
+
+    let (path, query) = match target.find('?') {
+        Some(i) => (&target[..i], &target[i + 1..]),
+        None => (target, "")
+    };
+    let field = |name: &str, value: Node| Node::List(vec![Node::Identifier(name.to_string()), value]);
+    let headers = headers.into_iter().map(|(name, value)| Node::List(vec![Node::String(name), Node::String(value)])).collect();
+    Some(Node::List(vec![
+        field("method", Node::String(method.to_string())),
+        field("path", Node::String(path.to_string())),
+        field("query", Node::String(query.to_string())),
+        field("headers", Node::List(headers)),
+        field("body", Node::String(String::from_utf8_lossy(&body).into_owned())),
+    ]))
+}
+
+// The status, headers and body of a handler's response
+fn response_from_node(node: &Node) -> Result<(u16, Headers, String), String> {
+    let entries = match *node {
+        Node::String(ref body) => return Ok((200, Vec::new(), body.clone())),
+        Node::List(ref entries) => entries,
+        _ => return Err(message!("Expected a response list or string: {}", printer::print(node, Style::Write)))
+    };
+    let (mut status, mut headers, mut body) = (200, Vec::new(), String::new());
+    for entry in entries.iter() {
+        let (name, value) = match *entry {
+            Node::List(ref pair) if pair.len() == 2 => match pair[0] {
+                Node::Identifier(ref name) => (name.as_str(), &pair[1]),
+                _ => return Err(message!("Expected a (name value) response entry: {}", printer::print(entry, Style::Write)))
+            },
+            _ => return Err(message!("Expected a (name value) response entry: {}", printer::print(entry, Style::Write)))
+        };
+        match (name, value) {
+            ("status", &Node::Integer(code)) if (100..=999).contains(&code) => status = code as u16,
+            ("status", _) => return Err(message!("Expected a status code from 100 to 999: {}", printer::print(value, Style::Write))),
+            ("headers", _) => headers = try!(headers_from_node(value)),
+            ("body", Node::String(s)) => body = s.clone(),
+            ("body", _) => return Err(message!("Expected a string response body: {}", printer::print(value, Style::Write))),
+            _ => return Err(message!("Unknown response entry: {}", name))
+        }
+    }
+    Ok((status, headers, body))
+}
+
+fn write_response(stream: &mut TcpStream, status: u16, headers: &[(String, String)], body: &str) -> Result<(), String> {
+    let mut response = format!("HTTP/1.1 {} {}\r\n", status, reason(status));
+    let has = |name: &str| headers.iter().any(|h| h.0.eq_ignore_ascii_case(name));
+    if !has("content-type") {
+        response.push_str("Content-Type: text/plain; charset=utf-8\r\n");
+    }
+    for (name, value) in headers.iter() {
+        if !(name.eq_ignore_ascii_case("content-length") || name.eq_ignore_ascii_case("connection")) {
+            response.push_str(&format!("{}: {}\r\n", name, value));
+        }
+    }
+    response.push_str(&format!("Content-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body));
+    stream.write_all(response.as_bytes()).and_then(|_| stream.flush()).map_err(|e| e.to_string())
+}
+
+// The reason phrase for the usual status codes; clients don't depend on it, so the rest go without
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        500 => "Internal Server Error",
+        _ => ""
+    }
+}
+
+// Headers to send, from a list of (name value) lists of strings
+pub fn headers_from_node(node: &Node) -> Result<Headers, String> {
+    let items = match *node {
+        Node::List(ref items) => items,
+        _ => return Err(message!("Expected a list of (name value) headers: {}", printer::print(node, Style::Write)))
+    };
+    let mut headers = Vec::new();
+    for item in items.iter() {
+        match *item {
+            Node::List(ref pair) if pair.len() == 2 => match (&pair[0], &pair[1]) {
+                (Node::String(name), Node::String(value)) if !(name.contains(is_newline) || value.contains(is_newline)) => {
+                    headers.push((name.clone(), value.clone()))
+                },
+                _ => return Err(message!("Expected a header name and value as strings: {}", printer::print(item, Style::Write)))
+            },
+            _ => return Err(message!("Expected a (name value) header: {}", printer::print(item, Style::Write)))
+        }
+    }
+    Ok(headers)
+}
+
+// A header can't start another one
+fn is_newline(c: char) -> bool {
+    c == '\r' || c == '\n'
+}
+
+// The host, port and path (with any query) of an http URL
+fn parse_url(url: &str) -> Result<(String, u16, String), String> {
+    let rest = match url.find("://") {
+        Some(i) if url[..i].eq_ignore_ascii_case("http") => &url[i + 3..],
+        Some(i) => return Err(message!("Unsupported URL scheme {} (only http is): {}", &url[..i], url)),
+        None => return Err(message!("Expected a URL starting with http://: {}", url))
+    };
+    let end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+    let (authority, path) = rest.split_at(end);
+    let path = match path.find('#') {
+        Some(i) => &path[..i],
+        None => path
+    };
+    let path = if path.starts_with('/') { path.to_string() } else { format!("/{}", path) };
+    // a bracketed IPv6 address contains colons of its own
+    let port_start = match authority.rfind(':') {
+        Some(i) if !authority[i..].contains(']') => Some(i),
+        _ => None
+    };
+    let (host, port) = match port_start {
+        Some(i) => match authority[i + 1..].parse() {
+            Ok(port) => (&authority[..i], port),
+            Err(_) => return Err(message!("Invalid port in URL: {}", url))
+        },
+        None => (authority, 80)
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if host.is_empty() {
+        return Err(message!("Expected a host in URL: {}", url))
+    }
+    Ok((host.to_string(), port, path))
+}
+
+fn parse_response(bytes: &[u8]) -> Option<Response> {
+    let head_end = bytes.windows(4).position(|w| w == b"\r\n\r\n")?;
+    let head = String::from_utf8_lossy(&bytes[..head_end]);
+    let mut lines = head.split("\r\n");
+    let status = lines.next().and_then(|line| line.split(' ').nth(1)).and_then(|s| s.parse().ok())?;
+    let headers = parse_headers(lines)?;
+
+    let rest = &bytes[head_end + 4..];
+    let header = |name: &str| headers.iter().find(|h| h.0.eq_ignore_ascii_case(name)).map(|h| h.1.clone());
+    let body = if header("transfer-encoding").is_some_and(|t| t.eq_ignore_ascii_case("chunked")) {
+        dechunk(rest)?
+    } else {
+        match header("content-length").and_then(|l| l.parse::<usize>().ok()) {
+            Some(length) if length <= rest.len() => rest[..length].to_vec(),
+            _ => rest.to_vec()
+        }
+    };
+    Some(Response { status, headers, body: String::from_utf8_lossy(&body).into_owned() })
+}
+
+fn parse_headers<'a, I: Iterator<Item = &'a str>>(lines: I) -> Option<Headers> {
+    let mut headers = Vec::new();
+    for line in lines {
+        match line.find(':') {
+            Some(i) => headers.push((line[..i].trim().to_string(), line[i + 1..].trim().to_string())),
+            None => return None
+        }
+    }
+    Some(headers)
+}
+
+// Join the chunks of a chunked body: each is its size in hex, CRLF, the data and CRLF, ending with a chunk of size 0
+fn dechunk(mut bytes: &[u8]) -> Option<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let line_end = bytes.windows(2).position(|w| w == b"\r\n")?;
+        let line = String::from_utf8_lossy(&bytes[..line_end]);
+        // chunk extensions follow a semicolon
+        let size = match usize::from_str_radix(line.split(';').next().unwrap().trim(), 16) {
+            Ok(size) => size,
+            Err(_) => return None
+        };
+        if size == 0 {
+            return Some(body)
+        }
+        let data = &bytes[line_end + 2..];
+        if data.len() < size {
+            return None
+        }
+        body.extend_from_slice(&data[..size]);
+        bytes = if data.len() >= size + 2 { &data[size + 2..] } else { &data[size..] };
+    }
+}
+
+#[test]
+fn test_http_request() {
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::thread;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut head = Vec::new();
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if line == "\r\n" { break }
+            head.push(line.trim_end().to_string());
+        }
+        let length: usize = head.iter().find(|h| h.starts_with("Content-Length: ")).unwrap()[16..].parse().unwrap();
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).unwrap();
+        let mut stream = stream;
+        stream.write_all(b"HTTP/1.1 201 Created\r\nTransfer-Encoding: chunked\r\nX-Echo: yes\r\n\r\n5\r\nhello\r\n").unwrap();
+        stream.write_all(format!("{:x}\r\n {}\r\n0\r\n\r\n", length + 1, String::from_utf8(body).unwrap()).as_bytes()).unwrap();
+        head
+    });
+
+    let url = format!("http://127.0.0.1:{}/things?x=1#top", port);
+    let response = request("POST", &url, &[("Content-Type".to_string(), "text/plain".to_string())], Some("wörld")).unwrap();
+    assert_eq!(response.status, 201);
+    assert_eq!(response.headers, vec![("Transfer-Encoding".to_string(), "chunked".to_string()),
+                                      ("X-Echo".to_string(), "yes".to_string())]);
+    assert_eq!(response.body, "hello wörld");
+    let head = server.join().unwrap();
+    assert_eq!(head[0], "POST /things?x=1 HTTP/1.1");
+    assert!(head.contains(&format!("Host: 127.0.0.1:{}", port)));
+    assert!(head.contains(&"Content-Type: text/plain".to_string()));
+
+    assert_eq!(parse_url("http://example.com"), Ok(("example.com".to_string(), 80, "/".to_string())));
+    assert_eq!(parse_url("HTTP://[::1]:8080?q"), Ok(("::1".to_string(), 8080, "/?q".to_string())));
+    assert_eq!(parse_url("https://example.com/").err().unwrap(), "Unsupported URL scheme https (only http is): https://example.com/");
+    assert_eq!(parse_url("example.com").err().unwrap(), "Expected a URL starting with http://: example.com");
+    assert_eq!(parse_url("http://example.com:http/").err().unwrap(), "Invalid port in URL: http://example.com:http/");
+    let headers = ::parser::parse_str("((\"Accept\" \"text/html\"))").unwrap().remove(0);
+    assert_eq!(headers_from_node(&headers), Ok(vec![("Accept".to_string(), "text/html".to_string())]));
+    let headers = ::parser::parse_str("((Accept \"text/html\"))").unwrap().remove(0);
+    assert_eq!(headers_from_node(&headers).err().unwrap(), "Expected a header name and value as strings: (Accept \"text/html\")");
+    assert_eq!(dechunk(b"3\r\nabc\r\n1;ext=1\r\nd\r\n0\r\n\r\n"), Some(b"abcd".to_vec()));
+
+    let response = ::parser::parse_str("((status 404) (body \"gone\"))").unwrap().remove(0);
+    assert_eq!(response_from_node(&response), Ok((404, vec![], "gone".to_string())));
+    assert_eq!(response_from_node(&Node::String("hi".to_string())), Ok((200, vec![], "hi".to_string())));
+    let response = ::parser::parse_str("((status 42))").unwrap().remove(0);
+    assert_eq!(response_from_node(&response).err().unwrap(), "Expected a status code from 100 to 999: 42");
+    let response = ::parser::parse_str("((stauts 200))").unwrap().remove(0);
+    assert_eq!(response_from_node(&response).err().unwrap(), "Unknown response entry: stauts");
+}
@@ -0,0 +1,215 @@
+// Conventional arithmetic for #i(...) literals, for people who'd rather write #i(2 * (x + 1)) than (* 2 (+ x 1)):
+// integers and decimals like 1.5, variables, + - * / with the usual precedence (left to right within a level), unary
+// minus, a comparison with < > or =, parentheses, and calls written f(x, y). The result is the equivalent prefix expression.
+
+use parser::Node;
+use printer::{self, Style};
+
+use std::iter;
+use std::str;
+
+#[derive(PartialEq, Debug)]
+enum Token {
+    // an integer or a float
+    Number(Node),
+    Name(String),
+    Operator(char),
+    Open,
+    Close,
+    Comma,
+}
+
+pub fn parse(text: &str) -> Result<Node, String> {
+    let tokens = try!(tokenize(text));
+    let mut parser = Parser { tokens: tokens.into_iter().peekable() };
+    let node = try!(parser.comparison());
+    match parser.tokens.next() {
+        None => Ok(node),
+        Some(t) => Err(format!("unexpected {}", describe(&t)))
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            _ if c.is_whitespace() => (),
+            '0'..='9' => {
+                let digits = take_while(c, &mut chars, |c| c.is_ascii_digit() || c == '.');
+                if digits.contains('.') {
+                    match digits.parse() {
+                        Ok(f) => tokens.push(Token::Number(Node::Float(f))),
+                        Err(_) => return Err(format!("not a number: {}", digits))
+                    }
+                } else {
+                    match digits.parse() {
+                        Ok(n) => tokens.push(Token::Number(Node::Integer(n))),
+                        Err(_) => return Err(format!("number too large: {}", digits))
+                    }
+                }
+            },
+            '+' | '-' | '*' | '/' | '<' | '>' | '=' => tokens.push(Token::Operator(c)),
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            ',' => tokens.push(Token::Comma),
+            _ if c.is_alphabetic() || c == '_' => {
+                tokens.push(Token::Name(take_while(c, &mut chars, |c| c.is_alphanumeric() || c == '_' || c == '?' || c == '!')));
+            },
+            _ => return Err(format!("unexpected character: {}", c))
+        }
+    }
+    Ok(tokens)
+}
+
+fn take_while<F: Fn(char) -> bool>(first: char, chars: &mut iter::Peekable<str::Chars>, f: F) -> String {
+    let mut s = first.to_string();
+    while let Some(&c) = chars.peek() {
+        if !f(c) {
+            break
+        }
+        s.push(c);
+        chars.next();
+    }
+    s
+}
+
+fn describe(token: &Token) -> String {
+    match *token {
+        Token::Number(ref n) => printer::print(n, Style::Write),
+        Token::Name(ref name) => name.clone(),
+        Token::Operator(c) => c.to_string(),
+        Token::Open => "(".to_string(),
+        Token::Close => ")".to_string(),
+        Token::Comma => ",".to_string(),
+    }
+}
+
+fn call(operator: &str, args: Vec<Node>) -> Node {
+    let mut list = vec![Node::Identifier(operator.to_string())];
+    list.extend(args);
+    Node::List(list)
+}
+
+struct Parser {
+    tokens: iter::Peekable<::std::vec::IntoIter<Token>>,
+}
+
+impl Parser {
+    fn next_operator(&mut self, operators: &str) -> Option<char> {
+        let found = match self.tokens.peek() {
+            Some(&Token::Operator(c)) if operators.contains(c) => Some(c),
+            _ => None
+        };
+        if found.is_some() {
+            self.tokens.next();
+        }
+        found
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), String> {
+        match self.tokens.next() {
+            Some(ref t) if *t == expected => Ok(()),
+            Some(t) => Err(format!("expected {}, but found {}", describe(&expected), describe(&t))),
+            None => Err(format!("expected {}, but found the end", describe(&expected)))
+        }
+    }
+
+    fn comparison(&mut self) -> Result<Node, String> {
+        let left = try!(self.sum());
+        match self.next_operator("<>=") {
+            Some(op) => {
+                let right = try!(self.sum());
+                Ok(call(&op.to_string(), vec![left, right]))
+            },
+            None => Ok(left)
+        }
+    }
+
+    fn sum(&mut self) -> Result<Node, String> {
+        let mut left = try!(self.product());
+        while let Some(op) = self.next_operator("+-") {
+            let right = try!(self.product());
+            left = call(&op.to_string(), vec![left, right]);
+        }
+        Ok(left)
+    }
+
+    fn product(&mut self) -> Result<Node, String> {
+        let mut left = try!(self.unary());
+        while let Some(op) = self.next_operator("*/") {
+            let right = try!(self.unary());
+            left = call(&op.to_string(), vec![left, right]);
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        if self.next_operator("-").is_none() {
+            return self.atom()
+        }
+        match try!(self.unary()) {
+            Node::Integer(n) => Ok(Node::Integer(-n)),
+            Node::Float(f) => Ok(Node::Float(-f)),
+            // - takes exactly two arguments, so negate by subtracting from zero
+            operand => Ok(call("-", vec![Node::Integer(0), operand]))
+        }
+    }
+
+    fn atom(&mut self) -> Result<Node, String> {
+        match self.tokens.next() {
+            Some(Token::Number(n)) => Ok(n),
+            Some(Token::Name(name)) => {
+                if self.tokens.peek() != Some(&Token::Open) {
+                    return Ok(Node::Identifier(name))
+                }
+                self.tokens.next();
+                let mut args = Vec::new();
+                if self.tokens.peek() == Some(&Token::Close) {
+                    self.tokens.next();
+                    return Ok(call(&name, args))
+                }
+                loop {
+                    args.push(try!(self.comparison()));
+                    match self.tokens.next() {
+                        Some(Token::Comma) => (),
+                        Some(Token::Close) => return Ok(call(&name, args)),
+                        Some(t) => return Err(format!("expected , or ), but found {}", describe(&t))),
+                        None => return Err("expected ), but found the end".to_string())
+                    }
+                }
+            },
+            Some(Token::Open) => {
+                let inner = try!(self.comparison());
+                try!(self.expect(Token::Close));
+                Ok(inner)
+            },
+            Some(t) => Err(format!("unexpected {}", describe(&t))),
+            None => Err("unexpected end of expression".to_string())
+        }
+    }
+}
+
+#[test]
+fn test_infix() {
+    assert_eq!(parse("1 + 2 * 3").unwrap(),
+               call("+", vec![Node::Integer(1), call("*", vec![Node::Integer(2), Node::Integer(3)])]));
+    assert_eq!(parse("(1 + 2) * 3").unwrap(),
+               call("*", vec![call("+", vec![Node::Integer(1), Node::Integer(2)]), Node::Integer(3)]));
+    assert_eq!(parse("10 - 4 - 3").unwrap(),
+               call("-", vec![call("-", vec![Node::Integer(10), Node::Integer(4)]), Node::Integer(3)]));
+    assert_eq!(parse("-x + -2").unwrap(),
+               call("+", vec![call("-", vec![Node::Integer(0), Node::Identifier("x".to_string())]), Node::Integer(-2)]));
+    assert_eq!(parse("max(a, b + 1) > 2").unwrap(),
+               call(">", vec![call("max", vec![Node::Identifier("a".to_string()), call("+", vec![Node::Identifier("b".to_string()), Node::Integer(1)])]),
+                              Node::Integer(2)]));
+    assert_eq!(parse("f()").unwrap(), call("f", vec![]));
+    assert_eq!(parse("1.5 * -x + -0.5").unwrap(),
+               call("+", vec![call("*", vec![Node::Float(1.5), call("-", vec![Node::Integer(0), Node::Identifier("x".to_string())])]),
+                              Node::Float(-0.5)]));
+    assert_eq!(parse("1.2.3").err().unwrap(), "not a number: 1.2.3");
+    assert_eq!(parse("1 +").err().unwrap(), "unexpected end of expression");
+    assert_eq!(parse("(1 + 2").err().unwrap(), "expected ), but found the end");
+    assert_eq!(parse("1 2").err().unwrap(), "unexpected 2");
+    assert_eq!(parse("1 % 2").err().unwrap(), "unexpected character: %");
+}
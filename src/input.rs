@@ -0,0 +1,196 @@
+// Buffered standard input shared by the read and read-char builtins of both interpreters. Input is pulled in a
+// line at a time, and whatever the last call didn't consume stays buffered for the next one.
+
+use parser::{self, Node};
+
+use std::cell::RefCell;
+use std::io::{self, BufRead};
+
+thread_local!(static PENDING: RefCell<String> = const { RefCell::new(String::new()) });
+
+// Append another line of stdin to the buffer, returning false at end of input
+fn fill() -> Result<bool, String> {
+    let mut line = String::new();
+    let stdin = io::stdin();
+    match stdin.lock().read_line(&mut line) {
+        Ok(0) => Ok(false),
+        Ok(_) => {
+            PENDING.with(|p| p.borrow_mut().push_str(&line));
+            Ok(true)
+        },
+        Err(e) => Err(format!("Couldn't read from stdin: {}", e))
+    }
+}
+
+// The next character of input, or None at end of input
+pub fn read_char() -> Result<Option<char>, String> {
+    loop {
+        let c = PENDING.with(|p| {
+            let mut pending = p.borrow_mut();
+            match pending.chars().next() {
+                Some(c) => {
+                    pending.remove(0);
+                    Some(c)
+                },
+                None => None
+            }
+        });
+        match c {
+            Some(c) => return Ok(Some(c)),
+            None => if !try!(fill()) { return Ok(None) }
+        }
+    }
+}
+
+// The next datum of input, or None if only whitespace and comments were left
+pub fn read_datum() -> Result<Option<Node>, String> {
+    loop {
+        let end = PENDING.with(|p| datum_end(&p.borrow()));
+        match end {
+            Some(end) => {
+                let text: String = PENDING.with(|p| p.borrow_mut().drain(..end).collect());
+                let mut nodes = match parser::parse_str(&text) {
+                    Ok(nodes) => nodes,
+                    Err(e) => return Err(e.to_string())
+                };
+                if !nodes.is_empty() {
+                    return Ok(Some(nodes.remove(0)));
+                }
+            },
+            None => {
+                if !try!(fill()) {
+                    let rest = PENDING.with(|p| p.borrow_mut().split_off(0));
+                    return match parser::parse_str(&rest) {
+                        Ok(ref nodes) if nodes.is_empty() => Ok(None),
+                        Ok(mut nodes) => Ok(Some(nodes.remove(0))),
+                        Err(e) => Err(e.to_string())
+                    };
+                }
+            }
+        }
+    }
+}
+
+// Byte offset just past the first complete datum in s, or None if s doesn't contain a complete one yet. Atoms
+// only count as complete once a delimiter follows them, since more of the atom may be on the next line.
+fn datum_end(s: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut in_string = false;
+    // Inside a |symbol|, and just after a backslash in one
+    let mut in_bars = false;
+    let mut escaped = false;
+    let mut in_comment = false;
+    let mut in_atom = false;
+    // How deeply nested in #| ... |# comments, and how many characters of a #| or |# (or a #u8( prefix) are left
+    let mut block_comments = 0;
+    let mut skip = 0;
+    for (i, c) in s.char_indices() {
+        if skip > 0 {
+            skip -= 1;
+            continue;
+        }
+        if in_comment {
+            if c == '\n' { in_comment = false; }
+            continue;
+        }
+        if block_comments > 0 {
+            if s[i..].starts_with("|#") {
+                block_comments -= 1;
+                skip = 1;
+            } else if s[i..].starts_with("#|") {
+                block_comments += 1;
+                skip = 1;
+            }
+            continue;
+        }
+        if in_string {
+            if c == '"' {
+                in_string = false;
+                if depth == 0 { return Some(i + 1) }
+            }
+            continue;
+        }
+        if in_bars {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '|' {
+                in_bars = false;
+                if depth == 0 { return Some(i + 1) }
+            }
+            continue;
+        }
+        if s[i..].starts_with("#;") {
+            // like a quote, it's read along with the datum that follows it
+            if in_atom && depth == 0 { return Some(i) }
+            in_atom = false;
+            skip = 1;
+            continue;
+        }
+        if s[i..].starts_with("#|") {
+            if in_atom && depth == 0 { return Some(i) }
+            in_atom = false;
+            block_comments = 1;
+            skip = 1;
+            continue;
+        }
+        if !in_atom && (s[i..].starts_with("#(") || s[i..].starts_with("#u8(")) {
+            // the open paren that follows counts as usual
+            skip = if s[i..].starts_with("#u8(") { 2 } else { 0 };
+            continue;
+        }
+        if in_atom {
+            match c {
+                '(' | ')' | '[' | ']' | '"' | '|' | ';' | '\'' | '`' | ',' => {
+                    if depth == 0 { return Some(i) }
+                    in_atom = false;
+                },
+                _ if c.is_whitespace() => {
+                    if depth == 0 { return Some(i) }
+                    in_atom = false;
+                    continue;
+                },
+                _ => continue
+            }
+        }
+        match c {
+            ';' => in_comment = true,
+            '"' => in_string = true,
+            '|' => in_bars = true,
+            '(' | '[' => depth += 1,
+            ')' | ']' => {
+                if depth == 0 {
+                    // let the parser report the stray close paren
+                    return Some(i + 1)
+                }
+                depth -= 1;
+                if depth == 0 { return Some(i + 1) }
+            },
+            _ if c.is_whitespace() => (),
+            '\'' | '`' | ',' => (),
+            _ => in_atom = true
+        }
+    }
+    None
+}
+
+#[test]
+fn test_input_datum_end() {
+    assert_eq!(datum_end("foo bar"), Some(3));
+    assert_eq!(datum_end("foo"), None);
+    assert_eq!(datum_end("  (a (b \")\") c) d"), Some(15));
+    assert_eq!(datum_end("'(a\n"), None);
+    assert_eq!(datum_end("; (\n\"x\" 1"), Some(7));
+    assert_eq!(datum_end("   \n"), None);
+    assert_eq!(datum_end("#| ( #| \" |# ) |# (a #|)|#) b"), Some(27));
+    assert_eq!(datum_end("#| (a) |# #| x\n"), None);
+    assert_eq!(datum_end("12#|x|#"), Some(2));
+    assert_eq!(datum_end("#; (a b) c"), Some(8));
+    assert_eq!(datum_end("#(1 (2)) 3"), Some(8));
+    assert_eq!(datum_end("#u8(1 2) 3"), Some(8));
+    assert_eq!(datum_end("#u8(1\n"), None);
+    assert_eq!(datum_end("|a \\| b| c"), Some(8));
+    assert_eq!(datum_end("(|)| x) y"), Some(7));
+    assert_eq!(datum_end("|a\n"), None);
+}
@@ -1,19 +1,38 @@
-use lexer;
+use capabilities::Capabilities;
+use condition::Kind;
+use docs;
+use expand;
+use features;
+use fold::{self, Folder};
+use lexer::ReaderConfig;
+use lint;
+use log;
 use parser;
+use plugin::{self, Plugin};
+use printer::{self, PrinterConfig, Printable, Style};
+use random;
+use search_path;
+use stats;
+use strict;
+use warning;
 use ast_walk_interpreter;
 use cps_interpreter;
 
 #[cfg(not(test))]
 use repl;
 
-#[cfg(not(test))]
+// The level of a message passed to a log handler
+pub use log::Level as LogLevel;
+
 use std::fs::File;
 
-#[cfg(not(test))]
-use std::path::Path;
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::slice;
+use std::time::Instant;
 
-#[cfg(not(test))]
-use std::io::Read;
+use std::io::{self, IsTerminal, Read};
 
 macro_rules! try_or_err_to_string {
     ($inp:expr) => (
@@ -25,41 +44,729 @@ macro_rules! try_or_err_to_string {
 }
 
 pub fn new(t: &str) -> Interpreter {
-    Interpreter::new(t)
+    match Builder::new().interpreter_type(t).build() {
+        Ok(i) => i,
+        Err(e) => panic!("{}", e)
+    }
+}
+
+pub fn from_image(path: &Path) -> Result<Interpreter, String> {
+    Builder::new().image(path).build()
+}
+
+#[derive(Clone)]
+pub struct Builder {
+    interpreter_type: String,
+    image: Option<PathBuf>,
+    reader: ReaderConfig,
+    printer: Option<PrinterConfig>,
+    seed: Option<u64>,
+    search_path: Option<Vec<PathBuf>>,
+    allow_network: bool,
+    allow_foreign: bool,
+    fold_constants: bool,
+    strict_booleans: bool,
+}
+
+impl Default for Builder {
+    fn default() -> Builder {
+        Builder::new()
+    }
+}
+
+impl Builder {
+    pub fn new() -> Builder {
+        Builder { interpreter_type: "cps".to_string(), image: None, reader: ReaderConfig::default(), printer: None, seed: None,
+                  search_path: None, allow_network: false, allow_foreign: false,
+                  fold_constants: false, strict_booleans: false }
+    }
+
+    pub fn interpreter_type(mut self, t: &str) -> Builder {
+        self.interpreter_type = t.to_string();
+        self
+    }
+
+    // Resume from a heap image instead of a fresh environment (implies the cps interpreter)
+    pub fn image(mut self, path: &Path) -> Builder {
+        self.image = Some(path.to_path_buf());
+        self
+    }
+
+    pub fn reader(mut self, config: ReaderConfig) -> Builder {
+        self.reader = config;
+        self
+    }
+
+    // Printer settings are shared by every interpreter on the current thread
+    pub fn printer(mut self, config: PrinterConfig) -> Builder {
+        self.printer = Some(config);
+        self
+    }
+
+    // Make random, gensym and the order of hashed environments repeat from run to run (also per thread)
+    pub fn seed(mut self, seed: u64) -> Builder {
+        self.seed = Some(seed);
+        self
+    }
+
+    // Directories to look in, in order, for autoloaded and included files that aren't found where their names say
+    // (also per thread)
+    pub fn search_path(mut self, dirs: Vec<PathBuf>) -> Builder {
+        self.search_path = Some(dirs);
+        self
+    }
+
+    // Let http-get, http-post and serve-http use the network, which they can't by default
+    pub fn allow_network(mut self, allow: bool) -> Builder {
+        self.allow_network = allow;
+        self
+    }
+
+    // Let load-foreign-library and foreign-procedure call C functions, with the ffi feature
+    pub fn allow_foreign(mut self, allow: bool) -> Builder {
+        self.allow_foreign = allow;
+        self
+    }
+
+    // Fold calls to pure builtins with literal arguments into their results before running code (see fold.rs). Code
+    // that redefines a builtin with eval or load, rather than define or set!, can still see it folded.
+    pub fn fold_constants(mut self, fold: bool) -> Builder {
+        self.fold_constants = fold;
+        self
+    }
+
+    // Make if, and and or raise a type error when a condition isn't a boolean (see strict.rs; also per thread)
+    pub fn strict_booleans(mut self, strict: bool) -> Builder {
+        self.strict_booleans = strict;
+        self
+    }
+
+    pub fn build(self) -> Result<Interpreter, String> {
+        if let Some(seed) = self.seed {
+            random::set_seed(seed);
+        }
+        let evaluator = match self.image {
+            Some(ref path) => Evaluator::Cps(try_or_err_to_string!(cps_interpreter::Interpreter::load_image(path))),
+            None => {
+                match self.interpreter_type.as_ref() {
+                    "cps" => Evaluator::Cps(try_or_err_to_string!(cps_interpreter::new())),
+                    "ast_walk" => Evaluator::AstWalk(ast_walk_interpreter::new()),
+                    _ => return Err("Interpreter type must be 'cps' or 'ast_walk'".to_string())
+                }
+            }
+        };
+        if let Some(config) = self.printer {
+            printer::set_config(config);
+        }
+        if let Some(dirs) = self.search_path {
+            search_path::set(dirs);
+        }
+        let granted = Capabilities { network: self.allow_network, foreign: self.allow_foreign };
+        match evaluator {
+            Evaluator::AstWalk(ref i) => i.grant(granted),
+            Evaluator::Cps(ref i)     => i.grant(granted),
+        }
+        strict::set_booleans(self.strict_booleans);
+        Ok(Interpreter { evaluator, reader: self.reader, fold_constants: self.fold_constants, history: Cell::new(0),
+                        show_stats: Cell::new(false), frame: RefCell::new(None),
+                        theme: RefCell::new(ReplTheme { prompt: "> ".to_string(), result_color: Some("green".to_string()),
+                                                        error_color: Some("red".to_string()), colors: ColorMode::Never,
+                                                        banner: true }) })
+    }
+}
+
+// Why execute_checked failed. A violation is a bug in the Scheme program: a procedure called with the wrong number
+// or types of arguments, or an assertion-violation it raised. Anything else (a missing file, unreadable input, an
+// error raised with error) is recoverable, so an embedder can handle just those and let violations propagate.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    Violation(String),
+    Recoverable(String),
+}
+
+impl Error {
+    fn new(kind: Kind, message: String) -> Error {
+        if kind.is_violation() {
+            Error::Violation(message)
+        } else {
+            Error::Recoverable(message)
+        }
+    }
+
+    pub fn is_recoverable(&self) -> bool {
+        match *self {
+            Error::Violation(_) => false,
+            Error::Recoverable(_) => true,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Violation(ref message) | Error::Recoverable(ref message) => write!(f, "{}", message),
+        }
+    }
+}
+
+// When the REPL colors its results and errors: always, never, or only when its output is a terminal
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum ColorMode {
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorMode {
+    pub fn from_name(name: &str) -> Option<ColorMode> {
+        match name {
+            "auto" => Some(ColorMode::Auto),
+            "always" => Some(ColorMode::Always),
+            "never" => Some(ColorMode::Never),
+            _ => None
+        }
+    }
+}
+
+// The ANSI code for a color's name
+fn color_code(name: &str) -> Option<u8> {
+    match name {
+        "black" => Some(30),
+        "red" => Some(31),
+        "green" => Some(32),
+        "yellow" => Some(33),
+        "blue" => Some(34),
+        "magenta" => Some(35),
+        "cyan" => Some(36),
+        "white" => Some(37),
+        _ => None
+    }
 }
 
-pub enum Interpreter {
+// How the REPL looks. Each part can also be set from Scheme, e.g. in the init file, by defining repl-prompt,
+// repl-result-color or repl-error-color, which wins over the setting here.
+struct ReplTheme {
+    prompt: String,
+    result_color: Option<String>,
+    error_color: Option<String>,
+    colors: ColorMode,
+    // Whether to greet the user before the first prompt
+    banner: bool,
+}
+
+fn checked_color(color: Option<&str>) -> Result<Option<String>, String> {
+    match color {
+        Some(name) if color_code(name).is_none() => Err(format!("Unknown color: {}", name)),
+        _ => Ok(color.map(|name| name.to_string()))
+    }
+}
+
+// How many elements of each list a REPL result shows when no max-length is set, so that a huge result doesn't flood
+// the terminal; ,full prints the last one in full
+const REPL_MAX_LENGTH: usize = 100;
+
+fn print_result<T: Printable>(value: &T) -> String {
+    let mut config = printer::config();
+    if config.max_length.is_none() {
+        config.max_length = Some(REPL_MAX_LENGTH);
+    }
+    printer::print_with(value, Style::Write, config)
+}
+
+pub struct Interpreter {
+    evaluator: Evaluator,
+    reader: ReaderConfig,
+    fold_constants: bool,
+    // How many results have been bound to $1, $2, ... so far
+    history: Cell<usize>,
+    // Whether the REPL reports time and counters after each evaluation (toggled with ,stats on/off)
+    show_stats: Cell<bool>,
+    // Where the last REPL error happened: until ,abort, input is evaluated there, so its variables can be inspected
+    frame: RefCell<Option<Frame>>,
+    theme: RefCell<ReplTheme>,
+}
+
+enum Evaluator {
     AstWalk(ast_walk_interpreter::Interpreter),
     Cps(cps_interpreter::Interpreter),
 }
 
+impl Folder for Evaluator {
+    fn is_macro(&self, name: &str) -> bool {
+        match *self {
+            Evaluator::AstWalk(ref i) => i.is_macro(name),
+            Evaluator::Cps(ref i) => i.is_macro(name),
+        }
+    }
+
+    fn call(&self, call: &parser::Node) -> Option<parser::Node> {
+        match *self {
+            Evaluator::AstWalk(ref i) => i.fold_call(call),
+            Evaluator::Cps(ref i) => i.fold_call(call),
+        }
+    }
+}
+
+// How many macro uses ,expand-step expands before giving up
+const MAX_EXPANSION_STEPS: usize = 100;
+
+// The node as it's written, and where in that (in characters) the subform at path is
+fn print_marked(node: &parser::Node, path: &[usize]) -> (String, usize, usize) {
+    let mut printed = String::new();
+    let (start, end) = write_marked(node, path, &mut printed);
+    (printed, start, end)
+}
+
+fn write_marked(node: &parser::Node, path: &[usize], out: &mut String) -> (usize, usize) {
+    match (node, path.split_first()) {
+        (parser::Node::List(items), Some((&index, rest))) => {
+            let mut marked = (0, 0);
+            out.push('(');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(' ');
+                }
+                if i == index {
+                    marked = write_marked(item, rest, out);
+                } else {
+                    out.push_str(&printer::print_with(item, Style::Write, PrinterConfig::default()));
+                }
+            }
+            out.push(')');
+            marked
+        },
+        _ => {
+            let start = out.chars().count();
+            out.push_str(&printer::print_with(node, Style::Write, PrinterConfig::default()));
+            (start, out.chars().count())
+        }
+    }
+}
+
+enum Frame {
+    AstWalk(ast_walk_interpreter::Frame),
+    Cps(cps_interpreter::Frame),
+}
+
+impl Frame {
+    // The failing call and the local variables it could see, one per line
+    fn describe(&self) -> String {
+        let (expression, locals) = match *self {
+            Frame::AstWalk(ref f) => (f.expression(), f.locals()),
+            Frame::Cps(ref f) => (f.expression(), f.locals()),
+        };
+        let mut lines = vec![format!("In {}", expression)];
+        for (name, value) in locals {
+            lines.push(format!("  {} = {}", name, value));
+        }
+        lines.push("Evaluate expressions to inspect them, or ,abort to return to the top level".to_string());
+        lines.join("\n")
+    }
+}
+
 impl Interpreter {
-    fn new(t: &str) -> Interpreter {
-        match t.as_ref() {
-            "cps" => Interpreter::Cps(cps_interpreter::new().unwrap()),
-            "ast_walk" => Interpreter::AstWalk(ast_walk_interpreter::new()),
-            _ => panic!("Interpreter type must be 'cps' or 'ast_walk'")
+    // Read the input and expand its includes, which are relative to the file it came from (if any)
+    fn read(&self, input: &str, file: Option<&Path>) -> Result<Vec<parser::Node>, Error> {
+        let nodes = match parser::parse_str_with(input, &self.reader) {
+            Ok(nodes) => nodes,
+            Err(e) => return Err(Error::new(Kind::ReadError, e.to_string()))
+        };
+        expand::expand_includes(nodes, file, &self.reader).map_err(|e| Error::new(e.kind(), e.to_string()))
+    }
+
+    // Read code to run at the top level, folding its constants if that's turned on. Code run in an error's frame
+    // isn't folded, since its local variables could shadow builtins.
+    fn read_top_level(&self, input: &str, file: Option<&Path>) -> Result<Vec<parser::Node>, Error> {
+        let nodes = try!(self.read(input, file));
+        if warning::enabled() {
+            for l in lint::lint_variables(&nodes) {
+                warning::warn_once(l.to_string());
+            }
+        }
+        if self.fold_constants {
+            Ok(fold::fold(nodes, &self.evaluator))
+        } else {
+            Ok(nodes)
         }
     }
 
     fn parse(&self, input: &str) -> Result<Vec<parser::Node>, String> {
-        let tokens = try_or_err_to_string!(lexer::tokenize(input));
-        let ast = try_or_err_to_string!(parser::parse(&tokens));
-        Ok(ast)
+        self.read(input, None).map_err(|e| e.to_string())
     }
 
     pub fn execute(&self, input: &str) -> Result<String, String> {
+        self.execute_checked(input).map_err(|e| e.to_string())
+    }
+
+    // Like execute, but says whether an error was a violation or something recoverable
+    pub fn execute_checked(&self, input: &str) -> Result<String, Error> {
+        self.execute_from(input, None)
+    }
+
+    fn execute_from(&self, input: &str, file: Option<&Path>) -> Result<String, Error> {
+        let parsed = try!(self.read_top_level(input, file));
+        match self.evaluator {
+            Evaluator::AstWalk(ref i) => {
+                match i.run(&parsed) {
+                    Ok(value) => Ok(printer::print(&value, Style::Write)),
+                    Err(e) => Err(Error::new(e.kind(), e.to_string()))
+                }
+            },
+            Evaluator::Cps(ref i) => {
+                match i.run(&parsed) {
+                    Ok(value) => Ok(printer::print(&value, Style::Write)),
+                    Err(e) => Err(Error::new(e.kind(), e.to_string()))
+                }
+            },
+        }
+    }
+
+    // Run a user's init file, e.g. ~/.rusty_scheme.scm, so the definitions and settings in it are there from the first
+    // prompt on. Not having one is fine.
+    pub fn load_init_file(&self, path: &Path) -> Result<(), String> {
+        let mut contents = String::new();
+        match File::open(path).and_then(|mut f| f.read_to_string(&mut contents)) {
+            Ok(_) => (),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(format!("Couldn't read init file {}: {}", path.display(), e))
+        }
+        match self.execute_from(&contents, Some(path)) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(format!("In init file {}: {}", path.display(), e))
+        }
+    }
+
+    // Like execute, but also binds the result to the next of $1, $2, ... (and to $$), so REPL users can refer back to it
+    pub fn execute_and_remember(&self, input: &str) -> Result<String, String> {
+        self.remember(input).map_err(|(e, _)| e)
+    }
+
+    // execute_and_remember, keeping the frame an error happened in
+    fn remember(&self, input: &str) -> Result<String, (String, Option<Frame>)> {
+        let parsed = match self.read_top_level(input, None) {
+            Ok(parsed) => parsed,
+            Err(e) => return Err((e.to_string(), None))
+        };
+        if parsed.len() < 2 {
+            return self.remember_nodes(&parsed)
+        }
+        // A pasted block of several expressions runs one at a time, each with its own result, up to the first error
+        let mut printed = Vec::new();
+        for node in parsed.iter() {
+            match self.remember_nodes(slice::from_ref(node)) {
+                Ok(result) => printed.push(result),
+                Err((e, frame)) => {
+                    printed.push(e);
+                    return Err((printed.join("\n"), frame))
+                }
+            }
+        }
+        Ok(printed.join("\n"))
+    }
+
+    fn remember_nodes(&self, parsed: &[parser::Node]) -> Result<String, (String, Option<Frame>)> {
+        let n = self.history.get() + 1;
+        let name = format!("${}", n);
+        let printed = match self.evaluator {
+            Evaluator::AstWalk(ref i) => {
+                let value = match i.run(parsed) {
+                    Ok(value) => value,
+                    Err(mut e) => return Err((e.to_string(), e.take_frame().map(Frame::AstWalk)))
+                };
+                i.set_global(&name, value.clone());
+                i.set_global("$$", value.clone());
+                print_result(&value)
+            },
+            Evaluator::Cps(ref i) => {
+                let value = match i.run(parsed) {
+                    Ok(value) => value,
+                    Err(mut e) => return Err((e.to_string(), e.take_frame().map(Frame::Cps)))
+                };
+                i.set_global(&name, value.clone());
+                i.set_global("$$", value.clone());
+                print_result(&value)
+            },
+        };
+        self.history.set(n);
+        Ok(format!("{} = {}", name, printed))
+    }
+
+    // Evaluate REPL input in the frame of the last error, without binding the result
+    fn execute_in_frame(&self, input: &str, frame: &Frame) -> Result<String, String> {
         let parsed = try!(self.parse(input));
-        match *self {
-            Interpreter::AstWalk(ref i) => Ok(format!("{:?}", try_or_err_to_string!(i.run(&parsed)))),
-            Interpreter::Cps(ref i)     => Ok(format!("{:?}", try_or_err_to_string!(i.run(&parsed)))),
+        let value = match (&self.evaluator, frame) {
+            (Evaluator::AstWalk(i), Frame::AstWalk(f)) => printer::print(&try_or_err_to_string!(i.run_in(&parsed, f)), Style::Write),
+            (Evaluator::Cps(i), Frame::Cps(f)) => printer::print(&try_or_err_to_string!(i.run_in(&parsed, f)), Style::Write),
+            _ => return Err("The frame belongs to another interpreter".to_string())
+        };
+        Ok(value)
+    }
+
+    // Handle one line of REPL input: either a ,command or an expression to evaluate and remember. The output is
+    // colored if the REPL's colors are on.
+    pub fn execute_repl_line(&self, input: &str) -> Result<String, String> {
+        let result = self.evaluate_repl_line(input);
+        let colors = match self.theme.borrow().colors {
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+            ColorMode::Auto => io::stdout().is_terminal(),
+        };
+        if !colors {
+            return result
+        }
+        match result {
+            Ok(printed) => Ok(self.paint(printed, "repl-result-color", |t| t.result_color.clone())),
+            Err(e) => Err(self.paint(e, "repl-error-color", |t| t.error_color.clone())),
+        }
+    }
+
+    // The display form of a global variable that customizes the REPL, if it's defined
+    fn repl_setting(&self, name: &str) -> Option<String> {
+        match self.evaluator {
+            Evaluator::AstWalk(ref i) => i.get_global(name).map(|v| printer::print(&v, Style::Display)),
+            Evaluator::Cps(ref i) => i.get_global(name).map(|v| printer::print(&v, Style::Display)),
         }
     }
 
+    fn paint<F: Fn(&ReplTheme) -> Option<String>>(&self, text: String, setting: &str, default: F) -> String {
+        let color = match self.repl_setting(setting) {
+            Some(name) => Some(name),
+            None => default(&self.theme.borrow())
+        };
+        match color.as_ref().and_then(|name| color_code(name)) {
+            Some(code) => format!("\x1b[{}m{}\x1b[0m", code, text),
+            None => text
+        }
+    }
+
+    fn evaluate_repl_line(&self, input: &str) -> Result<String, String> {
+        match input.trim() {
+            ",stats on" => {
+                self.show_stats.set(true);
+                return Ok("Stats on".to_string())
+            },
+            ",stats off" => {
+                self.show_stats.set(false);
+                return Ok("Stats off".to_string())
+            },
+            ",abort" => {
+                return match self.frame.borrow_mut().take() {
+                    Some(_) => Ok("Back to the top level".to_string()),
+                    None => Err("Already at the top level".to_string())
+                }
+            },
+            ",full" => {
+                // the last result without any of the printer's limits
+                let config = PrinterConfig { max_depth: None, max_length: None, width: None, ..printer::config() };
+                let printed = match self.evaluator {
+                    Evaluator::AstWalk(ref i) => i.get_global("$$").map(|v| printer::print_with(&v, Style::Write, config)),
+                    Evaluator::Cps(ref i) => i.get_global("$$").map(|v| printer::print_with(&v, Style::Write, config)),
+                };
+                return printed.ok_or("No result to show yet".to_string())
+            },
+            ",frame" => {
+                return match *self.frame.borrow() {
+                    Some(ref frame) => Ok(frame.describe()),
+                    None => Err("No error to inspect".to_string())
+                }
+            },
+            command if command == ",expand-step" || command.starts_with(",expand-step ") => {
+                return self.expand_steps(&command[",expand-step".len()..])
+            },
+            command if command.starts_with(",") => {
+                return Err(format!("Unknown REPL command: {}", command))
+            },
+            _ => ()
+        }
+        if let Some(ref frame) = *self.frame.borrow() {
+            return self.execute_in_frame(input, frame);
+        }
+        if !self.show_stats.get() {
+            return self.remember_frame(self.remember(input));
+        }
+
+        let start = Instant::now();
+        let (result, counted) = stats::measure(|| self.remember_frame(self.remember(input)));
+        let elapsed = start.elapsed();
+        let report = format!(";; {:.3}ms, {} steps, {} values allocated",
+                             elapsed.as_secs() as f64 * 1000.0 + elapsed.subsec_nanos() as f64 / 1000000.0,
+                             counted.steps, counted.allocations);
+        match result {
+            Ok(printed) => Ok(format!("{}\n{}", printed, report)),
+            Err(e) => Err(format!("{}\n{}", e, report))
+        }
+    }
+
+    // Every step of expanding the macro uses in an expression one at a time, for ,expand-step: the expression, then
+    // the form after each expansion, with the part that expansion changed marked
+    fn expand_steps(&self, input: &str) -> Result<String, String> {
+        let mut nodes = try!(parser::parse_str_with(input, &self.reader).map_err(|e| e.to_string()));
+        if nodes.len() != 1 {
+            return Err("Usage: ,expand-step expression".to_string())
+        }
+        let mut env = expand::MacroEnv::new();
+        let macros = match self.evaluator {
+            Evaluator::AstWalk(ref i) => i.macros(),
+            Evaluator::Cps(ref i) => i.macros(),
+        };
+        for (name, params, template) in macros {
+            env.define(&name, params, template);
+        }
+        let mut form = nodes.remove(0);
+        let mut lines = vec![printer::print_with(&form, Style::Write, PrinterConfig::default())];
+        for _ in 0..MAX_EXPANSION_STEPS {
+            match try!(expand::expand_step(&form, &env).map_err(|e| e.to_string())) {
+                Some((expanded, path)) => {
+                    let (printed, start, end) = print_marked(&expanded, &path);
+                    lines.push(format!("=> {}", printed));
+                    lines.push(format!("   {}{}", " ".repeat(start), "^".repeat(end - start)));
+                    form = expanded;
+                },
+                None => {
+                    if lines.len() == 1 {
+                        lines.push(";; no macro uses to expand".to_string());
+                    }
+                    return Ok(lines.join("\n"))
+                }
+            }
+        }
+        Err(format!("Still expanding after {} steps, so a macro probably expands into itself", MAX_EXPANSION_STEPS))
+    }
+
+    // Keep the frame of an error, for the REPL's error level, and show where it happened
+    fn remember_frame(&self, result: Result<String, (String, Option<Frame>)>) -> Result<String, String> {
+        match result {
+            Ok(printed) => Ok(printed),
+            Err((e, None)) => Err(e),
+            Err((e, Some(frame))) => {
+                let message = format!("{}\n{}", e, frame.describe());
+                *self.frame.borrow_mut() = Some(frame);
+                Err(message)
+            }
+        }
+    }
+
+    // Whether REPL input stops partway through an expression, so the REPL should read another line before running it
+    pub fn is_incomplete(&self, input: &str) -> bool {
+        match parser::parse_str_with(input, &self.reader) {
+            Err(ref e) => e.is_incomplete(),
+            Ok(_) => false
+        }
+    }
+
+    // The REPL's prompt, which shows when it's at an error's frame
+    pub fn repl_prompt(&self) -> String {
+        let prompt = match self.repl_setting("repl-prompt") {
+            Some(prompt) => prompt,
+            None => self.theme.borrow().prompt.clone()
+        };
+        if self.frame.borrow().is_some() { format!("error{}", prompt) } else { prompt }
+    }
+
+    pub fn set_repl_prompt(&self, prompt: &str) {
+        self.theme.borrow_mut().prompt = prompt.to_string();
+    }
+
+    pub fn set_repl_colors(&self, colors: ColorMode) {
+        self.theme.borrow_mut().colors = colors;
+    }
+
+    pub fn set_repl_banner(&self, show: bool) {
+        self.theme.borrow_mut().banner = show;
+    }
+
+    // Colors are named black, red, green, yellow, blue, magenta, cyan or white; None leaves that output uncolored
+    pub fn set_repl_result_color(&self, color: Option<&str>) -> Result<(), String> {
+        self.theme.borrow_mut().result_color = try!(checked_color(color));
+        Ok(())
+    }
+
+    pub fn set_repl_error_color(&self, color: Option<&str>) -> Result<(), String> {
+        self.theme.borrow_mut().error_color = try!(checked_color(color));
+        Ok(())
+    }
+
+    // Register names that load the given file into the global environment the first time they're referenced
+    pub fn autoload(&self, path: &str, names: &[&str]) {
+        for name in names.iter() {
+            match self.evaluator {
+                Evaluator::AstWalk(ref i) => i.autoload(path, name),
+                Evaluator::Cps(ref i)     => i.autoload(path, name),
+            }
+        }
+    }
+
+    // Define the builtins and constants a plugin provides as globals, replacing any with the same names
+    pub fn load_plugin(&self, plugin: &dyn Plugin) {
+        let mut env = plugin::Environment::new();
+        plugin.register(&mut env);
+        for (name, parameters, text) in env.docs().iter() {
+            docs::add(name, parameters, text);
+        }
+        match self.evaluator {
+            Evaluator::AstWalk(ref i) => i.load_plugin(&env),
+            Evaluator::Cps(ref i)     => i.load_plugin(&env),
+        }
+    }
+
+    // Load a plugin built as a cdylib that declares it with declare_plugin!
+    #[cfg(feature = "ffi")]
+    pub fn load_plugin_library(&self, path: &str) -> Result<(), String> {
+        let plugin = try!(plugin::load_library(path));
+        self.load_plugin(&*plugin);
+        Ok(())
+    }
+
+    // Declare a feature, for cond-expand requirements and (features) on this thread
+    pub fn add_feature(&self, name: &str) {
+        features::add_feature(name);
+    }
+
+    // Declare a library as available to cond-expand's (library name) requirements, e.g. add_library("(srfi 1)")
+    pub fn add_library(&self, name: &str) -> Result<(), String> {
+        features::add_library(name)
+    }
+
+    // Receive warnings (from warn, or about suspicious code) on this thread; without a handler they're dropped
+    pub fn set_warning_handler<F: Fn(&str) + 'static>(&self, handler: F) {
+        warning::set_handler(Box::new(handler));
+    }
+
+    // Receive what log-debug, log-info, log-warn and log-error log on this thread; without a handler it goes to
+    // stderr
+    pub fn set_log_handler<F: Fn(LogLevel, &str) + 'static>(&self, handler: F) {
+        log::set_handler(Box::new(handler));
+    }
+
+    // Restart random and gensym from the seed, and reorder the global environment to match
+    pub fn set_seed(&self, seed: u64) {
+        random::set_seed(seed);
+        match self.evaluator {
+            Evaluator::AstWalk(ref i) => i.rehash_globals(),
+            Evaluator::Cps(ref i)     => i.rehash_globals(),
+        }
+    }
+
+    pub fn dump_image(&self, path: &Path) -> Result<(), String> {
+        match self.evaluator {
+            Evaluator::AstWalk(_) => Err("Images are only supported by the cps interpreter".to_string()),
+            Evaluator::Cps(ref i) => i.dump_image(path).map_err(|e| e.to_string()),
+        }
+    }
+
+    // Lines typed at the prompt are kept in the history file, if given, across sessions
     #[cfg(not(test))]
-    pub fn start_repl(&self) {
-        println!("\nWelcome to the RustyScheme REPL!");
-        repl::start("> ", (|s| self.execute(&s)))
+    pub fn start_repl(&self, history: Option<&Path>) {
+        if self.theme.borrow().banner {
+            println!("\nWelcome to the RustyScheme REPL!");
+        }
+        repl::start(history, || self.repl_prompt(), |s| self.is_incomplete(s), |s| self.execute_repl_line(&s) )
+    }
+
+    // Run a whole script, e.g. one piped in for --batch, without printing its result: all it prints is what it
+    // asks to. Relative includes are looked for next to file, if it came from one.
+    pub fn run_script(&self, source: &str, file: Option<&Path>) -> Result<(), String> {
+        self.execute_from(source, file).map(|_| ()).map_err(|e| e.to_string())
     }
 
     #[cfg(not(test))]
@@ -68,7 +775,7 @@ impl Interpreter {
         let mut file = File::open(&path).unwrap();
         let mut contents = String::new();
         file.read_to_string(&mut contents).unwrap();
-        match self.execute(&contents) {
+        match self.execute_from(&contents, Some(path)) {
             Ok(_) => {},
             Err(e) => println!("{}", e),
         }
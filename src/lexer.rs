@@ -1,28 +1,168 @@
+use infix;
+use parser::{self, Node};
+use printer;
+use text;
+
+use std::char;
+use std::collections::{HashMap, VecDeque};
 use std::str;
 use std::fmt;
 use std::iter;
+use std::rc::Rc;
 
 pub fn tokenize(s: &str) -> Result<Vec<Token>, SyntaxError> {
-    Lexer::tokenize(s)
+    Lexer::tokenize(s, &ReaderConfig::default())
+}
+
+pub fn tokenize_with(s: &str, config: &ReaderConfig) -> Result<Vec<Token>, SyntaxError> {
+    Lexer::tokenize(s, config)
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum ReaderMode {
+    // Only accept standard R7RS syntax
+    Strict,
+    // Also accept common extensions, like square brackets
+    Lenient,
+}
+
+impl ReaderMode {
+    pub fn from_name(name: &str) -> Option<ReaderMode> {
+        match name {
+            "strict" => Some(ReaderMode::Strict),
+            "lenient" => Some(ReaderMode::Lenient),
+            _ => None
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ReaderConfig {
+    pub mode: ReaderMode,
+    // Normalize string literals to NFC as they're read
+    pub normalize_strings: bool,
+    // Read #i(...) as infix arithmetic, e.g. #i(1 + 2 * 3) as (+ 1 (* 2 3))
+    pub infix: bool,
+    // The characters besides letters, digits and combining marks (in any script) that strict mode allows in an
+    // identifier. The lenient reader takes any character that doesn't end the identifier.
+    pub extended_chars: String,
+    pub extensions: ReaderExtensions,
+    pub limits: ReaderLimits,
+}
+
+// The extended identifier characters of R7RS
+pub const EXTENDED_CHARS: &str = "!$%&*/:<=>?^_~+-.@";
+
+impl Default for ReaderConfig {
+    fn default() -> ReaderConfig {
+        ReaderConfig { mode: ReaderMode::Lenient, normalize_strings: false, infix: false, extended_chars: EXTENDED_CHARS.to_string(),
+                       extensions: ReaderExtensions::new(), limits: ReaderLimits::default() }
+    }
+}
+
+// Bounds on what the reader accepts, for servers reading untrusted input: going over one is a syntax error of kind
+// LimitExceeded, raised as soon as it's found. None means no limit, which is the default.
+#[derive(PartialEq, Clone, Copy, Debug, Default)]
+pub struct ReaderLimits {
+    // How many characters a string literal can have
+    pub max_string_length: Option<usize>,
+    // How many lists can be open inside each other
+    pub max_depth: Option<usize>,
+    // How many datums (atoms and lists, at any depth) the whole input can have
+    pub max_datums: Option<usize>,
+}
+
+type Reader = Rc<dyn Fn(&str) -> Result<Node, String>>;
+
+// Literals an embedder adds to the syntax: #name"text", where a reader registered for the name turns the text into
+// a datum (e.g. #date"2024-01-01" into (date 2024 1 1)). Reading fails if it returns an error.
+#[derive(Clone, Default)]
+pub struct ReaderExtensions {
+    readers: HashMap<String, Reader>,
+}
+
+impl ReaderExtensions {
+    pub fn new() -> ReaderExtensions {
+        ReaderExtensions { readers: HashMap::new() }
+    }
+
+    pub fn register<F: Fn(&str) -> Result<Node, String> + 'static>(&mut self, name: &str, reader: F) {
+        self.readers.insert(name.to_string(), Rc::new(reader));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.readers.is_empty()
+    }
+}
+
+impl fmt::Debug for ReaderExtensions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.readers.keys()).finish()
+    }
 }
 
 #[derive(PartialEq, Debug)]
 pub enum Token {
     OpenParen,
     CloseParen,
+    // #(, which a close paren ends
+    OpenVector,
+    // #u8(, likewise
+    OpenBytevector,
+    OpenBracket,
+    CloseBracket,
     Quote,
     Quasiquote,
     Unquote,
+    // ,@
+    UnquoteSplicing,
+    // A lone ., between the items of a dotted list and its tail
+    Dot,
     Identifier(String),
     Integer(i64),
+    // A fraction as it was written, e.g. 2/4
+    Rational(i64, i64),
+    Float(f64),
     Boolean(bool),
+    Character(char),
+    EofObject,
+    Keyword(String),
     String(String),
+    // Read by a reader extension
+    Datum(Node),
+    // #&, before the contents of a box
+    Box,
+    // #n=, before a datum that #n# refers back to
+    Label(u32),
+    Reference(u32),
+    // #;, before a datum to skip
+    DatumComment,
 }
 
 pub struct SyntaxError {
     message: String,
     line: u32,
     column: u32,
+    kind: SyntaxErrorKind,
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum SyntaxErrorKind {
+    Invalid,
+    // The input ran out partway through a token, so more input could make it valid
+    Incomplete,
+    // The input goes over one of the reader's limits
+    LimitExceeded,
+}
+
+impl SyntaxError {
+    pub fn kind(&self) -> SyntaxErrorKind {
+        self.kind
+    }
+
+    pub fn is_incomplete(&self) -> bool {
+        self.kind == SyntaxErrorKind::Incomplete
+    }
 }
 
 impl fmt::Display for SyntaxError {
@@ -38,23 +178,111 @@ impl fmt::Debug for SyntaxError {
 
 macro_rules! syntax_error {
     ($lexer:ident, $($arg:tt)*) => (
-        return Err(SyntaxError { message: format!($($arg)*), line: $lexer.line, column: $lexer.column })
+        return Err(SyntaxError { message: message!($($arg)*), line: $lexer.line, column: $lexer.column,
+                                 kind: SyntaxErrorKind::Invalid })
+    )
+}
+
+macro_rules! eof_error {
+    ($lexer:ident, $($arg:tt)*) => (
+        return Err(SyntaxError { message: message!($($arg)*), line: $lexer.line, column: $lexer.column,
+                                 kind: SyntaxErrorKind::Incomplete })
     )
 }
 
-struct Lexer<'a> {
+macro_rules! limit_error {
+    ($lexer:ident, $($arg:tt)*) => (
+        return Err(SyntaxError { message: message!($($arg)*), line: $lexer.line, column: $lexer.column,
+                                 kind: SyntaxErrorKind::LimitExceeded })
+    )
+}
+
+/// Reads tokens lazily, one at a time, so a caller can stop early or handle a huge file without holding all of its
+/// tokens at once. Iteration ends after the first error.
+pub struct Lexer<'a> {
+    config: &'a ReaderConfig,
     chars: iter::Peekable<str::Chars<'a>>,
     current: Option<char>,
-    tokens: Vec<Token>,
+    // Lexed but not yet returned
+    tokens: VecDeque<Token>,
     line: u32,
     column: u32,
+    done: bool,
+    // How deeply lists are nested at this point, and how many datums have been read, for the reader's limits
+    depth: usize,
+    datums: usize,
+}
+
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Result<Token, SyntaxError>;
+
+    fn next(&mut self) -> Option<Result<Token, SyntaxError>> {
+        loop {
+            match self.tokens.pop_front() {
+                Some(token) => return Some(Ok(token)),
+                None if self.done => return None,
+                None => ()
+            }
+            match self.step() {
+                Ok(true) => (),
+                Ok(false) => self.done = true,
+                Err(e) => {
+                    self.done = true;
+                    self.tokens.clear();
+                    return Some(Err(e))
+                }
+            }
+        }
+    }
 }
 
 impl<'a> Lexer<'a> {
-    fn tokenize(s: &str) -> Result<Vec<Token>, SyntaxError> {
-        let mut lexer = Lexer { chars: s.chars().peekable(), current: None, tokens: Vec::new(), line: 1, column: 0 };
-        try!(lexer.run());
-        Ok(lexer.tokens)
+    pub fn new(s: &'a str, config: &'a ReaderConfig) -> Lexer<'a> {
+        let mut lexer = Lexer { config, chars: s.chars().peekable(), current: None, tokens: VecDeque::new(),
+                                line: 1, column: 0, done: false, depth: 0, datums: 0 };
+        lexer.advance();
+        lexer
+    }
+
+    fn tokenize(s: &'a str, config: &'a ReaderConfig) -> Result<Vec<Token>, SyntaxError> {
+        Lexer::new(s, config).collect()
+    }
+
+    /// How far the lexer has read, as a line and column
+    pub fn position(&self) -> (u32, u32) {
+        (self.line, self.column)
+    }
+
+    // Queue a token to return, checking it against the reader's limits
+    fn push(&mut self, token: Token) -> Result<(), SyntaxError> {
+        let limits = self.config.limits;
+        match token {
+            Token::CloseParen | Token::CloseBracket => {
+                self.depth = self.depth.saturating_sub(1);
+            },
+            Token::Quote | Token::Quasiquote | Token::Unquote | Token::UnquoteSplicing | Token::Dot | Token::Box | Token::Label(_) | Token::DatumComment => (),
+            _ => {
+                self.datums += 1;
+                if limits.max_datums.is_some_and(|max| self.datums > max) {
+                    limit_error!(self, "Input has more than the limit of {} datums", limits.max_datums.unwrap());
+                }
+            }
+        }
+        match token {
+            Token::OpenParen | Token::OpenBracket | Token::OpenVector | Token::OpenBytevector => {
+                self.depth += 1;
+                if limits.max_depth.is_some_and(|max| self.depth > max) {
+                    limit_error!(self, "Lists are nested deeper than the limit of {}", limits.max_depth.unwrap());
+                }
+            },
+            _ => ()
+        }
+        self.tokens.push_back(token);
+        Ok(())
+    }
+
+    fn lenient(&self) -> bool {
+        self.config.mode == ReaderMode::Lenient
     }
 
     fn current(&self) -> Option<char> {
@@ -78,98 +306,226 @@ impl<'a> Lexer<'a> {
         }
     }
 
-    fn run(&mut self) -> Result<(), SyntaxError> {
-        self.advance();
-        loop {
-            match self.current() {
-                Some(c) => {
-                    match c {
-                        _ if c.is_whitespace() => {
-                            self.advance();
-                        },
-                        ';' => {
-                            // comment, advance until newline
-                            self.advance();
-                            loop {
-                                match self.current() {
-                                    Some(c) if c == '\n' => {
-                                        self.advance();
-                                        break
-                                    }
-                                    Some(_) => {
-                                        self.advance();
-                                    },
-                                    None => break
+    // The character after the next one
+    fn second(&self) -> Option<char> {
+        self.chars.clone().nth(1)
+    }
+
+    // Whether the input from the current character on starts with s
+    fn looking_at(&self, s: &str) -> bool {
+        let mut expected = s.chars();
+        expected.next() == self.current() && self.chars.clone().zip(expected.by_ref()).all(|(a, b)| a == b)
+            && expected.next().is_none()
+    }
+
+    // Lex whatever starts at the current character, which may be nothing (whitespace or a comment) or more than one
+    // token (one followed by a close paren). Returns false at the end of the input.
+    fn step(&mut self) -> Result<bool, SyntaxError> {
+        match self.current() {
+            Some(c) => {
+                match c {
+                    _ if c.is_whitespace() => {
+                        self.advance();
+                    },
+                    ';' => {
+                        // comment, advance until newline
+                        self.advance();
+                        loop {
+                            match self.current() {
+                                Some(c) if c == '\n' => {
+                                    self.advance();
+                                    break
                                 }
-                            }
-                        },
-                        '(' => {
-                            self.tokens.push(Token::OpenParen);
-                            self.advance();
-                        },
-                        ')' => {
-                            self.tokens.push(Token::CloseParen);
-                            self.advance();
-                        },
-                        '\'' => {
-                            self.tokens.push(Token::Quote);
-                            self.advance();
-                        },
-                        '`' => {
-                            self.tokens.push(Token::Quasiquote);
-                            self.advance();
-                        },
-                        ',' => {
-                            self.tokens.push(Token::Unquote);
-                            self.advance();
-                        },
-                        '+' | '-' => {
-                            match self.peek() {
-                                Some('0'...'9') => {
-                                    // skip past the +/- symbol and parse the number
+                                Some(_) => {
                                     self.advance();
-                                    let val = try!(self.parse_number());
-                                    self.tokens.push(Token::Integer(if c == '-' { -1 * val } else { val }));
-                                    try!(self.parse_delimiter());
                                 },
-                                _ => {
-                                    // not followed by a digit, must be an identifier
-                                    self.tokens.push(Token::Identifier(c.to_string()));
-                                    self.advance();
-                                    try!(self.parse_delimiter());
-                                }
+                                None => break
                             }
-                        },
-                        '#' => {
-                            let val = try!(self.parse_boolean());
-                            self.tokens.push(Token::Boolean(val));
-                            try!(self.parse_delimiter());
-                        },
-                        '0'...'9' => {
-                            // don't advance -- let parse_number advance as needed
-                            let val = try!(self.parse_number());
-                            self.tokens.push(Token::Integer(val));
-                            try!(self.parse_delimiter());
-                        },
-                        '\"' => {
-                            let val = try!(self.parse_string());
-                            self.tokens.push(Token::String(val));
-                            try!(self.parse_delimiter());
-                        },
-                        '[' | ']' | '{' | '}' | '|' | '\\' => {
-                            syntax_error!(self, "Unexpected character: {}", c);
-                        },
-                        _ => {
-                            let val = try!(self.parse_identifier());
-                            self.tokens.push(Token::Identifier(val));
+                        }
+                    },
+                    '(' => {
+                        try!(self.push(Token::OpenParen));
+                        self.advance();
+                    },
+                    ')' => {
+                        try!(self.push(Token::CloseParen));
+                        self.advance();
+                    },
+                    '[' if self.lenient() => {
+                        try!(self.push(Token::OpenBracket));
+                        self.advance();
+                    },
+                    ']' if self.lenient() => {
+                        try!(self.push(Token::CloseBracket));
+                        self.advance();
+                    },
+                    '\'' => {
+                        try!(self.push(Token::Quote));
+                        self.advance();
+                    },
+                    '`' => {
+                        try!(self.push(Token::Quasiquote));
+                        self.advance();
+                    },
+                    ',' if self.looking_at(",@") => {
+                        try!(self.push(Token::UnquoteSplicing));
+                        self.advance();
+                        self.advance();
+                    },
+                    ',' => {
+                        try!(self.push(Token::Unquote));
+                        self.advance();
+                    },
+                    '+' | '-' => {
+                        match self.peek() {
+                            Some('0'..='9') => {
+                                // skip past the +/- symbol and parse the number
+                                self.advance();
+                                let token = try!(self.parse_real(c == '-'));
+                                try!(self.push(token));
+                                try!(self.parse_delimiter());
+                            },
+                            Some('.') if self.second().is_some_and(|c| c.is_ascii_digit()) => {
+                                // -.5, with no digits before the point
+                                self.advance();
+                                let token = try!(self.parse_real(c == '-'));
+                                try!(self.push(token));
+                                try!(self.parse_delimiter());
+                            },
+                            Some('i') | Some('n') => {
+                                // +inf.0, -inf.0 and +nan.0, the floats with no digits
+                                self.advance();
+                                let word = try!(self.parse_identifier());
+                                let val = match word.as_str() {
+                                    "inf.0" if c == '-' => -f64::INFINITY,
+                                    "inf.0" => f64::INFINITY,
+                                    "nan.0" => f64::NAN,
+                                    _ => syntax_error!(self, "Unexpected character when looking for a delimiter: {}", &word[..1])
+                                };
+                                try!(self.push(Token::Float(val)));
+                                try!(self.parse_delimiter());
+                            },
+                            _ => {
+                                // not followed by a digit, must be an identifier
+                                try!(self.push(Token::Identifier(c.to_string())));
+                                self.advance();
+                                try!(self.parse_delimiter());
+                            }
+                        }
+                    },
+                    '#' if self.peek() == Some('|') => {
+                        try!(self.parse_block_comment());
+                    },
+                    '#' if self.peek() == Some('(') => {
+                        self.advance();
+                        self.advance();
+                        try!(self.push(Token::OpenVector));
+                    },
+                    '#' if self.looking_at("#u8(") => {
+                        for _ in 0..4 {
+                            self.advance();
+                        }
+                        try!(self.push(Token::OpenBytevector));
+                    },
+                    '#' if self.peek() == Some(';') => {
+                        self.advance();
+                        self.advance();
+                        try!(self.push(Token::DatumComment));
+                    },
+                    '#' if self.lenient() && self.peek() == Some('!') => {
+                        try!(self.parse_eof_object());
+                        try!(self.push(Token::EofObject));
+                        try!(self.parse_delimiter());
+                    },
+                    '#' if self.lenient() && self.peek() == Some('&') => {
+                        self.advance();
+                        self.advance();
+                        try!(self.push(Token::Box));
+                    },
+                    '#' if self.peek() == Some('\\') => {
+                        let val = try!(self.parse_character());
+                        try!(self.push(Token::Character(val)));
+                        try!(self.parse_delimiter());
+                    },
+                    '#' if self.peek().is_some_and(|c| c.is_ascii_digit()) => {
+                        let token = try!(self.parse_label());
+                        let is_reference = matches!(token, Token::Reference(_));
+                        try!(self.push(token));
+                        if is_reference {
                             try!(self.parse_delimiter());
                         }
+                    },
+                    '#' if self.lenient() && self.peek() == Some(':') => {
+                        // skip past the #: and parse the keyword's name
+                        self.advance();
+                        self.advance();
+                        let val = try!(self.parse_identifier());
+                        if val.is_empty() {
+                            syntax_error!(self, "Expected a keyword name after #:");
+                        }
+                        try!(self.push(Token::Keyword(val)));
+                        try!(self.parse_delimiter());
+                    },
+                    '#' if self.lenient() && self.peek() == Some('\"') => {
+                        // skip past the #, and let parse_string take the quotes
+                        self.advance();
+                        let token = try!(self.parse_interpolated_string());
+                        try!(self.push(token));
+                        try!(self.parse_delimiter());
+                    },
+                    '#' if (self.config.infix || !self.config.extensions.is_empty()) && self.peek().is_some_and(|c| c.is_alphabetic()) => {
+                        let token = try!(self.parse_extension());
+                        try!(self.push(token));
+                        try!(self.parse_delimiter());
+                    },
+                    '#' => {
+                        let token = try!(self.parse_hash());
+                        try!(self.push(token));
+                        try!(self.parse_delimiter());
+                    },
+                    '0'..='9' => {
+                        // don't advance -- let parse_real advance as needed
+                        let token = try!(self.parse_real(false));
+                        try!(self.push(token));
+                        try!(self.parse_delimiter());
+                    },
+                    '.' if self.peek().is_some_and(|c| c.is_ascii_digit()) => {
+                        // a float with no digits before the point, like .5
+                        let token = try!(self.parse_real(false));
+                        try!(self.push(token));
+                        try!(self.parse_delimiter());
+                    },
+                    '\"' => {
+                        let val = try!(self.parse_string());
+                        try!(self.push(Token::String(val)));
+                        try!(self.parse_delimiter());
+                    },
+                    '|' => {
+                        let val = try!(self.parse_bar_identifier());
+                        try!(self.push(Token::Identifier(val)));
+                        try!(self.parse_delimiter());
+                    },
+                    '[' | ']' | '{' | '}' | '\\' => {
+                        syntax_error!(self, "Unexpected character: {}", c);
+                    },
+                    _ => {
+                        let val = try!(self.parse_identifier());
+                        if self.lenient() && val.len() > 1 && val.ends_with(':') {
+                            // key: is another spelling of #:key
+                            let name = val[..val.len() - 1].to_string();
+                            try!(self.push(Token::Keyword(name)));
+                        } else if val == "." {
+                            try!(self.push(Token::Dot));
+                        } else {
+                            try!(self.push(Token::Identifier(val)));
+                        }
+                        try!(self.parse_delimiter());
                     }
-                },
-                None => break
-            }
-        };
-        Ok(())
+                }
+                Ok(true)
+            },
+            None => Ok(false)
+        }
     }
 
     fn parse_number(&mut self) -> Result<i64, SyntaxError> {
@@ -194,6 +550,163 @@ impl<'a> Lexer<'a> {
         }
     }
 
+    // An integer, a rational (1/3) or a float if it has a decimal point or an exponent (1.5, 1., .5 or 6.02e23), after
+    // any sign
+    fn parse_real(&mut self, negative: bool) -> Result<Token, SyntaxError> {
+        let mut s = if negative { "-".to_string() } else { String::new() };
+        self.parse_digits(&mut s);
+        let mut float = false;
+        if self.current() == Some('.') {
+            float = true;
+            s.push('.');
+            self.advance();
+            self.parse_digits(&mut s);
+        }
+        match self.current() {
+            Some('e') | Some('E') => {
+                float = true;
+                s.push('e');
+                self.advance();
+                match self.current() {
+                    Some(c) if c == '+' || c == '-' => {
+                        s.push(c);
+                        self.advance();
+                    },
+                    _ => ()
+                }
+                if !self.parse_digits(&mut s) {
+                    syntax_error!(self, "Not a number: {}", s);
+                }
+            },
+            _ => ()
+        }
+        if float {
+            // digits with a point or an exponent always parse, however many there are
+            Ok(Token::Float(s.parse().unwrap()))
+        } else if self.current() == Some('/') {
+            s.push('/');
+            self.advance();
+            let numerator = s.len() - 1;
+            if !self.parse_digits(&mut s) {
+                syntax_error!(self, "Not a number: {}", s);
+            }
+            match (s[..numerator].parse(), s[numerator + 1..].parse()) {
+                (Ok(n), Ok(d)) => Ok(Token::Rational(n, d)),
+                _ => syntax_error!(self, "Integer too large: {}", s)
+            }
+        } else {
+            match s.parse() {
+                Ok(value) => Ok(Token::Integer(value)),
+                Err(_) => syntax_error!(self, "Integer too large: {}", s)
+            }
+        }
+    }
+
+    // Add the digits at the current character to s, returning whether there were any
+    fn parse_digits(&mut self, s: &mut String) -> bool {
+        let start = s.len();
+        while let Some(c) = self.current() {
+            if !c.is_ascii_digit() {
+                break
+            }
+            s.push(c);
+            self.advance();
+        }
+        s.len() > start
+    }
+
+    // #t/#true, #f/#false, or a number with prefixes (see prefixed_number)
+    fn parse_hash(&mut self) -> Result<Token, SyntaxError> {
+        match self.peek() {
+            Some(c) if is_number_prefix(c) => {
+                self.advance();
+                self.parse_prefixed_number()
+            },
+            _ => self.parse_boolean().map(Token::Boolean)
+        }
+    }
+
+    // A number from the letter of its first prefix on, reading the #s between prefixes here, since the strict reader
+    // doesn't allow them in an identifier
+    fn parse_prefixed_number(&mut self) -> Result<Token, SyntaxError> {
+        let mut word = String::new();
+        while let Some(c) = self.current() {
+            word.push(c);
+            self.advance();
+            if self.current() != Some('#') || !self.peek().is_some_and(is_number_prefix) {
+                break
+            }
+            word.push('#');
+            self.advance();
+        }
+        word.push_str(&try!(self.parse_identifier()));
+        self.prefixed_number(&word)
+    }
+
+    // The number a word read after # stands for, which starts with a radix (#xff, #o777 or #b1010), an exactness
+    // (#e1.5 or #i3), or both in either order (#e#x10 or #x#i10). A radix takes an integer with any sign (#x-ff), and
+    // an exactness a decimal or a rational, so #e1.25 is 5/4 and #i1/2 is 0.5.
+    fn prefixed_number(&mut self, word: &str) -> Result<Token, SyntaxError> {
+        let (mut radix_, mut exact) = (None, None);
+        let mut rest = word;
+        loop {
+            let prefix = rest.chars().next().unwrap();
+            match (radix(prefix), exactness(prefix)) {
+                (Some(r), _) if radix_.is_none() => radix_ = Some(r),
+                (_, Some(e)) if exact.is_none() => exact = Some(e),
+                _ => syntax_error!(self, "Not a number: #{}", word)
+            }
+            rest = &rest[prefix.len_utf8()..];
+            if rest.len() > 1 && rest.starts_with('#') {
+                rest = &rest[1..];
+            } else {
+                break
+            }
+        }
+        let digits = rest.trim_start_matches(['+', '-']);
+        if rest.len() - digits.len() > 1 {
+            syntax_error!(self, "Not a number: #{}", word);
+        }
+        let negative = rest.starts_with('-');
+        let value = match radix_ {
+            Some(radix) => {
+                if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+                    syntax_error!(self, "Not a number: #{}", word);
+                }
+                i64::from_str_radix(rest, radix).ok().map(Token::Integer)
+            },
+            None => match digits.find('/') {
+                Some(i) => {
+                    let (numerator, denominator) = (&digits[..i], &digits[i + 1..]);
+                    if !is_digits(numerator) || !is_digits(denominator) {
+                        syntax_error!(self, "Not a number: #{}", word);
+                    }
+                    match (numerator.parse::<i64>(), denominator.parse::<i64>()) {
+                        (_, Ok(0)) => syntax_error!(self, "Division by zero in #{}", word),
+                        (Ok(n), Ok(d)) => Some(Token::Rational(if negative { -n } else { n }, d)),
+                        _ => None
+                    }
+                },
+                None => match decimal(digits) {
+                    // an inexact decimal is read as it would be without the prefix, rather than rounded from an
+                    // exact one
+                    Some(_) if exact == Some(false) => Some(Token::Float(rest.parse().unwrap())),
+                    Some((digits, exponent)) => exact_decimal(&digits, exponent, negative),
+                    None => syntax_error!(self, "Not a number: #{}", word)
+                }
+            }
+        };
+        let value = match value {
+            Some(value) => value,
+            None => syntax_error!(self, "Integer too large: #{}", word)
+        };
+        Ok(match (value, exact) {
+            (Token::Integer(n), Some(false)) => Token::Float(n as f64),
+            (Token::Rational(n, d), Some(false)) => Token::Float(n as f64 / d as f64),
+            (value, _) => value
+        })
+    }
+
     fn parse_boolean(&mut self) -> Result<bool, SyntaxError> {
         if self.current() != Some('#') { syntax_error!(self, "Unexpected character: {}", self.current().unwrap()) };
         self.advance();
@@ -201,10 +714,12 @@ impl<'a> Lexer<'a> {
         match self.current() {
             Some('t') => {
                 self.advance();
+                try!(self.parse_rest_of_word("rue"));
                 Ok(true)
             },
             Some('f') => {
                 self.advance();
+                try!(self.parse_rest_of_word("alse"));
                 Ok(false)
             },
             _ => {
@@ -213,6 +728,217 @@ impl<'a> Lexer<'a> {
         }
     }
 
+    // After the first letter of #t/#true or #f/#false, either stop or expect the rest of the long spelling
+    fn parse_rest_of_word(&mut self, rest: &str) -> Result<(), SyntaxError> {
+        if self.current() != rest.chars().next() {
+            return Ok(());
+        }
+        for expected in rest.chars() {
+            match self.current() {
+                Some(c) if c == expected => self.advance(),
+                Some(c) => syntax_error!(self, "Unexpected character when looking for {}: {}", rest, c),
+                None => syntax_error!(self, "Expected {}, but found EOF instead", rest)
+            }
+        }
+        Ok(())
+    }
+
+    // #name"text" for a registered name, or #i(...) when infix is on, though #t, #true, #f and #false are still
+    // booleans
+    fn parse_extension(&mut self) -> Result<Token, SyntaxError> {
+        // skip past the #
+        self.advance();
+        if self.peek() == Some('#') && self.current().is_some_and(is_number_prefix) {
+            return self.parse_prefixed_number()
+        }
+        let name = try!(self.parse_identifier());
+        if name == "i" && self.config.infix && self.current() == Some('(') {
+            return self.parse_infix()
+        }
+        if self.current() != Some('\"') {
+            return match name.as_ref() {
+                "t" | "true" => Ok(Token::Boolean(true)),
+                "f" | "false" => Ok(Token::Boolean(false)),
+                _ if is_number_prefix(name.chars().next().unwrap()) => self.prefixed_number(&name),
+                _ => syntax_error!(self, "Unknown reader syntax: #{}", name)
+            }
+        }
+        let reader = match self.config.extensions.readers.get(&name) {
+            Some(reader) => reader.clone(),
+            None => syntax_error!(self, "Unknown reader extension: #{}", name)
+        };
+        let text = try!(self.parse_string());
+        match reader(&text) {
+            Ok(node) => Ok(Token::Datum(node)),
+            Err(e) => syntax_error!(self, "Invalid #{} literal: {}", name, e)
+        }
+    }
+
+    // #"x = ~{(+ 1 2)}" reads as (display->string "x = " (+ 1 2)), so each ~{expression} is evaluated where the
+    // string is and spliced in as display would show it. ~~ stands for a ~ of its own.
+    fn parse_interpolated_string(&mut self) -> Result<Token, SyntaxError> {
+        let text = try!(self.parse_string());
+        let mut parts = vec![Node::Identifier("display->string".to_string())];
+        let mut literal = String::new();
+        let mut rest = &text[..];
+        while let Some(i) = rest.find('~') {
+            literal.push_str(&rest[..i]);
+            rest = &rest[i..];
+            if rest.starts_with("~~") {
+                literal.push('~');
+                rest = &rest[2..];
+            } else if rest.starts_with("~{") {
+                let end = match rest.find('}') {
+                    Some(end) => end,
+                    None => eof_error!(self, "Expected } to end ~{ in an interpolated string")
+                };
+                if !literal.is_empty() {
+                    parts.push(Node::String(literal.split_off(0)));
+                }
+                parts.push(try!(self.parse_interpolated_expression(&rest[2..end])));
+                rest = &rest[end + 1..];
+            } else {
+                literal.push('~');
+                rest = &rest[1..];
+            }
+        }
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            parts.push(Node::String(literal));
+        }
+        Ok(Token::Datum(Node::List(parts)))
+    }
+
+    fn parse_interpolated_expression(&mut self, source: &str) -> Result<Node, SyntaxError> {
+        let tokens = match Lexer::tokenize(source, self.config) {
+            Ok(tokens) => tokens,
+            Err(e) => syntax_error!(self, "Invalid expression in interpolated string: {}", e.message)
+        };
+        match parser::parse(&tokens) {
+            Ok(mut nodes) => {
+                if nodes.len() != 1 {
+                    syntax_error!(self, "Expected one expression in ~{...}, but found {}", nodes.len());
+                }
+                Ok(nodes.remove(0))
+            },
+            Err(e) => syntax_error!(self, "Invalid expression in interpolated string: {}", e)
+        }
+    }
+
+    // The parenthesized text after #i, up to the matching close paren
+    fn parse_infix(&mut self) -> Result<Token, SyntaxError> {
+        let mut text = String::new();
+        let mut depth = 0;
+        loop {
+            match self.current() {
+                Some(c) => {
+                    self.advance();
+                    match c {
+                        '(' => depth += 1,
+                        ')' => depth -= 1,
+                        _ => ()
+                    }
+                    if depth == 0 {
+                        break
+                    }
+                    text.push(c);
+                },
+                None => eof_error!(self, "Expected ) to end #i(, but found EOF instead")
+            }
+        }
+        match infix::parse(&text[1..]) {
+            Ok(node) => Ok(Token::Datum(node)),
+            Err(e) => syntax_error!(self, "Invalid infix expression: {}", e)
+        }
+    }
+
+    // #n= labels the datum after it, and #n# refers back to it
+    fn parse_label(&mut self) -> Result<Token, SyntaxError> {
+        // skip past the #, and let parse_number read n
+        self.advance();
+        let n = try!(self.parse_number());
+        if n > u32::MAX as i64 {
+            syntax_error!(self, "Datum label is too large: {}", n);
+        }
+        match self.current() {
+            Some('=') => {
+                self.advance();
+                Ok(Token::Label(n as u32))
+            },
+            Some('#') => {
+                self.advance();
+                Ok(Token::Reference(n as u32))
+            },
+            Some(c) => syntax_error!(self, "Expected = or # after #{}, but found {}", n, c),
+            None => eof_error!(self, "Expected = or # after #{}, but found EOF instead", n)
+        }
+    }
+
+    // #\a, a named character like #\space, or a character by its code point in hex, like #\x3bb
+    fn parse_character(&mut self) -> Result<char, SyntaxError> {
+        // skip past the #\ and take the character after it
+        self.advance();
+        self.advance();
+        let c = match self.current() {
+            Some(c) => c,
+            None => eof_error!(self, "Expected a character after #\\, but found EOF instead")
+        };
+        self.advance();
+        // any character stands for itself, even a delimiter, but a letter followed by more is a name
+        if !c.is_alphabetic() {
+            return Ok(c)
+        }
+        let name = format!("{}{}", c, try!(self.parse_identifier()));
+        if name.chars().count() == 1 {
+            return Ok(c)
+        }
+        if let Some(&(_, c)) = printer::CHARACTER_NAMES.iter().find(|n| n.0 == name) {
+            return Ok(c);
+        }
+        let code = name.strip_prefix('x').and_then(|hex| u32::from_str_radix(hex, 16).ok());
+        match code.and_then(char::from_u32) {
+            Some(c) => Ok(c),
+            None => syntax_error!(self, "Unknown character name: #\\{}", name)
+        }
+    }
+
+    // Skip a #| ... |# comment, which can contain others: it only ends once each #| in it has been matched
+    fn parse_block_comment(&mut self) -> Result<(), SyntaxError> {
+        // an unterminated comment is reported where it starts rather than at the end of the input
+        let (line, column) = (self.line, self.column);
+        let mut depth = 1;
+        self.advance();
+        self.advance();
+        while depth > 0 {
+            match self.current() {
+                Some('|') if self.peek() == Some('#') => {
+                    depth -= 1;
+                    self.advance();
+                },
+                Some('#') if self.peek() == Some('|') => {
+                    depth += 1;
+                    self.advance();
+                },
+                Some(_) => (),
+                None => return Err(SyntaxError { message: "Expected |# to end the block comment, but found EOF instead".to_string(),
+                                                 line, column, kind: SyntaxErrorKind::Incomplete })
+            }
+            self.advance();
+        }
+        Ok(())
+    }
+
+    fn parse_eof_object(&mut self) -> Result<(), SyntaxError> {
+        // skip past the #!
+        self.advance();
+        self.advance();
+        let name = try!(self.parse_identifier());
+        if name != "eof" {
+            syntax_error!(self, "Unknown #! syntax: #!{}", name);
+        }
+        Ok(())
+    }
+
     fn parse_identifier(&mut self) -> Result<String, SyntaxError> {
         let mut s = String::new();
         loop {
@@ -225,6 +951,9 @@ impl<'a> Lexer<'a> {
                         '(' | ')' | '[' | ']' | '{' | '}' | '\"' | ',' | '\'' | '`' | ';' | '|' | '\\' => {
                             break;
                         },
+                        _ if !self.lenient() && !text::is_identifier_char(c) && !self.config.extended_chars.contains(c) => {
+                            syntax_error!(self, "Unexpected character in an identifier: {}", c);
+                        },
                         _ => {
                             s.push(c);
                             self.advance();
@@ -237,11 +966,61 @@ impl<'a> Lexer<'a> {
         Ok(s)
     }
 
+    // A symbol written between bars, like |hello world|, which can hold any characters: \| and \\ stand for a bar and
+    // a backslash, \t, \n, \r, \a and \b for those control characters, and \xHH; for the character with that code
+    fn parse_bar_identifier(&mut self) -> Result<String, SyntaxError> {
+        self.advance();
+        let mut s = String::new();
+        loop {
+            match self.current() {
+                Some('|') => {
+                    self.advance();
+                    return Ok(s)
+                },
+                Some('\\') => {
+                    self.advance();
+                    let c = match self.current() {
+                        Some('|') => '|',
+                        Some('\\') => '\\',
+                        Some('t') => '\t',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('a') => '\x07',
+                        Some('b') => '\x08',
+                        Some('x') => {
+                            let mut digits = String::new();
+                            self.advance();
+                            while self.current().is_some_and(|c| c.is_ascii_hexdigit()) {
+                                digits.push(self.current().unwrap());
+                                self.advance();
+                            }
+                            let code = u32::from_str_radix(&digits, 16).ok().and_then(char::from_u32);
+                            match (code, self.current()) {
+                                (Some(c), Some(';')) => c,
+                                _ => syntax_error!(self, "Expected a hex character code ending with ; after \\x in a symbol")
+                            }
+                        },
+                        Some(c) => syntax_error!(self, "Unknown escape in a symbol: \\{}", c),
+                        None => eof_error!(self, "Expected a closing |, but found EOF instead")
+                    };
+                    s.push(c);
+                    self.advance();
+                },
+                Some(c) => {
+                    s.push(c);
+                    self.advance();
+                },
+                None => eof_error!(self, "Expected a closing |, but found EOF instead")
+            }
+        }
+    }
+
     fn parse_string(&mut self) -> Result<String, SyntaxError> {
         if self.current() != Some('\"') { syntax_error!(self, "Unexpected character: {}", self.current().unwrap()) };
         self.advance();
 
         let mut s = String::new();
+        let mut length = 0;
         loop {
             match self.current() {
                 Some(c) => {
@@ -253,39 +1032,215 @@ impl<'a> Lexer<'a> {
                         _ => {
                             s.push(c);
                             self.advance();
+                            length += 1;
+                            if self.config.limits.max_string_length.is_some_and(|max| length > max) {
+                                limit_error!(self, "String is longer than the limit of {} characters",
+                                             self.config.limits.max_string_length.unwrap());
+                            }
                         }
                     }
                 },
-                None => syntax_error!(self, "Expected end quote, but found EOF instead")
+                None => eof_error!(self, "Expected end quote, but found EOF instead")
             }
         }
+        if self.config.normalize_strings {
+            s = text::nfc(&s);
+        }
         Ok(s)
     }
 
     fn parse_delimiter(&mut self) -> Result<(), SyntaxError> {
-        match self.current() {
-            Some(c) => {
-                match c {
-                    _ if c.is_whitespace() => (),
-                    ')' => {
-                        self.tokens.push(Token::CloseParen);
-                        self.advance();
-                    },
-                    _ => syntax_error!(self, "Unexpected character when looking for a delimiter: {}", c),
-                }
-            },
-            None => ()
+        if let Some(c) = self.current() {
+            match c {
+                // a comment ends the token too, and is skipped like any other
+                _ if c.is_whitespace() || c == ';' => (),
+                '#' if self.peek() == Some('|') => (),
+                ')' => {
+                    try!(self.push(Token::CloseParen));
+                    self.advance();
+                },
+                ']' if self.lenient() => {
+                    try!(self.push(Token::CloseBracket));
+                    self.advance();
+                },
+                _ => syntax_error!(self, "Unexpected character when looking for a delimiter: {}", c),
+            }
         };
         Ok(())
     }
 }
 
+// The radix a prefix letter after # stands for
+fn radix(prefix: char) -> Option<u32> {
+    match prefix {
+        'x' | 'X' => Some(16),
+        'o' | 'O' => Some(8),
+        'b' | 'B' => Some(2),
+        _ => None
+    }
+}
+
+// Whether an exactness prefix letter after # stands for exact numbers rather than inexact ones
+fn exactness(prefix: char) -> Option<bool> {
+    match prefix {
+        'e' | 'E' => Some(true),
+        'i' | 'I' => Some(false),
+        _ => None
+    }
+}
+
+fn is_number_prefix(c: char) -> bool {
+    radix(c).is_some() || exactness(c).is_some()
+}
+
+fn is_digits(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(|c| c.is_ascii_digit())
+}
+
+// The digits of an unsigned decimal like 1.25e1, without its point, and the power of ten they're multiplied by: 125
+// and -1. None unless it's digits with a point before, among or after them (.5, 1.5 or 1.), or none, and then an
+// optional exponent.
+fn decimal(s: &str) -> Option<(String, i32)> {
+    let (mantissa, exponent) = match s.find(['e', 'E']) {
+        Some(i) => {
+            let exponent = &s[i + 1..];
+            if !is_digits(exponent.trim_start_matches(['+', '-'])) || exponent.len() > 6 {
+                return None
+            }
+            (&s[..i], exponent.parse().unwrap())
+        },
+        None => (s, 0)
+    };
+    let (whole, fraction) = match mantissa.find('.') {
+        Some(i) => (&mantissa[..i], &mantissa[i + 1..]),
+        None => (mantissa, "")
+    };
+    let digits = (is_digits(whole) && (fraction.is_empty() || is_digits(fraction))) || (whole.is_empty() && is_digits(fraction));
+    if !digits {
+        return None
+    }
+    Some((format!("{}{}", whole, fraction), exponent - fraction.len() as i32))
+}
+
+// The exact number digits * 10^exponent, negated if negative is, if it fits
+fn exact_decimal(digits: &str, exponent: i32, negative: bool) -> Option<Token> {
+    let digits = digits.trim_start_matches('0');
+    let n = if digits.is_empty() { Some(0) } else { digits.parse::<i128>().ok() };
+    let value = n.and_then(|n| {
+        let n = if negative { -n } else { n };
+        10i128.checked_pow(exponent.unsigned_abs()).and_then(|scale| {
+            if exponent < 0 { Node::rational(n, scale) } else { n.checked_mul(scale).and_then(|n| Node::rational(n, 1)) }
+        })
+    });
+    match value {
+        Some(Node::Integer(n)) => Some(Token::Integer(n)),
+        Some(Node::Rational(n, d)) => Some(Token::Rational(n, d)),
+        _ => None
+    }
+}
+
 #[test]
 fn test_lexer_simple_lexing() {
     assert_eq!(tokenize("(+ 2 3)").unwrap(),
                vec![Token::OpenParen, Token::Identifier("+".to_string()), Token::Integer(2), Token::Integer(3), Token::CloseParen]);
 }
 
+#[test]
+fn test_lexer_comments() {
+    assert_eq!(tokenize("; start\n(x; one\n \"s\";two\n1.5;three\n#t;)\n);end").unwrap(),
+               vec![Token::OpenParen, Token::Identifier("x".to_string()), Token::String("s".to_string()), Token::Float(1.5),
+                    Token::Boolean(true), Token::CloseParen]);
+    // positions after a comment still count its line
+    assert_eq!(tokenize("x ; comment\n  }").err().unwrap().to_string(), "SyntaxError: Unexpected character: } (line: 2, column: 3)");
+}
+
+#[test]
+fn test_lexer_block_comments() {
+    assert_eq!(tokenize("(a #| b (c |# d #| e |#)").unwrap(),
+               vec![Token::OpenParen, Token::Identifier("a".to_string()), Token::Identifier("d".to_string()), Token::CloseParen]);
+    // comments nest, so one can comment out code that has comments in it
+    assert_eq!(tokenize("#| x #| y |# z |# 1#|one|#\n#||#2").unwrap(), vec![Token::Integer(1), Token::Integer(2)]);
+    // an unterminated comment is reported at the #| that's missing its end, and more input could finish it
+    let error = tokenize("1 #| a\n  #| b |#\n  #| c\n").err().unwrap();
+    assert_eq!(error.to_string(), "SyntaxError: Expected |# to end the block comment, but found EOF instead (line: 1, column: 3)");
+    assert!(error.is_incomplete());
+    assert_eq!(tokenize("#| a #| b\n c |#").err().unwrap().to_string(),
+               "SyntaxError: Expected |# to end the block comment, but found EOF instead (line: 1, column: 1)");
+    // positions after a comment still count its lines
+    assert_eq!(tokenize("#| a\nb |# }").err().unwrap().to_string(), "SyntaxError: Unexpected character: } (line: 2, column: 6)");
+}
+
+#[test]
+fn test_lexer_datum_comments() {
+    assert_eq!(tokenize("(a #;(b) #; c)").unwrap(),
+               vec![Token::OpenParen, Token::Identifier("a".to_string()), Token::DatumComment, Token::OpenParen,
+                    Token::Identifier("b".to_string()), Token::CloseParen, Token::DatumComment,
+                    Token::Identifier("c".to_string()), Token::CloseParen]);
+}
+
+#[test]
+fn test_lexer_vectors() {
+    assert_eq!(tokenize("#(1 #(a) ())").unwrap(),
+               vec![Token::OpenVector, Token::Integer(1), Token::OpenVector, Token::Identifier("a".to_string()), Token::CloseParen,
+                    Token::OpenParen, Token::CloseParen, Token::CloseParen]);
+}
+
+#[test]
+fn test_lexer_dots() {
+    assert_eq!(tokenize("(a . b) (... .a)").unwrap(),
+               vec![Token::OpenParen, Token::Identifier("a".to_string()), Token::Dot, Token::Identifier("b".to_string()),
+                    Token::CloseParen, Token::OpenParen, Token::Identifier("...".to_string()), Token::Identifier(".a".to_string()),
+                    Token::CloseParen]);
+}
+
+#[test]
+fn test_lexer_bytevectors() {
+    assert_eq!(tokenize("#u8(0 255) #u8()").unwrap(),
+               vec![Token::OpenBytevector, Token::Integer(0), Token::Integer(255), Token::CloseParen, Token::OpenBytevector,
+                    Token::CloseParen]);
+    assert_eq!(tokenize("#u16(1)").err().unwrap().to_string(), "SyntaxError: Unexpected character when looking for t/f: u (line: 1, column: 2)");
+    assert!(tokenize("#u8").is_err());
+}
+
+#[test]
+fn test_lexer_iterator() {
+    let config = ReaderConfig::default();
+    let mut lexer = Lexer::new("(car x) (cdr 'y) #bad", &config);
+    assert_eq!(lexer.next().unwrap().unwrap(), Token::OpenParen);
+    let rest: Vec<Token> = lexer.by_ref().take(5).map(|t| t.unwrap()).collect();
+    assert_eq!(rest, vec![Token::Identifier("car".to_string()), Token::Identifier("x".to_string()), Token::CloseParen,
+                          Token::OpenParen, Token::Identifier("cdr".to_string())]);
+    assert_eq!(lexer.position(), (1, 13));
+    assert_eq!(lexer.by_ref().map(|t| t.is_ok()).collect::<Vec<bool>>(), vec![true, true, true, false]);
+    assert!(lexer.next().is_none());
+}
+
+#[test]
+fn test_lexer_datum_labels() {
+    assert_eq!(tokenize("#0=#&(#12#)").unwrap(),
+               vec![Token::Label(0), Token::Box, Token::OpenParen, Token::Reference(12), Token::CloseParen]);
+    assert_eq!(tokenize("#0x").err().unwrap().to_string(),
+               "SyntaxError: Expected = or # after #0, but found x (line: 1, column: 3)");
+}
+
+#[test]
+fn test_lexer_limits() {
+    let config = ReaderConfig { limits: ReaderLimits { max_string_length: Some(5), max_depth: Some(2), max_datums: Some(6) },
+                                ..ReaderConfig::default() };
+    assert!(tokenize_with("(a (b \"hello\")) c", &config).is_ok());
+    let errors = vec![("\"hello!\"", "SyntaxError: String is longer than the limit of 5 characters (line: 1, column: 8)"),
+                      ("(a (b (c)))", "SyntaxError: Lists are nested deeper than the limit of 2 (line: 1, column: 7)"),
+                      ("(a b) (c '(d e f))", "SyntaxError: Input has more than the limit of 6 datums (line: 1, column: 13)")];
+    for (input, message) in errors {
+        let e = tokenize_with(input, &config).err().unwrap();
+        assert_eq!(e.kind(), SyntaxErrorKind::LimitExceeded);
+        assert_eq!(e.to_string(), message);
+    }
+    assert!(tokenize("(a\n").is_ok());
+    assert_eq!(tokenize("\"abc").err().unwrap().kind(), SyntaxErrorKind::Incomplete);
+    assert_eq!(tokenize("(22+)").err().unwrap().kind(), SyntaxErrorKind::Invalid);
+}
+
 #[test]
 fn test_lexer_multi_digit_integers() {
     assert_eq!(tokenize("(+ 21 325)").unwrap(),
@@ -304,6 +1259,84 @@ fn test_lexer_negative_integers() {
                vec![Token::OpenParen, Token::Identifier("+".to_string()), Token::Integer(-8), Token::Integer(2), Token::Integer(-33), Token::CloseParen]);
 }
 
+#[test]
+fn test_lexer_floats() {
+    assert_eq!(tokenize("(+ 1.5 -2.25 1. 6.02e23 1E-3 -1e+2)").unwrap(),
+               vec![Token::OpenParen, Token::Identifier("+".to_string()), Token::Float(1.5), Token::Float(-2.25), Token::Float(1.0),
+                    Token::Float(6.02e23), Token::Float(0.001), Token::Float(-100.0), Token::CloseParen]);
+    assert_eq!(tokenize("+inf.0 -inf.0").unwrap(), vec![Token::Float(f64::INFINITY), Token::Float(-f64::INFINITY)]);
+    match tokenize("+nan.0").unwrap()[0] {
+        Token::Float(f) => assert!(f.is_nan()),
+        ref t => panic!("Expected a float, not {:?}", t)
+    }
+    assert_eq!(tokenize("(.5 -.25 +.5e1 (a . b) .x)").unwrap(),
+               vec![Token::OpenParen, Token::Float(0.5), Token::Float(-0.25), Token::Float(5.0),
+                    Token::OpenParen, Token::Identifier("a".to_string()), Token::Dot, Token::Identifier("b".to_string()), Token::CloseParen,
+                    Token::Identifier(".x".to_string()), Token::CloseParen]);
+    assert_eq!(tokenize("1e").err().unwrap().to_string(), "SyntaxError: Not a number: 1e (line: 1, column: 3)");
+    assert_eq!(tokenize("1.5x").err().unwrap().to_string(),
+               "SyntaxError: Unexpected character when looking for a delimiter: x (line: 1, column: 4)");
+    assert_eq!(tokenize("+info").err().unwrap().to_string(),
+               "SyntaxError: Unexpected character when looking for a delimiter: i (line: 1, column: 6)");
+    assert_eq!(tokenize("99999999999999999999").err().unwrap().to_string(),
+               "SyntaxError: Integer too large: 99999999999999999999 (line: 1, column: 21)");
+}
+
+#[test]
+fn test_lexer_radixes() {
+    assert_eq!(tokenize("(#xFF #o777 #b1010 #x-1f #B+11)").unwrap(),
+               vec![Token::OpenParen, Token::Integer(255), Token::Integer(511), Token::Integer(10), Token::Integer(-31),
+                    Token::Integer(3), Token::CloseParen]);
+    assert_eq!(tokenize("#b102").err().unwrap().to_string(), "SyntaxError: Not a number: #b102 (line: 1, column: 6)");
+    assert_eq!(tokenize("#x").err().unwrap().to_string(), "SyntaxError: Not a number: #x (line: 1, column: 3)");
+    assert_eq!(tokenize("#x10000000000000000").err().unwrap().to_string(),
+               "SyntaxError: Integer too large: #x10000000000000000 (line: 1, column: 20)");
+    // also when #name is reader syntax
+    let config = ReaderConfig { infix: true, ..ReaderConfig::default() };
+    assert_eq!(tokenize_with("#x10 #t", &config).unwrap(), vec![Token::Integer(16), Token::Boolean(true)]);
+}
+
+#[test]
+fn test_lexer_exactness() {
+    assert_eq!(tokenize("(#e1.5 #e-1.25e1 #e1e3 #e3 #E1/2 #i3 #i-1/2 #I1.5e1)").unwrap(),
+               vec![Token::OpenParen, Token::Rational(3, 2), Token::Rational(-25, 2), Token::Integer(1000), Token::Integer(3),
+                    Token::Rational(1, 2), Token::Float(3.0), Token::Float(-0.5), Token::Float(15.0), Token::CloseParen]);
+    // with a radix, in either order
+    assert_eq!(tokenize("#e#x10 #x#i-10 #i#b101").unwrap(), vec![Token::Integer(16), Token::Float(-16.0), Token::Float(5.0)]);
+    assert_eq!(tokenize("#e.5 #i-.5").unwrap(), vec![Token::Rational(1, 2), Token::Float(-0.5)]);
+    assert_eq!(tokenize("#e.").err().unwrap().to_string(), "SyntaxError: Not a number: #e. (line: 1, column: 4)");
+    assert_eq!(tokenize("#e").err().unwrap().to_string(), "SyntaxError: Not a number: #e (line: 1, column: 3)");
+    assert_eq!(tokenize("#e1.5.2").err().unwrap().to_string(), "SyntaxError: Not a number: #e1.5.2 (line: 1, column: 8)");
+    assert_eq!(tokenize("#e#i1").err().unwrap().to_string(), "SyntaxError: Not a number: #e#i1 (line: 1, column: 6)");
+    assert_eq!(tokenize("#i1/0").err().unwrap().to_string(), "SyntaxError: Division by zero in #i1/0 (line: 1, column: 6)");
+    assert_eq!(tokenize("#e1e30").err().unwrap().to_string(), "SyntaxError: Integer too large: #e1e30 (line: 1, column: 7)");
+    // in the strict reader, and when #i( is infix
+    let config = ReaderConfig { mode: ReaderMode::Strict, ..ReaderConfig::default() };
+    assert_eq!(tokenize_with("#e#x10 #e0.5", &config).unwrap(), vec![Token::Integer(16), Token::Rational(1, 2)]);
+    let config = ReaderConfig { infix: true, mode: ReaderMode::Strict, ..ReaderConfig::default() };
+    assert_eq!(tokenize_with("#i2 #e#x10 #e0.5", &config).unwrap(), vec![Token::Float(2.0), Token::Integer(16), Token::Rational(1, 2)]);
+}
+
+#[test]
+fn test_lexer_characters() {
+    assert_eq!(tokenize("(#\\a #\\space #\\( #\\) #\\x3bb #\\x #\\λ)").unwrap(),
+               vec![Token::OpenParen, Token::Character('a'), Token::Character(' '), Token::Character('('), Token::Character(')'),
+                    Token::Character('λ'), Token::Character('x'), Token::Character('λ'), Token::CloseParen]);
+    assert_eq!(tokenize("#\\ ").unwrap(), vec![Token::Character(' ')]);
+    assert_eq!(tokenize("#\\spaces").err().unwrap().to_string(), "SyntaxError: Unknown character name: #\\spaces (line: 1, column: 9)");
+    assert_eq!(tokenize("#\\ab)").err().unwrap().to_string(), "SyntaxError: Unknown character name: #\\ab (line: 1, column: 5)");
+    assert!(tokenize("#\\").err().unwrap().is_incomplete());
+}
+
+#[test]
+fn test_lexer_rationals() {
+    assert_eq!(tokenize("(+ 1/3 -2/4)").unwrap(),
+               vec![Token::OpenParen, Token::Identifier("+".to_string()), Token::Rational(1, 3), Token::Rational(-2, 4), Token::CloseParen]);
+    assert_eq!(tokenize("1/").err().unwrap().to_string(), "SyntaxError: Not a number: 1/ (line: 1, column: 3)");
+    assert_eq!(tokenize("1/2.5").err().unwrap().to_string(),
+               "SyntaxError: Unexpected character when looking for a delimiter: . (line: 1, column: 4)");
+}
+
 #[test]
 fn test_lexer_booleans() {
     assert_eq!(tokenize("#t").unwrap(),
@@ -315,11 +1348,24 @@ fn test_lexer_booleans() {
 #[test]
 fn test_lexer_identifiers() {
     for identifier in ["*", "<", "<=", "if", "while", "$t$%*=:t059s"].iter() {
-        assert_eq!(tokenize(*identifier).unwrap(),
+        assert_eq!(tokenize(identifier).unwrap(),
                    vec![Token::Identifier(identifier.to_string())]);
     }
 }
 
+#[test]
+fn test_lexer_bar_identifiers() {
+    assert_eq!(tokenize("(|hello world| |a\\|b\\\\| |\\x3bb;\\t| || |1|)").unwrap(),
+               vec![Token::OpenParen, Token::Identifier("hello world".to_string()), Token::Identifier("a|b\\".to_string()),
+                    Token::Identifier("λ\t".to_string()), Token::Identifier("".to_string()), Token::Identifier("1".to_string()),
+                    Token::CloseParen]);
+    assert_eq!(tokenize("|abc").err().unwrap().to_string(), "SyntaxError: Expected a closing |, but found EOF instead (line: 1, column: 5)");
+    assert_eq!(tokenize("|a\\q|").err().unwrap().to_string(), "SyntaxError: Unknown escape in a symbol: \\q (line: 1, column: 4)");
+    assert_eq!(tokenize("|\\x3bb|").err().unwrap().to_string(),
+               "SyntaxError: Expected a hex character code ending with ; after \\x in a symbol (line: 1, column: 7)");
+    assert!(tokenize("|a|b").is_err());
+}
+
 #[test]
 fn test_lexer_strings() {
     assert_eq!(tokenize("\"hello\"").unwrap(),
@@ -339,8 +1385,61 @@ fn test_lexer_whitespace() {
 
 #[test]
 fn test_lexer_bad_syntax() {
-    assert_eq!(tokenize("([)").err().unwrap().to_string(),
+    let strict = ReaderConfig { mode: ReaderMode::Strict, ..ReaderConfig::default() };
+    assert_eq!(tokenize_with("([)", &strict).err().unwrap().to_string(),
                "SyntaxError: Unexpected character: [ (line: 1, column: 2)");
+    assert_eq!(tokenize("({)").err().unwrap().to_string(),
+               "SyntaxError: Unexpected character: { (line: 1, column: 2)");
+}
+
+#[test]
+fn test_lexer_long_booleans() {
+    assert_eq!(tokenize("#t #true #f #false").unwrap(),
+               vec![Token::Boolean(true), Token::Boolean(true), Token::Boolean(false), Token::Boolean(false)]);
+    assert_eq!(tokenize("#tru").err().unwrap().to_string(),
+               "SyntaxError: Expected rue, but found EOF instead (line: 1, column: 5)");
+    assert_eq!(tokenize("#fals)").err().unwrap().to_string(),
+               "SyntaxError: Unexpected character when looking for alse: ) (line: 1, column: 6)");
+}
+
+#[test]
+fn test_lexer_eof_object() {
+    let strict = ReaderConfig { mode: ReaderMode::Strict, ..ReaderConfig::default() };
+    assert_eq!(tokenize("(#!eof)").unwrap(),
+               vec![Token::OpenParen, Token::EofObject, Token::CloseParen]);
+    assert_eq!(tokenize("#!foo").err().unwrap().to_string(),
+               "SyntaxError: Unknown #! syntax: #!foo (line: 1, column: 6)");
+    assert!(tokenize_with("#!eof", &strict).is_err());
+}
+
+#[test]
+fn test_lexer_keywords() {
+    let strict = ReaderConfig { mode: ReaderMode::Strict, ..ReaderConfig::default() };
+    assert_eq!(tokenize("(f #:size 2 color: red :)").unwrap(),
+               vec![Token::OpenParen, Token::Identifier("f".to_string()), Token::Keyword("size".to_string()), Token::Integer(2),
+                    Token::Keyword("color".to_string()), Token::Identifier("red".to_string()), Token::Identifier(":".to_string()), Token::CloseParen]);
+    assert_eq!(tokenize_with("color:", &strict).unwrap(),
+               vec![Token::Identifier("color:".to_string())]);
+    assert_eq!(tokenize("#: x").err().unwrap().to_string(),
+               "SyntaxError: Expected a keyword name after #: (line: 1, column: 3)");
+}
+
+#[test]
+fn test_lexer_normalize_strings() {
+    let nfc = ReaderConfig { normalize_strings: true, ..ReaderConfig::default() };
+    assert_eq!(tokenize_with("\"cafe\u{301}\"", &nfc).unwrap(),
+               vec![Token::String("caf\u{e9}".to_string())]);
+    assert_eq!(tokenize("\"cafe\u{301}\"").unwrap(),
+               vec![Token::String("cafe\u{301}".to_string())]);
+}
+
+#[test]
+fn test_lexer_reader_modes() {
+    let strict = ReaderConfig { mode: ReaderMode::Strict, ..ReaderConfig::default() };
+    assert_eq!(tokenize("[a 1]").unwrap(),
+               vec![Token::OpenBracket, Token::Identifier("a".to_string()), Token::Integer(1), Token::CloseBracket]);
+    assert_eq!(tokenize_with("(a 1]", &strict).err().unwrap().to_string(),
+               "SyntaxError: Unexpected character when looking for a delimiter: ] (line: 1, column: 5)");
 }
 
 #[test]
@@ -374,6 +1473,10 @@ fn test_lexer_quasiquoting() {
                vec![Token::Quasiquote, Token::OpenParen, Token::Unquote, Token::Identifier("a".to_string()), Token::CloseParen]);
     assert_eq!(tokenize("`(,a b ,c)").unwrap(),
                vec![Token::Quasiquote, Token::OpenParen, Token::Unquote, Token::Identifier("a".to_string()), Token::Identifier("b".to_string()), Token::Unquote, Token::Identifier("c".to_string()), Token::CloseParen]);
+    assert_eq!(tokenize("`(a ,@b)").unwrap(),
+               vec![Token::Quasiquote, Token::OpenParen, Token::Identifier("a".to_string()), Token::UnquoteSplicing, Token::Identifier("b".to_string()), Token::CloseParen]);
+    assert_eq!(tokenize(", @b").unwrap(),
+               vec![Token::Unquote, Token::Identifier("@b".to_string())]);
 }
 
 #[test]
@@ -390,4 +1493,79 @@ fn test_lexer_unicode_identifiers() {
                vec![Token::Identifier("★☎♫✂".to_string())]);
     assert_eq!(tokenize("日本国").unwrap(),
                vec![Token::Identifier("日本国".to_string())]);
+    // the strict reader only takes letters, digits, marks and the extended characters
+    let strict = ReaderConfig { mode: ReaderMode::Strict, ..ReaderConfig::default() };
+    for identifier in ["café", "cafe\u{301}", "λx", "число", "変数", "x²", "a->b?"].iter() {
+        assert_eq!(tokenize_with(identifier, &strict).unwrap(), vec![Token::Identifier(identifier.to_string())]);
+    }
+    assert_eq!(tokenize("λ→μ").unwrap(), vec![Token::Identifier("λ→μ".to_string())]);
+    assert_eq!(tokenize_with("λ→μ", &strict).err().unwrap().to_string(),
+               "SyntaxError: Unexpected character in an identifier: → (line: 1, column: 2)");
+    let arrows = ReaderConfig { extended_chars: format!("{}→", EXTENDED_CHARS), ..strict };
+    assert_eq!(tokenize_with("λ→μ", &arrows).unwrap(), vec![Token::Identifier("λ→μ".to_string())]);
+}
+
+#[test]
+fn test_lexer_reader_extensions() {
+    let mut config = ReaderConfig::default();
+    config.extensions.register("date", |text| {
+        let parts: Result<Vec<i64>, _> = text.split('-').map(|p| p.parse()).collect();
+        match parts {
+            Ok(ref parts) if parts.len() == 3 => {
+                Ok(Node::List(vec![Node::Identifier("date".to_string()), Node::Integer(parts[0]), Node::Integer(parts[1]), Node::Integer(parts[2])]))
+            },
+            _ => Err(format!("not a date: {}", text))
+        }
+    });
+    assert_eq!(tokenize_with("(f #date\"2024-01-31\" #t #false)", &config).unwrap(),
+               vec![Token::OpenParen, Token::Identifier("f".to_string()),
+                    Token::Datum(Node::List(vec![Node::Identifier("date".to_string()), Node::Integer(2024), Node::Integer(1), Node::Integer(31)])),
+                    Token::Boolean(true), Token::Boolean(false), Token::CloseParen]);
+    assert_eq!(tokenize_with("#date\"soon\"", &config).err().unwrap().to_string(),
+               "SyntaxError: Invalid #date literal: not a date: soon (line: 1, column: 12)");
+    assert_eq!(tokenize_with("#time\"12:00\"", &config).err().unwrap().to_string(),
+               "SyntaxError: Unknown reader extension: #time (line: 1, column: 6)");
+    assert_eq!(tokenize_with("#dat", &config).err().unwrap().to_string(),
+               "SyntaxError: Unknown reader syntax: #dat (line: 1, column: 5)");
+    assert_eq!(tokenize("#date\"2024-01-31\"").err().unwrap().to_string(),
+               "SyntaxError: Unexpected character when looking for t/f: d (line: 1, column: 2)");
+}
+
+#[test]
+fn test_lexer_infix() {
+    let infix = ReaderConfig { infix: true, ..ReaderConfig::default() };
+    assert_eq!(tokenize_with("(f #i(2 * (x + 1)) #t)", &infix).unwrap(),
+               vec![Token::OpenParen, Token::Identifier("f".to_string()),
+                    Token::Datum(Node::List(vec![Node::Identifier("*".to_string()), Node::Integer(2),
+                                                 Node::List(vec![Node::Identifier("+".to_string()), Node::Identifier("x".to_string()), Node::Integer(1)])])),
+                    Token::Boolean(true), Token::CloseParen]);
+    assert_eq!(tokenize_with("#i(1 +)", &infix).err().unwrap().to_string(),
+               "SyntaxError: Invalid infix expression: unexpected end of expression (line: 1, column: 8)");
+    assert_eq!(tokenize_with("#i(1 + (2", &infix).err().unwrap().to_string(),
+               "SyntaxError: Expected ) to end #i(, but found EOF instead (line: 1, column: 10)");
+    assert!(tokenize("#i(1 + 2)").is_err());
+}
+
+#[test]
+fn test_lexer_interpolated_strings() {
+    fn display(parts: Vec<Node>) -> Vec<Token> {
+        let mut list = vec![Node::Identifier("display->string".to_string())];
+        list.extend(parts);
+        vec![Token::Datum(Node::List(list))]
+    }
+    assert_eq!(tokenize("#\"x = ~{(+ 1 2)}!\"").unwrap(),
+               display(vec![Node::String("x = ".to_string()),
+                            Node::List(vec![Node::Identifier("+".to_string()), Node::Integer(1), Node::Integer(2)]),
+                            Node::String("!".to_string())]));
+    assert_eq!(tokenize("#\"~{a}~{b} costs ~~5~\"").unwrap(),
+               display(vec![Node::Identifier("a".to_string()), Node::Identifier("b".to_string()), Node::String(" costs ~5~".to_string())]));
+    assert_eq!(tokenize("#\"\"").unwrap(), display(vec![]));
+    assert_eq!(tokenize("#\"~{a b}\"").err().unwrap().to_string(),
+               "SyntaxError: Expected one expression in ~{...}, but found 2 (line: 1, column: 10)");
+    assert_eq!(tokenize("#\"~{(a}\"").err().unwrap().to_string(),
+               "SyntaxError: Invalid expression in interpolated string: ParseError: Unexpected end of input, depth: 1 (line: 1, column: 9)");
+    assert_eq!(tokenize("#\"~{a\"").err().unwrap().to_string(),
+               "SyntaxError: Expected } to end ~{ in an interpolated string (line: 1, column: 7)");
+    let strict = ReaderConfig { mode: ReaderMode::Strict, ..ReaderConfig::default() };
+    assert!(tokenize_with("#\"x\"", &strict).is_err());
 }
@@ -0,0 +1,67 @@
+// RustyScheme as a library. The lexer, parser and macro expander can be used on their own (e.g. for tooling), and
+// the interpreter module wraps both evaluators behind a single string-in, string-out API.
+#[macro_use]
+pub mod messages;
+pub mod lexer;
+pub mod parser;
+pub mod expand;
+pub mod lint;
+pub mod interpreter;
+pub mod printer;
+pub mod stats;
+pub mod bundle;
+pub mod cbor;
+pub mod docs;
+pub mod plugin;
+pub mod watch;
+mod aliases;
+mod ast_walk_interpreter;
+mod bindings;
+mod capabilities;
+mod condition;
+mod cps_interpreter;
+mod features;
+mod fold;
+mod infix;
+mod log;
+mod random;
+mod ranges;
+mod search_path;
+mod shared;
+mod strict;
+mod text;
+mod warning;
+
+#[cfg(feature = "io")]
+mod canonical;
+#[cfg(feature = "io")]
+mod csv;
+#[cfg(feature = "io")]
+mod files;
+#[cfg(feature = "io")]
+mod input;
+#[cfg(feature = "io")]
+mod paths;
+
+mod numbers;
+
+#[cfg(feature = "conformance")]
+pub mod conformance;
+
+#[cfg(feature = "net")]
+mod http;
+
+#[cfg(all(unix, feature = "process"))]
+mod signals;
+
+#[cfg(feature = "parallel")]
+mod parallel;
+
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+#[cfg(feature = "ffi")]
+mod ffi;
+
+#[cfg(not(test))]
+mod repl;
@@ -0,0 +1,353 @@
+// Checks parsed code for likely mistakes without running it. The CLI's --lint prints what it finds for a file;
+// tooling can call lint on the nodes from parser::parse_str.
+//
+// Only the cps interpreter does tail-call optimization, and only for calls in tail position, so a procedure that
+// loops by calling itself anywhere else takes stack for every iteration and can overflow on long inputs. A
+// procedure calling itself more than once (like a tree walk, or fib) recurses as deep as its data is, which is
+// usually fine, so only procedures that call themselves exactly once, outside tail position, are reported.
+//
+// The variables bound by let, let*, letrec, letrec*, named lets, lambdas and procedure definitions are checked too:
+// one that nothing in its scope refers to is probably a typo or left over, and one named after a builtin hides it.
+// The interpreters report these through the warning handler as they read code. A variable whose name starts with _
+// is meant to be unused, and any mention of a name in its scope counts as a use, even one a nested binding hides.
+
+use docs;
+use parser::Node;
+use printer::{self, Printable, PrinterConfig, Shape, Style};
+
+use std::collections::HashSet;
+use std::fmt;
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum LintKind {
+    // A procedure calls itself once, outside tail position
+    NonTailRecursion,
+    // A local variable nothing refers to
+    UnusedVariable,
+    // A local variable with the same name as a builtin
+    ShadowedBuiltin,
+}
+
+#[derive(Debug)]
+pub struct Lint {
+    kind: LintKind,
+    message: String,
+}
+
+impl Lint {
+    pub fn kind(&self) -> LintKind {
+        self.kind
+    }
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+pub fn lint(nodes: &[Node]) -> Vec<Lint> {
+    let mut lints = Vec::new();
+    for node in nodes.iter() {
+        find_procedures(node, &mut lints);
+    }
+    lints.extend(lint_variables(nodes));
+    lints
+}
+
+// Just the checks on local variables: unused ones and ones shadowing builtins
+pub fn lint_variables(nodes: &[Node]) -> Vec<Lint> {
+    let builtins: HashSet<String> = docs::all().into_iter().map(|d| d.name).collect();
+    let mut lints = Vec::new();
+    for node in nodes.iter() {
+        find_scopes(node, &builtins, &mut lints);
+    }
+    lints
+}
+
+// Check the variables of every scope in node, however deeply nested
+fn find_scopes(node: &Node, builtins: &HashSet<String>, lints: &mut Vec<Lint>) {
+    let mut pending = vec![node];
+    while let Some(node) = pending.pop() {
+        let items = match *node {
+            Node::List(ref items) => items,
+            _ => continue
+        };
+        if items.first() == Some(&Node::Identifier("quote".to_string())) {
+            continue
+        }
+        if let Some((names, code)) = scope(items) {
+            check_variables(node, &names, &code, builtins, lints);
+        }
+        pending.extend(items.iter().rev());
+    }
+}
+
+// The variables a binding form binds, and the code that can refer to them
+fn scope(items: &[Node]) -> Option<(Vec<&str>, Vec<&Node>)> {
+    let head = match items.first() {
+        Some(Node::Identifier(head)) => head.as_str(),
+        _ => return None
+    };
+    match (head, items.get(1)) {
+        ("lambda", Some(params)) | ("λ", Some(params)) => Some((identifiers(params), items[2..].iter().collect())),
+        ("define", Some(Node::List(signature))) if !signature.is_empty() => {
+            Some((signature[1..].iter().filter_map(identifier).collect(), items[2..].iter().collect()))
+        },
+        ("let", Some(&Node::Identifier(_))) => match items.get(2) {
+            // a named let: the loop's name is used by calling it, which isn't worth a warning if it isn't
+            Some(Node::List(bindings)) => Some((binding_names(bindings), items[3..].iter().collect())),
+            _ => None
+        },
+        ("let", Some(Node::List(bindings))) => Some((binding_names(bindings), items[2..].iter().collect())),
+        ("let*", Some(&Node::List(ref bindings))) | ("letrec", Some(&Node::List(ref bindings)))
+            | ("letrec*", Some(&Node::List(ref bindings))) => {
+            // the values can refer to the variables too (in let*, to the ones before them)
+            let values = bindings.iter().filter_map(|b| match *b {
+                Node::List(ref pair) if pair.len() == 2 => Some(&pair[1]),
+                _ => None
+            });
+            Some((binding_names(bindings), values.chain(items[2..].iter()).collect()))
+        },
+        _ => None
+    }
+}
+
+fn identifier(node: &Node) -> Option<&str> {
+    match *node {
+        Node::Identifier(ref name) => Some(name),
+        _ => None
+    }
+}
+
+// A lambda's parameters, which are a list of names or one name for all the arguments
+fn identifiers(params: &Node) -> Vec<&str> {
+    match *params {
+        Node::List(ref names) => names.iter().filter_map(identifier).collect(),
+        ref name => identifier(name).into_iter().collect()
+    }
+}
+
+fn binding_names(bindings: &[Node]) -> Vec<&str> {
+    bindings.iter().filter_map(|b| match *b {
+        Node::List(ref pair) if !pair.is_empty() => identifier(&pair[0]),
+        _ => None
+    }).collect()
+}
+
+fn check_variables(form: &Node, names: &[&str], code: &[&Node], builtins: &HashSet<String>, lints: &mut Vec<Lint>) {
+    for name in names.iter() {
+        if builtins.contains(*name) {
+            lints.push(Lint {
+                kind: LintKind::ShadowedBuiltin,
+                message: message!("Local variable {} shadows a builtin procedure", name)
+            });
+        }
+        if !name.starts_with('_') && !code.iter().any(|n| mentions(n, name)) {
+            let config = PrinterConfig { width: Some(60), ..PrinterConfig::default() };
+            lints.push(Lint {
+                kind: LintKind::UnusedVariable,
+                message: message!("Local variable {} is never used: {}", name, printer::print_with(form, Style::Write, config))
+            });
+        }
+    }
+}
+
+// Whether name appears in node as code, i.e. not in quoted data
+fn mentions(node: &Node, name: &str) -> bool {
+    let mut pending = vec![node];
+    while let Some(node) = pending.pop() {
+        match *node {
+            Node::Identifier(ref s) if s == name => return true,
+            Node::List(ref items) if items.first() != Some(&Node::Identifier("quote".to_string())) => pending.extend(items.iter()),
+            _ => ()
+        }
+    }
+    false
+}
+
+// Lint every procedure defined with a name in node, however deeply nested
+fn find_procedures(node: &Node, lints: &mut Vec<Lint>) {
+    let mut pending = vec![node];
+    while let Some(node) = pending.pop() {
+        let items = match *node {
+            Node::List(ref items) => items,
+            _ => continue
+        };
+        if let Some((name, params, body)) = procedure_definition(items) {
+            check_recursion(name, params, body, lints);
+        }
+        pending.extend(items.iter().rev());
+    }
+}
+
+// The name, parameters and body of (define (name param...) body...) or (define name (lambda (param...) body...))
+fn procedure_definition(items: &[Node]) -> Option<(&str, &[Node], &[Node])> {
+    if items.len() < 3 || items[0] != Node::Identifier("define".to_string()) {
+        return None
+    }
+    match items[1] {
+        Node::List(ref signature) => match signature.split_first() {
+            Some((Node::Identifier(name), params)) => Some((name, params, &items[2..])),
+            _ => None
+        },
+        Node::Identifier(ref name) => match items[2] {
+            Node::List(ref lambda) if lambda.len() >= 3 && is_lambda(&lambda[0]) => match lambda[1] {
+                Node::List(ref params) => Some((name, params, &lambda[2..])),
+                _ => None
+            },
+            _ => None
+        },
+        _ => None
+    }
+}
+
+fn is_lambda(node: &Node) -> bool {
+    match *node {
+        Node::Identifier(ref s) => s == "lambda" || s == "λ",
+        _ => false
+    }
+}
+
+fn check_recursion(name: &str, params: &[Node], body: &[Node], lints: &mut Vec<Lint>) {
+    // a parameter with the procedure's name hides it
+    if params.iter().any(|p| *p == Node::Identifier(name.to_string())) {
+        return
+    }
+    let mut calls = Vec::new();
+    find_self_calls_in_body(name, body, true, &mut calls);
+    if calls.len() == 1 && !calls[0].1 {
+        let config = PrinterConfig { width: Some(60), ..PrinterConfig::default() };
+        let call = printer::print_with(calls[0].0, Style::Write, config);
+        lints.push(Lint {
+            kind: LintKind::NonTailRecursion,
+            message: message!("{} calls itself outside tail position, so every level of recursion takes stack: {}", name, call)
+        });
+    }
+}
+
+// The last form of a body is in tail position if the body is
+fn find_self_calls_in_body<'a>(name: &str, body: &'a [Node], tail: bool, calls: &mut Vec<(&'a Node, bool)>) {
+    for (i, node) in body.iter().enumerate() {
+        find_self_calls(name, node, tail && i == body.len() - 1, calls);
+    }
+}
+
+// Collect the calls to name in node, each with whether it's in tail position. Nested procedures are left alone:
+// calling name from one isn't the loop this one makes.
+fn find_self_calls<'a>(name: &str, node: &'a Node, tail: bool, calls: &mut Vec<(&'a Node, bool)>) {
+    let items = match *node {
+        Node::List(ref items) => items,
+        _ => return
+    };
+    let head = match items.first() {
+        Some(Node::Identifier(head)) => head.as_str(),
+        _ => return find_self_calls_in_body(name, items, false, calls)
+    };
+    match head {
+        "quote" | "quasiquote" | "lambda" | "λ" | "define-syntax-rule" => (),
+        "define" if items.len() > 1 => match items[1] {
+            Node::List(_) => (),
+            _ => find_self_calls_in_body(name, &items[2..], false, calls)
+        },
+        "if" if items.len() > 1 => {
+            find_self_calls(name, &items[1], false, calls);
+            for branch in items[2..].iter() {
+                find_self_calls(name, branch, tail, calls);
+            }
+        },
+        "begin" | "and" | "or" => find_self_calls_in_body(name, &items[1..], tail, calls),
+        "let" if items.len() > 1 => {
+            let mut shadowed = false;
+            if let Node::List(ref bindings) = items[1] { for binding in bindings.iter() {
+                match *binding {
+                    Node::List(ref pair) if pair.len() == 2 => {
+                        find_self_calls(name, &pair[1], false, calls);
+                        shadowed = shadowed || pair[0] == Node::Identifier(name.to_string());
+                    },
+                    _ => ()
+                }
+            } }
+            if !shadowed {
+                find_self_calls_in_body(name, &items[2..], tail, calls);
+            }
+        },
+        _ => {
+            if head == name {
+                calls.push((node, tail));
+            }
+            find_self_calls_in_body(name, &items[1..], false, calls);
+        }
+    }
+}
+
+// For quoting code in messages
+impl Printable for Node {
+    fn shape(&self, style: Style) -> Shape<Node> {
+        match *self {
+            Node::Identifier(ref s) => Shape::Atom(printer::symbol(s)),
+            Node::Integer(i) => Shape::Atom(i.to_string()),
+            Node::Rational(n, d) => Shape::Atom(format!("{}/{}", n, d)),
+            Node::Float(f) => Shape::Atom(printer::float(f)),
+            Node::Boolean(b) => Shape::Atom(if b { "#t" } else { "#f" }.to_string()),
+            Node::Character(c) => Shape::Atom(printer::character(c)),
+            Node::String(ref s) => Shape::Atom(format!("\"{}\"", s)),
+            Node::List(ref items) => Shape::List(None, items.clone()),
+            Node::DottedList(ref items, ref tail) => Shape::DottedList(None, items.clone(), (**tail).clone()),
+            Node::Vector(ref items) => Shape::Vector(None, items.clone()),
+            Node::Bytevector(ref bytes) => Shape::Atom(printer::bytevector(bytes)),
+            Node::EofObject => Shape::Atom("#<eof>".to_string()),
+            Node::Keyword(ref k) => Shape::Atom(format!("#:{}", k)),
+            Node::Box(_, ref contents) => Shape::Box(&**contents as *const Node as usize, (**contents).clone()),
+            Node::Labelled(n, ref inner) => Shape::Atom(format!("#{}={}", n, printer::print(&**inner, style))),
+            Node::Reference(n) => Shape::Atom(format!("#{}#", n)),
+        }
+    }
+}
+
+#[test]
+fn test_lint_non_tail_recursion() {
+    use parser::parse_str;
+
+    let lints = |s: &str| -> Vec<String> {
+        lint(&parse_str(s).unwrap()).iter().map(|l| l.to_string()).collect()
+    };
+    assert_eq!(lints("(define (len l) (if (null? l) 0 (+ 1 (len (cdr l)))))"),
+               vec!["len calls itself outside tail position, so every level of recursion takes stack: (len (cdr l))"]);
+    assert_eq!(lints("(define count (lambda (n) (begin (count (- n 1)) n)))").len(), 1);
+    // tail calls, including through if, begin, let, and and or
+    assert!(lints("(define (loop n acc) (if (= n 0) acc (loop (- n 1) (+ acc n))))").is_empty());
+    assert!(lints("(define (f n) (begin (display n) (let ((m (- n 1))) (and (> m 0) (f m)))))").is_empty());
+    // tree recursion goes as deep as the data, not as long as it
+    assert!(lints("(define (fib n) (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2)))))").is_empty());
+    // calls from nested procedures, or to something else with the same name, aren't loops
+    assert!(lints("(define (f l) (map (lambda (x) (f x)) l))").is_empty());
+    assert!(lints("(define (f f) (+ 1 (f 2)))").is_empty());
+    assert!(lints("(define (f n) (let ((f car)) (+ 1 (f n))))").is_empty());
+    // nested definitions are checked too
+    assert_eq!(lints("(define (outer l) (define (inner l) (cons 1 (inner l))) (inner l))").len(), 1);
+}
+
+#[test]
+fn test_lint_variables() {
+    use parser::parse_str;
+
+    let lints = |s: &str| -> Vec<(LintKind, String)> {
+        lint(&parse_str(s).unwrap()).iter().map(|l| (l.kind(), l.to_string())).collect()
+    };
+    assert_eq!(lints("(let ((x 1) (y 2)) (+ y 1))"),
+               vec![(LintKind::UnusedVariable, "Local variable x is never used: (let ((x 1) (y 2)) (+ y 1))".to_string())]);
+    assert_eq!(lints("(define (f a b) a) (map (lambda (x) 1) '(1))"),
+               vec![(LintKind::UnusedVariable, "Local variable b is never used: (define (f a b) a)".to_string()),
+                    (LintKind::UnusedVariable, "Local variable x is never used: (lambda (x) 1)".to_string())]);
+    assert_eq!(lints("(define (f car) (car 1))"),
+               vec![(LintKind::ShadowedBuiltin, "Local variable car shadows a builtin procedure".to_string())]);
+    // quoted names aren't uses
+    assert_eq!(lints("(lambda args 'args)").len(), 1);
+    // uses in later bindings count for let* and letrec, and unused variables starting with _ are fine
+    assert!(lints("(let* ((a 1) (b a)) b) (letrec ((ev? (lambda (n) (od? n))) (od? (lambda (n) (ev? n)))) 1)").is_empty());
+    assert!(lints("(lambda (_ignored x) x) (let loop ((i 0)) (loop i))").is_empty());
+    assert_eq!(lints("(let loop ((i 0)) 1)").len(), 1);
+    // any mention counts, even one a nested binding hides
+    assert!(lints("(let ((x 1)) (let ((x 2)) x))").is_empty());
+}
@@ -0,0 +1,95 @@
+// Logging from scripts, with log-debug, log-info, log-warn and log-error. Messages go to a handler, which an embedder
+// can set to pass them on to its own logging; until one does, they're written to stderr. Like the warning handler,
+// it's per thread.
+
+use printer::{self, Printable, Style};
+
+use std::cell::RefCell;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Level {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl Level {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Level::Debug => "debug",
+            Level::Info => "info",
+            Level::Warn => "warn",
+            Level::Error => "error",
+        }
+    }
+}
+
+pub type Handler = Box<dyn Fn(Level, &str)>;
+
+thread_local!(static HANDLER: RefCell<Option<Handler>> = RefCell::new(None));
+
+pub fn set_handler(handler: Handler) {
+    HANDLER.with(|h| *h.borrow_mut() = Some(handler));
+}
+
+pub fn log(level: Level, message: &str) {
+    HANDLER.with(|h| {
+        match *h.borrow() {
+            Some(ref handler) => handler(level, message),
+            None => eprintln!("{}: {}", level.name(), message)
+        }
+    })
+}
+
+// Fill in a log message's template: ~a is the next argument as display shows it, ~s as write does, and ~~ is a ~
+pub fn format<T: Printable>(template: &str, args: &[T]) -> Result<String, String> {
+    let mut message = String::new();
+    let mut args = args.iter();
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        if c != '~' {
+            message.push(c);
+            continue
+        }
+        let style = match chars.next() {
+            Some('a') => Style::Display,
+            Some('s') => Style::Write,
+            Some('~') => {
+                message.push('~');
+                continue
+            },
+            Some(d) => return Err(message!("Unknown directive ~{} in log message: {}", d, template)),
+            None => return Err(message!("Log message ends in ~: {}", template))
+        };
+        match args.next() {
+            Some(arg) => message.push_str(&printer::print(arg, style)),
+            None => return Err(message!("Not enough arguments for log message: {}", template))
+        }
+    }
+    if args.next().is_some() {
+        return Err(message!("Too many arguments for log message: {}", template))
+    }
+    Ok(message)
+}
+
+#[test]
+fn test_log() {
+    use parser::Node;
+    use std::rc::Rc;
+
+    let args = [Node::String("x".to_string()), Node::Integer(2)];
+    assert_eq!(format("~a is ~s, ~s~~", &[Node::Identifier("x".to_string()), Node::String("x".to_string()), Node::Integer(2)]),
+               Ok("x is \"x\", 2~".to_string()));
+    assert_eq!(format("~a", &args), Err("Too many arguments for log message: ~a".to_string()));
+    assert_eq!(format("~a ~a ~a", &args), Err("Not enough arguments for log message: ~a ~a ~a".to_string()));
+    assert_eq!(format("~d", &args), Err("Unknown directive ~d in log message: ~d".to_string()));
+
+    let seen = Rc::new(RefCell::new(Vec::new()));
+    let sink = seen.clone();
+    set_handler(Box::new(move |level, m| sink.borrow_mut().push(format!("{:?} {}", level, m))));
+    log(Level::Info, "started");
+    log(Level::Error, "failed");
+    assert_eq!(*seen.borrow(), vec!["Info started", "Error failed"]);
+    assert!(Level::Debug < Level::Warn);
+}
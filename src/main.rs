@@ -1,4 +1,5 @@
 extern crate getopts;
+extern crate rusty_scheme;
 
 #[cfg(not(test))]
 use getopts::Options;
@@ -6,22 +7,65 @@ use getopts::Options;
 #[cfg(not(test))]
 use std::env;
 
-mod lexer;
-mod parser;
-mod interpreter;
-mod ast_walk_interpreter;
-mod cps_interpreter;
+#[cfg(not(test))]
+use std::fs;
+
+#[cfg(not(test))]
+use std::io::{self, Read};
 
 #[cfg(not(test))]
-mod repl;
+use std::process;
+
+#[cfg(not(test))]
+use std::path::{Path, PathBuf};
+
+use rusty_scheme::interpreter;
+
+#[cfg(not(test))]
+use rusty_scheme::interpreter::ColorMode;
+
+#[cfg(not(test))]
+use rusty_scheme::lexer::{ReaderConfig, ReaderMode};
+
+#[cfg(not(test))]
+use rusty_scheme::{bundle, docs, lint, parser, watch};
+
+#[cfg(not(test))]
+use std::time::Duration;
 
 #[cfg(not(test))]
 fn main() {
+    // a bundled program runs its script, and leaves the command line to it
+    match env::current_exe().map_err(|e| e.to_string()).and_then(|exe| bundle::embedded_script(&exe)) {
+        Ok(Some(script)) => return run_bundled(&script),
+        Ok(None) => {},
+        Err(e) => { println!("{}", e); return }
+    }
+
     // parse command-line arguments & options
     let args: Vec<String> = env::args().collect();
     let program = &args[0];
     let mut opts = Options::new();
     opts.optopt("t", "type", "set interpreter type", "ast_walk/cps");
+    opts.optopt("", "image", "resume from a heap image instead of a fresh environment", "FILE");
+    opts.optopt("", "dump-image", "write a heap image after running", "FILE");
+    opts.optopt("", "reader", "set reader mode", "strict/lenient");
+    opts.optflag("", "nfc", "normalize string literals to NFC");
+    opts.optflag("", "infix", "read #i(...) as infix arithmetic, e.g. #i(1 + 2 * 3)");
+    opts.optflag("", "lint", "check the file for likely mistakes instead of running it");
+    opts.optopt("o", "output", "where `bundle FILE` writes the executable", "FILE");
+    opts.optflag("", "allow-net", "let the script use the network, e.g. with http-get");
+    opts.optflag("", "allow-ffi", "let the script call C functions with foreign-procedure");
+    opts.optflag("", "fold-constants", "evaluate calls to pure builtins with literal arguments before running");
+    opts.optflag("", "strict-booleans", "make if, and and or raise an error for a condition that isn't #t or #f");
+    opts.optflag("", "no-init", "don't load ~/.rusty_scheme.scm before starting the REPL");
+    opts.optflag("q", "quiet", "don't print the REPL's welcome banner");
+    opts.optflag("", "batch", "run the file, or standard input, without a REPL, printing only what the script prints and errors");
+    opts.optopt("", "prompt", "set the REPL prompt", "TEXT");
+    opts.optopt("", "color", "color REPL results and errors (auto: only on a terminal)", "auto/always/never");
+    opts.optopt("", "result-color", "color for REPL results", "COLOR");
+    opts.optopt("", "error-color", "color for REPL errors", "COLOR");
+    opts.optopt("", "seed", "seed random, gensym and hash order, for reproducible runs", "N");
     opts.optflag("h", "help", "print this help menu");
     let matches = match opts.parse(&args[1..]) {
         Ok(m) => { m }
@@ -33,22 +77,224 @@ fn main() {
         return;
     }
 
-    let interpreter = match matches.opt_str("t") {
-        Some(t) => interpreter::new(&t),
-        None => interpreter::new("cps")
+    let mut builder = interpreter::Builder::new();
+    if let Some(t) = matches.opt_str("t") {
+        builder = builder.interpreter_type(&t);
+    }
+    if let Some(image) = matches.opt_str("image") {
+        builder = builder.image(Path::new(&image));
+    }
+    if let Some(dirs) = env::var_os("RUSTY_SCHEME_PATH") {
+        builder = builder.search_path(env::split_paths(&dirs).collect());
+    }
+    if let Some(seed) = matches.opt_str("seed") {
+        match seed.parse() {
+            Ok(n) => builder = builder.seed(n),
+            Err(_) => { println!("Seed must be a non-negative integer"); return }
+        }
+    }
+    let mut reader = ReaderConfig::default();
+    if let Some(mode) = matches.opt_str("reader") {
+        match ReaderMode::from_name(&mode) {
+            Some(m) => reader.mode = m,
+            None => { println!("Reader mode must be 'strict' or 'lenient'"); return }
+        }
+    }
+    reader.normalize_strings = matches.opt_present("nfc");
+    reader.infix = matches.opt_present("infix");
+    if matches.opt_present("lint") {
+        match matches.free.first() {
+            Some(file) => lint_file(file, &reader),
+            None => println!("--lint needs a file to check")
+        }
+        return
+    }
+    if matches.free.first().is_some_and(|command| command == "bundle") {
+        match (matches.free.get(1), matches.opt_str("o")) {
+            (Some(script), Some(out)) => bundle_file(script, &out, &reader),
+            _ => println!("Usage: {} bundle FILE -o OUTPUT", program)
+        }
+        return
+    }
+    if matches.free.first().is_some_and(|command| command == "doc") {
+        print_docs(&matches.free[1..]);
+        return
+    }
+    builder = builder.reader(reader).allow_network(matches.opt_present("allow-net"))
+                     .allow_foreign(matches.opt_present("allow-ffi"))
+                     .fold_constants(matches.opt_present("fold-constants"))
+                     .strict_booleans(matches.opt_present("strict-booleans"));
+    if matches.free.first().is_some_and(|command| command == "watch") {
+        match matches.free.get(1) {
+            Some(script) => watch_file(script, &builder),
+            None => println!("Usage: {} watch FILE", program)
+        }
+        return
+    }
+    let interpreter = match builder.build() {
+        Ok(i) => i,
+        Err(e) => { println!("{}", e); return }
+    };
+
+    interpreter.set_warning_handler(|message| eprintln!("Warning: {}", message));
+
+    if let Some(prompt) = matches.opt_str("prompt") {
+        interpreter.set_repl_prompt(&prompt);
+    }
+    match matches.opt_str("color") {
+        Some(when) => {
+            match ColorMode::from_name(&when) {
+                Some(colors) => interpreter.set_repl_colors(colors),
+                None => { println!("Color must be 'auto', 'always' or 'never'"); return }
+            }
+        },
+        None => interpreter.set_repl_colors(ColorMode::Auto)
+    }
+    if let Some(color) = matches.opt_str("result-color") { if let Err(e) = interpreter.set_repl_result_color(Some(&color)) { println!("{}", e); return } }
+    if let Some(color) = matches.opt_str("error-color") { if let Err(e) = interpreter.set_repl_error_color(Some(&color)) { println!("{}", e); return } }
+
+    let rest = &matches.free;
+    if rest.len() > 1 {
+        panic!("You must provide 0 or 1 arguments to RustyScheme: {:?}", rest)
+    }
+    interpreter.set_repl_banner(!matches.opt_present("quiet"));
+    let mut failed = false;
+    if matches.opt_present("batch") {
+        // errors go to stderr and the exit status, so only the script's own output reaches stdout
+        match read_script(rest.first()).and_then(|source| interpreter.run_script(&source, rest.first().map(Path::new))) {
+            Ok(_) => {},
+            Err(e) => { eprintln!("{}", e); failed = true }
+        }
+    } else {
+        match rest.len() {
+            0 => {
+                if !matches.opt_present("no-init") {
+                    if let Some(home) = env::var_os("HOME") {
+                        match interpreter.load_init_file(&Path::new(&home).join(".rusty_scheme.scm")) {
+                            Ok(_) => {},
+                            Err(e) => println!("{}", e),
+                        }
+                    }
+                }
+                interpreter.start_repl(history_file().as_deref())
+            },
+            _ => interpreter.run_file(&rest[0])
+        }
+    }
+
+    if let Some(image) = matches.opt_str("dump-image") {
+        match interpreter.dump_image(Path::new(&image)) {
+            Ok(_) => {},
+            Err(e) => println!("{}", e),
+        }
+    }
+    if failed {
+        process::exit(1)
+    }
+}
+
+// The script --batch runs: the file's contents, or all of standard input if there's no file
+#[cfg(not(test))]
+fn read_script(file: Option<&String>) -> Result<String, String> {
+    let mut source = String::new();
+    let read = match file {
+        Some(file) => fs::File::open(file).and_then(|mut f| f.read_to_string(&mut source))
+                                          .map_err(|e| format!("Couldn't read {}: {}", file, e)),
+        None => io::stdin().read_to_string(&mut source).map_err(|e| format!("Couldn't read standard input: {}", e))
     };
+    read.map(|_| source)
+}
 
-    let rest = matches.free;
-    match rest.len() {
-        0 => interpreter.start_repl(),
-        1 => interpreter.run_file(&rest[0]),
-        _ => panic!("You must provide 0 or 1 arguments to RustyScheme: {:?}", rest)
+// Run the script, then run it again in a fresh interpreter whenever it or a file it loaded changes, until the
+// process is stopped
+#[cfg(not(test))]
+fn watch_file(script: &String, builder: &interpreter::Builder) {
+    let path = Path::new(script);
+    loop {
+        // forget files loaded before this run
+        watch::take();
+        match builder.clone().build() {
+            Ok(interpreter) => {
+                interpreter.set_warning_handler(|message| eprintln!("Warning: {}", message));
+                match read_script(Some(script)).and_then(|source| interpreter.run_script(&source, Some(path))) {
+                    Ok(_) => {},
+                    Err(e) => println!("{}", e)
+                }
+            },
+            Err(e) => println!("{}", e)
+        }
+        let mut files = vec![path.to_path_buf()];
+        files.extend(watch::take());
+        let watcher = watch::Watcher::new(files);
+        println!("\n{} changed, running {} again", watcher.wait(Duration::from_millis(250)).display(), script);
+    }
+}
+
+// Print what the linter finds in a file, as warnings like those printed while running
+#[cfg(not(test))]
+fn lint_file(file: &str, reader: &ReaderConfig) {
+    let source = match fs::read_to_string(file) {
+        Ok(source) => source,
+        Err(e) => { println!("Couldn't read {}: {}", file, e); return }
+    };
+    match parser::parse_str_with(&source, reader) {
+        Ok(nodes) => for l in lint::lint(&nodes) {
+            eprintln!("Warning: {}", l);
+        },
+        Err(e) => println!("{}", e)
+    }
+}
+
+// Make a standalone executable running the script, out of this one
+#[cfg(not(test))]
+fn bundle_file(script: &str, out: &str, reader: &ReaderConfig) {
+    let exe = match env::current_exe() {
+        Ok(exe) => exe,
+        Err(e) => { println!("Couldn't find the rusty_scheme executable: {}", e); return }
+    };
+    match bundle::bundle(&exe, Path::new(script), reader, Path::new(out)) {
+        Ok(_) => {},
+        Err(e) => println!("{}", e)
+    }
+}
+
+// Print the documentation for the named builtins, or for all of them
+#[cfg(not(test))]
+fn print_docs(names: &[String]) {
+    let rendered: Vec<String> = if names.is_empty() {
+        docs::all().iter().map(|d| d.render()).collect()
+    } else {
+        names.iter().map(|name| match docs::lookup(name) {
+            Some(d) => d.render(),
+            None => format!("No documentation for {}", name)
+        }).collect()
+    };
+    println!("{}", rendered.join("\n\n"));
+}
+
+#[cfg(not(test))]
+fn run_bundled(script: &str) {
+    let interpreter = interpreter::new("cps");
+    interpreter.set_warning_handler(|message| eprintln!("Warning: {}", message));
+    match interpreter.execute(script) {
+        Ok(_) => {},
+        Err(e) => println!("{}", e)
+    }
+}
+
+// RUSTY_SCHEME_HISTORY if it's set, otherwise ~/.rusty_scheme_history
+#[cfg(not(test))]
+fn history_file() -> Option<PathBuf> {
+    match env::var_os("RUSTY_SCHEME_HISTORY") {
+        Some(path) => Some(PathBuf::from(path)),
+        None => env::var_os("HOME").map(|home| Path::new(&home).join(".rusty_scheme_history"))
     }
 }
 
 #[cfg(not(test))]
 fn print_usage(program: &str, opts: Options) {
-    let brief = format!("Usage: {} [options]", program);
+    let brief = format!("Usage: {} [options]\n       {} bundle FILE -o OUTPUT\n       {} doc [NAME...]\n       {} watch FILE",
+                        program, program, program, program);
     print!("{}", opts.usage(&brief));
 }
 
@@ -62,6 +308,14 @@ macro_rules! test_fail {
     ($name:ident, $src:expr, $res:expr, cps) => (#[test] fn $name() { assert_execute_fail_cps!($src, $res); });
 }
 
+// Runs a test body once against each interpreter
+#[cfg(test)]
+fn each_interpreter<F: Fn(&str)>(f: F) {
+    for t in ["cps", "ast_walk"].iter() {
+        f(t);
+    }
+}
+
 macro_rules! assert_execute_all {
     ($src:expr, $res:expr) => (
         assert_execute_ast_walk!($src, $res);
@@ -110,9 +364,41 @@ test!(multiplication2, "(* 2 -3)", "-6");
 test!(multiplication3, "(* 2 3 4 5)", "120");
 
 test!(division1, "(/ 4 2)", "2");
-test!(division2, "(/ 4 3)", "1");
+test!(division2, "(list (/ 4 3) (/ 1 3) (/ -6 4) (/ 6 -3))", "(4/3 1/3 -3/2 -2)");
 test!(division3, "(/ 4 -2)", "-2");
 
+test!(floats1, "(+ 1.5 2.25)", "3.75");
+test!(floats2, "(list (+ 1 0.5) (- 1 0.25) (* 2 1.5) (/ 7 2.0) (/ 7 2))", "(1.5 0.75 3.0 3.5 7/2)");
+test!(floats3, "(list (< 1 1.5) (> 2.5 2) (= 1 1.0) (= +nan.0 +nan.0))", "(#t #t #t #f)");
+test!(floats4, "(list 1e21 1.5e-3 -0.0 (/ 1 0.0) (/ -1 0.0) (/ 0 0.0))", "(1e21 0.0015 -0.0 +inf.0 -inf.0 +nan.0)");
+#[cfg(feature = "math")]
+test!(floats5, "(list (number->string 2.5) (= (hash 1.5) (hash 1.5)) (= (hash 1.5) (hash 1)))", "(\"2.5\" #t #f)");
+test_fail!(floats6, "(+ 1.5 \"2\")", "RuntimeError: Expected a number: \"2\"");
+#[cfg(feature = "math")]
+test_fail!(floats7, "(number->string 2.5 16)", "RuntimeError: number->string only takes a radix and width for integers, not 2.5");
+test_fail!(division4, "(/ 1 0)", "RuntimeError: Division by zero in /: [1, 0]");
+test!(overflow1, "(list (+ 9223372036854775807 1) (- -9223372036854775808 1) (* 4611686018427387904 2) (+ 9223372036854775806 1))",
+      "(9.223372036854776e18 -9.223372036854776e18 9.223372036854776e18 9223372036854775807)");
+test!(floats8, "(list .5 -.25 (+ .5 1))", "(0.5 -0.25 1.5)");
+test!(characters1, "(list #\\a #\\space #\\x41 (char->integer #\\λ) (integer->char 97) (char? #\\a) (char? \"a\"))", "(#\\a #\\space #\\A 955 #\\a #t #f)");
+test!(characters2, "(display->string #\\a #\\b \"c\")", "\"abc\"");
+test!(characters3, "(list (= (hash #\\a) (hash #\\a)) (= (hash #\\a) (hash \"a\")))", "(#t #f)");
+test_fail!(characters4, "(integer->char 55296)", "RuntimeError: Not a Unicode code point: 55296");
+test_fail!(characters5, "(char->integer \"a\")", "RuntimeError: Expected a character value: \"a\"");
+test!(characters6, "(list (char-alphabetic? #\\a) (char-alphabetic? #\\λ) (char-alphabetic? #\\7) (char-numeric? #\\7) (char-numeric? #\\a) (char-whitespace? #\\space) (char-whitespace? #\\tab) (char-whitespace? #\\-))",
+      "(#t #t #f #t #f #t #t #f)");
+test!(characters7, "(list (char-upcase #\\a) (char-upcase #\\λ) (char-upcase #\\ß) (char-upcase #\\1) (char-downcase #\\A))", "(#\\A #\\Λ #\\ß #\\1 #\\a)");
+test_fail!(characters8, "(char-upcase \"a\")", "RuntimeError: Expected a character value: \"a\"");
+test!(radixes1, "(list #xff #o17 #b-101 (+ #x10 1))", "(255 15 -5 17)");
+test!(exactness1, "(list #e1.5 #e0.1 #e2.0 #i3 #i1/4 #e#x10 (+ #e0.1 #e0.2))", "(3/2 1/10 2 3.0 0.25 16 3/10)");
+test_fail!(exactness2, "#e1.5x", "SyntaxError: Not a number: #e1.5x (line: 1, column: 7)");
+test!(rationals1, "(list 1/3 6/4 -4/2 (+ 1/3 1/6) (- 1 1/3) (+ 1/3 1/3 1/3))", "(1/3 3/2 -2 1/2 2/3 1)");
+test!(rationals2, "(list (* 2/3 3/4) (/ 1/2 1/4) (/ 1 1/3) (/ 1/3 2) (+ 1/2 0.25))", "(1/2 2 3 1/6 0.75)");
+#[cfg(feature = "math")]
+test!(rationals3, "(list (< 1/3 1/2) (= 1/2 0.5) (> 2/3 1) (number->string -3/9) (= (hash 2/4) (hash 1/2)))", "(#t #t #f \"-1/3\" #t)");
+test_fail!(rationals4, "(/ 1/2 0)", "RuntimeError: Division by zero in /: [1/2, 0]");
+test_fail!(rationals5, "1/0", "ParseError: Division by zero in 1/0");
+
 test!(lessthan1, "(< 1 2)", "#t");
 test!(lessthan2, "(< 2 2)", "#f");
 test!(lessthan3, "(< 3 2)", "#f");
@@ -167,6 +453,12 @@ test!(append2, "(append '(1) '())", "(1)");
 test!(append3, "(append '() '(2))", "(2)");
 test!(append4, "(append '() '())", "()");
 test!(append5, "(append '(1) '((2)))", "(1 (2))");
+test!(append6, "(list (append) (append '(1)) (append '(1) '(2 3) '() '(4)))", "(() (1) (1 2 3 4))");
+test!(append7, "(define a '(1 2)) (define b (append a '(3))) (list a b)", "((1 2) (1 2 3))");
+test_fail!(append8, "(append '(1) 2)", "RuntimeError: Arguments to append must be lists: 2");
+test!(reverse1, "(list (reverse '()) (reverse '(1 (2 3) 4)))", "(() (4 (2 3) 1))");
+test!(reverse2, "(define (build n acc) (if (= n 0) (reverse acc) (build (- n 1) (cons n acc)))) (build 3 '())", "(3 2 1)");
+test_fail!(reverse3, "(reverse! '(1 2 3))", "RuntimeError: Identifier not found: reverse! (did you mean reverse?)");
 
 test!(variable_definition1, "(define x 2) (+ x x x)", "6");
 test!(variable_definition2, "(define x 2) ((lambda (x) x) 3)", "3");
@@ -191,6 +483,8 @@ test!(procedure_definition4, "((λ (x) (+ x x)) 8)", "16");
 test!(procedure_definition5, "(define foo (λ (x) (λ (y) (+ x y)))) (define add2 (foo 2)) (add2 5)", "7");
 test!(procedure_definition6, "(define foo (λ (x) (λ (y) (+ x y)))) (define add2 (foo 2)) ((λ (x) (add2 (+ x 1))) 1)", "4");
 test!(procedure_definition7, "(define (twice f v) (f (f v))) (twice (lambda (x) (+ x x)) 8)", "32");
+test!(procedure_definition8, "(define (f a b c d e g) (define h 7) (list a b c d e g h)) (f 1 (+ 1 1) 3 4 5 6)", "(1 2 3 4 5 6 7)");
+test_fail!(procedure_definition9, "(define (f a b) a) (f 1)", "RuntimeError: Must supply exactly 2 arguments to function: (1)", cps);
 
 test!(begin_statement1, "(define x 1) (begin (set! x 5) (set! x (+ x 2)) x)", "7");
 
@@ -198,6 +492,13 @@ test!(let_statement1, "(let ((x 2)) (+ x x))", "4");
 test!(let_statement2, "(let ((x 2) (y 3)) (+ x y))", "5");
 test!(let_statement3, "(let ((x 2) (y 3)) (set! y (+ y 1)) (+ x y))", "6");
 
+test!(letrec1, "(letrec ((even? (lambda (n) (if (= n 0) #t (odd? (- n 1))))) (odd? (lambda (n) (if (= n 0) #f (even? (- n 1)))))) (even? 10))", "#t");
+test!(letrec2, "(letrec* ((a 1) (b (+ a 1))) (list a b))", "(1 2)");
+test!(letrec3, "(define b 5) (letrec ((f (lambda () b)) (b 1)) (f))", "1");
+test_fail!(letrec4, "(define b 5) (letrec ((a b) (b 1)) a)", "RuntimeError: b was used before its letrec initialization finished");
+test_fail!(letrec5, "(letrec ((a 1) (b (+ a 1))) b)", "RuntimeError: a was used before its letrec initialization finished");
+test_fail!(letrec6, "(letrec* ((a (begin (set! b 2) 1)) (b 1)) a)", "RuntimeError: Can't set! b before its letrec initialization finished");
+
 test!(conditional_execution1, "(if #t 1 2)", "1");
 test!(conditional_execution2, "(if #f 1 2)", "2");
 test!(conditional_execution3, "(if 0 1 2)", "1");
@@ -233,10 +534,23 @@ test!(quoting9, "'(1 2)", "(1 2)");
 test!(quoting10, "'(a b (c (d) e ()))", "(a b (c (d) e ()))");
 test!(quoting11, "'(1 '2)", "(1 (quote 2))");
 
+test!(bar_symbols1, "'|hello world|", "|hello world|");
+test!(bar_symbols2, "(list '|abc| '|1| '|| '|a\\|b| '|\\x3bb;|)", "(abc |1| || |a\\|b| λ)");
+test!(bar_symbols3, "(display->string '|a b| '(|c\\|d|))", "\"a b(c|d)\"");
+test!(bar_symbols4, "(define |my var| 2) (* |my var| 3)", "6");
+
 test!(quasiquoting1, "(quasiquote (1 2))", "(1 2)");
 test!(quasiquoting2, "(quasiquote (2 (unquote (+ 1 2)) 4))", "(2 3 4)");
 test!(quasiquoting3, "`(2 ,(+ 1 2) 4)", "(2 3 4)");
 test!(quasiquoting4, "(define formula '(+ x y)) `((lambda (x y) ,formula) 2 3)", "((lambda (x y) (+ x y)) 2 3)");
+test!(quasiquoting5, "(define xs '(2 3)) `(1 ,@xs 4 ,@xs)", "(1 2 3 4 2 3)");
+test!(quasiquoting6, "`(1 (unquote-splicing (list)) (a ,@(list (+ 1 1) 'b)))", "(1 (a 2 b))");
+test!(quasiquoting7, "`(,@'(1 2))", "(1 2)");
+test!(quasiquoting8, "(let ((x 1)) `,x)", "1");
+test_fail!(quasiquoting9, "`(1 ,@2)", "RuntimeError: Can't splice a non-list into a quasiquoted list: 2");
+test_fail!(quasiquoting10, "`,@'(1 2)", "RuntimeError: Can't use unquote-splicing outside a quasiquoted list: (unquote-splicing (quote (1 2)))");
+test!(quasiquoting11, "(list `(1 . ,(+ 1 1)) `(1 ,@'(2) . 3) `(a (b . ,(+ 1 2)) . c) `(1 . ,(list 2 3)))", "((1 . 2) (1 2 . 3) (a (b . 3) . c) (1 2 3))");
+test!(quasiquoting12, "(let ((x '(y . z))) `(0 . ,x))", "(0 y . z)");
 
 test!(apply1, "(apply + '(1 2 3))", "6");
 test!(apply2, "(define foo (lambda (f) (lambda (x y) (f (f x y) y)))) (apply (apply foo (list +)) '(5 3))", "11");
@@ -245,6 +559,11 @@ test!(eval1, "(eval '(+ 1 2 3))", "6");
 test!(eval2, "(define eval-formula (lambda (formula) (eval `((lambda (x y) ,formula) 2 3)))) (eval-formula '(+ (- y x) y))", "4");
 test_fail!(eval3, "(define bad-eval-formula (lambda (formula) ((lambda (x y) (eval formula)) 2 3))) (bad-eval-formula '(+ x y))", "RuntimeError: Identifier not found: x");
 
+test_fail!(suggest1, "(define (length l) (if (null? l) 0 (+ 1 (length (cdr l))))) (lenght '(1 2))", "RuntimeError: Identifier not found: lenght (did you mean length, length+?)");
+test_fail!(suggest2, "(define (f items) (car itmes)) (f '(1))", "RuntimeError: Identifier not found: itmes (did you mean items?)");
+test_fail!(suggest3, "(define total 1) (define totals 2) totalz", "RuntimeError: Identifier not found: totalz (did you mean total, totals?)");
+test_fail!(suggest4, "((lamda (x) x) 1)", "RuntimeError: Identifier not found: lamda (did you mean lambda?)");
+
 test_fail!(bad_syntax1, "(22+)", "SyntaxError: Unexpected character when looking for a delimiter: + (line: 1, column: 4)");
 test_fail!(bad_syntax2, "(+ 2 3)\n(+ 1 2-)", "SyntaxError: Unexpected character when looking for a delimiter: - (line: 2, column: 7)");
 
@@ -264,5 +583,862 @@ test!(macros5, "(define-syntax-rule (testy a b c) (if a b c)) (testy #t 1 (error
 test!(multiline1, "(define x 3)\n(define y 4)\n(+ x y)", "7");
 
 test!(comment1, "(define x 3)\n(define y 4)\n;(set! y 5)\n(+ x y); (+ x y)", "7");
+test!(comment2, "(define x 3); three\n(+ x 1);four\nx;x", "3");
+test!(comment3, "(define x 3)\n#| (set! x 4)\n   #| nested |#\n   (set! x 5) |#\n(+ x #| between |#1)", "4");
+test!(comment4, "(define x 3)\n#;(set! x 4)\n(list x #; x #;#; 1 2 (+ x 1))", "(3 4)");
+
+#[test]
+fn image_round_trip() {
+    let path = std::env::temp_dir().join("rusty_scheme_image_round_trip.img");
+    let i = interpreter::new("cps");
+    i.execute("(define y 5) (define (f x) (* x y)) (define-syntax-rule (twice e) (begin e e)) (define counter (let ((n 0)) (lambda () (set! n (+ n 1)) n)))").unwrap();
+    i.execute("(define b1 (box 1)) (define b2 b1) (define v (vector 1 #(2) b1 #u8(9) '(3 . 4)))").unwrap();
+    i.dump_image(&path).unwrap();
+
+    let j = interpreter::from_image(&path).unwrap();
+    assert_eq!(j.execute("(f y)").unwrap(), "25");
+    assert_eq!(j.execute("(twice (counter))").unwrap(), "2");
+    assert_eq!(j.execute("(car (cons 1 (list)))").unwrap(), "1");
+    assert_eq!(j.execute("(set-box! b1 2) (unbox b2)").unwrap(), "2");
+    assert_eq!(j.execute("v").unwrap(), "#(1 #(2) #&2 #u8(9) (3 . 4))");
+
+    // queues, sets and heaps keep their contents and stay shared, and conditions keep their kind
+    i.execute("(define q (make-queue 1 2)) (define q2 q) (define s (make-set 'a \"b\")) (define h (make-heap <)) (heap-push! h 3) (heap-push! h 1)").unwrap();
+    i.execute("(define c (guard (e (#t e)) (car '())))").unwrap();
+    i.dump_image(&path).unwrap();
+    let j = interpreter::from_image(&path).unwrap();
+    assert_eq!(j.execute("(enqueue! q2 3) (queue->list q)").unwrap(), "(1 2 3)");
+    assert_eq!(j.execute("(list (set-contains? s 'a) (set-contains? s \"b\") (set-contains? s 'c))").unwrap(), "(#t #t #f)");
+    assert_eq!(j.execute("(list (heap-pop! h) (heap-pop! h))").unwrap(), "(1 3)");
+    assert_eq!(j.execute("(list (type-error? c) (error-object-message c))").unwrap(), "(#t \"Can't run car on an empty list\")");
+    // but a continuation can't be written
+    i.execute("(define k (call/cc (lambda (k) k)))").unwrap();
+    assert!(i.dump_image(&path).err().unwrap().starts_with("RuntimeError: Can't write #<continuation"));
+    assert_eq!(interpreter::new("ast_walk").dump_image(&path).err().unwrap(), "Images are only supported by the cps interpreter");
+}
+
+#[test]
+fn run_script() {
+    let i = interpreter::new("cps");
+    assert_eq!(i.run_script("(define x 41) (+ x 1)", None), Ok(()));
+    assert_eq!(i.execute("x").unwrap(), "41");
+    assert_eq!(i.run_script("(define y 1) (car '())", None), Err("RuntimeError: Can't run car on an empty list".to_string()));
+    assert_eq!(i.execute("y").unwrap(), "1");
+}
+
+#[test]
+fn autoload_on_first_reference() {
+    use std::io::Write;
+    let path = std::env::temp_dir().join("rusty_scheme_autoload_lib.scm");
+    std::fs::File::create(&path).unwrap().write_all(b"(define (lib-double x) (* 2 x)) (define lib-name \"lib\")").unwrap();
+    let path = path.to_str().unwrap();
+
+    each_interpreter(|t| {
+        let i = interpreter::new(t);
+        i.execute(&format!("(autoload {:?} lib-double lib-name)", path)).unwrap();
+        assert_eq!(i.execute("(lib-double 21)").unwrap(), "42");
+        assert_eq!(i.execute("lib-name").unwrap(), "\"lib\"");
+
+        let j = interpreter::new(t);
+        j.autoload(path, &["lib-name"]);
+        assert_eq!(j.execute("lib-name").unwrap(), "\"lib\"");
+        assert_eq!(j.execute("(lib-double 1)").unwrap(), "2");
+        assert_eq!(j.execute("lib-triple").err().unwrap(), "RuntimeError: Identifier not found: lib-triple");
+    });
+
+    // a library that fails to load stays registered, so fixing it and referring to the name again loads it
+    let broken = std::env::temp_dir().join("rusty_scheme_autoload_broken.scm");
+    let _ = std::fs::remove_file(&broken);
+    each_interpreter(|t| {
+        let i = interpreter::new(t);
+        i.execute(&format!("(autoload {:?} broken-double)", broken.to_str().unwrap())).unwrap();
+        assert!(i.execute("(broken-double 1)").err().unwrap().contains("Couldn't load"));
+        std::fs::File::create(&broken).unwrap().write_all(b"(define (broken-double x) (* 2 x)) (car '())").unwrap();
+        assert_eq!(i.execute("(broken-double 1)").err().unwrap(), "RuntimeError: Can't run car on an empty list");
+        std::fs::File::create(&broken).unwrap().write_all(b"(define (broken-double x) (* 2 x))").unwrap();
+        assert_eq!(i.execute("(broken-double 4)").unwrap(), "8");
+        std::fs::remove_file(&broken).unwrap();
+    });
+}
+
+#[test]
+fn include_files() {
+    use std::io::Write;
+    let dir = std::env::temp_dir().join("rusty_scheme_include");
+    std::fs::create_dir_all(dir.join("lib")).unwrap();
+    let write = |name: &str, contents: &str| std::fs::File::create(dir.join(name)).unwrap().write_all(contents.as_bytes()).unwrap();
+    write("lib/shapes.scm", "(include \"helpers.scm\") (define (quadruple x) (double (double x)))");
+    write("lib/helpers.scm", "(define (double x) (* 2 x))");
+    write("LOUD.scm", "(DEFINE (Shout X) (LIST X 'HEY))");
+    write("a.scm", "(include \"b.scm\")");
+    write("b.scm", "(include \"a.scm\")");
+    let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+
+    each_interpreter(|t| {
+        let i = interpreter::new(t);
+        assert_eq!(i.execute(&format!("(include {:?}) (list (double 1) (quadruple 1))", path("lib/shapes.scm"))).unwrap(), "(2 4)");
+        assert_eq!(i.execute(&format!("(define (f) (include {:?}) (shout 1)) (f)", path("LOUD.scm"))).unwrap_err(),
+                   "RuntimeError: Identifier not found: DEFINE");
+        assert_eq!(i.execute(&format!("(define (g) (include-ci {:?}) (shout 1)) (g)", path("LOUD.scm"))).unwrap(), "(1 hey)");
+        assert_eq!(i.execute(&format!("'(include {:?})", path("missing.scm"))).unwrap(), format!("(include {:?})", path("missing.scm")));
+        let err = i.execute_checked(&format!("(include {:?})", path("missing.scm"))).unwrap_err();
+        assert!(err.is_recoverable());
+        assert!(err.to_string().starts_with(&format!("ExpandError: Couldn't include {}: ", path("missing.scm"))));
+        assert_eq!(i.execute(&format!("(include {:?})", path("a.scm"))).unwrap_err(),
+                   format!("ExpandError: Circular include: {} -> {} -> {}", path("a.scm"), path("b.scm"), path("a.scm")));
+    });
+}
+
+#[cfg(feature = "io")]
+#[test]
+fn directory_walk_and_glob() {
+    let dir = std::env::temp_dir().join("rusty_scheme_walk");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("src/lib")).unwrap();
+    for file in ["notes.txt", "src/main.scm", "src/lib/list.scm"].iter() {
+        std::fs::write(dir.join(file), "").unwrap();
+    }
+    let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+
+    each_interpreter(|t| {
+        let i = interpreter::new(t);
+        let program = format!("(define found (box '())) (directory-walk {:?} (lambda (f) (set-box! found (cons f (unbox found))))) (reverse (unbox found))", path(""));
+        assert_eq!(i.execute(&program).unwrap(), format!("({:?} {:?} {:?})", path("notes.txt"), path("src/lib/list.scm"), path("src/main.scm")));
+        assert_eq!(i.execute(&format!("(glob {:?})", path("src/**/*.scm"))).unwrap(), format!("({:?} {:?})", path("src/lib/list.scm"), path("src/main.scm")));
+        assert_eq!(i.execute(&format!("(glob {:?})", path("*.md"))).unwrap(), "()");
+        assert!(i.execute(&format!("(directory-walk {:?} display)", path("missing"))).unwrap_err().starts_with("RuntimeError: Couldn't read "));
+    });
+}
+
+#[test]
+fn search_path() {
+    use std::io::Write;
+    let dir = std::env::temp_dir().join("rusty_scheme_search");
+    std::fs::create_dir_all(dir.join("libs")).unwrap();
+    std::fs::File::create(dir.join("libs/triple.scm")).unwrap().write_all(b"(define (triple x) (* 3 x))").unwrap();
+
+    each_interpreter(|t| {
+        let i = interpreter::Builder::new().interpreter_type(t).search_path(vec![dir.join("missing"), dir.join("libs")]).build().unwrap();
+        assert_eq!(i.execute("(include \"triple.scm\") (triple 2)").unwrap(), "6");
+        let i = interpreter::Builder::new().interpreter_type(t).search_path(vec![dir.join("libs")]).build().unwrap();
+        assert_eq!(i.execute("(autoload \"triple.scm\" triple) (triple 3)").unwrap(), "9");
+        assert!(i.execute("(include \"quadruple.scm\")").unwrap_err().starts_with("ExpandError: Couldn't include quadruple.scm: "));
+    });
+}
+
+#[cfg(feature = "io")]
+#[test]
+fn init_file() {
+    use std::io::Write;
+    let dir = std::env::temp_dir().join("rusty_scheme_init");
+    std::fs::create_dir_all(&dir).unwrap();
+    let write = |name: &str, contents: &str| std::fs::File::create(dir.join(name)).unwrap().write_all(contents.as_bytes()).unwrap();
+    write("init.scm", "(define (sq x) (* x x)) (set-printer-option! 'max-length 2)");
+    write("broken.scm", "(define (ok) 1) (undefined-thing)");
+
+    each_interpreter(|t| {
+        let i = interpreter::new(t);
+        assert_eq!(i.load_init_file(&dir.join("missing.scm")), Ok(()));
+        assert_eq!(i.load_init_file(&dir.join("init.scm")), Ok(()));
+        assert_eq!(i.execute_repl_line("(list (sq 3) 2 1)").unwrap(), "$1 = (9 2 ... 1 more)");
+        i.execute("(set-printer-option! 'max-length #f)").unwrap();
+        assert_eq!(i.load_init_file(&dir.join("broken.scm")).unwrap_err(),
+                   format!("In init file {}: RuntimeError: Identifier not found: undefined-thing", dir.join("broken.scm").display()));
+        assert_eq!(i.execute("(ok)").unwrap(), "1");
+    });
+}
+
+test!(eqv1, "(list (eqv? 1 1) (eqv? 1 1.0) (= 1 1.0) (eqv? 2/4 1/2) (eqv? 1/2 0.5) (= 1/2 0.5) (eqv? (* 2 1/2) 1) (eqv? (+ 1 0.5) 1.5))",
+      "(#t #f #t #t #f #t #t #t)");
+test!(eqv2, "(define b (box 1)) (define l '(1 2)) (list (eqv? b b) (eqv? b (box 1)) (eqv? l l) (eqv? l (list 1 2)) (eqv? '() '()) (eqv? 'a 'a) (eqv? #\\a #\\a) (eqv? 1 'a))",
+      "(#t #f #t #f #t #t #t #f)");
+test!(cond_expand1, "(cond-expand ((and r7rs (not spaceships)) (define x 1) (define y 2)) (else (define x 0))) (list x y)", "(1 2)");
+test!(cond_expand2, "(define (f) (cond-expand ((library (srfi 1)) 'srfi-1) (rusty-scheme 'builtin))) (f)", "builtin");
+test!(cond_expand3, "(car (features))", "r7rs");
+test_fail!(cond_expand4, "(define x (cond-expand (spaceships 1) ((not r7rs) 2))) x", "ExpandError: No matching cond-expand clause");
+#[test]
+fn cond_expand_embedder_features() {
+    each_interpreter(|t| {
+        let i = interpreter::new(t);
+        i.add_feature("spaceships");
+        i.add_library("(srfi 1)").unwrap();
+        assert_eq!(i.execute("(cond-expand ((and spaceships (library (srfi 1))) 'yes) (else 'no))").unwrap(), "yes");
+        assert!(i.execute("(features)").unwrap().ends_with(" spaceships)"));
+    });
+}
+
+test!(datum_labels1, "'#0=#&(1 #0#)", "#0=#&(1 #0#)");
+test!(datum_labels2, "(define b '#0=#&(1 #0#)) (set-box! (car (cdr (unbox b))) 2) (unbox b)", "2");
+test!(datum_labels3, "(define l '(#0=#&1 #0#)) (set-box! (car l) 5) (list l (unbox (car (cdr l))))", "((#&5 #&5) 5)");
+test!(datum_labels4, "(list '(#0=(a b) #0#) #&(+ 1 2))", "(((a b) (a b)) #&(+ 1 2))");
+test_fail!(datum_labels5, "'#0=(1 #0#)", "ParseError: #0# refers to a list that contains it, but lists can't contain themselves (only boxes and vectors can), depth: 1");
+test_fail!(datum_labels6, "'(#0=#&1) '#0#", "ParseError: Undefined datum label #0#, depth: 0");
+test!(datum_labels7, "(define (shared? l) (eqv? (car l) (car (cdr l)))) (list (shared? '(#0=(a b) #0#)) (shared? '(#0=\"s\" #0#)) (shared? '((c) (c))))",
+      "(#t #t #f)");
+test!(datum_labels8, "(define v '#0=#(1 #0#)) (list v (eqv? v (vector-ref v 1)))", "(#0=#(1 #0#) #t)");
+test!(square_brackets1, "(let ([x 2] [y 3]) (+ x y))", "5");
+test!(square_brackets2, "(define [f x] [* x 2]) (f [+ 1 2])", "6");
+test_fail!(square_brackets_mismatched, "(let ([x 2)) x)", "ParseError: Mismatched close paren, depth: 3");
+
+#[test]
+fn strict_reader_mode() {
+    use rusty_scheme::lexer::{ReaderConfig, ReaderMode};
+    let i = interpreter::Builder::new().reader(ReaderConfig { mode: ReaderMode::Strict, ..ReaderConfig::default() }).build().unwrap();
+    assert_eq!(i.execute("(let [(x 2)] x)").err().unwrap(), "SyntaxError: Unexpected character: [ (line: 1, column: 6)");
+    assert_eq!(i.execute("(let ((x 2)) x)").unwrap(), "2");
+}
+
+#[test]
+fn reader_limits() {
+    use rusty_scheme::lexer::{ReaderConfig, ReaderLimits};
+    let limits = ReaderLimits { max_depth: Some(3), ..ReaderLimits::default() };
+    let i = interpreter::Builder::new().reader(ReaderConfig { limits, ..ReaderConfig::default() }).build().unwrap();
+    assert_eq!(i.execute("(car (cdr '(1 2)))").unwrap(), "2");
+    let err = i.execute_checked("(car (cdr '(1 (2))))").unwrap_err();
+    assert!(err.is_recoverable());
+    assert_eq!(err.to_string(), "SyntaxError: Lists are nested deeper than the limit of 3 (line: 1, column: 15)");
+}
+
+#[test]
+fn reader_extensions() {
+    use rusty_scheme::lexer::ReaderConfig;
+    use rusty_scheme::parser::Node;
+    let mut reader = ReaderConfig::default();
+    reader.extensions.register("upper", |text| Ok(Node::String(text.to_uppercase())));
+    reader.extensions.register("point", |text| {
+        let coords: Result<Vec<Node>, String> = text.split(',').map(|c| c.trim().parse().map(Node::Integer).map_err(|_| c.to_string())).collect();
+        coords.map(|c| Node::List(vec![Node::Identifier("quote".to_string()), Node::List(c)]))
+    });
+    each_interpreter(|t| {
+        let i = interpreter::Builder::new().interpreter_type(t).reader(reader.clone()).build().unwrap();
+        assert_eq!(i.execute("(list #upper\"abc\" #t)").unwrap(), "(\"ABC\" #t)");
+        assert_eq!(i.execute("(car (cdr #point\"3, 4\"))").unwrap(), "4");
+        assert_eq!(i.execute("#point\"3, x\"").unwrap_err(), "SyntaxError: Invalid #point literal:  x (line: 1, column: 13)");
+    });
+}
+
+#[test]
+fn infix_reader() {
+    use rusty_scheme::lexer::ReaderConfig;
+    each_interpreter(|t| {
+        let i = interpreter::Builder::new().interpreter_type(t).reader(ReaderConfig { infix: true, ..ReaderConfig::default() }).build().unwrap();
+        assert_eq!(i.execute("#i(1 + 2 * 3)").unwrap(), "7");
+        assert_eq!(i.execute("(define (square x) (* x x)) (define r 3) #i(square(r + 1) - 10 / 2 - 1)").unwrap(), "10");
+        assert_eq!(i.execute("(if #i(r * 2 > 5) 'big 'small)").unwrap(), "big");
+        assert_eq!(i.execute("#i(-r + --r * -(r - 1))").unwrap(), "-9");
+    });
+}
+
+test!(interpolate1, "(define n 3) #\"n = ~{n}, n squared = ~{(* n n)}\"", "\"n = 3, n squared = 9\"");
+test!(interpolate2, "(define (greet name) #\"Hello, ~{name}! ~{(list name 'and 'friends)}\") (greet \"Ada\")", "\"Hello, Ada! (Ada and friends)\"");
+test!(interpolate3, "(display->string \"a\" 1 'b '(c \"d\"))", "\"a1b(c d)\"");
+test!(long_booleans, "(list #true #false)", "(#t #f)");
+test!(eof_object1, "(eof-object? (eof-object))", "#t");
+test!(eof_object2, "(list (eof-object? 1) (eof-object? '()))", "(#f #f)");
+test!(eof_object3, "(eof-object? #!eof)", "#t");
+test!(eof_object4, "(eof-object)", "#<eof>");
+#[cfg(feature = "math")]
+test!(random1, "(let ((n (random 10))) (and (< -1 n) (< n 10)))", "#t");
+#[cfg(feature = "math")]
+test!(random2, "(random 1)", "0");
+#[cfg(feature = "math")]
+test_fail!(random3, "(random 0)", "RuntimeError: Argument to random must be a positive integer: 0");
+test!(hash1, "(= (hash (list 1 \"a\" 'b)) (hash (list 1 \"a\" 'b)))", "#t");
+test!(hash2, "(= (hash '(1 2)) (hash '(1 (2))))", "#f");
+test!(hash3, "(< -1 (hash (box 'x)))", "#t");
+test!(hash4, "(define l '(1 2)) (= (hash-by-identity l) (hash-by-identity l))", "#t");
+test!(hash5, "(= (hash-by-identity 'a) (hash 'a))", "#t");
+test!(hash6, "(define b (box 1)) (set-box! b b) (< -1 (hash b))", "#t");
+
+#[test]
+fn hash_across_interpreters() {
+    // (hash x) goes by what x is, not by which interpreter made it
+    let hashes = |t: &str| interpreter::new(t).execute("(list (hash 1.5) (hash '(1 . 2)) (hash #\\a) (hash 1/2))").unwrap();
+    assert_eq!(hashes("cps"), hashes("ast_walk"));
+}
+
+test!(guard1, "(guard (e (#t 'caught)) (+ 1 2))", "3");
+test!(guard2, "(guard (e ((type-error? e) 'type) ((arity-error? e) 'arity)) (car 1 2))", "arity");
+test!(guard3, "(guard (e ((file-error? e) 'file) ((type-error? e) (error-object-message e))) (car '()))", "\"Can't run car on an empty list\"");
+test!(guard4, "(autoload \"no/such/file.scm\" missing) (guard (e ((file-error? e) (error? e))) missing)", "#t");
+test!(guard5, "(guard (e ((read-error? e) 'read) (else 'other)) (error \"oops\"))", "other");
+test!(guard6, "(guard (e ((error? e))) (error 'oops))", "#t");
+test!(guard7, "(guard (outer (#t (list 'outer (arity-error? outer)))) (guard (inner ((type-error? inner) 'inner)) ((lambda (x) x))))", "(outer #t)");
+test!(guard8, "(list (error? 'oops) (type-error? 1))", "(#f #f)");
+test!(guard10, "(guard (e ((assertion-violation? e) (list (type-error? e) (error? e)))) (+ 1 'a))", "(#t #t)");
+test!(guard11, "(guard (e ((assertion-violation? e) 'bug) ((error? e) 'other)) (assertion-violation \"bad\"))", "bug");
+test_fail!(guard9, "(guard (e ((file-error? e) 'file)) (error \"oops\"))", "RuntimeError: \"oops\"");
+test!(aliases1, "(list (call/cc (lambda (k) 1)) (call-with-current-continuation (lambda (k) 2)))", "(1 2)", cps);
+test!(aliases2, "(define (call-with-current-continuation f) 'mine) (call-with-current-continuation 1)", "mine");
+test!(keywords1, "#:size", "#:size");
+test!(keywords2, "(list size: (keyword? #:size) (keyword? 'size))", "(#:size #t #f)");
+test!(keywords3, "(list (keyword->string color:) (string->keyword \"color\"))", "(\"color\" #:color)");
+test!(keywords4, "(car '(#:a b))", "#:a");
+test!(boxes1, "(define b (box 1)) (set-box! b (+ (unbox b) 1)) (unbox b)", "2");
+test!(boxes2, "(list (box? (box 1)) (box? 1) (box \"a\"))", "(#t #f #&\"a\")");
+test!(boxes3, "(define b (box 0)) (define (inc!) (set-box! b (+ (unbox b) 1))) (inc!) (inc!) (unbox b)", "2");
+test_fail!(boxes4, "(unbox 1)", "RuntimeError: Expected a box value: 1");
+test!(string_ci1, "(list (string-ci=? \"Hello\" \"hELLO\") (string-ci=? \"Straße\" \"STRASSE\") (string-ci=? \"a\" \"b\"))", "(#t #t #f)");
+test!(string_ci2, "(list (string-ci<? \"apple\" \"BANANA\") (string-ci>? \"apple\" \"BANANA\") (string-ci<=? \"A\" \"a\") (string-ci>=? \"b\" \"A\"))", "(#t #f #t #t)");
+test!(string_ci3, "(string-foldcase \"ΧΑΟΣ Straße\")", "\"χαοσ strasse\"");
+test_fail!(string_ci4, "(string-ci=? \"a\" 1)", "RuntimeError: Expected a string value: 1");
+test!(printer1, "(define b (box 1)) (list b b)", "(#&1 #&1)");
+test!(printer2, "(define b (box 1)) (set-box! b b) b", "#0=#&#0#");
+test!(printer_cycles1, "(define b (box 1)) (set-box! b (list \"x\" b)) (display->string b)", "\"#0=#&(x #0#)\"");
+#[cfg(feature = "io")]
+test!(printer3, "(set-printer-option! 'max-length 2) (list (printer-option 'max-length) (printer-option 'width))", "(2 #f)");
+#[cfg(feature = "io")]
+test!(printer4, "(set-printer-option! 'max-depth 2) '(1 (2 (3 (4))))", "(1 (2 ...))");
+#[cfg(feature = "io")]
+test_fail!(printer5, "(set-printer-option! 'colour 2)", "RuntimeError: Unknown printer option: colour");
+test!(introspect1, "(list (length+ '(1 2 3)) (length+ '()) (length+ 5))", "(3 0 #f)");
+test!(introspect2, "(list (tree-size 1) (tree-size '(1 (2 3))) (depth 1) (depth '()) (depth '(1 (2 (3)))))", "(1 5 0 1 3)");
+test!(introspect3, "(define b (box 1)) (set-box! b (list 1 b)) (list (tree-size b) (depth b) (depth (list b b)))", "(4 #f #f)");
+test!(list_structure1, "(list (list-copy '(1 (2))) (list-copy 5) (last-pair '(1 2 3)) (last-pair '(1)))", "((1 (2)) 5 (3) (1))");
+test!(list_structure2, "(define (kinds x) (list (proper-list? x) (circular-list? x) (dotted-list? x))) (define b (box 1)) (set-box! b (list b)) (list (kinds '()) (kinds '(1 2)) (kinds 3) (kinds b))",
+      "((#t #f #f) (#t #f #f) (#f #f #t) (#f #f #t))");
+test_fail!(list_structure3, "(last-pair '())", "RuntimeError: Can't take the last pair of an empty list");
+test!(values1, "(call-with-values (lambda () (values 1 2 3)) list)", "(1 2 3)");
+test!(values2, "(list (values 4) (call-with-values (lambda () (values)) list) (call-with-values (lambda () 5) list))", "(4 () (5))");
+test!(values3, "(values 1 \"two\")", "1 \"two\"");
+test!(values4, "(call-with-values (lambda () (values 'a '(b c))) list)", "(a (b c))");
+test!(values5, "(call-with-values (lambda () (values '(1 . 2) 'x)) list)", "((1 . 2) x)");
+#[cfg(feature = "math")]
+test!(integer_division1, "(list (call-with-values (lambda () (floor/ -5 2)) list) (call-with-values (lambda () (floor/ 5 -2)) list))", "((-3 1) (-3 -1))");
+#[cfg(feature = "math")]
+test!(integer_division2, "(list (call-with-values (lambda () (truncate/ -5 2)) list) (call-with-values (lambda () (truncate/ 5 -2)) list))", "((-2 -1) (-2 1))");
+#[cfg(feature = "math")]
+test!(integer_division3, "(call-with-values (lambda () (exact-integer-sqrt 17)) (lambda (s r) (list s r)))", "(4 1)");
+#[cfg(feature = "math")]
+test_fail!(integer_division4, "(floor/ 1 0)", "RuntimeError: Division by zero in floor/: [1, 0]");
+#[cfg(feature = "math")]
+test_fail!(integer_division5, "(exact-integer-sqrt -4)", "RuntimeError: Argument to exact-integer-sqrt must be a non-negative integer: -4");
+test!(queue1, "(define q (make-queue 1)) (enqueue! q 2) (enqueue! q 3) (list (dequeue! q) (queue->list q) (queue-length q))", "(1 (2 3) 2)");
+test!(queue2, "(define q (make-queue)) (list q (queue? q) (queue? (list)) (queue-length q))", "(#<queue> #t #f 0)");
+test_fail!(queue3, "(dequeue! (make-queue))", "RuntimeError: Can't dequeue from an empty queue");
+test!(set1, "(define s (make-set 1 2 2)) (set-add! s '(3 4)) (set-add! s 1) (list (length+ (set->list s)) (set-contains? s 2) (set-contains? s (list 3 4)) (set-contains? s 5))", "(3 #t #t #f)");
+test!(set2, "(define a (make-set 1 2 3)) (define b (make-set 2 3 4)) (define u (set-union a b)) (define i (set-intersection a b (make-set 3))) (list (length+ (set->list u)) (set-contains? u 4) (set->list i) (set? i) (set? 3))", "(4 #t (3) #t #f)");
+test!(set_identity1, "(define b (box 1)) (define s (make-set b)) (set-box! b 2) (list (set-contains? s b) (set-contains? s (box 2)))", "(#t #f)");
+test!(set_identity2, "(define (f) 1) (define (g) 1) (define s (make-set)) (set-add! s f) (set-add! s f) (set-add! s car) (set-add! s car) (list (length+ (set->list s)) (set-contains? s f) (set-contains? s g))", "(2 #t #f)");
+test_fail!(set3, "(set-add! (make-queue) 1)", "RuntimeError: Expected a set value: #<queue>");
+test!(heap1, "(define h (make-heap <)) (heap-push! h 5) (heap-push! h 1) (heap-push! h 4) (heap-push! h 2) (heap-push! h 3) (list (heap-peek h) (heap-size h) (heap-pop! h) (heap-pop! h) (heap-pop! h) (heap-pop! h) (heap-pop! h) (heap-size h))", "(1 5 1 2 3 4 5 0)");
+test!(heap2, "(define h (make-heap (lambda (a b) (> (car a) (car b))))) (heap-push! h '(1 a)) (heap-push! h '(3 c)) (heap-push! h '(2 b)) (list (heap-pop! h) (heap-pop! h) (heap-peek h) (heap? h) (heap? 1))", "((3 c) (2 b) (1 a) #t #f)");
+test!(heap5, "(define h (make-heap (lambda (a b) (< (car a) (car b))))) (heap-push! h '(2 . b)) (heap-push! h '(1 . a)) (list (heap-pop! h) (heap-pop! h))", "((1 . a) (2 . b))");
+test!(heap4, "(define h (make-heap <)) (define (push-all l) (if (null? l) 0 (begin (heap-push! h (car l)) (push-all (cdr l))))) (define (pop-all) (if (= (heap-size h) 0) '() (cons (heap-pop! h) (pop-all)))) (push-all '(9 3 7 1 8 2 6 4 5 0 3 11 10)) (pop-all)", "(0 1 2 3 3 4 5 6 7 8 9 10 11)");
+test_fail!(heap3, "(heap-pop! (make-heap <))", "RuntimeError: Can't pop from an empty heap");
+#[cfg(feature = "math")]
+test!(number_to_string1, "(list (number->string 42) (number->string 255 16) (number->string -255 16) (number->string 35 36))", "(\"42\" \"ff\" \"-ff\" \"z\")");
+#[cfg(feature = "math")]
+test!(number_to_string2, "(list (number->string 5 2 8) (number->string -5 2 4) (number->string 1234 10 2))", "(\"00000101\" \"-0101\" \"1234\")");
+#[cfg(feature = "math")]
+test_fail!(number_to_string3, "(number->string 5 37)", "RuntimeError: Radix must be from 2 to 36: 37");
+test!(string_length, "(list (string-length \"\") (string-length \"héllo\") (string-length \"日本語\"))", "(0 5 3)");
+test!(substring1, "(list (substring \"héllo\" 1 3) (substring \"日本語\" 1) (substring \"abc\" 3))", "(\"él\" \"本語\" \"\")");
+test_fail!(substring2, "(substring \"abc\" 2 4)", "RuntimeError: Indexes 2 to 4 out of range for a string of length 3");
+test!(string_ranges1, "(list (string-copy \"héllo\" 1) (string-copy \"abc\" 1 2) (string->list \"日本語\") (string->list \"abcd\" 1 3))",
+      "(\"éllo\" \"b\" (#\\日 #\\本 #\\語) (#\\b #\\c))");
+test!(string_ranges2, "(list (list->string (list #\\a #\\é)) (list->string (string->list \"hello\") 2) (list->string '() 0 0))",
+      "(\"aé\" \"llo\" \"\")");
+test_fail!(string_ranges3, "(string->list \"abc\" 2 1)", "RuntimeError: Indexes 2 to 1 out of range for a string of length 3");
+test_fail!(string_ranges4, "(list->string (list #\\a #\\b) 1 3)", "RuntimeError: Indexes 1 to 3 out of range for a list of length 2");
+test_fail!(string_ranges5, "(list->string (list #\\a 1))", "RuntimeError: Expected a character value: 1");
+test_fail!(string_ranges6, "(string-copy \"abc\" -1)", "RuntimeError: Expected a non-negative integer value: -1");
+test!(vectors1, "(define v #(1 \"two\" #\\3 (4 5))) (list v (vector-length v) (vector-ref v 1) (vector-ref v 3) (vector? v) (vector? '(1)))",
+      "(#(1 \"two\" #\\3 (4 5)) 4 \"two\" (4 5) #t #f)");
+test!(vectors2, "(list (vector) (vector 1 (+ 1 1)) #(a (b)) '#(c) (vector->list #(1 2 3) 1) (list->vector '(1 2 3) 0 2))",
+      "(#() #(1 2) #(a (b)) #(c) (2 3) #(1 2))");
+test!(vectors3, "(define v #(1 2)) (list (= (hash v) (hash v)) (= (hash #(1 2)) (hash '(1 2))) (list->vector (vector->list #(1 #(2)))))", "(#t #f #(1 #(2)))");
+test_fail!(vectors4, "(vector-ref #(1 2) 2)", "RuntimeError: Index 2 out of range for a vector of length 2");
+test_fail!(vectors5, "(vector->list #(1 2) 1 3)", "RuntimeError: Indexes 1 to 3 out of range for a vector of length 2");
+test_fail!(vectors6, "(vector-length '(1 2))", "RuntimeError: Expected a vector value: (1 2)");
+test!(bytevectors1, "(define b #u8(0 7 255)) (list b (bytevector-length b) (bytevector-u8-ref b 2) (bytevector? b) (bytevector? #(0)) (bytevector) (bytevector 1 2))",
+      "(#u8(0 7 255) 3 255 #t #f #u8() #u8(1 2))");
+test!(bytevectors2, "(list (= (hash #u8(1 2)) (hash (bytevector 1 2))) (= (hash #u8(1 2)) (hash '(1 2))) '#u8(3))", "(#t #f #u8(3))");
+test!(vectors7, "(define v (vector 1 2 3 4)) (define w v) (define c (vector-copy v 1)) (vector-fill! w 0 2) (list v c (vector-copy #(a b c) 1 2))",
+      "(#(1 2 0 0) #(2 3 4) #(b))");
+test!(vectors8, "(define v (vector 1 2)) (vector-fill! v v 1) v", "#0=#(1 #0#)");
+test_fail!(vectors9, "(vector-fill! (vector 1 2) 0 1 3)", "RuntimeError: Indexes 1 to 3 out of range for a vector of length 2");
+test!(vectors10, "(define v (vector 1 2 3 4 5)) (vector-copy! v 1 v 0 3) (define w (vector-append #(a) v #())) (vector-push! w 'z) (list v (vector-length w) (vector-pop! w) (vector-pop! w) w)",
+      "(#(1 1 2 3 5) 7 z 5 #(a 1 1 2 3))");
+test_fail!(vectors11, "(vector-copy! (vector 1 2) 1 #(a b))", "RuntimeError: Indexes 1 to 3 out of range for a vector of length 2");
+test_fail!(vectors12, "(vector-pop! (vector))", "RuntimeError: Can't pop from an empty vector");
+test!(bytevectors6, "(list (bytevector-copy #u8(1 2 3) 1) (bytevector-copy #u8(1 2 3)) (bytevector-copy #u8(1 2 3) 0 1))", "(#u8(2 3) #u8(1 2 3) #u8(1))");
+test_fail!(bytevectors3, "(bytevector-u8-ref #u8(1) 1)", "RuntimeError: Index 1 out of range for a bytevector of length 1");
+test_fail!(bytevectors4, "(bytevector 1 256)", "RuntimeError: Expected a byte (an integer from 0 to 255): 256");
+test_fail!(bytevectors5, "(list #u8(1 2 300))", "ParseError: Expected a byte (an integer from 0 to 255) in a bytevector: 300");
+test!(dotted_lists1, "(list '(1 . 2) '(a b . c) (cons 1 2) (cons 0 '(1 . 2)) (cons 1 '()) '(1 . (2 3)))",
+      "((1 . 2) (a b . c) (1 . 2) (0 1 . 2) (1) (1 2 3))");
+test!(dotted_lists2, "(define p '(1 2 . 3)) (list (car p) (cdr p) (cdr (cdr p)) (last-pair p) (proper-list? p) (dotted-list? p) (null? p))",
+      "(1 (2 . 3) 3 (2 . 3) #f #t #f)");
+test!(dotted_lists3, "(list (= (hash '(1 . 2)) (hash (cons 1 2))) (= (hash '(1 . 2)) (hash '(1 2))) (display->string '(\"a\" . #\\b)))",
+      "(#t #f \"(a . b)\")");
+#[cfg(feature = "io")]
+test!(dotted_lists4, "(set-printer-option! 'max-length 2) (list '(1 . 2) '(1 2 3 . 4))", "((1 . 2) (1 2 ... 1 more))");
+test_fail!(dotted_lists5, "(1 . 2)", "RuntimeError: Can't evaluate a dotted list: (1 . 2)");
+test_fail!(dotted_lists6, "(list->string '(#\\a . #\\b))", "RuntimeError: Expected a list value: (#\\a . #\\b)");
+#[cfg(feature = "io")]
+test!(show_procedures1, "(set-printer-option! 'show-procedures #t) (lambda (x y) (+ x y))", "#<procedure (x y) (+ x y)>");
+#[cfg(feature = "io")]
+test!(show_procedures2, "(set-printer-option! 'show-procedures #t) (define (f) (display 1) (newline)) (list f (printer-option 'show-procedures))", "(#<procedure () (display 1) ...> #t)");
+#[cfg(feature = "io")]
+test_fail!(show_procedures3, "(set-printer-option! 'show-procedures 3)", "RuntimeError: Printer option show-procedures must be #t or #f");
+#[cfg(feature = "parallel")]
+test!(par_map1, "(par-map (lambda (x) (* x x)) '(1 2 3 4 5 6 7 8 9 10))", "(1 4 9 16 25 36 49 64 81 100)");
+#[cfg(feature = "parallel")]
+test!(par_map2, "(define offset 10) (define (square x) (* x x)) (par-map (lambda (x) (+ offset (square x))) '(1 2 3))", "(11 14 19)");
+#[cfg(feature = "parallel")]
+test!(par_map3, "(define (add-all n l) (par-map (lambda (x) (list x n)) l)) (add-all 'a '(1 2))", "((1 a) (2 a))");
+#[cfg(feature = "parallel")]
+test!(par_map4, "(list (par-map car '((1 2) (3 4))) (par-map car '()))", "((1 3) ())");
+#[cfg(feature = "parallel")]
+test_fail!(par_map5, "(par-map (lambda (x) (box x)) '(1 2))", "RuntimeError: Can't send #&1 to another thread");
+test!(cbor1, "(value->cbor '(1 \"a\" b))", "#u8(131 1 97 97 216 39 97 98)");
+test!(cbor2, "(cbor->value (value->cbor '(define (f x) (list x -500 #t \"héllo\"))))", "(define (f x) (list x -500 #t \"héllo\"))");
+test_fail!(cbor3, "(value->cbor (list 1 (box 2)))", "RuntimeError: Can't encode #&2 as CBOR");
+test_fail!(cbor4, "(cbor->value #u8(130 1))", "RuntimeError: CBOR item ends early, at byte 2");
+test_fail!(cbor5, "(cbor->value '(1))", "RuntimeError: Expected a bytevector value: (1)");
+test!(cbor6, "(list (value->cbor #u8(1 255)) (cbor->value (value->cbor '(#u8(1 2) #(a 3)))))", "(#u8(66 1 255) (#u8(1 2) (a 3)))");
+
+#[cfg(feature = "io")]
+test_fail!(write_canonical1, "(write-canonical (list 1 (lambda (x) x)))", "RuntimeError: Can't write #<procedure> as a canonical s-expression");
+#[cfg(all(feature = "io", unix))]
+test!(path1, "(list (path-join \"src\" \"lib\" \"list.scm\") (path-directory \"src/list.scm\") (path-filename \"src/list.scm\") (path-extension \"src/list.scm\"))", "(\"src/lib/list.scm\" \"src\" \"list.scm\" \"scm\")");
+#[cfg(all(feature = "io", unix))]
+test!(path2, "(list (path-directory \"/\") (path-filename \"..\") (path-extension \"README\") (path-absolute? \"/usr\") (path-absolute? \"usr\"))", "(#f #f #f #t #f)");
+test!(document1, "(document 'cons)", "\"(cons x list)\n    A list of x followed by the elements of list, or the dotted list (x . list) if list isn't a list.\"");
+test!(document2, "(document 'no-such-builtin)", "#f");
+test_fail!(document3, "(document \"cons\")", "RuntimeError: Expected a symbol value: \"cons\"");
+#[cfg(feature = "io")]
+test!(csv_read1, "(csv-read \"name,note\r\nbob,\n,\n\")", "((\"name\" \"note\") (\"bob\" \"\") (\"\" \"\"))");
+#[cfg(feature = "io")]
+test_fail!(csv_read2, "(csv-read 42)", "RuntimeError: Expected a string value: 42");
+#[cfg(feature = "io")]
+test_fail!(csv_write1, "(csv-write (list (list 1 (box 2))))", "RuntimeError: Can't write #&2 as a CSV field");
+#[cfg(feature = "sqlite")]
+test!(sqlite1, "(define db (sqlite-open \":memory:\")) (sqlite-exec db \"create table t (id integer, name text)\") (list (sqlite-exec db \"insert into t values (?, ?), (?, ?)\" 1 \"one\" 2 '()) (sqlite-query db \"select * from t order by id\"))", "(2 (((id 1) (name \"one\")) ((id 2) (name ()))))");
+#[cfg(feature = "sqlite")]
+test!(sqlite2, "(sqlite-open \":memory:\")", "#<sqlite-database :memory:>");
+#[cfg(feature = "sqlite")]
+test_fail!(sqlite3, "(sqlite-query (sqlite-open \":memory:\") \"select * from missing\")", "RuntimeError: no such table: missing");
+#[cfg(feature = "sqlite")]
+test_fail!(sqlite4, "(sqlite-exec (sqlite-open \":memory:\") \"select ?\" (box 1))", "RuntimeError: Can't pass #&1 to SQLite");
+
+#[cfg(feature = "parallel")]
+test_fail!(par_map6, "(define b (box 1)) (par-map (lambda (x) (unbox b)) '(1))", "RuntimeError: Identifier not found: b");
+
+#[cfg(feature = "math")]
+#[test]
+fn seeded_runs_repeat() {
+    each_interpreter(|t| {
+        let program = "(list (random 100) (random 100) (gensym) (gensym 'tmp))";
+        let first = interpreter::Builder::new().interpreter_type(t).seed(42).build().unwrap().execute(program).unwrap();
+        let second = interpreter::Builder::new().interpreter_type(t).seed(42).build().unwrap().execute(program).unwrap();
+        assert_eq!(first, second);
+        assert!(first.ends_with(" g1 tmp2)"));
+        let i = interpreter::new(t);
+        i.set_seed(42);
+        assert_eq!(i.execute(program).unwrap(), first);
+    });
+}
+
+#[cfg(feature = "net")]
+#[test]
+fn http_needs_network_access() {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    each_interpreter(|t| {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://127.0.0.1:{}/", listener.local_addr().unwrap().port());
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 1024];
+            let n = stream.read(&mut request).unwrap();
+            stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nhi").unwrap();
+            String::from_utf8_lossy(&request[..n]).into_owned()
+        });
+
+        let program = format!("(http-get \"{}\" '((\"Accept\" \"text/plain\")))", url);
+        assert_eq!(interpreter::new(t).execute(&program),
+                   Err("RuntimeError: http-get needs network access, which isn't allowed (see --allow-net)".to_string()));
+        let i = interpreter::Builder::new().interpreter_type(t).allow_network(true).build().unwrap();
+        assert_eq!(i.execute(&program), Ok("(200 ((\"Content-Length\" \"2\")) \"hi\")".to_string()));
+        assert!(server.join().unwrap().contains("Accept: text/plain\r\n"));
+        assert_eq!(i.execute("(http-post \"https://example.com\" \"\")"),
+                   Err("RuntimeError: Unsupported URL scheme https (only http is): https://example.com".to_string()));
+    });
+}
+
+#[cfg(feature = "net")]
+#[test]
+fn capabilities_per_interpreter() {
+    each_interpreter(|t| {
+        let sandboxed = interpreter::new(t);
+        let trusted = interpreter::Builder::new().interpreter_type(t).allow_network(true).build().unwrap();
+        let program = "(http-post \"https://example.com\" \"\")";
+        assert_eq!(sandboxed.execute(program),
+                   Err("RuntimeError: http-post needs network access, which isn't allowed (see --allow-net)".to_string()));
+        assert_eq!(trusted.execute(program),
+                   Err("RuntimeError: Unsupported URL scheme https (only http is): https://example.com".to_string()));
+        assert_eq!(sandboxed.execute("(define (post) (http-post \"http://localhost:1\" \"\")) (post)"),
+                   Err("RuntimeError: http-post needs network access, which isn't allowed (see --allow-net)".to_string()));
+    });
+}
+
+#[cfg(feature = "net")]
+#[test]
+fn serve_http() {
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+    use std::time::Duration;
+
+    // Send a request once the server is listening, and return its response
+    fn send(port: u16, request: &str) -> String {
+        for _ in 0..200 {
+            match TcpStream::connect(("127.0.0.1", port)) {
+                Ok(mut stream) => {
+                    let mut response = String::new();
+                    stream.write_all(request.as_bytes()).unwrap();
+                    stream.read_to_string(&mut response).unwrap();
+                    return response
+                },
+                Err(_) => thread::sleep(Duration::from_millis(25))
+            }
+        }
+        panic!("the server never started listening on port {}", port)
+    }
+
+    each_interpreter(|t| {
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let handler = "(lambda (r) (if (string-ci=? (car (cdr (car r))) \"GET\") \
+                       `((status 201) (headers ((\"X-Path\" ,(car (cdr (car (cdr r))))))) (body ,(car (cdr (car (cdr (cdr r))))))) \
+                       (car (cdr (car (cdr (cdr (cdr (cdr r)))))))))";
+        let program = format!("(serve-http {} {} 2)", port, handler);
+        assert_eq!(interpreter::new(t).execute(&program),
+                   Err("RuntimeError: serve-http needs network access, which isn't allowed (see --allow-net)".to_string()));
+
+        let i = interpreter::Builder::new().interpreter_type(t).allow_network(true).build().unwrap();
+        let client = thread::spawn(move || vec![
+            send(port, "nonsense\r\n\r\n"),
+            send(port, "GET /hello?name=x HTTP/1.1\r\nHost: localhost\r\n\r\n"),
+            send(port, "POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello"),
+        ]);
+        assert!(i.execute(&program).is_ok());
+        let responses = client.join().unwrap();
+        assert!(responses[0].starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        assert!(responses[1].starts_with("HTTP/1.1 201 Created\r\n"));
+        assert!(responses[1].contains("\r\nX-Path: /hello\r\n"));
+        assert!(responses[1].ends_with("\r\n\r\nname=x"));
+        assert!(responses[2].starts_with("HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=utf-8\r\n"));
+        assert!(responses[2].ends_with("\r\n\r\nhello"));
+
+        // a handler that fails answers 500 and stops the server
+        let client = thread::spawn(move || send(port, "GET / HTTP/1.1\r\n\r\n"));
+        assert!(i.execute(&format!("(serve-http \"127.0.0.1:{}\" (lambda (r) (car 1)))", port)).is_err());
+        assert!(client.join().unwrap().starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+    });
+}
+
+#[test]
+fn log_handler() {
+    use rusty_scheme::interpreter::LogLevel;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    each_interpreter(|t| {
+        let i = interpreter::new(t);
+        let logged = Rc::new(RefCell::new(Vec::new()));
+        let sink = logged.clone();
+        i.set_log_handler(move |level, message| sink.borrow_mut().push((level, message.to_string())));
+        i.execute("(log-info \"loaded ~a from ~s\" 3 \"a.csv\") (log-error \"~a rows failed\" (list 1 \"two\"))").unwrap();
+        assert_eq!(*logged.borrow(), vec![(LogLevel::Info, "loaded 3 from \"a.csv\"".to_string()),
+                                          (LogLevel::Error, "(1 two) rows failed".to_string())]);
+        assert_eq!(i.execute("(log-warn \"~a and ~a\" 1)").unwrap_err(), "RuntimeError: Not enough arguments for log message: ~a and ~a");
+    });
+}
+
+#[cfg(all(unix, feature = "process"))]
+#[test]
+fn on_signal() {
+    extern "C" {
+        fn raise(signum: i32) -> i32;
+    }
+    // SIGUSR1
+    let usr1 = if cfg!(target_os = "linux") { 10 } else { 30 };
+    each_interpreter(|t| {
+        let i = interpreter::new(t);
+        i.execute("(define got (box '())) (on-signal 'SIGUSR1 (lambda (name) (set-box! got (cons name (unbox got)))))").unwrap();
+        unsafe { raise(usr1) };
+        assert_eq!(i.execute("(unbox got)").unwrap(), "(SIGUSR1)");
+        assert_eq!(i.execute("(unbox got)").unwrap(), "(SIGUSR1)");
+        i.execute("(on-signal 'SIGUSR1 #f)").unwrap();
+        assert!(i.execute("(on-signal 'SIGKILL (lambda (name) name))").is_err());
+        assert!(i.execute("(on-signal 'SIGUSR1 1)").is_err());
+    });
+}
+
+#[cfg(all(feature = "ffi", target_os = "linux"))]
+#[test]
+fn foreign_procedures() {
+    each_interpreter(|t| {
+        let i = interpreter::new(t);
+        assert_eq!(i.execute("(load-foreign-library \"libc.so.6\")"),
+                   Err("RuntimeError: load-foreign-library needs foreign function access, which isn't allowed (see --allow-ffi)".to_string()));
+        let i = interpreter::Builder::new().interpreter_type(t).allow_foreign(true).build().unwrap();
+        i.execute("(define libc (load-foreign-library \"libc.so.6\")) (define labs (foreign-procedure libc \"labs\" '(long) 'long))").unwrap();
+        assert_eq!(i.execute("(list (labs -42) labs)").unwrap(), "(42 #<procedure>)");
+        assert_eq!(i.execute("((foreign-procedure libc \"strlen\" '(string) 'long) \"hello\")").unwrap(), "5");
+        assert_eq!(i.execute("((foreign-procedure libc \"getenv\" '(string) 'string) \"RUSTY_SCHEME_UNSET\")").unwrap(), "#f");
+        assert!(i.execute("(labs \"1\")").is_err());
+        i.execute("(define libm (load-foreign-library \"libm.so.6\"))").unwrap();
+        assert_eq!(i.execute("(list ((foreign-procedure libm \"cos\" '(double) 'double) 0.0) ((foreign-procedure libm \"pow\" '(double double) 'double) 2 10))").unwrap(),
+                   "(1.0 1024.0)");
+        assert!(i.execute("(load-foreign-library \"no-such-library.so\")").is_err());
+    });
+}
+
+#[test]
+fn plugins() {
+    use rusty_scheme::parser::Node;
+    use rusty_scheme::plugin::{Environment, Plugin};
+
+    struct Counting;
+
+    impl Plugin for Counting {
+        fn register(&self, env: &mut Environment) {
+            env.define("count-limit", Node::Integer(3));
+            env.document("count-up", "n", "The integers from 1 to n.");
+            env.define_builtin("count-up", |args| match args {
+                [Node::Integer(n)] => Ok(Node::List((1..*n + 1).map(Node::Integer).collect())),
+                _ => Err("count-up takes an integer".to_string())
+            });
+        }
+    }
+
+    each_interpreter(|t| {
+        let i = interpreter::new(t);
+        i.load_plugin(&Counting);
+        assert_eq!(i.execute("(count-up count-limit)").unwrap(), "(1 2 3)");
+        assert_eq!(i.execute("count-up").unwrap(), "#<procedure>");
+        assert_eq!(i.execute("(count-up 'x)"), Err("RuntimeError: count-up takes an integer".to_string()));
+        assert!(i.execute("(count-up (box 1))").is_err());
+        assert_eq!(i.execute("(document 'count-up)").unwrap(), "\"(count-up n)\n    The integers from 1 to n.\"");
+    });
+}
+
+#[test]
+fn fold_constants() {
+    each_interpreter(|t| {
+        let i = interpreter::Builder::new().interpreter_type(t).fold_constants(true).build().unwrap();
+        assert_eq!(i.execute("(+ (string-length \"hello\") (* 2 3))").unwrap(), "11");
+        assert_eq!(i.execute("(define-syntax-rule (greeting) (substring \"hello world\" 0 5)) (greeting)").unwrap(), "\"hello\"");
+        assert_eq!(i.execute("(let ((+ -)) (+ 1 2))").unwrap(), "-1");
+        assert_eq!(i.execute("(car '())"), Err("RuntimeError: Can't run car on an empty list".to_string()));
+        // folded once and for all, so redefining + afterwards doesn't change it, but does stop later code folding
+        i.execute("(define (three) (+ 1 2))").unwrap();
+        i.execute("(set! + -)").unwrap();
+        assert_eq!(i.execute("(list (three) (+ 1 2))").unwrap(), "(3 -1)");
+    });
+}
+
+#[test]
+fn strict_booleans() {
+    each_interpreter(|t| {
+        let i = interpreter::Builder::new().interpreter_type(t).strict_booleans(true).build().unwrap();
+        assert_eq!(i.execute("(list (if #f 1 2) (and #t 5) (or #f '(1)) (and) (or))").unwrap(), "(2 5 (1) #t #f)");
+        assert_eq!(i.execute("(if 0 1 2)"), Err("RuntimeError: Expected a boolean condition in if: 0".to_string()));
+        assert_eq!(i.execute("(and '() #t)"), Err("RuntimeError: Expected a boolean condition in and: ()".to_string()));
+        assert_eq!(i.execute("(or \"yes\" #f)"), Err("RuntimeError: Expected a boolean condition in or: \"yes\"".to_string()));
+        assert_eq!(i.execute("(guard (e ((error? e) 'caught)) (if 1 2 3))").unwrap(), "caught");
+    });
+    // and off by default, as Scheme has it
+    let i = interpreter::Builder::new().build().unwrap();
+    assert_eq!(i.execute("(if 0 1 2)").unwrap(), "1");
+}
+
+#[test]
+fn violations_and_recoverable_errors() {
+    use rusty_scheme::interpreter::Error;
+    each_interpreter(|t| {
+        let i = interpreter::new(t);
+        match i.execute_checked("(car 1 2)") {
+            Err(Error::Violation(_)) => (),
+            r => panic!("expected a violation: {:?}", r)
+        }
+        assert!(!i.execute_checked("(assertion-violation \"bad\")").unwrap_err().is_recoverable());
+        assert!(i.execute_checked("(error \"oops\")").unwrap_err().is_recoverable());
+        assert!(i.execute_checked("(autoload \"no/such/file.scm\" missing) missing").unwrap_err().is_recoverable());
+        assert!(i.execute_checked("(car").unwrap_err().is_recoverable());
+    });
+}
+
+#[test]
+fn repl_theme() {
+    use rusty_scheme::interpreter::ColorMode;
+    each_interpreter(|t| {
+        let i = interpreter::new(t);
+        assert_eq!(i.execute_repl_line("1").unwrap(), "$1 = 1");
+        i.set_repl_prompt("scheme> ");
+        assert_eq!(i.repl_prompt(), "scheme> ");
+        i.execute("(define repl-prompt \"λ> \")").unwrap();
+        assert_eq!(i.repl_prompt(), "λ> ");
+        i.set_repl_colors(ColorMode::Always);
+        assert_eq!(i.execute_repl_line("2").unwrap(), "\x1b[32m$2 = 2\x1b[0m");
+        assert_eq!(i.execute_repl_line(",nope").unwrap_err(), "\x1b[31mUnknown REPL command: ,nope\x1b[0m");
+        i.set_repl_error_color(Some("yellow")).unwrap();
+        assert_eq!(i.execute_repl_line(",nope").unwrap_err(), "\x1b[33mUnknown REPL command: ,nope\x1b[0m");
+        i.execute("(define repl-error-color 'blue) (define repl-result-color #f)").unwrap();
+        assert_eq!(i.execute_repl_line(",nope").unwrap_err(), "\x1b[34mUnknown REPL command: ,nope\x1b[0m");
+        assert_eq!(i.execute_repl_line("3").unwrap(), "$3 = 3");
+        assert_eq!(i.set_repl_result_color(Some("mauve")), Err("Unknown color: mauve".to_string()));
+    });
+}
+
+#[test]
+fn repl_expand_step() {
+    each_interpreter(|t| {
+        let i = interpreter::new(t);
+        i.execute("(define-syntax-rule (unless c body) (if c #f body))").unwrap();
+        assert_eq!(i.execute_repl_line(",expand-step (list (unless x (unless y 1)))").unwrap(),
+                   ["(list (unless x (unless y 1)))",
+                    "=> (list (if x #f (unless y 1)))",
+                    "         ^^^^^^^^^^^^^^^^^^^^^^",
+                    "=> (list (if x #f (if y #f 1)))",
+                    "                  ^^^^^^^^^^^"].join("\n"));
+        assert_eq!(i.execute_repl_line(",expand-step '(unless x y)").unwrap(), "(quote (unless x y))\n;; no macro uses to expand");
+        assert_eq!(i.execute_repl_line(",expand-step (unless x)").unwrap_err(),
+                   "ExpandError: Must supply exactly 2 arguments to unless: (x)");
+        assert_eq!(i.execute_repl_line(",expand-step").unwrap_err(), "Usage: ,expand-step expression");
+        i.execute("(define-syntax-rule (forever x) (list (forever x)))").unwrap();
+        assert_eq!(i.execute_repl_line(",expand-step (forever 1)").unwrap_err(),
+                   "Still expanding after 100 steps, so a macro probably expands into itself");
+    });
+}
+
+#[test]
+fn repl_pasted_block() {
+    each_interpreter(|t| {
+        let i = interpreter::new(t);
+        assert!(i.is_incomplete("(define (sq x)\n  (* x"));
+        assert!(!i.is_incomplete("(+ 1 2))"));
+        assert!(!i.is_incomplete("(+ 1 2)"));
+        i.execute("(define (sq x) (* x x))").unwrap();
+        assert_eq!(i.execute_repl_line("(sq 2)\n'(a b)\n(car $2)").unwrap(), "$1 = 4\n$2 = (a b)\n$3 = a");
+        assert!(i.execute_repl_line("(sq 3) (car '()) (sq 4)").unwrap_err()
+                 .starts_with("$4 = 9\nRuntimeError: Can't run car on an empty list\nIn (car (quote ()))"));
+        i.execute_repl_line(",abort").unwrap();
+        assert_eq!(i.execute_repl_line("$$").unwrap(), "$5 = 9");
+    });
+}
+
+#[cfg(feature = "io")]
+#[test]
+fn repl_truncation() {
+    each_interpreter(|t| {
+        let i = interpreter::new(t);
+        assert_eq!(i.execute_repl_line(",full").unwrap_err(), "No result to show yet");
+        i.execute("(define (down i l) (if (= i 0) l (down (- i 1) (cons i l)))) (define (upto n) (down n '()))").unwrap();
+        let printed = i.execute_repl_line("(upto 150)").unwrap();
+        assert!(printed.starts_with("$1 = (1 2 3 "));
+        assert!(printed.ends_with(" 99 100 ... 50 more)"));
+        assert!(i.execute_repl_line(",full").unwrap().ends_with(" 149 150)"));
+        // a limit that's been set wins, and is lifted by ,full too
+        i.execute("(set-printer-option! 'max-length 2)").unwrap();
+        assert_eq!(i.execute_repl_line("(upto 3)").unwrap(), "$2 = (1 2 ... 1 more)");
+        assert_eq!(i.execute_repl_line(",full").unwrap(), "(1 2 3)");
+        // and plain execute isn't limited
+        i.execute("(set-printer-option! 'max-length #f)").unwrap();
+        assert!(i.execute("(upto 150)").unwrap().ends_with(" 149 150)"));
+    });
+}
+
+#[test]
+fn warning_handler() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    each_interpreter(|t| {
+        let i = interpreter::new(t);
+        let warnings = Rc::new(RefCell::new(Vec::new()));
+        let sink = warnings.clone();
+        i.set_warning_handler(move |m| sink.borrow_mut().push(m.to_string()));
+        assert_eq!(i.execute("(warn \"low on\" 'disk 42) 'done").unwrap(), "done");
+        assert_eq!(i.execute("(define (f car) car) (list (f 1) (f 2))").unwrap(), "(1 2)");
+        assert_eq!(*warnings.borrow(), vec!["low on disk 42", "Local variable car shadows a builtin procedure"]);
+    });
+}
+
+#[test]
+fn translated_error_messages() {
+    use std::collections::HashMap;
+    let mut catalog = HashMap::new();
+    catalog.insert("Can't run car on an empty list".to_string(), "car d'une liste vide".to_string());
+    catalog.insert("Identifier not found: {}".to_string(), "Identificateur inconnu : {}".to_string());
+    rusty_scheme::messages::set_catalog(catalog);
+    let i = interpreter::new("cps");
+    assert_eq!(i.execute("(car '())").unwrap_err(), "RuntimeError: car d'une liste vide");
+    assert_eq!(i.execute("x").unwrap_err(), "RuntimeError: Identificateur inconnu : x");
+    assert_eq!(i.execute("(cdr '())").unwrap_err(), "RuntimeError: Can't run cdr on an empty list");
+}
+
+#[test]
+fn repl_error_frame() {
+    each_interpreter(|t| {
+        let i = interpreter::new(t);
+        i.execute("(define (f l n) (let ((m (* n 2))) (+ m (car l))))").unwrap();
+        assert_eq!(i.execute_repl_line("(f '() 4)").unwrap_err(),
+                   "RuntimeError: Can't run car on an empty list\nIn (car l)\n  m = 8\n  l = ()\n  n = 4\n\
+                    Evaluate expressions to inspect them, or ,abort to return to the top level");
+        assert_eq!(i.repl_prompt(), "error> ");
+        assert_eq!(i.execute_repl_line("(list l n m)").unwrap(), "(() 4 8)");
+        assert_eq!(i.execute_repl_line("(cdr l)").unwrap_err(), "RuntimeError: Can't run cdr on an empty list");
+        assert!(i.execute_repl_line(",frame").unwrap().starts_with("In (car l)\n"));
+        assert_eq!(i.execute_repl_line(",abort").unwrap(), "Back to the top level");
+        assert_eq!(i.repl_prompt(), "> ");
+        assert!(i.execute_repl_line("m").is_err());
+        assert_eq!(i.execute_repl_line("(f '(1) 4)").unwrap(), "$1 = 9");
+        assert_eq!(i.execute_repl_line(",abort").unwrap_err(), "Already at the top level");
+    });
+}
+
+#[cfg(feature = "io")]
+#[test]
+fn printer_config_from_builder() {
+    use rusty_scheme::printer::PrinterConfig;
+    let i = interpreter::Builder::new().printer(PrinterConfig { max_length: Some(3), ..PrinterConfig::default() }).build().unwrap();
+    assert_eq!(i.execute("'(1 2 3 4 5)").unwrap(), "(1 2 3 ... 2 more)");
+    assert_eq!(i.execute("(set-printer-option! 'max-length #f) '(1 2 3 4 5)").unwrap(), "(1 2 3 4 5)");
+}
+
+#[test]
+fn repl_result_history() {
+    each_interpreter(|t| {
+        let i = interpreter::new(t);
+        assert_eq!(i.execute_and_remember("(+ 1 2)").unwrap(), "$1 = 3");
+        assert_eq!(i.execute_and_remember("'(a b)").unwrap(), "$2 = (a b)");
+        assert_eq!(i.execute_and_remember("(car $2)").unwrap(), "$3 = a");
+        assert_eq!(i.execute("(list $1 $$)").unwrap(), "(3 a)");
+        assert!(i.execute_and_remember("(car '())").is_err());
+        assert_eq!(i.execute_and_remember("$$").unwrap(), "$4 = a");
+    });
+}
+
+#[test]
+fn repl_stats_toggle() {
+    each_interpreter(|t| {
+        let i = interpreter::new(t);
+        assert_eq!(i.execute_repl_line("(+ 1 2)").unwrap(), "$1 = 3");
+        assert_eq!(i.execute_repl_line(",stats on").unwrap(), "Stats on");
+        let out = i.execute_repl_line("(list 1 2 3)").unwrap();
+        assert!(out.starts_with("$2 = (1 2 3)\n;; "));
+        assert!(out.ends_with(" values allocated"));
+        assert!(out.contains(" steps, "));
+        assert!(i.execute_repl_line("(car '())").err().unwrap().contains(" steps, "));
+        assert_eq!(i.execute_repl_line(",abort").unwrap(), "Back to the top level");
+        assert_eq!(i.execute_repl_line(",stats off").unwrap(), "Stats off");
+        assert_eq!(i.execute_repl_line("$$").unwrap(), "$3 = (1 2 3)");
+        assert_eq!(i.execute_repl_line(",frobnicate").err().unwrap(), "Unknown REPL command: ,frobnicate");
+    });
+}
 
 test!(tail_call_optimization1, "(define (f i) (if (= i 1000) '() (f (+ i 1)))) (f 1)", "()", cps);
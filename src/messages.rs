@@ -0,0 +1,102 @@
+// Error messages, which embedders can translate. Errors are raised with a template, the English text with {} and {:?}
+// placeholders (e.g. "Unexpected character: {}"), which also serves as the message's code, plus the parameters to
+// fill in. A catalog maps templates to translations using the same placeholders in the same order; templates it
+// doesn't cover stay in English. Like the printer settings, the catalog is per thread.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+
+thread_local!(static CATALOG: RefCell<HashMap<String, String>> = RefCell::new(HashMap::new()));
+
+pub fn set_catalog(catalog: HashMap<String, String>) {
+    CATALOG.with(|c| *c.borrow_mut() = catalog);
+}
+
+// A parameter, shown both ways since the translation decides which placeholder it fills
+pub struct Param {
+    display: String,
+    debug: String,
+}
+
+// Parameters that can't be displayed (like slices of values) are shown with Debug for {} as well. To pick the right
+// way without changing every call site, message! calls param() on &Wrap(&x): the ViaDisplay impl applies directly when
+// x can be displayed, otherwise method lookup borrows once more and finds the ViaDebug one.
+pub struct Wrap<'a, T: ?Sized + 'a>(pub &'a T);
+
+pub trait ViaDisplay {
+    fn param(&self) -> Param;
+}
+
+impl<'a, T: fmt::Display + fmt::Debug + ?Sized> ViaDisplay for Wrap<'a, T> {
+    fn param(&self) -> Param {
+        Param { display: format!("{}", self.0), debug: format!("{:?}", self.0) }
+    }
+}
+
+pub trait ViaDebug {
+    fn param(&self) -> Param;
+}
+
+impl<'a, 'b, T: fmt::Debug + ?Sized> ViaDebug for &'b Wrap<'a, T> {
+    fn param(&self) -> Param {
+        let debug = format!("{:?}", self.0);
+        Param { display: debug.clone(), debug }
+    }
+}
+
+// The message for a template in the current catalog's language
+pub fn format(template: &str, params: &[Param]) -> String {
+    CATALOG.with(|c| {
+        match c.borrow().get(template) {
+            Some(translation) => fill(translation, params),
+            None => fill(template, params)
+        }
+    })
+}
+
+fn fill(template: &str, params: &[Param]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut params = params.iter();
+    let mut rest = template;
+    while let Some(i) = rest.find('{') {
+        out.push_str(&rest[..i]);
+        rest = &rest[i..];
+        let (len, param) = if rest.starts_with("{}") {
+            (2, params.next().map(|p| &p.display))
+        } else if rest.starts_with("{:?}") {
+            (4, params.next().map(|p| &p.debug))
+        } else {
+            (1, None)
+        };
+        match param {
+            Some(p) => out.push_str(p),
+            None => out.push_str(&rest[..len])
+        }
+        rest = &rest[len..];
+    }
+    out.push_str(rest);
+    out
+}
+
+// For the error macros: takes format!'s arguments, and looks the template up in the catalog
+macro_rules! message {
+    ($template:expr) => (::messages::format($template, &[]));
+    ($template:expr, $($param:expr),+) => ({
+        #[allow(unused_imports)]
+        use ::messages::{ViaDebug, ViaDisplay};
+        ::messages::format($template, &[$((&::messages::Wrap(&$param)).param()),+])
+    });
+}
+
+#[test]
+fn test_messages() {
+    assert_eq!(message!("Expected {}, but found {:?}", "a list", "x"), "Expected a list, but found \"x\"");
+    assert_eq!(message!("Not displayable: {} {:?}", [1, 2], [3]), "Not displayable: [1, 2] [3]");
+    let mut catalog = HashMap::new();
+    catalog.insert("Expected {}, but found {:?}".to_string(), "Attendu : {}, trouvé : {:?}".to_string());
+    set_catalog(catalog);
+    assert_eq!(message!("Expected {}, but found {:?}", "a list", "x"), "Attendu : a list, trouvé : \"x\"");
+    assert_eq!(message!("Not translated: {}", 1), "Not translated: 1");
+    assert_eq!(fill("{ } {x} {}", &[]), "{ } {x} {}");
+}
@@ -0,0 +1,235 @@
+// Arithmetic on integers, rationals and floats, and (with the math feature) integer division and roots for the R7RS
+// division operators, shared by both interpreters. Each returns None when there's no answer: a zero divisor, a
+// negative square root, or a quotient too large for an integer. A sum, difference or product too large for an integer
+// is worked out in floats instead.
+
+use parser::Node;
+
+#[cfg(feature = "math")]
+use std::char;
+use std::cmp::Ordering;
+
+// A number as +, -, *, / and the comparisons see it. Integers stay integers and rationals stay exact, but a float
+// anywhere in a calculation makes its result a float. A rational is in lowest terms with a denominator above 1, so
+// one that reduces to a whole number is an integer instead.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Number {
+    Integer(i64),
+    Rational(i64, i64),
+    Float(f64),
+}
+
+impl Number {
+    pub fn to_float(self) -> f64 {
+        match self {
+            Number::Integer(i) => i as f64,
+            Number::Rational(n, d) => n as f64 / d as f64,
+            Number::Float(f) => f,
+        }
+    }
+
+    // The numerator and denominator of an exact number, wide enough to multiply without overflowing
+    fn fraction(self) -> Option<(i128, i128)> {
+        match self {
+            Number::Integer(i) => Some((i as i128, 1)),
+            Number::Rational(n, d) => Some((n as i128, d as i128)),
+            Number::Float(_) => None
+        }
+    }
+
+    pub fn add(self, other: Number) -> Number {
+        match (self, other) {
+            (Number::Integer(a), Number::Integer(b)) => match a.checked_add(b) {
+                Some(n) => Number::Integer(n),
+                None => Number::Float(a as f64 + b as f64)
+            },
+            (a, b) => exact(a, b, |(an, ad), (bn, bd)| ratio(an * bd + bn * ad, ad * bd))
+                .unwrap_or_else(|| Number::Float(a.to_float() + b.to_float()))
+        }
+    }
+
+    pub fn subtract(self, other: Number) -> Number {
+        match (self, other) {
+            (Number::Integer(a), Number::Integer(b)) => match a.checked_sub(b) {
+                Some(n) => Number::Integer(n),
+                None => Number::Float(a as f64 - b as f64)
+            },
+            (a, b) => exact(a, b, |(an, ad), (bn, bd)| ratio(an * bd - bn * ad, ad * bd))
+                .unwrap_or_else(|| Number::Float(a.to_float() - b.to_float()))
+        }
+    }
+
+    pub fn multiply(self, other: Number) -> Number {
+        match (self, other) {
+            (Number::Integer(a), Number::Integer(b)) => match a.checked_mul(b) {
+                Some(n) => Number::Integer(n),
+                None => Number::Float(a as f64 * b as f64)
+            },
+            (a, b) => exact(a, b, |(an, ad), (bn, bd)| ratio(an * bn, ad * bd))
+                .unwrap_or_else(|| Number::Float(a.to_float() * b.to_float()))
+        }
+    }
+
+    // Exact numbers divide exactly, so 1/3 is a rational and 4/2 an integer. Dividing a float by zero gives an
+    // infinity (or +nan.0 for zero by zero), so only an exact number divided by an exact zero has no answer.
+    pub fn divide(self, other: Number) -> Option<Number> {
+        match (self, other) {
+            (_, Number::Integer(0)) if self.fraction().is_some() => None,
+            (a, b) => Some(exact(a, b, |(an, ad), (bn, bd)| ratio(an * bd, ad * bn))
+                           .unwrap_or_else(|| Number::Float(a.to_float() / b.to_float())))
+        }
+    }
+
+    // Compared by value, so 1 is equal to 1.0 and 1/2 to 0.5. +nan.0 isn't less than, greater than or equal to
+    // anything.
+    pub fn compare(self, other: Number) -> Option<Ordering> {
+        match (self, other) {
+            (Number::Integer(a), Number::Integer(b)) => Some(a.cmp(&b)),
+            (a, b) => match (a.fraction(), b.fraction()) {
+                (Some((an, ad)), Some((bn, bd))) => Some((an * bd).cmp(&(bn * ad))),
+                _ => a.to_float().partial_cmp(&b.to_float())
+            }
+        }
+    }
+}
+
+// The exact result of an operation on two exact numbers, or None if either is a float or the result doesn't fit, in
+// which case it's worked out in floats
+fn exact<F: Fn((i128, i128), (i128, i128)) -> Option<Number>>(a: Number, b: Number, operation: F) -> Option<Number> {
+    match (a.fraction(), b.fraction()) {
+        (Some(a), Some(b)) => operation(a, b),
+        _ => None
+    }
+}
+
+fn ratio(numerator: i128, denominator: i128) -> Option<Number> {
+    match Node::rational(numerator, denominator) {
+        Some(Node::Integer(i)) => Some(Number::Integer(i)),
+        Some(Node::Rational(n, d)) => Some(Number::Rational(n, d)),
+        _ => None
+    }
+}
+
+// floor/: the quotient rounded towards negative infinity, so the remainder has the divisor's sign
+#[cfg(feature = "math")]
+pub fn floor_divide(n: i64, d: i64) -> Option<(i64, i64)> {
+    let (q, r) = truncate_divide(n, d)?;
+    if r != 0 && (r < 0) != (d < 0) {
+        Some((q - 1, r + d))
+    } else {
+        Some((q, r))
+    }
+}
+
+// truncate/: the quotient rounded towards zero, so the remainder has the dividend's sign
+#[cfg(feature = "math")]
+pub fn truncate_divide(n: i64, d: i64) -> Option<(i64, i64)> {
+    match (n.checked_div(d), n.checked_rem(d)) {
+        (Some(q), Some(r)) => Some((q, r)),
+        _ => None
+    }
+}
+
+// exact-integer-sqrt: the largest s with s * s <= n, and what's left over
+#[cfg(feature = "math")]
+pub fn exact_integer_sqrt(n: i64) -> Option<(i64, i64)> {
+    if n < 0 {
+        return None
+    }
+    // the float estimate can be off by one either way for large n
+    let mut s = (n as f64).sqrt() as i64;
+    while s.checked_mul(s).is_none_or(|sq| sq > n) {
+        s -= 1;
+    }
+    while (s + 1).checked_mul(s + 1).is_some_and(|sq| sq <= n) {
+        s += 1;
+    }
+    Some((s, n - s * s))
+}
+
+// number->string: the digits of n in a radix from 2 to 36, lower case past 9, and zero-padded to at least width
+// digits (not counting a minus sign)
+#[cfg(feature = "math")]
+pub fn to_string_radix(n: i64, radix: u32, width: usize) -> String {
+    let mut magnitude = n.unsigned_abs();
+    let mut digits = Vec::new();
+    loop {
+        digits.push(char::from_digit((magnitude % radix as u64) as u32, radix).unwrap());
+        magnitude /= radix as u64;
+        if magnitude == 0 {
+            break
+        }
+    }
+    while digits.len() < width {
+        digits.push('0');
+    }
+    if n < 0 {
+        digits.push('-');
+    }
+    digits.iter().rev().collect()
+}
+
+#[test]
+fn test_numbers() {
+    use std::f64;
+
+    let (one, half) = (Number::Integer(1), Number::Float(0.5));
+    assert_eq!(one.add(one), Number::Integer(2));
+    assert_eq!(one.add(half), Number::Float(1.5));
+    assert_eq!(half.subtract(one), Number::Float(-0.5));
+    assert_eq!(Number::Integer(3).multiply(half), Number::Float(1.5));
+    assert_eq!(Number::Integer(7).divide(Number::Integer(2)), Some(Number::Rational(7, 2)));
+    assert_eq!(Number::Integer(-4).divide(Number::Integer(2)), Some(Number::Integer(-2)));
+    assert_eq!(Number::Integer(7).divide(Number::Float(2.0)), Some(Number::Float(3.5)));
+    assert_eq!(one.divide(Number::Integer(0)), None);
+    assert_eq!(one.divide(Number::Float(0.0)), Some(Number::Float(f64::INFINITY)));
+    assert_eq!(one.compare(Number::Float(1.0)), Some(Ordering::Equal));
+    assert_eq!(half.compare(one), Some(Ordering::Less));
+    assert_eq!(Number::Float(f64::NAN).compare(Number::Float(f64::NAN)), None);
+
+    let (third, sixth) = (Number::Rational(1, 3), Number::Rational(1, 6));
+    assert_eq!(third.add(sixth), Number::Rational(1, 2));
+    assert_eq!(third.add(third).add(third), Number::Integer(1));
+    assert_eq!(one.subtract(third), Number::Rational(2, 3));
+    assert_eq!(third.multiply(Number::Integer(3)), Number::Integer(1));
+    assert_eq!(third.divide(sixth), Some(Number::Integer(2)));
+    assert_eq!(one.divide(third), Some(Number::Integer(3)));
+    assert_eq!(third.divide(Number::Integer(0)), None);
+    assert_eq!(third.add(half), Number::Float(1.0 / 3.0 + 0.5));
+    assert_eq!(sixth.compare(third), Some(Ordering::Less));
+    assert_eq!(Number::Rational(1, 2).compare(half), Some(Ordering::Equal));
+    // too large to stay exact
+    let (max, min) = (Number::Integer(i64::MAX), Number::Integer(i64::MIN));
+    assert_eq!(max.add(one), Number::Float(i64::MAX as f64 + 1.0));
+    assert_eq!(min.subtract(one), Number::Float(i64::MIN as f64 - 1.0));
+    assert_eq!(max.multiply(Number::Integer(2)), Number::Float(i64::MAX as f64 * 2.0));
+    assert_eq!(min.multiply(Number::Integer(-1)), Number::Float(-(i64::MIN as f64)));
+    assert_eq!(max.add(Number::Integer(-1)).add(one), max);
+    assert_eq!(min.divide(Number::Integer(-1)), Some(Number::Float(-(i64::MIN as f64))));
+    assert_eq!(Number::Rational(1, i64::MAX).multiply(Number::Rational(1, 3)), Number::Float(1.0 / i64::MAX as f64 / 3.0));
+}
+
+#[cfg(feature = "math")]
+#[test]
+fn test_division_operators() {
+    assert_eq!(floor_divide(5, 2), Some((2, 1)));
+    assert_eq!(floor_divide(-5, 2), Some((-3, 1)));
+    assert_eq!(floor_divide(5, -2), Some((-3, -1)));
+    assert_eq!(floor_divide(-5, -2), Some((2, -1)));
+    assert_eq!(truncate_divide(5, 2), Some((2, 1)));
+    assert_eq!(truncate_divide(-5, 2), Some((-2, -1)));
+    assert_eq!(truncate_divide(5, -2), Some((-2, 1)));
+    assert_eq!(truncate_divide(-5, -2), Some((2, -1)));
+    assert_eq!(floor_divide(1, 0), None);
+    assert_eq!(truncate_divide(i64::MIN, -1), None);
+    assert_eq!(exact_integer_sqrt(0), Some((0, 0)));
+    assert_eq!(exact_integer_sqrt(17), Some((4, 1)));
+    assert_eq!(exact_integer_sqrt(i64::MAX), Some((3037000499, 5928526806)));
+    assert_eq!(exact_integer_sqrt(-1), None);
+    assert_eq!(to_string_radix(255, 16, 0), "ff");
+    assert_eq!(to_string_radix(5, 2, 8), "00000101");
+    assert_eq!(to_string_radix(-5, 2, 4), "-0101");
+    assert_eq!(to_string_radix(0, 36, 0), "0");
+    assert_eq!(to_string_radix(35, 36, 0), "z");
+    assert_eq!(to_string_radix(i64::MIN, 2, 0), format!("-1{}", "0".repeat(63)));
+}
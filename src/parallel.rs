@@ -0,0 +1,103 @@
+// The thread pool behind par-map. Values are reference counted and can't cross threads, so the procedure, the
+// definitions it may need and every element are sent to the workers as source (parser nodes). Each worker runs
+// its own interpreter, and the results come back as nodes too.
+
+use parser::Node;
+use random;
+
+use std::thread;
+
+// Worker threads get as much stack as the main thread, since both interpreters recurse on deeply nested code
+const STACK_SIZE: usize = 8 * 1024 * 1024;
+
+// What a worker thread needs from an interpreter
+pub trait Worker: Sized {
+    // A fresh interpreter with the given definitions already run
+    fn start(prelude: &[Node]) -> Result<Self, String>;
+
+    // Apply the procedure (an expression evaluating to one) to an element, returning the result as data
+    fn call(&self, procedure: &Node, element: Node) -> Result<Node, String>;
+}
+
+// Map the procedure over the elements, split into one contiguous chunk per core. The results are in the same
+// order as the elements; if any calls fail, the error of the earliest one is returned.
+pub fn map<W: Worker + 'static>(prelude: Vec<Node>, procedure: Node, elements: Vec<Node>) -> Result<Vec<Node>, String> {
+    let threads = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    let chunk_size = elements.len().div_ceil(threads);
+    let total = elements.len();
+
+    let mut chunks = Vec::new();
+    let mut rest = elements;
+    while !rest.is_empty() {
+        let tail = rest.split_off(if chunk_size < rest.len() { chunk_size } else { rest.len() });
+        chunks.push(rest);
+        rest = tail;
+    }
+
+    // Under a fixed seed, each worker gets its own seed derived from it, so par-map runs repeat too
+    let seed = random::seed();
+    let mut workers = Vec::new();
+    for (index, chunk) in chunks.into_iter().enumerate() {
+        let prelude = prelude.clone();
+        let procedure = procedure.clone();
+        let spawned = thread::Builder::new().stack_size(STACK_SIZE).spawn(move || -> Result<Vec<Node>, String> {
+            if let Some(seed) = seed {
+                random::set_seed(seed.wrapping_add(index as u64 + 1));
+            }
+            let worker = try!(W::start(&prelude));
+            chunk.into_iter().map(|element| worker.call(&procedure, element)).collect()
+        });
+        match spawned {
+            Ok(handle) => workers.push(handle),
+            Err(e) => return Err(format!("Couldn't start a par-map worker: {}", e))
+        }
+    }
+
+    let mut results = Vec::with_capacity(total);
+    for worker in workers.into_iter() {
+        match worker.join() {
+            Ok(Ok(chunk)) => results.extend(chunk),
+            Ok(Err(e)) => return Err(e),
+            Err(_) => return Err("A par-map worker panicked".to_string())
+        }
+    }
+    Ok(results)
+}
+
+// The source for a lambda, wrapped in a let binding the variables it closed over (if any)
+pub fn lambda(arg_names: &[String], body: Vec<Node>, captured: Vec<(String, Node)>) -> Node {
+    let mut lambda = vec![Node::Identifier("lambda".to_string()),
+                          Node::List(arg_names.iter().map(|n| Node::Identifier(n.clone())).collect())];
+    lambda.extend(body);
+    if captured.is_empty() {
+        return Node::List(lambda)
+    }
+    let bindings = captured.into_iter().map(|(name, value)| Node::List(vec![Node::Identifier(name), quote(value)])).collect();
+    Node::List(vec![Node::Identifier("let".to_string()), Node::List(bindings), Node::List(lambda)])
+}
+
+pub fn quote(node: Node) -> Node {
+    Node::List(vec![Node::Identifier("quote".to_string()), node])
+}
+
+pub fn define(name: &str, value: Node) -> Node {
+    Node::List(vec![Node::Identifier("define".to_string()), Node::Identifier(name.to_string()), value])
+}
+
+pub fn define_syntax_rule(name: &str, arg_names: &[String], body: Vec<Node>) -> Node {
+    let mut pattern = vec![Node::Identifier(name.to_string())];
+    pattern.extend(arg_names.iter().map(|n| Node::Identifier(n.clone())));
+    let mut rule = vec![Node::Identifier("define-syntax-rule".to_string()), Node::List(pattern)];
+    rule.extend(body);
+    Node::List(rule)
+}
+
+#[test]
+fn test_parallel_lambda_source() {
+    let id = |s: &str| Node::Identifier(s.to_string());
+    let body = vec![Node::List(vec![id("+"), id("x"), id("n")])];
+    let plain = Node::List(vec![id("lambda"), Node::List(vec![id("x")]), body[0].clone()]);
+    assert_eq!(lambda(&["x".to_string()], body.clone(), Vec::new()), plain);
+    assert_eq!(lambda(&["x".to_string()], body, vec![("n".to_string(), Node::Integer(1))]),
+               Node::List(vec![id("let"), Node::List(vec![Node::List(vec![id("n"), quote(Node::Integer(1))])]), plain]));
+}
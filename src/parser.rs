@@ -1,23 +1,119 @@
 use lexer::*;
+use printer::{self, Style};
 
+use std::collections::HashMap;
 use std::fmt;
 use std::slice;
 
+/// Parses a token stream (as produced by `lexer::tokenize`) into a list of top-level nodes.
 pub fn parse(tokens: &Vec<Token>) -> Result<Vec<Node>, ParseError> {
     Parser::parse(tokens)
 }
 
+/// Parses a token stream without stopping at the first problem: returns a best-effort AST (unclosed lists are
+/// closed at the end of input, stray close parens and dangling quotes are dropped) along with every error found.
+pub fn parse_recovering(tokens: &[Token]) -> (Vec<Node>, Vec<ParseError>) {
+    Parser::parse_recovering(tokens)
+}
+
+/// Tokenizes and parses source text in one step, without evaluating anything.
+pub fn parse_str(s: &str) -> Result<Vec<Node>, ReadError> {
+    parse_str_with(s, &ReaderConfig::default())
+}
+
+/// Like `parse_str`, with control over which syntax the reader accepts.
+pub fn parse_str_with(s: &str, config: &ReaderConfig) -> Result<Vec<Node>, ReadError> {
+    let tokens = match tokenize_with(s, config) {
+        Ok(tokens) => tokens,
+        Err(e) => return Err(ReadError::Syntax(e))
+    };
+    match parse(&tokens) {
+        Ok(nodes) => Ok(nodes),
+        Err(e) => Err(ReadError::Parse(e))
+    }
+}
+
 #[derive(PartialEq, Clone, Debug)]
 pub enum Node {
     Identifier(String),
     Integer(i64),
+    /// An exact fraction in lowest terms, whose denominator is more than 1
+    Rational(i64, i64),
+    Float(f64),
     Boolean(bool),
+    Character(char),
     String(String),
     List(Vec<Node>),
+    /// An improper list, (datum ... . datum), with at least one item before the dot and a tail that isn't a list
+    DottedList(Vec<Node>, Box<Node>),
+    /// A vector literal, #(datum ...)
+    Vector(Vec<Node>),
+    /// A bytevector literal, #u8(byte ...)
+    Bytevector(Vec<u8>),
+    EofObject,
+    Keyword(String),
+    /// A box literal, #&datum, with the number of its datum label if it has one (#n=#&datum)
+    Box(Option<u32>, Box<Node>),
+    /// A list, dotted list, vector or string with a datum label, #n=datum, so that references to n share it
+    Labelled(u32, Box<Node>),
+    /// #n#, a reference back to the box or datum labelled n in the same top-level datum. A vector can contain a
+    /// reference to itself, but a list can't, since lists can't be changed in place to close the cycle. References to
+    /// labelled atoms are replaced by copies of them as they're read.
+    Reference(u32),
+}
+
+impl Node {
+    /// The exact number numerator/denominator: an integer if it's a whole number, otherwise a rational in lowest
+    /// terms. None if the denominator is 0 or the result doesn't fit.
+    pub fn rational(numerator: i128, denominator: i128) -> Option<Node> {
+        if denominator == 0 {
+            return None
+        }
+        let (mut a, mut b) = (numerator.unsigned_abs(), denominator.unsigned_abs());
+        while b != 0 {
+            let r = a % b;
+            a = b;
+            b = r;
+        }
+        let sign = if denominator < 0 { -1 } else { 1 };
+        let (n, d) = (sign * numerator / a as i128, sign * denominator / a as i128);
+        if n < i64::MIN as i128 || n > i64::MAX as i128 || d > i64::MAX as i128 {
+            None
+        } else if d == 1 {
+            Some(Node::Integer(n as i64))
+        } else {
+            Some(Node::Rational(n as i64, d as i64))
+        }
+    }
+
+    /// The list of items followed by tail: a proper list if tail is one, so (a . (b)) is (a b), and otherwise a
+    /// dotted list, whose items take in those of a dotted tail, so (a . (b . c)) is (a b . c)
+    pub fn dotted(mut items: Vec<Node>, tail: Node) -> Node {
+        match tail {
+            Node::List(rest) => {
+                items.extend(rest);
+                Node::List(items)
+            },
+            Node::DottedList(rest, tail) => {
+                items.extend(rest);
+                Node::DottedList(items, tail)
+            },
+            _ if items.is_empty() => tail,
+            tail => Node::DottedList(items, Box::new(tail))
+        }
+    }
 }
 
 pub struct ParseError {
     message: String,
+    // A list was still open at the end of the input, so more input could complete it
+    incomplete: bool,
+}
+
+impl ParseError {
+    pub fn is_incomplete(&self) -> bool {
+        self.incomplete
+    }
 }
 
 impl fmt::Display for ParseError {
@@ -31,25 +127,101 @@ impl fmt::Debug for ParseError {
     }
 }
 
+// Either stage of reading source text can fail
+pub enum ReadError {
+    Syntax(SyntaxError),
+    Parse(ParseError),
+}
+
+impl ReadError {
+    /// Whether the input was cut off rather than malformed, e.g. an unclosed list or string, so a REPL can read
+    /// another line and try again.
+    pub fn is_incomplete(&self) -> bool {
+        match *self {
+            ReadError::Syntax(ref e) => e.is_incomplete(),
+            ReadError::Parse(ref e) => e.is_incomplete(),
+        }
+    }
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ReadError::Syntax(ref e) => write!(f, "{}", e),
+            ReadError::Parse(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+impl fmt::Debug for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
 macro_rules! parse_error {
     ($($arg:tt)*) => (
-        return Err(ParseError { message: format!($($arg)*)})
+        return Err(ParseError { message: message!($($arg)*), incomplete: false })
+    )
+}
+
+// In recovery mode, record the error and let the caller carry on; otherwise bail out
+macro_rules! recoverable_parse_error {
+    ($parser:ident, $($arg:tt)*) => (
+        if $parser.recover {
+            $parser.diagnostics.push(ParseError { message: message!($($arg)*), incomplete: false })
+        } else {
+            parse_error!($($arg)*)
+        }
     )
 }
 
 struct Parser<'a> {
     tokens: slice::Iter<'a, Token>,
+    recover: bool,
+    diagnostics: Vec<ParseError>,
+    // The close token expected for each list currently open
+    closers: Vec<Token>,
+    // The datum labels seen so far in the current top-level datum
+    labels: HashMap<u32, Label>,
+}
+
+enum Label {
+    // A box or vector, which can be referred to even from inside itself
+    Shared,
+    // Still reading the labelled list, so it can't be referred to yet
+    Pending,
+    // A list, dotted list or string, which references share
+    Labelled,
+    // Anything else, which references copy
+    Datum(Node),
 }
 
 impl<'a> Parser<'a> {
     fn parse(tokens: &Vec<Token>) -> Result<Vec<Node>, ParseError> {
-        let mut parser = Parser { tokens: tokens.iter() };
+        let mut parser = Parser { tokens: tokens.iter(), recover: false, diagnostics: Vec::new(), closers: Vec::new(),
+                                 labels: HashMap::new() };
         parser.parse_nodes(0)
     }
 
+    fn parse_recovering(tokens: &[Token]) -> (Vec<Node>, Vec<ParseError>) {
+        let mut parser = Parser { tokens: tokens.iter(), recover: true, diagnostics: Vec::new(), closers: Vec::new(),
+                                 labels: HashMap::new() };
+        match parser.parse_nodes(0) {
+            Ok(nodes) => (nodes, parser.diagnostics),
+            Err(e) => {
+                parser.diagnostics.push(e);
+                (Vec::new(), parser.diagnostics)
+            }
+        }
+    }
+
     fn parse_nodes(&mut self, depth: u32) -> Result<Vec<Node>, ParseError> {
         let mut vec = Vec::new();
         loop {
+            if depth == 0 {
+                // labels are local to a top-level datum
+                self.labels.clear();
+            }
             match try!(self.parse_node(depth)) {
                 Some(node) => {
                     vec.push(node);
@@ -66,14 +238,47 @@ impl<'a> Parser<'a> {
             Some(token) => {
                 match *token {
                     Token::OpenParen => {
-                        let inner = try!(self.parse_nodes(depth + 1));
-                        Ok(Some(Node::List(inner)))
+                        self.parse_list(depth, Token::CloseParen)
+                    },
+                    Token::OpenBracket => {
+                        self.parse_list(depth, Token::CloseBracket)
+                    },
+                    Token::OpenVector => {
+                        match try!(self.parse_list(depth, Token::CloseParen)) {
+                            Some(Node::List(items)) => Ok(Some(Node::Vector(items))),
+                            _ => {
+                                recoverable_parse_error!(self, "Unexpected . in a vector, depth: {}", depth);
+                                Ok(Some(Node::Vector(Vec::new())))
+                            }
+                        }
+                    },
+                    Token::OpenBytevector => {
+                        let items = match try!(self.parse_list(depth, Token::CloseParen)) {
+                            Some(Node::List(items)) => items,
+                            _ => {
+                                recoverable_parse_error!(self, "Unexpected . in a bytevector, depth: {}", depth);
+                                Vec::new()
+                            }
+                        };
+                        let mut bytes = Vec::with_capacity(items.len());
+                        for item in items.into_iter() {
+                            match item {
+                                Node::Integer(n) if (0..=255).contains(&n) => bytes.push(n as u8),
+                                item => recoverable_parse_error!(self, "Expected a byte (an integer from 0 to 255) in a bytevector: {}",
+                                                                 printer::print(&item, Style::Write))
+                            }
+                        }
+                        Ok(Some(Node::Bytevector(bytes)))
                     },
-                    Token::CloseParen => {
+                    Token::CloseParen | Token::CloseBracket => {
                         if depth > 0 {
+                            if self.closers.last() != Some(token) {
+                                recoverable_parse_error!(self, "Mismatched close {}, depth: {}", delimiter_name(token), depth);
+                            }
                             Ok(None)
                         } else {
-                            parse_error!("Unexpected close paren, depth: {}", depth)
+                            recoverable_parse_error!(self, "Unexpected close {}, depth: {}", delimiter_name(token), depth);
+                            self.parse_node(depth)
                         }
                     },
                     Token::Quote => {
@@ -82,7 +287,10 @@ impl<'a> Parser<'a> {
                                 let quoted = Node::List(vec![Node::Identifier("quote".to_string()), inner]);
                                 Ok(Some(quoted))
                             },
-                            None => parse_error!("Missing quoted value, depth: {}", depth)
+                            None => {
+                                recoverable_parse_error!(self, "Missing quoted value, depth: {}", depth);
+                                Ok(None)
+                            }
                         }
                     },
                     Token::Quasiquote => {
@@ -91,7 +299,10 @@ impl<'a> Parser<'a> {
                                 let quoted = Node::List(vec![Node::Identifier("quasiquote".to_string()), inner]);
                                 Ok(Some(quoted))
                             },
-                            None => parse_error!("Missing quasiquoted value, depth: {}", depth)
+                            None => {
+                                recoverable_parse_error!(self, "Missing quasiquoted value, depth: {}", depth);
+                                Ok(None)
+                            }
                         }
                     }
                     Token::Unquote => {
@@ -100,31 +311,190 @@ impl<'a> Parser<'a> {
                                 let quoted = Node::List(vec![Node::Identifier("unquote".to_string()), inner]);
                                 Ok(Some(quoted))
                             },
-                            None => parse_error!("Missing unquoted value, depth: {}", depth)
+                            None => {
+                                recoverable_parse_error!(self, "Missing unquoted value, depth: {}", depth);
+                                Ok(None)
+                            }
+                        }
+                    }
+                    Token::UnquoteSplicing => {
+                        match try!(self.parse_node(depth)) {
+                            Some(inner) => {
+                                let quoted = Node::List(vec![Node::Identifier("unquote-splicing".to_string()), inner]);
+                                Ok(Some(quoted))
+                            },
+                            None => {
+                                recoverable_parse_error!(self, "Missing unquote-spliced value, depth: {}", depth);
+                                Ok(None)
+                            }
                         }
                     }
+                    Token::Dot => {
+                        // parse_list reads the dots that belong to lists
+                        recoverable_parse_error!(self, "Unexpected . outside a list, depth: {}", depth);
+                        self.parse_node(depth)
+                    },
                     Token::Identifier(ref val) => {
                         Ok(Some(Node::Identifier(val.clone())))
                     },
                     Token::Integer(ref val) => {
                         Ok(Some(Node::Integer(val.clone())))
                     },
+                    Token::Rational(n, d) => {
+                        match Node::rational(n as i128, d as i128) {
+                            Some(node) => Ok(Some(node)),
+                            None => {
+                                recoverable_parse_error!(self, "Division by zero in {}/{}", n, d);
+                                Ok(None)
+                            }
+                        }
+                    },
+                    Token::Float(val) => {
+                        Ok(Some(Node::Float(val)))
+                    },
                     Token::Boolean(ref val) => {
                         Ok(Some(Node::Boolean(val.clone())))
                     },
+                    Token::Character(val) => {
+                        Ok(Some(Node::Character(val)))
+                    },
                     Token::String(ref val) => {
                         Ok(Some(Node::String(val.clone())))
+                    },
+                    Token::EofObject => {
+                        Ok(Some(Node::EofObject))
+                    },
+                    Token::Keyword(ref val) => {
+                        Ok(Some(Node::Keyword(val.clone())))
+                    },
+                    Token::Datum(ref node) => {
+                        Ok(Some(node.clone()))
+                    },
+                    Token::Box => {
+                        self.parse_box(depth, None)
+                    },
+                    Token::Label(n) => {
+                        match self.tokens.as_slice().first() {
+                            Some(&Token::Box) => {
+                                self.tokens.next();
+                                self.labels.insert(n, Label::Shared);
+                                return self.parse_box(depth, Some(n))
+                            },
+                            Some(&Token::OpenVector) => { self.labels.insert(n, Label::Shared); },
+                            _ => { self.labels.insert(n, Label::Pending); }
+                        }
+                        match try!(self.parse_node(depth)) {
+                            Some(node) => match node {
+                                Node::List(_) | Node::DottedList(..) | Node::Vector(_) | Node::String(_) => {
+                                    self.labels.insert(n, Label::Labelled);
+                                    Ok(Some(Node::Labelled(n, Box::new(node))))
+                                },
+                                node => {
+                                    self.labels.insert(n, Label::Datum(node.clone()));
+                                    Ok(Some(node))
+                                }
+                            },
+                            None => {
+                                recoverable_parse_error!(self, "Missing labelled value for #{}=, depth: {}", n, depth);
+                                Ok(None)
+                            }
+                        }
+                    },
+                    Token::Reference(n) => {
+                        match self.labels.get(&n) {
+                            Some(&Label::Shared) | Some(&Label::Labelled) => return Ok(Some(Node::Reference(n))),
+                            Some(Label::Datum(node)) => return Ok(Some(node.clone())),
+                            Some(&Label::Pending) => {
+                                recoverable_parse_error!(self, "#{}# refers to a list that contains it, but lists can't contain themselves (only boxes and vectors can), depth: {}", n, depth)
+                            },
+                            None => recoverable_parse_error!(self, "Undefined datum label #{}#, depth: {}", n, depth)
+                        }
+                        self.parse_node(depth)
+                    },
+                    Token::DatumComment => {
+                        // read the datum the comment covers, then whatever follows it in its place
+                        if try!(self.parse_node(depth)).is_none() {
+                            if depth > 0 {
+                                recoverable_parse_error!(self, "Missing datum to comment out after #;, depth: {}", depth);
+                            } else {
+                                // only the end of the input ends the top level, and more input could finish it
+                                let error = ParseError { message: message!("Missing datum to comment out after #;, depth: {}", depth),
+                                                         incomplete: true };
+                                if !self.recover {
+                                    return Err(error)
+                                }
+                                self.diagnostics.push(error);
+                            }
+                            return Ok(None)
+                        }
+                        self.parse_node(depth)
                     }
                 }
             },
             None => {
-                if depth == 0 {
-                    Ok(None)
-                } else {
-                    parse_error!("Unexpected end of input, depth: {}", depth)
+                if depth > 0 {
+                    let error = ParseError { message: message!("Unexpected end of input, depth: {}", depth), incomplete: true };
+                    if !self.recover {
+                        return Err(error)
+                    }
+                    self.diagnostics.push(error);
+                }
+                Ok(None)
+            }
+        }
+    }
+
+    fn parse_box(&mut self, depth: u32, label: Option<u32>) -> Result<Option<Node>, ParseError> {
+        match try!(self.parse_node(depth)) {
+            Some(inner) => Ok(Some(Node::Box(label, Box::new(inner)))),
+            None => {
+                recoverable_parse_error!(self, "Missing boxed value, depth: {}", depth);
+                Ok(None)
+            }
+        }
+    }
+
+    // The items up to closer, and the tail after a dot if there is one
+    fn parse_list(&mut self, depth: u32, closer: Token) -> Result<Option<Node>, ParseError> {
+        self.closers.push(closer);
+        let mut items = Vec::new();
+        let mut tail = None;
+        loop {
+            if self.tokens.as_slice().first() == Some(&Token::Dot) {
+                self.tokens.next();
+                if items.is_empty() {
+                    recoverable_parse_error!(self, "Expected a datum before . in a list, depth: {}", depth + 1);
+                }
+                match try!(self.parse_node(depth + 1)) {
+                    Some(node) => {
+                        tail = Some(node);
+                        // the list has to end after its tail
+                        if try!(self.parse_node(depth + 1)).is_some() {
+                            recoverable_parse_error!(self, "Expected the list to end after the datum after ., depth: {}", depth + 1);
+                            while try!(self.parse_node(depth + 1)).is_some() {}
+                        }
+                    },
+                    None => recoverable_parse_error!(self, "Expected a datum after . in a list, depth: {}", depth + 1)
                 }
+                break
+            }
+            match try!(self.parse_node(depth + 1)) {
+                Some(node) => items.push(node),
+                None => break
             }
         }
+        self.closers.pop();
+        Ok(Some(match tail {
+            Some(tail) => Node::dotted(items, tail),
+            None => Node::List(items)
+        }))
+    }
+}
+
+fn delimiter_name(token: &Token) -> &'static str {
+    match *token {
+        Token::CloseBracket => "bracket",
+        _ => "paren"
     }
 }
 
@@ -134,6 +504,14 @@ fn test_parser_simple() {
                vec![Node::List(vec![Node::Identifier("+".to_string())])]);
 }
 
+#[test]
+fn test_parser_rationals() {
+    assert_eq!(parse_str("2/4 -6/3 0/5").unwrap(), vec![Node::Rational(1, 2), Node::Integer(-2), Node::Integer(0)]);
+    assert_eq!(parse_str("1/0").err().unwrap().to_string(), "ParseError: Division by zero in 1/0");
+    assert_eq!(Node::rational(3, -6), Some(Node::Rational(-1, 2)));
+    assert_eq!(Node::rational(1 << 70, 3), None);
+}
+
 #[test]
 fn test_parser_nested() {
     assert_eq!(parse(&vec![Token::OpenParen, Token::Identifier("+".to_string()), Token::OpenParen, Token::Identifier("+".to_string()), Token::Integer(1), Token::OpenParen, Token::Identifier("+".to_string()), Token::Integer(3), Token::Integer(4), Token::CloseParen, Token::CloseParen, Token::Integer(5), Token::CloseParen]).unwrap(),
@@ -154,6 +532,93 @@ fn test_parser_quasiquoting() {
                vec![Node::List(vec![Node::Identifier("quasiquote".to_string()), Node::List(vec![Node::List(vec![Node::Identifier("unquote".to_string()), Node::Identifier("a".to_string())])])])]);
     assert_eq!(parse(&vec![Token::Quasiquote, Token::OpenParen, Token::Unquote, Token::Identifier("a".to_string()), Token::Identifier("b".to_string()), Token::Unquote, Token::Identifier("c".to_string()), Token::CloseParen]).unwrap(),
                vec![Node::List(vec![Node::Identifier("quasiquote".to_string()), Node::List(vec![Node::List(vec![Node::Identifier("unquote".to_string()), Node::Identifier("a".to_string())]), Node::Identifier("b".to_string()), Node::List(vec![Node::Identifier("unquote".to_string()), Node::Identifier("c".to_string())])])])]);
+    assert_eq!(parse(&vec![Token::Quasiquote, Token::OpenParen, Token::UnquoteSplicing, Token::Identifier("a".to_string()), Token::CloseParen]).unwrap(),
+               vec![Node::List(vec![Node::Identifier("quasiquote".to_string()), Node::List(vec![Node::List(vec![Node::Identifier("unquote-splicing".to_string()), Node::Identifier("a".to_string())])])])]);
+    assert_eq!(parse_str("`(a ,@)").err().unwrap().to_string(),
+               "ParseError: Missing unquote-spliced value, depth: 1");
+}
+
+#[test]
+fn test_parser_parse_str() {
+    assert_eq!(parse_str("(+ 1 '(a \"b\"))").unwrap(),
+               vec![Node::List(vec![Node::Identifier("+".to_string()), Node::Integer(1), Node::List(vec![Node::Identifier("quote".to_string()), Node::List(vec![Node::Identifier("a".to_string()), Node::String("b".to_string())])])])]);
+    assert_eq!(parse_str("(+ 1 2").err().unwrap().to_string(),
+               "ParseError: Unexpected end of input, depth: 1");
+    assert_eq!(parse_str("(22+)").err().unwrap().to_string(),
+               "SyntaxError: Unexpected character when looking for a delimiter: + (line: 1, column: 4)");
+}
+
+#[test]
+fn test_parser_incomplete() {
+    assert!(parse_str("(define (f x)\n  (+ x").err().unwrap().is_incomplete());
+    assert!(parse_str("(display \"abc").err().unwrap().is_incomplete());
+    assert!(!parse_str("(+ 1 2))").err().unwrap().is_incomplete());
+    assert!(!parse_str("(22+").err().unwrap().is_incomplete());
+}
+
+#[test]
+fn test_parser_datum_comments() {
+    let a = Node::Identifier("a".to_string());
+    assert_eq!(parse_str("#;(b c) a (a #;b) (#;#;1 2 a) #;'b").unwrap(),
+               vec![a.clone(), Node::List(vec![a.clone()]), Node::List(vec![a.clone()])]);
+    assert_eq!(parse_str("(a #;)").err().unwrap().to_string(), "ParseError: Missing datum to comment out after #;, depth: 1");
+    // at the top level, the datum may be on the next line
+    assert!(parse_str("a #;").err().unwrap().is_incomplete());
+}
+
+#[test]
+fn test_parser_vectors() {
+    assert_eq!(parse_str("#(1 (a) #()) '#(b)").unwrap(),
+               vec![Node::Vector(vec![Node::Integer(1), Node::List(vec![Node::Identifier("a".to_string())]), Node::Vector(vec![])]),
+                    Node::List(vec![Node::Identifier("quote".to_string()), Node::Vector(vec![Node::Identifier("b".to_string())])])]);
+    assert_eq!(parse_str("#(1 2]").err().unwrap().to_string(), "ParseError: Mismatched close bracket, depth: 1");
+    assert!(parse_str("#(1 (2)").err().unwrap().is_incomplete());
+}
+
+#[test]
+fn test_parser_dotted_lists() {
+    let id = |s: &str| Node::Identifier(s.to_string());
+    assert_eq!(parse_str("(a . b) (a b . c) '(1 . 2)").unwrap(),
+               vec![Node::DottedList(vec![id("a")], Box::new(id("b"))),
+                    Node::DottedList(vec![id("a"), id("b")], Box::new(id("c"))),
+                    Node::List(vec![id("quote"), Node::DottedList(vec![Node::Integer(1)], Box::new(Node::Integer(2)))])]);
+    // a list after the dot is just more items
+    assert_eq!(parse_str("(a . (b)) (a . ()) (a . (b . c)) [a . b]").unwrap(),
+               vec![Node::List(vec![id("a"), id("b")]), Node::List(vec![id("a")]),
+                    Node::DottedList(vec![id("a"), id("b")], Box::new(id("c"))),
+                    Node::DottedList(vec![id("a")], Box::new(id("b")))]);
+    assert_eq!(parse_str("(. a)").err().unwrap().to_string(), "ParseError: Expected a datum before . in a list, depth: 1");
+    assert_eq!(parse_str("(a .)").err().unwrap().to_string(), "ParseError: Expected a datum after . in a list, depth: 1");
+    assert_eq!(parse_str("(a . b c)").err().unwrap().to_string(), "ParseError: Expected the list to end after the datum after ., depth: 1");
+    assert_eq!(parse_str("a . b").err().unwrap().to_string(), "ParseError: Unexpected . outside a list, depth: 0");
+    assert_eq!(parse_str("#(a . b)").err().unwrap().to_string(), "ParseError: Unexpected . in a vector, depth: 0");
+    assert!(parse_str("(a . ").err().unwrap().is_incomplete());
+    assert!(parse_str("(a . b").err().unwrap().is_incomplete());
+}
+
+#[test]
+fn test_parser_bytevectors() {
+    assert_eq!(parse_str("#u8(0 7 255) '#u8()").unwrap(),
+               vec![Node::Bytevector(vec![0, 7, 255]),
+                    Node::List(vec![Node::Identifier("quote".to_string()), Node::Bytevector(vec![])])]);
+    assert_eq!(parse_str("#u8(1 256)").err().unwrap().to_string(), "ParseError: Expected a byte (an integer from 0 to 255) in a bytevector: 256");
+    assert_eq!(parse_str("#u8(-1)").err().unwrap().to_string(), "ParseError: Expected a byte (an integer from 0 to 255) in a bytevector: -1");
+    assert_eq!(parse_str("#u8(a (1))").err().unwrap().to_string(), "ParseError: Expected a byte (an integer from 0 to 255) in a bytevector: a");
+    assert!(parse_str("#u8(1 2").err().unwrap().is_incomplete());
+}
+
+#[test]
+fn test_parser_datum_labels() {
+    let a = Node::Identifier("a".to_string());
+    assert_eq!(parse_str("#0=#&(a #0#) (#1=(a) #1#) #2=#(a #2#) (#3=5 #3#)").unwrap(),
+               vec![Node::Box(Some(0), Box::new(Node::List(vec![a.clone(), Node::Reference(0)]))),
+                    Node::List(vec![Node::Labelled(1, Box::new(Node::List(vec![a.clone()]))), Node::Reference(1)]),
+                    Node::Labelled(2, Box::new(Node::Vector(vec![a.clone(), Node::Reference(2)]))),
+                    Node::List(vec![Node::Integer(5), Node::Integer(5)])]);
+    // labels only last until the end of their top-level datum
+    assert_eq!(parse_str("#0=a #0#").err().unwrap().to_string(), "ParseError: Undefined datum label #0#, depth: 0");
+    assert_eq!(parse_str("#0=(a #0#)").err().unwrap().to_string(),
+               "ParseError: #0# refers to a list that contains it, but lists can't contain themselves (only boxes and vectors can), depth: 1");
 }
 
 #[test]
@@ -167,3 +632,29 @@ fn test_parser_bad_syntax() {
     assert_eq!(parse(&vec![Token::OpenParen, Token::OpenParen, Token::CloseParen, Token::OpenParen, Token::OpenParen, Token::CloseParen]).err().unwrap().to_string(),
                "ParseError: Unexpected end of input, depth: 2");
 }
+
+#[test]
+fn test_parser_brackets() {
+    assert_eq!(parse(&vec![Token::OpenParen, Token::OpenBracket, Token::Identifier("a".to_string()), Token::CloseBracket, Token::CloseParen]).unwrap(),
+               vec![Node::List(vec![Node::List(vec![Node::Identifier("a".to_string())])])]);
+    assert_eq!(parse(&vec![Token::OpenBracket, Token::Integer(1), Token::CloseParen]).err().unwrap().to_string(),
+               "ParseError: Mismatched close paren, depth: 1");
+    assert_eq!(parse(&vec![Token::OpenParen, Token::Integer(1), Token::CloseBracket]).err().unwrap().to_string(),
+               "ParseError: Mismatched close bracket, depth: 1");
+    assert_eq!(parse(&vec![Token::CloseBracket]).err().unwrap().to_string(),
+               "ParseError: Unexpected close bracket, depth: 0");
+}
+
+#[test]
+fn test_parser_recovering() {
+    let (nodes, errors) = parse_recovering(&[Token::CloseParen, Token::Integer(1), Token::OpenParen, Token::Identifier("a".to_string()), Token::Quote, Token::CloseParen, Token::OpenParen, Token::Integer(2)]);
+    assert_eq!(nodes,
+               vec![Node::Integer(1), Node::List(vec![Node::Identifier("a".to_string())]), Node::List(vec![Node::Integer(2)])]);
+    let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+    assert_eq!(messages,
+               vec!["ParseError: Unexpected close paren, depth: 0", "ParseError: Missing quoted value, depth: 1", "ParseError: Unexpected end of input, depth: 1"]);
+
+    let (nodes, errors) = parse_recovering(&[Token::OpenParen, Token::Integer(1), Token::CloseParen]);
+    assert_eq!(nodes, vec![Node::List(vec![Node::Integer(1)])]);
+    assert!(errors.is_empty());
+}
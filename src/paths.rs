@@ -0,0 +1,76 @@
+// Path handling for the path-* builtins of both interpreters, on paths as strings. It's Rust's std::path, so it
+// follows the platform: path-join separates with \ on Windows, where / and \ both separate components and a drive
+// letter is part of an absolute path.
+
+use std::env;
+use std::path::{Path, PathBuf};
+
+// Each part is joined on to the ones before it, unless it's absolute, in which case it replaces them
+pub fn join(parts: &[&str]) -> String {
+    let mut path = PathBuf::new();
+    for part in parts.iter() {
+        path.push(part);
+    }
+    path.to_string_lossy().into_owned()
+}
+
+// Everything before the last component, which is "" for a name on its own, or None for a root or an empty path
+pub fn directory(path: &str) -> Option<String> {
+    Path::new(path).parent().map(|p| p.to_string_lossy().into_owned())
+}
+
+// The last component, or None if the path ends in .. or is a root
+pub fn filename(path: &str) -> Option<String> {
+    Path::new(path).file_name().map(|p| p.to_string_lossy().into_owned())
+}
+
+// What follows the last dot of the last component, or None if there's no dot (other than at its start)
+pub fn extension(path: &str) -> Option<String> {
+    Path::new(path).extension().map(|p| p.to_string_lossy().into_owned())
+}
+
+pub fn is_absolute(path: &str) -> bool {
+    Path::new(path).is_absolute()
+}
+
+// The path with a leading ~ replaced by the user's home directory. ~user isn't supported, so it's left as it is.
+pub fn expand_user(path: &str) -> Result<String, String> {
+    let rest = if path == "~" {
+        ""
+    } else if path.starts_with("~/") || (cfg!(windows) && path.starts_with("~\\")) {
+        &path[2..]
+    } else {
+        return Ok(path.to_string())
+    };
+    // USERPROFILE is where Windows keeps it
+    match env::var_os("HOME").or_else(|| env::var_os("USERPROFILE")) {
+        Some(home) => Ok(if rest.is_empty() {
+            home.to_string_lossy().into_owned()
+        } else {
+            Path::new(&home).join(rest).to_string_lossy().into_owned()
+        }),
+        None => Err(message!("Couldn't expand {}: the home directory isn't known", path))
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_paths() {
+    assert_eq!(join(&["src", "lib", "list.scm"]), "src/lib/list.scm");
+    assert_eq!(join(&["src/", "/etc", "hosts"]), "/etc/hosts");
+    assert_eq!(directory("src/lib/list.scm"), Some("src/lib".to_string()));
+    assert_eq!(directory("list.scm"), Some("".to_string()));
+    assert_eq!(directory("/"), None);
+    assert_eq!(filename("src/lib/list.scm"), Some("list.scm".to_string()));
+    assert_eq!(filename("src/.."), None);
+    assert_eq!(extension("archive.tar.gz"), Some("gz".to_string()));
+    assert_eq!(extension(".profile"), None);
+    assert!(is_absolute("/usr"));
+    assert!(!is_absolute("usr"));
+
+    let home = env::var("HOME").unwrap();
+    assert_eq!(expand_user("~/notes.txt"), Ok(format!("{}/notes.txt", home)));
+    assert_eq!(expand_user("~"), Ok(home));
+    assert_eq!(expand_user("~bob/notes.txt"), Ok("~bob/notes.txt".to_string()));
+    assert_eq!(expand_user("notes/~"), Ok("notes/~".to_string()));
+}
@@ -0,0 +1,154 @@
+// Packs of builtins written in Rust, which other crates can ship for either interpreter. A plugin adds its builtins
+// and constants to an Environment, and Interpreter::load_plugin defines them as globals. Builtins take and return
+// plain data as parser nodes, the same for both interpreters, and return an error message to raise a runtime error:
+//
+//     struct Strings;
+//
+//     impl Plugin for Strings {
+//         fn register(&self, env: &mut Environment) {
+//             env.define_builtin("string-reverse", |args| match args {
+//                 [Node::String(s)] => Ok(Node::String(s.chars().rev().collect())),
+//                 _ => Err("string-reverse takes a string".to_string())
+//             });
+//         }
+//     }
+//
+// With the ffi feature, a plugin can also be built as a cdylib and loaded at run time with
+// Interpreter::load_plugin_library. The library declares its plugin with declare_plugin!, and has to be built by the
+// same compiler against the same version of this crate, since a plugin is passed as a Rust trait object.
+
+use parser::Node;
+
+use std::fmt;
+use std::ptr;
+use std::rc::Rc;
+
+pub trait Plugin {
+    fn register(&self, env: &mut Environment);
+}
+
+// What a plugin defines, to be bound in an interpreter's global environment
+#[derive(Default)]
+pub struct Environment {
+    builtins: Vec<Rc<Builtin>>,
+    constants: Vec<(String, Node)>,
+    docs: Vec<(String, String, String)>,
+}
+
+impl Environment {
+    pub fn new() -> Environment {
+        Environment { builtins: Vec::new(), constants: Vec::new(), docs: Vec::new() }
+    }
+
+    pub fn define_builtin<F: Fn(&[Node]) -> Result<Node, String> + 'static>(&mut self, name: &str, function: F) {
+        self.builtins.push(Rc::new(Builtin { name: name.to_string(), function: Box::new(function) }));
+    }
+
+    pub fn define(&mut self, name: &str, value: Node) {
+        self.constants.push((name.to_string(), value));
+    }
+
+    // Document a builtin for document and `rusty_scheme doc`, with its parameters written as they'd be called, e.g.
+    // "string [start]"
+    pub fn document(&mut self, name: &str, parameters: &str, text: &str) {
+        self.docs.push((name.to_string(), parameters.to_string(), text.to_string()));
+    }
+
+    pub fn builtins(&self) -> &[Rc<Builtin>] {
+        &self.builtins
+    }
+
+    pub fn constants(&self) -> &[(String, Node)] {
+        &self.constants
+    }
+
+    pub fn docs(&self) -> &[(String, String, String)] {
+        &self.docs
+    }
+}
+
+type Function = Box<dyn Fn(&[Node]) -> Result<Node, String>>;
+
+// A procedure defined by a plugin
+pub struct Builtin {
+    name: String,
+    function: Function,
+}
+
+impl Builtin {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn call(&self, args: &[Node]) -> Result<Node, String> {
+        (self.function)(args)
+    }
+}
+
+// A builtin is only equal to itself
+impl PartialEq for Builtin {
+    fn eq(&self, other: &Builtin) -> bool {
+        ptr::eq(self, other)
+    }
+}
+
+impl fmt::Debug for Builtin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#<procedure:{}>", self.name)
+    }
+}
+
+// The symbol a plugin library exports, which declare_plugin! defines
+pub const PLUGIN_SYMBOL: &str = "rusty_scheme_plugin";
+
+// Declare the plugin a cdylib provides, given an expression that makes it, e.g. declare_plugin!(Strings)
+#[macro_export]
+macro_rules! declare_plugin {
+    ($plugin:expr) => (
+        #[no_mangle]
+        pub extern "C" fn rusty_scheme_plugin() -> *mut Box<dyn $crate::plugin::Plugin> {
+            let plugin: Box<dyn $crate::plugin::Plugin> = Box::new($plugin);
+            Box::into_raw(Box::new(plugin))
+        }
+    )
+}
+
+// Load the plugin from the library at path. The library is never unloaded, since the builtins it defines can be
+// referred to for as long as the program runs.
+#[cfg(feature = "ffi")]
+pub fn load_library(path: &str) -> Result<Box<dyn Plugin>, String> {
+    use ffi;
+    use std::mem;
+    use std::os::raw::c_void;
+
+    let library = try!(ffi::Library::open(path));
+    let symbol = try!(library.symbol(PLUGIN_SYMBOL));
+    let make = unsafe { mem::transmute::<*mut c_void, extern "C" fn() -> *mut Box<dyn Plugin>>(symbol) };
+    let plugin = unsafe { Box::from_raw(make()) };
+    mem::forget(library);
+    Ok(*plugin)
+}
+
+#[test]
+fn test_plugin() {
+    struct Constants;
+
+    impl Plugin for Constants {
+        fn register(&self, env: &mut Environment) {
+            env.define("answer", Node::Integer(42));
+            env.define_builtin("double", |args| match args {
+                [Node::Integer(n)] => Ok(Node::Integer(n * 2)),
+                _ => Err("double takes an integer".to_string())
+            });
+        }
+    }
+
+    let mut env = Environment::new();
+    Constants.register(&mut env);
+    assert_eq!(env.constants(), &[("answer".to_string(), Node::Integer(42))]);
+    let double = env.builtins()[0].clone();
+    assert_eq!(double.name(), "double");
+    assert_eq!(double.call(&[Node::Integer(4)]), Ok(Node::Integer(8)));
+    assert_eq!(double.call(&[]), Err("double takes an integer".to_string()));
+    assert_eq!(format!("{:?}", double), "#<procedure:double>");
+}
@@ -0,0 +1,663 @@
+// Turns values into text for write, display and the REPL. Both interpreters describe their values through the
+// Printable trait, so the datum labels of write/write-shared and the elision limits of PrinterConfig are
+// implemented once here.
+//
+// The configuration is per thread rather than per interpreter, since the native procedures that print don't
+// have access to the interpreter they're running in.
+
+use lexer::{self, Token};
+
+use std::cell::RefCell;
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+
+#[cfg(test)]
+use std::mem;
+#[cfg(test)]
+use std::rc::Rc;
+
+#[derive(PartialEq, Clone, Debug, Default)]
+pub struct PrinterConfig {
+    // How many levels of nested lists and boxes to print before eliding the rest with ...
+    pub max_depth: Option<usize>,
+    // How many elements of each list to print before eliding the rest with ... and how many there are, e.g.
+    // (1 2 3 ... 97 more)
+    pub max_length: Option<usize>,
+    // How many characters to print in total before cutting the output off with ...
+    pub width: Option<usize>,
+    // Print procedures written in Scheme with their parameters and the start of their body, for debugging, instead
+    // of just #<procedure>
+    pub show_procedures: bool,
+}
+
+thread_local!(static CONFIG: RefCell<PrinterConfig> = RefCell::new(PrinterConfig::default()));
+
+pub fn config() -> PrinterConfig {
+    CONFIG.with(|c| c.borrow().clone())
+}
+
+pub fn set_config(config: PrinterConfig) {
+    CONFIG.with(|c| *c.borrow_mut() = config);
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Style {
+    // Human-readable: strings without quotes, and datum labels where needed to break cycles
+    Display,
+    // Machine-readable, with datum labels only where needed to break cycles
+    Write,
+    // Machine-readable, without datum labels, so a box or vector met again inside itself is cut off with ... instead
+    WriteSimple,
+    // Machine-readable, with datum labels for every shared list, string, box or vector
+    WriteShared,
+}
+
+pub enum Shape<T> {
+    Atom(String),
+    // A list's items, and the address of a non-empty one, so that write-shared can label it if it's reached twice.
+    // Lists can't be changed, so unlike vectors and boxes they can't contain themselves.
+    List(Option<usize>, Vec<T>),
+    // The items of an improper list, its address and its tail
+    DottedList(Option<usize>, Vec<T>, T),
+    // A string as written, and its address, which like a list's is only for write-shared
+    String(usize, String),
+    // A vector's elements, and the address of a mutable one, which like a box's can make shared and cyclic structure
+    Vector(Option<usize>, Vec<T>),
+    // A mutable cell, identified by its address so shared and cyclic structure can be detected
+    Box(usize, T),
+}
+
+pub trait Printable: Sized {
+    fn shape(&self, style: Style) -> Shape<Self>;
+}
+
+// How a float is written: with a point or an exponent, so it reads back as a float, or as +inf.0, -inf.0 or +nan.0
+pub fn float(f: f64) -> String {
+    if f.is_nan() {
+        "+nan.0".to_string()
+    } else if f.is_infinite() {
+        (if f > 0.0 { "+inf.0" } else { "-inf.0" }).to_string()
+    } else {
+        format!("{:?}", f)
+    }
+}
+
+// The characters written by name, as R7RS names them
+pub const CHARACTER_NAMES: &[(&str, char)] = &[
+    ("alarm", '\x07'), ("backspace", '\x08'), ("delete", '\x7f'), ("escape", '\x1b'), ("newline", '\n'), ("null", '\0'),
+    ("return", '\r'), ("space", ' '), ("tab", '\t'),
+];
+
+// How a character is written: by name if it has one (#\space), by its code point if it's some other control
+// character (#\x1), and otherwise as itself (#\a)
+pub fn character(c: char) -> String {
+    match CHARACTER_NAMES.iter().find(|n| n.1 == c) {
+        Some(&(name, _)) => format!("#\\{}", name),
+        None if c.is_control() => format!("#\\x{:x}", c as u32),
+        None => format!("#\\{}", c)
+    }
+}
+
+// How a bytevector is written, e.g. #u8(0 255)
+pub fn bytevector(bytes: &[u8]) -> String {
+    let bytes: Vec<String> = bytes.iter().map(|b| b.to_string()).collect();
+    format!("#u8({})", bytes.join(" "))
+}
+
+// How a symbol is written: as itself if that reads back as the same symbol, and otherwise between bars, e.g.
+// |hello world|, escaping bars, backslashes and control characters
+pub fn symbol(name: &str) -> String {
+    if reads_as_symbol(name) {
+        return name.to_string()
+    }
+    let mut written = "|".to_string();
+    for c in name.chars() {
+        match c {
+            '|' => written.push_str("\\|"),
+            '\\' => written.push_str("\\\\"),
+            '\t' => written.push_str("\\t"),
+            '\n' => written.push_str("\\n"),
+            '\r' => written.push_str("\\r"),
+            _ if c.is_control() => written.push_str(&format!("\\x{:x};", c as u32)),
+            _ => written.push(c)
+        }
+    }
+    written.push('|');
+    written
+}
+
+fn reads_as_symbol(name: &str) -> bool {
+    let plain = |c: char| c.is_alphanumeric() || "!$%&*/:<=>?^_~+-.@".contains(c);
+    match name.chars().next() {
+        // the usual kind of name, which can't be a number or a keyword
+        Some(c) if (c.is_alphabetic() || "!$%&*/<=>?^_~".contains(c)) && name.chars().all(plain) && !name.ends_with(':') => true,
+        _ => match lexer::tokenize(name) {
+            Ok(tokens) => tokens == [Token::Identifier(name.to_string())],
+            Err(_) => false
+        }
+    }
+}
+
+pub fn print<T: Printable>(value: &T, style: Style) -> String {
+    print_with(value, style, config())
+}
+
+// Print with the given settings instead of the current thread's ones
+pub fn print_with<T: Printable>(value: &T, style: Style, config: PrinterConfig) -> String {
+    let labelled = find_labelled(value, style, style == Style::WriteShared);
+    let mut printer = Printer { config, style, labelled, labels: HashMap::new(), out: String::new() };
+    printer.print(value);
+
+    let out = printer.out;
+    match printer.config.width {
+        Some(width) if out.chars().count() > width => {
+            let mut cut: String = out.chars().take(width.saturating_sub(3)).collect();
+            cut.push_str("...");
+            cut
+        },
+        _ => out
+    }
+}
+
+// Both passes over a value keep their own stack of work instead of recursing, so that printing a deeply nested
+// list can't overflow the Rust stack
+enum Visit<T> {
+    Enter(Shape<T>),
+    // Done with the contents of a box or mutable vector, so it's no longer on the path from the root
+    Leave(usize),
+}
+
+// Which values need a datum label (or, for write-simple, cutting off): boxes and mutable vectors that contain
+// themselves, and (if shared is set) any list, string, box or vector reachable twice
+fn find_labelled<T: Printable>(value: &T, style: Style, shared: bool) -> HashSet<usize> {
+    let mut labelled = HashSet::new();
+    let mut seen = HashSet::new();
+    let mut path = HashSet::new();
+    let mut stack = vec![Visit::Enter(value.shape(style))];
+    while let Some(visit) = stack.pop() {
+        // the id of a box or mutable vector, and what's inside it
+        let (id, inner) = match visit {
+            Visit::Enter(Shape::Atom(_)) => continue,
+            Visit::Enter(Shape::String(id, _)) => {
+                if shared {
+                    reached_again(Some(id), &mut seen, &mut labelled);
+                }
+                continue
+            },
+            Visit::Enter(Shape::List(id, items)) => {
+                if !(shared && reached_again(id, &mut seen, &mut labelled)) {
+                    stack.extend(items.iter().rev().map(|item| Visit::Enter(item.shape(style))));
+                }
+                continue
+            },
+            Visit::Enter(Shape::Vector(None, items)) => {
+                stack.extend(items.iter().rev().map(|item| Visit::Enter(item.shape(style))));
+                continue
+            },
+            Visit::Enter(Shape::DottedList(id, items, tail)) => {
+                if !(shared && reached_again(id, &mut seen, &mut labelled)) {
+                    stack.push(Visit::Enter(tail.shape(style)));
+                    stack.extend(items.iter().rev().map(|item| Visit::Enter(item.shape(style))));
+                }
+                continue
+            },
+            Visit::Enter(Shape::Vector(Some(id), items)) => (id, items),
+            Visit::Enter(Shape::Box(id, inner)) => (id, vec![inner]),
+            Visit::Leave(id) => {
+                path.remove(&id);
+                continue
+            }
+        };
+        if path.contains(&id) || (shared && seen.contains(&id)) {
+            labelled.insert(id);
+        }
+        if seen.insert(id) {
+            path.insert(id);
+            stack.push(Visit::Leave(id));
+            stack.extend(inner.iter().rev().map(|item| Visit::Enter(item.shape(style))));
+        }
+    }
+    labelled
+}
+
+// Whether a list or string has been reached before, labelling it if so. Its contents needn't be looked at again,
+// since it can't contain itself.
+fn reached_again(id: Option<usize>, seen: &mut HashSet<usize>, labelled: &mut HashSet<usize>) -> bool {
+    match id {
+        Some(id) if !seen.insert(id) => {
+            labelled.insert(id);
+            true
+        },
+        _ => false
+    }
+}
+
+enum Print<T> {
+    Value(T, usize),
+    Text(&'static str),
+    // The elements of a list cut short
+    More(usize),
+}
+
+struct Printer {
+    config: PrinterConfig,
+    style: Style,
+    labelled: HashSet<usize>,
+    labels: HashMap<usize, usize>,
+    out: String,
+}
+
+impl Printer {
+    fn print<T: Printable>(&mut self, value: &T) {
+        let mut stack = Vec::new();
+        self.print_shape(value.shape(self.style), 0, &mut stack);
+        while let Some(next) = stack.pop() {
+            if self.past_width() {
+                break
+            }
+            match next {
+                Print::Value(value, depth) => {
+                    let shape = value.shape(self.style);
+                    self.print_shape(shape, depth, &mut stack)
+                },
+                Print::Text(s) => self.out.push_str(s),
+                Print::More(n) => self.out.push_str(&format!("... {} more", n)),
+            }
+        }
+    }
+
+    // Print what can be printed right away, and push whatever is inside onto the stack
+    fn print_shape<T: Printable>(&mut self, shape: Shape<T>, depth: usize, stack: &mut Vec<Print<T>>) {
+        match shape {
+            Shape::Atom(s) => self.out.push_str(&s),
+            Shape::String(id, s) => {
+                if !self.print_label(id) {
+                    self.out.push_str(&s)
+                }
+            },
+            Shape::List(id, items) => {
+                if id.is_some_and(|id| self.print_label(id)) {
+                    return
+                }
+                self.print_items("(", items, None, depth, stack)
+            },
+            Shape::DottedList(id, items, tail) => {
+                if id.is_some_and(|id| self.print_label(id)) {
+                    return
+                }
+                self.print_items("(", items, Some(tail), depth, stack)
+            },
+            Shape::Vector(id, items) => {
+                if id.is_some_and(|id| self.print_label(id)) {
+                    return
+                }
+                self.print_items("#(", items, None, depth, stack)
+            },
+            Shape::Box(id, inner) => {
+                if self.print_label(id) {
+                    return
+                }
+                if self.too_deep(depth) {
+                    self.out.push_str("...");
+                    return
+                }
+                self.out.push_str("#&");
+                stack.push(Print::Value(inner, depth + 1));
+            }
+        }
+    }
+
+    // Print the label of a value that needs one: #0= the first time, and then #0# (or, for write-simple, ...)
+    // in place of it, returning true since it's been printed already
+    fn print_label(&mut self, id: usize) -> bool {
+        if !self.labelled.contains(&id) {
+            return false
+        }
+        match self.labels.get(&id) {
+            Some(_) if self.style == Style::WriteSimple => {
+                self.out.push_str("...");
+                return true
+            },
+            Some(label) => {
+                self.out.push_str(&format!("#{}#", label));
+                return true
+            },
+            None => ()
+        }
+        let label = self.labels.len();
+        self.labels.insert(id, label);
+        if self.style != Style::WriteSimple {
+            self.out.push_str(&format!("#{}=", label));
+        }
+        false
+    }
+
+    // Print the opening of a list or vector, and push its elements, the tail of a dotted list and the close paren
+    fn print_items<T: Printable>(&mut self, open: &str, mut items: Vec<T>, tail: Option<T>, depth: usize,
+                                 stack: &mut Vec<Print<T>>) {
+        if self.too_deep(depth) {
+            self.out.push_str("...");
+            return
+        }
+        self.out.push_str(open);
+        stack.push(Print::Text(")"));
+        // pushed last-to-first so they come off the stack in order
+        let shown = self.config.max_length.map_or(items.len(), |max| if max < items.len() { max } else { items.len() });
+        if shown < items.len() {
+            // the tail is cut off along with the items
+            stack.push(Print::More(items.len() - shown));
+            if shown > 0 {
+                stack.push(Print::Text(" "));
+            }
+        } else if let Some(tail) = tail {
+            stack.push(Print::Value(tail, depth + 1));
+            stack.push(Print::Text(" . "));
+        }
+        items.truncate(shown);
+        for (i, item) in items.into_iter().enumerate().rev() {
+            stack.push(Print::Value(item, depth + 1));
+            if i > 0 {
+                stack.push(Print::Text(" "));
+            }
+        }
+    }
+
+    // Whether the output is already certain to be cut off, so the rest needn't be printed (a character takes at
+    // most four bytes, so this errs on the side of printing too much)
+    fn past_width(&self) -> bool {
+        self.config.width.is_some_and(|width| self.out.len() > width * 4)
+    }
+
+    fn too_deep(&self, depth: usize) -> bool {
+        self.config.max_depth.is_some_and(|max| depth >= max)
+    }
+}
+
+// A setting's value as Scheme code sees it: limits are numbers, or #f for none, and switches are #t or #f
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Setting {
+    Limit(usize),
+    On,
+    Off,
+}
+
+fn limit_setting(limit: Option<usize>) -> Setting {
+    match limit {
+        Some(n) => Setting::Limit(n),
+        None => Setting::Off
+    }
+}
+
+fn switch_setting(on: bool) -> Setting {
+    if on { Setting::On } else { Setting::Off }
+}
+
+fn limit(name: &str, value: Setting) -> Result<Option<usize>, String> {
+    match value {
+        Setting::Limit(n) => Ok(Some(n)),
+        Setting::Off => Ok(None),
+        Setting::On => Err(format!("Printer option {} must be a non-negative integer or #f", name))
+    }
+}
+
+fn switch(name: &str, value: Setting) -> Result<bool, String> {
+    match value {
+        Setting::On => Ok(true),
+        Setting::Off => Ok(false),
+        Setting::Limit(_) => Err(format!("Printer option {} must be #t or #f", name))
+    }
+}
+
+// Read or change a single setting by the name Scheme code uses for it
+pub fn option(name: &str) -> Result<Setting, String> {
+    let c = config();
+    match name {
+        "max-depth" => Ok(limit_setting(c.max_depth)),
+        "max-length" => Ok(limit_setting(c.max_length)),
+        "width" => Ok(limit_setting(c.width)),
+        "show-procedures" => Ok(switch_setting(c.show_procedures)),
+        _ => Err(format!("Unknown printer option: {}", name))
+    }
+}
+
+pub fn set_option(name: &str, value: Setting) -> Result<(), String> {
+    let mut c = config();
+    match name {
+        "max-depth" => c.max_depth = try!(limit(name, value)),
+        "max-length" => c.max_length = try!(limit(name, value)),
+        "width" => c.width = try!(limit(name, value)),
+        "show-procedures" => c.show_procedures = try!(switch(name, value)),
+        _ => return Err(format!("Unknown printer option: {}", name))
+    }
+    set_config(c);
+    Ok(())
+}
+
+// How a procedure prints with show_procedures on: #<procedure (x y) (+ x y)>, with ... after the first expression
+// of the body if there are more
+pub fn procedure<'a, T: Printable + 'a, I: Iterator<Item=&'a T>>(params: &[String], mut body: I) -> String {
+    let mut out = format!("#<procedure ({})", params.join(" "));
+    if let Some(first) = body.next() {
+        out.push(' ');
+        out.push_str(&print_with(first, Style::Write, PrinterConfig { width: Some(40), ..config() }));
+    }
+    if body.next().is_some() {
+        out.push_str(" ...");
+    }
+    out.push('>');
+    out
+}
+
+// The number of values a value is made of: itself and, for lists, vectors and boxes, everything inside them. A box or
+// mutable vector that turns up more than once is counted each time, but what it holds only the first time, so cyclic
+// values have a size.
+pub fn size<T: Printable>(value: &T) -> usize {
+    let mut size = 0;
+    let mut seen = HashSet::new();
+    let mut stack = vec![value.shape(Style::Write)];
+    while let Some(shape) = stack.pop() {
+        size += 1;
+        match shape {
+            Shape::Atom(_) | Shape::String(_, _) => (),
+            Shape::List(_, items) | Shape::Vector(None, items) => stack.extend(items.iter().map(|item| item.shape(Style::Write))),
+            Shape::Vector(Some(id), items) => {
+                if seen.insert(id) {
+                    stack.extend(items.iter().map(|item| item.shape(Style::Write)));
+                }
+            },
+            Shape::DottedList(_, items, tail) => {
+                stack.extend(items.iter().map(|item| item.shape(Style::Write)));
+                stack.push(tail.shape(Style::Write));
+            },
+            Shape::Box(id, inner) => {
+                if seen.insert(id) {
+                    stack.push(inner.shape(Style::Write));
+                }
+            }
+        }
+    }
+    size
+}
+
+// How many lists, vectors and boxes deep a value nests, counting the same way as the max_depth setting: 0 for an
+// atom, 1 for a list of atoms, and so on. A cyclic value has no depth.
+pub fn depth<T: Printable>(value: &T) -> Option<usize> {
+    let mut depth = 0;
+    let mut path = HashSet::new();
+    let mut stack = vec![(Visit::Enter(value.shape(Style::Write)), 0)];
+    while let Some((visit, level)) = stack.pop() {
+        match visit {
+            Visit::Enter(Shape::Atom(_)) | Visit::Enter(Shape::String(_, _)) => (),
+            Visit::Enter(Shape::List(_, items)) | Visit::Enter(Shape::Vector(None, items)) => {
+                depth = cmp::max(depth, level + 1);
+                stack.extend(items.iter().map(|item| (Visit::Enter(item.shape(Style::Write)), level + 1)));
+            },
+            Visit::Enter(Shape::DottedList(_, items, tail)) => {
+                depth = cmp::max(depth, level + 1);
+                stack.extend(items.iter().map(|item| (Visit::Enter(item.shape(Style::Write)), level + 1)));
+                stack.push((Visit::Enter(tail.shape(Style::Write)), level + 1));
+            },
+            Visit::Enter(Shape::Vector(Some(id), items)) => {
+                if !path.insert(id) {
+                    return None
+                }
+                depth = cmp::max(depth, level + 1);
+                stack.push((Visit::Leave(id), level));
+                stack.extend(items.iter().map(|item| (Visit::Enter(item.shape(Style::Write)), level + 1)));
+            },
+            Visit::Enter(Shape::Box(id, inner)) => {
+                if !path.insert(id) {
+                    return None
+                }
+                depth = cmp::max(depth, level + 1);
+                stack.push((Visit::Leave(id), level));
+                stack.push((Visit::Enter(inner.shape(Style::Write)), level + 1));
+            },
+            Visit::Leave(id) => {
+                path.remove(&id);
+            }
+        }
+    }
+    Some(depth)
+}
+
+// Lists are reference counted so that taking the shape of one is cheap however deep it is
+#[cfg(test)]
+#[derive(Clone)]
+enum TestValue {
+    Atom(&'static str),
+    List(Rc<Vec<TestValue>>),
+    Box(usize, Rc<Vec<TestValue>>),
+}
+
+#[cfg(test)]
+impl Printable for TestValue {
+    fn shape(&self, _: Style) -> Shape<TestValue> {
+        match *self {
+            TestValue::Atom(s) => Shape::Atom(s.to_string()),
+            TestValue::List(ref items) => Shape::List(Some(&**items as *const Vec<TestValue> as usize), (**items).clone()),
+            // the contents of a test box is a list, or itself when empty
+            TestValue::Box(id, ref items) if items.is_empty() => Shape::Box(id, self.clone()),
+            TestValue::Box(id, ref items) => Shape::Box(id, TestValue::List(items.clone())),
+        }
+    }
+}
+
+// Like the interpreters' values, test values are taken apart without recursing when they're dropped
+#[cfg(test)]
+impl Drop for TestValue {
+    fn drop(&mut self) {
+        let mut stack = self.take_items();
+        while let Some(mut value) = stack.pop() {
+            // with its items taken, value doesn't hold anything more to drop
+            stack.extend(value.take_items());
+        }
+    }
+}
+
+#[cfg(test)]
+impl TestValue {
+    fn take_items(&mut self) -> Vec<TestValue> {
+        match *self {
+            TestValue::List(ref mut items) | TestValue::Box(_, ref mut items) => match Rc::get_mut(items) {
+                Some(items) => mem::take(items),
+                None => Vec::new()
+            },
+            TestValue::Atom(_) => Vec::new()
+        }
+    }
+}
+
+#[cfg(test)]
+fn list(items: Vec<TestValue>) -> TestValue {
+    TestValue::List(Rc::new(items))
+}
+
+#[test]
+fn test_printer_limits() {
+    let value = list(vec![TestValue::Atom("1"), list(vec![TestValue::Atom("2"), list(vec![TestValue::Atom("3")])]), TestValue::Atom("4")]);
+    assert_eq!(print(&value, Style::Write), "(1 (2 (3)) 4)");
+    set_config(PrinterConfig { max_depth: Some(2), ..PrinterConfig::default() });
+    assert_eq!(print(&value, Style::Write), "(1 (2 ...) 4)");
+    set_config(PrinterConfig { max_length: Some(2), ..PrinterConfig::default() });
+    assert_eq!(print(&value, Style::Write), "(1 (2 (3)) ... 1 more)");
+    set_config(PrinterConfig { width: Some(8), ..PrinterConfig::default() });
+    assert_eq!(print(&value, Style::Write), "(1 (2...");
+    let long = list((0..1000).map(|_| TestValue::Atom("x")).collect());
+    assert_eq!(print(&long, Style::Write), "(x x ...");
+    set_config(PrinterConfig::default());
+}
+
+#[test]
+fn test_printer_procedure() {
+    let params = vec!["x".to_string(), "y".to_string()];
+    let body = [list(vec![TestValue::Atom("+"), TestValue::Atom("x"), TestValue::Atom("y")])];
+    assert_eq!(procedure(&params, body.iter()), "#<procedure (x y) (+ x y)>");
+    let long = [list((0..100).map(|_| TestValue::Atom("x")).collect()), TestValue::Atom("y")];
+    assert_eq!(procedure(&[], long.iter()), "#<procedure () (x x x x x x x x x x x x x x x x x x ... ...>");
+    assert_eq!(procedure::<TestValue, _>(&[], [].iter()), "#<procedure ()>");
+}
+
+#[test]
+fn test_printer_symbols() {
+    for name in ["abc", "set-car!", "λ", "x->y", "...", "+", "-", "a.b"].iter() {
+        assert_eq!(symbol(name), *name);
+    }
+    assert_eq!(symbol("hello world"), "|hello world|");
+    assert_eq!(symbol(""), "||");
+    assert_eq!(symbol("12"), "|12|");
+    assert_eq!(symbol("-1.5"), "|-1.5|");
+    assert_eq!(symbol("."), "|.|");
+    assert_eq!(symbol("#t"), "|#t|");
+    assert_eq!(symbol("key:"), "|key:|");
+    // the reader takes a + or - followed by something else for a number
+    assert_eq!(symbol("->x"), "|->x|");
+    assert_eq!(symbol("a|b\\c\td\x01"), "|a\\|b\\\\c\\td\\x1;|");
+    // and they read back as the same symbol
+    for name in ["hello world", "12", "a|b\\c\td\x01", "(x)"].iter() {
+        assert_eq!(lexer::tokenize(&symbol(name)).unwrap(), vec![Token::Identifier(name.to_string())]);
+    }
+}
+
+#[test]
+fn test_printer_size_and_depth() {
+    let value = list(vec![TestValue::Atom("1"), list(vec![TestValue::Atom("2"), list(vec![TestValue::Atom("3")])]), TestValue::Atom("4")]);
+    assert_eq!(size(&value), 7);
+    assert_eq!(depth(&value), Some(3));
+    assert_eq!(size(&TestValue::Atom("1")), 1);
+    assert_eq!(depth(&TestValue::Atom("1")), Some(0));
+    assert_eq!(depth(&list(vec![])), Some(1));
+    let shared = list(vec![TestValue::Box(1, Rc::new(vec![TestValue::Atom("a")])), TestValue::Box(1, Rc::new(vec![TestValue::Atom("a")]))]);
+    assert_eq!(size(&shared), 5);
+    assert_eq!(depth(&shared), Some(3));
+    let cyclic = TestValue::Box(1, Rc::new(vec![TestValue::Atom("a"), TestValue::Box(1, Rc::new(vec![]))]));
+    assert_eq!(size(&cyclic), 4);
+    assert_eq!(depth(&cyclic), None);
+}
+
+#[test]
+fn test_printer_labels() {
+    let shared = list(vec![TestValue::Box(1, Rc::new(vec![TestValue::Atom("a")])), TestValue::Box(1, Rc::new(vec![TestValue::Atom("a")]))]);
+    assert_eq!(print(&shared, Style::Write), "(#&(a) #&(a))");
+    assert_eq!(print(&shared, Style::WriteShared), "(#0=#&(a) #0#)");
+    let cyclic = TestValue::Box(7, Rc::new(Vec::new()));
+    assert_eq!(print(&cyclic, Style::Write), "#0=#&#0#");
+    assert_eq!(print(&cyclic, Style::WriteShared), "#0=#&#0#");
+    assert_eq!(print(&cyclic, Style::Display), "#0=#&#0#");
+    assert_eq!(print(&cyclic, Style::WriteSimple), "#&...");
+    assert_eq!(print(&shared, Style::WriteSimple), "(#&(a) #&(a))");
+    let inner = list(vec![TestValue::Atom("1"), TestValue::Atom("2")]);
+    let lists = list(vec![inner.clone(), list(vec![inner.clone()]), inner]);
+    assert_eq!(print(&lists, Style::Write), "((1 2) ((1 2)) (1 2))");
+    assert_eq!(print(&lists, Style::WriteShared), "(#0=(1 2) (#0#) #0#)");
+}
+
+#[test]
+fn test_printer_deep() {
+    let mut deep = TestValue::Atom("x");
+    for _ in 0..1000000 {
+        deep = list(vec![deep]);
+    }
+    let printed = print(&deep, Style::WriteShared);
+    assert!(printed == format!("{}x{}", "(".repeat(1000000), ")".repeat(1000000)));
+}
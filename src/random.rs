@@ -0,0 +1,163 @@
+// The sources of run-to-run variation: the random builtin, gensym's counter and the order hashed environments
+// iterate in (which decides e.g. the layout of heap images). Normally the generator is seeded from the clock;
+// after set_seed, every run with the same seed behaves the same, so bug reports and test failures can be
+// reproduced. Like the printer settings this is per thread, since the builtins don't know their interpreter.
+
+use std::cell::Cell;
+use std::collections::hash_map::{DefaultHasher, RandomState};
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Only the random builtin (in the math feature) draws numbers
+#[cfg_attr(not(feature = "math"), allow(dead_code))]
+#[derive(Clone, Copy)]
+struct State {
+    seed: Option<u64>,
+    next: u64,
+    gensyms: u64,
+}
+
+thread_local!(static STATE: Cell<State> = Cell::new(State { seed: None, next: clock_seed(), gensyms: 0 }));
+
+fn clock_seed() -> u64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs() ^ (d.subsec_nanos() as u64) << 32,
+        Err(_) => 0
+    }
+}
+
+// Restart the generator and the gensym counter from a fixed point
+pub fn set_seed(seed: u64) {
+    STATE.with(|s| s.set(State { seed: Some(seed), next: seed, gensyms: 0 }));
+}
+
+pub fn seed() -> Option<u64> {
+    STATE.with(|s| s.get().seed)
+}
+
+// splitmix64: tiny, fast, and good enough for a scripting language's random
+#[cfg_attr(not(feature = "math"), allow(dead_code))]
+pub fn next_u64() -> u64 {
+    STATE.with(|s| {
+        let mut state = s.get();
+        state.next = state.next.wrapping_add(0x9e3779b97f4a7c15);
+        s.set(state);
+        let mut z = state.next;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    })
+}
+
+// A number in [0, n), without the bias of a plain modulo
+#[cfg_attr(not(feature = "math"), allow(dead_code))]
+pub fn below(n: u64) -> u64 {
+    let limit = u64::MAX - u64::MAX % n;
+    loop {
+        let r = next_u64();
+        if r < limit {
+            return r % n
+        }
+    }
+}
+
+// A fresh symbol name: g1, g2, ...
+pub fn gensym(prefix: &str) -> String {
+    STATE.with(|s| {
+        let mut state = s.get();
+        state.gensyms += 1;
+        s.set(state);
+        format!("{}{}", prefix, state.gensyms)
+    })
+}
+
+// How hashed environments hash their names: randomly keyed as usual, or keyed by the seed once there is one
+#[derive(Clone)]
+pub enum HashState {
+    Random(RandomState),
+    Seeded(u64),
+}
+
+impl HashState {
+    pub fn new() -> HashState {
+        match seed() {
+            Some(seed) => HashState::Seeded(seed),
+            None => HashState::Random(RandomState::new())
+        }
+    }
+}
+
+impl BuildHasher for HashState {
+    type Hasher = DefaultHasher;
+
+    fn build_hasher(&self) -> DefaultHasher {
+        match *self {
+            HashState::Random(ref state) => state.build_hasher(),
+            HashState::Seeded(seed) => {
+                let mut hasher = DefaultHasher::new();
+                hasher.write_u64(seed);
+                hasher
+            }
+        }
+    }
+}
+
+// A hash that's the same in every run, whatever the seed, for (hash obj). Scheme integers are signed, so the top
+// bit is dropped to keep it non-negative.
+pub fn stable_hash<T: Hash + ?Sized>(t: &T) -> i64 {
+    let mut hasher = DefaultHasher::new();
+    t.hash(&mut hasher);
+    (hasher.finish() >> 1) as i64
+}
+
+// What each kind of value hashes as ahead of its contents. Both interpreters use the same numbering (with fixed
+// values, so leaving out a feature doesn't shift the rest), so (hash x) is the same whichever one runs.
+#[derive(Clone, Copy)]
+pub enum HashTag {
+    Symbol = 0,
+    Integer = 1,
+    Boolean = 2,
+    String = 3,
+    List = 4,
+    Procedure = 5,
+    SpecialForm = 6,
+    Macro = 7,
+    Continuation = 8,
+    EofObject = 9,
+    Keyword = 10,
+    Box = 11,
+    Condition = 12,
+    Values = 13,
+    Queue = 14,
+    Set = 15,
+    Heap = 16,
+    #[cfg(feature = "sqlite")]
+    Database = 17,
+    #[cfg(feature = "ffi")]
+    Library = 18,
+    Float = 19,
+    Rational = 20,
+    Character = 21,
+    Vector = 22,
+    Bytevector = 23,
+    DottedList = 24,
+}
+
+impl Hash for HashTag {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (*self as u8).hash(state)
+    }
+}
+
+#[test]
+fn test_random_seed() {
+    set_seed(7);
+    let first: Vec<u64> = (0..5).map(|_| below(100)).collect();
+    assert_eq!(gensym("g"), "g1");
+    set_seed(7);
+    let second: Vec<u64> = (0..5).map(|_| below(100)).collect();
+    assert_eq!(first, second);
+    assert!(first.iter().all(|&n| n < 100));
+    assert_eq!(gensym("g"), "g1");
+    assert_eq!(gensym("g"), "g2");
+}
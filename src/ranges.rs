@@ -0,0 +1,25 @@
+// The optional start and end indexes that substring, string-copy, the ->list, ->vector, copy and fill procedures take,
+// as in R7RS:
+// the part of a sequence from start up to (not including) end, which default to its beginning and its end. Both
+// interpreters check them here, so a bad range is reported the same way whichever procedure it was passed to.
+
+// The start and end of the range given by indexes, which has at most two of them, in a sequence with length elements
+// of the kind named, e.g. "string"
+pub fn range(indexes: &[usize], length: usize, kind: &str) -> Result<(usize, usize), String> {
+    let start = indexes.first().cloned().unwrap_or(0);
+    let end = indexes.get(1).cloned().unwrap_or(length);
+    if start > end || end > length {
+        return Err(message!("Indexes {} to {} out of range for a {} of length {}", start, end, kind, length))
+    }
+    Ok((start, end))
+}
+
+#[test]
+fn test_ranges() {
+    assert_eq!(range(&[], 3, "string"), Ok((0, 3)));
+    assert_eq!(range(&[1], 3, "string"), Ok((1, 3)));
+    assert_eq!(range(&[3, 3], 3, "list"), Ok((3, 3)));
+    assert_eq!(range(&[2, 4], 3, "string"), Err("Indexes 2 to 4 out of range for a string of length 3".to_string()));
+    assert_eq!(range(&[2, 1], 3, "list"), Err("Indexes 2 to 1 out of range for a list of length 3".to_string()));
+    assert_eq!(range(&[4], 3, "list"), Err("Indexes 4 to 3 out of range for a list of length 3".to_string()));
+}
@@ -1,11 +1,19 @@
 extern crate libc;
 use std::ffi::CString;
 use std::ffi::CStr;
+use std::path::Path;
 
 #[link(name = "readline")]
-extern {
+extern "C" {
     fn readline(prompt: *const libc::c_char) -> *const libc::c_char;
     fn add_history(entry: *const libc::c_char);
+    fn read_history(filename: *const libc::c_char) -> libc::c_int;
+    fn write_history(filename: *const libc::c_char) -> libc::c_int;
+    fn rl_variable_bind(variable: *const libc::c_char, value: *const libc::c_char) -> libc::c_int;
+}
+
+fn history_file(path: &Path) -> Option<CString> {
+    path.to_str().and_then(|p| CString::new(p).ok())
 }
 
 fn prompt_for_input(prompt: &str) -> Option<String> {
@@ -32,11 +40,27 @@ fn prompt_for_input(prompt: &str) -> Option<String> {
     }
 }
 
-pub fn start<F: Fn(String) -> Result<String, String>>(prompt: &str, f: F) {
+// Input continues over several lines while incomplete says it's unfinished, e.g. inside an open list
+pub fn start<P, I, F>(history: Option<&Path>, prompt: P, incomplete: I, f: F)
+        where P: Fn() -> String, I: Fn(&str) -> bool, F: Fn(String) -> Result<String, String> {
+    // with bracketed paste, a pasted block arrives as one input, newlines and all, however many expressions it has
+    let (variable, on) = (CString::new("enable-bracketed-paste").unwrap(), CString::new("on").unwrap());
+    unsafe { rl_variable_bind(variable.as_ptr(), on.as_ptr()); }
+    let history = history.and_then(history_file);
+    // a missing history file just means there's no history yet
+    if let Some(ref file) = history { unsafe { read_history(file.as_ptr()); } }
     loop {
-        match prompt_for_input(prompt) {
-            Some(input) => {
+        match prompt_for_input(&prompt()) {
+            Some(mut input) => {
+                while incomplete(&input) {
+                    match prompt_for_input("... ") {
+                        Some(line) => { input.push('\n'); input.push_str(&line); },
+                        None => break
+                    }
+                }
                 if input.len() > 0 {
+                    // saved after every line, since the REPL is often left with CTRL-C
+                    if let Some(ref file) = history { unsafe { write_history(file.as_ptr()); } }
                     let result = f(input);
                     println!("{}", result.unwrap_or_else(|e| e));
                 }
@@ -0,0 +1,40 @@
+// Where autoload and include look for a file that isn't where its name says: each directory in turn, first match
+// wins. The CLI takes the directories from RUSTY_SCHEME_PATH; embedders set them with Builder::search_path. Like
+// the printer settings, the search path is per thread.
+
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+
+thread_local!(static SEARCH_PATH: RefCell<Vec<PathBuf>> = const { RefCell::new(Vec::new()) });
+
+pub fn set(dirs: Vec<PathBuf>) {
+    SEARCH_PATH.with(|p| *p.borrow_mut() = dirs);
+}
+
+// The file a name refers to from dir: the name relative to dir if that exists, otherwise the first match on the
+// search path, otherwise the name relative to dir again so that opening it reports the file missing there
+pub fn resolve(dir: &Path, name: &str) -> PathBuf {
+    let direct = dir.join(name);
+    if direct.exists() || Path::new(name).is_absolute() {
+        return direct
+    }
+    SEARCH_PATH.with(|p| {
+        p.borrow().iter().map(|d| d.join(name)).find(|path| path.exists()).unwrap_or(direct)
+    })
+}
+
+#[test]
+fn test_search_path() {
+    use std::env;
+    use std::fs::{self, File};
+
+    let dir = env::temp_dir().join("rusty_scheme_search_path");
+    fs::create_dir_all(dir.join("first")).unwrap();
+    fs::create_dir_all(dir.join("second")).unwrap();
+    File::create(dir.join("second/lib.scm")).unwrap();
+    assert_eq!(resolve(Path::new("here"), "lib.scm"), Path::new("here/lib.scm"));
+    set(vec![dir.join("first"), dir.join("second")]);
+    assert_eq!(resolve(Path::new("here"), "lib.scm"), dir.join("second/lib.scm"));
+    assert_eq!(resolve(Path::new(""), "missing.scm"), Path::new("missing.scm"));
+    set(Vec::new());
+}
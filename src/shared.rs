@@ -0,0 +1,9 @@
+// Both interpreters reference count lists, strings and procedure bodies, so cloning a value (e.g. when looking up a
+// variable) is O(1) no matter how big it is, and code that needs to modify one only copies it when it's shared.
+
+use std::rc::Rc;
+
+// What rc refers to, copied only if something else still refers to it too
+pub fn unwrap_or_clone<T: Clone>(rc: Rc<T>) -> T {
+    Rc::try_unwrap(rc).unwrap_or_else(|rc| (*rc).clone())
+}
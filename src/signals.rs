@@ -0,0 +1,91 @@
+// Unix signals for on-signal. The OS-level handler only records that a signal arrived; each interpreter checks for
+// one between evaluation steps, its safe points, and calls the Scheme handler there, so a handler never runs in the
+// middle of a builtin. A signal that arrives during a blocking call, like reading stdin or waiting for an HTTP
+// request, is handled once the call returns.
+//
+// Signals are per process but the Scheme handlers are per thread, so a signal is taken by the first thread with a
+// handler for it to reach a safe point.
+
+use std::os::raw::c_int;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+pub type Signal = c_int;
+
+// One bit per signal number that has arrived and not been handled yet
+static PENDING: AtomicUsize = AtomicUsize::new(0);
+
+const SIG_DFL: usize = 0;
+const SIG_ERR: usize = !0;
+
+extern "C" {
+    fn signal(signum: c_int, handler: usize) -> usize;
+}
+
+extern "C" fn record(signum: c_int) {
+    PENDING.fetch_or(1 << signum, Ordering::SeqCst);
+}
+
+// The signals a script can handle: the ones that ask a program to stop or to reload, whose numbers differ between
+// platforms for the user-defined pair
+#[cfg(target_os = "linux")]
+const SIGNALS: &[(&str, c_int)] = &[("SIGHUP", 1), ("SIGINT", 2), ("SIGQUIT", 3), ("SIGUSR1", 10),
+                                                     ("SIGUSR2", 12), ("SIGTERM", 15)];
+#[cfg(not(target_os = "linux"))]
+const SIGNALS: &[(&str, c_int)] = &[("SIGHUP", 1), ("SIGINT", 2), ("SIGQUIT", 3), ("SIGTERM", 15),
+                                                     ("SIGUSR1", 30), ("SIGUSR2", 31)];
+
+// A signal's number from its name, e.g. SIGTERM
+pub fn number(name: &str) -> Option<c_int> {
+    SIGNALS.iter().find(|s| s.0 == name).map(|s| s.1)
+}
+
+pub fn name(number: c_int) -> &'static str {
+    SIGNALS.iter().find(|s| s.1 == number).map_or("?", |s| s.0)
+}
+
+pub fn names() -> Vec<&'static str> {
+    SIGNALS.iter().map(|s| s.0).collect()
+}
+
+// Start recording the signal instead of letting it take its default action
+pub fn watch(number: c_int) -> Result<(), String> {
+    let handler = record as extern "C" fn(c_int) as usize;
+    if unsafe { signal(number, handler) } == SIG_ERR {
+        return Err(message!("Couldn't handle {}", name(number)))
+    }
+    Ok(())
+}
+
+// Go back to the signal's default action
+pub fn unwatch(number: c_int) {
+    unsafe { signal(number, SIG_DFL) };
+    PENDING.fetch_and(!(1 << number), Ordering::SeqCst);
+}
+
+// Whether any signal is waiting to be handled; cheap enough to check at every step
+pub fn pending() -> bool {
+    PENDING.load(Ordering::Relaxed) != 0
+}
+
+// Whether the signal arrived since it was last taken, taking it if it did
+pub fn take(number: c_int) -> bool {
+    PENDING.fetch_and(!(1 << number), Ordering::SeqCst) & (1 << number) != 0
+}
+
+#[test]
+fn test_signals() {
+    extern "C" {
+        fn raise(signum: c_int) -> c_int;
+    }
+    let usr2 = number("SIGUSR2").unwrap();
+    assert_eq!(name(usr2), "SIGUSR2");
+    assert_eq!(number("SIGKILL"), None);
+
+    watch(usr2).unwrap();
+    assert!(!take(usr2));
+    unsafe { raise(usr2) };
+    assert!(pending());
+    assert!(take(usr2));
+    assert!(!take(usr2));
+    unwatch(usr2);
+}
@@ -0,0 +1,111 @@
+// SQLite databases for sqlite-open, sqlite-exec and sqlite-query, shared by both interpreters. A database is a value
+// of its own, closed when the last reference to it goes away. Parameters and results are plain data: integers,
+// floats, strings and () for NULL both ways, booleans going in as 1 and 0, and blobs coming out as lists of bytes,
+// as from value->cbor.
+
+extern crate rusqlite;
+
+use self::rusqlite::types::{Value, ValueRef};
+use self::rusqlite::{params_from_iter, Connection};
+
+use parser::Node;
+use printer::{self, Style};
+
+use std::fmt;
+use std::ptr;
+
+pub struct Database {
+    connection: Connection,
+    path: String,
+}
+
+impl Database {
+    // Open the database file at path, creating it if it doesn't exist; ":memory:" is a new database in memory
+    pub fn open(path: &str) -> Result<Database, String> {
+        match Connection::open(path) {
+            Ok(connection) => Ok(Database { connection, path: path.to_string() }),
+            Err(e) => Err(message!("Couldn't open the database {}: {}", path, e))
+        }
+    }
+
+    // Run statements that don't return rows, and return how many rows the last one changed. Without parameters, sql
+    // can be several statements separated by semicolons, such as a schema.
+    pub fn exec(&self, sql: &str, parameters: &[Node]) -> Result<i64, String> {
+        let executed = if parameters.is_empty() {
+            self.connection.execute_batch(sql)
+        } else {
+            let parameters = try!(to_sql_values(parameters));
+            self.connection.execute(sql, params_from_iter(parameters)).map(|_| ())
+        };
+        match executed {
+            Ok(_) => Ok(self.connection.changes() as i64),
+            Err(e) => Err(e.to_string())
+        }
+    }
+
+    // Run a query, returning its rows as lists of (column value) lists
+    pub fn query(&self, sql: &str, parameters: &[Node]) -> Result<Node, String> {
+        let parameters = try!(to_sql_values(parameters));
+        let mut statement = try!(self.connection.prepare(sql).map_err(|e| e.to_string()));
+        let columns: Vec<String> = statement.column_names().iter().map(|c| c.to_string()).collect();
+        let mut rows = try!(statement.query(params_from_iter(parameters)).map_err(|e| e.to_string()));
+        let mut result = Vec::new();
+        while let Some(row) = try!(rows.next().map_err(|e| e.to_string())) {
+            let mut fields = Vec::new();
+            for (i, column) in columns.iter().enumerate() {
+                let value = match try!(row.get_ref(i).map_err(|e| e.to_string())) {
+                    ValueRef::Null => Node::List(Vec::new()),
+                    ValueRef::Integer(n) => Node::Integer(n),
+                    ValueRef::Text(text) => Node::String(String::from_utf8_lossy(text).into_owned()),
+                    ValueRef::Blob(bytes) => Node::List(bytes.iter().map(|&b| Node::Integer(b as i64)).collect()),
+                    ValueRef::Real(f) => Node::Float(f)
+                };
+                fields.push(Node::List(vec![Node::Identifier(column.clone()), value]));
+            }
+            result.push(Node::List(fields));
+        }
+        Ok(Node::List(result))
+    }
+}
+
+// A database is only equal to itself
+impl PartialEq for Database {
+    fn eq(&self, other: &Database) -> bool {
+        ptr::eq(self, other)
+    }
+}
+
+impl fmt::Debug for Database {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#<sqlite-database {}>", self.path)
+    }
+}
+
+fn to_sql_values(parameters: &[Node]) -> Result<Vec<Value>, String> {
+    parameters.iter().map(|parameter| match *parameter {
+        Node::Integer(n) => Ok(Value::Integer(n)),
+        Node::Float(f) => Ok(Value::Real(f)),
+        Node::String(ref s) => Ok(Value::Text(s.clone())),
+        Node::Boolean(b) => Ok(Value::Integer(b as i64)),
+        Node::List(ref items) if items.is_empty() => Ok(Value::Null),
+        _ => Err(message!("Can't pass {} to SQLite", printer::print(parameter, Style::Write)))
+    }).collect()
+}
+
+#[test]
+fn test_sqlite() {
+    use parser::parse_str;
+
+    let db = Database::open(":memory:").unwrap();
+    assert_eq!(db.exec("create table t (id integer, name text, data blob); create index i on t (id)", &[]), Ok(0));
+    let row = [Node::Integer(1), Node::String("one".to_string()), Node::List(vec![])];
+    assert_eq!(db.exec("insert into t values (?, ?, ?)", &row), Ok(1));
+    assert_eq!(db.exec("insert into t values (2, 'two', x'0aff')", &[]), Ok(1));
+    assert_eq!(db.query("select * from t where id > ? order by id", &[Node::Integer(0)]),
+               Ok(parse_str("(((id 1) (name \"one\") (data ())) ((id 2) (name \"two\") (data (10 255))))").unwrap().remove(0)));
+    assert_eq!(db.exec("update t set name = upper(name)", &[]), Ok(2));
+    assert_eq!(db.query("select 1.5 as x, ? * 2 as y", &[Node::Float(0.25)]), Ok(parse_str("(((x 1.5) (y 0.5)))").unwrap().remove(0)));
+    assert_eq!(db.query("select ?", &[Node::Identifier("x".to_string())]), Err("Can't pass x to SQLite".to_string()));
+    assert!(db.exec("select * from missing", &[]).unwrap_err().contains("no such table: missing"));
+    assert_eq!(format!("{:?}", db), "#<sqlite-database :memory:>");
+}
@@ -0,0 +1,84 @@
+// Instrumentation counters, bumped by both interpreters as they run. "Steps" are evaluation steps (trampoline
+// bounces for the CPS interpreter, evaluate_value calls for the AST walker), and "allocations" count the heap
+// objects a program creates: list cells, boxes, closures and environments.
+//
+// The counters are only bumped while something is being measured, on any thread, so code that never asks for stats
+// only pays for checking that.
+
+use std::cell::Cell;
+use std::ops::Sub;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[derive(PartialEq, Clone, Copy, Debug, Default)]
+pub struct Stats {
+    pub steps: u64,
+    pub allocations: u64,
+}
+
+impl Sub for Stats {
+    type Output = Stats;
+
+    fn sub(self, other: Stats) -> Stats {
+        Stats { steps: self.steps - other.steps, allocations: self.allocations - other.allocations }
+    }
+}
+
+thread_local!(static STATS: Cell<Stats> = Cell::new(Stats::default()));
+
+// How many measurements are running
+static MEASURING: AtomicUsize = AtomicUsize::new(0);
+
+fn counting() -> bool {
+    MEASURING.load(Ordering::Relaxed) > 0
+}
+
+pub fn count_step() {
+    if !counting() {
+        return
+    }
+    STATS.with(|s| {
+        let mut stats = s.get();
+        stats.steps += 1;
+        s.set(stats);
+    });
+}
+
+pub fn count_allocations(n: u64) {
+    if !counting() {
+        return
+    }
+    STATS.with(|s| {
+        let mut stats = s.get();
+        stats.allocations += n;
+        s.set(stats);
+    });
+}
+
+// Ends a measurement when it's dropped, even if what was measured panicked
+struct Measuring;
+
+impl Drop for Measuring {
+    fn drop(&mut self) {
+        MEASURING.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// Run f, and return its result with the steps and allocations it took on this thread
+pub fn measure<T, F: FnOnce() -> T>(f: F) -> (T, Stats) {
+    MEASURING.fetch_add(1, Ordering::SeqCst);
+    let _measuring = Measuring;
+    let before = STATS.with(|s| s.get());
+    let result = f();
+    (result, STATS.with(|s| s.get()) - before)
+}
+
+#[test]
+fn test_stats() {
+    count_step();
+    let ((), stats) = measure(|| {
+        count_step();
+        count_allocations(3);
+        count_step();
+    });
+    assert_eq!(stats, Stats { steps: 2, allocations: 3 });
+}
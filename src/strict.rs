@@ -0,0 +1,16 @@
+// Strict booleans, which Builder::strict_booleans turns on (the CLI's --strict-booleans): if, and and or raise a type
+// error when a condition they test isn't #t or #f, rather than treating everything but #f as true, which catches a
+// number, list or string tested by mistake. The last operand of and or or isn't tested, only returned, so it can
+// still be anything. Like the capabilities, this is per thread.
+
+use std::cell::Cell;
+
+thread_local!(static BOOLEANS: Cell<bool> = const { Cell::new(false) });
+
+pub fn set_booleans(strict: bool) {
+    BOOLEANS.with(|b| b.set(strict));
+}
+
+pub fn booleans() -> bool {
+    BOOLEANS.with(|b| b.get())
+}
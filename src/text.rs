@@ -0,0 +1,163 @@
+// Unicode-aware string helpers shared by the reader and both interpreters
+
+extern crate unicode_normalization;
+
+use self::unicode_normalization::UnicodeNormalization;
+use self::unicode_normalization::char::is_combining_mark;
+
+use std::char;
+use std::cmp;
+
+// Full case folding, for case-insensitive comparisons. Upcasing first takes care of the characters whose folded
+// form is longer than the original (e.g. ß folds to ss), then downcasing gives the canonical folded form.
+pub fn fold_case(s: &str) -> String {
+    let mut folded = String::new();
+    for c in s.chars() {
+        for upper in c.to_uppercase() {
+            folded.extend(upper.to_lowercase());
+        }
+    }
+    folded
+}
+
+// Canonical composition (NFC), so that precomposed and decomposed spellings of the same text compare equal
+pub fn nfc(s: &str) -> String {
+    s.nfc().collect()
+}
+
+// Whether c can be part of an identifier whatever the reader's settings: letters, digits and the combining marks
+// that decorate them, like the accent of a decomposed é, in any script
+pub fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || is_combining_mark(c)
+}
+
+// How many single-character insertions, deletions, substitutions or swaps of neighbours it takes to turn one string
+// into the other (so typos like "lenght" are one edit from what was meant)
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    // rows[i][j] is the distance between the first i characters of a and the first j of b
+    let mut rows = vec![(0..b.len() + 1).collect::<Vec<usize>>()];
+    for i in 1..a.len() + 1 {
+        let mut row = vec![i; b.len() + 1];
+        for j in 1..b.len() + 1 {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            row[j] = min3(rows[i - 1][j] + 1, row[j - 1] + 1, rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                row[j] = cmp::min(row[j], rows[i - 2][j - 2] + 1);
+            }
+        }
+        rows.push(row);
+    }
+    rows[a.len()][b.len()]
+}
+
+fn min3(a: usize, b: usize, c: usize) -> usize {
+    cmp::min(a, cmp::min(b, c))
+}
+
+// The names close enough to a misspelled one to suggest instead: at most a third of its length in edits away,
+// closest first, and no more than three of them
+pub fn closest<'a, I: Iterator<Item=&'a str>>(name: &str, names: I) -> Vec<String> {
+    let limit = name.chars().count() / 3;
+    let mut close: Vec<(usize, &str)> = names.filter(|n| *n != name)
+                                             .map(|n| (edit_distance(name, n), n))
+                                             .filter(|&(d, _)| d <= limit)
+                                             .collect();
+    close.sort();
+    close.dedup();
+    close.into_iter().take(3).map(|(_, n)| n.to_string()).collect()
+}
+
+// Strings are indexed by character (Unicode scalar value), as string-length counts them. They're kept as UTF-8, so
+// finding a character index means walking the string up to it. A grapheme made of several characters, like e
+// followed by a combining accent, counts as each of them.
+pub fn char_length(s: &str) -> usize {
+    s.chars().count()
+}
+
+// The characters from index start up to (not including) end, or None if those aren't indexes into s in order
+pub fn substring(s: &str, start: usize, end: usize) -> Option<&str> {
+    if start > end {
+        return None
+    }
+    let mut offsets = s.char_indices().map(|(i, _)| i).chain(Some(s.len()));
+    let from = offsets.nth(start)?;
+    let to = if end == start { Some(from) } else { offsets.nth(end - start - 1) };
+    to.map(|to| &s[from..to])
+}
+
+// The character with the code point n, or None if n isn't a Unicode scalar value (a surrogate, or out of range)
+pub fn char_from_code(n: i64) -> Option<char> {
+    if n < 0 || n > u32::MAX as i64 {
+        return None
+    }
+    char::from_u32(n as u32)
+}
+
+// The upper case form of c, as char-upcase gives it. Where that's more than one character (ß upcases to SS), c has
+// no single-character form to map to, so it stays as it is.
+pub fn upcase(c: char) -> char {
+    single(c.to_uppercase()).unwrap_or(c)
+}
+
+// The lower case form of c, or c itself where that's more than one character
+pub fn downcase(c: char) -> char {
+    single(c.to_lowercase()).unwrap_or(c)
+}
+
+fn single<I: Iterator<Item=char>>(mut chars: I) -> Option<char> {
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(c),
+        _ => None
+    }
+}
+
+#[test]
+fn test_text_fold_case() {
+    assert_eq!(fold_case("Hello"), "hello");
+    assert_eq!(fold_case("Straße"), "strasse");
+    assert_eq!(fold_case("ΣΊΣΥΦΟΣ"), fold_case("σίσυφος"));
+}
+
+#[test]
+fn test_text_change_case() {
+    assert_eq!(upcase('a'), 'A');
+    assert_eq!(upcase('λ'), 'Λ');
+    assert_eq!(upcase('ß'), 'ß');
+    assert_eq!(upcase('1'), '1');
+    assert_eq!(downcase('Σ'), 'σ');
+    assert_eq!(downcase('\u{130}'), '\u{130}');
+}
+
+#[test]
+fn test_text_nfc() {
+    assert_eq!(nfc("e\u{301}"), "\u{e9}");
+    assert_eq!(nfc("\u{e9}"), "\u{e9}");
+}
+
+#[test]
+fn test_text_substring() {
+    assert_eq!(char_length("héllo"), 5);
+    assert_eq!(char_length("e\u{301}"), 2);
+    assert_eq!(substring("héllo", 1, 3), Some("él"));
+    assert_eq!(substring("héllo", 0, 5), Some("héllo"));
+    assert_eq!(substring("héllo", 5, 5), Some(""));
+    assert_eq!(substring("héllo", 2, 6), None);
+    assert_eq!(substring("héllo", 6, 6), None);
+    assert_eq!(substring("héllo", 3, 2), None);
+    assert_eq!(char_from_code(0x3bb), Some('λ'));
+    assert_eq!(char_from_code(0xd800), None);
+    assert_eq!(char_from_code(-1), None);
+}
+
+#[test]
+fn test_text_edit_distance() {
+    assert_eq!(edit_distance("length", "length"), 0);
+    assert_eq!(edit_distance("lenght", "length"), 1);
+    assert_eq!(edit_distance("lambda", "lamda"), 1);
+    assert_eq!(edit_distance("", "abc"), 3);
+    assert_eq!(edit_distance("kitten", "sitting"), 3);
+    assert_eq!(closest("lenght", vec!["length", "list", "lengths", "left"].into_iter()), vec!["length", "lengths"]);
+    assert!(closest("x", vec!["+", "y"].into_iter()).is_empty());
+}
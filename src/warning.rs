@@ -0,0 +1,53 @@
+// Non-fatal problems: reported to a handler, and evaluation carries on. The handler is per thread, like the printer
+// settings, since the builtins don't know their interpreter. There's none by default, so warnings are dropped
+// unless an embedder (or the CLI, which prints them to stderr) asks for them.
+
+use std::cell::RefCell;
+use std::collections::HashSet;
+
+pub type Handler = Box<dyn Fn(&str)>;
+
+thread_local!(static HANDLER: RefCell<Option<Handler>> = RefCell::new(None));
+thread_local!(static REPORTED: RefCell<HashSet<String>> = RefCell::new(HashSet::new()));
+
+pub fn set_handler(handler: Handler) {
+    HANDLER.with(|h| *h.borrow_mut() = Some(handler));
+    REPORTED.with(|r| r.borrow_mut().clear());
+}
+
+// Whether anyone is listening, so checks that only produce warnings can be skipped
+pub fn enabled() -> bool {
+    HANDLER.with(|h| h.borrow().is_some())
+}
+
+pub fn warn(message: &str) {
+    HANDLER.with(|h| {
+        if let Some(ref handler) = *h.borrow() {
+            handler(message);
+        }
+    })
+}
+
+// For warnings the interpreter finds itself, which would otherwise repeat every time the code runs
+pub fn warn_once(message: String) {
+    if REPORTED.with(|r| r.borrow_mut().insert(message.clone())) {
+        warn(&message);
+    }
+}
+
+#[test]
+fn test_warning_handler() {
+    use std::rc::Rc;
+    warn("nobody hears this");
+    assert!(!enabled());
+
+    let seen = Rc::new(RefCell::new(Vec::new()));
+    let sink = seen.clone();
+    set_handler(Box::new(move |m| sink.borrow_mut().push(m.to_string())));
+    assert!(enabled());
+    warn("a");
+    warn("a");
+    warn_once("b".to_string());
+    warn_once("b".to_string());
+    assert_eq!(*seen.borrow(), vec!["a", "a", "b"]);
+}
@@ -0,0 +1,84 @@
+// Watching the files a script reads, for `rusty_scheme watch`. The loaders (include, include-ci and autoload) record
+// each file they're asked for here, even one that's missing, so creating it counts as a change; a Watcher then
+// polls their modification times until one of them changes. Like the search path, the record is per thread.
+
+use std::cell::RefCell;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+thread_local!(static LOADED: RefCell<Vec<PathBuf>> = const { RefCell::new(Vec::new()) });
+
+// Note that path was loaded
+pub fn record(path: &Path) {
+    LOADED.with(|l| {
+        let mut loaded = l.borrow_mut();
+        if !loaded.iter().any(|p| p == path) {
+            loaded.push(path.to_path_buf());
+        }
+    });
+}
+
+// The files loaded since the last call, in the order they were first loaded
+pub fn take() -> Vec<PathBuf> {
+    LOADED.with(|l| l.borrow_mut().drain(..).collect())
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+pub struct Watcher {
+    // Each file and when it was last modified, or None if it didn't exist
+    files: Vec<(PathBuf, Option<SystemTime>)>,
+}
+
+impl Watcher {
+    pub fn new(files: Vec<PathBuf>) -> Watcher {
+        Watcher { files: files.into_iter().map(|f| { let t = modified(&f); (f, t) }).collect() }
+    }
+
+    // The first file that's been modified, created or deleted since the watcher was made
+    pub fn changed(&self) -> Option<&Path> {
+        self.files.iter().find(|f| modified(&f.0) != f.1).map(|f| f.0.as_path())
+    }
+
+    // Check every interval until a file changes, and return it
+    pub fn wait(&self, interval: Duration) -> &Path {
+        loop {
+            match self.changed() {
+                Some(path) => return path,
+                None => thread::sleep(interval)
+            }
+        }
+    }
+}
+
+#[test]
+fn test_watch() {
+    use std::env;
+    use std::fs::File;
+
+    let dir = env::temp_dir().join("rusty_scheme_watch");
+    fs::create_dir_all(&dir).unwrap();
+    let script = dir.join("script.scm");
+    let missing = dir.join("missing.scm");
+    let _ = fs::remove_file(&missing);
+    File::create(&script).unwrap().set_modified(SystemTime::UNIX_EPOCH).unwrap();
+
+    record(&script);
+    record(&missing);
+    record(&script);
+    assert_eq!(take(), vec![script.clone(), missing.clone()]);
+    assert_eq!(take(), Vec::<PathBuf>::new());
+
+    let watcher = Watcher::new(vec![script.clone(), missing.clone()]);
+    assert_eq!(watcher.changed(), None);
+    File::create(&script).unwrap().set_modified(SystemTime::now()).unwrap();
+    assert_eq!(watcher.wait(Duration::from_millis(1)), script.as_path());
+    let watcher = Watcher::new(vec![script.clone(), missing.clone()]);
+    File::create(&missing).unwrap();
+    assert_eq!(watcher.changed(), Some(missing.as_path()));
+    fs::remove_file(&missing).unwrap();
+}